@@ -0,0 +1,465 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    io::{Cursor, Read},
+};
+
+use bitcoin_hashes::siphash24;
+
+use crate::{
+    compact_size::CompactSize,
+    constants::{BASIC_FILTER_M, BASIC_FILTER_P, OP_RETURN},
+    node_error::NodeError,
+    transactions::{pk_script::PkScript, utxo_set::UtxoSet},
+};
+
+use super::Block;
+
+/// Basic compact block filter (BIP158) of a block.
+///
+/// The filter is a Golomb-coded set of the output scripts of the block and of the scripts of the
+/// outputs it spends, keyed with the block hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockFilter {
+    /// The number of items in the set.
+    n: u64,
+    /// The Golomb-Rice coded items, without the leading item count.
+    encoded: Vec<u8>,
+    /// The SipHash key, taken from the first 16 bytes of the block hash.
+    key: (u64, u64),
+}
+
+impl BlockFilter {
+    /// Builds the basic filter of a block.
+    ///
+    /// Empty scripts and OP_RETURN outputs are left out of the set, as BIP158 specifies.
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - The block, whose hash keys the filter.
+    /// * `spent_scripts` - The public key scripts of the outputs spent by the block, as returned by
+    ///   `spent_scripts`.
+    ///
+    /// # Returns
+    ///
+    /// The `BlockFilter` of the block.
+    pub fn build(block: &Block, spent_scripts: &[PkScript]) -> BlockFilter {
+        let items: BTreeSet<&PkScript> = block
+            .transactions
+            .iter()
+            .flat_map(|transaction| transaction.tx_outputs.iter())
+            .map(|tx_output| &tx_output.pk_script)
+            .filter(|pk_script| !pk_script.is_empty() && pk_script[0] != OP_RETURN)
+            .chain(
+                spent_scripts
+                    .iter()
+                    .filter(|pk_script| !pk_script.is_empty()),
+            )
+            .collect();
+
+        let key = key_from_hash(block.header.hash());
+        let n = items.len() as u64;
+        let mut hashed_items: Vec<u64> = items
+            .iter()
+            .map(|item| hash_to_range(key, item, n * BASIC_FILTER_M))
+            .collect();
+        hashed_items.sort_unstable();
+
+        let mut writer = BitWriter::new();
+        let mut last_value = 0;
+        for value in hashed_items {
+            golomb_encode(&mut writer, value - last_value);
+            last_value = value;
+        }
+
+        BlockFilter {
+            n,
+            encoded: writer.finish(),
+            key,
+        }
+    }
+
+    /// Builds the basic filter of a block saved on disk.
+    ///
+    /// The UTXO set must not have been updated with the block yet, so the outputs it spends are
+    /// still in it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A string reference representing the file path to the block file.
+    /// * `utxo_set` - The UTXO set to look up the outputs spent by the block in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `BlockFilter` of the block, or `None` if an output spent by the block
+    /// is not in the UTXO set, or a `NodeError` if the file could not be read.
+    pub fn from_block_file(
+        path: &String,
+        utxo_set: &UtxoSet,
+    ) -> Result<Option<BlockFilter>, NodeError> {
+        let block_data = fs::read(path)
+            .map_err(|_| NodeError::FailedToOpenFile("Failed to open file block".to_string()))?;
+        let block = Block::from_bytes(&block_data)?;
+
+        Ok(spent_scripts(&block, utxo_set)
+            .map(|spent_scripts| BlockFilter::build(&block, &spent_scripts)))
+    }
+
+    /// Parses a filter received from a peer, serialized as in the `cfilter` message.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_hash` - The hash of the block, which keys the filter.
+    /// * `bytes` - The item count followed by the coded items.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `BlockFilter`, or a `NodeError` if the item count could not be read.
+    pub fn from_bytes(block_hash: &[u8], bytes: &[u8]) -> Result<BlockFilter, NodeError> {
+        let mut cursor = Cursor::new(bytes);
+        let n = CompactSize::read_varint(&mut cursor)?.get_value();
+        let encoded = bytes[cursor.position() as usize..].to_vec();
+
+        Ok(BlockFilter {
+            n,
+            encoded,
+            key: key_from_hash(block_hash),
+        })
+    }
+
+    /// Returns true if any of the scripts might be part of the block.
+    ///
+    /// As the filter is probabilistic, false positives are possible (with a rate of 1/784931), but
+    /// a script of the block always matches.
+    ///
+    /// # Arguments
+    ///
+    /// * `scripts` - The public key scripts to look for.
+    pub fn matches(&self, scripts: &[PkScript]) -> bool {
+        if self.n == 0 || scripts.is_empty() {
+            return false;
+        }
+
+        let mut queries: Vec<u64> = scripts
+            .iter()
+            .map(|script| hash_to_range(self.key, script, self.n * BASIC_FILTER_M))
+            .collect();
+        queries.sort_unstable();
+
+        let mut reader = BitReader::new(&self.encoded);
+        let mut value = 0;
+        let mut queries = queries.into_iter().peekable();
+        for _ in 0..self.n {
+            value += match golomb_decode(&mut reader) {
+                Some(delta) => delta,
+                None => return false,
+            };
+            while let Some(query) = queries.peek() {
+                if *query == value {
+                    return true;
+                }
+                if *query > value {
+                    break;
+                }
+                queries.next();
+            }
+            if queries.peek().is_none() {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Returns the number of items in the filter.
+    pub fn len(&self) -> u64 {
+        self.n
+    }
+
+    /// Returns true if the filter has no items.
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// Serializes the filter as in the `cfilter` message: the item count followed by the coded items.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = CompactSize::new(self.n as usize).to_bytes();
+        bytes.extend(&self.encoded);
+        bytes
+    }
+}
+
+/// Returns the public key scripts of the outputs spent by a block.
+///
+/// The outputs created earlier in the same block are skipped, as their scripts are already part
+/// of the block.
+///
+/// # Returns
+///
+/// The scripts, or `None` if an output spent by the block is not in the UTXO set. The UTXO set
+/// only holds the outputs created since STARTING_DATE, so it's expected for blocks spending older
+/// outputs, whose filter can't be built.
+pub fn spent_scripts(block: &Block, utxo_set: &UtxoSet) -> Option<Vec<PkScript>> {
+    let mut block_tx_ids = HashSet::new();
+    let mut scripts = Vec::new();
+    for (position, transaction) in block.transactions.iter().enumerate() {
+        if position > 0 {
+            for tx_input in transaction.tx_inputs.iter() {
+                let outpoint = &tx_input.previous_output;
+                if block_tx_ids.contains(&outpoint.tx_id) {
+                    continue;
+                }
+                scripts.push(utxo_set.output(outpoint)?.pk_script);
+            }
+        }
+        block_tx_ids.insert(transaction.tx_id());
+    }
+    Some(scripts)
+}
+
+/// Returns the SipHash key of a block, made of the first 16 bytes of its hash as two little endian integers.
+fn key_from_hash(block_hash: &[u8]) -> (u64, u64) {
+    let mut first_half = [0; 8];
+    let mut second_half = [0; 8];
+    if block_hash.len() >= 16 {
+        first_half.copy_from_slice(&block_hash[0..8]);
+        second_half.copy_from_slice(&block_hash[8..16]);
+    }
+    (
+        u64::from_le_bytes(first_half),
+        u64::from_le_bytes(second_half),
+    )
+}
+
+/// Hashes an item with SipHash-2-4 and maps it uniformly to the range [0, f).
+fn hash_to_range(key: (u64, u64), item: &[u8], f: u64) -> u64 {
+    let hash = siphash24::Hash::hash_to_u64_with_keys(key.0, key.1, item);
+    ((hash as u128 * f as u128) >> 64) as u64
+}
+
+/// Writes a value with Golomb-Rice coding: the quotient in unary followed by the remainder in P bits.
+fn golomb_encode(writer: &mut BitWriter, value: u64) {
+    for _ in 0..(value >> BASIC_FILTER_P) {
+        writer.write_bit(true);
+    }
+    writer.write_bit(false);
+    for i in (0..BASIC_FILTER_P).rev() {
+        writer.write_bit((value >> i) & 1 == 1);
+    }
+}
+
+/// Reads a Golomb-Rice coded value, returning None if the data ends before the value does.
+fn golomb_decode(reader: &mut BitReader) -> Option<u64> {
+    let mut quotient = 0;
+    while reader.read_bit()? {
+        quotient += 1;
+    }
+    let mut remainder = 0;
+    for _ in 0..BASIC_FILTER_P {
+        remainder = (remainder << 1) | reader.read_bit()? as u64;
+    }
+    Some((quotient << BASIC_FILTER_P) | remainder)
+}
+
+/// Writes bits from the most significant to the least significant bit of each byte.
+struct BitWriter {
+    bytes: Vec<u8>,
+    used_bits: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            used_bits: 8,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.used_bits == 8 {
+            self.bytes.push(0);
+            self.used_bits = 0;
+        }
+        if bit {
+            if let Some(last) = self.bytes.last_mut() {
+                *last |= 0x80 >> self.used_bits;
+            }
+        }
+        self.used_bits += 1;
+    }
+
+    /// Returns the written bytes, with the last one padded with zeros.
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads bits from the most significant to the least significant bit of each byte.
+struct BitReader<'a> {
+    bytes: Cursor<&'a [u8]>,
+    current: u8,
+    remaining_bits: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader {
+            bytes: Cursor::new(bytes),
+            current: 0,
+            remaining_bits: 0,
+        }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        if self.remaining_bits == 0 {
+            let mut byte = [0; 1];
+            self.bytes.read_exact(&mut byte).ok()?;
+            self.current = byte[0];
+            self.remaining_bits = 8;
+        }
+        self.remaining_bits -= 1;
+        Some((self.current >> self.remaining_bits) & 1 == 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{
+        block::retrieve_transactions_from_block, block_header::BlockHeader,
+        transactions::transaction::Transaction, utils::Utils,
+    };
+
+    use super::*;
+
+    // TODO: add a vector of the BIP158 testnet-19 set for a block with inputs, with the raw block,
+    // its previous output scripts and the expected filter, to check the spent scripts against the
+    // reference instead of only against `FUNDING_BLOCK`.
+
+    /// Header of the testnet genesis block, from the BIP158 test vectors.
+    const TESTNET_GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff001d1aa4ae18";
+    /// Coinbase transaction of the testnet genesis block.
+    const TESTNET_GENESIS_COINBASE: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+    /// Block whose outputs are spent by `SPENDING_BLOCK`.
+    const FUNDING_BLOCK: &str =
+        "blocks-test/0000000000000027898516270708e0c8db276e6f8302b05c8c8c208bab36ea59.bin";
+    /// Block that spends outputs of `FUNDING_BLOCK`.
+    const SPENDING_BLOCK: &str =
+        "blocks-test/00000000000000100415543e85ed470b4c381f6adc97850c0124f367a45b4bfe.bin";
+
+    fn testnet_genesis() -> Result<Block, NodeError> {
+        let header_bytes = Utils::hex_string_to_bytes(TESTNET_GENESIS_HEADER.to_string())?;
+        let coinbase_bytes = Utils::hex_string_to_bytes(TESTNET_GENESIS_COINBASE.to_string())?;
+        let coinbase = Transaction::read_coinbase_transaction(&mut Cursor::new(coinbase_bytes))?;
+        Ok(Block {
+            header: BlockHeader::from_bytes(&header_bytes)?,
+            transactions: vec![coinbase],
+        })
+    }
+
+    fn block_from_file(path: &str) -> Result<Block, NodeError> {
+        let block_data = fs::read(path)
+            .map_err(|_| NodeError::FailedToOpenFile("Failed to open file block".to_string()))?;
+        Block::from_bytes(&block_data)
+    }
+
+    #[test]
+    fn test_genesis_filter_matches_bip158_vector() -> Result<(), NodeError> {
+        let block = testnet_genesis()?;
+
+        let filter = BlockFilter::build(&block, &[]);
+
+        assert_eq!(filter.to_bytes(), vec![0x01, 0x9d, 0xfc, 0xa8]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_genesis_filter_matches_its_output_script() -> Result<(), NodeError> {
+        let block = testnet_genesis()?;
+        let pk_script = block.transactions[0].tx_outputs[0].pk_script.clone();
+
+        let filter = BlockFilter::build(&block, &[]);
+
+        assert!(filter.matches(&[vec![0x00], pk_script]));
+        assert!(!filter.matches(&[vec![0x00]]));
+        assert!(!filter.matches(&[]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_filter_matches_every_output_script() -> Result<(), NodeError> {
+        let path =
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin"
+                .to_string();
+        let transactions = retrieve_transactions_from_block(&path)?;
+
+        let filter = BlockFilter::build(&block_from_file(&path)?, &[]);
+
+        assert!(!filter.is_empty());
+        for transaction in transactions.iter() {
+            for tx_output in transaction.tx_outputs.iter() {
+                if !tx_output.pk_script.is_empty() && tx_output.pk_script[0] != OP_RETURN {
+                    assert!(filter.matches(std::slice::from_ref(&tx_output.pk_script)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_filter_includes_the_spent_scripts() -> Result<(), NodeError> {
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.update(&FUNDING_BLOCK.to_string())?;
+        let block = block_from_file(SPENDING_BLOCK)?;
+        let funded_scripts: Vec<PkScript> = block
+            .transactions
+            .iter()
+            .skip(1)
+            .flat_map(|transaction| transaction.tx_inputs.iter())
+            .filter_map(|tx_input| utxo_set.output(&tx_input.previous_output))
+            .map(|tx_output| tx_output.pk_script)
+            .collect();
+        let new_scripts: Vec<PkScript> = funded_scripts
+            .iter()
+            .filter(|pk_script| {
+                !block.transactions.iter().any(|transaction| {
+                    transaction
+                        .tx_outputs
+                        .iter()
+                        .any(|tx_output| &&tx_output.pk_script == pk_script)
+                })
+            })
+            .cloned()
+            .collect();
+
+        let filter = BlockFilter::build(&block, &funded_scripts);
+        let outputs_filter = BlockFilter::build(&block, &[]);
+
+        assert!(!new_scripts.is_empty());
+        for pk_script in new_scripts.iter() {
+            assert!(filter.matches(std::slice::from_ref(pk_script)));
+        }
+        assert!(filter.len() > outputs_filter.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_filter_is_not_built_when_a_spent_output_is_unknown() -> Result<(), NodeError> {
+        let result = BlockFilter::from_block_file(&SPENDING_BLOCK.to_string(), &UtxoSet::new())?;
+
+        assert!(result.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_filter_matches_nothing() -> Result<(), NodeError> {
+        let mut block = testnet_genesis()?;
+        block.transactions.clear();
+
+        let filter = BlockFilter::build(&block, &[]);
+
+        assert_eq!(filter.to_bytes(), vec![0x00]);
+        assert!(!filter.matches(&[vec![0x51]]));
+        Ok(())
+    }
+}
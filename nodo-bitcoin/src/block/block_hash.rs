@@ -1,2 +1,131 @@
+use std::fmt;
+
+use crate::{node_error::NodeError, utils::Utils};
+
 /// Represents a 32-byte block hash.
 pub type BlockHash = [u8; 32];
+
+/// A 32-byte hash in internal byte order: little-endian, the order hashes are computed, stored and
+/// sent over the network in.
+///
+/// It can only be shown to the user after converting it to a `DisplayHash`, so a hash is never
+/// shown or compared with an explorer hash in the wrong order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InternalHash([u8; 32]);
+
+/// A 32-byte hash in display byte order: big-endian, the order block explorers show hashes in.
+///
+/// It's shown as lowercase hex, and must be converted back to an `InternalHash` to be used in the
+/// protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DisplayHash([u8; 32]);
+
+impl InternalHash {
+    /// Creates a hash from its bytes in internal byte order.
+    pub fn new(bytes: BlockHash) -> Self {
+        InternalHash(bytes)
+    }
+
+    /// Creates a hash from a slice in internal byte order, such as a `TxHash`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToConvert` if the slice is not 32 bytes long.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, NodeError> {
+        let bytes: BlockHash = bytes.try_into().map_err(|_| {
+            NodeError::FailedToConvert(format!("A hash has 32 bytes, not {}", bytes.len()))
+        })?;
+        Ok(InternalHash(bytes))
+    }
+
+    /// Returns the bytes of the hash, in internal byte order.
+    pub fn as_bytes(&self) -> &BlockHash {
+        &self.0
+    }
+
+    /// Returns the bytes of the hash as a vector, in internal byte order.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+
+    /// Converts the hash to display byte order.
+    pub fn to_display(&self) -> DisplayHash {
+        let mut bytes = self.0;
+        bytes.reverse();
+        DisplayHash(bytes)
+    }
+}
+
+impl DisplayHash {
+    /// Parses a hash as shown by block explorers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidHexString` if the string is not hex, or a
+    /// `NodeError::FailedToConvert` if it's not a 32-byte hash.
+    pub fn from_hex(hex: &str) -> Result<Self, NodeError> {
+        let bytes = Utils::hex_string_to_bytes(hex.to_string())?;
+        let bytes: BlockHash = bytes.as_slice().try_into().map_err(|_| {
+            NodeError::FailedToConvert(format!("A hash has 32 bytes, not {}", bytes.len()))
+        })?;
+        Ok(DisplayHash(bytes))
+    }
+
+    /// Returns the bytes of the hash, in display byte order.
+    pub fn as_bytes(&self) -> &BlockHash {
+        &self.0
+    }
+
+    /// Converts the hash to internal byte order.
+    pub fn to_internal(&self) -> InternalHash {
+        let mut bytes = self.0;
+        bytes.reverse();
+        InternalHash(bytes)
+    }
+}
+
+impl fmt::Display for DisplayHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Utils::bytes_to_hex(&self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::block_header::BlockHeader;
+
+    use super::*;
+
+    const BLOCK_HASH: &str = "000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b";
+
+    #[test]
+    fn test_hash_conversions_round_trip() -> Result<(), NodeError> {
+        let display_hash = DisplayHash::from_hex(BLOCK_HASH)?;
+        let internal_hash = display_hash.to_internal();
+
+        assert_eq!(internal_hash.to_display(), display_hash);
+        assert_eq!(
+            InternalHash::from_slice(&internal_hash.to_vec())?,
+            internal_hash
+        );
+        assert_eq!(display_hash.to_string(), BLOCK_HASH);
+        assert_eq!(internal_hash.as_bytes()[31], 0x00);
+        assert!(InternalHash::from_slice(&[0; 20]).is_err());
+        assert!(DisplayHash::from_hex("0011").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_hash_of_a_block_matches_explorers() -> Result<(), NodeError> {
+        let block_data = fs::read(format!("blocks-test/{}.bin", BLOCK_HASH))
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let block_header = BlockHeader::from_bytes(&block_data[..80].to_vec())?;
+
+        let internal_hash = InternalHash::from_slice(block_header.hash())?;
+
+        assert_eq!(internal_hash.to_display().to_string(), BLOCK_HASH);
+        Ok(())
+    }
+}
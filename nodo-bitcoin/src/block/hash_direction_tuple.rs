@@ -1,3 +1,4 @@
-use super::tx_hash::TxHash;
+use super::block_hash::DisplayHash;
 
-pub type HashDirectionTuple = (TxHash, String);
+/// A hash of a merkle proof, as shown by block explorers, and the side of its parent it's on.
+pub type HashDirectionTuple = (DisplayHash, String);
@@ -66,9 +66,24 @@ impl MerkleTree {
     }
 
     /// Creates a new Merkle Tree from a list of transaction hashes.
+    ///
+    /// A block with only the coinbase transaction has a tree with a single level, where the
+    /// merkle root is the coinbase txid (the hash is not duplicated nor hashed).
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidMerkleTree` if the list of hashes is empty.
     pub fn new_from_hashes(hashes: &mut Vec<TxHash>) -> Result<Self, NodeError> {
         let mut merkle_tree = MerkleTree::new();
-        Self::build_merkle_tree_from_hashes(hashes, &mut merkle_tree)?;
+        match hashes.len() {
+            0 => {
+                return Err(NodeError::InvalidMerkleTree(
+                    "Can not build a merkle tree without hashes".to_string(),
+                ))
+            }
+            1 => merkle_tree.push(hashes),
+            _ => Self::build_merkle_tree_from_hashes(hashes, &mut merkle_tree)?,
+        }
         Ok(merkle_tree)
     }
 
@@ -88,6 +103,65 @@ impl MerkleTree {
     }
 }
 
+/// Computes a Merkle Root from transaction hashes pushed one at a time, keeping only the root of
+/// each complete subtree instead of every level of the tree.
+#[derive(Debug, Default)]
+pub struct MerkleRootBuilder {
+    /// The roots of the complete subtrees with the height of each one, the highest first.
+    subtrees: Vec<(u32, TxHash)>,
+}
+
+impl MerkleRootBuilder {
+    /// Creates a builder without transaction hashes.
+    pub fn new() -> Self {
+        MerkleRootBuilder {
+            subtrees: Vec::new(),
+        }
+    }
+
+    /// Adds the next transaction hash, merging the subtrees of the same height.
+    pub fn push(&mut self, tx_hash: TxHash) {
+        let (mut height, mut root) = (0, tx_hash);
+        while let Some((left_height, left)) = self.subtrees.pop() {
+            if left_height != height {
+                self.subtrees.push((left_height, left));
+                break;
+            }
+            root = hash_pair(&left, &root);
+            height += 1;
+        }
+        self.subtrees.push((height, root));
+    }
+
+    /// Returns the Merkle Root of the pushed transaction hashes. The last hash of a level with an
+    /// odd number of hashes is duplicated, as in `MerkleTree::new_from_hashes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidMerkleTree` if no hash was pushed.
+    pub fn finalize(mut self) -> Result<TxHash, NodeError> {
+        let (mut height, mut root) = self.subtrees.pop().ok_or_else(|| {
+            NodeError::InvalidMerkleTree("Can not build a merkle root without hashes".to_string())
+        })?;
+        while let Some((left_height, left)) = self.subtrees.pop() {
+            while height < left_height {
+                root = hash_pair(&root, &root);
+                height += 1;
+            }
+            root = hash_pair(&left, &root);
+            height += 1;
+        }
+        Ok(root)
+    }
+}
+
+/// Hashes two sibling nodes into their parent.
+fn hash_pair(left: &TxHash, right: &TxHash) -> TxHash {
+    sha256d::Hash::hash(&[left.as_slice(), right.as_slice()].concat())
+        .to_byte_array()
+        .to_vec()
+}
+
 fn impar_tx_hashes(tx_hashes: &Vec<TxHash>) -> bool {
     tx_hashes.len() % 2 != 0
 }
@@ -115,7 +189,7 @@ mod test {
 
     use crate::{
         block::retrieve_transaction_ids, block_header::BlockHeader, compact_size::CompactSize,
-        connectors::peer_connector::receive_message, constants::LENGTH_BLOCK_HEADERS,
+        connectors::peer_connector::receive_message, constants::LENGTH_BLOCK_HEADERS, utils::Utils,
     };
 
     use std::{
@@ -201,4 +275,77 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_streamed_merkle_root_matches_the_merkle_tree() -> Result<(), NodeError> {
+        for count in [1, 2, 3, 4, 5, 6, 7, 8, 9, 13, 16, 17, 112] {
+            let mut tx_ids: Vec<TxHash> = (0..count as u32)
+                .map(|i| {
+                    sha256d::Hash::hash(&i.to_le_bytes())
+                        .to_byte_array()
+                        .to_vec()
+                })
+                .collect();
+            let mut builder = MerkleRootBuilder::new();
+            for tx_id in tx_ids.iter() {
+                builder.push(tx_id.clone());
+            }
+
+            let streamed_root = builder.finalize()?;
+
+            let merkle_tree = MerkleTree::new_from_hashes(&mut tx_ids)?;
+            assert_eq!(&streamed_root, merkle_tree.root(), "{} transactions", count);
+        }
+        assert!(MerkleRootBuilder::new().finalize().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merkle_tree_with_only_coinbase_transaction() -> Result<(), NodeError> {
+        let coinbase_tx_id = Utils::hex_string_to_bytes(
+            "3ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a".to_string(),
+        )?;
+
+        let merkle_tree = MerkleTree::new_from_hashes(&mut vec![coinbase_tx_id.clone()])?;
+
+        assert_eq!(merkle_tree.root(), &coinbase_tx_id);
+        assert_eq!(merkle_tree.levels(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merkle_tree_with_two_transactions() -> Result<(), NodeError> {
+        let mut tx_ids = vec![vec![1; 32], vec![2; 32]];
+
+        let merkle_tree = MerkleTree::new_from_hashes(&mut tx_ids)?;
+
+        let expected_root = Utils::hex_string_to_bytes(
+            "39ce20bede82c96b8908bec4a157b09c549b3db90b9b474bda9ae9b9030310b4".to_string(),
+        )?;
+        assert_eq!(merkle_tree.root(), &expected_root);
+        assert_eq!(merkle_tree.levels(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merkle_tree_with_three_transactions_duplicates_last_hash() -> Result<(), NodeError> {
+        let mut tx_ids = vec![vec![1; 32], vec![2; 32], vec![3; 32]];
+
+        let merkle_tree = MerkleTree::new_from_hashes(&mut tx_ids)?;
+
+        let expected_root = Utils::hex_string_to_bytes(
+            "223e023fadf1f053df26988871f893c821c28edf77d64a955e6c2a02d547bdac".to_string(),
+        )?;
+        assert_eq!(merkle_tree.root(), &expected_root);
+        assert_eq!(merkle_tree.levels(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merkle_tree_without_transactions_is_invalid() {
+        assert!(MerkleTree::new_from_hashes(&mut vec![]).is_err());
+    }
 }
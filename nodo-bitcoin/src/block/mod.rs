@@ -1,28 +1,216 @@
-use glib::Sender;
-
 use crate::{
     block_header::{block_header_bytes::BlockHeaderBytes, BlockHeader},
+    channels::event_sink::{EventSink, NodeEvent},
     compact_size::CompactSize,
     config::obtain_dir_path,
     connectors::peer_connector::receive_message,
-    constants::{BLOCK_HEADERS_FILE, LENGTH_BLOCK_HEADERS},
+    constants::{
+        BIP34_MIN_BLOCK_VERSION, BLOCK_HEADERS_FILE, INITIAL_BLOCK_SUBSIDY, LENGTH_BLOCK_HEADERS,
+        MAX_FUTURE_BLOCK_TIME, OP_0, OP_1, OP_16, SUBSIDY_HALVING_INTERVAL,
+        TESTNET_CHECKPOINT_HEIGHT, WITNESS_COMMITMENT_HEADER, WITNESS_COMMITMENT_LENGTH,
+    },
+    messages::merkle_block_message::MerkleBlockMessage,
+    node::read::{
+        read_block_height, retrieve_bip34_height, retrieve_full_validation,
+        retrieve_max_open_block_files, retrieve_max_reorg_depth,
+    },
     node_error::NodeError,
-    transactions::transaction::Transaction,
-    ui::ui_message::UIMessage,
+    transactions::{
+        outpoint::Outpoint,
+        script_interpreter::{is_p2pkh, verify_p2pkh_input},
+        transaction::Transaction,
+        tx_output::TxOutput,
+        utxo_set::UtxoSet,
+    },
+    utils::semaphore::Semaphore,
 };
 
+use bitcoin_hashes::{sha256d, Hash};
 use std::{
+    collections::HashMap,
     fs::{self, File, OpenOptions},
-    io::{Cursor, Read, Write},
+    io::{BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Mutex, MutexGuard, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use self::{merkle_tree::MerkleTree, tx_hash::TxHash};
+use self::{
+    block_hash::{BlockHash, DisplayHash, InternalHash},
+    merkle_tree::MerkleTree,
+    tx_hash::TxHash,
+    validated_blocks::mark_block_as_validated,
+};
 
+pub mod block_filter;
 pub mod block_hash;
 pub mod hash_direction_tuple;
 pub mod merkle_tree;
 pub mod proof_of_inclusion;
 pub mod tx_hash;
+pub mod validated_blocks;
+
+/// A block with its header and its transactions, as it's stored on disk.
+#[derive(Debug, Clone)]
+pub struct Block {
+    /// The header of the block.
+    pub header: BlockHeader,
+    /// The transactions of the block, starting with the coinbase.
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    /// Parses a block from its bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_data` - A slice of bytes containing the block data.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the parsed `Block`, or a `NodeError` if the data is not a valid block.
+    pub fn from_bytes(block_data: &[u8]) -> Result<Block, NodeError> {
+        let mut cursor = Cursor::new(block_data);
+        let block_header_bytes = receive_message(&mut cursor, LENGTH_BLOCK_HEADERS)?;
+        let header = BlockHeader::from_bytes(&block_header_bytes)?;
+        let txs_count = CompactSize::read_varint(&mut cursor)?;
+        let transactions = retrieve_transactions(&mut cursor, txs_count.get_value())?;
+
+        Ok(Block {
+            header,
+            transactions,
+        })
+    }
+
+    /// Serializes the block as it's sent on the wire and stored on disk: the header, the
+    /// transaction count and the transactions.
+    ///
+    /// # Returns
+    ///
+    /// A vector of bytes representing the block.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.to_bytes();
+        bytes.extend(CompactSize::new(self.transactions.len()).to_bytes());
+        for transaction in self.transactions.iter() {
+            bytes.extend(transaction.to_bytes());
+        }
+        bytes
+    }
+
+    /// Serializes the block without the witnesses of its transactions, as it's sent to peers that
+    /// request it with `MSG_BLOCK` instead of `MSG_WITNESS_BLOCK` (BIP144).
+    ///
+    /// # Returns
+    ///
+    /// A vector of bytes representing the block without witnesses.
+    pub fn to_bytes_no_witness(&self) -> Vec<u8> {
+        let mut bytes = self.header.to_bytes();
+        bytes.extend(CompactSize::new(self.transactions.len()).to_bytes());
+        for transaction in self.transactions.iter() {
+            bytes.extend(transaction.to_bytes_no_witness());
+        }
+        bytes
+    }
+
+    /// Returns the coinbase transaction of the block, the first one.
+    pub fn coinbase(&self) -> Option<&Transaction> {
+        self.transactions.first()
+    }
+
+    /// Reads the height of the block from the first push of its coinbase signature script (BIP34):
+    /// a little-endian number of up to 4 bytes, or OP_0 to OP_16 for the first heights.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::MissingCoinbaseHeight` if the block has no coinbase or the push can't
+    /// be trusted to be its height, as in the blocks before BIP34, see `bip34_height`.
+    pub fn height_from_coinbase(&self) -> Result<u32, NodeError> {
+        let coinbase = self.coinbase().ok_or_else(|| {
+            NodeError::MissingCoinbaseHeight("The block has no coinbase".to_string())
+        })?;
+        bip34_height(&self.header, coinbase, retrieve_bip34_height())
+    }
+
+    /// Returns the data the miner put in the coinbase script after the height of the block (BIP34),
+    /// such as the tag of its pool. Printable ASCII characters are kept and any other byte is
+    /// escaped as `\xNN`.
+    pub fn coinbase_message(&self) -> String {
+        let script = match self
+            .coinbase()
+            .and_then(|coinbase| coinbase.tx_inputs.first())
+        {
+            Some(coinbase_input) => &coinbase_input.signature_script,
+            None => return String::new(),
+        };
+        let height_push_length = read_height_push(script).map_or(0, |(_, length)| length);
+
+        script
+            .iter()
+            .skip(height_push_length)
+            .map(|byte| match byte {
+                0x20..=0x7e => (*byte as char).to_string(),
+                _ => format!("\\x{:02x}", byte),
+            })
+            .collect()
+    }
+
+    /// Verifies the witness commitment of the block (BIP141): the last coinbase output starting with
+    /// the commitment header must hold the hash of the witness merkle root and the witness reserved
+    /// value, the only item of the coinbase witness. Blocks without witness data are not checked.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidWitnessCommitment` if the block has witness data and the
+    /// commitment is missing or doesn't match it.
+    pub fn verify_witness_commitment(&self) -> Result<(), NodeError> {
+        if !self.transactions.iter().any(|tx| tx.has_witness()) {
+            return Ok(());
+        }
+        let coinbase = self.coinbase().ok_or(NodeError::InvalidWitnessCommitment(
+            "The block has no coinbase".to_string(),
+        ))?;
+        let commitment = coinbase
+            .tx_outputs()
+            .iter()
+            .rev()
+            .map(|tx_output| &tx_output.pk_script)
+            .find(|pk_script| {
+                pk_script.len() >= WITNESS_COMMITMENT_LENGTH
+                    && pk_script.starts_with(&WITNESS_COMMITMENT_HEADER)
+            })
+            .ok_or(NodeError::InvalidWitnessCommitment(
+                "The coinbase has no witness commitment".to_string(),
+            ))?;
+        let reserved_value = match coinbase.witnesses.first().map(|witness| witness.as_slice()) {
+            Some([reserved_value]) if reserved_value.len() == 32 => reserved_value,
+            _ => {
+                return Err(NodeError::InvalidWitnessCommitment(
+                    "The coinbase witness must be a 32 byte reserved value".to_string(),
+                ))
+            }
+        };
+
+        let mut wtxids: Vec<TxHash> = vec![vec![0; 32]];
+        wtxids.extend(
+            self.transactions
+                .iter()
+                .skip(1)
+                .map(|tx| tx.witness_tx_id()),
+        );
+        let mut bytes = compute_merkle_root(&wtxids)?;
+        bytes.extend(reserved_value);
+        let expected_commitment = sha256d::Hash::hash(&bytes).to_byte_array();
+
+        if commitment[WITNESS_COMMITMENT_HEADER.len()..WITNESS_COMMITMENT_LENGTH]
+            != expected_commitment
+        {
+            return Err(NodeError::InvalidWitnessCommitment(
+                "The witness commitment doesn't match the witnesses of the block".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
 
 /// Validates a block's Merkle Root.
 ///
@@ -51,6 +239,22 @@ pub fn validate_merkle_root(
     Ok(())
 }
 
+/// Computes the merkle root of a list of transaction ids, without keeping the intermediate tree.
+///
+/// A single transaction id is its own merkle root.
+///
+/// # Arguments
+///
+/// * `txids` - The ids of the transactions, in the order they appear in the block.
+///
+/// # Returns
+///
+/// A `Result` containing the merkle root, or a `NodeError::InvalidMerkleTree` if the list is empty.
+pub fn compute_merkle_root(txids: &[TxHash]) -> Result<TxHash, NodeError> {
+    let merkle_tree = MerkleTree::new_from_hashes(&mut txids.to_vec())?;
+    Ok(merkle_tree.root().clone())
+}
+
 /// Validates the proof-of-work of a block.
 ///
 /// # Arguments
@@ -61,11 +265,10 @@ pub fn validate_merkle_root(
 ///
 /// A `Result` indicating the result of the validation. If the proof-of-work is valid, the `Result` will be `Ok`, if its not it will return a `NodeError`.
 pub fn validate_proof_of_work(block_header: &BlockHeader) -> Result<(), NodeError> {
-    let target_threshold = block_header.calculate_target_threshold().to_vec();
-    let mut hash = block_header.hash().clone();
-    hash.reverse();
+    let target_threshold = block_header.calculate_target_threshold();
+    let hash = InternalHash::from_slice(block_header.hash())?.to_display();
 
-    if hash > target_threshold {
+    if hash.as_bytes() > &target_threshold {
         return Err(NodeError::InvalidProofOfWork(
             "Invalid target threshold".to_string(),
         ));
@@ -73,6 +276,37 @@ pub fn validate_proof_of_work(block_header: &BlockHeader) -> Result<(), NodeErro
 
     Ok(())
 }
+
+/// Validates that the timestamp of a block is not too far in the future.
+///
+/// # Arguments
+///
+/// * `block_header` - A reference to the block header to validate.
+/// * `now` - The current time, as a Unix timestamp.
+///
+/// # Returns
+///
+/// `Ok` if the block timestamp is at most `MAX_FUTURE_BLOCK_TIME` seconds ahead of `now`, or a
+/// `NodeError::TimestampTooFuture` otherwise.
+pub fn validate_timestamp(block_header: &BlockHeader, now: u32) -> Result<(), NodeError> {
+    if !block_header.is_timestamp_not_too_future(now) {
+        return Err(NodeError::TimestampTooFuture(format!(
+            "Block timestamp {} is more than {} seconds ahead of {}",
+            block_header.timestamp, MAX_FUTURE_BLOCK_TIME, now
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the current time as a Unix timestamp.
+pub fn current_timestamp() -> Result<u32, NodeError> {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| NodeError::FailedToGetDate("System time is before 1970".to_string()))?;
+    Ok(since_epoch.as_secs() as u32)
+}
+
 /// Validates a block.
 ///
 /// # Arguments
@@ -87,10 +321,103 @@ pub fn validate_block(
     block_header: &BlockHeader,
     block_txs: &mut Vec<TxHash>,
 ) -> Result<(), NodeError> {
+    validate_timestamp(block_header, current_timestamp()?)?;
     validate_proof_of_work(block_header)?;
     validate_merkle_root(block_header, block_txs)
 }
 
+/// Reads the height of a block from the first push of the signature script of its coinbase
+/// transaction (BIP34). Before BIP34 the coinbase could start with any data, so the push is only
+/// trusted in the blocks of version 2 or later, and only if it's not below the activation height.
+///
+/// # Arguments
+///
+/// * `block_header` - The header of the block.
+/// * `coinbase` - The coinbase transaction of the block.
+/// * `activation_height` - The height BIP34 activated at in the network.
+///
+/// # Errors
+///
+/// Returns a `NodeError::MissingCoinbaseHeight` if the block is older than BIP34, or the
+/// transaction has no input or its script doesn't start with the height.
+fn bip34_height(
+    block_header: &BlockHeader,
+    coinbase: &Transaction,
+    activation_height: u32,
+) -> Result<u32, NodeError> {
+    if block_header.version < BIP34_MIN_BLOCK_VERSION {
+        return Err(NodeError::MissingCoinbaseHeight(
+            "The block version is older than BIP34".to_string(),
+        ));
+    }
+    let height = coinbase_height(coinbase)?;
+    if height < activation_height {
+        return Err(NodeError::MissingCoinbaseHeight(
+            "The height is below the activation of BIP34".to_string(),
+        ));
+    }
+    Ok(height)
+}
+
+/// Returns the height of a block file: the one in its coinbase (BIP34) if it can be trusted, or
+/// its height in the header chain otherwise. It's None if neither is known.
+///
+/// Only the header and the coinbase are read from the file.
+///
+/// # Arguments
+///
+/// * `path` - The path of the block file.
+pub fn retrieve_block_height(path: &String) -> Option<u32> {
+    let mut block = BufReader::new(open_block_file(path).ok()?);
+    let block_header_bytes = receive_message(&mut block, LENGTH_BLOCK_HEADERS).ok()?;
+    let block_header = BlockHeader::from_bytes(&block_header_bytes).ok()?;
+    let coinbase_height = CompactSize::read_varint(&mut block)
+        .and_then(|_| Transaction::read_transaction(&mut block))
+        .and_then(|coinbase| bip34_height(&block_header, &coinbase, retrieve_bip34_height()));
+    match coinbase_height {
+        Ok(height) => Some(height),
+        Err(_) => read_block_height(&block_header).ok().flatten(),
+    }
+}
+
+/// Reads the height of a block from the first push of the signature script of its coinbase
+/// transaction, without checking the block is from after BIP34, see `bip34_height`.
+///
+/// # Errors
+///
+/// Returns a `NodeError::MissingCoinbaseHeight` if the transaction has no input or its script doesn't
+/// start with the height.
+fn coinbase_height(coinbase: &Transaction) -> Result<u32, NodeError> {
+    let signature_script = coinbase
+        .tx_inputs
+        .first()
+        .map(|coinbase_input| &coinbase_input.signature_script)
+        .ok_or_else(|| NodeError::MissingCoinbaseHeight("The coinbase has no input".to_string()))?;
+
+    read_height_push(signature_script).map(|(height, _)| height)
+}
+
+/// Reads the push of the height at the start of a coinbase signature script, returning the height
+/// and the number of bytes the push takes.
+fn read_height_push(signature_script: &[u8]) -> Result<(u32, usize), NodeError> {
+    match signature_script.first() {
+        Some(&OP_0) => Ok((0, 1)),
+        Some(&opcode) if (OP_1..=OP_16).contains(&opcode) => Ok(((opcode - OP_1 + 1) as u32, 1)),
+        Some(&length @ 1..=4) => {
+            let length = length as usize;
+            let height_bytes = signature_script.get(1..=length).ok_or_else(|| {
+                NodeError::MissingCoinbaseHeight("The height push is truncated".to_string())
+            })?;
+            let mut height = [0; 4];
+            height[..length].copy_from_slice(height_bytes);
+            Ok((u32::from_le_bytes(height), 1 + length))
+        }
+        _ => Err(NodeError::MissingCoinbaseHeight(
+            "The coinbase doesn't start with the push of the height".to_string(),
+        )),
+    }
+}
+
 /// Retrieves transaction IDs from a TCP stream.
 ///
 /// # Arguments
@@ -101,10 +428,20 @@ pub fn validate_block(
 /// # Returns
 ///
 /// A `Result` containing a vector of transaction IDs.
+///
+/// # Errors
+///
+/// Returns a `NodeError::InvalidMessageFormat` if the count is zero, as every block has a coinbase
+/// transaction.
 pub fn retrieve_transaction_ids<R: Read>(
     source: &mut R,
     txs_count: u64,
 ) -> Result<Vec<TxHash>, NodeError> {
+    if txs_count == 0 {
+        return Err(NodeError::InvalidMessageFormat(
+            "The block has no transactions".to_string(),
+        ));
+    }
     let mut transaction_ids = Vec::new();
 
     let mut transaction = Transaction::read_coinbase_transaction(source)?;
@@ -124,6 +461,7 @@ pub fn retrieve_transaction_ids<R: Read>(
 /// Given the number of transactions to read (`txs_count_value`) and a file
 /// handle (`file`), this function reads the specified number of transactions
 /// from the file and returns them as a vector of `Transaction` objects.
+/// Each transaction must pass `Transaction::validate_structure`.
 ///
 /// # Arguments
 ///
@@ -142,26 +480,94 @@ pub fn retrieve_transactions<R: Read>(
     let mut transactions = Vec::new();
     for _ in 0..txs_count_value {
         let transaction = Transaction::read_transaction(source)?;
+        transaction.validate_structure()?;
         transactions.push(transaction);
     }
     Ok(transactions)
 }
 
+/// The outcome of saving a block that was valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockSave {
+    /// The block was written to disk.
+    Saved,
+    /// An identical block file was already stored, so nothing was written.
+    AlreadySaved,
+}
+
 /// Writes a block to disk.
+///
+/// If a file already exists at the given path, it is compared with the block data: an identical
+/// file is not an error, since the same block might be saved concurrently by the downloaders and
+/// the listeners, but it's reported as `BlockSave::AlreadySaved` so the block is not applied twice.
+/// At most `MAX_OPEN_BLOCK_FILES` blocks are written at the same time.
 /// # Arguments
 /// * `block_data` - A vector of bytes containing the block data.
 /// * `path` - A reference to a string containing the path to the blocks directory.
 /// # Returns
-/// A `Result` indicating the result of the writing. If the block is valid, the `Result` will be `Ok`, if its not it will return a `NodeError`.
-fn write_block_to_disk(block_data: Vec<u8>, path: &String) -> Result<(), NodeError> {
-    let mut file = fs::OpenOptions::new()
+/// A `Result` with the `BlockSave` outcome of the writing, or a `NodeError` if the block could not be
+/// written or a different block file is stored at the path.
+fn write_block_to_disk(block_data: Vec<u8>, path: &String) -> Result<BlockSave, NodeError> {
+    write_block_to_disk_limited(block_data, path, block_writes_semaphore())
+}
+
+/// Writes a block to disk like `write_block_to_disk`, holding a permit of the given semaphore
+/// while the file is written.
+fn write_block_to_disk_limited(
+    block_data: Vec<u8>,
+    path: &String,
+    block_writes: &Semaphore,
+) -> Result<BlockSave, NodeError> {
+    let _permit = block_writes.acquire()?;
+    let mut file = match fs::OpenOptions::new()
         .create_new(true)
         .write(true)
         .open(path)
-        .map_err(|_| NodeError::FailedToOpenFile("Failed to open file".to_string()))?;
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            check_existing_block_file(&block_data, path)?;
+            return Ok(BlockSave::AlreadySaved);
+        }
+        Err(_) => {
+            return Err(NodeError::FailedToOpenFile(
+                "Failed to open file".to_string(),
+            ))
+        }
+    };
 
     file.write_all(&block_data)
         .map_err(|_| NodeError::FailedToWrite("Failed to write block to file".to_string()))?;
+    Ok(BlockSave::Saved)
+}
+
+/// Returns the semaphore that bounds how many block files are written at the same time, sized
+/// with `MAX_OPEN_BLOCK_FILES` the first time it's used.
+fn block_writes_semaphore() -> &'static Semaphore {
+    static BLOCK_WRITES: OnceLock<Semaphore> = OnceLock::new();
+    BLOCK_WRITES.get_or_init(|| Semaphore::new(retrieve_max_open_block_files()))
+}
+
+/// Checks that the block file already stored at the given path matches the block data.
+/// # Arguments
+/// * `block_data` - A reference to the bytes of the block that was going to be saved.
+/// * `path` - A reference to a string containing the path of the existing block file.
+/// # Returns
+/// `Ok` if the existing file has the same size and hash as the block data, or a
+/// `NodeError::BlockFileMismatch` if a different file is stored at the path.
+fn check_existing_block_file(block_data: &[u8], path: &String) -> Result<(), NodeError> {
+    let existing_data = fs::read(path)
+        .map_err(|_| NodeError::FailedToRead("Failed to read existing block file".to_string()))?;
+
+    if existing_data.len() != block_data.len()
+        || sha256d::Hash::hash(&existing_data) != sha256d::Hash::hash(block_data)
+    {
+        return Err(NodeError::BlockFileMismatch(format!(
+            "A different block file already exists at {}",
+            path
+        )));
+    }
+
     Ok(())
 }
 
@@ -185,11 +591,35 @@ fn write_block_to_disk(block_data: Vec<u8>, path: &String) -> Result<(), NodeErr
 /// * `FailedToWriteAll` - If the function fails to write the block header to the file.
 pub fn write_block_header_to_file(block_header: &BlockHeaderBytes) -> Result<(), NodeError> {
     let dir_headers_file = obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?;
+    append_block_header(&dir_headers_file, block_header)?;
+    Ok(())
+}
+
+/// Appends a block header to the headers file at the given path.
+///
+/// # Returns
+///
+/// The height of the appended header, derived from the length of the file: the headers file starts
+/// with the genesis block, at height 0.
+fn append_block_header(
+    headers_path: &str,
+    block_header: &BlockHeaderBytes,
+) -> Result<u64, NodeError> {
+    let _headers_file_guard = lock_headers_file()?;
+    append_block_header_locked(headers_path, block_header)
+}
+
+/// Appends a block header to the headers file at the given path, which the caller must have locked
+/// with `lock_headers_file`.
+fn append_block_header_locked(
+    headers_path: &str,
+    block_header: &BlockHeaderBytes,
+) -> Result<u64, NodeError> {
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
         .append(true)
-        .open(dir_headers_file)
+        .open(headers_path)
         .map_err(|_| {
             NodeError::FailedToOpenFile("Failed to open block headers file".to_string())
         })?;
@@ -198,33 +628,395 @@ pub fn write_block_header_to_file(block_header: &BlockHeaderBytes) -> Result<(),
         NodeError::FailedToWriteAll("Failed to write block header to file".to_string())
     })?;
 
-    Ok(())
+    let headers_file_len = file
+        .metadata()
+        .map_err(|_| NodeError::FailedToRead("Failed to obtain headers file len".to_string()))?
+        .len();
+    Ok((headers_file_len / LENGTH_BLOCK_HEADERS as u64).saturating_sub(1))
 }
 
-/// Handles a new block message.
+/// Checks that a block extends the tip of the chain of the headers file.
+///
+/// A block extending the tip rewinds nothing and is accepted, as is any block when no header is stored
+/// yet. The node can't rewind the headers file, so every other block is refused: forks deeper than the
+/// maximum reorg depth or below the checkpoint as too deep, shallower ones as unsupported, and blocks
+/// whose parent is not stored as unknown. Only the last `max_reorg_depth + 1` headers are searched
+/// while the headers file is locked; the older ones are only read to tell a deep fork from an unknown
+/// parent, after the lock is released.
 ///
 /// # Arguments
-/// * `block_data` - A vector of bytes containing the block data.
-/// * `path` - A reference to a string containing the path to the blocks directory.
+///
+/// * `headers_path` - The path of the block headers file.
+/// * `parent_hash` - The hash of the parent of the block, as stored in its header.
+/// * `max_reorg_depth` - The maximum number of blocks the chain may be rewound.
+///
+/// # Errors
+///
+/// Returns a `NodeError::ReorgTooDeep` if switching to the branch of the block would rewind more
+/// than `max_reorg_depth` blocks or below the checkpoint, a `NodeError::UnsupportedReorg` if it
+/// would rewind fewer blocks, a `NodeError::UnknownParent` if its parent is not stored, or a
+/// `NodeError` if the headers file could not be read.
+pub fn check_reorg_depth(
+    headers_path: &str,
+    parent_hash: &BlockHash,
+    max_reorg_depth: u64,
+) -> Result<(), NodeError> {
+    let headers_file_guard = lock_headers_file()?;
+    let recent_headers = read_recent_headers(headers_path, max_reorg_depth.saturating_add(1))?;
+    let fork_check = check_fork_depth(&recent_headers, parent_hash, max_reorg_depth);
+    drop(headers_file_guard);
+    fork_check
+        .map_err(|error| classify_unknown_parent(error, headers_path, parent_hash, &recent_headers))
+}
+
+/// The last headers of the headers file, read while the file is locked.
+struct RecentHeaders {
+    /// The number of headers stored in the file.
+    headers_count: u64,
+    /// The hashes of the last headers, from the oldest to the tip.
+    hashes: Vec<BlockHash>,
+}
+
+impl RecentHeaders {
+    /// Returns the height of the first of the last headers.
+    fn first_height(&self) -> u64 {
+        self.headers_count - self.hashes.len() as u64
+    }
+}
+
+/// Reads the hashes of up to the last `count` headers of the headers file at the given path, which
+/// the caller must have locked with `lock_headers_file`. A missing file has no headers.
+fn read_recent_headers(headers_path: &str, count: u64) -> Result<RecentHeaders, NodeError> {
+    let mut file = match File::open(headers_path) {
+        Ok(file) => file,
+        Err(_) => {
+            return Ok(RecentHeaders {
+                headers_count: 0,
+                hashes: Vec::new(),
+            })
+        }
+    };
+    let headers_count = file
+        .seek(SeekFrom::End(0))
+        .map_err(|_| NodeError::FailedToRead("Failed to seek end of headers file".to_string()))?
+        / LENGTH_BLOCK_HEADERS as u64;
+
+    let scanned_count = count.min(headers_count);
+    let first_scanned = headers_count - scanned_count;
+    let mut recent_headers = vec![0u8; scanned_count as usize * LENGTH_BLOCK_HEADERS];
+    file.seek(SeekFrom::Start(first_scanned * LENGTH_BLOCK_HEADERS as u64))
+        .and_then(|_| file.read_exact(&mut recent_headers))
+        .map_err(|_| NodeError::FailedToRead("Failed to read the last headers".to_string()))?;
+
+    Ok(RecentHeaders {
+        headers_count,
+        hashes: recent_headers
+            .chunks(LENGTH_BLOCK_HEADERS)
+            .map(|header| sha256d::Hash::hash(header).to_byte_array())
+            .collect(),
+    })
+}
+
+/// Checks the fork a block extending the given parent would create against the last headers, see
+/// `check_reorg_depth`. A parent that is not among them is reported as unknown.
+fn check_fork_depth(
+    recent_headers: &RecentHeaders,
+    parent_hash: &BlockHash,
+    max_reorg_depth: u64,
+) -> Result<(), NodeError> {
+    let headers_count = recent_headers.headers_count;
+    if headers_count == 0 {
+        return Ok(());
+    }
+    let first_scanned = recent_headers.first_height();
+
+    let fork_height = match recent_headers
+        .hashes
+        .iter()
+        .rposition(|hash| hash == parent_hash)
+    {
+        Some(offset) => first_scanned + offset as u64,
+        None => {
+            return Err(NodeError::UnknownParent(format!(
+                "Refusing a block whose parent is not among the last {} headers",
+                recent_headers.hashes.len()
+            )))
+        }
+    };
+    let depth = headers_count - 1 - fork_height;
+    if depth == 0 {
+        return Ok(());
+    }
+    if fork_height < TESTNET_CHECKPOINT_HEIGHT {
+        let msg = format!(
+            "Refusing to rewind {} blocks to the fork at height {} (max reorg depth {}, checkpoint at {})",
+            depth, fork_height, max_reorg_depth, TESTNET_CHECKPOINT_HEIGHT
+        );
+        println!("Warning: {}", msg);
+        return Err(NodeError::ReorgTooDeep(msg));
+    }
+    let msg = format!(
+        "Refusing a block forking the chain {} blocks below the tip, at height {}: the chain can't be rewound",
+        depth, fork_height
+    );
+    println!("Warning: {}", msg);
+    Err(NodeError::UnsupportedReorg(msg))
+}
+
+/// Tells whether a block refused for having an unknown parent forks the chain deeper than the last
+/// headers, by searching the headers stored before them. Those are never rewritten, so the headers
+/// file doesn't need to be locked while they are read.
 ///
 /// # Returns
 ///
-/// A `Result` indicating the result of the handling. If the block is valid, the `Result` will be `Ok`, if its not it will return a `NodeError`.
-pub fn validate_and_save_block(block_data: Vec<u8>, path: &String) -> Result<(), NodeError> {
-    let mut cursor = Cursor::new(&block_data);
+/// A `NodeError::ReorgTooDeep` if the parent is stored before the last headers, or the given error
+/// otherwise.
+fn classify_unknown_parent(
+    error: NodeError,
+    headers_path: &str,
+    parent_hash: &BlockHash,
+    recent_headers: &RecentHeaders,
+) -> NodeError {
+    if !matches!(error, NodeError::UnknownParent(_)) {
+        return error;
+    }
+    match is_header_stored(headers_path, parent_hash, recent_headers.first_height()) {
+        Ok(true) => {
+            let msg = format!(
+                "Refusing a block whose parent is deeper than the last {} headers",
+                recent_headers.hashes.len()
+            );
+            println!("Warning: {}", msg);
+            NodeError::ReorgTooDeep(msg)
+        }
+        Ok(false) => error,
+        Err(read_error) => read_error,
+    }
+}
+
+/// Returns whether a header with the given hash is among the first `count` headers of the headers
+/// file at the given path.
+fn is_header_stored(
+    headers_path: &str,
+    block_hash: &BlockHash,
+    count: u64,
+) -> Result<bool, NodeError> {
+    if count == 0 {
+        return Ok(false);
+    }
+    let file = File::open(headers_path).map_err(|_| {
+        NodeError::FailedToOpenFile("Failed to open block headers file".to_string())
+    })?;
+    let mut reader = BufReader::new(file);
+    let mut header = [0u8; LENGTH_BLOCK_HEADERS];
+    for _ in 0..count {
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block header".to_string()))?;
+        if sha256d::Hash::hash(&header).as_byte_array() == block_hash {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Locks the block headers file, so headers are appended one at a time and readers never see a
+/// header that is only partially written.
+///
+/// Every function that writes to the headers file, or reads its last headers, must hold the
+/// returned guard while it accesses the file.
+///
+/// # Errors
+///
+/// Returns a `NodeError::MutexError` if a thread panicked while holding the lock.
+pub fn lock_headers_file() -> Result<MutexGuard<'static, ()>, NodeError> {
+    static HEADERS_FILE: Mutex<()> = Mutex::new(());
+    HEADERS_FILE
+        .lock()
+        .map_err(|_| NodeError::MutexError("Failed to lock block headers file".to_string()))
+}
+
+/// Parses and validates a block from its bytes, without touching the filesystem.
+///
+/// # Arguments
+/// * `block_data` - A slice of bytes containing the block data.
+///
+/// # Returns
+///
+/// A `Result` containing the block header and the ids of the block transactions if the proof of work
+/// and the merkle root are valid, or a `NodeError` if the block could not be parsed or is invalid.
+pub fn validate_block_bytes(block_data: &[u8]) -> Result<(BlockHeader, Vec<TxHash>), NodeError> {
+    let mut cursor = Cursor::new(block_data);
     let block_header_bytes = receive_message(&mut cursor, LENGTH_BLOCK_HEADERS)?;
     let block_header = BlockHeader::from_bytes(&block_header_bytes)?;
     let txs_count = CompactSize::read_varint(&mut cursor)?;
     let txs_count_value = txs_count.get_value();
     let mut transaction_ids = retrieve_transaction_ids(&mut cursor, txs_count_value)?;
 
-    match validate_block(&block_header, &mut transaction_ids) {
-        Ok(()) => {
-            write_block_to_disk(block_data, path)?;
-            Ok(())
+    validate_block(&block_header, &mut transaction_ids)?;
+
+    Ok((block_header, transaction_ids))
+}
+
+/// Verifies the scripts of every non-coinbase input of a block.
+///
+/// The previous output of each input is looked up in the outputs created earlier in the same block
+/// and then in the UTXO set. Only P2PKH outputs are verified: inputs spending other kinds of scripts,
+/// or outputs that are not known by the node, are skipped.
+///
+/// # Arguments
+/// * `block_data` - A slice of bytes containing the block data.
+/// * `utxo_set` - A reference to the UTXO set of the node.
+///
+/// # Returns
+///
+/// A `Result` indicating whether every verified input spends its previous output correctly, or a
+/// `NodeError::ScriptValidationFailed` if an input does not.
+pub fn validate_block_scripts(block_data: &[u8], utxo_set: &UtxoSet) -> Result<(), NodeError> {
+    let block = Block::from_bytes(block_data)?;
+
+    let mut block_outputs = HashMap::new();
+    for (position, transaction) in block.transactions.iter().enumerate() {
+        if position > 0 {
+            for (i, tx_input) in transaction.tx_inputs.iter().enumerate() {
+                let pk_script =
+                    match previous_output(&tx_input.previous_output, &block_outputs, utxo_set) {
+                        Some(output) if is_p2pkh(&output.pk_script) => output.pk_script,
+                        _ => continue,
+                    };
+
+                match verify_p2pkh_input(transaction, i, &pk_script) {
+                    Ok(()) | Err(NodeError::UnsupportedScript(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
         }
-        Err(e) => Err(e),
+        block_outputs.insert(transaction.tx_id(), transaction.tx_outputs.clone());
     }
+    Ok(())
+}
+
+/// Looks up the output spent by an input, first in the outputs created earlier in the same block
+/// and then in the UTXO set, including the entries it evicted to disk.
+fn previous_output(
+    outpoint: &Outpoint,
+    block_outputs: &HashMap<TxHash, Vec<TxOutput>>,
+    utxo_set: &UtxoSet,
+) -> Option<TxOutput> {
+    block_outputs
+        .get(&outpoint.tx_id)
+        .and_then(|outputs| {
+            outputs
+                .iter()
+                .find(|output| output.index == outpoint.index as u64)
+                .cloned()
+        })
+        .or_else(|| utxo_set.output(outpoint))
+}
+
+/// Returns the block subsidy, in satoshis, for a block at the given height.
+///
+/// The subsidy starts at 50 BTC and is halved every 210000 blocks.
+pub fn block_subsidy(height: u32) -> u64 {
+    let halvings = height / SUBSIDY_HALVING_INTERVAL;
+    if halvings >= 64 {
+        return 0;
+    }
+    INITIAL_BLOCK_SUBSIDY >> halvings
+}
+
+/// Validates that the coinbase of a block doesn't claim more than the subsidy plus the fees.
+///
+/// # Arguments
+///
+/// * `block` - A reference to the block to validate.
+/// * `total_fees` - The sum of the fees of the other transactions of the block, in satoshis.
+/// * `height` - The height of the block.
+///
+/// # Returns
+///
+/// A `Result` indicating whether the coinbase value is valid, or a `NodeError::InvalidCoinbaseValue`
+/// if the block has no coinbase or its outputs add up to more than the subsidy plus the fees. An output
+/// with a negative value is a `NodeError::InvalidTransaction`.
+pub fn validate_coinbase(block: &Block, total_fees: u64, height: u32) -> Result<(), NodeError> {
+    let coinbase = block.coinbase().ok_or_else(|| {
+        NodeError::InvalidCoinbaseValue("Block has no coinbase transaction".to_string())
+    })?;
+    let claimed_value = coinbase.total_output_value()?;
+    let allowed_value = block_subsidy(height) + total_fees;
+
+    if claimed_value > allowed_value {
+        return Err(NodeError::InvalidCoinbaseValue(format!(
+            "Coinbase claims {} satoshis but only {} are allowed",
+            claimed_value, allowed_value
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the sum of the fees of the non-coinbase transactions of a block, in satoshis.
+///
+/// The outputs spent by the block are looked up in the block itself and in the UTXO set, so
+/// `None` is returned if any of them is not known by the node.
+pub fn block_fees(block: &Block, utxo_set: &UtxoSet) -> Option<u64> {
+    let mut block_outputs = HashMap::new();
+    let mut total_fees = 0;
+    for (position, transaction) in block.transactions.iter().enumerate() {
+        if position > 0 {
+            let fee = transaction
+                .fee_spending(|outpoint| previous_output(outpoint, &block_outputs, utxo_set))
+                .ok()?;
+            total_fees += fee.unwrap_or(0);
+        }
+        block_outputs.insert(transaction.tx_id(), transaction.tx_outputs.clone());
+    }
+    Some(total_fees)
+}
+
+/// Verifies that the coinbase of a block commits to the witnesses of the block and respects the
+/// subsidy for its height plus the fees.
+///
+/// The value check is skipped when the fees can't be computed because some spent outputs are not known
+/// by the node, or when the height is not encoded in the coinbase.
+///
+/// # Arguments
+/// * `block_data` - A slice of bytes containing the block data.
+/// * `utxo_set` - A reference to the UTXO set of the node.
+///
+/// # Returns
+///
+/// A `Result` indicating whether the coinbase is valid, or a `NodeError::InvalidWitnessCommitment` or
+/// `NodeError::InvalidCoinbaseValue` if it's not.
+pub fn validate_block_coinbase(block_data: &[u8], utxo_set: &UtxoSet) -> Result<(), NodeError> {
+    let block = Block::from_bytes(block_data)?;
+    block.verify_witness_commitment()?;
+    let (total_fees, height) = match (block_fees(&block, utxo_set), block.height_from_coinbase()) {
+        (Some(total_fees), Ok(height)) => (total_fees, height),
+        _ => return Ok(()),
+    };
+    validate_coinbase(&block, total_fees, height)
+}
+
+/// Handles a new block message.
+///
+/// # Arguments
+/// * `block_data` - A vector of bytes containing the block data.
+/// * `path` - A reference to a string containing the path to the blocks directory.
+///
+/// # Returns
+///
+/// A `Result` with the `BlockSave` outcome if the block is valid, or a `NodeError` if its not.
+///
+/// The blocks downloaded in parallel can't be checked against the UTXO set, so with `FULL_VALIDATION`
+/// the block is not recorded as validated: its scripts and coinbase value are verified when the UTXO set
+/// is built from the stored blocks, in chain order.
+pub fn validate_and_save_block(block_data: Vec<u8>, path: &String) -> Result<BlockSave, NodeError> {
+    let (block_header, _transaction_ids) = validate_block_bytes(&block_data)?;
+    let block_save = write_block_to_disk(block_data, path)?;
+    if block_save == BlockSave::Saved && !retrieve_full_validation() {
+        record_validated_block(&block_header);
+    }
+    Ok(block_save)
 }
 
 /// Handles a new block message.
@@ -232,38 +1024,262 @@ pub fn validate_and_save_block(block_data: Vec<u8>, path: &String) -> Result<(),
 /// # Arguments
 /// * `block_data` - A vector of bytes containing the block data.
 /// * `path` - A reference to a string containing the path to the blocks directory.
+/// * `events` - The sink the new block and chain tip events are emitted to, such as the UI.
 ///
 /// # Returns
 ///
-/// A `Result` indicating the result of the handling. If the block is valid, the `Result` will be `Ok`, if its not it will return a `NodeError`.
+/// A `Result` with the `BlockSave` outcome if the block is valid, or a `NodeError` if its not. A block
+/// that was already saved is not appended to the headers file nor emitted again.
 pub fn validate_and_save_block_listener(
     block_data: Vec<u8>,
     path: &String,
-    ui_sender: &Sender<UIMessage>,
+    events: &impl EventSink,
+) -> Result<BlockSave, NodeError> {
+    let headers_path = obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?;
+    save_block_and_header(block_data, path, &headers_path, events)
+}
+
+/// Validates a merkleblock (BIP37) received in headers-only mode and appends its header to the
+/// headers file. There is no block to save: the merkleblock only proves the transactions of the
+/// wallet.
+///
+/// # Returns
+///
+/// The ids of the transactions proven by the merkleblock.
+///
+/// # Errors
+///
+/// Returns a `NodeError` if the header or the partial merkle tree is invalid, or if the header
+/// could not be saved.
+pub fn validate_and_save_merkle_block_listener(
+    merkle_block: &MerkleBlockMessage,
+    events: &impl EventSink,
+) -> Result<Vec<TxHash>, NodeError> {
+    let headers_path = obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?;
+    save_merkle_block_header(merkle_block, &headers_path, events)
+}
+
+/// Validates a block, saves it and appends its header to the headers file at the given path.
+fn save_block_and_header(
+    block_data: Vec<u8>,
+    path: &String,
+    headers_path: &str,
+    events: &impl EventSink,
+) -> Result<BlockSave, NodeError> {
+    let (block_header, _transaction_ids) = validate_block_bytes(&block_data)?;
+    save_header(&block_header, headers_path, events, || {
+        let block_save = write_block_to_disk(block_data, path)?;
+        if block_save == BlockSave::Saved {
+            record_validated_block(&block_header);
+        }
+        Ok(block_save)
+    })
+}
+
+/// Validates the header and the partial merkle tree of a merkleblock and appends the header to the
+/// headers file at the given path, returning the ids of the transactions it proves.
+fn save_merkle_block_header(
+    merkle_block: &MerkleBlockMessage,
+    headers_path: &str,
+    events: &impl EventSink,
+) -> Result<Vec<TxHash>, NodeError> {
+    let block_header = &merkle_block.block_header;
+    validate_timestamp(block_header, current_timestamp()?)?;
+    validate_proof_of_work(block_header)?;
+    let matched_tx_ids = merkle_block.matched_tx_ids()?;
+    save_header(block_header, headers_path, events, || Ok(BlockSave::Saved))?;
+    Ok(matched_tx_ids)
+}
+
+/// Appends a validated header to the headers file at the given path, saving its block first.
+///
+/// The new block is emitted and, once the header is saved, the new chain tip. If the block was
+/// already saved, or its header is already among the last headers, the header is not appended
+/// again and nothing is emitted. Blocks that don't extend the tip are refused, see `check_reorg_depth`.
+///
+/// The headers file stays locked from the checks until the header is appended, so the same block
+/// received by several listeners at once is only appended by one of them.
+fn save_header<F>(
+    block_header: &BlockHeader,
+    headers_path: &str,
+    events: &impl EventSink,
+    save_block: F,
+) -> Result<BlockSave, NodeError>
+where
+    F: FnOnce() -> Result<BlockSave, NodeError>,
+{
+    let headers_file_guard = lock_headers_file()?;
+    let max_reorg_depth = retrieve_max_reorg_depth();
+    let recent_headers = read_recent_headers(headers_path, max_reorg_depth.saturating_add(1))?;
+    let block_header_bytes = block_header.to_bytes();
+    let block_hash = sha256d::Hash::hash(&block_header_bytes).to_byte_array();
+    if recent_headers.hashes.contains(&block_hash) {
+        return Ok(BlockSave::AlreadySaved);
+    }
+    if let Err(error) = check_fork_depth(
+        &recent_headers,
+        &block_header.prev_blockhash,
+        max_reorg_depth,
+    ) {
+        drop(headers_file_guard);
+        return Err(classify_unknown_parent(
+            error,
+            headers_path,
+            &block_header.prev_blockhash,
+            &recent_headers,
+        ));
+    }
+
+    if save_block()? == BlockSave::AlreadySaved {
+        return Ok(BlockSave::AlreadySaved);
+    }
+    events
+        .emit(NodeEvent::NewBlock(block_header.clone()))
+        .unwrap_or_else(|_| {
+            println!("Failed to emit new block event");
+        });
+    let height = append_block_header_locked(headers_path, &block_header_bytes)?;
+
+    let block_hash = InternalHash::new(block_hash).to_display();
+    events
+        .emit(NodeEvent::ChainTip(height, block_hash.to_string()))
+        .unwrap_or_else(|_| {
+            println!("Failed to emit chain tip event");
+        });
+    Ok(BlockSave::Saved)
+}
+
+/// Records a saved block as validated, so it's not validated again when the node restarts.
+///
+/// Failing to record it is not an error: the block will just be validated again.
+fn record_validated_block(block_header: &BlockHeader) {
+    let recorded = block_header
+        .hash()
+        .as_slice()
+        .try_into()
+        .map_err(|_| NodeError::FailedToConvert("Invalid block hash".to_string()))
+        .and_then(|block_hash| mark_block_as_validated(&block_hash));
+    if recorded.is_err() {
+        println!("Failed to record the block as validated");
+    }
+}
+
+/// Validates the proof of work and the merkle root of a block saved on disk and, with full validation,
+/// its input scripts and coinbase value against the UTXO set the block is about to be applied to.
+///
+/// # Arguments
+///
+/// * `path` - A string reference representing the file path to the block file.
+/// * `utxo_set` - The UTXO set before the block is applied.
+/// * `full_validation` - Whether the scripts and the coinbase value are verified, see `FULL_VALIDATION`.
+///
+/// # Returns
+///
+/// A `Result` indicating whether the stored block is valid, or a `NodeError` if it could not be read or is invalid.
+pub fn validate_stored_block(
+    path: &String,
+    utxo_set: &UtxoSet,
+    full_validation: bool,
 ) -> Result<(), NodeError> {
-    let mut cursor = Cursor::new(&block_data);
-    let block_header_bytes = receive_message(&mut cursor, LENGTH_BLOCK_HEADERS)?;
-    let block_header = BlockHeader::from_bytes(&block_header_bytes)?;
-    let txs_count = CompactSize::read_varint(&mut cursor)?;
-    let txs_count_value = txs_count.get_value();
-    let mut transaction_ids = retrieve_transaction_ids(&mut cursor, txs_count_value)?;
+    let block_data = fs::read(path)
+        .map_err(|_| NodeError::FailedToRead("Failed to read block file".to_string()))?;
+    validate_block_bytes(&block_data)?;
+    if full_validation {
+        validate_block_scripts(&block_data, utxo_set)?;
+        validate_block_coinbase(&block_data, utxo_set)?;
+    }
+    Ok(())
+}
 
-    match validate_block(&block_header, &mut transaction_ids) {
-        Ok(()) => {
-            ui_sender
-                .send(UIMessage::NewBlock(block_header))
-                .unwrap_or_else(|_| {
-                    println!("Failed to send new block message to UI thread");
-                });
-            write_block_to_disk(block_data, path)?;
-            write_block_header_to_file(&block_header_bytes)?;
-            Ok(())
+/// Verifies that a block file on disk was not corrupted.
+///
+/// The block is parsed again, and its hash, merkle root and proof of work are recomputed. The file
+/// must hold exactly one block and, when it's named after a block hash, the hash must match.
+///
+/// # Arguments
+///
+/// * `path` - The path to the block file.
+///
+/// # Errors
+///
+/// Returns a `NodeError::CorruptBlockFile` describing the first mismatch found, or a
+/// `NodeError::FailedToRead` if the file could not be read.
+pub fn verify_block_file(path: &str) -> Result<(), NodeError> {
+    let block_data = fs::read(path)
+        .map_err(|_| NodeError::FailedToRead(format!("Failed to read block file {}", path)))?;
+    let corrupt = |reason: &str| NodeError::CorruptBlockFile(format!("{}: {}", path, reason));
+
+    let block = Block::from_bytes(&block_data).map_err(|_| corrupt("the block can't be parsed"))?;
+    if block.to_bytes().len() != block_data.len() {
+        return Err(corrupt("the file has bytes after the block"));
+    }
+
+    let block_hash = InternalHash::from_slice(block.header.hash())
+        .map_err(|_| corrupt("the block hash can't be computed"))?
+        .to_display();
+    let file_stem = Path::new(path)
+        .file_stem()
+        .and_then(|file_stem| file_stem.to_str())
+        .unwrap_or_default();
+    if let Ok(expected_hash) = DisplayHash::from_hex(file_stem) {
+        if expected_hash != block_hash {
+            return Err(corrupt("the block hash doesn't match the file name"));
         }
-        Err(e) => Err(e),
     }
+
+    let txids: Vec<TxHash> = block.transactions.iter().map(|tx| tx.tx_id()).collect();
+    let merkle_root =
+        compute_merkle_root(&txids).map_err(|_| corrupt("the block has no transactions"))?;
+    if merkle_root != block.header.merkle_root_hash {
+        return Err(corrupt("the merkle root doesn't match the transactions"));
+    }
+
+    validate_proof_of_work(&block.header)
+        .map_err(|_| corrupt("the block hash doesn't meet the proof of work target"))
 }
 
-/// Retrieves the transactions from a block file.
+/// Verifies every block file (with the `.bin` extension) of a directory, including the ones in its
+/// date subdirectories when the blocks are sharded by date.
+///
+/// # Arguments
+///
+/// * `dir` - The path to the blocks directory.
+///
+/// # Returns
+///
+/// A `Result` containing the paths of the corrupt block files, sorted, or a `NodeError` if the
+/// directory could not be read.
+pub fn verify_all_blocks(dir: &str) -> Result<Vec<String>, NodeError> {
+    let mut corrupt_files = vec![];
+    for path in block_files_in(Path::new(dir))? {
+        if verify_block_file(&path).is_err() {
+            corrupt_files.push(path);
+        }
+    }
+    corrupt_files.sort();
+    Ok(corrupt_files)
+}
+
+/// Returns the paths of the block files of a directory and of its subdirectories.
+fn block_files_in(dir: &Path) -> Result<Vec<String>, NodeError> {
+    let entries = fs::read_dir(dir).map_err(|_| {
+        NodeError::FailedToRead(format!("Failed to read directory {}", dir.display()))
+    })?;
+
+    let mut block_files = vec![];
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if path.is_dir() {
+            block_files.extend(block_files_in(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "bin") {
+            if let Some(path) = path.to_str() {
+                block_files.push(path.to_string());
+            }
+        }
+    }
+    Ok(block_files)
+}
+
+/// Reads the timestamp of a block from its block file.
 ///
 /// # Arguments
 ///
@@ -271,34 +1287,93 @@ pub fn validate_and_save_block_listener(
 ///
 /// # Returns
 ///
-/// A `Result` containing a vector of `Transaction` objects on success, or an
-/// error of type `NodeError` if there was an issue opening or reading the file.
-pub fn retrieve_transactions_from_block(path: &String) -> Result<Vec<Transaction>, NodeError> {
+/// A `Result` containing the timestamp of the block header, or a `NodeError` if the file could not be read.
+pub fn read_block_timestamp(path: &String) -> Result<u32, NodeError> {
     let mut file = File::options()
         .read(true)
         .open(path)
         .map_err(|_| NodeError::FailedToOpenFile("Failed to open file block".to_string()))?;
 
     let block_header_bytes = receive_message(&mut file, LENGTH_BLOCK_HEADERS)?;
-    let _block_header = BlockHeader::from_bytes(&block_header_bytes)?;
-    let txs_count = CompactSize::read_varint(&mut file)?;
-    let txs_count_value = txs_count.get_value();
+    Ok(BlockHeader::from_bytes(&block_header_bytes)?.timestamp)
+}
 
-    let transactions = retrieve_transactions(&mut file, txs_count_value)?;
+/// Retrieves the transactions from a block file.
+///
+/// With the `mmap` feature the file is memory-mapped and parsed in place, otherwise it's read
+/// through a buffer.
+///
+/// # Arguments
+///
+/// * `path` - A string reference representing the file path to the block file.
+///
+/// # Returns
+///
+/// A `Result` containing a vector of `Transaction` objects on success, or an
+/// error of type `NodeError` if there was an issue opening or reading the file.
+pub fn retrieve_transactions_from_block(path: &String) -> Result<Vec<Transaction>, NodeError> {
+    #[cfg(feature = "mmap")]
+    let transactions = retrieve_transactions_from_mapped_block(path)?;
+    #[cfg(not(feature = "mmap"))]
+    let transactions = retrieve_transactions_from_buffered_block(path)?;
 
     Ok(transactions)
 }
 
+/// Retrieves the transactions from a block file, reading it through a buffer.
+fn retrieve_transactions_from_buffered_block(path: &String) -> Result<Vec<Transaction>, NodeError> {
+    let file = open_block_file(path)?;
+    read_block_transactions(&mut BufReader::new(file))
+}
+
+/// Retrieves the transactions from a block file, parsing them from the memory-mapped file so the
+/// block is not copied to the heap. The file is read through a buffer if it can't be mapped.
+#[cfg(feature = "mmap")]
+fn retrieve_transactions_from_mapped_block(path: &String) -> Result<Vec<Transaction>, NodeError> {
+    let file = open_block_file(path)?;
+    // SAFETY: block files are written once and never modified while they are read, so the
+    // mapped region doesn't change under the parser.
+    match unsafe { memmap2::Mmap::map(&file) } {
+        Ok(block) => read_block_transactions(&mut Cursor::new(&block[..])),
+        Err(_) => retrieve_transactions_from_buffered_block(path),
+    }
+}
+
+/// Opens a block file for reading.
+fn open_block_file(path: &String) -> Result<File, NodeError> {
+    File::options()
+        .read(true)
+        .open(path)
+        .map_err(|_| NodeError::FailedToOpenFile("Failed to open file block".to_string()))
+}
+
+/// Reads the transactions of a block, skipping its header.
+fn read_block_transactions<R: Read>(block: &mut R) -> Result<Vec<Transaction>, NodeError> {
+    let block_header_bytes = receive_message(block, LENGTH_BLOCK_HEADERS)?;
+    let _block_header = BlockHeader::from_bytes(&block_header_bytes)?;
+    let txs_count = CompactSize::read_varint(block)?;
+
+    retrieve_transactions(block, txs_count.get_value())
+}
+
 #[cfg(test)]
 mod test {
 
     use std::{
+        cell::RefCell,
         env,
         io::{BufRead, BufReader},
+        rc::Rc,
+        sync::mpsc,
     };
 
-    use crate::{config::parse_line, constants::DEFAULT_CONFIG};
-    use bitcoin_hashes::hex::FromHex;
+    use crate::{
+        block_header::GENESIS_BLOCK_HEADER,
+        config::parse_line,
+        constants::{DEFAULT_CONFIG, TESTNET_BIP34_HEIGHT},
+        ui::ui_message::UIMessage,
+        wallet::account::Account,
+    };
 
     use super::*;
 
@@ -437,20 +1512,21 @@ mod test {
             "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin"
                 .to_string(),
         )?;
-        let mut coinbase =
-            Vec::from_hex("7e92b6982a89b2bcb7f40c9cbd05db22d46bb08b1b0a001a40e4fca0b49f80a9")
-                .unwrap();
-        coinbase.reverse();
-
-        let mut tx_7 =
-            Vec::from_hex("5b32f673a000733900a2208388a6da5e2d21306b935b6bfdaca3982e4315db09")
-                .unwrap();
-        tx_7.reverse();
-
-        let mut tx_50 =
-            Vec::from_hex("38b4025adf72314d8d80536c3a7fe42e86a301e5a22b2d156cc946b81ce8b9a8")
-                .unwrap();
-        tx_50.reverse();
+        let coinbase = DisplayHash::from_hex(
+            "7e92b6982a89b2bcb7f40c9cbd05db22d46bb08b1b0a001a40e4fca0b49f80a9",
+        )?
+        .to_internal()
+        .to_vec();
+        let tx_7 = DisplayHash::from_hex(
+            "5b32f673a000733900a2208388a6da5e2d21306b935b6bfdaca3982e4315db09",
+        )?
+        .to_internal()
+        .to_vec();
+        let tx_50 = DisplayHash::from_hex(
+            "38b4025adf72314d8d80536c3a7fe42e86a301e5a22b2d156cc946b81ce8b9a8",
+        )?
+        .to_internal()
+        .to_vec();
 
         assert_eq!(transaction_test_hashes[0], coinbase);
         assert_eq!(transaction_test_hashes[7], tx_7);
@@ -539,4 +1615,720 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_write_same_block_twice_is_ok() -> Result<(), NodeError> {
+        let block_data = fs::read(
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read file".to_string()))?;
+        let path = "test_write_same_block_twice.bin".to_string();
+
+        let first_write = write_block_to_disk(block_data.clone(), &path);
+        let second_write = write_block_to_disk(block_data.clone(), &path);
+        let different_write = write_block_to_disk(block_data[1..].to_vec(), &path);
+        let stored_data = fs::read(&path);
+        fs::remove_file(&path)
+            .map_err(|_| NodeError::FailedToDeleteFile("Failed to delete file".to_string()))?;
+
+        assert_eq!(first_write?, BlockSave::Saved);
+        assert_eq!(second_write?, BlockSave::AlreadySaved);
+        assert!(different_write.is_err());
+        assert_eq!(stored_data.unwrap(), block_data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_writes_wait_when_the_limit_is_reached() -> Result<(), NodeError> {
+        let block_data = fs::read(
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read file".to_string()))?;
+        let path = "test_block_writes_limit.bin".to_string();
+        let _ = fs::remove_file(&path);
+        let block_writes = Semaphore::new(2);
+        let held_permits = (0..2)
+            .map(|_| block_writes.acquire())
+            .collect::<Result<Vec<_>, NodeError>>()?;
+
+        let (written_while_limited, write) = std::thread::scope(|scope| {
+            let writer =
+                scope.spawn(|| write_block_to_disk_limited(block_data, &path, &block_writes));
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            let written_while_limited = fs::metadata(&path).is_ok();
+            drop(held_permits);
+            let write = writer
+                .join()
+                .map_err(|_| NodeError::FailedToWrite("Writer thread panicked".to_string()));
+            (written_while_limited, write)
+        });
+        let write = write?;
+        let written = fs::metadata(&path).is_ok();
+        let _ = fs::remove_file(&path);
+
+        assert!(!written_while_limited);
+        assert!(write.is_ok());
+        assert!(written);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_block_bytes() -> Result<(), NodeError> {
+        let block_data = fs::read(
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read file".to_string()))?;
+
+        let (block_header, transaction_ids) = validate_block_bytes(&block_data)?;
+
+        let expected_hash = DisplayHash::from_hex(
+            "00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3",
+        )?
+        .to_internal();
+        assert_eq!(block_header.hash(), expected_hash.as_bytes());
+        assert_eq!(transaction_ids.len(), 112);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_tampered_block_bytes() -> Result<(), NodeError> {
+        let mut block_data = fs::read(
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read file".to_string()))?;
+        let last_byte = block_data.len() - 5;
+        block_data[last_byte] ^= 0xff;
+
+        assert!(matches!(
+            validate_block_bytes(&block_data),
+            Err(NodeError::InvalidMerkleRoot(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_without_transactions_is_rejected() -> Result<(), NodeError> {
+        let mut block_data = fs::read(
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read file".to_string()))?;
+        block_data[LENGTH_BLOCK_HEADERS] = 0;
+
+        assert!(matches!(
+            validate_block_bytes(&block_data),
+            Err(NodeError::InvalidMessageFormat(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_block_scripts() -> Result<(), NodeError> {
+        let block_data = fs::read(
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+
+        validate_block_scripts(&block_data, &UtxoSet::new())
+    }
+
+    #[test]
+    fn test_validate_block_scripts_tampered_signature() -> Result<(), NodeError> {
+        let path =
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin";
+        let mut block_data = fs::read(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let transactions = retrieve_transactions_from_block(&path.to_string())?;
+
+        let spend = transactions
+            .iter()
+            .find(|transaction| {
+                let outpoint = &transaction.tx_inputs[0].previous_output;
+                transactions.iter().any(|previous| {
+                    previous.tx_id() == outpoint.tx_id
+                        && is_p2pkh(&previous.tx_outputs[outpoint.index as usize].pk_script)
+                })
+            })
+            .ok_or(NodeError::FailedToRead("No P2PKH spend found".to_string()))?;
+        let signature_script = &spend.tx_inputs[0].signature_script;
+        let position = block_data
+            .windows(signature_script.len())
+            .position(|window| window == signature_script.as_slice())
+            .ok_or(NodeError::FailedToRead("Signature not found".to_string()))?;
+        block_data[position + 10] ^= 0x01;
+
+        let result = validate_block_scripts(&block_data, &UtxoSet::new());
+
+        assert!(matches!(result, Err(NodeError::ScriptValidationFailed(_))));
+        Ok(())
+    }
+
+    /// Block with a coinbase and a single transaction paying 141 satoshis of fees.
+    const TWO_TRANSACTIONS_BLOCK: &str =
+        "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin";
+
+    fn read_block(path: &str) -> Result<Block, NodeError> {
+        let block_data = fs::read(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        Block::from_bytes(&block_data)
+    }
+
+    #[test]
+    fn test_block_subsidy_halvings() {
+        assert_eq!(block_subsidy(0), 5_000_000_000);
+        assert_eq!(block_subsidy(209_999), 5_000_000_000);
+        assert_eq!(block_subsidy(210_000), 2_500_000_000);
+        assert_eq!(block_subsidy(2_432_868), 2_441_406);
+        assert_eq!(block_subsidy(64 * 210_000), 0);
+    }
+
+    #[test]
+    fn test_validate_coinbase_with_subsidy_and_fees() -> Result<(), NodeError> {
+        let block = read_block(TWO_TRANSACTIONS_BLOCK)?;
+        let height = block.height_from_coinbase()?;
+
+        assert_eq!(height, 2_432_868);
+        validate_coinbase(&block, 141, height)
+    }
+
+    #[test]
+    fn test_validate_coinbase_over_claiming_fails() -> Result<(), NodeError> {
+        let block = read_block(TWO_TRANSACTIONS_BLOCK)?;
+
+        let result = validate_coinbase(&block, 140, 2_432_868);
+
+        assert!(matches!(result, Err(NodeError::InvalidCoinbaseValue(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_block_coinbase_skips_unknown_fees() -> Result<(), NodeError> {
+        let block_data = fs::read(TWO_TRANSACTIONS_BLOCK)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+
+        assert!(block_fees(&Block::from_bytes(&block_data)?, &UtxoSet::new()).is_none());
+        validate_block_coinbase(&block_data, &UtxoSet::new())
+    }
+
+    #[test]
+    fn test_full_validation_of_stored_block_checks_the_scripts() -> Result<(), NodeError> {
+        let path = TWO_TRANSACTIONS_BLOCK.to_string();
+        let block = read_block(TWO_TRANSACTIONS_BLOCK)?;
+        let outpoint = &block.transactions[1].tx_inputs[0].previous_output;
+        let mut wrong_output = TxOutput::new(
+            1.0,
+            Account::pk_hash_to_pk_script(&vec![3; 20]),
+            u64::from(outpoint.index),
+        );
+        wrong_output.tx_id = outpoint.tx_id.clone();
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.insert(outpoint.tx_id.clone(), vec![wrong_output]);
+
+        validate_stored_block(&path, &utxo_set, false)?;
+        let result = validate_stored_block(&path, &utxo_set, true);
+
+        assert!(matches!(result, Err(NodeError::ScriptValidationFailed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_to_bytes_round_trip() -> Result<(), NodeError> {
+        for path in [
+            TWO_TRANSACTIONS_BLOCK,
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin",
+            "blocks-test/0000000000001fdc30a4b54fff00ae2494add9f41297b1cc426d8b8230129a38.bin",
+        ] {
+            let block_data = fs::read(path)
+                .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+
+            let block = Block::from_bytes(&block_data)?;
+
+            assert_eq!(block.to_bytes(), block_data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_saving_block_sends_new_chain_tip() -> Result<(), NodeError> {
+        let block_data = fs::read(TWO_TRANSACTIONS_BLOCK)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let headers_path = "blocks-test/test_chain_tip_headers.bin";
+        let block_path = "blocks-test/test_chain_tip_block.bin".to_string();
+        fs::write(headers_path, b"")
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+        let (ui_sender, ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+
+        let result = save_block_and_header(block_data, &block_path, headers_path, &ui_sender);
+        let _ = fs::remove_file(headers_path);
+        let _ = fs::remove_file(&block_path);
+        result?;
+
+        let chain_tips = Rc::new(RefCell::new(Vec::new()));
+        let received_chain_tips = Rc::clone(&chain_tips);
+        let context = glib::MainContext::new();
+        ui_receiver.attach(Some(&context), move |message| {
+            if let UIMessage::ChainTip(height, block_hash) = message {
+                received_chain_tips.borrow_mut().push((height, block_hash));
+            }
+            glib::Continue(true)
+        });
+        while context.iteration(false) {}
+
+        assert_eq!(
+            *chain_tips.borrow(),
+            vec![(
+                0,
+                "000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b".to_string()
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reorg_deeper_than_the_max_depth_is_refused() -> Result<(), NodeError> {
+        let headers_path = "blocks-test/test_reorg_depth_headers.bin";
+        let headers = (0..700)
+            .map(|nonce| {
+                BlockHeader::from_bytes(
+                    &BlockHeader {
+                        nonce,
+                        ..GENESIS_BLOCK_HEADER
+                    }
+                    .to_bytes(),
+                )
+            })
+            .collect::<Result<Vec<BlockHeader>, NodeError>>()?;
+        fs::write(
+            headers_path,
+            headers
+                .iter()
+                .flat_map(BlockHeader::to_bytes)
+                .collect::<Vec<u8>>(),
+        )
+        .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+        let hash_at = |height: usize| -> Result<BlockHash, NodeError> {
+            headers[height]
+                .hash()
+                .as_slice()
+                .try_into()
+                .map_err(|_| NodeError::FailedToConvert("Invalid block hash".to_string()))
+        };
+
+        let deep_fork = check_reorg_depth(headers_path, &hash_at(689)?, 5);
+        let shallow_fork = check_reorg_depth(headers_path, &hash_at(697)?, 5);
+        let tip = check_reorg_depth(headers_path, &hash_at(699)?, 0);
+        let below_checkpoint = check_reorg_depth(headers_path, &hash_at(500)?, 1000);
+        let unknown_parent = check_reorg_depth(headers_path, &[1; 32], 5);
+        let _ = fs::remove_file(headers_path);
+
+        assert!(matches!(deep_fork, Err(NodeError::ReorgTooDeep(_))));
+        assert!(matches!(shallow_fork, Err(NodeError::UnsupportedReorg(_))));
+        assert!(tip.is_ok());
+        assert!(matches!(below_checkpoint, Err(NodeError::ReorgTooDeep(_))));
+        assert!(matches!(unknown_parent, Err(NodeError::UnknownParent(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_saving_block_emits_events_to_a_plain_channel() -> Result<(), NodeError> {
+        let block_data = fs::read(TWO_TRANSACTIONS_BLOCK)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let headers_path = "blocks-test/test_plain_events_headers.bin";
+        let block_path = "blocks-test/test_plain_events_block.bin".to_string();
+        fs::write(headers_path, b"")
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+        let (event_sender, event_receiver) = mpsc::channel();
+
+        let result = save_block_and_header(block_data, &block_path, headers_path, &event_sender);
+        let _ = fs::remove_file(headers_path);
+        let _ = fs::remove_file(&block_path);
+        result?;
+        drop(event_sender);
+
+        let events: Vec<NodeEvent> = event_receiver.iter().collect();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], NodeEvent::NewBlock(_)));
+        assert!(matches!(&events[1], NodeEvent::ChainTip(0, block_hash)
+            if block_hash == "000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_block_whose_parent_is_not_stored_is_not_saved() -> Result<(), NodeError> {
+        let block_data = fs::read(TWO_TRANSACTIONS_BLOCK)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let headers_path = "blocks-test/test_unknown_parent_headers.bin";
+        let block_path = "blocks-test/test_unknown_parent_block.bin".to_string();
+        fs::write(headers_path, GENESIS_BLOCK_HEADER.to_bytes())
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+        let (event_sender, event_receiver) = mpsc::channel();
+
+        let result = save_block_and_header(block_data, &block_path, headers_path, &event_sender);
+        let headers = fs::read(headers_path);
+        let block_saved = Path::new(&block_path).exists();
+        let _ = fs::remove_file(headers_path);
+        let _ = fs::remove_file(&block_path);
+        drop(event_sender);
+
+        assert!(matches!(result, Err(NodeError::UnknownParent(_))));
+        assert_eq!(
+            headers.map_err(|_| NodeError::FailedToRead("Failed to read headers".to_string()))?,
+            GENESIS_BLOCK_HEADER.to_bytes()
+        );
+        assert!(!block_saved);
+        assert_eq!(event_receiver.iter().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_saving_a_block_already_saved_does_not_append_its_header() -> Result<(), NodeError> {
+        let block_data = fs::read(TWO_TRANSACTIONS_BLOCK)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let headers_path = "blocks-test/test_already_saved_headers.bin";
+        let block_path = "blocks-test/test_already_saved_block.bin".to_string();
+        fs::write(headers_path, b"")
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+        let (event_sender, event_receiver) = mpsc::channel();
+
+        let first_save =
+            save_block_and_header(block_data.clone(), &block_path, headers_path, &event_sender);
+        let second_save =
+            save_block_and_header(block_data, &block_path, headers_path, &event_sender);
+        let headers = fs::read(headers_path);
+        let _ = fs::remove_file(headers_path);
+        let _ = fs::remove_file(&block_path);
+        drop(event_sender);
+
+        assert_eq!(first_save?, BlockSave::Saved);
+        assert_eq!(second_save?, BlockSave::AlreadySaved);
+        let headers =
+            headers.map_err(|_| NodeError::FailedToRead("Failed to read headers".to_string()))?;
+        assert_eq!(headers.len(), LENGTH_BLOCK_HEADERS);
+        assert_eq!(event_receiver.iter().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_block_received_by_several_listeners_at_once_is_appended_once() -> Result<(), NodeError>
+    {
+        let headers_path = "blocks-test/test_concurrent_save_headers.bin";
+        let genesis_header = GENESIS_BLOCK_HEADER.to_bytes();
+        fs::write(headers_path, &genesis_header)
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+        let block_header = BlockHeader::from_bytes(
+            &BlockHeader {
+                prev_blockhash: sha256d::Hash::hash(&genesis_header).to_byte_array(),
+                ..GENESIS_BLOCK_HEADER
+            }
+            .to_bytes(),
+        )?;
+        let (event_sender, event_receiver) = mpsc::channel();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let block_header = block_header.clone();
+                let event_sender = event_sender.clone();
+                std::thread::spawn(move || {
+                    save_header(&block_header, headers_path, &event_sender, || {
+                        std::thread::sleep(std::time::Duration::from_millis(50));
+                        Ok(BlockSave::Saved)
+                    })
+                })
+            })
+            .collect();
+        drop(event_sender);
+        let mut saves = Vec::new();
+        for handle in handles {
+            let save = handle
+                .join()
+                .map_err(|_| NodeError::FailedToJoinThread("Failed to join".to_string()))
+                .and_then(|save| save);
+            saves.push(save);
+        }
+        let headers = fs::read(headers_path);
+        let _ = fs::remove_file(headers_path);
+        let saves = saves
+            .into_iter()
+            .collect::<Result<Vec<BlockSave>, NodeError>>()?;
+        let headers =
+            headers.map_err(|_| NodeError::FailedToRead("Failed to read headers".to_string()))?;
+
+        assert_eq!(
+            saves
+                .iter()
+                .filter(|save| **save == BlockSave::Saved)
+                .count(),
+            1
+        );
+        assert_eq!(headers.len(), 2 * LENGTH_BLOCK_HEADERS);
+        assert_eq!(event_receiver.iter().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_height_is_read_from_the_coinbase() -> Result<(), NodeError> {
+        let mut block = read_block(
+            "blocks-test/000000000000001ea1833f96dbbe35fd5e0d0f2d6fce810bd91a3d236163dc94.bin",
+        )?;
+
+        assert_eq!(block.height_from_coinbase()?, 2_439_230);
+
+        block.transactions[0].tx_inputs[0].signature_script[0] = OP_1 + 2;
+        let coinbase = &block.transactions[0];
+        assert_eq!(bip34_height(&block.header, coinbase, 1)?, 3);
+        // Below the activation height the push is not trusted to be the height.
+        assert!(matches!(
+            bip34_height(&block.header, coinbase, TESTNET_BIP34_HEIGHT),
+            Err(NodeError::MissingCoinbaseHeight(_))
+        ));
+        let version_1 = BlockHeader {
+            version: 1,
+            ..block.header.clone()
+        };
+        assert!(matches!(
+            bip34_height(&version_1, coinbase, 1),
+            Err(NodeError::MissingCoinbaseHeight(_))
+        ));
+        // Before BIP34 the coinbase could start with any data, like the 8 bytes pushed here.
+        block.transactions[0].tx_inputs[0].signature_script[0] = 0x08;
+        assert!(matches!(
+            block.height_from_coinbase(),
+            Err(NodeError::MissingCoinbaseHeight(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_coinbase_message_skips_the_height_and_escapes_binary_data() -> Result<(), NodeError> {
+        let tagged = read_block(
+            "blocks-test/0000000064cb619ed029a4dec104c841bc97127054918a3275237900b5944cff.bin",
+        )?;
+        let pool = read_block(
+            "blocks-test/000000000000001ea1833f96dbbe35fd5e0d0f2d6fce810bd91a3d236163dc94.bin",
+        )?;
+
+        assert_eq!(
+            tagged.coinbase_message(),
+            "\\x12\\x00\\x00\\x00bingbong!\\x00\\x04\\x00\\x00\\x00"
+        );
+        assert!(pool.coinbase_message().contains("Mermaider FTW!"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merkle_block_header_is_saved_without_a_block() -> Result<(), NodeError> {
+        let block = read_block(TWO_TRANSACTIONS_BLOCK)?;
+        let tx_ids: Vec<TxHash> = block.transactions.iter().map(|tx| tx.tx_id()).collect();
+        let merkle_block = MerkleBlockMessage::new(block.header.clone(), &tx_ids, &[false, true]);
+        let headers_path = "blocks-test/test_headers_only_headers.bin";
+        fs::write(headers_path, b"")
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+        let (ui_sender, _ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+
+        let result = save_merkle_block_header(&merkle_block, headers_path, &ui_sender);
+        let headers = fs::read(headers_path);
+        let _ = fs::remove_file(headers_path);
+        let headers =
+            headers.map_err(|_| NodeError::FailedToRead("Failed to read headers".to_string()))?;
+
+        assert_eq!(result?, vec![tx_ids[1].clone()]);
+        assert_eq!(headers.len(), LENGTH_BLOCK_HEADERS);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_merkle_root_matches_block_header() -> Result<(), NodeError> {
+        let block = read_block(TWO_TRANSACTIONS_BLOCK)?;
+        let txids: Vec<TxHash> = block.transactions.iter().map(|tx| tx.tx_id()).collect();
+
+        let merkle_root = compute_merkle_root(&txids)?;
+
+        assert_eq!(merkle_root, block.header.merkle_root_hash.to_vec());
+        assert_eq!(compute_merkle_root(&txids[..1])?, txids[0]);
+        assert!(matches!(
+            compute_merkle_root(&[]),
+            Err(NodeError::InvalidMerkleTree(_))
+        ));
+        Ok(())
+    }
+
+    /// The stored blocks are downloaded without their witnesses, so the test adds a witness to a
+    /// transaction of a real block and commits to it in the coinbase, as a SegWit miner would.
+    #[test]
+    fn test_verify_witness_commitment() -> Result<(), NodeError> {
+        let mut block = read_block(
+            "blocks-test/0000000000001fdc30a4b54fff00ae2494add9f41297b1cc426d8b8230129a38.bin",
+        )?;
+        block.verify_witness_commitment()?;
+
+        let reserved_value = vec![0; 32];
+        block.transactions[0].witnesses = vec![vec![reserved_value.clone()]];
+        block.transactions[1].witnesses = vec![vec![vec![0x30; 71], vec![0x02; 33]]];
+        let mut wtxids = vec![vec![0; 32]];
+        wtxids.extend(block.transactions[1..].iter().map(|tx| tx.witness_tx_id()));
+        let mut bytes = compute_merkle_root(&wtxids)?;
+        bytes.extend(reserved_value);
+        let mut commitment = WITNESS_COMMITMENT_HEADER.to_vec();
+        commitment.extend(sha256d::Hash::hash(&bytes).to_byte_array());
+        let commitment_output = block.transactions[0]
+            .tx_outputs()
+            .iter()
+            .rposition(|tx_output| tx_output.pk_script.starts_with(&WITNESS_COMMITMENT_HEADER))
+            .ok_or(NodeError::FailedToRead("No witness commitment".to_string()))?;
+        block.transactions[0].tx_outputs[commitment_output].pk_script = commitment;
+        block.verify_witness_commitment()?;
+
+        block.transactions[1].witnesses[0][0][0] ^= 1;
+
+        assert!(matches!(
+            block.verify_witness_commitment(),
+            Err(NodeError::InvalidWitnessCommitment(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_without_witnesses_strips_only_the_witnesses() -> Result<(), NodeError> {
+        let path =
+            "blocks-test/0000000000001fdc30a4b54fff00ae2494add9f41297b1cc426d8b8230129a38.bin";
+        let block_data = fs::read(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let mut block = Block::from_bytes(&block_data)?;
+        block.transactions[1].witnesses = vec![vec![vec![0x30; 71], vec![0x02; 33]]];
+
+        let with_witnesses = block.to_bytes();
+        let without_witnesses = block.to_bytes_no_witness();
+
+        assert_ne!(with_witnesses, block_data);
+        assert_eq!(without_witnesses, block_data);
+        assert_eq!(
+            Block::from_bytes(&without_witnesses)?.transactions[1].tx_id(),
+            block.transactions[1].tx_id()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_block_file_accepts_a_good_block() -> Result<(), NodeError> {
+        verify_block_file(TWO_TRANSACTIONS_BLOCK)
+    }
+
+    #[test]
+    fn test_verify_all_blocks_finds_a_flipped_byte_in_a_date_subdirectory() -> Result<(), NodeError>
+    {
+        let dir = "blocks-test/test_verify_all_blocks";
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir)
+            .map_err(|_| NodeError::FailedToWrite("Failed to create directory".to_string()))?;
+        let good_block = fs::read(TWO_TRANSACTIONS_BLOCK)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let mut corrupt_block = fs::read(
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        corrupt_block[200] ^= 0x01;
+        let good_path = format!(
+            "{}/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+            dir
+        );
+        let corrupt_path = format!(
+            "{}/2009-01/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin",
+            dir
+        );
+        let written = fs::write(&good_path, good_block)
+            .and_then(|_| fs::create_dir_all(format!("{}/2009-01", dir)))
+            .and_then(|_| fs::write(&corrupt_path, corrupt_block))
+            .map_err(|_| NodeError::FailedToWrite("Failed to write block".to_string()));
+
+        let corrupt_file_error = verify_block_file(&corrupt_path);
+        let corrupt_files = written.and_then(|_| verify_all_blocks(dir));
+        let _ = fs::remove_dir_all(dir);
+
+        assert!(matches!(
+            corrupt_file_error,
+            Err(NodeError::CorruptBlockFile(_))
+        ));
+        assert_eq!(corrupt_files?, vec![corrupt_path]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_header_appends_do_not_interleave() -> Result<(), NodeError> {
+        let headers_path = "blocks-test/test_concurrent_headers.bin";
+        let _ = fs::remove_file(headers_path);
+        let threads: u8 = 8;
+        let headers_per_thread = 25;
+
+        let handles: Vec<_> = (0..threads)
+            .map(|thread_id| {
+                std::thread::spawn(move || -> Result<(), NodeError> {
+                    for _ in 0..headers_per_thread {
+                        append_block_header(headers_path, &vec![thread_id; LENGTH_BLOCK_HEADERS])?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        let mut appended = Ok(());
+        for handle in handles {
+            let result = handle
+                .join()
+                .map_err(|_| NodeError::FailedToJoinThread("Failed to join".to_string()))
+                .and_then(|result| result);
+            appended = appended.and(result);
+        }
+        let headers = fs::read(headers_path);
+        let _ = fs::remove_file(headers_path);
+        appended?;
+        let headers =
+            headers.map_err(|_| NodeError::FailedToRead("Failed to read headers".to_string()))?;
+
+        assert_eq!(
+            headers.len(),
+            threads as usize * headers_per_thread * LENGTH_BLOCK_HEADERS
+        );
+        for header in headers.chunks(LENGTH_BLOCK_HEADERS) {
+            assert!(header.iter().all(|byte| *byte == header[0]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_timestamp_rejects_blocks_too_far_in_the_future() -> Result<(), NodeError> {
+        let block = read_block(TWO_TRANSACTIONS_BLOCK)?;
+        let now = block.header.timestamp - 7200;
+
+        validate_timestamp(&block.header, now)?;
+        assert!(matches!(
+            validate_timestamp(&block.header, now - 1),
+            Err(NodeError::TimestampTooFuture(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mapped_and_buffered_blocks_have_the_same_transactions() -> Result<(), NodeError> {
+        for path in [
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+            "blocks-test/0000000000000005847b65f037ec3d08f499c3c22ae6723ffefee1adca3e9af5.bin",
+        ] {
+            let path = path.to_string();
+            let mapped = retrieve_transactions_from_mapped_block(&path)?;
+            let buffered = retrieve_transactions_from_buffered_block(&path)?;
+
+            assert!(!mapped.is_empty());
+            assert_eq!(
+                mapped.iter().map(Transaction::to_bytes).collect::<Vec<_>>(),
+                buffered
+                    .iter()
+                    .map(Transaction::to_bytes)
+                    .collect::<Vec<_>>()
+            );
+        }
+        Ok(())
+    }
 }
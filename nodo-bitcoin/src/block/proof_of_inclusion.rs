@@ -3,13 +3,12 @@ use bitcoin_hashes::{sha256d, Hash};
 use crate::{
     constants::{LEFT, RIGHT},
     node_error::NodeError,
-    utils::Utils,
 };
 
 use super::{
+    block_hash::{DisplayHash, InternalHash},
     hash_direction_tuple::HashDirectionTuple,
     merkle_tree::{generate_merkle_tree, MerkleTree},
-    tx_hash::TxHash,
 };
 
 /// Represents a Merkle proof for a specific transaction in a Merkle tree.
@@ -24,26 +23,37 @@ impl MerkleProof {
     ///
     /// # Arguments
     ///
-    /// * `tx_id` - A reference to the transaction ID as a vector of bytes.
+    /// * `tx_id` - A reference to the transaction ID.
     /// * `merkle_tree` - A reference to the Merkle Tree.
     ///
     /// # Returns
     ///
     /// * `Ok(String)` - The direction of the leaf node ("left" or "right") if the transaction ID is found in the Merkle tree.
     /// * `Err(NodeError)` - An error indicating failure to obtain the hash index or transaction not found in the block.
-    fn leaf_node_direction(tx_id: &TxHash, merkle_tree: &MerkleTree) -> Result<String, NodeError> {
-        let hash_index =
-            match merkle_tree.leefs[0].iter().position(|h| h == tx_id) {
-                Some(index) => index,
-                None => return Err(NodeError::InvalidMerkleTree(
-                    "Failed to obtain hash index from merkle tree, transaction not found in block"
-                        .to_string(),
-                )),
-            };
+    fn leaf_node_direction(
+        tx_id: &InternalHash,
+        merkle_tree: &MerkleTree,
+    ) -> Result<String, NodeError> {
+        let hash_index = Self::leaf_index(tx_id, merkle_tree)?;
 
         Ok(if hash_index % 2 == 0 { LEFT } else { RIGHT }.to_string())
     }
 
+    /// Returns the position of a transaction among the leaves of the Merkle Tree.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidMerkleTree` if the transaction is not in the block.
+    fn leaf_index(tx_id: &InternalHash, merkle_tree: &MerkleTree) -> Result<usize, NodeError> {
+        merkle_tree.leefs[0]
+            .iter()
+            .position(|h| h.as_slice() == tx_id.as_bytes())
+            .ok_or(NodeError::InvalidMerkleTree(
+                "Failed to obtain hash index from merkle tree, transaction not found in block"
+                    .to_string(),
+            ))
+    }
+
     /// Initializes a Merkle Proof by constructing the proof path starting from a leaf node identified by the given transaction ID.
     ///
     /// The function takes ownership of a vector representing the transaction ID (`tx_id`) and a reference to a `MerkleTree` (`merkle_tree`).
@@ -51,7 +61,7 @@ impl MerkleProof {
     ///
     /// # Arguments
     ///
-    /// * `tx_id` - A vector representing the transaction ID.
+    /// * `tx_id` - The transaction ID.
     /// * `merkle_tree` - A reference to the Merkle tree.
     ///
     /// # Returns
@@ -59,19 +69,18 @@ impl MerkleProof {
     /// * `Ok(MerkleProof)` - The initialized `MerkleProof` struct containing the proof path.
     /// * `Err(NodeError)` - An error indicating failure to determine the leaf node direction or transaction not found in the block.
     fn initialize_from_leaf(
-        mut tx_id: TxHash,
+        tx_id: InternalHash,
         merkle_tree: &MerkleTree,
     ) -> Result<MerkleProof, NodeError> {
         let mut proof_path = Vec::new();
         let leaf_node_direction = Self::leaf_node_direction(&tx_id, merkle_tree)?;
-        tx_id.reverse();
-        proof_path.push((tx_id, leaf_node_direction));
+        proof_path.push((tx_id.to_display(), leaf_node_direction));
 
         Ok(MerkleProof { proof_path })
     }
 
     /// Pushes a new hash and direction to the proof path.
-    fn push(&mut self, hash: TxHash, direction: String) {
+    fn push(&mut self, hash: DisplayHash, direction: String) {
         self.proof_path.push((hash, direction));
     }
 
@@ -83,7 +92,7 @@ impl MerkleProof {
     ///
     /// # Arguments
     ///
-    /// * `tx_id` - The transaction ID as a string.
+    /// * `tx_id` - The transaction ID as shown by block explorers.
     /// * `block` - The block path as a string.
     ///
     /// # Returns
@@ -92,20 +101,11 @@ impl MerkleProof {
     /// * `Err(NodeError)` - An error indicating failure to generate the Merkle Proof.
     pub fn for_tx_in_block(tx_id: String, block: String) -> Result<MerkleProof, NodeError> {
         let merkle_tree = generate_merkle_tree(&block)?;
-        let mut tx_id_bytes = Utils::hex_string_to_bytes(tx_id)?;
-        tx_id_bytes.reverse();
-
-        let mut merkle_proof =
-            MerkleProof::initialize_from_leaf(tx_id_bytes.clone(), &merkle_tree)?;
-
-        let mut hash_index =
-            match merkle_tree.leefs[0].iter().position(|h| h == &tx_id_bytes) {
-                Some(index) => index,
-                None => return Err(NodeError::InvalidMerkleTree(
-                    "Failed to obtain hash index from merkle tree, transaction not found in block"
-                        .to_string(),
-                )),
-            };
+        let tx_id = DisplayHash::from_hex(&tx_id)?.to_internal();
+
+        let mut merkle_proof = MerkleProof::initialize_from_leaf(tx_id, &merkle_tree)?;
+
+        let mut hash_index = Self::leaf_index(&tx_id, &merkle_tree)?;
 
         for level in 0..merkle_tree.levels() - 1 {
             let is_left_child = hash_index % 2 == 0;
@@ -118,10 +118,9 @@ impl MerkleProof {
                 hash_index - 1
             };
 
-            let mut sib = merkle_tree.leefs[level][sibling_index].clone();
-            sib.reverse();
+            let sibling = InternalHash::from_slice(&merkle_tree.leefs[level][sibling_index])?;
 
-            merkle_proof.push(sib, sibling_direction);
+            merkle_proof.push(sibling.to_display(), sibling_direction);
             hash_index /= 2;
         }
 
@@ -132,22 +131,20 @@ impl MerkleProof {
     ///
     /// # Returns
     ///
-    /// * `Vec<u8>` - The computed Merkle root hash as a byte array.
-    pub fn build_merkle_root(&self) -> TxHash {
-        let mut merkle_root = self.proof_path[0].0.clone();
-        merkle_root.reverse();
+    /// * `InternalHash` - The computed Merkle root hash, in the order of the block header.
+    pub fn build_merkle_root(&self) -> InternalHash {
+        let mut merkle_root = self.proof_path[0].0.to_internal();
 
         for (sibling_hash, sibling_direction) in &self.proof_path[1..] {
-            let mut sibling_hash = sibling_hash.clone();
-            sibling_hash.reverse();
+            let sibling_hash = sibling_hash.to_internal();
 
-            if sibling_direction == RIGHT {
-                merkle_root = [merkle_root, sibling_hash].concat();
+            let concatenated = if sibling_direction == RIGHT {
+                [merkle_root.as_bytes().as_slice(), sibling_hash.as_bytes()].concat()
             } else {
-                merkle_root = [sibling_hash, merkle_root].concat();
-            }
+                [sibling_hash.as_bytes().as_slice(), merkle_root.as_bytes()].concat()
+            };
 
-            merkle_root = sha256d::Hash::hash(&merkle_root).to_byte_array().to_vec();
+            merkle_root = InternalHash::new(sha256d::Hash::hash(&concatenated).to_byte_array());
         }
 
         merkle_root
@@ -161,8 +158,8 @@ impl MerkleProof {
         let merkle_tree = generate_merkle_tree(&block)?;
         let proof_of_inclusion = MerkleProof::for_tx_in_block(tx_id, block)?;
 
-        let trees_merkle_root = merkle_tree.root();
-        let inclusions_merkle_root = &proof_of_inclusion.build_merkle_root();
+        let trees_merkle_root = InternalHash::from_slice(merkle_tree.root())?;
+        let inclusions_merkle_root = proof_of_inclusion.build_merkle_root();
 
         Ok(trees_merkle_root == inclusions_merkle_root)
     }
@@ -171,13 +168,10 @@ impl MerkleProof {
     pub fn to_string_format(&self) -> String {
         let mut path = String::new();
 
-        path.push_str(&format!(
-            "Merkle Proof for {} =\n",
-            Utils::bytes_to_hex(&self.proof_path[0].0)
-        ));
+        path.push_str(&format!("Merkle Proof for {} =\n", self.proof_path[0].0));
 
         for hash_direction_tuple in &self.proof_path {
-            let hash = Utils::bytes_to_hex(&hash_direction_tuple.0);
+            let hash = hash_direction_tuple.0;
             let direction = &hash_direction_tuple.1;
 
             path.push_str("{\n");
@@ -269,7 +263,7 @@ mod test {
 
         assert_eq!(
             merkle_proof.build_merkle_root(),
-            block_header.merkle_root_hash.to_vec()
+            InternalHash::new(block_header.merkle_root_hash)
         );
 
         println!("{}", merkle_proof.to_string_format());
@@ -293,7 +287,7 @@ mod test {
 
         assert_eq!(
             merkle_proof.build_merkle_root(),
-            block_header.merkle_root_hash.to_vec()
+            InternalHash::new(block_header.merkle_root_hash)
         );
 
         println!("{}", merkle_proof.to_string_format());
@@ -328,20 +322,18 @@ mod test {
         )?;
 
         assert_eq!(
-            Utils::bytes_to_hex(&merkle_proof.proof_path[0].0),
+            merkle_proof.proof_path[0].0.to_string(),
             "5b32f673a000733900a2208388a6da5e2d21306b935b6bfdaca3982e4315db09".to_string()
         );
 
-        let mut merkle_root_bloque = block_header.merkle_root_hash.to_vec();
-        merkle_root_bloque.reverse();
+        let merkle_root_bloque = InternalHash::new(block_header.merkle_root_hash).to_display();
 
-        let mut merkle_root_de_proof = merkle_proof.build_merkle_root();
-        merkle_root_de_proof.reverse();
+        let merkle_root_de_proof = merkle_proof.build_merkle_root().to_display();
 
         // si se busca la merkle root en un explorador se puede ver que es 55a61eb710b66ed7f6f6c8a1b20451f971f48d8dc7a6326b4670601ba454c29e, que es la misma.
         println!(
             "Proof's Merkle Root: {:?}",
-            Utils::bytes_to_hex(&merkle_root_de_proof)
+            merkle_root_de_proof.to_string()
         );
 
         assert_eq!(merkle_root_de_proof, merkle_root_bloque);
@@ -371,19 +363,17 @@ mod test {
         )?;
 
         assert_eq!(
-            Utils::bytes_to_hex(&merkle_proof.proof_path[0].0),
+            merkle_proof.proof_path[0].0.to_string(),
             "530d7c4e56c5133c05fd0b0b56e9245c27897cceb35fa3affd60fe47539a72bc".to_string()
         );
 
-        let mut merkle_root_bloque = block_header.merkle_root_hash.to_vec();
-        merkle_root_bloque.reverse();
+        let merkle_root_bloque = InternalHash::new(block_header.merkle_root_hash).to_display();
 
-        let mut merkle_root_de_proof = merkle_proof.build_merkle_root();
-        merkle_root_de_proof.reverse();
+        let merkle_root_de_proof = merkle_proof.build_merkle_root().to_display();
 
         println!(
             "Proof's Merkle Root: {:?}",
-            Utils::bytes_to_hex(&merkle_root_de_proof)
+            merkle_root_de_proof.to_string()
         );
 
         assert_eq!(merkle_root_de_proof, merkle_root_bloque);
@@ -413,19 +403,17 @@ mod test {
         )?;
 
         assert_eq!(
-            Utils::bytes_to_hex(&merkle_proof.proof_path[0].0),
+            merkle_proof.proof_path[0].0.to_string(),
             "3e1cfe72a326ff21b00ba424cb396a48ce75e44bbafc21fabd549e4cbf884b34".to_string()
         );
 
-        let mut merkle_root_bloque = block_header.merkle_root_hash.to_vec();
-        merkle_root_bloque.reverse();
+        let merkle_root_bloque = InternalHash::new(block_header.merkle_root_hash).to_display();
 
-        let mut merkle_root_de_proof = merkle_proof.build_merkle_root();
-        merkle_root_de_proof.reverse();
+        let merkle_root_de_proof = merkle_proof.build_merkle_root().to_display();
 
         println!(
             "Proof's Merkle Root: {:?}",
-            Utils::bytes_to_hex(&merkle_root_de_proof)
+            merkle_root_de_proof.to_string()
         );
 
         assert_eq!(merkle_root_de_proof, merkle_root_bloque);
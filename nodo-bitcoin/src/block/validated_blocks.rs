@@ -0,0 +1,194 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    sync::Mutex,
+};
+
+use crate::{
+    config::obtain_dir_path,
+    constants::{PATH_BLOCKS, VALIDATED_BLOCKS_FILE},
+    node_error::NodeError,
+    utils::Utils,
+};
+
+use super::block_hash::BlockHash;
+
+/// Hashes of the stored blocks that already passed the proof of work and merkle root validation.
+///
+/// The hashes are kept in a marker file next to the blocks, one hex encoded hash per line, so the
+/// blocks don't need to be validated again when the node restarts.
+#[derive(Debug)]
+pub struct ValidatedBlocks {
+    path: String,
+    hashes: HashSet<BlockHash>,
+}
+
+impl ValidatedBlocks {
+    /// Loads the validated blocks from the marker file of the blocks directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the blocks directory is not configured or the file could not be read.
+    pub fn open() -> Result<ValidatedBlocks, NodeError> {
+        Self::open_from_path(&validated_blocks_path()?)
+    }
+
+    /// Loads the validated blocks from the given marker file, which might not exist yet.
+    ///
+    /// Lines that are not a valid block hash are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the marker file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToOpenFile` if the file exists but could not be opened.
+    pub fn open_from_path(path: &str) -> Result<ValidatedBlocks, NodeError> {
+        let mut hashes = HashSet::new();
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines().map_while(Result::ok) {
+                    let bytes = match Utils::hex_string_to_bytes(line.trim().to_string()) {
+                        Ok(bytes) => bytes,
+                        Err(_) => continue,
+                    };
+                    if let Ok(hash) = bytes.try_into() {
+                        hashes.insert(hash);
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(_) => {
+                return Err(NodeError::FailedToOpenFile(
+                    "Failed to open validated blocks file".to_string(),
+                ))
+            }
+        }
+
+        Ok(ValidatedBlocks {
+            path: path.to_string(),
+            hashes,
+        })
+    }
+
+    /// Returns true if the block was already validated.
+    pub fn contains(&self, block_hash: &BlockHash) -> bool {
+        self.hashes.contains(block_hash)
+    }
+
+    /// Marks a block as validated, appending its hash to the marker file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the hash could not be written to the file.
+    pub fn mark(&mut self, block_hash: &BlockHash) -> Result<(), NodeError> {
+        if self.hashes.insert(*block_hash) {
+            append_hash(&self.path, block_hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// Marks a block as validated in the marker file of the blocks directory.
+///
+/// The hashes already in the file are loaded once and shared between threads, so a block that is
+/// downloaded again is not appended twice.
+///
+/// # Arguments
+///
+/// * `block_hash` - The hash of the block that passed the validation.
+///
+/// # Errors
+///
+/// Returns a `NodeError` if the blocks directory is not configured or the hash could not be written.
+pub fn mark_block_as_validated(block_hash: &BlockHash) -> Result<(), NodeError> {
+    mark_in_shared(&validated_blocks_path()?, block_hash)
+}
+
+/// Marks a block as validated in the shared `ValidatedBlocks` of the given marker file, loading it
+/// the first time it's used.
+fn mark_in_shared(path: &str, block_hash: &BlockHash) -> Result<(), NodeError> {
+    static VALIDATED_BLOCKS: Mutex<Option<ValidatedBlocks>> = Mutex::new(None);
+    let mut shared = VALIDATED_BLOCKS
+        .lock()
+        .map_err(|_| NodeError::MutexError("Failed to lock validated blocks".to_string()))?;
+
+    match shared.as_mut() {
+        Some(validated_blocks) if validated_blocks.path == path => {
+            validated_blocks.mark(block_hash)
+        }
+        _ => {
+            let mut validated_blocks = ValidatedBlocks::open_from_path(path)?;
+            validated_blocks.mark(block_hash)?;
+            *shared = Some(validated_blocks);
+            Ok(())
+        }
+    }
+}
+
+/// Returns the path of the marker file, inside the blocks directory.
+fn validated_blocks_path() -> Result<String, NodeError> {
+    let directory = obtain_dir_path(PATH_BLOCKS.to_owned())?;
+    Ok(format!("{}/{}", directory, VALIDATED_BLOCKS_FILE))
+}
+
+/// Appends a hash to the marker file with a single write, so concurrent writers don't mix lines.
+fn append_hash(path: &str, block_hash: &BlockHash) -> Result<(), NodeError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|_| {
+            NodeError::FailedToOpenFile("Failed to open validated blocks file".to_string())
+        })?;
+
+    file.write_all(format!("{}\n", Utils::bytes_to_hex(block_hash)).as_bytes())
+        .map_err(|_| NodeError::FailedToWrite("Failed to write validated block hash".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_marked_blocks_are_loaded_again() -> Result<(), NodeError> {
+        let path = "blocks-test/test_validated_blocks.txt";
+        let _ = fs::remove_file(path);
+
+        let mut validated_blocks = ValidatedBlocks::open_from_path(path)?;
+        assert!(!validated_blocks.contains(&[1; 32]));
+        validated_blocks.mark(&[1; 32])?;
+        validated_blocks.mark(&[1; 32])?;
+
+        let reloaded = ValidatedBlocks::open_from_path(path)?;
+        let lines = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read file".to_string()))?;
+        let _ = fs::remove_file(path);
+
+        assert!(reloaded.contains(&[1; 32]));
+        assert!(!reloaded.contains(&[2; 32]));
+        assert_eq!(lines.lines().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_marking_a_block_twice_appends_it_once() -> Result<(), NodeError> {
+        let path = "blocks-test/test_shared_validated_blocks.txt";
+        let _ = fs::remove_file(path);
+
+        mark_in_shared(path, &[3; 32])?;
+        mark_in_shared(path, &[3; 32])?;
+        mark_in_shared(path, &[4; 32])?;
+
+        let lines = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read file".to_string()))?;
+        let _ = fs::remove_file(path);
+
+        assert_eq!(lines.lines().count(), 2);
+        Ok(())
+    }
+}
@@ -2,7 +2,11 @@ use bitcoin_hashes::{sha256d, Hash};
 
 use crate::{
     block::block_hash::BlockHash,
-    constants::{GENESIS_BITS, GENESIS_NONCE, GENESIS_TIMESTAMP, LENGTH_BLOCK_HEADERS},
+    constants::{
+        COMPACT_MANTISSA_MASK, COMPACT_SIGN_BIT, GENESIS_BITS, GENESIS_MERKLE_ROOT, GENESIS_NONCE,
+        GENESIS_TIMESTAMP, LENGTH_BLOCK_HEADERS, MAX_FUTURE_BLOCK_TIME, REGTEST_GENESIS_BITS,
+        REGTEST_GENESIS_NONCE, REGTEST_GENESIS_TIMESTAMP,
+    },
     node_error::NodeError,
 };
 
@@ -44,6 +48,17 @@ pub const GENESIS_BLOCK_HEADER: BlockHeader = BlockHeader {
     hash: Vec::new(),
 };
 
+/// Genesis block header of regtest, the chain local `bitcoind -regtest` nodes start from.
+pub const REGTEST_GENESIS_BLOCK_HEADER: BlockHeader = BlockHeader {
+    version: 1,
+    prev_blockhash: [0; 32],
+    merkle_root_hash: GENESIS_MERKLE_ROOT,
+    timestamp: REGTEST_GENESIS_TIMESTAMP,
+    n_bits: REGTEST_GENESIS_BITS,
+    nonce: REGTEST_GENESIS_NONCE,
+    hash: Vec::new(),
+};
+
 impl BlockHeader {
     /// Creates a new block header.
     /// # Arguments
@@ -80,24 +95,134 @@ impl BlockHeader {
         &self.hash
     }
 
+    /// Returns the hash of the block header as a `BlockHash`, the cached one if it has it.
+    ///
+    /// Headers created with `from_bytes` already have it, but headers built from their fields, such
+    /// as `GENESIS_BLOCK_HEADER`, have an empty hash, so it's computed from their serialization.
+    pub fn block_hash(&self) -> BlockHash {
+        match self.hash.as_slice().try_into() {
+            Ok(hash) => hash,
+            Err(_) => sha256d::Hash::hash(&self.to_bytes()).to_byte_array(),
+        }
+    }
+
     /// Calculates the target threshold based on the `n_bits` value.
     ///
     /// # Returns
     ///
     /// A 256-bit array representing the target threshold.
     pub fn calculate_target_threshold(&self) -> BlockHash {
-        let bytes_n_bits = self.n_bits.to_le_bytes();
+        Self::compact_to_target(self.n_bits)
+    }
+
+    /// Expands a compact `n_bits` value into its 256-bit target.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_bits` - The compact encoding: 1 byte exponent followed by a 3 byte mantissa.
+    ///
+    /// # Returns
+    ///
+    /// The target as a 256-bit big endian array. Negative or overflowing encodings are
+    /// invalid as targets, so an all-zero target is returned for them.
+    pub fn compact_to_target(n_bits: u32) -> [u8; 32] {
+        let exponent = (n_bits >> 24) as usize;
+        let mantissa = n_bits & COMPACT_MANTISSA_MASK;
+        let mut target = [0u8; 32];
 
-        let exponent = bytes_n_bits[3];
-        let mantisa = &bytes_n_bits[..3];
+        if n_bits & COMPACT_SIGN_BIT != 0 || mantissa == 0 {
+            return target;
+        }
 
-        let mut target_bytes = [0u8; 32];
+        if exponent <= 3 {
+            let value = mantissa >> (8 * (3 - exponent));
+            target[29..].copy_from_slice(&value.to_be_bytes()[1..]);
+            return target;
+        }
 
-        target_bytes[(32 - exponent + 2) as usize] = mantisa[0];
-        target_bytes[(31 - exponent + 2) as usize] = mantisa[1];
-        target_bytes[(30 - exponent + 2) as usize] = mantisa[2];
+        for (i, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+            if *byte == 0 {
+                continue;
+            }
+            if exponent > 32 + i {
+                return [0u8; 32];
+            }
+            target[32 + i - exponent] = *byte;
+        }
 
-        target_bytes
+        target
+    }
+
+    /// Compresses a 256-bit target into its compact `n_bits` encoding.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The target as a 256-bit big endian array.
+    ///
+    /// # Returns
+    ///
+    /// The compact encoding of the target. The mantissa is shifted when its highest bit is set,
+    /// so the result is never interpreted as negative.
+    pub fn target_to_compact(target: &[u8; 32]) -> u32 {
+        let first_non_zero = match target.iter().position(|byte| *byte != 0) {
+            Some(position) => position,
+            None => return 0,
+        };
+        let mut size = 32 - first_non_zero;
+
+        let mut mantissa_bytes = [0u8; 4];
+        let significant = &target[first_non_zero..(first_non_zero + 3).min(32)];
+        mantissa_bytes[1..1 + significant.len()].copy_from_slice(significant);
+        let mut mantissa = u32::from_be_bytes(mantissa_bytes);
+
+        if mantissa & COMPACT_SIGN_BIT != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+
+        mantissa | ((size as u32) << 24)
+    }
+
+    /// Returns the difficulty of the block: how many times harder its target is to meet than the
+    /// maximum target, the one of the genesis block.
+    ///
+    /// # Returns
+    ///
+    /// The difficulty as a float, or 0 if the `n_bits` of the block don't encode a valid target.
+    pub fn difficulty(&self) -> f64 {
+        let target = target_to_f64(&Self::compact_to_target(self.n_bits));
+        if target == 0.0 {
+            return 0.0;
+        }
+        target_to_f64(&Self::compact_to_target(GENESIS_BITS)) / target
+    }
+
+    /// Returns the expected number of hashes needed to find a block with this target, that is
+    /// 2^256 / (target + 1). The division is done in floating point and saturates at `u128::MAX`,
+    /// which only targets far below any real one reach.
+    ///
+    /// # Returns
+    ///
+    /// The work of the block, or 0 if the `n_bits` of the block don't encode a valid target.
+    pub fn work(&self) -> u128 {
+        let target = target_to_f64(&Self::compact_to_target(self.n_bits));
+        if target == 0.0 {
+            return 0;
+        }
+        (2f64.powi(256) / (target + 1.0)) as u128
+    }
+
+    /// Checks that the timestamp of the block is at most two hours ahead of the given time.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time, as a Unix timestamp.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the block timestamp is not more than `MAX_FUTURE_BLOCK_TIME` seconds after `now`.
+    pub fn is_timestamp_not_too_future(&self, now: u32) -> bool {
+        self.timestamp <= now.saturating_add(MAX_FUTURE_BLOCK_TIME)
     }
 
     /// Serialize a block header to a byte array
@@ -180,9 +305,61 @@ impl BlockHeader {
     }
 }
 
+/// Computes the cumulative work of a range of headers, the measure used to pick the branch with the
+/// most proof-of-work.
+///
+/// # Arguments
+///
+/// * `headers` - The headers of the range.
+///
+/// # Returns
+///
+/// The sum of the work of every header, saturating at `u128::MAX`.
+pub fn chainwork(headers: &[BlockHeader]) -> u128 {
+    headers.iter().fold(0, |chainwork, header| {
+        chainwork.saturating_add(header.work())
+    })
+}
+
+/// Computes the mean time between consecutive blocks over the last headers of a chain.
+///
+/// # Arguments
+///
+/// * `headers` - The headers of the chain, oldest first.
+/// * `window` - The number of most recent headers to consider. If there are fewer headers, all of
+///   them are considered.
+///
+/// # Returns
+///
+/// The average interval in seconds, or `None` if fewer than two headers are considered.
+pub fn average_interval(headers: &[BlockHeader], window: usize) -> Option<f64> {
+    let recent = &headers[headers.len().saturating_sub(window)..];
+    match (recent.first(), recent.last()) {
+        (Some(first), Some(last)) if recent.len() > 1 => {
+            Some((last.timestamp as f64 - first.timestamp as f64) / (recent.len() - 1) as f64)
+        }
+        _ => None,
+    }
+}
+
+/// Converts a 256-bit big endian target into a float.
+fn target_to_f64(target: &[u8; 32]) -> f64 {
+    target
+        .iter()
+        .fold(0.0, |value, byte| value * 256.0 + *byte as f64)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{block_header::BlockHeader, node_error::NodeError};
+    use crate::{
+        block::block_hash::InternalHash,
+        block_header::{
+            average_interval, chainwork, BlockHeader, GENESIS_BLOCK_HEADER,
+            REGTEST_GENESIS_BLOCK_HEADER,
+        },
+        constants::GENESIS_TIMESTAMP,
+        node_error::NodeError,
+    };
 
     #[test]
     fn serialize_block_header() {
@@ -257,4 +434,151 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_compact_round_trip_real_n_bits() {
+        let real_n_bits: [u32; 6] = [
+            0x1d00ffff, 0x181bc330, 0x1a05db8b, 0x17034219, 0x1b0404cb, 0x1c05a3f4,
+        ];
+
+        for n_bits in real_n_bits {
+            let target = BlockHeader::compact_to_target(n_bits);
+            assert_eq!(BlockHeader::target_to_compact(&target), n_bits);
+        }
+    }
+
+    #[test]
+    fn test_compact_to_target_genesis() {
+        let target = BlockHeader::compact_to_target(0x1d00ffff);
+
+        let mut expected = [0u8; 32];
+        expected[4] = 0xff;
+        expected[5] = 0xff;
+
+        assert_eq!(target, expected);
+    }
+
+    #[test]
+    fn test_compact_small_exponent() {
+        let target = BlockHeader::compact_to_target(0x01123456);
+        let mut expected = [0u8; 32];
+        expected[31] = 0x12;
+
+        assert_eq!(target, expected);
+        assert_eq!(BlockHeader::target_to_compact(&target), 0x01120000);
+    }
+
+    #[test]
+    fn test_compact_sign_bit_shifts_mantissa() {
+        let target = BlockHeader::compact_to_target(0x05009234);
+
+        assert_eq!(BlockHeader::target_to_compact(&target), 0x05009234);
+    }
+
+    #[test]
+    fn test_compact_negative_and_overflow_are_zero() {
+        assert_eq!(BlockHeader::compact_to_target(0x04923456), [0u8; 32]);
+        assert_eq!(BlockHeader::compact_to_target(0xff123456), [0u8; 32]);
+        assert_eq!(BlockHeader::target_to_compact(&[0u8; 32]), 0);
+    }
+
+    #[test]
+    fn test_difficulty_for_known_n_bits() {
+        let mut block_header = GENESIS_BLOCK_HEADER;
+        assert_eq!(block_header.difficulty(), 1.0);
+
+        block_header.n_bits = 0x1b0404cb;
+        assert!((block_header.difficulty() - 16307.420938523983).abs() < 1e-6);
+
+        block_header.n_bits = 0x04923456;
+        assert_eq!(block_header.difficulty(), 0.0);
+    }
+
+    #[test]
+    fn test_harder_headers_add_more_chainwork() {
+        let mut harder_header = GENESIS_BLOCK_HEADER;
+        harder_header.n_bits = 0x1b0404cb;
+        let mut invalid_header = GENESIS_BLOCK_HEADER;
+        invalid_header.n_bits = 0x04923456;
+
+        assert_eq!(GENESIS_BLOCK_HEADER.work(), 4_295_032_833);
+        assert!(harder_header.work() > GENESIS_BLOCK_HEADER.work());
+        assert_eq!(invalid_header.work(), 0);
+
+        let headers = [
+            GENESIS_BLOCK_HEADER,
+            harder_header.clone(),
+            invalid_header,
+            GENESIS_BLOCK_HEADER,
+        ];
+        let chainworks: Vec<u128> = (0..=headers.len())
+            .map(|end| chainwork(&headers[..end]))
+            .collect();
+        assert_eq!(chainworks[0], 0);
+        assert!(chainworks.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(
+            chainworks[4],
+            2 * GENESIS_BLOCK_HEADER.work() + harder_header.work()
+        );
+    }
+
+    #[test]
+    fn test_average_interval_of_headers_at_fixed_intervals() {
+        let headers: Vec<BlockHeader> = [0, 3000, 3600, 4200, 4800, 5400]
+            .iter()
+            .map(|offset| BlockHeader {
+                timestamp: GENESIS_TIMESTAMP + offset,
+                ..GENESIS_BLOCK_HEADER
+            })
+            .collect();
+
+        assert_eq!(average_interval(&headers, 5), Some(600.0));
+        assert_eq!(average_interval(&headers, 6), Some(1080.0));
+        assert_eq!(average_interval(&headers, 100), Some(1080.0));
+        assert_eq!(average_interval(&headers, 1), None);
+        assert_eq!(average_interval(&[], 5), None);
+    }
+
+    #[test]
+    fn test_timestamp_two_hours_ahead_is_not_too_future() {
+        let now = 1_700_000_000;
+        let mut block_header = GENESIS_BLOCK_HEADER;
+
+        block_header.timestamp = now + 7200;
+        assert!(block_header.is_timestamp_not_too_future(now));
+
+        block_header.timestamp = now + 7201;
+        assert!(!block_header.is_timestamp_not_too_future(now));
+    }
+
+    #[test]
+    fn test_block_hash_of_genesis_header_is_its_known_hash() -> Result<(), NodeError> {
+        let block_header = GENESIS_BLOCK_HEADER;
+        assert!(block_header.hash().is_empty());
+
+        let hash = block_header.block_hash().to_vec();
+
+        assert_eq!(
+            InternalHash::from_slice(&hash)?.to_display().to_string(),
+            "b72871578430604d489686f3f2cea8dff206bf7575745f72f90cdc4581445a1a"
+        );
+        assert_eq!(
+            &hash,
+            BlockHeader::from_bytes(&GENESIS_BLOCK_HEADER.to_bytes())?.hash()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_regtest_genesis_header_has_the_regtest_genesis_hash() -> Result<(), NodeError> {
+        let block_header = REGTEST_GENESIS_BLOCK_HEADER;
+
+        let hash = block_header.block_hash();
+
+        assert_eq!(
+            InternalHash::from_slice(&hash)?.to_display().to_string(),
+            "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"
+        );
+        Ok(())
+    }
 }
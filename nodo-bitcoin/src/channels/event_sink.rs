@@ -0,0 +1,81 @@
+use std::sync::mpsc;
+
+use crate::{
+    block_header::BlockHeader, node_error::NodeError, transactions::transaction::Transaction,
+    ui::ui_message::UIMessage,
+};
+
+/// The events of the node, independent of the UI that shows them.
+#[derive(Debug, Clone)]
+pub enum NodeEvent {
+    /// A new block was received and validated
+    NewBlock(BlockHeader),
+    /// The chain tip changed after saving a block: height and block hash
+    ChainTip(u64, String),
+    /// A new transaction was received from a peer
+    NewTransaction(Transaction),
+    /// A block of the initial block download was saved
+    BlockDownloaded,
+    /// A batch of headers of the initial headers download was received
+    HeadersDownloaded,
+    /// The initial headers download finished
+    HeadersDownloadFinished,
+    /// The starting date of the download, read from the config
+    StartingDate(String),
+    /// The number of blocks of the initial block download
+    TotalBlocksToDownload(usize),
+    /// The last block headers of the initial headers download
+    InitialBlockHeaders(Vec<BlockHeader>),
+    /// A message for the user, such as a failed download
+    Notification(String),
+    /// A line written to the log
+    LogLine(String),
+}
+
+/// Receives the events emitted by the node, such as the GTK UI or a headless consumer.
+pub trait EventSink {
+    /// Emits an event to the sink.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToSendMessage` if the event could not be delivered.
+    fn emit(&self, event: NodeEvent) -> Result<(), NodeError>;
+}
+
+impl EventSink for mpsc::Sender<NodeEvent> {
+    fn emit(&self, event: NodeEvent) -> Result<(), NodeError> {
+        self.send(event).map_err(|_| {
+            NodeError::FailedToSendMessage("Failed to send event to the channel".to_string())
+        })
+    }
+}
+
+impl EventSink for glib::Sender<UIMessage> {
+    /// Sends the event to the UI as the matching `UIMessage`. Events the UI doesn't show are skipped.
+    fn emit(&self, event: NodeEvent) -> Result<(), NodeError> {
+        let message = match event {
+            NodeEvent::NewBlock(block_header) => UIMessage::NewBlock(block_header),
+            NodeEvent::ChainTip(height, block_hash) => UIMessage::ChainTip(height, block_hash),
+            NodeEvent::BlockDownloaded => UIMessage::UpdateBlocksProgress,
+            NodeEvent::HeadersDownloaded => UIMessage::UpdateHeadersProgress,
+            NodeEvent::HeadersDownloadFinished => UIMessage::HeadersDownloadFinished,
+            NodeEvent::StartingDate(date) => UIMessage::StartingDate(date),
+            NodeEvent::TotalBlocksToDownload(total) => {
+                UIMessage::TotalBlocksToDownload(total.try_into().map_err(|_| {
+                    NodeError::FailedToSendMessage(
+                        "Failed to convert the total blocks to download".to_string(),
+                    )
+                })?)
+            }
+            NodeEvent::InitialBlockHeaders(block_headers) => {
+                UIMessage::InitialBlockHeaders(block_headers)
+            }
+            NodeEvent::Notification(message) => UIMessage::NotificationMessage(message),
+            NodeEvent::LogLine(line) => UIMessage::LogLine(line),
+            NodeEvent::NewTransaction(_) => return Ok(()),
+        };
+        self.send(message).map_err(|_| {
+            NodeError::FailedToSendMessage("Failed to send event to the UI".to_string())
+        })
+    }
+}
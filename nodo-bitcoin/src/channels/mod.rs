@@ -1,2 +1,3 @@
+pub mod event_sink;
 pub mod ui_channel;
 pub mod wallet_channel;
@@ -1,11 +1,15 @@
 use std::io::{BufRead, BufReader};
-use std::{env, fs::File};
+use std::{
+    env,
+    fs::{self, File},
+};
 
+use crate::channels::event_sink::{EventSink, NodeEvent};
 use crate::constants::{
     STARTING_DATE, {DEFAULT_CONFIG, PATH_LOG},
 };
+use crate::node::read::{retrieve_log_append, retrieve_log_rotate};
 use crate::node_error::NodeError;
-use crate::ui::ui_message::UIMessage;
 
 /// Returns the path to the configuration file.
 ///
@@ -23,28 +27,28 @@ fn get_config_path(default_file: String) -> String {
 ///
 /// The function reads a configuration file, specified either by the `CONFIG` environment variable
 /// or by a default path. The configuration file should contain key-value pairs, with each line
-/// representing a separate pair. The function sets environment variables for each key-value pair
-/// found in the configuration file.
+/// representing a separate pair. Blank lines and lines starting with `#` are ignored. The function
+/// sets environment variables for each key-value pair found in the configuration file.
+///
+/// The log file of the previous run is deleted, unless LOG_APPEND keeps it or LOG_ROTATE moves it
+/// to `<PATH_LOG>.1`. The starting date of the download is emitted to `events`, if given.
 ///
 /// # Errors
 ///
-/// The function returns an error if the configuration file could not be opened or read.
+/// The function returns an error if the configuration file could not be opened or read, if a
+/// line is not formatted correctly, or if the starting date could not be emitted.
 ///
-pub fn load_app_config(ui_sender: Option<&glib::Sender<UIMessage>>) -> Result<(), NodeError> {
+pub fn load_app_config(events: Option<&dyn EventSink>) -> Result<(), NodeError> {
     let path_config = get_config_path(DEFAULT_CONFIG.to_string());
     let file = File::open(path_config)
         .map_err(|_| NodeError::FailedToOpenFile("Failed to open config file".to_string()))?;
     let reader = BufReader::new(file);
 
-    for line in reader.lines() {
-        let line_content =
-            line.map_err(|_| NodeError::FailedToRead("Failed to read line".to_string()))?;
-        let (key, value) = parse_line(&line_content)?;
+    for (key, value) in parse_config(reader)? {
+        let (key, value) = (key.as_str(), value.as_str());
         if key == STARTING_DATE {
-            if let Some(sender) = ui_sender {
-                sender
-                    .send(UIMessage::StartingDate(value.to_string()))
-                    .expect("Failed to send starting date");
+            if let Some(events) = events {
+                events.emit(NodeEvent::StartingDate(value.to_string()))?;
             }
         }
         configure_environ_var(key, value);
@@ -52,11 +56,32 @@ pub fn load_app_config(ui_sender: Option<&glib::Sender<UIMessage>>) -> Result<()
     let path_log = std::env::var(PATH_LOG).map_err(|_| {
         NodeError::EnvironVarNotFound("PATH_LOG not found in env vars to delete".to_string())
     })?;
-    let _ = std::fs::remove_file(path_log);
+    prepare_log_file(&path_log, retrieve_log_append(), retrieve_log_rotate());
 
     Ok(())
 }
 
+/// Prepares the log file for a new run of the node.
+///
+/// # Arguments
+///
+/// * `path_log` - The path of the log file.
+/// * `append` - Whether the log of the previous runs is kept, so the new lines are appended to it.
+/// * `rotate` - Whether the log of the previous run is moved to `<path_log>.1` instead of being
+///   deleted. It's ignored when appending.
+///
+/// Failing to delete or move the log is not an error: the log might not exist yet.
+fn prepare_log_file(path_log: &str, append: bool, rotate: bool) {
+    if append {
+        return;
+    }
+    if rotate {
+        let _ = fs::rename(path_log, format!("{}.1", path_log));
+    } else {
+        let _ = fs::remove_file(path_log);
+    }
+}
+
 /// Returns the directory path for the key passed as an argument from the environment variables.
 pub fn obtain_dir_path(config_key: String) -> Result<String, NodeError> {
     let directory = match std::env::var(&config_key) {
@@ -69,26 +94,57 @@ pub fn obtain_dir_path(config_key: String) -> Result<String, NodeError> {
     Ok(directory)
 }
 
+/// Parse the key-value pairs of a configuration.
+///
+/// Blank lines and lines starting with `#` are skipped, so the configuration can be documented
+/// inline.
+///
+/// # Errors
+///
+/// The function returns an error if a line could not be read, or if a line is not formatted
+/// correctly, reporting its line number.
+pub fn parse_config<R: BufRead>(reader: R) -> Result<Vec<(String, String)>, NodeError> {
+    let mut pairs = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line_content =
+            line.map_err(|_| NodeError::FailedToRead("Failed to read line".to_string()))?;
+        let trimmed_line = line_content.trim();
+        if trimmed_line.is_empty() || trimmed_line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = parse_line(trimmed_line).map_err(|_| {
+            NodeError::FailedToParse(format!(
+                "Invalid config line {}: {}",
+                index + 1,
+                trimmed_line
+            ))
+        })?;
+        pairs.push((key.to_string(), value.to_string()));
+    }
+    Ok(pairs)
+}
+
 /// Parse a key-value pair from a line of text.
 ///
 /// The function takes a line of text containing a single key-value pair in the format `key=value`.
-/// The function returns a tuple containing the key and value as separate strings.
+/// The function returns a tuple containing the key and value as separate strings, with the
+/// whitespace around them trimmed.
 ///
 /// # Errors
 ///
 /// The function returns an error if the input line is not formatted correctly, i.e. if it does not
-/// contain an equal sign or if either the key or value is missing.
+/// contain an equal sign or if the key is missing.
 pub fn parse_line(line: &str) -> Result<(&str, &str), NodeError> {
-    let mut split = line.split('=');
-    let key = split
-        .next()
-        .ok_or("Invalid config file format")
-        .map_err(|_| NodeError::FailedToParse("Failed to parse key from config".to_string()))?;
-    let value = split
-        .next()
-        .ok_or("Invalid config file format")
-        .map_err(|_| NodeError::FailedToParse("Failed to parse value from config".to_string()))?;
-    Ok((key, value))
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| NodeError::FailedToParse("Failed to parse value from config".to_string()))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(NodeError::FailedToParse(
+            "Failed to parse key from config".to_string(),
+        ));
+    }
+    Ok((key, value.trim()))
 }
 
 /// Set an environment variable with the given key and value.
@@ -98,10 +154,10 @@ fn configure_environ_var(key: &str, value: &str) {
 
 #[cfg(test)]
 mod test {
-    use std::env;
+    use std::{env, fs, io::Cursor};
 
     use crate::{
-        config::{load_app_config, parse_line},
+        config::{load_app_config, parse_config, parse_line, prepare_log_file},
         node_error::NodeError,
     };
 
@@ -111,6 +167,28 @@ mod test {
         assert!(env::var("DNS").is_ok());
     }
 
+    #[test]
+    fn test_log_survives_a_reload_in_append_mode() {
+        let path_log = "blocks-test/test_log_append.txt";
+        let rotated_log = "blocks-test/test_log_append.txt.1";
+        fs::write(path_log, "previous run\n").unwrap();
+
+        prepare_log_file(path_log, true, false);
+        let appended = fs::read_to_string(path_log).ok();
+        prepare_log_file(path_log, false, true);
+        let rotated = fs::read_to_string(rotated_log).ok();
+        let exists_after_rotation = fs::metadata(path_log).is_ok();
+        fs::write(path_log, "previous run\n").unwrap();
+        prepare_log_file(path_log, false, false);
+        let exists_after_deletion = fs::metadata(path_log).is_ok();
+        let _ = fs::remove_file(rotated_log);
+
+        assert_eq!(appended.as_deref(), Some("previous run\n"));
+        assert_eq!(rotated.as_deref(), Some("previous run\n"));
+        assert!(!exists_after_rotation);
+        assert!(!exists_after_deletion);
+    }
+
     #[test]
     fn test_parse_line() -> Result<(), NodeError> {
         let line = "DNS=seed.testnet.bitcoin.sprovoost.nl";
@@ -125,4 +203,41 @@ mod test {
         let line = "DNS";
         parse_line(line).expect_err("Invalid config file format");
     }
+
+    #[test]
+    fn test_parse_config_skips_comments() -> Result<(), NodeError> {
+        let config = "# peer list\nDNS = seed.testnet.bitcoin.sprovoost.nl\n  # port\nPORT=18333";
+        let pairs = parse_config(Cursor::new(config))?;
+        assert_eq!(
+            pairs,
+            vec![
+                (
+                    "DNS".to_string(),
+                    "seed.testnet.bitcoin.sprovoost.nl".to_string()
+                ),
+                ("PORT".to_string(), "18333".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_config_skips_blank_lines() -> Result<(), NodeError> {
+        let config = "\nDNS=seed.testnet.bitcoin.sprovoost.nl\n   \n\nPORT=18333\n";
+        let pairs = parse_config(Cursor::new(config))?;
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[1], ("PORT".to_string(), "18333".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_config_reports_bad_line_number() {
+        let config = "# peer list\nDNS=seed.testnet.bitcoin.sprovoost.nl\n\nPORT 18333";
+        match parse_config(Cursor::new(config)) {
+            Err(NodeError::FailedToParse(message)) => {
+                assert_eq!(message, "Invalid config line 4: PORT 18333")
+            }
+            result => panic!("Expected a parse error, got {:?}", result),
+        }
+    }
 }
@@ -9,13 +9,13 @@ use crate::node_error::NodeError;
 ///
 /// # Arguments
 ///
-/// * stream - A mutable reference to a TcpStream over which to send the message.
+/// * stream - A mutable reference to a stream, usually a TcpStream, over which to send the message.
 /// * message - A vector of bytes containing the message to send.
 ///
 /// # Errors
 ///
 /// Returns a NodeError::FailedToSendMessage if the message fails to send.
-pub fn send_message(stream: &mut TcpStream, message: Vec<u8>) -> Result<(), NodeError> {
+pub fn send_message<W: Write>(stream: &mut W, message: Vec<u8>) -> Result<(), NodeError> {
     stream
         .write_all(&message)
         .map_err(|e| NodeError::FailedToSendMessage(format!("Failed to send message: {}", e)))?;
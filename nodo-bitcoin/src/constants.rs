@@ -2,6 +2,8 @@ pub const DNS: &str = "DNS";
 pub const PORT: &str = "PORT";
 pub const DEFAULT_CONFIG: &str = "nodo.conf";
 pub const LOCAL_IP: &str = "LOCAL_IP";
+pub const SERVER_BIND_ADDR: &str = "SERVER_BIND_ADDR";
+pub const SERVER_PORT: &str = "SERVER_PORT";
 pub const VERSION: &str = "VERSION";
 pub const DEFAULT_VERSION: i32 = 70015;
 pub const BLOCK_HEADERS_FILE: &str = "PATH_BLOCK_HEADERS";
@@ -27,30 +29,77 @@ pub const COMMAND_NAME_INV: &str = "inv";
 pub const COMMAND_NAME_BLOCK: &str = "block";
 pub const COMMAND_NAME_TX: &str = "tx";
 pub const COMMAND_NAME_NOTFOUND: &str = "notfound";
+pub const COMMAND_NAME_SENDCMPCT: &str = "sendcmpct";
+pub const COMMAND_NAME_GETCFILTERS: &str = "getcfilters";
+pub const COMMAND_NAME_GETCFHEADERS: &str = "getcfheaders";
+pub const COMMAND_NAME_CFILTER: &str = "cfilter";
+pub const COMMAND_NAME_CFHEADERS: &str = "cfheaders";
 pub const GENESIS_TIMESTAMP: u32 = 1231006505;
 pub const GENESIS_BITS: u32 = 486604799;
 pub const GENESIS_NONCE: u32 = 2083236893;
+pub const COMPACT_SIGN_BIT: u32 = 0x0080_0000;
+pub const COMPACT_MANTISSA_MASK: u32 = 0x007f_ffff;
 pub const MAX_HEADERS_COUNT: u64 = 2000;
+pub const MAX_LOCATOR_HASHES: u64 = 101;
 pub const INVENTORY_LENGTH: usize = 36;
 pub const MAX_INVENTORY_VECTOR: u64 = 50000;
 pub const MSG_TX: u32 = 1;
 pub const MSG_BLOCK: u32 = 2;
+pub const MSG_FILTERED_BLOCK: u32 = 3;
+pub const MSG_CMPCT_BLOCK: u32 = 4;
+pub const MSG_WITNESS_TX: u32 = 0x40000001;
+pub const MSG_WITNESS_BLOCK: u32 = 0x40000002;
+pub const MSG_FILTERED_WITNESS_BLOCK: u32 = 0x40000003;
 pub const STARTING_DATE: &str = "STARTING_DATE";
 pub const PATH_LOG: &str = "PATH_LOG";
 pub const MAX_THREADS: usize = 21;
+pub const MAX_DOWNLOAD_WORKERS: &str = "MAX_DOWNLOAD_WORKERS";
+pub const MAX_LISTENER_WORKERS: &str = "MAX_LISTENER_WORKERS";
 pub const LENGTH_SEQUENCE: usize = 4;
 pub const LENGTH_VERSION: usize = 4;
 pub const LENGTH_VALUE: usize = 8;
 pub const LENGTH_LOCK_TIME: usize = 4;
 pub const LENGTH_HEIGHT: usize = 4;
 pub const LENGTH_INDEX: usize = 4;
+pub const SEGWIT_MARKER: u8 = 0x00;
+pub const SEGWIT_FLAG: u8 = 0x01;
 pub const MAX_FAILED_COUNT: usize = 2;
+pub const MAX_BLOCK_RETRIES: &str = "MAX_BLOCK_RETRIES";
+pub const DEFAULT_MAX_BLOCK_RETRIES: usize = 3;
+pub const MAX_OPEN_BLOCK_FILES: &str = "MAX_OPEN_BLOCK_FILES";
+pub const DEFAULT_MAX_OPEN_BLOCK_FILES: usize = 16;
 pub const SIGHASH_ALL: u32 = 1;
 pub const PATH_BLOCKS: &str = "PATH_BLOCKS";
+pub const VALIDATED_BLOCKS_FILE: &str = "validated_blocks.txt";
 pub const OP_DUP: u8 = 0x76;
 pub const OP_HASH160: u8 = 0xa9;
 pub const OP_CHECKSIG: u8 = 0xac;
 pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const FULL_VALIDATION: &str = "FULL_VALIDATION";
+pub const MIN_RELAY_FEE_RATE: &str = "MIN_RELAY_FEE_RATE";
+pub const DEFAULT_MIN_RELAY_FEE_RATE: f64 = 1.0;
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+pub const LENGTH_FEE_RATE: usize = 8;
+pub const P2PKH_INPUT_SIZE: usize = 148;
+pub const P2PKH_OUTPUT_SIZE: usize = 34;
+pub const MAX_FUTURE_BLOCK_TIME: u32 = 7200;
+pub const BROADCAST_PEER_COUNT: &str = "BROADCAST_PEER_COUNT";
+pub const DEFAULT_BROADCAST_PEER_COUNT: usize = 3;
+pub const UTXO_MEMORY_LIMIT: &str = "UTXO_MEMORY_LIMIT";
+pub const UTXO_SPILL_FILE: &str = "PATH_UTXO_SPILL";
+pub const BLOCKS_SHARDED_BY_DATE: &str = "BLOCKS_SHARDED_BY_DATE";
+pub const MAX_CONNECTIONS: &str = "MAX_CONNECTIONS";
+pub const DEFAULT_MAX_CONNECTIONS: usize = 125;
+pub const DNS_RETRIES: &str = "DNS_RETRIES";
+pub const DEFAULT_DNS_RETRIES: usize = 3;
+pub const DNS_RETRY_BACKOFF: u64 = 500;
+pub const PEERS_CACHE_FILE: &str = "PATH_PEERS_CACHE";
+pub const PEERS: &str = "PEERS";
+pub const LOG_LEVEL: &str = "LOG_LEVEL";
+pub const PROGRESS_LOG_INTERVAL: &str = "PROGRESS_LOG_INTERVAL";
+pub const DEFAULT_PROGRESS_LOG_INTERVAL: u64 = 30;
 pub const PK_HASH_LENGTH: u8 = 0x14;
 pub const SAVED_ACCOUNTS: &str = "saved_accounts.txt";
 pub const SATOSHI_CONVERSION_COEFFICIENT: f64 = 100000000.0;
@@ -60,6 +109,92 @@ pub const HEXADECIMAL_DIGITS_BASE: u32 = 16;
 pub const LENGTH_IP: usize = 4;
 pub const NO_ARGS_LEN: usize = 1;
 pub const CONNECTION_TIMEOUT: u64 = 10;
+pub const HANDSHAKE_TIMEOUT: u64 = 10;
+pub const BLOCK_REQUEST_TIMEOUT: u64 = 3;
+pub const HEADERS_MESSAGE_TIMEOUT: u64 = 30;
 pub const MAX_RETRY_ATTEMPTS: u64 = 2;
 pub const COMPLETE_DOWNLOAD_FRACTION: f64 = 1.0;
 pub const BLOCKS_TO_SHOW: usize = 10000;
+pub const OP_RETURN: u8 = 0x6a;
+pub const BASIC_FILTER_P: u8 = 19;
+pub const BASIC_FILTER_M: u64 = 784931;
+pub const BASIC_FILTER_TYPE: u8 = 0;
+pub const INITIAL_BLOCK_SUBSIDY: u64 = 5_000_000_000;
+pub const SUBSIDY_HALVING_INTERVAL: u32 = 210_000;
+pub const MIN_BLOCKS_PER_MINUTE: f64 = 2.0;
+pub const DOWNLOAD_RATE_WINDOW: u64 = 60;
+pub const OP_EQUAL: u8 = 0x87;
+pub const TESTNET_P2PKH_VERSION: u8 = 0x6f;
+pub const TESTNET_P2SH_VERSION: u8 = 0xc4;
+pub const ADDRESS_LENGTH: usize = 25;
+pub const ADDRESS_CHECKSUM_LENGTH: usize = 4;
+pub const MESSAGE_SIGNATURE_PREFIX: &str = "Bitcoin Signed Message:\n";
+pub const MESSAGE_SIGNATURE_LENGTH: usize = 65;
+pub const MESSAGE_SIGNATURE_HEADER: u8 = 27;
+pub const MESSAGE_SIGNATURE_COMPRESSED_FLAG: u8 = 4;
+pub const MAINNET_P2PKH_VERSION: u8 = 0x00;
+pub const MAINNET_P2SH_VERSION: u8 = 0x05;
+pub const OP_0: u8 = 0x00;
+pub const MAX_MONEY: i64 = 2_100_000_000_000_000;
+pub const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+pub const WITNESS_COMMITMENT_LENGTH: usize = 38;
+pub const LOG_CONSOLE_MAX_LINES: i32 = 500;
+pub const HEADERS_ONLY: &str = "HEADERS_ONLY";
+pub const COMMAND_NAME_MERKLEBLOCK: &str = "merkleblock";
+pub const COMMAND_NAME_FILTERLOAD: &str = "filterload";
+pub const BLOOM_UPDATE_ALL: u8 = 1;
+pub const BLOOM_MAX_FILTER_SIZE: usize = 36000;
+pub const BLOOM_MAX_HASH_FUNCS: u32 = 50;
+pub const BLOOM_SEED_MULTIPLIER: u32 = 0xfba4c795;
+pub const NODE_BLOOM: u64 = 1 << 2;
+pub const MERKLE_BLOCK_TIMEOUT: u64 = 30;
+pub const MAX_BLOCK_SIZE: usize = 4_000_000;
+pub const MIN_TX_INPUT_SIZE: usize = 41;
+pub const MIN_TX_OUTPUT_SIZE: usize = 9;
+pub const WALLET_AUTOSAVE_SECS: &str = "WALLET_AUTOSAVE_SECS";
+pub const DEFAULT_WALLET_AUTOSAVE_SECS: u64 = 60;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+pub const CONFIRMATION_DEPTH: &str = "CONFIRMATION_DEPTH";
+pub const DEFAULT_CONFIRMATION_DEPTH: u32 = 1;
+pub const MAX_REORG_DEPTH: &str = "MAX_REORG_DEPTH";
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 100;
+pub const TESTNET_CHECKPOINT_HEIGHT: u64 = 546;
+pub const BIP21_URI_SCHEME: &str = "bitcoin:";
+pub const BLOCK_INTERVAL_WINDOW: usize = 144;
+pub const USER_AGENT: &str = "USER_AGENT";
+pub const DEFAULT_USER_AGENT: &str = "";
+pub const MAX_USER_AGENT_LENGTH: usize = 256;
+pub const MAX_HANDSHAKE_MESSAGES: usize = 10;
+pub const MAX_TX_SIZE: &str = "MAX_TX_SIZE";
+pub const DEFAULT_MAX_TX_SIZE: usize = 100_000;
+pub const NETWORK: &str = "NETWORK";
+pub const REGTEST_NETWORK: &str = "regtest";
+pub const REGTEST_MAGIC_BYTES: [u8; 4] = [0xFA, 0xBF, 0xB5, 0xDA];
+pub const GENESIS_MERKLE_ROOT: [u8; 32] = [
+    0x3b, 0xa3, 0xed, 0xfd, 0x7a, 0x7b, 0x12, 0xb2, 0x7a, 0xc7, 0x2c, 0x3e, 0x67, 0x76, 0x8f, 0x61,
+    0x7f, 0xc8, 0x1b, 0xc3, 0x88, 0x8a, 0x51, 0x32, 0x3a, 0x9f, 0xb8, 0xaa, 0x4b, 0x1e, 0x5e, 0x4a,
+];
+pub const REGTEST_GENESIS_TIMESTAMP: u32 = 1296688602;
+pub const REGTEST_GENESIS_BITS: u32 = 0x207fffff;
+pub const REGTEST_GENESIS_NONCE: u32 = 2;
+pub const BIP34_MIN_BLOCK_VERSION: i32 = 2;
+pub const TESTNET_BIP34_HEIGHT: u32 = 21_111;
+pub const REGTEST_BIP34_HEIGHT: u32 = 1;
+pub const UNCONFIRMED_TX_MAX_AGE: u32 = 1_209_600;
+pub const LOG_APPEND: &str = "LOG_APPEND";
+pub const LOG_ROTATE: &str = "LOG_ROTATE";
+pub const COMMAND_NAME_MEMPOOL: &str = "mempool";
+pub const REQUEST_MEMPOOL: &str = "REQUEST_MEMPOOL";
+pub const LOG_MESSAGE_TYPES: &str = "LOG_MESSAGE_TYPES";
+pub const IBD_TIMEOUT_SECS: &str = "IBD_TIMEOUT_SECS";
+pub const DEFAULT_IBD_TIMEOUT_SECS: u64 = 86_400;
+pub const IBD_JOIN_POLL_INTERVAL_MILLIS: u64 = 100;
+pub const HISTORY_CSV_PREFIX: &str = "history_";
+pub const ACCOUNT_HISTORY_PREFIX: &str = "account_history_";
+pub const CHANGE_ACCOUNT: &str = "CHANGE_ACCOUNT";
+pub const PEER_FILTERS_CHECKED: &str = "PEER_FILTERS_CHECKED";
+pub const DEFAULT_PEER_FILTERS_CHECKED: usize = 0;
+pub const BLOOM_FALSE_POSITIVE_RATE: f64 = 0.0001;
+pub const QR_CODE_MODULE_PIXELS: usize = 4;
+pub const QR_CODE_QUIET_ZONE_MODULES: usize = 4;
@@ -1,14 +1,15 @@
-use std::net::TcpStream;
+use std::{io::Read, net::TcpStream};
 
 use crate::{
     connectors::peer_connector::receive_message,
     constants::{
-        COMMAND_NAME_ADDR, COMMAND_NAME_BLOCK, COMMAND_NAME_FEEFILTER, COMMAND_NAME_GETHEADERS,
-        COMMAND_NAME_GET_DATA, COMMAND_NAME_HEADERS, COMMAND_NAME_INV, COMMAND_NAME_NOTFOUND,
-        COMMAND_NAME_PING, COMMAND_NAME_PONG, COMMAND_NAME_SENDHEADERS, COMMAND_NAME_TX,
-        COMMAND_NAME_VERACK, COMMAND_NAME_VERSION, LENGTH_HEADER_MESSAGE, TESTNET_MAGIC_BYTES,
+        COMMAND_NAME_ADDR, COMMAND_NAME_BLOCK, COMMAND_NAME_CFHEADERS, COMMAND_NAME_CFILTER,
+        COMMAND_NAME_FEEFILTER, COMMAND_NAME_GETHEADERS, COMMAND_NAME_GET_DATA,
+        COMMAND_NAME_HEADERS, COMMAND_NAME_INV, COMMAND_NAME_MERKLEBLOCK, COMMAND_NAME_NOTFOUND,
+        COMMAND_NAME_PING, COMMAND_NAME_PONG, COMMAND_NAME_SENDCMPCT, COMMAND_NAME_SENDHEADERS,
+        COMMAND_NAME_TX, COMMAND_NAME_VERACK, COMMAND_NAME_VERSION, LENGTH_HEADER_MESSAGE,
     },
-    node::message_type::MessageType,
+    node::{message_type::MessageType, read::retrieve_magic_bytes},
     node_error::NodeError,
 };
 
@@ -102,7 +103,8 @@ impl Header {
         Ok(bytes)
     }
 
-    /// Creates a new header byte array for the command_name message.
+    /// Creates a new header byte array for the command_name message, started by the magic bytes of
+    /// the configured network.
     ///
     /// # Arguments
     ///
@@ -119,7 +121,11 @@ impl Header {
         message_bytes: &Vec<u8>,
         command_name: &str,
     ) -> Result<Vec<u8>, NodeError> {
-        Self::new_header_bytes(TESTNET_MAGIC_BYTES, command_name.as_bytes(), message_bytes)
+        Self::new_header_bytes(
+            retrieve_magic_bytes(),
+            command_name.as_bytes(),
+            message_bytes,
+        )
     }
 
     /// Given a message header in the form of a byte array, returns the size of the payload as a u64.
@@ -191,23 +197,42 @@ impl Header {
             COMMAND_NAME_NOTFOUND => Ok(MessageType::NotFound),
             COMMAND_NAME_TX => Ok(MessageType::Tx),
             COMMAND_NAME_GET_DATA => Ok(MessageType::GetData),
-            _ => Err(NodeError::CommandTypeError(format!(
-                "Unknown command name: {:?}",
-                command_name_bytes
-            ))),
+            COMMAND_NAME_SENDCMPCT => Ok(MessageType::SendCmpct),
+            COMMAND_NAME_CFILTER => Ok(MessageType::CFilter),
+            COMMAND_NAME_CFHEADERS => Ok(MessageType::CFHeaders),
+            COMMAND_NAME_MERKLEBLOCK => Ok(MessageType::MerkleBlock),
+            _ => Ok(MessageType::Unknown(command_name.to_string())),
         }
     }
+
+    /// Reads and discards the payload of the message, so the next message can be read.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The reader the message is being received from.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the payload could not be read.
+    pub fn skip_payload<R: Read>(&self, source: &mut R) -> Result<(), NodeError> {
+        receive_message(source, self.payload_size())?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::{
+        io::Cursor,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+    };
 
     use super::*;
     use crate::{
         config::load_app_config,
-        constants::{COMMAND_NAME_VERSION, TESTNET_MAGIC_BYTES},
+        constants::{COMMAND_NAME_PING, COMMAND_NAME_VERSION, TESTNET_MAGIC_BYTES},
         messages::version_message::VersionMessage,
+        node::receive_messages::receive_sendcmpct_message,
     };
 
     #[test]
@@ -290,4 +315,42 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_unknown_command_payload_is_skipped() -> Result<(), NodeError> {
+        let payload = vec![1; 37];
+        let mut bytes = Header::create_header(&payload, "getcfilters")?;
+        bytes.extend(&payload);
+        bytes.extend(Header::create_header(&vec![], COMMAND_NAME_PING)?);
+        let mut cursor = Cursor::new(bytes);
+
+        let mut header = Header::from_bytes(&receive_message(&mut cursor, 24)?);
+        assert_eq!(
+            header.extract_command_name()?,
+            MessageType::Unknown("getcfilters".to_string())
+        );
+        header.skip_payload(&mut cursor)?;
+
+        let mut next_header = Header::from_bytes(&receive_message(&mut cursor, 24)?);
+        assert_eq!(next_header.extract_command_name()?, MessageType::Ping);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sendcmpct_is_recognized_and_its_payload_skipped() -> Result<(), NodeError> {
+        let mut payload = vec![0];
+        payload.extend(1u64.to_le_bytes());
+        let mut bytes = Header::create_header(&payload, COMMAND_NAME_SENDCMPCT)?;
+        bytes.extend(&payload);
+        bytes.extend(Header::create_header(&vec![], COMMAND_NAME_PING)?);
+        let mut cursor = Cursor::new(bytes);
+
+        let mut header = Header::from_bytes(&receive_message(&mut cursor, 24)?);
+        assert_eq!(header.extract_command_name()?, MessageType::SendCmpct);
+        receive_sendcmpct_message(&mut cursor, &header)?;
+
+        let mut next_header = Header::from_bytes(&receive_message(&mut cursor, 24)?);
+        assert_eq!(next_header.extract_command_name()?, MessageType::Ping);
+        Ok(())
+    }
 }
@@ -1,16 +1,70 @@
 use std::{
     fs::OpenOptions,
     io::{BufWriter, Write},
-    sync::mpsc::{self, Receiver, Sender},
-    thread,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
 };
 
-use crate::{constants::PATH_LOG, node_error::NodeError};
+use crate::{
+    channels::event_sink::{EventSink, NodeEvent},
+    constants::PATH_LOG,
+    node::{
+        message_type::MessageType,
+        read::{retrieve_log_level, retrieve_log_message_types},
+    },
+    node_error::NodeError,
+};
+
+pub mod progress_logger;
+
+/// The level of a log message. Messages below the level of the logger are discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Detailed messages, such as every message received from a peer.
+    Debug,
+    /// Regular messages.
+    Info,
+}
+
+/// The messages received by the logger thread.
+enum LogEntry {
+    /// A line to write to the log file.
+    Line(String),
+    /// Stops the logger thread once the previous lines are written.
+    Shutdown,
+}
+
+/// Shuts a logger down when dropped, so the messages logged before a function returns early, such as
+/// on an error, are still written to the log file.
+pub struct ShutdownGuard {
+    logger: Option<Logger>,
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        if let Some(logger) = self.logger.take() {
+            if let Err(e) = logger.shutdown() {
+                println!("Failed to shut the logger down: {:?}", e);
+            }
+        }
+    }
+}
 
-/// A logger that writes messages to a file.
+/// A logger that writes messages to a file, and optionally emits them to an event sink such as the UI.
 #[derive(Clone)]
 pub struct Logger {
-    sender: Sender<String>,
+    sender: Sender<LogEntry>,
+    /// The lowest level of the messages written to the file.
+    level: LogLevel,
+    /// The lowercased names of the message types whose messages are logged, or `None` to log them all.
+    message_types: Option<Vec<String>>,
+    /// The sink the logged messages are copied to, so the UI can show them.
+    events: Option<Arc<dyn EventSink + Send + Sync>>,
+    /// The handle of the logger thread, shared by every clone of the logger.
+    handle: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 impl Logger {
@@ -19,18 +73,26 @@ impl Logger {
     /// # Arguments
     ///
     /// * `path` - The path of the log file.
+    /// * `level` - The lowest level of the messages written to the file.
     ///
     /// # Errors
     ///
     /// Returns a `NodeError` if the log file could not be opened.
-    fn new_from_path(path: &str) -> Result<Logger, NodeError> {
+    fn new_from_path(path: &str, level: LogLevel) -> Result<Logger, NodeError> {
         let (sender, receiver) = mpsc::channel();
-        Logger::start(receiver, path)?;
-        Ok(Logger { sender })
+        let handle = Logger::start(receiver, path)?;
+        Ok(Logger {
+            sender,
+            level,
+            message_types: None,
+            events: None,
+            handle: Arc::new(Mutex::new(Some(handle))),
+        })
     }
 
     /// Creates a new `Logger` instance using the `PATH_LOG` environment variable
-    /// as the log file path.
+    /// as the log file path, the `LOG_LEVEL` one as its level, and the `LOG_MESSAGE_TYPES` one as
+    /// the types of the messages of peers it logs.
     ///
     /// # Errors
     ///
@@ -39,7 +101,30 @@ impl Logger {
         let path_log = std::env::var(PATH_LOG).map_err(|_| {
             NodeError::EnvironVarNotFound("PATH_LOG not found in env vars".to_string())
         })?;
-        Logger::new_from_path(&path_log)
+        Ok(Logger::new_from_path(&path_log, retrieve_log_level())?
+            .with_message_types(retrieve_log_message_types()))
+    }
+
+    /// Makes the logger log the messages of peers of the given types with `log_message`, at the
+    /// `Info` level, and skip the rest.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_types` - The lowercased names of the message types, or `None` to log them all at
+    ///   the `Debug` level.
+    pub fn with_message_types(mut self, message_types: Option<Vec<String>>) -> Logger {
+        self.message_types = message_types;
+        self
+    }
+
+    /// Makes the logger emit every message it writes as a `NodeEvent::LogLine`.
+    ///
+    /// # Arguments
+    ///
+    /// * `events` - The sink the messages are emitted to, such as the UI.
+    pub fn with_events(mut self, events: impl EventSink + Send + Sync + 'static) -> Logger {
+        self.events = Some(Arc::new(events));
+        self
     }
 
     /// Starts the logger thread.
@@ -48,12 +133,15 @@ impl Logger {
     ///
     /// * `receiver` - The receiver end of a channel used to receive log messages.
     /// * `path` - The path of the log file.
-    /// * `sender` - The sender end of the channel used to receive log messages.
+    ///
+    /// # Returns
+    ///
+    /// The `JoinHandle` of the logger thread.
     ///
     /// # Errors
     ///
     /// Returns a `NodeError::FailedToOpenFile` if the log file could not be opened.
-    fn start(receiver: Receiver<String>, path: &str) -> Result<(), NodeError> {
+    fn start(receiver: Receiver<LogEntry>, path: &str) -> Result<JoinHandle<()>, NodeError> {
         let file = OpenOptions::new()
             .create(true)
             .write(true)
@@ -63,8 +151,12 @@ impl Logger {
 
         let mut buf_writer = BufWriter::new(file);
 
-        thread::spawn(move || {
-            for msg in receiver {
+        let handle = thread::spawn(move || {
+            for entry in receiver {
+                let msg = match entry {
+                    LogEntry::Line(msg) => msg,
+                    LogEntry::Shutdown => break,
+                };
                 match writeln!(buf_writer, "{}", msg) {
                     Ok(_) => {
                         if let Err(e) = buf_writer.flush() {
@@ -76,12 +168,18 @@ impl Logger {
                     }
                 }
             }
+            if let Err(e) = buf_writer.flush() {
+                println!("Error flushing buffer: {}", e);
+            }
         });
 
-        Ok(())
+        Ok(handle)
     }
 
-    /// Writes a log message to the log file.
+    /// Writes a log message to the log file, and emits it if the logger has an event sink.
+    /// The UI may already be closed, so failing to emit it is not an error.
+    ///
+    /// Only these messages are emitted: the detailed ones logged with `debug` are too many to show.
     ///
     /// # Arguments
     ///
@@ -91,8 +189,224 @@ impl Logger {
     ///
     /// Returns a `NodeError` if the log message could not be sent to the logger thread.
     pub fn log(&self, msg: String) -> Result<(), NodeError> {
+        if let Some(events) = &self.events {
+            let _ = events.emit(NodeEvent::LogLine(msg.clone()));
+        }
+        self.write(msg)
+    }
+
+    /// Sends a log message to the logger thread to be written to the log file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the log message could not be sent to the logger thread.
+    fn write(&self, msg: String) -> Result<(), NodeError> {
         self.sender
-            .send(msg)
+            .send(LogEntry::Line(msg))
             .map_err(|_| NodeError::FailedToSendMessage("Failed to send".to_string()))
     }
+
+    /// Writes a detailed log message to the log file, only if the level of the logger is `Debug`.
+    /// It isn't emitted to the event sink.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the log message could not be sent to the logger thread.
+    pub fn debug(&self, msg: String) -> Result<(), NodeError> {
+        if self.level > LogLevel::Debug {
+            return Ok(());
+        }
+        self.write(msg)
+    }
+
+    /// Logs a message about a message received from a peer. If the logger has message types, the
+    /// message is logged at the `Info` level if its type is one of them and skipped otherwise.
+    /// Without message types, every message is logged at the `Debug` level.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_type` - The type of the message received.
+    /// * `msg` - The log message to write.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the log message could not be sent to the logger thread.
+    pub fn log_message(&self, message_type: &MessageType, msg: String) -> Result<(), NodeError> {
+        match &self.message_types {
+            Some(message_types) if message_types.contains(&message_type.name().to_lowercase()) => {
+                self.log(msg)
+            }
+            Some(_) => Ok(()),
+            None => self.debug(msg),
+        }
+    }
+
+    /// Returns a guard that shuts the logger down with `shutdown` when it's dropped.
+    pub fn shutdown_guard(&self) -> ShutdownGuard {
+        ShutdownGuard {
+            logger: Some(self.clone()),
+        }
+    }
+
+    /// Stops the logger thread after every message logged so far is written and flushed to the
+    /// log file, waiting for it to finish. Messages logged afterwards by other clones of the logger
+    /// are discarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the logger thread could not be stopped or panicked.
+    pub fn shutdown(self) -> Result<(), NodeError> {
+        let handle = self
+            .handle
+            .lock()
+            .map_err(|_| NodeError::MutexError("Logger mutex poisoned".to_string()))?
+            .take();
+        let handle = match handle {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+        self.sender.send(LogEntry::Shutdown).map_err(|_| {
+            NodeError::FailedToSendMessage("Failed to stop the logger thread".to_string())
+        })?;
+        handle
+            .join()
+            .map_err(|_| NodeError::FailedToJoinThread("Failed to join logger thread".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, fs, rc::Rc};
+
+    use super::*;
+    use crate::ui::ui_message::UIMessage;
+
+    #[test]
+    fn test_shutdown_writes_every_logged_message() -> Result<(), NodeError> {
+        let path = "test_logger_shutdown.txt";
+        let _ = fs::remove_file(path);
+        let logger = Logger::new_from_path(path, LogLevel::Info)?;
+        let cloned_logger = logger.clone();
+
+        for i in 0..100 {
+            logger.log(format!("message {}", i))?;
+        }
+        cloned_logger.log("message from a clone".to_string())?;
+        logger.shutdown()?;
+
+        let contents = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read log file".to_string()))?;
+        let _ = fs::remove_file(path);
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 101);
+        assert_eq!(lines[99], "message 99");
+        assert_eq!(lines[100], "message from a clone");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dropping_the_shutdown_guard_writes_every_logged_message() -> Result<(), NodeError> {
+        let path = "test_logger_shutdown_guard.txt";
+        let _ = fs::remove_file(path);
+        let logger = Logger::new_from_path(path, LogLevel::Info)?;
+
+        let log_until_error = || -> Result<(), NodeError> {
+            let _guard = logger.shutdown_guard();
+            for i in 0..100 {
+                logger.log(format!("message {}", i))?;
+            }
+            Err(NodeError::FailedToConnect("Early return".to_string()))
+        };
+        let result = log_until_error();
+
+        let contents = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read log file".to_string()))?;
+        let _ = fs::remove_file(path);
+        assert!(result.is_err());
+        assert_eq!(contents.lines().count(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_logger_with_ui_sender_emits_log_lines() -> Result<(), NodeError> {
+        let path = "test_logger_ui_sender.txt";
+        let _ = fs::remove_file(path);
+        let (ui_sender, ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+        let logger = Logger::new_from_path(path, LogLevel::Info)?.with_events(ui_sender);
+
+        logger.log("first line".to_string())?;
+        logger.debug("detail not shown".to_string())?;
+        logger.clone().log("second line".to_string())?;
+        logger.shutdown()?;
+        let _ = fs::remove_file(path);
+
+        let log_lines = Rc::new(RefCell::new(Vec::new()));
+        let received_log_lines = Rc::clone(&log_lines);
+        let context = glib::MainContext::new();
+        ui_receiver.attach(Some(&context), move |message| {
+            if let UIMessage::LogLine(line) = message {
+                received_log_lines.borrow_mut().push(line);
+            }
+            glib::Continue(true)
+        });
+        while context.iteration(false) {}
+
+        assert_eq!(*log_lines.borrow(), vec!["first line", "second line"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_messages_are_written_but_not_emitted() -> Result<(), NodeError> {
+        let path = "test_logger_debug_not_emitted.txt";
+        let _ = fs::remove_file(path);
+        let (ui_sender, ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+        let logger = Logger::new_from_path(path, LogLevel::Debug)?.with_events(ui_sender);
+
+        logger.debug("Downloaded new header".to_string())?;
+        logger.log("Downloaded 1 headers".to_string())?;
+        logger.shutdown()?;
+        let contents = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read log file".to_string()))?;
+        let _ = fs::remove_file(path);
+
+        let log_lines = Rc::new(RefCell::new(Vec::new()));
+        let received_log_lines = Rc::clone(&log_lines);
+        let context = glib::MainContext::new();
+        ui_receiver.attach(Some(&context), move |message| {
+            if let UIMessage::LogLine(line) = message {
+                received_log_lines.borrow_mut().push(line);
+            }
+            glib::Continue(true)
+        });
+        while context.iteration(false) {}
+
+        assert_eq!(contents.lines().count(), 2);
+        assert_eq!(*log_lines.borrow(), vec!["Downloaded 1 headers"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_messages_of_the_filtered_types_are_logged() -> Result<(), NodeError> {
+        let path = "test_logger_message_types.txt";
+        let _ = fs::remove_file(path);
+        let logger = Logger::new_from_path(path, LogLevel::Info)?
+            .with_message_types(Some(vec!["block".to_string()]));
+
+        logger.log_message(&MessageType::Ping, "Received: Ping".to_string())?;
+        logger.log_message(&MessageType::Block, "Received: Block".to_string())?;
+        logger.log_message(
+            &MessageType::Unknown("reject".to_string()),
+            "Received: reject".to_string(),
+        )?;
+        logger.shutdown()?;
+
+        let contents = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read log file".to_string()))?;
+        let _ = fs::remove_file(path);
+        assert_eq!(
+            contents.lines().collect::<Vec<&str>>(),
+            vec!["Received: Block"]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,117 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::node_error::NodeError;
+
+use super::Logger;
+
+/// Summarizes a repeated event in the log, such as the download of a block, so the log is not
+/// flooded with a line per event.
+///
+/// The details of each event are logged at the `Debug` level, and a line with the number of
+/// events is logged at most once per interval.
+pub struct ProgressLogger {
+    logger: Logger,
+    /// What the events are, as shown in the summary, like "blocks downloaded".
+    events: String,
+    /// The minimum time between two summaries.
+    interval: Duration,
+    /// The number of events since the last summary and when it was logged.
+    progress: Mutex<(usize, Instant)>,
+}
+
+impl ProgressLogger {
+    /// Creates a progress logger that writes its summaries to the given logger.
+    ///
+    /// # Arguments
+    ///
+    /// * `logger` - The logger the summaries and details are written to.
+    /// * `events` - What the events are, like "blocks downloaded".
+    /// * `interval` - The minimum time between two summaries.
+    pub fn new(logger: Logger, events: &str, interval: Duration) -> ProgressLogger {
+        Self::started_at(logger, events, interval, Instant::now())
+    }
+
+    /// Creates a progress logger like `new`, whose first interval starts at the given instant.
+    fn started_at(
+        logger: Logger,
+        events: &str,
+        interval: Duration,
+        start: Instant,
+    ) -> ProgressLogger {
+        ProgressLogger {
+            logger,
+            events: events.to_string(),
+            interval,
+            progress: Mutex::new((0, start)),
+        }
+    }
+
+    /// Records an event, logging its details at the `Debug` level, and a summary of the events
+    /// since the last one if the interval has passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the message could not be logged.
+    pub fn record(&self, details: String) -> Result<(), NodeError> {
+        self.record_at(details, Instant::now())
+    }
+
+    /// Records an event like `record`, as if it happened at the given instant.
+    fn record_at(&self, details: String, now: Instant) -> Result<(), NodeError> {
+        self.logger.debug(details)?;
+
+        let mut progress = self
+            .progress
+            .lock()
+            .map_err(|_| NodeError::MutexError("Progress logger mutex poisoned".to_string()))?;
+        progress.0 += 1;
+        let elapsed = now.saturating_duration_since(progress.1);
+        if elapsed < self.interval {
+            return Ok(());
+        }
+        let summary = format!("{} {} in {}s", progress.0, self.events, elapsed.as_secs());
+        *progress = (0, now);
+        drop(progress);
+        self.logger.log(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::logger::LogLevel;
+
+    use super::*;
+
+    #[test]
+    fn test_many_events_produce_one_summary_per_interval() -> Result<(), NodeError> {
+        let path = "test_progress_logger.txt";
+        let _ = fs::remove_file(path);
+        let logger = Logger::new_from_path(path, LogLevel::Info)?;
+        let start = Instant::now();
+        let interval = Duration::from_secs(30);
+        let progress =
+            ProgressLogger::started_at(logger.clone(), "blocks downloaded", interval, start);
+
+        for i in 0..500 {
+            progress.record_at(format!("Downloaded block {}", i), start)?;
+        }
+        let summary_time = start + interval;
+        progress.record_at("Downloaded block 500".to_string(), summary_time)?;
+        for i in 501..1000 {
+            progress.record_at(format!("Downloaded block {}", i), summary_time)?;
+        }
+        logger.shutdown()?;
+
+        let contents = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read log file".to_string()))?;
+        let _ = fs::remove_file(path);
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines, vec!["501 blocks downloaded in 30s"]);
+        Ok(())
+    }
+}
@@ -1,18 +1,21 @@
-use std::{fs, net::TcpStream};
+use std::{fs, net::TcpStream, path::Path};
 
 use bitcoin_hashes::{sha256d, Hash};
+use chrono::{TimeZone, Utc};
 
 use crate::{
-    block::block_hash::BlockHash,
+    block::{block_hash::BlockHash, Block},
+    block_header::BlockHeader,
     config::obtain_dir_path,
     connectors::peer_connector::send_message,
-    constants::{COMMAND_NAME_BLOCK, PATH_BLOCKS},
+    constants::{COMMAND_NAME_BLOCK, LENGTH_BLOCK_HEADERS, PATH_BLOCKS},
     header::Header,
+    node::read::retrieve_blocks_sharded_by_date,
     node_error::NodeError,
     utils::Utils,
 };
 
-use super::get_data_message::GetDataMessage;
+use super::{get_data_message::GetDataMessage, inv_message::InvType};
 
 /// A message containing a block header and a list of transactions.
 #[derive(Debug)]
@@ -75,6 +78,9 @@ impl BlockMessage {
     /// reads the block data from the file, creates a header for the block,
     /// and then sends the header and block data as a message over the TcpStream.
     ///
+    /// Blocks are stored with the witnesses of their transactions, which are only sent if the block
+    /// was requested with `MSG_WITNESS_BLOCK`: a block requested with `MSG_BLOCK` is sent without them.
+    ///
     /// # Arguments
     ///
     /// * `stream` - A mutable reference to a TcpStream where the message will be sent.
@@ -92,7 +98,7 @@ impl BlockMessage {
         stream: &mut TcpStream,
         getdata_message: GetDataMessage,
     ) -> Result<(), NodeError> {
-        let block_hash = getdata_message.block_hash()?;
+        let (inv_type, block_hash) = getdata_message.requested_block()?;
         let block_path = match Self::block_path(block_hash) {
             Some(path) => path,
             None => {
@@ -104,6 +110,10 @@ impl BlockMessage {
 
         let block_bytes = fs::read(block_path)
             .map_err(|_| NodeError::FailedToRead("Failed to read block file".to_string()))?;
+        let block_bytes = match inv_type {
+            InvType::WitnessBlock => block_bytes,
+            _ => Block::from_bytes(&block_bytes)?.to_bytes_no_witness(),
+        };
 
         let header = Header::create_header(&block_bytes, COMMAND_NAME_BLOCK)?;
 
@@ -118,6 +128,10 @@ impl BlockMessage {
     /// Returns the file path for the block with the specified hash. The file name is based on the
     /// hash of the block and has the `.bin` extension.
     ///
+    /// If the blocks are sharded by date, the date subdirectory can't be derived from the hash, so
+    /// the block is looked up in the subdirectories on disk. A block that isn't in any of them, or
+    /// any block if they aren't sharded, gets the path in the blocks directory itself.
+    ///
     /// # Arguments
     ///
     /// * `block_hash` - A fixed size array of bytes representing the hash of the block.
@@ -127,27 +141,118 @@ impl BlockMessage {
     /// An `Option` containing the file path for the block if the hash could be converted to a string,
     /// otherwise returns `None`.
     pub fn block_path(block_hash: &BlockHash) -> Option<String> {
-        let hash_string = match sha256d::Hash::from_slice(block_hash) {
-            Ok(hash) => hash,
-            Err(_) => {
-                println!("Error in block's hash");
-                return None;
-            }
-        };
+        let directory = obtain_dir_path(PATH_BLOCKS.to_owned()).ok()?;
+        block_path_in(&directory, block_hash, retrieve_blocks_sharded_by_date())
+    }
 
-        let directory = match obtain_dir_path(PATH_BLOCKS.to_owned()) {
-            Ok(value) => value,
-            Err(_) => return None,
-        };
+    /// Returns the file path for the block with the specified header.
+    ///
+    /// When `BLOCKS_SHARDED_BY_DATE` is enabled, blocks are saved in a subdirectory named after
+    /// the year and month of their timestamp, e.g. `blocks/2023-05/<hash>.bin`. Blocks that were
+    /// saved in the blocks directory itself before sharding was enabled keep their path.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_header` - The header of the block.
+    ///
+    /// # Returns
+    ///
+    /// An `Option` containing the file path for the block, or `None` if the blocks directory
+    /// could not be created.
+    pub fn block_path_for_header(block_header: &BlockHeader) -> Option<String> {
+        let directory = obtain_dir_path(PATH_BLOCKS.to_owned()).ok()?;
+        block_path_for_header_in(&directory, block_header, retrieve_blocks_sharded_by_date())
+    }
+
+    /// Returns the file path a downloaded block is saved to, creating its date subdirectory if the
+    /// blocks are sharded by date.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_data` - The bytes of the block, starting with its header.
+    ///
+    /// # Returns
+    ///
+    /// An `Option` containing the file path for the block, or `None` if the header could not be parsed.
+    pub fn block_path_for_block(block_data: &[u8]) -> Option<String> {
+        let header_bytes = block_data.get(..LENGTH_BLOCK_HEADERS)?.to_vec();
+        let block_header = BlockHeader::from_bytes(&header_bytes).ok()?;
+        let path = Self::block_path_for_header(&block_header)?;
+        create_parent_directory(&path)?;
+        Some(path)
+    }
+}
 
-        let path = format!("{}/{}.bin", directory, hash_string);
+/// Returns the path of a block in the given blocks directory: the one in the date subdirectory
+/// it was saved in if the blocks are sharded, or the path in the directory itself.
+fn block_path_in(directory: &str, block_hash: &BlockHash, sharded: bool) -> Option<String> {
+    let file_name = block_file_name(block_hash)?;
+    if let Err(e) = fs::create_dir_all(directory) {
+        println!("Error creating directory 'blocks': {}", e);
+        return None;
+    }
+    let flat_path = format!("{}/{}", directory, file_name);
+    if !sharded || Path::new(&flat_path).exists() {
+        return Some(flat_path);
+    }
 
-        if let Err(e) = fs::create_dir_all(&directory) {
-            println!("Error creating directory 'blocks': {}", e);
-            return None;
-        }
+    let sharded_path = fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .map(|date| format!("{}/{}/{}", directory, date, file_name))
+        .find(|path| Path::new(path).exists());
+    Some(sharded_path.unwrap_or(flat_path))
+}
 
-        Some(path)
+/// Returns the path of a block in the given blocks directory, in the date subdirectory of its
+/// timestamp if the blocks are sharded and it wasn't saved in the directory itself.
+fn block_path_for_header_in(
+    directory: &str,
+    block_header: &BlockHeader,
+    sharded: bool,
+) -> Option<String> {
+    let file_name = block_file_name(&block_header.block_hash())?;
+    if let Err(e) = fs::create_dir_all(directory) {
+        println!("Error creating directory 'blocks': {}", e);
+        return None;
+    }
+    let flat_path = format!("{}/{}", directory, file_name);
+    if !sharded || Path::new(&flat_path).exists() {
+        return Some(flat_path);
+    }
+
+    let date = Utc
+        .timestamp_opt(block_header.timestamp as i64, 0)
+        .single()?;
+    Some(format!(
+        "{}/{}/{}",
+        directory,
+        date.format("%Y-%m"),
+        file_name
+    ))
+}
+
+/// Creates the directory a block file is saved in if it doesn't exist.
+fn create_parent_directory(path: &str) -> Option<()> {
+    let directory = Path::new(path).parent()?;
+    if let Err(e) = fs::create_dir_all(directory) {
+        println!("Error creating directory {}: {}", directory.display(), e);
+        return None;
+    }
+    Some(())
+}
+
+/// Returns the name of the file of a block: its hash and the `.bin` extension.
+fn block_file_name(block_hash: &BlockHash) -> Option<String> {
+    match sha256d::Hash::from_slice(block_hash) {
+        Ok(hash) => Some(format!("{}.bin", hash)),
+        Err(_) => {
+            println!("Error in block's hash");
+            None
+        }
     }
 }
 
@@ -192,4 +297,58 @@ mod tests {
             vec![0x01, 0x04, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x01, 0x01]
         );
     }
+
+    #[test]
+    fn test_sharded_block_path_round_trips() -> Result<(), NodeError> {
+        let directory = "blocks-test/test_sharded_blocks";
+        let _ = fs::remove_dir_all(directory);
+        let block_data = fs::read(
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let block_header = BlockHeader::from_bytes(&block_data[..LENGTH_BLOCK_HEADERS].to_vec())?;
+        let block_hash: BlockHash = block_header
+            .hash()
+            .as_slice()
+            .try_into()
+            .map_err(|_| NodeError::FailedToConvert("Invalid block hash".to_string()))?;
+
+        let flat_path = block_path_in(directory, &block_hash, true);
+        let sharded_path = block_path_for_header_in(directory, &block_header, true);
+        let saved_sharded = match &sharded_path {
+            Some(path) => create_parent_directory(path)
+                .and_then(|_| fs::write(path, &block_data).ok())
+                .and_then(|_| block_path_in(directory, &block_hash, true)),
+            None => None,
+        };
+        if let Some(path) = &saved_sharded {
+            let _ = fs::remove_file(path);
+        }
+        let saved_flat = fs::write(
+            format!(
+                "{}/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+                directory
+            ),
+            &block_data,
+        )
+        .map_err(|_| NodeError::FailedToWrite("Failed to write block".to_string()));
+        let saved_flat_path = block_path_for_header_in(directory, &block_header, true);
+        let _ = fs::remove_dir_all(directory);
+        saved_flat?;
+
+        let expected_flat_path = format!(
+            "{}/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+            directory
+        );
+        let expected_sharded_path = format!(
+            "{}/2023-05/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+            directory
+        );
+        assert_eq!(block_header.timestamp, 1684070964);
+        assert_eq!(flat_path, Some(expected_flat_path.clone()));
+        assert_eq!(sharded_path, Some(expected_sharded_path.clone()));
+        assert_eq!(saved_sharded, Some(expected_sharded_path));
+        assert_eq!(saved_flat_path, Some(expected_flat_path));
+        Ok(())
+    }
 }
@@ -0,0 +1,344 @@
+use std::io::{Cursor, Write};
+
+use bitcoin_hashes::{sha256d, Hash};
+
+use crate::{
+    block::{block_filter::BlockFilter, block_hash::BlockHash},
+    compact_size::CompactSize,
+    connectors::peer_connector::{receive_message, send_message},
+    constants::BASIC_FILTER_TYPE,
+    header::Header,
+    node_error::NodeError,
+};
+
+/// Represents a 32-byte filter header or filter hash (BIP157).
+pub type FilterHeader = [u8; 32];
+
+/// Represents a "getcfilters" message (BIP157), that requests the compact filters of a range of
+/// blocks, from the block at the start height up to the block with the stop hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetCFiltersMessage {
+    /// The type of the filters requested.
+    pub filter_type: u8,
+    /// The height of the first block of the range.
+    pub start_height: u32,
+    /// The hash of the last block of the range.
+    pub stop_hash: BlockHash,
+}
+
+/// A "getcfheaders" message requests the filter headers of a range of blocks.
+/// Its structure is identical to a getcfilters message, that is why it is an alias.
+pub type GetCFHeadersMessage = GetCFiltersMessage;
+
+impl GetCFiltersMessage {
+    /// Creates a request for the basic filters of the blocks from the start height to the stop hash.
+    pub fn new(start_height: u32, stop_hash: BlockHash) -> GetCFiltersMessage {
+        GetCFiltersMessage {
+            filter_type: BASIC_FILTER_TYPE,
+            start_height,
+            stop_hash,
+        }
+    }
+
+    /// Converts the message to its payload bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.filter_type];
+        bytes.extend(self.start_height.to_le_bytes());
+        bytes.extend(self.stop_hash);
+        bytes
+    }
+
+    /// Sends the message to the given stream with the given command name, `getcfilters` or
+    /// `getcfheaders`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the header could not be created or the message could not be sent.
+    pub fn send_message<W: Write>(
+        &self,
+        stream: &mut W,
+        command_name: &str,
+    ) -> Result<(), NodeError> {
+        let payload = self.to_bytes();
+        let mut bytes = Header::create_header(&payload, command_name)?;
+        bytes.extend(payload);
+        send_message(stream, bytes)
+    }
+}
+
+/// Represents a "cfilter" message (BIP157), holding the compact filter of a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CFilterMessage {
+    /// The type of the filter.
+    pub filter_type: u8,
+    /// The hash of the block the filter belongs to.
+    pub block_hash: BlockHash,
+    /// The serialized filter: the item count followed by the coded items.
+    pub filter_bytes: Vec<u8>,
+}
+
+impl CFilterMessage {
+    /// Parses a "cfilter" message from its payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the payload is truncated.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CFilterMessage, NodeError> {
+        let mut cursor = Cursor::new(bytes);
+        let filter_type = receive_message(&mut cursor, 1)?[0];
+        let block_hash = read_hash(&mut cursor)?;
+        let filter_length = CompactSize::read_varint(&mut cursor)?.get_value() as usize;
+        let filter_bytes = receive_message(&mut cursor, filter_length)?;
+
+        Ok(CFilterMessage {
+            filter_type,
+            block_hash,
+            filter_bytes,
+        })
+    }
+
+    /// Returns the hash of the filter.
+    pub fn filter_hash(&self) -> FilterHeader {
+        sha256d::Hash::hash(&self.filter_bytes).to_byte_array()
+    }
+
+    /// Returns the header of the filter, which commits to the filter and to the header of the
+    /// filter of the previous block.
+    pub fn filter_header(&self, previous_filter_header: &FilterHeader) -> FilterHeader {
+        chain_filter_header(&self.filter_hash(), previous_filter_header)
+    }
+
+    /// Returns the filter, so the scripts of a wallet can be matched against it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the filter is malformed.
+    pub fn to_block_filter(&self) -> Result<BlockFilter, NodeError> {
+        BlockFilter::from_bytes(&self.block_hash, &self.filter_bytes)
+    }
+}
+
+/// Represents a "cfheaders" message (BIP157), holding the filter hashes of a range of blocks and
+/// the filter header of the block before the range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CFHeadersMessage {
+    /// The type of the filters.
+    pub filter_type: u8,
+    /// The hash of the last block of the range.
+    pub stop_hash: BlockHash,
+    /// The filter header of the block before the range.
+    pub previous_filter_header: FilterHeader,
+    /// The filter hash of each block of the range.
+    pub filter_hashes: Vec<FilterHeader>,
+}
+
+impl CFHeadersMessage {
+    /// Parses a "cfheaders" message from its payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the payload is truncated.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CFHeadersMessage, NodeError> {
+        let mut cursor = Cursor::new(bytes);
+        let filter_type = receive_message(&mut cursor, 1)?[0];
+        let stop_hash = read_hash(&mut cursor)?;
+        let previous_filter_header = read_hash(&mut cursor)?;
+        let hashes_count = CompactSize::read_varint(&mut cursor)?.get_value();
+        let filter_hashes = (0..hashes_count)
+            .map(|_| read_hash(&mut cursor))
+            .collect::<Result<Vec<FilterHeader>, NodeError>>()?;
+
+        Ok(CFHeadersMessage {
+            filter_type,
+            stop_hash,
+            previous_filter_header,
+            filter_hashes,
+        })
+    }
+
+    /// Returns the filter header of each block of the range, chained from the previous filter header.
+    pub fn filter_headers(&self) -> Vec<FilterHeader> {
+        let mut previous_filter_header = self.previous_filter_header;
+        self.filter_hashes
+            .iter()
+            .map(|filter_hash| {
+                previous_filter_header = chain_filter_header(filter_hash, &previous_filter_header);
+                previous_filter_header
+            })
+            .collect()
+    }
+
+    /// Verifies that the filter headers and the filters received answer the request: they are of the
+    /// requested type, cover the requested blocks in order, and each filter hashes to the filter hash
+    /// the peer committed to in the filter headers.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `getcfheaders` and `getcfilters` request the messages answer.
+    /// * `block_hashes` - The hashes of the blocks of the requested range, in order.
+    /// * `cfilters` - The filters of the blocks of the range, in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidFilterHeader` if the messages don't answer the request, a filter
+    /// is missing or it doesn't match its filter hash.
+    pub fn validate_filters(
+        &self,
+        request: &GetCFiltersMessage,
+        block_hashes: &[BlockHash],
+        cfilters: &[CFilterMessage],
+    ) -> Result<(), NodeError> {
+        if self.filter_type != request.filter_type || self.stop_hash != request.stop_hash {
+            return Err(NodeError::InvalidFilterHeader(
+                "The filter headers don't answer the request".to_string(),
+            ));
+        }
+        if self.filter_hashes.len() != block_hashes.len() || cfilters.len() != block_hashes.len() {
+            return Err(NodeError::InvalidFilterHeader(format!(
+                "Received {} filter headers and {} filters for {} blocks",
+                self.filter_hashes.len(),
+                cfilters.len(),
+                block_hashes.len()
+            )));
+        }
+
+        for (position, ((cfilter, filter_hash), block_hash)) in cfilters
+            .iter()
+            .zip(&self.filter_hashes)
+            .zip(block_hashes)
+            .enumerate()
+        {
+            if cfilter.filter_type != request.filter_type || cfilter.block_hash != *block_hash {
+                return Err(NodeError::InvalidFilterHeader(format!(
+                    "The filter {} is not the filter of the requested block",
+                    position
+                )));
+            }
+            if cfilter.filter_hash() != *filter_hash {
+                return Err(NodeError::InvalidFilterHeader(format!(
+                    "The filter {} does not match its filter header",
+                    position
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the filter header for a filter hash and the previous filter header.
+fn chain_filter_header(
+    filter_hash: &FilterHeader,
+    previous_filter_header: &FilterHeader,
+) -> FilterHeader {
+    let mut bytes = filter_hash.to_vec();
+    bytes.extend(previous_filter_header);
+    sha256d::Hash::hash(&bytes).to_byte_array()
+}
+
+/// Reads a 32-byte hash.
+fn read_hash(cursor: &mut Cursor<&[u8]>) -> Result<[u8; 32], NodeError> {
+    receive_message(cursor, 32)?
+        .try_into()
+        .map_err(|_| NodeError::FailedToConvert("A hash must be 32 bytes long".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block::block_hash::InternalHash, constants::COMMAND_NAME_GETCFILTERS};
+
+    /// Basic filter of the testnet genesis block, from the BIP158 test vectors.
+    const GENESIS_FILTER: [u8; 4] = [0x01, 0x9d, 0xfc, 0xa8];
+    /// Filter header of the testnet genesis block, from the BIP158 test vectors.
+    const GENESIS_FILTER_HEADER: &str =
+        "21584579b7eb08997773e5aeff3a7f932700042d0ed2a6129012b7d7ae81b750";
+
+    fn genesis_cfilter() -> CFilterMessage {
+        CFilterMessage {
+            filter_type: BASIC_FILTER_TYPE,
+            block_hash: [3; 32],
+            filter_bytes: GENESIS_FILTER.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_getcfilters_request_and_cfilter_reply() -> Result<(), NodeError> {
+        let mut request = Vec::new();
+        GetCFiltersMessage::new(100, [7; 32])
+            .send_message(&mut request, COMMAND_NAME_GETCFILTERS)?;
+
+        let header = Header::from_bytes(&request[..24]);
+        assert_eq!(header.payload_size(), 37);
+        assert_eq!(
+            &header.command_name[..11],
+            COMMAND_NAME_GETCFILTERS.as_bytes()
+        );
+        assert_eq!(request[24], BASIC_FILTER_TYPE);
+        assert_eq!(request[25..29], 100u32.to_le_bytes());
+        assert_eq!(request[29..], [7; 32]);
+
+        let mut reply = vec![BASIC_FILTER_TYPE];
+        reply.extend([3; 32]);
+        reply.push(GENESIS_FILTER.len() as u8);
+        reply.extend(GENESIS_FILTER);
+        let cfilter = CFilterMessage::from_bytes(&reply)?;
+
+        assert_eq!(cfilter, genesis_cfilter());
+        assert_eq!(cfilter.to_block_filter()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_header_chain_is_validated() -> Result<(), NodeError> {
+        let cfilter = genesis_cfilter();
+        let genesis_filter_header = InternalHash::new(cfilter.filter_header(&[0; 32]));
+        assert_eq!(
+            genesis_filter_header.to_display().to_string(),
+            GENESIS_FILTER_HEADER
+        );
+
+        let mut other_cfilter = genesis_cfilter();
+        other_cfilter.block_hash = [4; 32];
+        other_cfilter.filter_bytes = vec![0];
+        let request = GetCFiltersMessage::new(0, [4; 32]);
+        let cfheaders = CFHeadersMessage {
+            filter_type: BASIC_FILTER_TYPE,
+            stop_hash: [4; 32],
+            previous_filter_header: [0; 32],
+            filter_hashes: vec![cfilter.filter_hash(), other_cfilter.filter_hash()],
+        };
+        let block_hashes = [[3; 32], [4; 32]];
+
+        cfheaders.validate_filters(
+            &request,
+            &block_hashes,
+            &[cfilter.clone(), other_cfilter.clone()],
+        )?;
+        let mut forged_cfilter = other_cfilter.clone();
+        forged_cfilter.filter_bytes = GENESIS_FILTER.to_vec();
+        assert!(matches!(
+            cfheaders.validate_filters(&request, &block_hashes, &[cfilter.clone(), forged_cfilter]),
+            Err(NodeError::InvalidFilterHeader(_))
+        ));
+        assert!(matches!(
+            cfheaders.validate_filters(
+                &request,
+                &[[3; 32], [5; 32]],
+                &[cfilter.clone(), other_cfilter.clone()]
+            ),
+            Err(NodeError::InvalidFilterHeader(_))
+        ));
+        assert!(matches!(
+            cfheaders.validate_filters(
+                &GetCFiltersMessage::new(0, [5; 32]),
+                &block_hashes,
+                &[cfilter.clone(), other_cfilter]
+            ),
+            Err(NodeError::InvalidFilterHeader(_))
+        ));
+        assert!(cfheaders
+            .validate_filters(&request, &block_hashes, &[cfilter])
+            .is_err());
+        Ok(())
+    }
+}
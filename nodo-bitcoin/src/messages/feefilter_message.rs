@@ -0,0 +1,89 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{constants::LENGTH_FEE_RATE, node_error::NodeError};
+
+/// Represents a "feefilter" message in the Bitcoin peer-to-peer network (BIP133).
+///
+/// The peer that sends it asks not to be sent transactions whose fee rate is below the given one.
+///
+/// # Fields
+///
+/// * `fee_rate` - The minimum fee rate, in satoshis per 1000 bytes.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FeeFilterMessage {
+    pub fee_rate: u64,
+}
+
+/// The fee filters received from each peer.
+static PEER_FEE_FILTERS: OnceLock<Mutex<HashMap<SocketAddr, FeeFilterMessage>>> = OnceLock::new();
+
+impl FeeFilterMessage {
+    /// Converts 'bytes' to a FeeFilter message.
+    /// # Arguments
+    /// * `bytes` - A byte array with the message payload.
+    /// # Returns
+    /// * `Result<FeeFilterMessage, NodeError>` - A new FeeFilter message or NodeError if the payload is not 8 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Result<FeeFilterMessage, NodeError> {
+        let fee_rate: [u8; LENGTH_FEE_RATE] = bytes.try_into().map_err(|_| {
+            NodeError::InvalidSizeOfField("The feefilter payload must be 8 bytes long".to_string())
+        })?;
+        Ok(FeeFilterMessage {
+            fee_rate: u64::from_le_bytes(fee_rate),
+        })
+    }
+
+    /// Returns true if the peer accepts transactions with the given fee rate, in satoshis per virtual byte.
+    pub fn accepts(&self, fee_rate: f64) -> bool {
+        fee_rate * 1000.0 >= self.fee_rate as f64
+    }
+
+    /// Stores the fee filter as the one of the given peer, replacing the previous one.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::MutexError` if the fee filters mutex is poisoned.
+    pub fn store_for_peer(&self, peer: SocketAddr) -> Result<(), NodeError> {
+        peer_fee_filters()
+            .lock()
+            .map_err(|_| NodeError::MutexError("Fee filters mutex poisoned".to_string()))?
+            .insert(peer, *self);
+        Ok(())
+    }
+
+    /// Returns the last fee filter received from the given peer, if it sent one.
+    pub fn for_peer(peer: &SocketAddr) -> Option<FeeFilterMessage> {
+        peer_fee_filters().lock().ok()?.get(peer).copied()
+    }
+}
+
+/// Returns the fee filters of the peers, shared by every connection.
+fn peer_fee_filters() -> &'static Mutex<HashMap<SocketAddr, FeeFilterMessage>> {
+    PEER_FEE_FILTERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    #[test]
+    fn test_feefilter_is_parsed_and_stored_for_peer() -> Result<(), NodeError> {
+        let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 18333);
+        let payload = 1000u64.to_le_bytes();
+
+        let feefilter = FeeFilterMessage::from_bytes(&payload)?;
+        feefilter.store_for_peer(peer)?;
+
+        assert_eq!(feefilter.fee_rate, 1000);
+        assert_eq!(FeeFilterMessage::for_peer(&peer), Some(feefilter));
+        assert!(feefilter.accepts(1.0));
+        assert!(!feefilter.accepts(0.5));
+        assert!(FeeFilterMessage::from_bytes(&payload[..4]).is_err());
+        Ok(())
+    }
+}
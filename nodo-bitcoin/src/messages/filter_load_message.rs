@@ -0,0 +1,241 @@
+use std::{
+    collections::HashSet,
+    io::Write,
+    net::SocketAddr,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{
+    compact_size::CompactSize,
+    connectors::peer_connector::send_message,
+    constants::{
+        BLOOM_FALSE_POSITIVE_RATE, BLOOM_MAX_FILTER_SIZE, BLOOM_MAX_HASH_FUNCS,
+        BLOOM_SEED_MULTIPLIER, BLOOM_UPDATE_ALL, COMMAND_NAME_FILTERLOAD, NODE_BLOOM,
+    },
+    header::Header,
+    node_error::NodeError,
+};
+
+/// The filter of the wallet, sent to the peers on handshake in headers-only mode.
+static WALLET_FILTER: OnceLock<Mutex<FilterLoadMessage>> = OnceLock::new();
+
+/// The peers that announced the `NODE_BLOOM` service in their version message, so they accept a
+/// filter and answer filtered block requests.
+static BLOOM_PEERS: OnceLock<Mutex<HashSet<SocketAddr>>> = OnceLock::new();
+
+/// Represents a "filterload" message (BIP37): a bloom filter the peer matches the transactions
+/// against, so it only relays the ones of the wallet and answers filtered block requests with a
+/// merkleblock proving them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterLoadMessage {
+    /// The bit field of the filter.
+    pub filter: Vec<u8>,
+    /// The number of hash functions used for each element.
+    pub hash_funcs: u32,
+    /// The tweak added to the seed of the hash functions.
+    pub tweak: u32,
+    /// How the peer updates the filter when a transaction matches it.
+    pub flags: u8,
+}
+
+impl FilterLoadMessage {
+    /// Creates a filter sized for the given elements and false positive rate, holding them.
+    ///
+    /// # Arguments
+    ///
+    /// * `elements` - The elements to match, such as the public key hashes of the wallet.
+    /// * `false_positive_rate` - The probability of matching an element that was not inserted.
+    /// * `tweak` - A random value, so different filters for the same elements differ.
+    ///
+    /// The peer is asked to add the outpoints of the matched outputs to the filter, so the
+    /// transactions that spend them match too.
+    pub fn new(elements: &[Vec<u8>], false_positive_rate: f64, tweak: u32) -> Self {
+        let elements_count = elements.len().max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let filter_bits = (-1.0 / (ln2 * ln2) * elements_count * false_positive_rate.ln())
+            .min((BLOOM_MAX_FILTER_SIZE * 8) as f64) as usize;
+        let filter_size = (filter_bits / 8).max(1);
+        let hash_funcs = ((filter_size * 8) as f64 / elements_count * ln2) as u32;
+
+        let mut filter_load = FilterLoadMessage {
+            filter: vec![0; filter_size],
+            hash_funcs: hash_funcs.min(BLOOM_MAX_HASH_FUNCS),
+            tweak,
+            flags: BLOOM_UPDATE_ALL,
+        };
+        for element in elements {
+            filter_load.insert(element);
+        }
+        filter_load
+    }
+
+    /// Returns the filter of the wallet, the one sent to the peers on handshake. Until the wallet
+    /// loads its filter, it's an empty filter, which matches nothing.
+    pub fn wallet_filter() -> FilterLoadMessage {
+        let filter = WALLET_FILTER
+            .get_or_init(|| Mutex::new(FilterLoadMessage::new(&[], BLOOM_FALSE_POSITIVE_RATE, 0)));
+        match filter.lock() {
+            Ok(filter) => filter.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replaces the filter of the wallet sent to the peers on handshake.
+    pub fn set_wallet_filter(filter: FilterLoadMessage) {
+        let wallet_filter = WALLET_FILTER.get_or_init(|| Mutex::new(filter.clone()));
+        match wallet_filter.lock() {
+            Ok(mut wallet_filter) => *wallet_filter = filter,
+            Err(poisoned) => *poisoned.into_inner() = filter,
+        }
+    }
+
+    /// Records whether a peer serves bloom filters, from the services of its version message.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The address of the peer.
+    /// * `services` - The services the peer announced in its version message.
+    pub fn record_peer_services(peer: SocketAddr, services: u64) {
+        let bloom_peers = BLOOM_PEERS.get_or_init(|| Mutex::new(HashSet::new()));
+        let mut bloom_peers = match bloom_peers.lock() {
+            Ok(bloom_peers) => bloom_peers,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if services & NODE_BLOOM != 0 {
+            bloom_peers.insert(peer);
+        } else {
+            bloom_peers.remove(&peer);
+        }
+    }
+
+    /// Returns whether a peer announced the `NODE_BLOOM` service in its version message. Peers
+    /// without it disconnect nodes that send them a filter.
+    pub fn is_served_by(peer: &SocketAddr) -> bool {
+        let bloom_peers = BLOOM_PEERS.get_or_init(|| Mutex::new(HashSet::new()));
+        match bloom_peers.lock() {
+            Ok(bloom_peers) => bloom_peers.contains(peer),
+            Err(poisoned) => poisoned.into_inner().contains(peer),
+        }
+    }
+
+    /// Adds an element to the filter.
+    pub fn insert(&mut self, element: &[u8]) {
+        for hash_num in 0..self.hash_funcs {
+            let bit = self.bit_index(hash_num, element);
+            self.filter[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Checks if the filter matches an element. It may match elements that were not inserted.
+    pub fn contains(&self, element: &[u8]) -> bool {
+        (0..self.hash_funcs).all(|hash_num| {
+            let bit = self.bit_index(hash_num, element);
+            self.filter[bit / 8] & (1 << (bit % 8)) != 0
+        })
+    }
+
+    /// Converts the message to its payload bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = CompactSize::new(self.filter.len()).to_bytes();
+        bytes.extend(&self.filter);
+        bytes.extend(self.hash_funcs.to_le_bytes());
+        bytes.extend(self.tweak.to_le_bytes());
+        bytes.push(self.flags);
+        bytes
+    }
+
+    /// Sends the filter to a peer.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the header could not be created or the message could not be sent.
+    pub fn send_message<W: Write>(&self, stream: &mut W) -> Result<(), NodeError> {
+        let payload = self.to_bytes();
+        let mut bytes = Header::create_header(&payload, COMMAND_NAME_FILTERLOAD)?;
+        bytes.extend(payload);
+        send_message(stream, bytes)
+    }
+
+    /// Returns the bit of the filter set by the given hash function for an element.
+    fn bit_index(&self, hash_num: u32, element: &[u8]) -> usize {
+        let seed = hash_num
+            .wrapping_mul(BLOOM_SEED_MULTIPLIER)
+            .wrapping_add(self.tweak);
+        murmur3(seed, element) as usize % (self.filter.len() * 8)
+    }
+}
+
+/// Computes the 32-bit MurmurHash3 of the data, the hash function of bloom filters.
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut hash = seed;
+    let mut blocks = data.chunks_exact(4);
+    for block in blocks.by_ref() {
+        let k = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        hash = (hash ^ mix(k))
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0u32, |k, byte| (k << 8) | *byte as u32);
+        hash ^= mix(k);
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::Utils;
+
+    use super::*;
+
+    #[test]
+    fn test_filter_matches_bip37_test_vector() -> Result<(), NodeError> {
+        let elements = [
+            "99108ad8ed9bb6274d3980bab5a85c048f0950c8",
+            "b5a2c786d9ef4658287ced5914b37a1b4aa32eee",
+            "b9300670b4c5366e95b2699e8b18bc75e5f729c5",
+        ]
+        .iter()
+        .map(|element| Utils::hex_string_to_bytes(element.to_string()))
+        .collect::<Result<Vec<Vec<u8>>, NodeError>>()?;
+
+        let filter_load = FilterLoadMessage::new(&elements, 0.01, 0);
+
+        assert_eq!(
+            Utils::bytes_to_hex(&filter_load.to_bytes()),
+            "03614e9b050000000000000001"
+        );
+        assert!(elements.iter().all(|element| filter_load.contains(element)));
+        assert!(!filter_load.contains(&Utils::hex_string_to_bytes(
+            "19108ad8ed9bb6274d3980bab5a85c048f0950c8".to_string()
+        )?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_peers_announcing_node_bloom_serve_filters() {
+        let bloom_peer: SocketAddr = ([127, 0, 0, 1], 18333).into();
+        let other_peer: SocketAddr = ([127, 0, 0, 2], 18333).into();
+
+        FilterLoadMessage::record_peer_services(bloom_peer, NODE_BLOOM | 1);
+        FilterLoadMessage::record_peer_services(other_peer, 1);
+
+        assert!(FilterLoadMessage::is_served_by(&bloom_peer));
+        assert!(!FilterLoadMessage::is_served_by(&other_peer));
+    }
+}
@@ -1,4 +1,4 @@
-use std::net::TcpStream;
+use std::{io::Write, net::TcpStream};
 
 use crate::{
     connectors::peer_connector::{receive_message, send_message},
@@ -23,13 +23,13 @@ impl GetDataMessage {
     ///
     /// # Arguments
     ///
-    /// * `stream`: A mutable reference to the `TcpStream` to which to send the message.
+    /// * `stream`: A mutable reference to the stream to which to send the message.
     /// * `hash_bytes`: The 32-byte hash of the block to request.
     ///
     /// # Errors
     ///
     /// This function returns a `NodeError` if an error occurs while sending the message.
-    pub fn send_message(&self, stream: &mut TcpStream) -> Result<(), NodeError> {
+    pub fn send_message<W: Write>(&self, stream: &mut W) -> Result<(), NodeError> {
         let get_data_message = self.to_bytes()?;
         let header_get_data = Header::create_header(&get_data_message, COMMAND_NAME_GET_DATA)?;
         let mut bytes = vec![];
@@ -49,3 +49,23 @@ impl GetDataMessage {
         InvMessage::from_bytes(&get_data_bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{constants::MSG_TX, node_error::NodeError};
+
+    use super::GetDataMessage;
+
+    #[test]
+    fn test_get_data_for_transaction_has_tx_inventory_type() -> Result<(), NodeError> {
+        let tx_id = [7; 32];
+
+        let bytes = GetDataMessage::new(1, MSG_TX, tx_id)?.to_bytes()?;
+
+        assert_eq!(bytes.len(), 37);
+        assert_eq!(bytes[0], 1);
+        assert_eq!(bytes[1..5], MSG_TX.to_le_bytes());
+        assert_eq!(bytes[5..], tx_id);
+        Ok(())
+    }
+}
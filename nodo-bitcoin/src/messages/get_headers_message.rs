@@ -3,9 +3,13 @@ use std::{io::Read, net::TcpStream};
 use bitcoin_hashes::{sha256d, Hash};
 
 use crate::{
-    block::block_hash::BlockHash, block_header::block_header_bytes::BlockHeaderBytes,
-    compact_size::CompactSize, connectors::peer_connector::send_message,
-    constants::COMMAND_NAME_GETHEADERS, header::Header, node::read::retrieve_version,
+    block::block_hash::BlockHash,
+    block_header::block_header_bytes::BlockHeaderBytes,
+    compact_size::CompactSize,
+    connectors::peer_connector::send_message,
+    constants::{COMMAND_NAME_GETHEADERS, MAX_LOCATOR_HASHES},
+    header::Header,
+    node::read::retrieve_version,
     node_error::NodeError,
 };
 
@@ -16,8 +20,10 @@ pub struct GetHeadersMessage {
     pub protocol_version: i32,
     /// The number of header hashes included in the message.
     pub hash_count: CompactSize,
-    /// The hashes of the block headers being requested.
+    /// The block locator: hashes of the block headers the transmitting node has, newest first.
     pub header_hashes: Vec<BlockHash>,
+    /// The hash of the last header to send, or all zeros to send as many as possible.
+    pub hash_stop: BlockHash,
 }
 
 impl GetHeadersMessage {
@@ -47,12 +53,16 @@ impl GetHeadersMessage {
     ///
     /// # Arguments
     ///
-    /// * `stream` - A mutable reference to a TCP stream from which to read the message.
+    /// * `stream` - A mutable reference to a reader from which to read the message, like a TCP stream.
     ///
     /// # Returns
     ///
     /// A `Result` containing the parsed `GetheadersMessage` if successful, or a `NodeError` if an error occurs.
-    pub fn from_stream(stream: &mut TcpStream) -> Result<GetHeadersMessage, NodeError> {
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidSizeOfHeaders` if the block locator has more than `MAX_LOCATOR_HASHES` hashes.
+    pub fn from_stream<R: Read>(stream: &mut R) -> Result<GetHeadersMessage, NodeError> {
         let mut buffer_protocol_version = [0; 4];
         stream
             .read_exact(&mut buffer_protocol_version)
@@ -63,24 +73,31 @@ impl GetHeadersMessage {
 
         let hash_count = CompactSize::read_varint(stream)?;
         println!("Hash count: {}", hash_count.get_value());
+        if hash_count.get_value() > MAX_LOCATOR_HASHES {
+            return Err(NodeError::InvalidSizeOfHeaders(
+                "The block locator has too many hashes".to_string(),
+            ));
+        }
 
         let mut header_hashes = vec![];
-
-        let mut hash = [0; 32];
-        stream
-            .read_exact(&mut hash)
-            .map_err(|_| NodeError::FailedToRead("Failed to read all bytes".to_string()))?;
-
-        header_hashes.push(hash);
-
+        for _ in 0..hash_count.get_value() {
+            let mut hash = [0; 32];
+            stream
+                .read_exact(&mut hash)
+                .map_err(|_| NodeError::FailedToRead("Failed to read all bytes".to_string()))?;
+            header_hashes.push(hash);
+        }
+
+        let mut hash_stop = [0; 32];
         stream
-            .read_exact(&mut hash)
+            .read_exact(&mut hash_stop)
             .map_err(|_| NodeError::FailedToRead("Failed to read all bytes".to_string()))?;
 
         Ok(GetHeadersMessage {
             protocol_version,
             hash_count,
             header_hashes,
+            hash_stop,
         })
     }
 
@@ -147,4 +164,21 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_getheaders_from_stream_reads_whole_locator() -> Result<(), NodeError> {
+        let mut bytes = Vec::new();
+        bytes.extend(70015_i32.to_le_bytes());
+        bytes.push(2);
+        bytes.extend([1; 32]);
+        bytes.extend([2; 32]);
+        bytes.extend([3; 32]);
+
+        let getheaders_message = GetHeadersMessage::from_stream(&mut bytes.as_slice())?;
+
+        assert_eq!(getheaders_message.protocol_version, 70015);
+        assert_eq!(getheaders_message.header_hashes, vec![[1; 32], [2; 32]]);
+        assert_eq!(getheaders_message.hash_stop, [3; 32]);
+        Ok(())
+    }
 }
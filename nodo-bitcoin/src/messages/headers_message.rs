@@ -1,9 +1,12 @@
 use std::{
+    collections::HashMap,
     fs::File,
     io::{Read, Seek, SeekFrom},
     net::TcpStream,
 };
 
+use bitcoin_hashes::{sha256d, Hash};
+
 use crate::{
     block::block_hash::BlockHash,
     block_header::{block_header_bytes::BlockHeaderBytes, BlockHeader},
@@ -115,108 +118,89 @@ impl HeadersMessage {
         self.count
     }
 
-    /// Moves the block header file pointer to the block header with the specified starting header hash.
+    /// Finds the fork point between a block locator and the stored block headers.
     ///
     /// # Arguments
     ///
-    /// * `file` - A mutable reference to the opened file containing the block headers.
-    /// * `buffer` - A mutable reference to the buffer used for reading block headers.
-    /// * `starting_header_hash` - The starting header hash to search for.
+    /// * `locator` - The block locator hashes, newest first.
+    /// * `file` - A mutable reference to a reader over the block headers file.
     ///
     /// # Returns
     ///
-    /// * `Ok(())` if the starting header is found and the cursor is moved to that position.
-    fn move_file_to_starting_hash(
-        file: &mut File,
-        buffer: &mut BlockHeaderBytes,
-        starting_header_hash: &BlockHash,
-    ) -> Result<(), NodeError> {
-        loop {
-            match file.read_exact(buffer) {
-                Ok(_) => {
-                    let header = BlockHeader::from_bytes(buffer)?;
-
-                    if header.hash() == starting_header_hash {
-                        println!("Found starting hash");
-                        break;
-                    }
-                }
-                Err(_) => {
-                    println!("Reached end of file, starting hash not found");
-                    break;
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-    /// Saves headers after starting hash to the headers vector.
-    ///
-    /// # Arguments
-    ///
-    /// * `buffer` - A mutable array of 80 bytes used as a buffer for reading data.
-    /// * `file` - A mutable reference to a `File` object representing the headers file.
-    /// * `headers` - A mutable reference to a vector of vectors of bytes representing the headers.
+    /// * `Ok(Some(position))` - The position in the file of the first locator hash we have stored.
+    /// * `Ok(None)` - If none of the locator hashes is stored.
     ///
     /// # Errors
     ///
-    /// Returns an `Err` variant of `NodeError` if any error occurs during the process.
-    ///
-    /// The possible errors include:
-    /// - `NodeError::FailedToRead` - If there was an error reading the headers file.
-    fn obtain_headers_after_starting_hash(
-        mut buffer: BlockHeaderBytes,
-        file: &mut File,
-        headers: &mut Vec<BlockHeaderBytes>,
-    ) -> Result<(), NodeError> {
-        for _ in 0..MAX_HEADERS_COUNT {
-            match file.read_exact(&mut buffer) {
-                Ok(_) => {
-                    headers.push(buffer.to_vec());
-                }
-                Err(_) => {
-                    println!("Finished sending headers");
-                    break;
-                }
+    /// Returns a `NodeError` if the headers file could not be read or a header could not be parsed.
+    pub fn find_fork_point<R: Read + Seek>(
+        locator: &[BlockHash],
+        file: &mut R,
+    ) -> Result<Option<u64>, NodeError> {
+        file.seek(SeekFrom::Start(0))
+            .map_err(|_| NodeError::FailedToRead("Failed to read headers file".to_string()))?;
+
+        let mut found: HashMap<BlockHash, u64> = HashMap::new();
+        let mut buffer = [0u8; LENGTH_BLOCK_HEADERS].to_vec();
+        let mut position = 0;
+        while file.read_exact(&mut buffer).is_ok() {
+            let header = BlockHeader::from_bytes(&buffer)?;
+            let hash: BlockHash = header.hash().as_slice().try_into().map_err(|_| {
+                NodeError::FailedToParse("Failed to convert block hash to array".to_string())
+            })?;
+            if locator.contains(&hash) {
+                found.insert(hash, position);
             }
+            position += 1;
         }
 
-        Ok(())
+        Ok(locator.iter().find_map(|hash| found.get(hash).copied()))
     }
 
-    /// Retrieves the following block headers starting from the specified header hash.
+    /// Retrieves the block headers following the fork point of a block locator.
+    ///
+    /// If no locator hash is stored, the headers following the first stored header (the genesis) are returned.
     ///
     /// # Arguments
     ///
-    /// * `starting_header_hash` - The header hash to start retrieving block headers from.
+    /// * `locator` - The block locator hashes, newest first.
+    /// * `hash_stop` - The hash of the last header to retrieve, or all zeros to retrieve as many as possible.
+    /// * `file` - A mutable reference to a reader over the block headers file.
     ///
     /// # Returns
     ///
-    /// * `Ok(Vec<Vec<u8>>)` - A vector containing the retrieved block headers as byte vectors.
-    /// * `Err(NodeError)` - If there is an error opening or reading the block headers file.
-    fn retrieve_following_headers(
-        starting_header_hash: &BlockHash,
-        file: &mut File,
+    /// * `Ok(Vec<Vec<u8>>)` - Up to `MAX_HEADERS_COUNT` block headers as byte vectors.
+    /// * `Err(NodeError)` - If there is an error reading the block headers file.
+    pub fn headers_after_locator<R: Read + Seek>(
+        locator: &[BlockHash],
+        hash_stop: &BlockHash,
+        file: &mut R,
     ) -> Result<Vec<BlockHeaderBytes>, NodeError> {
+        let fork_point = Self::find_fork_point(locator, file)?.unwrap_or(0);
+        file.seek(SeekFrom::Start(
+            (fork_point + 1) * LENGTH_BLOCK_HEADERS as u64,
+        ))
+        .map_err(|_| NodeError::FailedToRead("Failed to read headers file".to_string()))?;
+
         let mut headers = Vec::new();
         let mut buffer = [0u8; LENGTH_BLOCK_HEADERS].to_vec();
-
-        Self::move_file_to_starting_hash(file, &mut buffer, starting_header_hash)?;
-        Self::obtain_headers_after_starting_hash(buffer, file, &mut headers)?;
-
-        file.seek(SeekFrom::Current(-80))
-            .map_err(|_| NodeError::FailedToRead("Failed to read headers file".to_string()))?;
+        while (headers.len() as u64) < MAX_HEADERS_COUNT && file.read_exact(&mut buffer).is_ok() {
+            headers.push(buffer.clone());
+            if sha256d::Hash::hash(&buffer).to_byte_array() == *hash_stop {
+                break;
+            }
+        }
 
         Ok(headers)
     }
 
-    /// Sends a batch of block headers to the specified TCP stream starting from the given header hash.
+    /// Sends a batch of block headers to the specified TCP stream, following the fork point of the
+    /// block locator of the getheaders message.
     ///
     /// # Arguments
     ///
     /// * `stream` - A mutable reference to a TCP stream representing the connection to the node.
-    /// * `getheaders_message` - The getheaders message received from the node, used to determine the starting header hash.
+    /// * `getheaders_message` - The getheaders message received from the node, with the block locator.
     /// * `header_file` - A mutable reference to the file containing block headers for retrieval.
     ///
     /// # Returns
@@ -228,9 +212,11 @@ impl HeadersMessage {
         get_headers_message: GetHeadersMessage,
         file: &mut File,
     ) -> Result<(), NodeError> {
-        let starting_header_hash = get_headers_message.header_hashes[0];
-
-        let headers_to_send = Self::retrieve_following_headers(&starting_header_hash, file)?;
+        let headers_to_send = Self::headers_after_locator(
+            &get_headers_message.header_hashes,
+            &get_headers_message.hash_stop,
+            file,
+        )?;
 
         println!("Sending {:?} headers", headers_to_send.len());
 
@@ -279,7 +265,25 @@ impl HeadersMessage {
 
 #[cfg(test)]
 mod tests {
-    use crate::{messages::headers_message::HeadersMessage, node_error::NodeError};
+    use std::io::Cursor;
+
+    use crate::{
+        block_header::GENESIS_BLOCK_HEADER, messages::headers_message::HeadersMessage,
+        node_error::NodeError,
+    };
+
+    use super::*;
+
+    /// Returns a headers file with the genesis followed by `count` chained headers.
+    fn headers_file(count: u32) -> Vec<BlockHeaderBytes> {
+        let mut headers = vec![GENESIS_BLOCK_HEADER.to_bytes()];
+        for i in 0..count {
+            let prev_blockhash = sha256d::Hash::hash(&headers[headers.len() - 1]).to_byte_array();
+            let header = BlockHeader::new(1, prev_blockhash, [0; 32], i, 0x1d00ffff, i, vec![]);
+            headers.push(header.to_bytes());
+        }
+        headers
+    }
 
     #[test]
     fn test_headers_message_is_deserialized() -> Result<(), NodeError> {
@@ -303,4 +307,52 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_headers_after_genesis_locator() -> Result<(), NodeError> {
+        let headers = headers_file(2500);
+        let mut file = Cursor::new(headers.concat());
+        let genesis_hash = sha256d::Hash::hash(&headers[0]).to_byte_array();
+
+        let batch = HeadersMessage::headers_after_locator(&[genesis_hash], &[0; 32], &mut file)?;
+
+        assert_eq!(batch.len(), MAX_HEADERS_COUNT as usize);
+        assert_eq!(batch[0], headers[1]);
+        assert_eq!(
+            BlockHeader::from_bytes(&batch[0])?.prev_blockhash,
+            genesis_hash
+        );
+        assert_eq!(batch[1999], headers[2000]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers_after_locator_uses_first_known_hash() -> Result<(), NodeError> {
+        let headers = headers_file(10);
+        let mut file = Cursor::new(headers.concat());
+        let unknown_hash = [7; 32];
+        let hash_5 = sha256d::Hash::hash(&headers[5]).to_byte_array();
+        let hash_2 = sha256d::Hash::hash(&headers[2]).to_byte_array();
+        let hash_stop = sha256d::Hash::hash(&headers[8]).to_byte_array();
+
+        let batch = HeadersMessage::headers_after_locator(
+            &[unknown_hash, hash_5, hash_2],
+            &hash_stop,
+            &mut file,
+        )?;
+
+        assert_eq!(batch, headers[6..9].to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers_after_unknown_locator_start_after_genesis() -> Result<(), NodeError> {
+        let headers = headers_file(3);
+        let mut file = Cursor::new(headers.concat());
+
+        let batch = HeadersMessage::headers_after_locator(&[[7; 32]], &[0; 32], &mut file)?;
+
+        assert_eq!(batch, headers[1..].to_vec());
+        Ok(())
+    }
 }
@@ -2,7 +2,11 @@ use std::io::Read;
 
 use crate::{
     block::block_hash::BlockHash,
-    constants::{INVENTORY_LENGTH, MAX_INVENTORY_VECTOR, MSG_BLOCK},
+    compact_size::CompactSize,
+    constants::{
+        INVENTORY_LENGTH, MAX_INVENTORY_VECTOR, MSG_BLOCK, MSG_CMPCT_BLOCK, MSG_FILTERED_BLOCK,
+        MSG_FILTERED_WITNESS_BLOCK, MSG_TX, MSG_WITNESS_BLOCK, MSG_WITNESS_TX,
+    },
     node_error::NodeError,
     utils::Utils,
 };
@@ -21,7 +25,7 @@ use crate::{
 /// * `inventory` - One or more inventory entries up to a maximum of 50,000 entries.
 #[derive(Debug, PartialEq)]
 pub struct InvMessage {
-    count: u8,
+    count: u64,
     pub inventory: Vec<InventoryEntry>,
 }
 
@@ -34,13 +38,31 @@ pub struct InvMessage {
 impl InvMessage {
     pub fn new(count: u64, inv_type: u32, hash: [u8; 32]) -> Result<Self, NodeError> {
         Ok(Self {
-            count: count.try_into().map_err(|_| {
-                NodeError::FailedToConvert("Failed to convert into count".to_string())
-            })?,
+            count,
             inventory: vec![InventoryEntry { inv_type, hash }],
         })
     }
 
+    /// Creates a message with an entry of the given inventory type for each hash, such as a
+    /// `getdata` asking for several transactions at once.
+    ///
+    /// # Arguments
+    ///
+    /// * `inv_type` - The inventory type of the entries.
+    /// * `hashes` - The hashes of the entries, in internal byte order.
+    pub fn with_hashes(inv_type: u32, hashes: &[[u8; 32]]) -> Self {
+        Self {
+            count: hashes.len() as u64,
+            inventory: hashes
+                .iter()
+                .map(|hash| InventoryEntry {
+                    inv_type,
+                    hash: *hash,
+                })
+                .collect(),
+        }
+    }
+
     /// Converts 'bytes' to an Inv message.
     /// # Arguments
     /// * `bytes` - A byte array with the message payload.
@@ -59,6 +81,12 @@ impl InvMessage {
             ));
         }
 
+        if bytes.len() < offset + count as usize * INVENTORY_LENGTH {
+            return Err(NodeError::InvalidSizeOfField(
+                "The inventory is shorter than its count".to_string(),
+            ));
+        }
+
         let mut inventory_entries = vec![];
         for _ in 0..count {
             let inventory = &bytes[offset..(offset + INVENTORY_LENGTH)];
@@ -69,9 +97,7 @@ impl InvMessage {
         }
 
         Ok(InvMessage {
-            count: count.try_into().map_err(|_| {
-                NodeError::FailedToConvert("Failed to convert into count".to_string())
-            })?,
+            count,
             inventory: inventory_entries,
         })
     }
@@ -83,7 +109,7 @@ impl InvMessage {
     /// * `Result<Vec<u8>, NodeError>` - A byte array with the message payload or NodeError in case of error.
     pub fn to_bytes(&self) -> Result<Vec<u8>, NodeError> {
         let mut bytes = vec![];
-        bytes.extend(&self.count.to_le_bytes());
+        bytes.extend(CompactSize::new(self.count as usize).to_bytes());
         for entry in &self.inventory {
             bytes.extend(&entry.to_bytes().map_err(|_| {
                 NodeError::FailedToWrite("Failed to extend Inv entry bytes".to_string())
@@ -92,23 +118,71 @@ impl InvMessage {
 
         Ok(bytes)
     }
-    /// Returns a reference to the block hash from the inventory.
+    /// Returns the inventory as typed pairs of inventory type and hash.
+    pub fn items(&self) -> Vec<(InvType, [u8; 32])> {
+        self.inventory
+            .iter()
+            .map(|entry| (entry.kind(), entry.hash))
+            .collect()
+    }
+
+    /// Returns the block requested by the first element of the inventory, with its inventory type.
     ///
-    /// This function retrieves the block hash from the first element of the inventory and
-    /// returns it as a reference. The function checks if the inventory type of the first element
-    /// is `MSG_BLOCK` and returns an error of type `NodeError::InvalidType` if it is not.
+    /// Blocks can be requested with `MSG_BLOCK`, to be sent without witnesses, or with
+    /// `MSG_WITNESS_BLOCK` (BIP144), to be sent with them.
     ///
     /// # Errors
     ///
-    /// Returns an `Err(NodeError::InvalidType)` if the first element of the inventory is not of type `MSG_BLOCK`.
-    pub fn block_hash(&self) -> Result<&BlockHash, NodeError> {
-        if self.inventory[0].inv_type != MSG_BLOCK {
-            return Err(NodeError::InvalidType(
+    /// Returns an `Err(NodeError::InvalidType)` if the inventory is empty or its first element is not
+    /// of type `MSG_BLOCK` nor `MSG_WITNESS_BLOCK`.
+    pub fn requested_block(&self) -> Result<(InvType, &BlockHash), NodeError> {
+        match self.inventory.first() {
+            Some(entry) if matches!(entry.kind(), InvType::Block | InvType::WitnessBlock) => {
+                Ok((entry.kind(), &entry.hash))
+            }
+            _ => Err(NodeError::InvalidType(
                 "Invalid type, not a block inventory".to_string(),
-            ));
+            )),
         }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Represents the type of an object being inventoried.
+pub enum InvType {
+    /// Any data of with this number may be ignored.
+    Error,
+    /// Hash is related to a transaction.
+    Tx,
+    /// Hash is related to a data block.
+    Block,
+    /// Hash of a block header, the reply should be a merkleblock message (BIP 37).
+    FilteredBlock,
+    /// Hash of a block header, the reply should be a cmpctblock message (BIP 152).
+    CmpctBlock,
+    /// Hash of a transaction with witness data (BIP 144).
+    WitnessTx,
+    /// Hash of a block with witness data (BIP 144).
+    WitnessBlock,
+    /// Hash of a block with witness data, the reply should be a merkleblock message (BIP 144).
+    FilteredWitnessBlock,
+    /// A type we don't know about.
+    Unknown(u32),
+}
 
-        Ok(&self.inventory[0].hash)
+impl From<u32> for InvType {
+    fn from(inv_type: u32) -> Self {
+        match inv_type {
+            0 => InvType::Error,
+            MSG_TX => InvType::Tx,
+            MSG_BLOCK => InvType::Block,
+            MSG_FILTERED_BLOCK => InvType::FilteredBlock,
+            MSG_CMPCT_BLOCK => InvType::CmpctBlock,
+            MSG_WITNESS_TX => InvType::WitnessTx,
+            MSG_WITNESS_BLOCK => InvType::WitnessBlock,
+            MSG_FILTERED_WITNESS_BLOCK => InvType::FilteredWitnessBlock,
+            other => InvType::Unknown(other),
+        }
     }
 }
 
@@ -131,6 +205,11 @@ pub struct InventoryEntry {
 }
 
 impl InventoryEntry {
+    /// Returns the type of the object being inventoried.
+    pub fn kind(&self) -> InvType {
+        InvType::from(self.inv_type)
+    }
+
     /// Converts 'bytes' to an InventoryEntry.
     /// # Arguments
     /// * `bytes` - A byte array with the message payload.
@@ -283,4 +362,60 @@ mod tests {
         ];
         assert_eq!(InventoryEntry::from_bytes(&bytes).unwrap(), expected_entry);
     }
+
+    #[test]
+    fn test_inv_items_with_mixed_types() -> Result<(), NodeError> {
+        let mut bytes = vec![0x04];
+        bytes.extend(MSG_TX.to_le_bytes());
+        bytes.extend([1u8; 32]);
+        bytes.extend(MSG_BLOCK.to_le_bytes());
+        bytes.extend([2u8; 32]);
+        bytes.extend(MSG_WITNESS_TX.to_le_bytes());
+        bytes.extend([3u8; 32]);
+        bytes.extend(0x20_u32.to_le_bytes());
+        bytes.extend([4u8; 32]);
+
+        let inv = InvMessage::from_bytes(&bytes)?;
+
+        assert_eq!(
+            inv.items(),
+            vec![
+                (InvType::Tx, [1u8; 32]),
+                (InvType::Block, [2u8; 32]),
+                (InvType::WitnessTx, [3u8; 32]),
+                (InvType::Unknown(0x20), [4u8; 32]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_requested_block_accepts_blocks_with_and_without_witnesses() -> Result<(), NodeError> {
+        let block = InvMessage::new(1, MSG_BLOCK, [1u8; 32])?;
+        let witness_block = InvMessage::new(1, MSG_WITNESS_BLOCK, [2u8; 32])?;
+        let transaction = InvMessage::new(1, MSG_TX, [3u8; 32])?;
+
+        assert_eq!(block.requested_block()?, (InvType::Block, &[1u8; 32]));
+        assert_eq!(
+            witness_block.requested_block()?,
+            (InvType::WitnessBlock, &[2u8; 32])
+        );
+        assert!(matches!(
+            transaction.requested_block(),
+            Err(NodeError::InvalidType(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_inv_shorter_than_count_fails() {
+        let mut bytes = vec![0x02];
+        bytes.extend(MSG_TX.to_le_bytes());
+        bytes.extend([1u8; 32]);
+
+        assert!(matches!(
+            InvMessage::from_bytes(&bytes),
+            Err(NodeError::InvalidSizeOfField(_))
+        ));
+    }
 }
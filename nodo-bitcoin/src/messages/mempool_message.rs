@@ -0,0 +1,57 @@
+use std::io::Write;
+
+use crate::{
+    connectors::peer_connector::send_message, constants::COMMAND_NAME_MEMPOOL, header::Header,
+    node_error::NodeError,
+};
+
+/// Represents a "mempool" message (BIP35), which asks a peer for the transactions of its mempool.
+///
+/// The peer answers with "inv" messages announcing the hashes of its unconfirmed transactions,
+/// which are then requested with "getdata" messages like the ones it relays. The message has no
+/// payload.
+///
+/// Peers only answer it if they serve bloom filters, most of them disconnect the node otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MempoolMessage;
+
+impl MempoolMessage {
+    /// Converts the message to bytes: a header with an empty payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the header could not be created.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, NodeError> {
+        Header::create_header(&vec![], COMMAND_NAME_MEMPOOL)
+    }
+
+    /// Sends the message to a peer.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the header could not be created or the message could not be sent.
+    pub fn send_message<W: Write>(&self, stream: &mut W) -> Result<(), NodeError> {
+        send_message(stream, self.to_bytes()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::node::read::retrieve_magic_bytes;
+
+    use super::*;
+
+    #[test]
+    fn test_mempool_message_is_a_header_with_an_empty_payload() -> Result<(), NodeError> {
+        let mut stream = vec![];
+
+        MempoolMessage.send_message(&mut stream)?;
+
+        assert_eq!(stream.len(), 24);
+        assert_eq!(stream[..4], retrieve_magic_bytes());
+        assert_eq!(&stream[4..16], b"mempool\0\0\0\0\0");
+        assert_eq!(Header::from_bytes(&stream).payload_size(), 0);
+        assert_eq!(stream[20..], [0x5d, 0xf6, 0xe0, 0xe2]);
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use std::io::Cursor;
+
+use bitcoin_hashes::{sha256d, Hash};
+
+use crate::{
+    block::tx_hash::TxHash,
+    block_header::BlockHeader,
+    compact_size::CompactSize,
+    connectors::peer_connector::receive_message,
+    constants::{LENGTH_BLOCK_HEADERS, LENGTH_BLOCK_TX},
+    node_error::NodeError,
+};
+
+/// Represents a "merkleblock" message (BIP37): a block header with a partial merkle tree that
+/// proves which of the transactions of the block match the bloom filter loaded in the peer.
+///
+/// It's the SPV proof that a transaction is included in a block, without downloading the block.
+#[derive(Debug, Clone)]
+pub struct MerkleBlockMessage {
+    /// The header of the block.
+    pub block_header: BlockHeader,
+    /// The number of transactions of the block.
+    pub total_transactions: u32,
+    /// The hashes of the partial merkle tree, in depth-first order.
+    pub hashes: Vec<TxHash>,
+    /// The flag bits of the partial merkle tree, in depth-first order, least significant bit first.
+    pub flags: Vec<u8>,
+}
+
+impl MerkleBlockMessage {
+    /// Builds the merkleblock of a block proving the inclusion of the matched transactions.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_header` - The header of the block.
+    /// * `tx_ids` - The ids of every transaction of the block, in order.
+    /// * `matches` - Whether each transaction of the block is matched.
+    pub fn new(block_header: BlockHeader, tx_ids: &[TxHash], matches: &[bool]) -> Self {
+        let mut partial_tree = PartialMerkleTree {
+            tx_ids,
+            bits: Vec::new(),
+            hashes: Vec::new(),
+        };
+        partial_tree.build(tree_height(tx_ids.len()), 0, matches);
+
+        let mut flags = vec![0; partial_tree.bits.len().div_ceil(8)];
+        for (position, bit) in partial_tree.bits.iter().enumerate() {
+            flags[position / 8] |= (*bit as u8) << (position % 8);
+        }
+        MerkleBlockMessage {
+            block_header,
+            total_transactions: tx_ids.len() as u32,
+            hashes: partial_tree.hashes,
+            flags,
+        }
+    }
+
+    /// Parses a "merkleblock" message from its payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the payload is truncated.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NodeError> {
+        let mut cursor = Cursor::new(bytes);
+        let block_header =
+            BlockHeader::from_bytes(&receive_message(&mut cursor, LENGTH_BLOCK_HEADERS)?)?;
+        let mut total_transactions = [0; 4];
+        total_transactions.copy_from_slice(&receive_message(&mut cursor, 4)?);
+        let hashes_count = CompactSize::read_varint(&mut cursor)?.get_value();
+        let hashes = (0..hashes_count)
+            .map(|_| receive_message(&mut cursor, LENGTH_BLOCK_TX))
+            .collect::<Result<Vec<TxHash>, NodeError>>()?;
+        let flags_count = CompactSize::read_varint(&mut cursor)?.get_value() as usize;
+        let flags = receive_message(&mut cursor, flags_count)?;
+
+        Ok(MerkleBlockMessage {
+            block_header,
+            total_transactions: u32::from_le_bytes(total_transactions),
+            hashes,
+            flags,
+        })
+    }
+
+    /// Converts the message to its payload bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.block_header.to_bytes();
+        bytes.extend(self.total_transactions.to_le_bytes());
+        bytes.extend(CompactSize::new(self.hashes.len()).to_bytes());
+        for hash in self.hashes.iter() {
+            bytes.extend(hash);
+        }
+        bytes.extend(CompactSize::new(self.flags.len()).to_bytes());
+        bytes.extend(&self.flags);
+        bytes
+    }
+
+    /// Verifies the partial merkle tree against the merkle root of the block header, and returns
+    /// the ids of the matched transactions.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidMerkleTree` if the partial merkle tree is malformed, or a
+    /// `NodeError::InvalidMerkleRoot` if it doesn't hash to the merkle root of the block.
+    pub fn matched_tx_ids(&self) -> Result<Vec<TxHash>, NodeError> {
+        let total_transactions = self.total_transactions as usize;
+        if total_transactions == 0 || self.hashes.len() > total_transactions {
+            return Err(NodeError::InvalidMerkleTree(format!(
+                "{} hashes for {} transactions",
+                self.hashes.len(),
+                total_transactions
+            )));
+        }
+
+        let mut extraction = PartialMerkleTreeExtraction {
+            total_transactions,
+            bits: &self.flags,
+            hashes: &self.hashes,
+            bits_used: 0,
+            hashes_used: 0,
+            matches: Vec::new(),
+        };
+        let merkle_root = extraction.extract(tree_height(total_transactions), 0)?;
+        if extraction.hashes_used != self.hashes.len()
+            || extraction.bits_used.div_ceil(8) != self.flags.len()
+        {
+            return Err(NodeError::InvalidMerkleTree(
+                "The partial merkle tree has unused hashes or flags".to_string(),
+            ));
+        }
+        if merkle_root != self.block_header.merkle_root_hash {
+            return Err(NodeError::InvalidMerkleRoot(
+                "The partial merkle tree doesn't match the merkle root".to_string(),
+            ));
+        }
+        Ok(extraction.matches)
+    }
+}
+
+/// Builds the hashes and flag bits of a partial merkle tree.
+struct PartialMerkleTree<'a> {
+    tx_ids: &'a [TxHash],
+    bits: Vec<bool>,
+    hashes: Vec<TxHash>,
+}
+
+impl PartialMerkleTree<'_> {
+    /// Adds the node at the given height and position, and its descendants if they lead to a match.
+    fn build(&mut self, height: u32, position: usize, matches: &[bool]) {
+        let first = position << height;
+        let last = ((position + 1) << height).min(self.tx_ids.len());
+        let parent_of_match = matches
+            .get(first..last)
+            .is_some_and(|matches| matches.contains(&true));
+        self.bits.push(parent_of_match);
+
+        if height == 0 || !parent_of_match {
+            let hash = node_hash(self.tx_ids, height, position);
+            self.hashes.push(hash);
+            return;
+        }
+        self.build(height - 1, position * 2, matches);
+        if position * 2 + 1 < tree_width(self.tx_ids.len(), height - 1) {
+            self.build(height - 1, position * 2 + 1, matches);
+        }
+    }
+}
+
+/// Walks a partial merkle tree, computing its root and collecting the matched transactions.
+struct PartialMerkleTreeExtraction<'a> {
+    total_transactions: usize,
+    bits: &'a [u8],
+    hashes: &'a [TxHash],
+    bits_used: usize,
+    hashes_used: usize,
+    matches: Vec<TxHash>,
+}
+
+impl PartialMerkleTreeExtraction<'_> {
+    /// Returns the hash of the node at the given height and position.
+    fn extract(&mut self, height: u32, position: usize) -> Result<TxHash, NodeError> {
+        let parent_of_match = self
+            .bits
+            .get(self.bits_used / 8)
+            .map(|byte| (byte >> (self.bits_used % 8)) & 1 == 1)
+            .ok_or(NodeError::InvalidMerkleTree(
+                "The partial merkle tree ran out of flags".to_string(),
+            ))?;
+        self.bits_used += 1;
+
+        if height == 0 || !parent_of_match {
+            let hash = self
+                .hashes
+                .get(self.hashes_used)
+                .ok_or(NodeError::InvalidMerkleTree(
+                    "The partial merkle tree ran out of hashes".to_string(),
+                ))?
+                .clone();
+            self.hashes_used += 1;
+            if height == 0 && parent_of_match {
+                self.matches.push(hash.clone());
+            }
+            return Ok(hash);
+        }
+
+        let left = self.extract(height - 1, position * 2)?;
+        let right = if position * 2 + 1 < tree_width(self.total_transactions, height - 1) {
+            let right = self.extract(height - 1, position * 2 + 1)?;
+            // Identical siblings would let a transaction be proven twice (CVE-2012-2459).
+            if right == left {
+                return Err(NodeError::InvalidMerkleTree(
+                    "The partial merkle tree has identical siblings".to_string(),
+                ));
+            }
+            right
+        } else {
+            left.clone()
+        };
+        Ok(hash_pair(&left, &right))
+    }
+}
+
+/// Returns the number of levels above the transactions in the merkle tree of a block.
+fn tree_height(total_transactions: usize) -> u32 {
+    let mut height = 0;
+    while tree_width(total_transactions, height) > 1 {
+        height += 1;
+    }
+    height
+}
+
+/// Returns the number of nodes of the merkle tree at the given height.
+fn tree_width(total_transactions: usize, height: u32) -> usize {
+    (total_transactions + (1 << height) - 1) >> height
+}
+
+/// Returns the hash of the node at the given height and position of the full merkle tree.
+fn node_hash(tx_ids: &[TxHash], height: u32, position: usize) -> TxHash {
+    if height == 0 {
+        return tx_ids[position].clone();
+    }
+    let left = node_hash(tx_ids, height - 1, position * 2);
+    if position * 2 + 1 < tree_width(tx_ids.len(), height - 1) {
+        hash_pair(&left, &node_hash(tx_ids, height - 1, position * 2 + 1))
+    } else {
+        hash_pair(&left, &left)
+    }
+}
+
+/// Hashes two sibling nodes into their parent.
+fn hash_pair(left: &TxHash, right: &TxHash) -> TxHash {
+    sha256d::Hash::hash(&[left.as_slice(), right.as_slice()].concat())
+        .to_byte_array()
+        .to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::block::Block;
+
+    use super::*;
+
+    const MANY_TRANSACTIONS_BLOCK: &str =
+        "blocks-test/0000000000001fdc30a4b54fff00ae2494add9f41297b1cc426d8b8230129a38.bin";
+
+    #[test]
+    fn test_merkle_block_proves_the_matched_transactions() -> Result<(), NodeError> {
+        let block_data = fs::read(MANY_TRANSACTIONS_BLOCK)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let block = Block::from_bytes(&block_data)?;
+        let tx_ids: Vec<TxHash> = block.transactions.iter().map(|tx| tx.tx_id()).collect();
+        let matches: Vec<bool> = (0..tx_ids.len()).map(|i| i == 1 || i == 6).collect();
+
+        let merkle_block = MerkleBlockMessage::new(block.header, &tx_ids, &matches);
+        let parsed = MerkleBlockMessage::from_bytes(&merkle_block.to_bytes())?;
+
+        assert!(parsed.hashes.len() < tx_ids.len());
+        assert_eq!(
+            parsed.matched_tx_ids()?,
+            vec![tx_ids[1].clone(), tx_ids[6].clone()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampered_merkle_block_is_rejected() -> Result<(), NodeError> {
+        let block_data = fs::read(MANY_TRANSACTIONS_BLOCK)
+            .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let block = Block::from_bytes(&block_data)?;
+        let tx_ids: Vec<TxHash> = block.transactions.iter().map(|tx| tx.tx_id()).collect();
+        let matches: Vec<bool> = (0..tx_ids.len()).map(|i| i == 3).collect();
+        let merkle_block = MerkleBlockMessage::new(block.header, &tx_ids, &matches);
+
+        let mut tampered_hash = merkle_block.clone();
+        tampered_hash.hashes[0][0] ^= 1;
+        let mut missing_flags = merkle_block.clone();
+        missing_flags.flags.pop();
+
+        assert!(matches!(
+            tampered_hash.matched_tx_ids(),
+            Err(NodeError::InvalidMerkleRoot(_))
+        ));
+        assert!(matches!(
+            missing_flags.matched_tx_ids(),
+            Err(NodeError::InvalidMerkleTree(_))
+        ));
+        Ok(())
+    }
+}
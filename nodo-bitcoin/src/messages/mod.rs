@@ -1,8 +1,14 @@
 pub mod block_message;
+pub mod compact_filter_message;
+pub mod feefilter_message;
+pub mod filter_load_message;
 pub mod get_data_message;
 pub mod get_headers_message;
 pub mod headers_message;
 pub mod inv_message;
+pub mod mempool_message;
+pub mod merkle_block_message;
+pub mod ping_message;
 pub mod tx_message;
 pub mod verack_message;
 pub mod version_message;
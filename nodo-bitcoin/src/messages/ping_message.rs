@@ -0,0 +1,68 @@
+use crate::{
+    constants::{COMMAND_NAME_PONG, LENGTH_PING_MESSAGE},
+    header::Header,
+    node_error::NodeError,
+};
+
+/// Represents a "ping" message (BIP31), sent to check that the connection to a peer is alive.
+///
+/// The peer answers with a "pong" message echoing the nonce, so the sender can match the answer
+/// to its ping.
+///
+/// # Fields
+///
+/// * `nonce` - A random value chosen by the sender of the ping.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PingMessage {
+    pub nonce: u64,
+}
+
+impl PingMessage {
+    /// Converts 'bytes' to a Ping message.
+    /// # Arguments
+    /// * `bytes` - A byte array with the message payload.
+    /// # Returns
+    /// * `Result<PingMessage, NodeError>` - A new Ping message or NodeError if the payload is not 8 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Result<PingMessage, NodeError> {
+        let nonce: [u8; LENGTH_PING_MESSAGE] = bytes.try_into().map_err(|_| {
+            NodeError::InvalidSizeOfField("The ping payload must be 8 bytes long".to_string())
+        })?;
+        Ok(PingMessage {
+            nonce: u64::from_le_bytes(nonce),
+        })
+    }
+
+    /// Builds the "pong" message answering the ping, header included, echoing its nonce.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the header of the pong could not be created.
+    pub fn pong_message(&self) -> Result<Vec<u8>, NodeError> {
+        let payload = self.nonce.to_le_bytes().to_vec();
+        let mut pong_message = Header::create_header(&payload, COMMAND_NAME_PONG)?;
+        pong_message.extend(payload);
+        Ok(pong_message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{constants::LENGTH_HEADER_MESSAGE, node::message_type::MessageType};
+
+    use super::*;
+
+    #[test]
+    fn test_pong_echoes_the_ping_nonce() -> Result<(), NodeError> {
+        let nonce = 0x0123_4567_89ab_cdefu64;
+
+        let ping = PingMessage::from_bytes(&nonce.to_le_bytes())?;
+        let pong_message = ping.pong_message()?;
+        let mut pong_header = Header::from_bytes(&pong_message[..LENGTH_HEADER_MESSAGE]);
+
+        assert_eq!(pong_header.extract_command_name()?, MessageType::Pong);
+        assert_eq!(pong_header.payload_size(), LENGTH_PING_MESSAGE);
+        assert_eq!(pong_message[LENGTH_HEADER_MESSAGE..], nonce.to_le_bytes());
+        assert!(PingMessage::from_bytes(&[]).is_err());
+        Ok(())
+    }
+}
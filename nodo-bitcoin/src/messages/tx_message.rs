@@ -1,8 +1,9 @@
-use std::net::TcpStream;
+use std::{io::Write, net::TcpStream};
 
 use crate::{
-    connectors::peer_connector::send_message, constants::COMMAND_NAME_TX, header::Header,
-    node_error::NodeError, transactions::transaction::Transaction,
+    block::block_hash::InternalHash, connectors::peer_connector::send_message,
+    constants::COMMAND_NAME_TX, header::Header, node_error::NodeError,
+    transactions::transaction::Transaction,
 };
 
 /// The `TxMessage` struct represents a Bitcoin `tx` message.
@@ -11,26 +12,25 @@ pub struct TxMessage;
 impl TxMessage {
     /// Receives a `tx` message from a peer over the given `TcpStream`.
     pub fn receive_tx_message(stream: &mut TcpStream) -> Result<Transaction, NodeError> {
-        let _tx_message_header = Header::new(stream)?;
-        Transaction::read_transaction(stream)
+        let tx_message_header = Header::new(stream)?;
+        Transaction::read_transaction_with_limit(stream, tx_message_header.payload_size())
     }
-    /// Sends a `tx` message to a peer over the given `TcpStream`.
-    pub fn send_tx_message(
+    /// Sends a `tx` message to a peer over the given stream, usually a `TcpStream`.
+    pub fn send_tx_message<W: Write>(
         transaction: &Transaction,
-        stream: &mut TcpStream,
+        stream: &mut W,
     ) -> Result<(), NodeError> {
         let tx_message_bytes = transaction.to_bytes();
         let header_tx = Header::create_header(&tx_message_bytes, COMMAND_NAME_TX)?;
 
         let mut bytes = vec![];
-        let mut tx_id = transaction.tx_id();
-        tx_id.reverse();
+        let tx_id = InternalHash::from_slice(&transaction.tx_id())?.to_display();
 
         bytes.extend(header_tx);
         bytes.extend(tx_message_bytes);
         send_message(stream, bytes)?;
 
-        println!("Broadcasted tx: {:?}", tx_id);
+        println!("Broadcasted tx: {}", tx_id);
         Ok(())
     }
 }
@@ -1,8 +1,8 @@
 use crate::compact_size::CompactSize;
 use crate::connectors::peer_connector::send_message;
-use crate::constants::{COMMAND_NAME_VERSION, LOCAL_IP, LOCAL_PORT};
+use crate::constants::{COMMAND_NAME_VERSION, LOCAL_IP, LOCAL_PORT, MAX_USER_AGENT_LENGTH};
 use crate::header::Header;
-use crate::node::read::retrieve_version;
+use crate::node::read::{retrieve_user_agent, retrieve_version};
 use crate::node_error::NodeError;
 use crate::utils::Utils;
 
@@ -38,7 +38,7 @@ pub struct VersionMessage {
     /// A random nonce used to detect connections to self.
     pub nonce: u64,
     /// The user agent of the transmitting node.
-    pub user_agent_bytes: u8,
+    pub user_agent: String,
     /// The last block received by the transmitting node.
     pub start_height: i32,
     /// Whether the transmitting node wants to receive inv messages for transactions.
@@ -56,6 +56,48 @@ impl VersionMessage {
     ///
     /// Returns a NodeError if there was an issue getting the local socket address.
     pub fn new(peer_addr: &SocketAddr) -> Result<VersionMessage, NodeError> {
+        Self::with_nonce(peer_addr, rand::thread_rng().gen())
+    }
+
+    /// Constructs a new VersionMessage struct like `new`, but with the given nonce instead of a random one,
+    /// so the bytes of the message can be checked in tests.
+    ///
+    /// # Arguments
+    ///
+    /// * peer_addr - A SocketAddr struct representing the address of the peer node.
+    /// * nonce - The nonce of the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns a NodeError if there was an issue getting the local socket address.
+    pub fn with_nonce(peer_addr: &SocketAddr, nonce: u64) -> Result<VersionMessage, NodeError> {
+        Self::with_user_agent(peer_addr, nonce, retrieve_user_agent())
+    }
+
+    /// Constructs a new VersionMessage struct like `with_nonce`, but with the given user agent instead of
+    /// the configured one.
+    ///
+    /// # Arguments
+    ///
+    /// * peer_addr - A SocketAddr struct representing the address of the peer node.
+    /// * nonce - The nonce of the message.
+    /// * user_agent - The user agent the node identifies itself with, such as `/inoxidables:1.0/`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a NodeError if there was an issue getting the local socket address, or a
+    /// NodeError::InvalidSizeOfField if the user agent is longer than MAX_USER_AGENT_LENGTH bytes.
+    pub fn with_user_agent(
+        peer_addr: &SocketAddr,
+        nonce: u64,
+        user_agent: String,
+    ) -> Result<VersionMessage, NodeError> {
+        if user_agent.len() > MAX_USER_AGENT_LENGTH {
+            return Err(NodeError::InvalidSizeOfField(format!(
+                "The user agent can't be longer than {} bytes",
+                MAX_USER_AGENT_LENGTH
+            )));
+        }
         let local_ip = Self::get_local_socket_addr()?;
 
         Ok(VersionMessage {
@@ -67,8 +109,8 @@ impl VersionMessage {
             addr_recv_port: peer_addr.port(),
             addr_trans_addr: Utils::socket_addr_to_ipv6_bytes(&local_ip),
             addr_trans_port: LOCAL_PORT,
-            nonce: rand::thread_rng().gen(),
-            user_agent_bytes: 0,
+            nonce,
+            user_agent,
             start_height: 0,
             addr_trans_services: 0,
             relay: 1,
@@ -105,17 +147,38 @@ impl VersionMessage {
         bytes.extend(&self.timestamp.to_le_bytes());
         bytes.extend(&self.addr_recv_services.to_le_bytes());
         bytes.extend(&self.addr_recv_address);
-        bytes.extend(&self.addr_recv_port.to_le_bytes());
+        bytes.extend(&self.addr_recv_port.to_be_bytes());
         bytes.extend(&self.addr_trans_services.to_le_bytes());
         bytes.extend(&self.addr_trans_addr);
-        bytes.extend(&self.addr_trans_port.to_le_bytes());
+        bytes.extend(&self.addr_trans_port.to_be_bytes());
         bytes.extend(&self.nonce.to_le_bytes());
-        bytes.extend(&self.user_agent_bytes.to_le_bytes());
+        bytes.extend(CompactSize::new(self.user_agent.len()).to_bytes());
+        bytes.extend(self.user_agent.as_bytes());
         bytes.extend(&self.start_height.to_le_bytes());
         bytes.extend(&self.relay.to_be_bytes());
         bytes
     }
 
+    /// Reads the services a peer announces from the payload of its version message.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The payload of the version message.
+    ///
+    /// # Errors
+    ///
+    /// NodeError::InvalidSizeOfField if the payload ends before the services field.
+    pub fn services_from_bytes(bytes: &[u8]) -> Result<u64, NodeError> {
+        match bytes.get(4..12) {
+            Some(services) => Ok(u64::from_le_bytes(services.try_into().map_err(|_| {
+                NodeError::InvalidSizeOfField("Invalid services field".to_string())
+            })?)),
+            None => Err(NodeError::InvalidSizeOfField(
+                "The version message is shorter than its services field".to_string(),
+            )),
+        }
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<VersionMessage, NodeError> {
         let version = i32::from_le_bytes(bytes[0..4].try_into().unwrap());
         let services = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
@@ -127,17 +190,17 @@ impl VersionMessage {
         let addr_trans_addr = bytes[54..70].try_into().unwrap();
         let addr_trans_port = u16::from_be_bytes(bytes[70..72].try_into().unwrap());
         let nonce = u64::from_le_bytes(bytes[72..80].try_into().unwrap());
-        let user_agent_bytes = CompactSize::read_varint(&mut &bytes[80..])?.get_value() as usize;
-        let start_height = i32::from_le_bytes(
-            bytes[80 + user_agent_bytes..84 + user_agent_bytes]
-                .try_into()
-                .unwrap(),
-        );
-        let relay = u8::from_be_bytes(
-            bytes[84 + user_agent_bytes..85 + user_agent_bytes]
-                .try_into()
-                .unwrap(),
-        );
+        let mut remaining = &bytes[80..];
+        let user_agent_length = CompactSize::read_varint(&mut remaining)?.get_value() as usize;
+        if remaining.len() < user_agent_length + 5 {
+            return Err(NodeError::InvalidSizeOfField(
+                "The version message is shorter than its user agent".to_string(),
+            ));
+        }
+        let (user_agent, remaining) = remaining.split_at(user_agent_length);
+        let user_agent = String::from_utf8_lossy(user_agent).to_string();
+        let start_height = i32::from_le_bytes(remaining[0..4].try_into().unwrap());
+        let relay = u8::from_be_bytes(remaining[4..5].try_into().unwrap());
 
         Ok(VersionMessage {
             version,
@@ -150,7 +213,7 @@ impl VersionMessage {
             addr_trans_addr,
             addr_trans_port,
             nonce,
-            user_agent_bytes: user_agent_bytes as u8,
+            user_agent,
             start_height,
             relay,
         })
@@ -222,7 +285,7 @@ mod tests {
             Utils::socket_addr_to_ipv6_bytes(&ip)
         );
         assert_eq!(version.addr_recv_port, ip.port());
-        assert_eq!(version.user_agent_bytes, 0);
+        assert_eq!(version.user_agent, retrieve_user_agent());
         assert_eq!(version.start_height, 0);
         assert_eq!(version.addr_trans_port, LOCAL_PORT);
         assert_eq!(
@@ -242,4 +305,54 @@ mod tests {
         assert!(bytes.len() > 84);
         Ok(())
     }
+
+    #[test]
+    fn test_version_message_with_nonce_bytes() -> Result<(), NodeError> {
+        load_app_config(None)?;
+        let ip = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8223);
+        let mut version = VersionMessage::with_nonce(&ip, 0x0102030405060708)?;
+        version.version = 70015;
+        version.timestamp = 1_700_000_000;
+
+        let expected = Utils::hex_string_to_bytes(
+            [
+                "7f110100",
+                "0000000000000000",
+                "00f1536500000000",
+                "0100000000000000",
+                "00000000000000000000ffff7f000001",
+                "201f",
+                "0000000000000000",
+                "00000000000000000000ffff7f000001",
+                "1f90",
+                "0807060504030201",
+                "00",
+                "00000000",
+                "01",
+            ]
+            .concat(),
+        )?;
+        assert_eq!(version.to_bytes(), expected);
+        assert_eq!(VersionMessage::from_bytes(&expected)?, version);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_agent_is_serialized_in_the_version_payload() -> Result<(), NodeError> {
+        load_app_config(None)?;
+        let ip = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8223);
+        let user_agent = "/inoxidables:1.0/";
+
+        let version = VersionMessage::with_user_agent(&ip, 1, user_agent.to_string())?;
+        let bytes = version.to_bytes();
+
+        assert_eq!(bytes[80] as usize, user_agent.len());
+        assert_eq!(&bytes[81..81 + user_agent.len()], user_agent.as_bytes());
+        assert_eq!(VersionMessage::from_bytes(&bytes)?, version);
+        assert!(matches!(
+            VersionMessage::with_user_agent(&ip, 1, "a".repeat(MAX_USER_AGENT_LENGTH + 1)),
+            Err(NodeError::InvalidSizeOfField(_))
+        ));
+        Ok(())
+    }
 }
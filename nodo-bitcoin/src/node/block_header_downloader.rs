@@ -2,28 +2,33 @@ use std::{
     fs::File,
     io::{self, Seek, Write},
     net::TcpStream,
+    time::{Duration, Instant},
 };
 
 use crate::{
+    block::lock_headers_file,
     block_header::{block_header_bytes::BlockHeaderBytes, BlockHeader},
+    channels::event_sink::{EventSink, NodeEvent},
     config::obtain_dir_path,
     connectors::peer_connector::receive_message,
-    constants::{BLOCK_HEADERS_FILE, LENGTH_BLOCK_HEADERS, MAX_HEADERS_COUNT},
+    constants::{
+        BLOCK_HEADERS_FILE, HEADERS_MESSAGE_TIMEOUT, LENGTH_BLOCK_HEADERS, MAX_HEADERS_COUNT,
+    },
     header::Header,
     logger::Logger,
     messages::{get_headers_message::GetHeadersMessage, headers_message::HeadersMessage},
     node::{
         message_type::MessageType,
         receive_messages::{
-            receive_addr_message, receive_feefilter_message, receive_inv_message, send_pong_message,
+            receive_addr_message, receive_feefilter_message, receive_inv_message,
+            receive_sendcmpct_message, send_pong_message,
         },
     },
     node_error::NodeError,
-    ui::ui_message::UIMessage,
     utils::Utils,
 };
 
-use super::read::read_last_block_header;
+use super::{read::read_last_block_header, time_left};
 
 /// The `BlockHeaderDownloader` struct handles the connection to a peer and the downloading of block headers.
 pub struct BlockHeaderDownloader<'a> {
@@ -81,8 +86,10 @@ impl<'a> BlockHeaderDownloader<'a> {
     ///
     /// * `stream` - A mutable reference to a `TcpStream` representing the connection
     ///             to the peer that is expected to send the `headers` message.
+    /// * `deadline` - The instant the download must finish by. The read timeout of the stream is
+    ///   restored to its previous value once the download ends.
     /// * `logger` - A mutable reference to a `Logger` to log messages received from the peer.
-    /// * `ui_sender` - A mutable reference to a `glib::Sender<UIMessage>` to send messages to the UI.
+    /// * `events` - The sink the download progress is emitted to, such as the UI.
     ///
     /// # Returns
     ///
@@ -90,18 +97,28 @@ impl<'a> BlockHeaderDownloader<'a> {
     ///
     /// # Errors
     ///
-    /// * `NodeError` - A `NodeError` is returned if there is an error sending or receiving
-    ///                the messages.
+    /// * `NodeError` - A `NodeError::IbdTimeout` is returned if the deadline passes, or another
+    ///                `NodeError` if there is an error sending or receiving the messages.
     pub fn start(
         &mut self,
+        deadline: Instant,
         logger: &Logger,
-        ui_sender: &glib::Sender<UIMessage>,
+        events: &impl EventSink,
     ) -> Result<(), NodeError> {
         println!("Sending getheaders message: Starting Headers download");
         let last_block_header = read_last_block_header()?;
         GetHeadersMessage::send_message(self.stream, &last_block_header)?;
 
-        self.handle_download(logger, ui_sender, last_block_header)
+        let previous_timeout = self.stream.read_timeout().map_err(|_| {
+            NodeError::ReadTimeoutFromStream("Failed to get read timeout".to_string())
+        })?;
+        let result = self.handle_download(deadline, logger, events, last_block_header);
+        self.stream
+            .set_read_timeout(previous_timeout)
+            .map_err(|_| {
+                NodeError::ReadTimeoutFromStream("Failed to restore read timeout".to_string())
+            })?;
+        result
     }
 
     /// Handles the initial block headers received over a TCP stream and performs corresponding actions.
@@ -109,8 +126,10 @@ impl<'a> BlockHeaderDownloader<'a> {
     /// # Arguments
     ///
     /// * `stream` - A mutable reference to a `TcpStream` to receive messages from.
+    /// * `deadline` - The instant the download must finish by. Each message is waited for no longer
+    ///   than `HEADERS_MESSAGE_TIMEOUT`, and never past the deadline, so a stalled peer is left early.
     /// * `logger` - A mutable reference to a `Logger` to log messages received from the peer.
-    /// * `ui_sender` - A mutable reference to a `glib::Sender<UIMessage>` to send messages to the UI.
+    /// * `events` - The sink the download progress is emitted to, such as the UI.
     /// * `last_bh` - A vector of bytes representing the last block header in the block headers file.
     ///
     /// # Returns
@@ -120,35 +139,42 @@ impl<'a> BlockHeaderDownloader<'a> {
     ///
     /// # Errors
     ///
-    /// Returns a `NodeError` if there was an error while receiving or handling messages,
-    /// or if the maximum number of headers is reached.
+    /// Returns a `NodeError::IbdTimeout` if the deadline passes, or a `NodeError` if there was an
+    /// error while receiving or handling messages, or if the maximum number of headers is reached.
     fn handle_download(
         &mut self,
+        deadline: Instant,
         logger: &Logger,
-        ui_sender: &glib::Sender<UIMessage>,
+        events: &impl EventSink,
         mut last_bh: BlockHeaderBytes,
     ) -> Result<(), NodeError> {
         let mut count_headers = 1;
         loop {
+            let message_timeout =
+                time_left(deadline)?.min(Duration::from_secs(HEADERS_MESSAGE_TIMEOUT));
+            self.stream
+                .set_read_timeout(Some(message_timeout))
+                .map_err(|_| {
+                    NodeError::ReadTimeoutFromStream("Failed to set read timeout".to_string())
+                })?;
             let mut header = Header::new(self.stream)?;
             let command_name = header.extract_command_name()?;
             if command_name == MessageType::Headers {
-                println!(
-                    "Receiving command: {:?}, count: {}",
-                    command_name, count_headers
-                );
+                logger.log_message(
+                    &command_name,
+                    format!(
+                        "Received: {:?} in headers download, count: {}",
+                        command_name, count_headers
+                    ),
+                )?;
                 count_headers += 1;
-                ui_sender
-                    .send(UIMessage::UpdateHeadersProgress)
-                    .map_err(|_| {
-                        NodeError::FailedToSendMessage(
-                            "Failed to send headers count message to UI".to_string(),
-                        )
-                    })?;
+                events.emit(NodeEvent::HeadersDownloaded)?;
             } else {
-                println!("Receiving command: {:?}", command_name);
+                logger.log_message(
+                    &command_name,
+                    format!("Received: {:?} in headers download", command_name),
+                )?;
             }
-            logger.log(format!("Received: {:?} in headers download", command_name))?;
 
             match command_name {
                 MessageType::Headers => {
@@ -158,47 +184,42 @@ impl<'a> BlockHeaderDownloader<'a> {
                         GetHeadersMessage::send_message(self.stream, &last_bh)?;
                         continue;
                     } else {
-                        ui_sender
-                            .send(UIMessage::HeadersDownloadFinished)
-                            .map_err(|_| {
-                                NodeError::FailedToSendMessage(
-                                    "Failed to send headers download finished message to UI"
-                                        .to_string(),
-                                )
-                            })?;
+                        events.emit(NodeEvent::HeadersDownloadFinished)?;
                         break;
                     }
                 }
                 MessageType::Ping => {
-                    println!("Handle ping message");
                     send_pong_message(self.stream, &header)?;
                     continue;
                 }
                 MessageType::Pong => {
-                    println!("Handle pong message");
                     continue;
                 }
                 MessageType::SendHeaders => {
-                    println!("Handle sendheaders message");
                     continue;
                 }
                 MessageType::Addr => {
-                    println!("Recieved an addr message");
                     receive_addr_message(self.stream, &header)?;
                     continue;
                 }
                 MessageType::FeeFilter => {
-                    println!("Recieved a feefilter message");
                     receive_feefilter_message(self.stream, &header)?;
                     continue;
                 }
+                MessageType::SendCmpct => {
+                    receive_sendcmpct_message(self.stream, &header)?;
+                    continue;
+                }
                 MessageType::Inv => {
-                    println!("Recieved a inv message");
                     receive_inv_message(self.stream, &header)?;
                     continue;
                 }
+                MessageType::Unknown(command) => {
+                    logger.debug(format!("Skipping unknown command: {}", command))?;
+                    header.skip_payload(self.stream)?;
+                }
                 _ => {
-                    println!("Command not supported");
+                    logger.debug(format!("Command not supported: {:?}", command_name))?;
                     receive_message(self.stream, header.payload_size())?;
                 }
             }
@@ -230,10 +251,11 @@ impl<'a> BlockHeaderDownloader<'a> {
 
         for _ in 0..headers_count {
             let recv_block_header = receive_message(self.stream, LENGTH_BLOCK_HEADERS)?;
-            logger.log("Downloaded new Header".to_string())?;
+            logger.debug("Downloaded new Header".to_string())?;
             receive_message(self.stream, 1)?;
             block_headers.push(recv_block_header);
         }
+        logger.log(format!("Downloaded {} headers", headers_count))?;
 
         let mut last_block_headers = Vec::new();
         let last_bh_timestamp = BlockHeader::from_bytes(&last_bh)?.timestamp;
@@ -270,6 +292,7 @@ impl<'a> BlockHeaderDownloader<'a> {
         &self,
         block_headers: &Vec<BlockHeaderBytes>,
     ) -> Result<(), NodeError> {
+        let _headers_file_guard = lock_headers_file()?;
         let mut file = &self.file;
         let file_size = file.seek(io::SeekFrom::End(0)).map_err(|_| {
             NodeError::FailedToWriteAll("Failed to write block header to file".to_string())
@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard, OnceLock},
+    time::Instant,
+};
+
+use crate::{node::read::retrieve_max_connections, node_error::NodeError};
+
+/// The connection limiter shared by the outbound connections of the node and the server.
+static CONNECTION_LIMITER: OnceLock<ConnectionLimiter> = OnceLock::new();
+
+/// Bounds the number of connections the node keeps open, adding up the outbound connections opened
+/// to peers and the inbound connections accepted by the server.
+///
+/// Once the limit is reached, new outbound connections are refused, while a new inbound connection
+/// takes the place of the least recently active inbound peer.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    /// The maximum number of connections open at the same time.
+    max_connections: usize,
+    /// The connections currently open.
+    connections: Mutex<Connections>,
+}
+
+/// The connections counted by a `ConnectionLimiter`.
+#[derive(Debug, Default)]
+struct Connections {
+    /// The addresses of the peers of the outbound connections, by the id of their permit.
+    outbound: HashMap<u64, SocketAddr>,
+    /// The inbound connections, by the id of their permit.
+    inbound: HashMap<u64, InboundPeer>,
+    /// The id of the next permit.
+    next_id: u64,
+}
+
+/// An inbound connection, kept so it can be closed when a newer one needs its place.
+#[derive(Debug)]
+struct InboundPeer {
+    /// A clone of the stream of the connection.
+    stream: TcpStream,
+    /// The last time a message was received from the peer.
+    last_active: Instant,
+}
+
+/// The permit of an inbound connection, which releases its place when it's dropped.
+#[derive(Debug)]
+pub struct InboundPermit<'a> {
+    limiter: &'a ConnectionLimiter,
+    id: u64,
+}
+
+/// The permit of an outbound connection, which releases its place when it's dropped.
+#[derive(Debug)]
+pub struct OutboundPermit<'a> {
+    limiter: &'a ConnectionLimiter,
+    id: u64,
+}
+
+/// An outbound connection to a peer, which keeps its place in the limiter of the node while the
+/// stream is open. It can be used wherever a `TcpStream` is expected.
+#[derive(Debug)]
+pub struct OutboundStream {
+    stream: TcpStream,
+    _permit: OutboundPermit<'static>,
+}
+
+impl ConnectionLimiter {
+    /// Creates a limiter allowing up to the given number of connections. At least one connection is
+    /// always allowed.
+    pub fn new(max_connections: usize) -> ConnectionLimiter {
+        ConnectionLimiter {
+            max_connections: max_connections.max(1),
+            connections: Mutex::new(Connections::default()),
+        }
+    }
+
+    /// Returns the limiter of the node, allowing the number of connections configured in MAX_CONNECTIONS.
+    pub fn global() -> &'static ConnectionLimiter {
+        CONNECTION_LIMITER.get_or_init(|| ConnectionLimiter::new(retrieve_max_connections()))
+    }
+
+    /// Returns the number of connections currently open.
+    pub fn len(&self) -> usize {
+        self.lock().map_or(0, |connections| {
+            connections.outbound.len() + connections.inbound.len()
+        })
+    }
+
+    /// Checks if there are no connections open.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Counts a new outbound connection to the given peer, if the limit has not been reached.
+    ///
+    /// # Returns
+    ///
+    /// The permit of the connection if it can be opened, which releases its place when it's dropped.
+    pub fn try_add_outbound(&self, peer: SocketAddr) -> Option<OutboundPermit<'_>> {
+        let mut connections = self.lock().ok()?;
+        if connections.outbound.len() + connections.inbound.len() >= self.max_connections {
+            return None;
+        }
+        let id = connections.next_id;
+        connections.next_id += 1;
+        connections.outbound.insert(id, peer);
+        Some(OutboundPermit { limiter: self, id })
+    }
+
+    /// Counts a connection accepted by the server. If the limit has been reached, the least recently
+    /// active inbound connection is closed to make room for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream of the accepted connection.
+    ///
+    /// # Returns
+    ///
+    /// The permit of the connection, which releases its place when it's dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::ConnectionLimitReached` if every place is taken by an outbound connection,
+    /// or a `NodeError::FailedToCloneStream` if the stream could not be cloned.
+    pub fn accept_inbound(&self, stream: &TcpStream) -> Result<InboundPermit<'_>, NodeError> {
+        let stream = stream.try_clone().map_err(|_| {
+            NodeError::FailedToCloneStream("Failed to clone inbound stream".to_string())
+        })?;
+        let mut connections = self.lock()?;
+
+        if connections.outbound.len() + connections.inbound.len() >= self.max_connections {
+            let least_recently_active = connections
+                .inbound
+                .iter()
+                .min_by_key(|(_, peer)| peer.last_active)
+                .map(|(id, _)| *id)
+                .ok_or_else(|| {
+                    NodeError::ConnectionLimitReached(format!(
+                        "The {} connections are taken by outbound peers",
+                        self.max_connections
+                    ))
+                })?;
+            if let Some(evicted) = connections.inbound.remove(&least_recently_active) {
+                println!(
+                    "Closing inbound connection {:?} to accept a new one",
+                    evicted.stream.peer_addr()
+                );
+                let _ = evicted.stream.shutdown(Shutdown::Both);
+            }
+        }
+
+        let id = connections.next_id;
+        connections.next_id += 1;
+        connections.inbound.insert(
+            id,
+            InboundPeer {
+                stream,
+                last_active: Instant::now(),
+            },
+        );
+        Ok(InboundPermit { limiter: self, id })
+    }
+
+    /// Locks the connections of the limiter.
+    fn lock(&self) -> Result<MutexGuard<'_, Connections>, NodeError> {
+        self.connections
+            .lock()
+            .map_err(|_| NodeError::MutexError("Connection limiter mutex poisoned".to_string()))
+    }
+}
+
+impl InboundPermit<'_> {
+    /// Records that a message was received from the peer, so it's not the first one to be closed.
+    pub fn record_activity(&self) {
+        if let Ok(mut connections) = self.limiter.lock() {
+            if let Some(peer) = connections.inbound.get_mut(&self.id) {
+                peer.last_active = Instant::now();
+            }
+        }
+    }
+
+    /// Checks if the connection still has its place, that is, if it was not closed for a newer one.
+    pub fn is_active(&self) -> bool {
+        self.limiter
+            .lock()
+            .is_ok_and(|connections| connections.inbound.contains_key(&self.id))
+    }
+}
+
+impl Drop for InboundPermit<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.limiter.lock() {
+            connections.inbound.remove(&self.id);
+        }
+    }
+}
+
+impl Drop for OutboundPermit<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.limiter.lock() {
+            connections.outbound.remove(&self.id);
+        }
+    }
+}
+
+impl OutboundStream {
+    /// Creates an outbound connection from its stream and the permit taken for it.
+    pub fn new(stream: TcpStream, permit: OutboundPermit<'static>) -> OutboundStream {
+        OutboundStream {
+            stream,
+            _permit: permit,
+        }
+    }
+}
+
+impl Deref for OutboundStream {
+    type Target = TcpStream;
+
+    fn deref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl DerefMut for OutboundStream {
+    fn deref_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+}
+
+impl Read for OutboundStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for OutboundStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener};
+
+    use super::*;
+
+    /// Opens a connection to the listener and returns both of its ends.
+    fn connection_pair(listener: &TcpListener) -> Result<(TcpStream, TcpStream), NodeError> {
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to get listener address".to_string()))?;
+        let client = TcpStream::connect(addr)
+            .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+        let (server, _) = listener
+            .accept()
+            .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+        Ok((client, server))
+    }
+
+    #[test]
+    fn test_fifth_connection_is_refused_or_evicts_the_oldest_inbound() -> Result<(), NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+        let limiter = ConnectionLimiter::new(4);
+        let peer = SocketAddr::from(([127, 0, 0, 1], 18333));
+
+        let (mut oldest_client, oldest_server) = connection_pair(&listener)?;
+        let (_, newer_server) = connection_pair(&listener)?;
+        let oldest = limiter.accept_inbound(&oldest_server)?;
+        let newer = limiter.accept_inbound(&newer_server)?;
+        oldest.record_activity();
+        newer.record_activity();
+        let first_outbound = limiter.try_add_outbound(peer);
+        let second_outbound = limiter.try_add_outbound(peer);
+        assert!(first_outbound.is_some() && second_outbound.is_some());
+        assert_eq!(limiter.len(), 4);
+
+        assert!(limiter.try_add_outbound(peer).is_none());
+
+        let (_, new_server) = connection_pair(&listener)?;
+        let new = limiter.accept_inbound(&new_server)?;
+        assert_eq!(limiter.len(), 4);
+        assert!(!oldest.is_active());
+        assert!(newer.is_active() && new.is_active());
+        let mut buffer = [0; 1];
+        assert_eq!(oldest_client.read(&mut buffer).unwrap_or(0), 0);
+
+        drop(oldest);
+        drop(newer);
+        drop(first_outbound);
+        assert_eq!(limiter.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inbound_connection_is_refused_when_outbound_take_every_place() -> Result<(), NodeError>
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+        let limiter = ConnectionLimiter::new(4);
+        let permits: Vec<Option<OutboundPermit>> = (0..4)
+            .map(|port| limiter.try_add_outbound(SocketAddr::from(([127, 0, 0, 1], port))))
+            .collect();
+        assert!(permits.iter().all(Option::is_some));
+
+        let (_, server) = connection_pair(&listener)?;
+        assert!(matches!(
+            limiter.accept_inbound(&server),
+            Err(NodeError::ConnectionLimitReached(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dropped_outbound_connection_frees_its_place() -> Result<(), NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+        let limiter: &'static ConnectionLimiter = Box::leak(Box::new(ConnectionLimiter::new(1)));
+        let (client, mut server) = connection_pair(&listener)?;
+        let peer = client
+            .peer_addr()
+            .map_err(|_| NodeError::FailedToConnect("Failed to get peer address".to_string()))?;
+
+        let permit = limiter.try_add_outbound(peer).ok_or_else(|| {
+            NodeError::ConnectionLimitReached("The first connection was refused".to_string())
+        })?;
+        let stream = OutboundStream::new(client, permit);
+        assert_eq!(limiter.len(), 1);
+        assert!(limiter.try_add_outbound(peer).is_none());
+
+        drop(stream);
+        let mut buffer = [0; 1];
+        assert_eq!(server.read(&mut buffer).unwrap_or(0), 0);
+        assert!(limiter.is_empty());
+        assert!(limiter.try_add_outbound(peer).is_some());
+        Ok(())
+    }
+}
@@ -15,4 +15,21 @@ pub enum MessageType {
     NotFound,
     Tx,
     GetData,
+    SendCmpct,
+    CFilter,
+    CFHeaders,
+    MerkleBlock,
+    /// A command the node doesn't know, with its name.
+    Unknown(String),
+}
+
+impl MessageType {
+    /// Returns the name of the message type: the name of its variant, or the command of an unknown
+    /// message. Lowercased, it's the command of the message.
+    pub fn name(&self) -> String {
+        match self {
+            MessageType::Unknown(command) => command.clone(),
+            message_type => format!("{:?}", message_type),
+        }
+    }
 }
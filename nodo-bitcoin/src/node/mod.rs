@@ -1,24 +1,40 @@
 pub mod block_header_downloader;
+pub mod connection_limiter;
 pub mod message_type;
 pub mod read;
 pub mod receive_messages;
 pub mod server;
 
 use crate::{
-    block::block_hash::BlockHash,
-    block_header::BlockHeader,
-    channels::wallet_channel::WalletChannel,
-    config::load_app_config,
+    block::{
+        block_filter::BlockFilter,
+        block_hash::{BlockHash, InternalHash},
+    },
+    block_header::{block_header_bytes::BlockHeaderBytes, BlockHeader},
+    channels::{
+        event_sink::{EventSink, NodeEvent},
+        wallet_channel::WalletChannel,
+    },
+    config::{load_app_config, obtain_dir_path},
     connectors::peer_connector::{receive_message, send_message},
-    constants::{BLOCKS_TO_SHOW, CONNECTION_TIMEOUT, LENGTH_HEADER_MESSAGE, MAX_RETRY_ATTEMPTS},
+    constants::{
+        BLOCKS_TO_SHOW, BLOCK_HEADERS_FILE, BLOCK_REQUEST_TIMEOUT, CONNECTION_TIMEOUT,
+        HANDSHAKE_TIMEOUT, LENGTH_BLOCK_HEADERS, MAX_DOWNLOAD_WORKERS, MAX_HANDSHAKE_MESSAGES,
+        MAX_LISTENER_WORKERS, MAX_RETRY_ATTEMPTS,
+    },
     header::Header,
     logger::Logger,
     messages::{
-        tx_message::TxMessage,
-        verack_message::{is_verack_message, VERACK_MESSAGE},
+        block_message::BlockMessage, feefilter_message::FeeFilterMessage,
+        filter_load_message::FilterLoadMessage, get_headers_message::GetHeadersMessage,
+        headers_message::HeadersMessage, tx_message::TxMessage, verack_message::VERACK_MESSAGE,
         version_message::VersionMessage,
     },
-    node::read::obtain_ips,
+    node::read::{
+        obtain_ips, retrieve_broadcast_peer_count, retrieve_headers_only,
+        retrieve_ibd_timeout_secs, retrieve_max_block_retries, retrieve_max_tx_size,
+        retrieve_max_workers, retrieve_min_relay_fee_rate, retrieve_peer_filters_checked,
+    },
     node_error::NodeError,
     node_pools::{
         block_downloader::BlockDownloader, block_downloader_pool::BlockDownloaderPool,
@@ -29,21 +45,27 @@ use crate::{
     utils::Utils,
     wallet::{node_wallet_message::NodeWalletMsg, wallet_impl::Wallet},
 };
-use bitcoin_hashes::{sha256d, Hash};
-use glib::Sender;
 
 use std::{
-    net::{SocketAddr, TcpStream},
+    collections::{HashSet, VecDeque},
+    fs,
+    io::Write,
+    net::{Shutdown, SocketAddr, TcpStream},
+    path::Path,
     sync::{
         mpsc::{self},
         Arc, Mutex,
     },
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use self::{
-    block_header_downloader::BlockHeaderDownloader, read::read_initial_block_headers_from_file,
+    block_header_downloader::BlockHeaderDownloader,
+    connection_limiter::{ConnectionLimiter, OutboundStream},
+    message_type::MessageType,
+    read::{read_initial_block_headers_from_file, read_last_block_header, repair_headers_file},
+    receive_messages::{fetch_compact_filters, request_block, send_pong_message},
     server::start_server,
 };
 
@@ -51,7 +73,8 @@ use self::{
 ///
 /// This function performs the handshake process with a peer node over a TCP connection.
 /// The handshake is a series of message exchanges that establish a connection and protocol version
-/// compatibility between the nodes.
+/// compatibility between the nodes. A peer that doesn't answer within `HANDSHAKE_TIMEOUT` seconds
+/// makes the handshake fail.
 ///
 /// # Arguments
 ///
@@ -67,21 +90,99 @@ pub fn handshake(
     stream: &mut TcpStream,
     logger: &Logger,
 ) -> Result<bool, NodeError> {
-    let version_message = VersionMessage::create_version_message(ip)?;
-    version_message.send_message(stream)?;
+    handshake_with_timeout(ip, stream, logger, Duration::from_secs(HANDSHAKE_TIMEOUT))
+}
 
-    let header = Header::new(stream)?;
-    let payload_size = header.payload_size();
-    receive_message(stream, payload_size)?;
-    logger.log("Received version message".to_string())?;
+/// Initiates a handshake with a peer node, waiting at most `timeout` for each of its messages.
+///
+/// The read timeout of the stream is restored to its previous value once the handshake ends.
+///
+/// # Arguments
+///
+/// * `ip` - A reference to the socket address of the peer node.
+/// * `stream` - A mutable reference to the TCP stream for communication with the peer node.
+/// * `logger` - A reference to the logger used to log events during the handshake process.
+/// * `timeout` - The maximum time to wait for each message of the peer.
+///
+/// # Errors
+///
+/// Returns a `NodeError::HandshakeFailed` if the peer doesn't answer in time, or an `Err(NodeError)`
+/// if any other error occurs during the handshake process.
+pub fn handshake_with_timeout(
+    ip: &SocketAddr,
+    stream: &mut TcpStream,
+    logger: &Logger,
+    timeout: Duration,
+) -> Result<bool, NodeError> {
+    let previous_timeout = stream
+        .read_timeout()
+        .map_err(|_| NodeError::ReadTimeoutFromStream("Failed to get read timeout".to_string()))?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(|_| NodeError::ReadTimeoutFromStream("Failed to set read timeout".to_string()))?;
 
-    let transmiting_ver_ack = VERACK_MESSAGE.to_vec();
+    let result = exchange_handshake_messages(ip, stream, logger).map_err(|e| match e {
+        NodeError::FailedToReceiveMessage(msg) => {
+            NodeError::HandshakeFailed(format!("No answer from {} during handshake: {}", ip, msg))
+        }
+        other => other,
+    });
 
-    send_message(stream, transmiting_ver_ack)?;
-    let verack_received = &receive_message(stream, LENGTH_HEADER_MESSAGE)?;
-    logger.log("Received verack message".to_string())?;
+    stream.set_read_timeout(previous_timeout).map_err(|_| {
+        NodeError::ReadTimeoutFromStream("Failed to restore read timeout".to_string())
+    })?;
+    result
+}
 
-    Ok(is_verack_message(verack_received))
+/// Exchanges the version and verack messages of the handshake with a peer node.
+///
+/// The version and verack of the peer are accepted in either order, and other messages sent before
+/// the handshake ends are skipped. Our verack is sent once the version of the peer is received.
+/// In headers-only mode, the filter of the wallet is loaded in the peer once the handshake ends, so
+/// it answers the filtered block requests.
+///
+/// # Returns
+///
+/// `true` once both the version and the verack of the peer are received, or `false` if they are
+/// not among its first MAX_HANDSHAKE_MESSAGES messages.
+fn exchange_handshake_messages(
+    ip: &SocketAddr,
+    stream: &mut TcpStream,
+    logger: &Logger,
+) -> Result<bool, NodeError> {
+    let version_message = VersionMessage::create_version_message(ip)?;
+    version_message.send_message(stream)?;
+
+    let (mut version_received, mut verack_received) = (false, false);
+    for _ in 0..MAX_HANDSHAKE_MESSAGES {
+        let mut header = Header::new(stream)?;
+        match header.extract_command_name()? {
+            MessageType::Version if !version_received => {
+                let payload = receive_message(stream, header.payload_size())?;
+                let services = VersionMessage::services_from_bytes(&payload)?;
+                FilterLoadMessage::record_peer_services(*ip, services);
+                logger.log("Received version message".to_string())?;
+                send_message(stream, VERACK_MESSAGE.to_vec())?;
+                version_received = true;
+            }
+            MessageType::Verack => {
+                logger.log("Received verack message".to_string())?;
+                verack_received = true;
+            }
+            _ => header.skip_payload(stream)?,
+        }
+        if version_received && verack_received {
+            if retrieve_headers_only() {
+                if FilterLoadMessage::is_served_by(ip) {
+                    FilterLoadMessage::wallet_filter().send_message(stream)?;
+                } else {
+                    logger.log(format!("{} does not serve bloom filters", ip))?;
+                }
+            }
+            return Ok(true);
+        }
+    }
+    Ok(false)
 }
 
 /// Initializes a connection with a node from the list of IP addresses.
@@ -94,7 +195,10 @@ pub fn handshake(
 ///
 /// Returns a `Result` containing the established `TcpStream` if successful, or an `Err` variant
 /// with a `NodeError` if an error occurs during the connection initialization.
-pub fn init_connection(ips: &Vec<SocketAddr>, logger: &Logger) -> Result<TcpStream, NodeError> {
+pub fn init_connection(
+    ips: &Vec<SocketAddr>,
+    logger: &Logger,
+) -> Result<OutboundStream, NodeError> {
     for ip in ips {
         if ip.is_ipv6() {
             continue;
@@ -117,7 +221,8 @@ pub fn init_connection(ips: &Vec<SocketAddr>, logger: &Logger) -> Result<TcpStre
 /// The function tries to connect to the provided IP address with a timeout. If the connection is successful,
 /// it performs a handshake with the remote node and returns a `TcpStream` if the handshake is successful. If an error
 /// occurs during the connection or the handshake, the function will retry the connection up to a maximum number of attempts.
-/// If the maximum number of attempts is reached, the function will return `None`.
+/// If the maximum number of attempts is reached, the function will return `None`. It also returns `None` without
+/// connecting if the node already has as many connections as MAX_CONNECTIONS allows.
 ///
 /// # Arguments
 ///
@@ -126,15 +231,25 @@ pub fn init_connection(ips: &Vec<SocketAddr>, logger: &Logger) -> Result<TcpStre
 ///
 /// # Returns
 ///
-/// Returns an `Option<TcpStream>` containing the established `TcpStream` if the connection and handshake are successful.
+/// Returns an `Option<OutboundStream>` containing the established connection, which keeps its place in the connection limiter while it's open, if the connection and handshake are successful.
 /// Otherwise, it returns `None` if there was an error during connection or if the handshake fails.
-pub fn connect_to_ip(ip: &SocketAddr, logger: &Logger) -> Option<TcpStream> {
+pub fn connect_to_ip(ip: &SocketAddr, logger: &Logger) -> Option<OutboundStream> {
+    let permit = match ConnectionLimiter::global().try_add_outbound(*ip) {
+        Some(permit) => permit,
+        None => {
+            println!(
+                "Connection limit reached, not connecting to node with ip: {}",
+                ip
+            );
+            return None;
+        }
+    };
     for attempt in 1..=MAX_RETRY_ATTEMPTS {
         let stream = TcpStream::connect_timeout(ip, Duration::from_secs(CONNECTION_TIMEOUT));
         match stream {
             Ok(mut stream) => {
                 if handshake_if_ok(ip, &mut stream, logger, attempt) {
-                    return Some(stream);
+                    return Some(OutboundStream::new(stream, permit));
                 }
             }
             Err(e) => {
@@ -214,22 +329,26 @@ fn is_connection_ok(stream: &TcpStream, ip: &SocketAddr) -> bool {
 }
 
 /// Downloads the initial block headers from a remote peer and returns them as a vector.
+/// Unless the node runs in headers-only mode, the hashes of the blocks are queued for download.
 ///
 /// # Arguments
 ///
 /// * `stream` - A `TcpStream` representing the network connection to the peer.
 /// * `sender` - A reference to an `mpsc::Sender<[u8; 32]>` for sending the hashes to the queue.
+/// * `deadline` - The instant the headers must be downloaded by.
 /// * `logger` - A logger to log the messages received.
-/// * `ui_sender` - A `glib::Sender<UIMessage>` for sending messages to the UI.
+/// * `events` - The sink the events of the node are emitted to, such as the UI.
 ///
 /// # Returns
 ///
 /// Returns a `Result` containing a vector of `BlockHeader` if the download is successful, or an
-/// `Err` variant with a `NodeError` if an error occurs during the download process.
+/// `Err` variant with a `NodeError` if an error occurs during the download process, such as a
+/// `NodeError::IbdTimeout` if the deadline passes.
 pub fn initial_block_headers_download(
     stream: &mut TcpStream,
     sender: &mpsc::Sender<BlockHash>,
-    ui_sender: &glib::Sender<UIMessage>,
+    deadline: Instant,
+    events: &impl EventSink,
     logger: &Logger,
 ) -> Result<Vec<BlockHeader>, NodeError> {
     let mut block_header_downloader = match BlockHeaderDownloader::new(stream) {
@@ -242,16 +361,75 @@ pub fn initial_block_headers_download(
         }
     };
 
-    block_header_downloader.start(logger, ui_sender)?;
+    let repaired_bytes = repair_headers_file(&obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?)?;
+    if repaired_bytes > 0 {
+        logger.log(format!(
+            "Removed a partial header of {} bytes from the headers file",
+            repaired_bytes
+        ))?;
+    }
+    block_header_downloader.start(deadline, logger, events)?;
     let header_blocks = read_initial_block_headers_from_file()?;
 
-    queue_hashes(&header_blocks, sender)?;
+    if retrieve_headers_only() {
+        logger.log(format!(
+            "Headers-only mode, skipping the download of {} blocks",
+            header_blocks.len()
+        ))?;
+        return Ok(header_blocks);
+    }
+    let skipped = queue_hashes(&header_blocks, sender)?;
+    logger.log(format!(
+        "Skipped {} block hashes already queued or downloaded",
+        skipped
+    ))?;
 
     Ok(header_blocks)
 }
 
+/// Checks if the node is synced with a peer, asking it for the headers after the last saved one.
+///
+/// # Arguments
+///
+/// * `stream` - A `TcpStream` representing the network connection to the peer.
+///
+/// # Returns
+///
+/// `true` if the peer has no headers after ours, that is, if the node is at the tip of its chain.
+///
+/// # Errors
+///
+/// Returns a `NodeError` if the last header could not be read or the messages could not be exchanged.
+pub fn is_synced(stream: &mut TcpStream) -> Result<bool, NodeError> {
+    is_synced_from(stream, &read_last_block_header()?)
+}
+
+/// Checks if a peer has headers after the given one. Pings received while waiting for the headers
+/// are answered, and other messages are skipped.
+fn is_synced_from(
+    stream: &mut TcpStream,
+    last_block_header: &BlockHeaderBytes,
+) -> Result<bool, NodeError> {
+    GetHeadersMessage::send_message(stream, last_block_header)?;
+    loop {
+        let mut header = Header::new(stream)?;
+        match header.extract_command_name()? {
+            MessageType::Headers => {
+                let payload = receive_message(stream, header.payload_size())?;
+                let headers_count = HeadersMessage::get_headers_count(&mut payload.as_slice())?;
+                return Ok(headers_count == 0);
+            }
+            MessageType::Ping => send_pong_message(stream, &header)?,
+            _ => header.skip_payload(stream)?,
+        }
+    }
+}
+
 /// Queues the hashes of block headers for sending through a channel.
 ///
+/// Hashes that were already queued (the headers file might contain duplicates after an interrupted
+/// sync) and blocks that are already saved on disk are skipped.
+///
 /// # Arguments
 ///
 /// * `header_blocks` - A vector of `BlockHeader` objects representing the block headers.
@@ -259,7 +437,7 @@ pub fn initial_block_headers_download(
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if the hashes are successfully queued,
+/// Returns the number of skipped hashes if the rest are successfully queued,
 /// or a `NodeError` on failure.
 ///
 /// # Errors
@@ -268,65 +446,170 @@ pub fn initial_block_headers_download(
 fn queue_hashes(
     header_blocks: &[BlockHeader],
     sender: &mpsc::Sender<BlockHash>,
-) -> Result<(), NodeError> {
-    let mut i = 0;
+) -> Result<usize, NodeError> {
+    let mut queued_hashes = HashSet::new();
+    let mut queued = 0;
+    let mut skipped = 0;
     for block in header_blocks.iter() {
-        let hash = sha256d::Hash::hash(&block.to_bytes()).to_byte_array();
-        i += 1;
+        let hash = block.block_hash();
+        if !queued_hashes.insert(hash) || is_block_on_disk(block) {
+            skipped += 1;
+            continue;
+        }
         sender.send(hash).map_err(|_| {
             NodeError::FailedToSendHash("Failed to send hash through channel".to_string())
         })?;
+        queued += 1;
     }
-    println!("Queued {} hashes", i);
-    Ok(())
+    println!(
+        "Queued {} hashes, skipped {} already queued or downloaded",
+        queued, skipped
+    );
+    Ok(skipped)
+}
+
+/// Returns true if the block with the given header is already saved in the blocks directory.
+fn is_block_on_disk(block_header: &BlockHeader) -> bool {
+    BlockMessage::block_path_for_header(block_header).is_some_and(|path| Path::new(&path).exists())
 }
 
 /// Downloads the blocks that could not be downloaded during the initial block download.
 /// Will try to download the blocks from other connections if it is not in one peer.
+/// Each block is retried up to MAX_BLOCK_RETRIES times, the blocks that still fail are reported to the UI
+/// and the node continues without them.
 ///
 /// # Arguments
 ///
 /// * `failed_receiver` - A reference to an `mpsc::Receiver<[u8; 32]>` for receiving the hashes of the failed blocks.
 /// * `connections` - A mutable reference to a vector of `TcpStream` representing the network connections to the peers.
+/// * `deadline` - The instant the retries must finish by.
 /// * `logger` - A reference to a `Logger` for logging messages.
+/// * `events` - The sink the events of the node are emitted to, such as the UI.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if the blocks are successfully downloaded,
-/// or a `NodeError` on failure.
+/// Returns `Ok(())` once every failed block was downloaded or ran out of retries.
 pub fn retry_failed_blocks(
     failed_receiver: &mpsc::Receiver<BlockHash>,
-    connections: &mut [TcpStream],
+    connections: &mut [OutboundStream],
+    deadline: Instant,
     logger: &Logger,
-    ui_sender: &glib::Sender<UIMessage>,
+    events: &impl EventSink,
 ) -> Result<(), NodeError> {
-    loop {
-        match failed_receiver.recv() {
-            Ok(hash) => {
-                println!("Retrying failed block with hash: {:?}", hash);
-                if !retry_download(
-                    connections,
-                    hash,
-                    Arc::new(Mutex::new(logger.clone())),
-                    ui_sender,
-                ) {
-                    return Err(NodeError::FailedToDownloadBlock(
-                        "Failed to download block from all connections".to_string(),
-                    ));
-                }
-            }
-            Err(_) => {
-                println!("Finished retrying failed blocks");
-                break;
-            }
-        }
+    let failed_hashes: Vec<BlockHash> = failed_receiver.iter().collect();
+    let logger = Arc::new(Mutex::new(logger.clone()));
+
+    let unrecovered_hashes = retry_with_limit(
+        failed_hashes,
+        retrieve_max_block_retries(),
+        deadline,
+        |hash| {
+            println!("Retrying failed block with hash: {:?}", hash);
+            retry_download(connections, hash, Arc::clone(&logger), events)
+        },
+    )?;
+    println!("Finished retrying failed blocks");
+
+    for hash in unrecovered_hashes {
+        events
+            .emit(NodeEvent::Notification(format!(
+                "Failed to download block {}",
+                InternalHash::new(hash).to_display()
+            )))
+            .unwrap_or_else(|_| println!("Failed to send failed block to ui"));
     }
 
     Ok(())
 }
 
+/// Retries the given block hashes in passes, re-queueing the ones that fail until they reach the max retries.
+///
+/// # Arguments
+///
+/// * `hashes` - The hashes of the blocks to download.
+/// * `max_retries` - The maximum number of download attempts for each block.
+/// * `deadline` - The instant the retries must finish by, no download is started past it.
+/// * `download` - The function that downloads a block, returning `true` on success.
+///
+/// # Returns
+///
+/// Returns the hashes of the blocks that could not be downloaded after `max_retries` attempts.
+///
+/// # Errors
+///
+/// Returns a `NodeError::IbdTimeout` if the deadline passes before every block was retried.
+fn retry_with_limit<F: FnMut(BlockHash) -> bool>(
+    hashes: Vec<BlockHash>,
+    max_retries: usize,
+    deadline: Instant,
+    mut download: F,
+) -> Result<Vec<BlockHash>, NodeError> {
+    let mut pending: VecDeque<(BlockHash, usize)> = hashes.into_iter().map(|h| (h, 0)).collect();
+    let mut unrecovered_hashes = Vec::new();
+
+    while let Some((hash, attempts)) = pending.pop_front() {
+        time_left(deadline)?;
+        if download(hash) {
+            continue;
+        }
+        if attempts + 1 < max_retries {
+            pending.push_back((hash, attempts + 1));
+        } else {
+            println!("Giving up block with hash: {:?}", hash);
+            unrecovered_hashes.push(hash);
+        }
+    }
+
+    Ok(unrecovered_hashes)
+}
+
+/// Asks the peers for a block in order, until one of them has it.
+///
+/// Peers that reply with a `notfound` message, don't answer within BLOCK_REQUEST_TIMEOUT, or whose
+/// connection fails, are considered not to have it. Peers usually don't reply to requests of blocks
+/// they don't have, so the wait is kept short. The read timeout of each connection is restored
+/// after asking.
+///
+/// # Arguments
+///
+/// * `connections` - The connections to the peers.
+/// * `hash` - The hash of the block.
+///
+/// # Returns
+///
+/// The index in `connections` of the first peer that has the block and the block it sent, or `None`
+/// if no peer has it.
+pub fn first_peer_with_block(
+    connections: &mut [OutboundStream],
+    hash: BlockHash,
+) -> Option<(usize, Vec<u8>)> {
+    for (index, conn) in connections.iter_mut().enumerate() {
+        let read_timeout = conn.read_timeout().unwrap_or(None);
+        if conn
+            .set_read_timeout(Some(Duration::from_secs(BLOCK_REQUEST_TIMEOUT)))
+            .is_err()
+        {
+            continue;
+        }
+        let block = match request_block(hash, conn) {
+            Ok(block) => block,
+            Err(err) => {
+                println!("Failed to ask a peer for the block: {:?}", err);
+                None
+            }
+        };
+        conn.set_read_timeout(read_timeout).unwrap_or_else(|_| {
+            println!("Failed to restore the read timeout of the connection");
+        });
+        if let Some(block) = block {
+            return Some((index, block));
+        }
+    }
+    None
+}
+
 /// Downloads the blocks that could not be downloaded during the initial block download.
-/// Will try to download the blocks from other connections if it is not in one peer.
+/// The peers are asked for the block in order, and the first one that has it sends it.
 ///
 /// # Arguments
 ///
@@ -339,40 +622,51 @@ pub fn retry_failed_blocks(
 /// Returns `true` if the block is successfully downloaded,
 /// or `false` on failure.
 fn retry_download(
-    connections: &mut [TcpStream],
+    connections: &mut [OutboundStream],
     hash: BlockHash,
     logger: Arc<Mutex<Logger>>,
-    ui_sender: &glib::Sender<UIMessage>,
+    events: &impl EventSink,
 ) -> bool {
-    let mut success = false;
-    for conn in connections.iter_mut() {
-        if let Err(err) =
-            BlockDownloader::download_block(hash, conn, 0, None, &mut 0, &logger, ui_sender)
-        {
-            println!("Failed to download block from connection: {:?}", err);
-            continue;
+    let block = match first_peer_with_block(connections, hash) {
+        Some((_, block)) => block,
+        None => {
+            println!("No peer has the block with hash: {:?}", hash);
+            return false;
         }
+    };
 
-        success = true;
-        break;
+    match BlockDownloader::save_downloaded_block(hash, block, 0, &logger, events) {
+        Ok(()) => true,
+        Err(err) => {
+            println!("Failed to save the block downloaded from a peer: {:?}", err);
+            false
+        }
     }
-    success
 }
 
 /// Starts the server for the node.
-pub fn run_server() -> JoinHandle<()> {
-    thread::spawn(move || match start_server() {
+///
+/// # Arguments
+///
+/// * `logger` - A `Logger` for logging the messages of the clients.
+pub fn run_server(logger: Logger) -> JoinHandle<()> {
+    thread::spawn(move || match start_server(logger) {
         Ok(_) => println!("Server finished"),
         Err(_) => println!("Server failed"),
     })
 }
 
+/// The channels of the wallet with the GTK UI: the sender of the messages to the UI and the
+/// receiver of the messages from it.
+pub type WalletUi = (glib::Sender<UIMessage>, mpsc::Receiver<UIMessage>);
+
 /// Runs the Bitcoin node
 ///
 /// # Arguments
 ///
-/// * `ui_sender` - A `glib::Sender<UIMessage>` for sending messages to the UI.
-/// * `wallet_ui_receiver` - A `mpsc::Receiver<UIMessage>` for receiving messages from the UI.
+/// * `events` - The sink the events of the node are emitted to, such as the GTK UI or a plain
+///   `mpsc::Sender<NodeEvent>` for a headless consumer.
+/// * `wallet_ui` - The channels of the wallet with the UI, or `None` to run the node without a wallet.
 ///
 /// # Returns
 ///
@@ -380,25 +674,41 @@ pub fn run_server() -> JoinHandle<()> {
 ///
 /// # Errors
 ///
-/// Returns a NodeError if an error occurs while running the node.
-pub fn run_node(
-    ui_sender: glib::Sender<UIMessage>,
-    wallet_ui_receiver: mpsc::Receiver<UIMessage>,
+/// Returns a NodeError if an error occurs while running the node. If the initial block download times
+/// out, a notification is emitted so the user can retry.
+pub fn run_node<E: EventSink + Clone + Send + Sync + 'static>(
+    events: E,
+    wallet_ui: Option<WalletUi>,
 ) -> Result<(), NodeError> {
-    let (logger, stream, block_downloader_pool, ips) = initialize_node(&ui_sender)?;
+    let (logger, stream, block_downloader_pool, ips) = initialize_node(&events)?;
+    let logger_guard = logger.shutdown_guard();
 
-    let (initial_block_headers, connections, _stream) =
-        download_headers_and_blocks(block_downloader_pool, stream, ips, &ui_sender, &logger)?;
+    let (initial_block_headers, connections, mut stream) =
+        download_headers_and_blocks(block_downloader_pool, stream, ips, &events, &logger)
+            .inspect_err(|e| {
+                if let NodeError::IbdTimeout(msg) = e {
+                    events
+                        .emit(NodeEvent::Notification(format!(
+                            "{}, restart the node to retry",
+                            msg
+                        )))
+                        .unwrap_or_else(|_| println!("Failed to send the IBD timeout to ui"));
+                }
+            })?;
+
+    notify_sync_state(&mut stream, &events, &logger)?;
 
-    let thread_server = run_server();
+    let thread_server = run_server(logger.clone());
 
-    broadcast(
+    let broadcast_result = broadcast(
         initial_block_headers,
         connections,
-        ui_sender,
-        wallet_ui_receiver,
+        events,
+        wallet_ui,
         logger,
-    )?;
+    );
+    drop(logger_guard);
+    broadcast_result?;
 
     thread_server
         .join()
@@ -406,14 +716,47 @@ pub fn run_node(
     Ok(())
 }
 
+/// Checks if the node is synced with the peer it downloaded the headers from and notifies the result,
+/// so the user knows whether the broadcast starts at the tip of the chain.
+///
+/// # Arguments
+///
+/// * `stream` - The connection to the peer the headers were downloaded from.
+/// * `events` - The sink the notification is emitted to, such as the UI.
+/// * `logger` - A `Logger` for logging messages.
+///
+/// # Errors
+///
+/// Returns a `NodeError` if the result could not be logged. A failed check is only logged.
+fn notify_sync_state(
+    stream: &mut TcpStream,
+    events: &impl EventSink,
+    logger: &Logger,
+) -> Result<(), NodeError> {
+    let message = match is_synced(stream) {
+        Ok(true) => "The node is synced with its peers".to_string(),
+        Ok(false) => "The node is behind its peers, new blocks will be received".to_string(),
+        Err(e) => {
+            logger.log(format!("Failed to check if the node is synced: {:?}", e))?;
+            return Ok(());
+        }
+    };
+    logger.log(message.clone())?;
+    events
+        .emit(NodeEvent::Notification(message))
+        .unwrap_or_else(|_| println!("Failed to send the sync state to ui"));
+    Ok(())
+}
+
 /// Starts the block and transaction broadcasting.
 ///
 /// #Arguments
 ///
 /// * `initial_block_headers` - A vector of `BlockHeader` objects representing the block headers.
 /// * `connections` - A vector of `TcpStream` representing the network connections to the peers.
-/// * `ui_sender` - A `glib::Sender<UIMessage>` for sending messages to the UI.
-/// * `wallet_ui_receiver` - A `mpsc::Receiver<UIMessage>` for receiving messages from the UI.
+/// * `events` - The sink the events of the node are emitted to, such as the UI.
+/// * `wallet_ui` - The channels of the wallet with the UI, or `None` to run the node without a
+///   wallet. Without one, the messages of the node to the wallet are discarded.
 /// * `logger` - A `Logger` for logging messages.
 ///
 /// # Returns
@@ -425,14 +768,26 @@ pub fn run_node(
 /// Returns a `NodeError` if an error occurs while broadcasting the blocks.
 fn broadcast(
     initial_block_headers: Vec<BlockHeader>,
-    connections: Vec<TcpStream>,
-    ui_sender: Sender<UIMessage>,
-    wallet_ui_receiver: mpsc::Receiver<UIMessage>,
+    connections: Vec<OutboundStream>,
+    events: impl EventSink + Clone + Send + 'static,
+    wallet_ui: Option<WalletUi>,
     logger: Logger,
 ) -> Result<(), NodeError> {
-    let utxo_set = UtxoSet::new_from_block_headers(initial_block_headers)?;
+    let (utxo_set, local_filters) =
+        build_utxo_set_and_tip_filters(initial_block_headers, retrieve_peer_filters_checked())?;
+    let mut connections =
+        keep_listened_connections(connections, retrieve_max_workers(MAX_LISTENER_WORKERS));
+    if let Some((start_height, local_filters)) = local_filters {
+        if let Err(e) = check_peer_filters(&mut connections, start_height, &local_filters, &logger)
+        {
+            logger.log(format!(
+                "Failed to check the compact filters of a peer: {:?}",
+                e
+            ))?;
+        }
+    }
     let (wallet_channel, node_wallet_channel) = WalletChannel::create_pairs();
-    let mut connection_to_peer = find_one_active_peer(&connections)?;
+    let mut connections_to_peers = clone_active_peers(&connections)?;
 
     let utxo_set_arc = Arc::new(Mutex::new(utxo_set));
 
@@ -440,22 +795,24 @@ fn broadcast(
         connections.len(),
         &connections,
         Arc::clone(&utxo_set_arc),
-        ui_sender.clone(),
+        events,
         node_wallet_channel,
-        logger,
+        logger.clone(),
     )?;
 
-    let thread_wallet = thread::spawn(move || {
-        match Wallet::run_wallet(
+    let thread_wallet = thread::spawn(move || match wallet_ui {
+        Some((ui_sender, wallet_ui_receiver)) => match Wallet::run_wallet(
             Arc::clone(&utxo_set_arc),
             wallet_channel,
             wallet_ui_receiver,
             ui_sender,
-            &mut connection_to_peer,
+            &mut connections_to_peers,
+            logger,
         ) {
             Ok(_) => println!("Wallet finished"),
             Err(_) => println!("Wallet failed"),
-        }
+        },
+        None => while wallet_channel.receive().is_ok() {},
     });
 
     broadcasting_pool.join()?;
@@ -466,40 +823,232 @@ fn broadcast(
     Ok(())
 }
 
-/// Finds one active peer to send to the wallet.
+/// The height of the first block of a range, and the hash and the compact filter of each block of it.
+/// The filter is `None` for the blocks that spend outputs older than the UTXO set.
+type RangeFilters = (u32, Vec<(BlockHash, Option<BlockFilter>)>);
+
+/// Builds the UTXO set from the block headers, and the compact filters (BIP158) of the blocks at the
+/// tip of the chain from the blocks on disk, before the UTXO set is updated with them.
+///
+/// # Arguments
+///
+/// * `block_headers` - The block headers, ending at the tip of the chain.
+/// * `filters_count` - The number of blocks at the tip whose filters are built.
+///
+/// # Returns
+///
+/// The UTXO set and, if all those blocks could be read, the height of the first of them along with
+/// the hash and the filter of each block.
+///
+/// # Errors
+///
+/// Returns a `NodeError` if the UTXO set could not be built or the headers file could not be read.
+fn build_utxo_set_and_tip_filters(
+    block_headers: Vec<BlockHeader>,
+    filters_count: usize,
+) -> Result<(UtxoSet, Option<RangeFilters>), NodeError> {
+    let first_filtered = block_headers.len().saturating_sub(filters_count);
+    let filtered_hashes: Vec<BlockHash> = block_headers[first_filtered..]
+        .iter()
+        .map(BlockHeader::block_hash)
+        .collect();
+    let mut local_filters = Vec::with_capacity(filtered_hashes.len());
+    let utxo_set = UtxoSet::new_from_block_headers_inspecting(
+        block_headers,
+        |position, block_path, utxo_set| {
+            if position < first_filtered {
+                return;
+            }
+            match BlockFilter::from_block_file(block_path, utxo_set) {
+                Ok(filter) => {
+                    local_filters.push((filtered_hashes[position - first_filtered], filter))
+                }
+                Err(e) => println!("Failed to build the filter of {}: {:?}", block_path, e),
+            }
+        },
+    )?;
+
+    if filtered_hashes.is_empty() || local_filters.len() != filtered_hashes.len() {
+        return Ok((utxo_set, None));
+    }
+    let headers_count = fs::metadata(obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?)
+        .map_err(|_| NodeError::FailedToRead("Failed to read the headers file".to_string()))?
+        .len()
+        / LENGTH_BLOCK_HEADERS as u64;
+    let start_height = headers_count.saturating_sub(filtered_hashes.len() as u64) as u32;
+    Ok((utxo_set, Some((start_height, local_filters))))
+}
+
+/// Checks the compact filters (BIP157) a peer serves for a range of blocks against the filters built
+/// from the blocks. Peers that don't send the filters in time are skipped, and the check ends with the
+/// first peer that sends them.
+///
+/// # Arguments
+///
+/// * `connections` - The connections to the peers, which are not being listened to yet.
+/// * `start_height` - The height of the first block of the range.
+/// * `local_filters` - The hash and the filter built from each block of the range, in order. The
+///   blocks without a filter are not checked.
+/// * `logger` - A reference to a `Logger` for logging messages.
+///
+/// # Errors
+///
+/// Returns a `NodeError::FilterMismatch` if a filter of the peer doesn't match the one built from the
+/// block, a `NodeError::InvalidFilterHeader` if it doesn't match the filter headers of the peer, or a
+/// `NodeError` if the read timeout of a connection could not be changed.
+fn check_peer_filters(
+    connections: &mut [OutboundStream],
+    start_height: u32,
+    local_filters: &[(BlockHash, Option<BlockFilter>)],
+    logger: &Logger,
+) -> Result<(), NodeError> {
+    let built_filters = local_filters
+        .iter()
+        .filter(|(_, local_filter)| local_filter.is_some())
+        .count();
+    if built_filters == 0 {
+        return logger.log(
+            "The compact filters of the last blocks can't be checked, they spend outputs older than the UTXO set"
+                .to_string(),
+        );
+    }
+    for stream in connections.iter_mut() {
+        let previous_timeout = stream.read_timeout().map_err(|_| {
+            NodeError::ReadTimeoutFromStream("Failed to get read timeout".to_string())
+        })?;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(CONNECTION_TIMEOUT)))
+            .map_err(|_| {
+                NodeError::ReadTimeoutFromStream("Failed to set read timeout".to_string())
+            })?;
+        let result = compare_peer_filters(stream, start_height, local_filters);
+        stream.set_read_timeout(previous_timeout).map_err(|_| {
+            NodeError::ReadTimeoutFromStream("Failed to restore read timeout".to_string())
+        })?;
+
+        match result {
+            Ok(()) => {
+                logger.log(format!(
+                    "The compact filters of {} of the last {} blocks match the ones of a peer",
+                    built_filters,
+                    local_filters.len()
+                ))?;
+                return Ok(());
+            }
+            Err(e @ (NodeError::FilterMismatch(_) | NodeError::InvalidFilterHeader(_))) => {
+                return Err(e)
+            }
+            Err(e) => logger.log(format!("A peer didn't send the compact filters: {:?}", e))?,
+        }
+    }
+    logger.log("No peer sent the compact filters of the last blocks".to_string())
+}
+
+/// Requests the compact filters of a range of blocks from a peer and compares them with the filters
+/// built from the blocks. The blocks without a filter are skipped.
+///
+/// # Errors
+///
+/// Returns a `NodeError::FilterMismatch` if a filter of the peer doesn't match the one built from the
+/// block, or the error of `fetch_compact_filters`.
+fn compare_peer_filters(
+    stream: &mut TcpStream,
+    start_height: u32,
+    local_filters: &[(BlockHash, Option<BlockFilter>)],
+) -> Result<(), NodeError> {
+    let block_hashes: Vec<BlockHash> = local_filters
+        .iter()
+        .map(|(block_hash, _)| *block_hash)
+        .collect();
+    let cfilters = fetch_compact_filters(stream, start_height, &block_hashes)?;
+    for (cfilter, (block_hash, local_filter)) in cfilters.iter().zip(local_filters) {
+        let local_filter = match local_filter {
+            Some(local_filter) => local_filter,
+            None => continue,
+        };
+        if cfilter.to_block_filter()? != *local_filter {
+            return Err(NodeError::FilterMismatch(format!(
+                "The filter of the block {} doesn't match the one built from the block",
+                InternalHash::new(*block_hash).to_display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the first `max_listeners` active connections and shuts down the rest, so that no peer is left with
+/// a connection nobody reads from. The block downloaders connect to every peer before connecting to one
+/// again, so the connections kept reach as many distinct peers as possible.
 ///
 /// # Arguments
 ///
 /// * `connections` - A vector of `TcpStream` representing the network connections to the peers.
+/// * `max_listeners` - The maximum number of connections to listen to.
 ///
 /// # Returns
 ///
-/// Returns a `TcpStream` representing the connection to the peer.
+/// Returns the connections to listen to.
+fn keep_listened_connections(
+    connections: Vec<OutboundStream>,
+    max_listeners: usize,
+) -> Vec<OutboundStream> {
+    let mut listened = Vec::with_capacity(max_listeners);
+    for connection in connections
+        .into_iter()
+        .filter(|connection| Utils::is_tcpstream_connected(connection))
+    {
+        if listened.len() < max_listeners {
+            listened.push(connection);
+        } else if let Err(e) = connection.shutdown(Shutdown::Both) {
+            println!("Failed to close the connection to a peer: {:?}", e);
+        }
+    }
+    listened
+}
+
+/// Clones the connections to the active peers, to send them to the wallet.
+///
+/// # Arguments
+///
+/// * `connections` - A vector of `TcpStream` representing the network connections to the peers.
+///
+/// # Returns
+///
+/// Returns the `TcpStream` connections to the active peers.
 ///
 /// # Errors
 ///
-/// Returns a `NodeError` if an error occurs while finding a peer.
-fn find_one_active_peer(connections: &[TcpStream]) -> Result<TcpStream, NodeError> {
-    let connection_to_peer = connections
+/// Returns a `NodeError` if there is no active peer or a connection could not be cloned.
+fn clone_active_peers(connections: &[OutboundStream]) -> Result<Vec<TcpStream>, NodeError> {
+    let connections_to_peers = connections
         .iter()
-        .find(|conn| Utils::is_tcpstream_connected(conn))
-        .ok_or(NodeError::FailedToConnect(
+        .filter(|conn| Utils::is_tcpstream_connected(conn))
+        .map(|conn| {
+            conn.try_clone()
+                .map_err(|_| NodeError::FailedToConnect("Failed to clone peer".to_string()))
+        })
+        .collect::<Result<Vec<TcpStream>, NodeError>>()?;
+    if connections_to_peers.is_empty() {
+        return Err(NodeError::FailedToConnect(
             "Failed to get a peer to send to wallet".to_string(),
-        ))?
-        .try_clone()
-        .map_err(|_| NodeError::FailedToConnect("Failed to clone peer".to_string()))?;
-    Ok(connection_to_peer)
+        ));
+    }
+    Ok(connections_to_peers)
 }
 
 /// Downloads all the block headers and blocks from the network from the config timestamp to now.
 /// If blocks or headers are already downloaded, it will not download them again.
 ///
+/// The whole download, headers and retries of failed blocks included, must finish in
+/// IBD_TIMEOUT_SECS, so the node doesn't hang forever on a peer that stops answering or a block no
+/// peer serves. If it doesn't, the block downloaders are stopped.
+///
 /// # Arguments
 ///
 /// * `block_downloaders_pool` - A `BlockDownloaderPool` for downloading the blocks.
 /// * `stream` - A `TcpStream` representing the network connection to the peer.
 /// * `logger` - A reference to a `Logger` for logging messages.
-/// * `ui_sender` - A `glib::Sender<UIMessage>` for sending messages to the UI.
+/// * `events` - The sink the events of the node are emitted to, such as the UI.
 ///
 /// # Returns
 ///
@@ -507,15 +1056,17 @@ fn find_one_active_peer(connections: &[TcpStream]) -> Result<TcpStream, NodeErro
 ///
 /// # Errors
 ///
-/// Returns a `NodeError` if an error occurs while downloading the blocks.
+/// Returns a `NodeError::IbdTimeout` with the number of blocks still missing if the download takes
+/// longer than IBD_TIMEOUT_SECS, or a `NodeError` if an error occurs while downloading the blocks.
 fn download_headers_and_blocks(
     mut block_downloader_pool: BlockDownloaderPool,
-    stream: TcpStream,
+    stream: OutboundStream,
     ips: Vec<SocketAddr>,
-    ui_sender: &glib::Sender<UIMessage>,
+    events: &impl EventSink,
     logger: &Logger,
-) -> Result<(Vec<BlockHeader>, Vec<TcpStream>, TcpStream), NodeError> {
+) -> Result<(Vec<BlockHeader>, Vec<OutboundStream>, OutboundStream), NodeError> {
     println!("Downloading headers and blocks");
+    let deadline = Instant::now() + Duration::from_secs(retrieve_ibd_timeout_secs());
     let sender = match block_downloader_pool.hash_sender.take() {
         Some(s) => s,
         None => Err(NodeError::FailedToSendMessage(
@@ -524,20 +1075,64 @@ fn download_headers_and_blocks(
     };
 
     let (initial_block_headers, stream) =
-        ibh_download_or_retry_connection(ips, stream, sender, ui_sender, logger)?;
+        match ibh_download_or_retry_connection(ips, stream, sender, deadline, events, logger) {
+            Ok(downloaded) => downloaded,
+            Err(e) => {
+                block_downloader_pool.stop();
+                return Err(e);
+            }
+        };
 
-    send_block_headers_to_ui(ui_sender, &initial_block_headers)?;
+    send_block_headers_to_ui(events, &initial_block_headers)?;
 
     block_downloader_pool.close_channel();
-    let (mut connections, failed_receiver) = block_downloader_pool.join()?;
+    let (mut connections, failed_receiver) =
+        block_downloader_pool
+            .join_before(deadline)
+            .map_err(|e| match e {
+                NodeError::IbdTimeout(_) => ibd_timeout_error(&initial_block_headers),
+                other => other,
+            })?;
 
     match failed_receiver {
-        Some(receiver) => retry_failed_blocks(&receiver, &mut connections, logger, ui_sender)?,
+        Some(receiver) => {
+            retry_failed_blocks(&receiver, &mut connections, deadline, logger, events).map_err(
+                |e| match e {
+                    NodeError::IbdTimeout(_) => ibd_timeout_error(&initial_block_headers),
+                    other => other,
+                },
+            )?
+        }
         None => println!("No failed blocks found"),
     };
     Ok((initial_block_headers, connections, stream))
 }
 
+/// Returns the time left until the deadline of the initial block download.
+///
+/// # Errors
+///
+/// Returns a `NodeError::IbdTimeout` if the deadline already passed.
+fn time_left(deadline: Instant) -> Result<Duration, NodeError> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|time_left| !time_left.is_zero())
+        .ok_or_else(|| NodeError::IbdTimeout("The initial block download timed out".to_string()))
+}
+
+/// Returns the error of an initial block download that timed out, with the number of blocks of the
+/// headers that are not on disk yet.
+fn ibd_timeout_error(block_headers: &[BlockHeader]) -> NodeError {
+    let missing_blocks = block_headers
+        .iter()
+        .filter(|block_header| !is_block_on_disk(block_header))
+        .count();
+    NodeError::IbdTimeout(format!(
+        "The initial block download timed out with {} blocks missing",
+        missing_blocks
+    ))
+}
+
 /// Attempts to download initial block headers (IBH) from multiple IP addresses or retries connections if needed.
 ///
 /// The function tries to download initial block headers from a list of IP addresses. If the download is successful,
@@ -549,8 +1144,9 @@ fn download_headers_and_blocks(
 /// * `ips` - A mutable vector of `SocketAddr` representing the list of IP addresses to attempt the download.
 /// * `stream` - A mutable `TcpStream` representing the established TCP connection.
 /// * `sender` - A `mpsc::Sender` for sending data to another thread (not directly used in this function).
+/// * `deadline` - The instant the headers must be downloaded by, no other IP is tried past it.
 /// * `logger` - A reference to a `Logger` instance used for logging purposes.
-/// * `ui_sender` - A `glib::Sender<UIMessage>` for sending messages to the UI.
+/// * `events` - The sink the events of the node are emitted to, such as the UI.
 ///
 /// # Returns
 ///
@@ -558,30 +1154,36 @@ fn download_headers_and_blocks(
 ///
 /// # Errors
 ///
-/// The function can return a `NodeError::FailedToConnect` if it exhausts all available IP addresses and cannot establish a successful connection.
+/// The function can return a `NodeError::FailedToConnect` if it exhausts all available IP addresses and cannot establish a successful connection,
+/// or a `NodeError::IbdTimeout` if the deadline passes.
 fn ibh_download_or_retry_connection(
     mut ips: Vec<SocketAddr>,
-    mut stream: TcpStream,
+    mut stream: OutboundStream,
     sender: mpsc::Sender<[u8; 32]>,
-    ui_sender: &glib::Sender<UIMessage>,
+    deadline: Instant,
+    events: &impl EventSink,
     logger: &Logger,
-) -> Result<(Vec<BlockHeader>, TcpStream), NodeError> {
+) -> Result<(Vec<BlockHeader>, OutboundStream), NodeError> {
     // In order to retry the current connection in case of error
     add_curr_ip_to_ips(&stream, &mut ips)?;
 
     while !ips.is_empty() {
-        match initial_block_headers_download(&mut stream, &sender, ui_sender, logger) {
+        match initial_block_headers_download(&mut stream, &sender, deadline, events, logger) {
             Ok(ibh) => {
                 return Ok((ibh, stream));
             }
             Err(e) => {
+                time_left(deadline)?;
                 if let Some(next_ip) = next_ipv4(&mut ips) {
                     println!(
                         "Retrying IBH download with ip: {} because of error {:?}",
                         next_ip, e
                     );
                     stream = match connect_to_ip(&next_ip, logger) {
-                        Some(s) => s,
+                        Some(s) => {
+                            let _ = stream.shutdown(std::net::Shutdown::Both);
+                            s
+                        }
                         None => {
                             continue;
                         }
@@ -655,81 +1257,245 @@ fn next_ipv4(ips: &mut Vec<SocketAddr>) -> Option<SocketAddr> {
 ///
 /// Returns a `NodeError` if an error occurs while initializing the node.
 fn initialize_node(
-    ui_sender: &glib::Sender<UIMessage>,
-) -> Result<(Logger, TcpStream, BlockDownloaderPool, Vec<SocketAddr>), NodeError> {
-    load_app_config(Some(ui_sender))?;
+    events: &(impl EventSink + Clone + Send + Sync + 'static),
+) -> Result<(Logger, OutboundStream, BlockDownloaderPool, Vec<SocketAddr>), NodeError> {
+    load_app_config(Some(events))?;
     println!("Loaded app config");
     let ips = obtain_ips()?;
-    let logger = Logger::new()?;
+    let logger = Logger::new()?.with_events(events.clone());
     let stream = init_connection(&ips, &logger)?;
-    let pool = BlockDownloaderPool::new(ips.len(), &ips, logger.clone(), ui_sender)?;
+    // Each worker connects to its own peer, so there are no more workers than peers
+    let pool_size = retrieve_max_workers(MAX_DOWNLOAD_WORKERS).min(ips.len());
+    let pool = BlockDownloaderPool::new(pool_size, &ips, logger.clone(), events)?;
     println!("Created thread pool");
     Ok((logger, stream, pool, ips))
 }
 
-/// Sends block headers from the config timestamp to the UI.
+/// Emits the number of blocks to download and the last block headers from the config timestamp.
 ///
 /// # Arguments
 ///
-/// * `ui_sender` - A reference to a `glib::Sender<UIMessage>` for sending messages to the UI.
+/// * `events` - The sink the events of the node are emitted to, such as the UI.
 /// * `initial_block_headers` - A slice of `BlockHeader` objects representing the block headers.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if the block headers are successfully sent to the UI.
+/// Returns `Ok(())` if the block headers are successfully emitted.
 ///
 /// # Errors
 ///
-/// Returns a `NodeError` if an error occurs while sending the block headers to the UI.
+/// Returns a `NodeError` if an error occurs while emitting the block headers.
 fn send_block_headers_to_ui(
-    ui_sender: &glib::Sender<UIMessage>,
+    events: &impl EventSink,
     initial_block_headers: &[BlockHeader],
 ) -> Result<(), NodeError> {
-    ui_sender
-        .send(UIMessage::TotalBlocksToDownload(
-            initial_block_headers.len().try_into().map_err(|_| {
-                NodeError::FailedToSendMessage(
-                    "Failed to send total blocks to download because usize conversion failed"
-                        .to_string(),
-                )
-            })?,
-        ))
-        .map_err(|_| {
-            NodeError::FailedToSendMessage("Failed to send total blocks to download".to_string())
-        })?;
+    events.emit(NodeEvent::TotalBlocksToDownload(
+        initial_block_headers.len(),
+    ))?;
 
-    let last_10k_blocks =
-        initial_block_headers[initial_block_headers.len() - BLOCKS_TO_SHOW..].to_owned();
-    ui_sender
-        .send(UIMessage::InitialBlockHeaders(last_10k_blocks))
-        .map_err(|_| {
-            NodeError::FailedToSendMessage("Failed to send initial block headers".to_string())
-        })?;
+    let last_10k_blocks = initial_block_headers
+        [initial_block_headers.len().saturating_sub(BLOCKS_TO_SHOW)..]
+        .to_owned();
+    events.emit(NodeEvent::InitialBlockHeaders(last_10k_blocks))?;
     Ok(())
 }
 
 /// Broadcasts a created transaction to the network.
 ///
+/// The transaction is not sent if it's larger than the configured `MAX_TX_SIZE` or its fee rate is
+/// below the configured `MIN_RELAY_FEE_RATE`, as every peer would reject it. It's sent to the first `BROADCAST_PEER_COUNT` connected peers whose
+/// fee filter accepts its fee rate, so a single unreliable peer can't swallow it.
+///
 /// # Arguments
 ///
 /// * `transaction` - A `Transaction` object representing the transaction to be broadcasted.
-/// * `connection` - A `TcpStream` representing the network connection to the peer.
+/// * `spent_outputs` - A `UtxoSet` holding the outputs spent by the transaction.
+/// * `connections` - The `TcpStream` connections to the peers the transaction can be sent to.
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` if the transaction is successfully broadcasted.
+/// Returns the number of peers the transaction was sent to.
 ///
 /// # Errors
 ///
-/// Returns a `NodeError` if an error occurs while broadcasting the transaction.
+/// Returns a `NodeError::TransactionTooLarge` if the transaction is too large, a
+/// `NodeError::FeeTooLow` if the fee rate is too low, a `NodeError::FailedToConnect` if
+/// no peer accepts the transaction, or a `NodeError::FailedToSendMessage` if it could not be sent
+/// to any of the selected peers.
 pub fn broadcast_transaction(
     transaction: Transaction,
-    connection: &mut TcpStream,
+    spent_outputs: &UtxoSet,
+    connections: &mut [TcpStream],
+) -> Result<usize, NodeError> {
+    check_transaction_size(&transaction, retrieve_max_tx_size())?;
+    check_min_relay_fee(&transaction, spent_outputs, retrieve_min_relay_fee_rate())?;
+
+    let fee_rate = transaction_fee_rate(&transaction, spent_outputs);
+    let peers: Vec<Option<SocketAddr>> = connections
+        .iter()
+        .map(|connection| {
+            if Utils::is_tcpstream_connected(connection) {
+                connection.peer_addr().ok()
+            } else {
+                None
+            }
+        })
+        .collect();
+    let positions = select_peers_for_fee_rate(&peers, fee_rate, retrieve_broadcast_peer_count());
+    if positions.is_empty() {
+        return Err(NodeError::FailedToConnect(
+            "No connected peer accepts the fee rate of the transaction".to_string(),
+        ));
+    }
+
+    let results = send_tx_to_peers(&transaction, connections, &positions);
+    let mut peers_reached = 0;
+    for (position, result) in positions.iter().zip(results) {
+        match result {
+            Ok(()) => peers_reached += 1,
+            Err(e) => println!(
+                "Failed to broadcast transaction to peer {:?}: {:?}",
+                peers[*position], e
+            ),
+        }
+    }
+    if peers_reached == 0 {
+        return Err(NodeError::FailedToSendMessage(
+            "Failed to broadcast the transaction to every selected peer".to_string(),
+        ));
+    }
+
+    Ok(peers_reached)
+}
+
+/// Sends a `tx` message to each of the given peers, going on when sending to one of them fails.
+///
+/// # Arguments
+///
+/// * `transaction` - The transaction to send.
+/// * `connections` - The streams to the peers.
+/// * `positions` - The positions in `connections` of the peers the transaction is sent to.
+///
+/// # Returns
+///
+/// The result of sending the transaction to each peer, in the order of `positions`.
+fn send_tx_to_peers<W: Write>(
+    transaction: &Transaction,
+    connections: &mut [W],
+    positions: &[usize],
+) -> Vec<Result<(), NodeError>> {
+    positions
+        .iter()
+        .map(|position| match connections.get_mut(*position) {
+            Some(connection) => TxMessage::send_tx_message(transaction, connection),
+            None => Err(NodeError::FailedToGetStream(format!(
+                "There is no peer at position {}",
+                position
+            ))),
+        })
+        .collect()
+}
+
+/// Returns the positions of the first peers whose fee filter accepts the fee rate of a transaction.
+///
+/// Peers that didn't send a fee filter accept every transaction, as do all of them when the fee
+/// rate is not known.
+///
+/// # Arguments
+///
+/// * `peers` - The addresses of the peers, `None` for the ones that are not connected.
+/// * `fee_rate` - The fee rate of the transaction, in satoshis per virtual byte.
+/// * `count` - The maximum number of peers to select.
+fn select_peers_for_fee_rate(
+    peers: &[Option<SocketAddr>],
+    fee_rate: Option<f64>,
+    count: usize,
+) -> Vec<usize> {
+    peers
+        .iter()
+        .enumerate()
+        .filter(|(_, peer)| match (peer, fee_rate) {
+            (Some(peer), Some(fee_rate)) => {
+                FeeFilterMessage::for_peer(peer).is_none_or(|feefilter| feefilter.accepts(fee_rate))
+            }
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+        .map(|(position, _)| position)
+        .take(count)
+        .collect()
+}
+
+/// Checks that the fee rate of a transaction, in satoshis per virtual byte, reaches the minimum fee rate.
+///
+/// # Arguments
+///
+/// * `transaction` - The transaction to be broadcasted.
+/// * `spent_outputs` - A `UtxoSet` holding the outputs spent by the transaction.
+/// * `min_fee_rate` - The minimum fee rate, in satoshis per virtual byte.
+///
+/// # Errors
+///
+/// Returns a `NodeError::FeeTooLow` if the fee rate of the transaction is below the minimum or the
+/// transaction is a coinbase, or a `NodeError::OutpointNotFound` if an output it spends is not
+/// known, as its fee can't be computed.
+pub fn check_min_relay_fee(
+    transaction: &Transaction,
+    spent_outputs: &UtxoSet,
+    min_fee_rate: f64,
+) -> Result<(), NodeError> {
+    let fee = transaction.fee(spent_outputs)?.ok_or_else(|| {
+        NodeError::FeeTooLow("A coinbase transaction doesn't pay a fee".to_string())
+    })?;
+    let fee_rate = fee as f64 / transaction.virtual_size() as f64;
+    if fee_rate < min_fee_rate {
+        return Err(NodeError::FeeTooLow(format!(
+            "The fee rate of {:.2} sat/vB is below the minimum of {} sat/vB",
+            fee_rate, min_fee_rate
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that a signed transaction is not larger than the maximum size peers relay.
+///
+/// # Arguments
+///
+/// * `transaction` - The signed transaction to be broadcasted.
+/// * `max_tx_size` - The maximum size of the transaction, in bytes.
+///
+/// # Errors
+///
+/// Returns a `NodeError::TransactionTooLarge` if the serialized transaction is larger than the
+/// maximum, suggesting to consolidate the outputs it spends.
+pub fn check_transaction_size(
+    transaction: &Transaction,
+    max_tx_size: usize,
 ) -> Result<(), NodeError> {
-    TxMessage::send_tx_message(&transaction, connection)?;
+    let size = transaction.serialized_size();
+    if size > max_tx_size {
+        return Err(NodeError::TransactionTooLarge(format!(
+            "The transaction takes {} bytes, more than the maximum of {}. Consider consolidating the {} outputs it spends",
+            size,
+            max_tx_size,
+            transaction.input_count()
+        )));
+    }
     Ok(())
 }
 
+/// Returns the fee rate of a transaction, in satoshis per virtual byte, or `None` if any of the
+/// outputs it spends is not known.
+///
+/// # Arguments
+///
+/// * `transaction` - The transaction.
+/// * `spent_outputs` - A `UtxoSet` holding the outputs spent by the transaction.
+fn transaction_fee_rate(transaction: &Transaction, spent_outputs: &UtxoSet) -> Option<f64> {
+    let fee = transaction.fee(spent_outputs).ok()??;
+    Some(fee as f64 / transaction.virtual_size() as f64)
+}
+
 /// Extracts user addresses from a transaction and performs address validation.
 ///
 /// This function takes a `Transaction` and a reference to a `WalletChannel` wrapped in an `Arc<Mutex>`.
@@ -765,22 +1531,54 @@ pub fn send_tx_to_wallet(
 mod tests {
     use std::{
         env,
-        fs::File,
-        io::{BufRead, BufReader},
-        net::{IpAddr, Ipv4Addr, SocketAddr},
+        fs::{self, File},
+        io::{BufRead, BufReader, Read, Write},
+        net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
     };
 
     use bitcoin_hashes::{sha256d, Hash};
 
     use crate::{
-        block_header::BlockHeader,
+        block::{
+            block_filter::BlockFilter,
+            block_hash::{BlockHash, InternalHash},
+            Block,
+        },
+        block_header::{BlockHeader, GENESIS_BLOCK_HEADER},
+        channels::event_sink::NodeEvent,
+        compact_size::CompactSize,
         config::{load_app_config, parse_line},
-        constants::{COMMAND_NAME_VERSION, DEFAULT_CONFIG, TESTNET_MAGIC_BYTES},
+        connectors::peer_connector::{receive_message, send_message},
+        constants::{
+            BASIC_FILTER_TYPE, COMMAND_NAME_BLOCK, COMMAND_NAME_CFHEADERS, COMMAND_NAME_CFILTER,
+            COMMAND_NAME_GETCFHEADERS, COMMAND_NAME_GETCFILTERS, COMMAND_NAME_NOTFOUND,
+            COMMAND_NAME_VERSION, DEFAULT_CONFIG, DEFAULT_MAX_TX_SIZE, LENGTH_BLOCK_HEADERS,
+            TESTNET_MAGIC_BYTES,
+        },
         header::Header,
-        messages::version_message::VersionMessage,
-        node::read::obtain_ips,
+        logger::Logger,
+        messages::{
+            feefilter_message::FeeFilterMessage,
+            get_headers_message::GetHeadersMessage,
+            headers_message::HeadersMessage,
+            verack_message::{is_verack_message, VERACK_MESSAGE},
+            version_message::VersionMessage,
+        },
+        node::{
+            check_min_relay_fee, check_transaction_size, compare_peer_filters,
+            connection_limiter::{ConnectionLimiter, OutboundStream},
+            first_peer_with_block, handshake_with_timeout, is_synced_from,
+            keep_listened_connections,
+            message_type::MessageType,
+            queue_hashes,
+            read::obtain_ips,
+            retry_failed_blocks, select_peers_for_fee_rate, send_tx_to_peers,
+        },
         node_error::NodeError,
-        transactions::utxo_set::UtxoSet,
+        transactions::{transaction::Transaction, tx_output::TxOutput, utxo_set::UtxoSet},
     };
 
     fn load_default_config() -> Result<(), NodeError> {
@@ -954,4 +1752,551 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_block_failing_twice_is_eventually_downloaded() -> Result<(), NodeError> {
+        let stubborn_hash = [1u8; 32];
+        let other_hash = [2u8; 32];
+        let mut attempts = Vec::new();
+
+        let unrecovered_hashes = super::retry_with_limit(
+            vec![stubborn_hash, other_hash],
+            3,
+            Instant::now() + Duration::from_secs(60),
+            |hash| {
+                attempts.push(hash);
+                hash != stubborn_hash || attempts.iter().filter(|h| **h == hash).count() > 2
+            },
+        )?;
+
+        assert!(unrecovered_hashes.is_empty());
+        assert_eq!(
+            attempts,
+            vec![stubborn_hash, other_hash, stubborn_hash, stubborn_hash]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_is_given_up_after_max_retries() -> Result<(), NodeError> {
+        let stubborn_hash = [1u8; 32];
+        let mut attempts = 0;
+
+        let unrecovered_hashes = super::retry_with_limit(
+            vec![stubborn_hash],
+            2,
+            Instant::now() + Duration::from_secs(60),
+            |_| {
+                attempts += 1;
+                false
+            },
+        )?;
+
+        assert_eq!(unrecovered_hashes, vec![stubborn_hash]);
+        assert_eq!(attempts, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_block_is_retried_past_the_deadline() {
+        let mut attempts = 0;
+
+        let result = super::retry_with_limit(vec![[1u8; 32]], 2, Instant::now(), |_| {
+            attempts += 1;
+            false
+        });
+
+        assert!(matches!(result, Err(NodeError::IbdTimeout(_))));
+        assert_eq!(attempts, 0);
+    }
+
+    #[test]
+    fn test_blocks_no_peer_has_are_notified_to_a_plain_channel() -> Result<(), NodeError> {
+        load_default_config()?;
+        let (failed_sender, failed_receiver) = mpsc::channel();
+        failed_sender
+            .send([1u8; 32])
+            .map_err(|_| NodeError::FailedToSendMessage("Failed to send hash".to_string()))?;
+        drop(failed_sender);
+        let (events, event_receiver) = mpsc::channel::<NodeEvent>();
+
+        retry_failed_blocks(
+            &failed_receiver,
+            &mut [],
+            Instant::now() + Duration::from_secs(60),
+            &Logger::new()?,
+            &events,
+        )?;
+        drop(events);
+
+        let notifications: Vec<String> = event_receiver
+            .iter()
+            .filter_map(|event| match event {
+                NodeEvent::Notification(message) => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            notifications,
+            vec![format!(
+                "Failed to download block {}",
+                InternalHash::new([1u8; 32]).to_display()
+            )]
+        );
+        Ok(())
+    }
+
+    /// Connects to the given address as an outbound peer counted by the limiter.
+    fn connect_outbound(
+        addr: SocketAddr,
+        limiter: &'static ConnectionLimiter,
+    ) -> Result<OutboundStream, NodeError> {
+        let stream = TcpStream::connect(addr)
+            .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+        let permit = limiter.try_add_outbound(addr).ok_or_else(|| {
+            NodeError::ConnectionLimitReached("The connection was refused".to_string())
+        })?;
+        Ok(OutboundStream::new(stream, permit))
+    }
+
+    #[test]
+    fn test_handshake_with_silent_peer_times_out() -> Result<(), NodeError> {
+        load_default_config()?;
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to obtain address".to_string()))?;
+        let silent_peer = thread::spawn(move || {
+            let accepted = listener.accept();
+            thread::sleep(Duration::from_millis(500));
+            drop(accepted);
+        });
+
+        let mut stream = TcpStream::connect(addr)
+            .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+        let logger = Logger::new()?;
+
+        let result =
+            handshake_with_timeout(&addr, &mut stream, &logger, Duration::from_millis(100));
+
+        assert!(matches!(result, Err(NodeError::HandshakeFailed(_))));
+        assert_eq!(stream.read_timeout().ok(), Some(None));
+        silent_peer
+            .join()
+            .map_err(|_| NodeError::FailedToConnect("Silent peer panicked".to_string()))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_handshake_with_peer_sending_verack_before_version() -> Result<(), NodeError> {
+        load_default_config()?;
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to obtain address".to_string()))?;
+        let peer = thread::spawn(move || -> Result<bool, NodeError> {
+            let (mut stream, peer_addr) = listener
+                .accept()
+                .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+            let mut header = Header::new(&mut stream)?;
+            assert_eq!(header.extract_command_name()?, MessageType::Version);
+            header.skip_payload(&mut stream)?;
+
+            send_message(&mut stream, VERACK_MESSAGE.to_vec())?;
+            VersionMessage::new(&peer_addr)?.send_message(&mut stream)?;
+            let verack = receive_message(&mut stream, VERACK_MESSAGE.len())?;
+            Ok(is_verack_message(&verack))
+        });
+
+        let mut stream = TcpStream::connect(addr)
+            .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+        let logger = Logger::new()?;
+
+        let result = handshake_with_timeout(&addr, &mut stream, &logger, Duration::from_secs(5));
+        let peer_received_verack = peer
+            .join()
+            .map_err(|_| NodeError::FailedToConnect("Mock peer panicked".to_string()))??;
+
+        assert!(result?);
+        assert!(peer_received_verack);
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_without_newer_headers_means_synced() -> Result<(), NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to obtain address".to_string()))?;
+        let peer = thread::spawn(move || -> Result<(), NodeError> {
+            for headers_count in [0, 1] {
+                let (mut stream, _) = listener
+                    .accept()
+                    .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+                let mut header = Header::new(&mut stream)?;
+                assert_eq!(header.extract_command_name()?, MessageType::GetHeaders);
+                GetHeadersMessage::from_stream(&mut stream)?;
+                let headers = vec![GENESIS_BLOCK_HEADER.to_bytes(); headers_count];
+                HeadersMessage::new(headers_count as u64, headers).send(&mut stream)?;
+            }
+            Ok(())
+        });
+
+        let mut synced = Vec::new();
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(addr)
+                .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+            synced.push(is_synced_from(
+                &mut stream,
+                &GENESIS_BLOCK_HEADER.to_bytes(),
+            )?);
+        }
+        peer.join()
+            .map_err(|_| NodeError::FailedToConnect("Mock peer panicked".to_string()))??;
+
+        assert_eq!(synced, vec![true, false]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_the_block_is_taken_from_the_first_peer_that_has_it() -> Result<(), NodeError> {
+        let read_block = |path: &str| {
+            fs::read(path).map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))
+        };
+        let requested_block = read_block(
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin",
+        )?;
+        let other_block = read_block(
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+        )?;
+        let requested_hash =
+            sha256d::Hash::hash(&requested_block[..LENGTH_BLOCK_HEADERS]).to_byte_array();
+        let mut connections = Vec::new();
+        let mut peers = Vec::new();
+        for (reply, block) in [
+            (COMMAND_NAME_NOTFOUND, None),
+            (COMMAND_NAME_BLOCK, Some(other_block)),
+            (COMMAND_NAME_BLOCK, Some(requested_block.clone())),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0")
+                .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+            let addr = listener
+                .local_addr()
+                .map_err(|_| NodeError::FailedToBind("Failed to obtain address".to_string()))?;
+            peers.push(thread::spawn(move || -> Result<Vec<u8>, NodeError> {
+                let (mut stream, _) = listener
+                    .accept()
+                    .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+                let mut header = Header::new(&mut stream)?;
+                assert_eq!(header.extract_command_name()?, MessageType::GetData);
+                let payload = receive_message(&mut stream, header.payload_size())?;
+                let payload = block.unwrap_or(payload);
+                let mut message = Header::create_header(&payload, reply)?;
+                message.extend(payload.clone());
+                send_message(&mut stream, message)?;
+                Ok(payload)
+            }));
+            connections.push(connect_outbound(addr, ConnectionLimiter::global())?);
+        }
+
+        let selected = first_peer_with_block(&mut connections, requested_hash);
+        for peer in peers {
+            peer.join()
+                .map_err(|_| NodeError::FailedToConnect("Mock peer panicked".to_string()))??;
+        }
+
+        let (index, block) = selected.ok_or_else(|| {
+            NodeError::FailedToDownloadBlock("No peer sent the block".to_string())
+        })?;
+        assert_eq!(index, 2);
+        assert_eq!(block, requested_block);
+        Ok(())
+    }
+
+    /// Starts a peer that answers a getcfheaders and a getcfilters request for one block with the
+    /// given filter, and returns its address.
+    fn spawn_filter_peer(
+        block_hash: BlockHash,
+        filter_bytes: Vec<u8>,
+    ) -> Result<(SocketAddr, thread::JoinHandle<Result<(), NodeError>>), NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to obtain address".to_string()))?;
+        let peer = thread::spawn(move || -> Result<(), NodeError> {
+            let (mut stream, _) = listener
+                .accept()
+                .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+            let filter_hash = sha256d::Hash::hash(&filter_bytes).to_byte_array();
+            let mut cfheaders = vec![BASIC_FILTER_TYPE];
+            cfheaders.extend(block_hash);
+            cfheaders.extend([0; 32]);
+            cfheaders.push(1);
+            cfheaders.extend(filter_hash);
+            let mut cfilter = vec![BASIC_FILTER_TYPE];
+            cfilter.extend(block_hash);
+            cfilter.extend(CompactSize::new(filter_bytes.len()).to_bytes());
+            cfilter.extend(filter_bytes);
+
+            for (request, reply, payload) in [
+                (COMMAND_NAME_GETCFHEADERS, COMMAND_NAME_CFHEADERS, cfheaders),
+                (COMMAND_NAME_GETCFILTERS, COMMAND_NAME_CFILTER, cfilter),
+            ] {
+                let header = Header::new(&mut stream)?;
+                assert_eq!(&header.command_name[..request.len()], request.as_bytes());
+                header.skip_payload(&mut stream)?;
+                let mut message = Header::create_header(&payload, reply)?;
+                message.extend(payload);
+                send_message(&mut stream, message)?;
+            }
+            Ok(())
+        });
+        Ok((addr, peer))
+    }
+
+    #[test]
+    fn test_peer_filters_are_compared_with_the_filters_built_from_the_blocks(
+    ) -> Result<(), NodeError> {
+        let block_data = fs::read(
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let block = Block::from_bytes(&block_data)?;
+        let block_hash: BlockHash = block
+            .header
+            .hash()
+            .as_slice()
+            .try_into()
+            .map_err(|_| NodeError::FailedToConvert("Invalid block hash".to_string()))?;
+        let local_filter = BlockFilter::build(&block, &[]);
+        let local_filters = vec![(block_hash, Some(local_filter.clone()))];
+        let other_block_filter = BlockFilter::build(&Block::from_bytes(&fs::read(
+            "blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?)?, &[]);
+
+        let unbuilt_filters = vec![(block_hash, None)];
+
+        let mut results = Vec::new();
+        for (filter_bytes, local_filters) in [
+            (local_filter.to_bytes(), &local_filters),
+            (other_block_filter.to_bytes(), &local_filters),
+            (other_block_filter.to_bytes(), &unbuilt_filters),
+        ] {
+            let (addr, peer) = spawn_filter_peer(block_hash, filter_bytes)?;
+            let mut stream = TcpStream::connect(addr)
+                .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+            results.push(compare_peer_filters(&mut stream, 100, local_filters));
+            peer.join()
+                .map_err(|_| NodeError::FailedToConnect("Mock peer panicked".to_string()))??;
+        }
+
+        assert!(matches!(results[0], Ok(())));
+        assert!(matches!(results[1], Err(NodeError::FilterMismatch(_))));
+        assert!(matches!(results[2], Ok(())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_hashes_are_queued_once() -> Result<(), NodeError> {
+        load_default_config()?;
+        let first_header = BlockHeader {
+            nonce: 1,
+            ..GENESIS_BLOCK_HEADER
+        };
+        let second_header = BlockHeader {
+            nonce: 2,
+            ..GENESIS_BLOCK_HEADER
+        };
+        let header_blocks = vec![
+            first_header.clone(),
+            second_header.clone(),
+            first_header.clone(),
+        ];
+        let (sender, receiver) = mpsc::channel();
+
+        let skipped = queue_hashes(&header_blocks, &sender)?;
+        drop(sender);
+
+        let queued: Vec<BlockHash> = receiver.iter().collect();
+        assert_eq!(skipped, 1);
+        assert_eq!(
+            queued,
+            vec![
+                sha256d::Hash::hash(&first_header.to_bytes()).to_byte_array(),
+                sha256d::Hash::hash(&second_header.to_bytes()).to_byte_array(),
+            ]
+        );
+        Ok(())
+    }
+
+    /// Returns the second transaction of a stored block and a UTXO set in which it pays the given fee.
+    fn transaction_paying_fee(fee: i64) -> Result<(Transaction, UtxoSet), NodeError> {
+        let block_data = fs::read(
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+        )
+        .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+        let transaction = Block::from_bytes(&block_data)?.transactions[1].clone();
+        let output_value = i64::try_from(transaction.total_output_value()?)
+            .map_err(|_| NodeError::InvalidTransaction("Output value too large".to_string()))?;
+
+        let mut spent_entries: std::collections::HashMap<Vec<u8>, Vec<TxOutput>> =
+            std::collections::HashMap::new();
+        for (position, tx_input) in transaction.tx_inputs.iter().enumerate() {
+            let value = if position == 0 { output_value + fee } else { 0 };
+            let outpoint = &tx_input.previous_output;
+            let mut spent_output = TxOutput::new(0.0, vec![], outpoint.index as u64);
+            spent_output.value = value;
+            spent_entries
+                .entry(outpoint.tx_id.clone())
+                .or_default()
+                .push(spent_output);
+        }
+        let mut spent_outputs = UtxoSet::new();
+        for (tx_id, tx_outputs) in spent_entries {
+            spent_outputs.insert(tx_id, tx_outputs);
+        }
+        Ok((transaction, spent_outputs))
+    }
+
+    #[test]
+    fn test_transaction_below_min_relay_fee_is_rejected() -> Result<(), NodeError> {
+        let (transaction, _) = transaction_paying_fee(0)?;
+        let virtual_size = transaction.virtual_size() as i64;
+
+        let (transaction, spent_outputs) = transaction_paying_fee(virtual_size - 1)?;
+        let result = check_min_relay_fee(&transaction, &spent_outputs, 1.0);
+
+        assert!(matches!(result, Err(NodeError::FeeTooLow(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_above_min_relay_fee_passes() -> Result<(), NodeError> {
+        let (transaction, _) = transaction_paying_fee(0)?;
+        let virtual_size = transaction.virtual_size() as i64;
+
+        let (transaction, spent_outputs) = transaction_paying_fee(virtual_size * 2)?;
+
+        check_min_relay_fee(&transaction, &spent_outputs, 1.0)
+    }
+
+    #[test]
+    fn test_transaction_spending_unknown_outputs_fails_the_min_relay_fee_check(
+    ) -> Result<(), NodeError> {
+        let (transaction, _) = transaction_paying_fee(0)?;
+
+        let result = check_min_relay_fee(&transaction, &UtxoSet::new(), 1.0);
+
+        assert!(matches!(result, Err(NodeError::OutpointNotFound(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_larger_than_max_tx_size_is_rejected() -> Result<(), NodeError> {
+        let (transaction, _) = transaction_paying_fee(0)?;
+        let mut oversized = transaction.clone();
+        while oversized.serialized_size() <= DEFAULT_MAX_TX_SIZE {
+            oversized.tx_inputs.extend(transaction.tx_inputs.clone());
+        }
+        oversized.tx_in_count = CompactSize::new(oversized.tx_inputs.len());
+
+        let result = check_transaction_size(&oversized, DEFAULT_MAX_TX_SIZE);
+
+        assert!(matches!(result, Err(NodeError::TransactionTooLarge(_))));
+        check_transaction_size(&transaction, DEFAULT_MAX_TX_SIZE)
+    }
+
+    #[test]
+    fn test_peer_with_demanding_feefilter_is_skipped() -> Result<(), NodeError> {
+        let demanding_peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 1, 1)), 18333);
+        let accepting_peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 1, 2)), 18333);
+        FeeFilterMessage::from_bytes(&5000u64.to_le_bytes())?.store_for_peer(demanding_peer)?;
+        FeeFilterMessage::from_bytes(&1000u64.to_le_bytes())?.store_for_peer(accepting_peer)?;
+        let peers = vec![None, Some(demanding_peer), Some(accepting_peer)];
+
+        assert_eq!(select_peers_for_fee_rate(&peers, Some(2.0), 1), vec![2]);
+        assert_eq!(select_peers_for_fee_rate(&peers, Some(5.0), 1), vec![1]);
+        assert_eq!(
+            select_peers_for_fee_rate(&peers, Some(0.5), 1),
+            Vec::<usize>::new()
+        );
+        assert_eq!(select_peers_for_fee_rate(&peers, None, 1), vec![1]);
+        assert_eq!(select_peers_for_fee_rate(&peers, Some(5.0), 3), vec![1, 2]);
+        Ok(())
+    }
+
+    /// A peer that either stores the bytes sent to it or fails every write.
+    enum MockPeer {
+        Accepting(Vec<u8>),
+        Failing,
+    }
+
+    impl Write for MockPeer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            match self {
+                MockPeer::Accepting(received) => received.write(buf),
+                MockPeer::Failing => Err(std::io::ErrorKind::BrokenPipe.into()),
+            }
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failing_peers_do_not_prevent_broadcasting_to_others() -> Result<(), NodeError> {
+        let (transaction, _) = transaction_paying_fee(0)?;
+        let mut peers = vec![
+            MockPeer::Accepting(Vec::new()),
+            MockPeer::Failing,
+            MockPeer::Accepting(Vec::new()),
+            MockPeer::Accepting(Vec::new()),
+        ];
+
+        let results = send_tx_to_peers(&transaction, &mut peers, &[0, 1, 2, 3]);
+
+        assert_eq!(
+            results.iter().map(Result::is_ok).collect::<Vec<bool>>(),
+            vec![true, false, true, true]
+        );
+        for peer in peers {
+            if let MockPeer::Accepting(received) = peer {
+                assert!(received.ends_with(&transaction.to_bytes()));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_connections_beyond_max_listeners_are_closed() -> Result<(), NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind fake peer".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to get fake peer addr".to_string()))?;
+        let limiter: &'static ConnectionLimiter = Box::leak(Box::new(ConnectionLimiter::new(3)));
+        let mut connections = Vec::new();
+        let mut accepted = Vec::new();
+        for _ in 0..3 {
+            connections.push(connect_outbound(addr, limiter)?);
+            accepted.push(listener.accept().map_err(|_| {
+                NodeError::FailedToConnect("Failed to accept connection".to_string())
+            })?);
+        }
+
+        let listened = keep_listened_connections(connections, 2);
+
+        assert_eq!(listened.len(), 2);
+        assert_eq!(limiter.len(), 2);
+        let (mut closed, _) = accepted.remove(2);
+        let mut buffer = [0u8; 1];
+        assert_eq!(closed.read(&mut buffer).ok(), Some(0));
+        Ok(())
+    }
 }
@@ -1,19 +1,38 @@
 use std::{
     fs::{File, OpenOptions},
-    io::{self, Read, Seek, Write},
-    net::{Ipv4Addr, SocketAddr},
+    io::{self, BufRead, BufReader, Read, Seek, Write},
+    net::SocketAddr,
     str::FromStr,
+    thread,
+    time::Duration,
     vec::IntoIter,
 };
 
 use crate::{
-    block_header::{block_header_bytes::BlockHeaderBytes, BlockHeader, GENESIS_BLOCK_HEADER},
+    block::lock_headers_file,
+    block_header::{
+        block_header_bytes::BlockHeaderBytes, BlockHeader, GENESIS_BLOCK_HEADER,
+        REGTEST_GENESIS_BLOCK_HEADER,
+    },
     config::obtain_dir_path,
     connectors::dns_connector::DNSConnector,
     constants::{
-        BLOCK_HEADERS_FILE, DEFAULT_VERSION, DNS, LENGTH_BLOCK_HEADERS, PORT, STARTING_DATE,
-        VERSION,
+        BLOCKS_SHARDED_BY_DATE, BLOCK_HEADERS_FILE, BROADCAST_PEER_COUNT, CHANGE_ACCOUNT,
+        CONFIRMATION_DEPTH, DEFAULT_BROADCAST_PEER_COUNT, DEFAULT_CONFIRMATION_DEPTH,
+        DEFAULT_DNS_RETRIES, DEFAULT_IBD_TIMEOUT_SECS, DEFAULT_MAX_BLOCK_RETRIES,
+        DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_OPEN_BLOCK_FILES, DEFAULT_MAX_REORG_DEPTH,
+        DEFAULT_MAX_TX_SIZE, DEFAULT_MIN_RELAY_FEE_RATE, DEFAULT_PEER_FILTERS_CHECKED,
+        DEFAULT_PROGRESS_LOG_INTERVAL, DEFAULT_USER_AGENT, DEFAULT_VERSION,
+        DEFAULT_WALLET_AUTOSAVE_SECS, DNS, DNS_RETRIES, DNS_RETRY_BACKOFF, FULL_VALIDATION,
+        HEADERS_ONLY, IBD_TIMEOUT_SECS, LENGTH_BLOCK_HEADERS, LOG_APPEND, LOG_LEVEL,
+        LOG_MESSAGE_TYPES, LOG_ROTATE, MAX_BLOCK_RETRIES, MAX_CONNECTIONS, MAX_FUTURE_BLOCK_TIME,
+        MAX_OPEN_BLOCK_FILES, MAX_REORG_DEPTH, MAX_THREADS, MAX_TX_SIZE, MAX_USER_AGENT_LENGTH,
+        MIN_RELAY_FEE_RATE, NETWORK, PEERS, PEERS_CACHE_FILE, PEER_FILTERS_CHECKED, PORT,
+        PROGRESS_LOG_INTERVAL, REGTEST_BIP34_HEIGHT, REGTEST_MAGIC_BYTES, REGTEST_NETWORK,
+        REQUEST_MEMPOOL, STARTING_DATE, TESTNET_BIP34_HEIGHT, TESTNET_MAGIC_BYTES, USER_AGENT,
+        UTXO_MEMORY_LIMIT, VERSION, WALLET_AUTOSAVE_SECS,
     },
+    logger::LogLevel,
     node_error::NodeError,
 };
 
@@ -38,8 +57,8 @@ pub fn turn_iter_into_vector(iter_ips: IntoIter<SocketAddr>) -> Vec<SocketAddr>
 /// Adds IP addresses and ports specified in the "PEER_IPS" environment variable to the given vector of `SocketAddr`.
 ///
 /// If the "PEER_IPS" environment variable is empty, the function returns early without modifying the vector.
-/// Otherwise, it parses the IP addresses and ports from the "PEER_IPS" string, constructs `SocketAddr` instances,
-/// and adds them to the vector.
+/// Otherwise, it parses the entries of the "PEER_IPS" string as `parse_peer_list` does and adds them to the front
+/// of the vector.
 ///
 /// # Arguments
 ///
@@ -51,50 +70,77 @@ pub fn turn_iter_into_vector(iter_ips: IntoIter<SocketAddr>) -> Vec<SocketAddr>
 ///
 /// # Errors
 ///
-/// The function can return a `NodeError` if parsing the IP addresses or ports fails.
+/// The function can return a `NodeError` if PEER_IPS is not set.
 fn add_config_ips(ips: &mut Vec<SocketAddr>) -> Result<(), NodeError> {
     let peer_ips = std::env::var("PEER_IPS")
         .map_err(|_| NodeError::EnvironVarNotFound("PEER_IPS not found in env vars".to_string()))?;
 
-    if peer_ips.is_empty() {
+    let new_ips = parse_peer_list(&peer_ips);
+    if new_ips.is_empty() {
         return Ok(());
     }
 
-    let new_ips: Vec<_> = peer_ips
+    println!("Adding IPs from PEER_IPS: {:?}", new_ips);
+    ips.splice(0..0, new_ips);
+    Ok(())
+}
+
+/// Returns the list of IP addresses the node connects to.
+///
+/// If the PEERS environment variable has a comma-separated list of `ip:port` entries, exactly those addresses
+/// are returned and neither DNS nor PEER_IPS are consulted. Otherwise, the addresses are obtained as
+/// `resolve_dns_peers` does.
+///
+/// # Errors
+///
+/// Returns the errors of `resolve_dns_peers`.
+pub fn obtain_ips() -> Result<Vec<SocketAddr>, NodeError> {
+    let peer_list = std::env::var(PEERS).ok();
+    select_peers(peer_list.as_deref(), resolve_dns_peers)
+}
+
+/// Returns the addresses of the given peer list if it has any entries, or the ones of the resolver otherwise.
+fn select_peers<F>(peer_list: Option<&str>, resolve: F) -> Result<Vec<SocketAddr>, NodeError>
+where
+    F: FnOnce() -> Result<Vec<SocketAddr>, NodeError>,
+{
+    match peer_list.map(str::trim) {
+        Some(peer_list) if !peer_list.is_empty() => Ok(parse_peer_list(peer_list)),
+        _ => resolve(),
+    }
+}
+
+/// Parses a comma-separated list of `ip:port` entries. Empty entries are skipped, and so are the entries that
+/// are not a valid address, which are reported.
+fn parse_peer_list(peer_list: &str) -> Vec<SocketAddr> {
+    peer_list
         .split(',')
-        .filter_map(|ip_port| {
-            let parts: Vec<_> = ip_port.split(':').collect();
-            if parts.len() == 2 {
-                let ip_str = parts[0].trim();
-                let ip = match Ipv4Addr::from_str(ip_str) {
-                    Ok(ip) => ip,
-                    Err(_) => return None,
-                };
-                let port = match parts[1].trim().parse::<u16>() {
-                    Ok(port) => port,
-                    Err(_) => return None,
-                };
-
-                Some(SocketAddr::new(std::net::IpAddr::V4(ip), port))
-            } else {
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match SocketAddr::from_str(entry) {
+            Ok(ip) => Some(ip),
+            Err(_) => {
+                println!("Skipping invalid peer address {}", entry);
                 None
             }
         })
-        .collect();
-
-    println!("Adding IPs from PEER_IPS: {:?}", new_ips);
-    ips.splice(0..0, new_ips);
-    Ok(())
+        .collect()
 }
 
-/// Returns the list of IP addresses obtained from DNS lookup using the DNS and PORT environment variables.
+/// Returns the list of IP addresses obtained from DNS lookup using the DNS and PORT environment variables,
+/// with the ones of PEER_IPS in front, as `add_config_ips` adds them.
+///
+/// The lookup is retried up to DNS_RETRIES times with an increasing backoff. The peers of the last successful
+/// lookup are saved to the file at PATH_PEERS_CACHE, and they are returned if every attempt fails.
 ///
 /// # Errors
 ///
 /// Returns a NodeError::EnvironVarNotFound error if the DNS or PORT environment variables are not set.
 ///
 /// Returns a NodeError::FailedToParse error if the PORT environment variable is not a valid u16 value.
-pub fn obtain_ips() -> Result<Vec<SocketAddr>, NodeError> {
+///
+/// Returns a NodeError::FailedToConnectDNS error if the lookup failed and there are no cached peers.
+fn resolve_dns_peers() -> Result<Vec<SocketAddr>, NodeError> {
     let dns = std::env::var(DNS)
         .map_err(|_| NodeError::EnvironVarNotFound("DNS not found in env vars".to_string()))?;
     let port = std::env::var(PORT)
@@ -102,12 +148,94 @@ pub fn obtain_ips() -> Result<Vec<SocketAddr>, NodeError> {
         .parse::<u16>()
         .map_err(|_| NodeError::FailedToParse("Invalid PORT format in env vars".to_string()))?;
     let dns_connector = DNSConnector::new(dns, port);
-    let mut ips = turn_iter_into_vector(dns_connector.connect()?);
+    let cache_path = obtain_dir_path(PEERS_CACHE_FILE.to_owned()).ok();
+    let mut ips = resolve_peers(
+        || dns_connector.connect().map(turn_iter_into_vector),
+        retrieve_dns_retries(),
+        Duration::from_millis(DNS_RETRY_BACKOFF),
+        cache_path.as_deref(),
+    )?;
     add_config_ips(&mut ips)?;
 
     Ok(ips)
 }
 
+/// Resolves the peers of the node, retrying the resolver and falling back to the cached peers.
+///
+/// # Arguments
+///
+/// * `resolve` - The resolver of the peers, usually a DNS lookup.
+/// * `retries` - The number of times the resolver is retried after the first attempt fails.
+/// * `backoff` - The wait before the first retry, doubled before each of the following ones.
+/// * `cache_path` - The file where the resolved peers are saved, and read back when every attempt fails.
+///
+/// # Errors
+///
+/// Returns the error of the last attempt if every attempt failed and there are no cached peers.
+fn resolve_peers<F>(
+    resolve: F,
+    retries: usize,
+    backoff: Duration,
+    cache_path: Option<&str>,
+) -> Result<Vec<SocketAddr>, NodeError>
+where
+    F: Fn() -> Result<Vec<SocketAddr>, NodeError>,
+{
+    let mut wait = backoff;
+    let mut attempt = 0;
+    let error = loop {
+        let error = match resolve() {
+            Ok(ips) if !ips.is_empty() => {
+                if let Some(path) = cache_path {
+                    if let Err(err) = save_peers_cache(path, &ips) {
+                        println!("Failed to cache the peers: {:?}", err);
+                    }
+                }
+                return Ok(ips);
+            }
+            Ok(_) => NodeError::FailedToConnectDNS("The DNS lookup returned no peers".to_string()),
+            Err(err) => err,
+        };
+        if attempt >= retries {
+            break error;
+        }
+        attempt += 1;
+        println!("DNS lookup failed, retrying in {:?}", wait);
+        thread::sleep(wait);
+        wait *= 2;
+    };
+
+    match cache_path.map(load_peers_cache) {
+        Some(Ok(ips)) if !ips.is_empty() => {
+            println!("Using {} cached peers", ips.len());
+            Ok(ips)
+        }
+        _ => Err(error),
+    }
+}
+
+/// Saves the peers to the cache file, one `ip:port` per line.
+fn save_peers_cache(path: &str, ips: &[SocketAddr]) -> Result<(), NodeError> {
+    let mut file = File::create(path)
+        .map_err(|_| NodeError::FailedToOpenFile("Failed to create peers cache".to_string()))?;
+    for ip in ips {
+        writeln!(file, "{}", ip)
+            .map_err(|_| NodeError::FailedToWrite("Failed to write peers cache".to_string()))?;
+    }
+    Ok(())
+}
+
+/// Reads the peers saved to the cache file, skipping the lines that are not valid addresses.
+fn load_peers_cache(path: &str) -> Result<Vec<SocketAddr>, NodeError> {
+    let file = File::open(path)
+        .map_err(|_| NodeError::FailedToOpenFile("Failed to open peers cache".to_string()))?;
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| SocketAddr::from_str(line.trim()).ok())
+        .collect())
+}
+
 /// Retrieves the version field of the VersionMessage from the environment variable VERSION, and returns it as an integer.
 /// If the VERSION environment variable is not found or cannot be parsed to an integer, the default value DEFAULT_VERSION (70015) is returned.
 pub fn retrieve_version() -> i32 {
@@ -121,6 +249,277 @@ pub fn retrieve_version() -> i32 {
     }
 }
 
+/// Retrieves the user agent sent in the VersionMessage from the environment variable USER_AGENT, such as
+/// `/inoxidables:1.0/`. If the variable is not found or is longer than MAX_USER_AGENT_LENGTH (256) bytes, the
+/// default value DEFAULT_USER_AGENT (empty) is returned.
+pub fn retrieve_user_agent() -> String {
+    match std::env::var(USER_AGENT) {
+        Ok(user_agent) if user_agent.len() <= MAX_USER_AGENT_LENGTH => user_agent,
+        _ => DEFAULT_USER_AGENT.to_string(),
+    }
+}
+
+/// Retrieves the value of the environment variable `config_key` parsed as a `T`, ignoring the surrounding whitespace.
+/// If the variable is not found, cannot be parsed or the value is rejected by `is_valid`, `default` is returned.
+fn retrieve_env<T: FromStr>(config_key: &str, default: T, is_valid: impl Fn(&T) -> bool) -> T {
+    std::env::var(config_key)
+        .ok()
+        .and_then(|value| value.trim().parse::<T>().ok())
+        .filter(|value| is_valid(value))
+        .unwrap_or(default)
+}
+
+/// Retrieves the maximum number of worker threads of a pool from the environment variable `config_key`.
+/// If the variable is not found, cannot be parsed or is zero, the default value MAX_THREADS (21) is returned.
+pub fn retrieve_max_workers(config_key: &str) -> usize {
+    retrieve_env(config_key, MAX_THREADS, |max_workers| *max_workers > 0)
+}
+
+/// Retrieves the maximum number of download attempts for a failed block from the environment variable MAX_BLOCK_RETRIES.
+/// If the variable is not found, cannot be parsed or is zero, the default value DEFAULT_MAX_BLOCK_RETRIES (3) is returned.
+pub fn retrieve_max_block_retries() -> usize {
+    retrieve_env(
+        MAX_BLOCK_RETRIES,
+        DEFAULT_MAX_BLOCK_RETRIES,
+        |max_retries| *max_retries > 0,
+    )
+}
+
+/// Retrieves the maximum number of seconds the initial block download can take from the environment variable
+/// IBD_TIMEOUT_SECS. If the variable is not found, cannot be parsed or is zero, the default value
+/// DEFAULT_IBD_TIMEOUT_SECS (86400) is returned.
+pub fn retrieve_ibd_timeout_secs() -> u64 {
+    retrieve_env(IBD_TIMEOUT_SECS, DEFAULT_IBD_TIMEOUT_SECS, |timeout| {
+        *timeout > 0
+    })
+}
+
+/// Retrieves the maximum number of block files that can be written at the same time from the environment variable
+/// MAX_OPEN_BLOCK_FILES. If the variable is not found, cannot be parsed or is zero, the default value
+/// DEFAULT_MAX_OPEN_BLOCK_FILES (16) is returned.
+pub fn retrieve_max_open_block_files() -> usize {
+    retrieve_env(
+        MAX_OPEN_BLOCK_FILES,
+        DEFAULT_MAX_OPEN_BLOCK_FILES,
+        |max_open_files| *max_open_files > 0,
+    )
+}
+
+/// Retrieves the number of peers a created transaction is broadcasted to from the environment variable
+/// BROADCAST_PEER_COUNT. If the variable is not found, cannot be parsed or is zero, the default value
+/// DEFAULT_BROADCAST_PEER_COUNT (3) is returned.
+pub fn retrieve_broadcast_peer_count() -> usize {
+    retrieve_env(
+        BROADCAST_PEER_COUNT,
+        DEFAULT_BROADCAST_PEER_COUNT,
+        |peer_count| *peer_count > 0,
+    )
+}
+
+/// Retrieves the level of the messages written to the log file from the environment variable LOG_LEVEL. Detailed
+/// per-message logs are only written when the variable is set to `debug`, otherwise the level is `Info`.
+pub fn retrieve_log_level() -> LogLevel {
+    match std::env::var(LOG_LEVEL) {
+        Ok(level) if level.trim().eq_ignore_ascii_case("debug") => LogLevel::Debug,
+        _ => LogLevel::Info,
+    }
+}
+
+/// Retrieves the types of the messages received from peers that are logged, from the environment variable
+/// LOG_MESSAGE_TYPES: a comma separated list of their names, like `Block,Tx,Reject`, compared ignoring case.
+///
+/// # Returns
+///
+/// The lowercased names, whose messages are logged at the `Info` level, or `None` if the variable is not found
+/// or empty, in which case every message is logged at the `Debug` level.
+pub fn retrieve_log_message_types() -> Option<Vec<String>> {
+    match std::env::var(LOG_MESSAGE_TYPES) {
+        Ok(message_types) if !message_types.trim().is_empty() => Some(
+            message_types
+                .split(',')
+                .map(|message_type| message_type.trim().to_lowercase())
+                .filter(|message_type| !message_type.is_empty())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Retrieves the minimum number of seconds between two progress summaries in the log from the environment variable
+/// PROGRESS_LOG_INTERVAL. If the variable is not found or cannot be parsed, the default value
+/// DEFAULT_PROGRESS_LOG_INTERVAL (30) is returned.
+pub fn retrieve_progress_log_interval() -> u64 {
+    retrieve_env(PROGRESS_LOG_INTERVAL, DEFAULT_PROGRESS_LOG_INTERVAL, |_| {
+        true
+    })
+}
+
+/// Retrieves the number of seconds between two saves of the wallet accounts from the environment variable
+/// WALLET_AUTOSAVE_SECS, where 0 disables the periodic save. If the variable is not found or cannot be parsed,
+/// the default value DEFAULT_WALLET_AUTOSAVE_SECS (60) is returned.
+pub fn retrieve_wallet_autosave_secs() -> u64 {
+    retrieve_env(WALLET_AUTOSAVE_SECS, DEFAULT_WALLET_AUTOSAVE_SECS, |_| true)
+}
+
+/// Retrieves the address of the account that receives the change of the created transactions from the
+/// environment variable CHANGE_ACCOUNT. If the variable is not found or empty, `None` is returned and the change
+/// goes back to the sender address.
+pub fn retrieve_change_account() -> Option<String> {
+    match std::env::var(CHANGE_ACCOUNT) {
+        Ok(address) if !address.trim().is_empty() => Some(address.trim().to_string()),
+        _ => None,
+    }
+}
+
+/// Retrieves the number of blocks at the tip of the chain whose compact filters are requested from a peer and
+/// checked against the filters built from the blocks, from the environment variable PEER_FILTERS_CHECKED. If the
+/// variable is not found or cannot be parsed, the default value DEFAULT_PEER_FILTERS_CHECKED (0) is returned,
+/// which disables the check.
+pub fn retrieve_peer_filters_checked() -> usize {
+    retrieve_env(PEER_FILTERS_CHECKED, DEFAULT_PEER_FILTERS_CHECKED, |_| true)
+}
+
+/// Retrieves the number of blocks a transaction must be buried under, counting its own, to be shown as confirmed
+/// from the environment variable CONFIRMATION_DEPTH. If the variable is not found, cannot be parsed or is 0, the
+/// default value DEFAULT_CONFIRMATION_DEPTH (1) is returned.
+pub fn retrieve_confirmation_depth() -> u32 {
+    retrieve_env(CONFIRMATION_DEPTH, DEFAULT_CONFIRMATION_DEPTH, |depth| {
+        *depth > 0
+    })
+}
+
+/// Retrieves the maximum number of blocks the chain may be rewound to switch to a competing branch from the
+/// environment variable MAX_REORG_DEPTH. If the variable is not found or cannot be parsed, the default value
+/// DEFAULT_MAX_REORG_DEPTH (100) is returned.
+pub fn retrieve_max_reorg_depth() -> u64 {
+    retrieve_env(MAX_REORG_DEPTH, DEFAULT_MAX_REORG_DEPTH, |_| true)
+}
+
+/// Retrieves the number of times a failed DNS lookup is retried from the environment variable DNS_RETRIES.
+/// If the variable is not found or cannot be parsed, the default value DEFAULT_DNS_RETRIES (3) is returned.
+pub fn retrieve_dns_retries() -> usize {
+    retrieve_env(DNS_RETRIES, DEFAULT_DNS_RETRIES, |_| true)
+}
+
+/// Retrieves the maximum number of connections the node keeps open, outbound and inbound, from the environment
+/// variable MAX_CONNECTIONS. If the variable is not found, cannot be parsed or is zero, the default value
+/// DEFAULT_MAX_CONNECTIONS (125) is returned.
+pub fn retrieve_max_connections() -> usize {
+    retrieve_env(
+        MAX_CONNECTIONS,
+        DEFAULT_MAX_CONNECTIONS,
+        |max_connections| *max_connections > 0,
+    )
+}
+
+/// Retrieves the maximum number of transactions whose outputs the UTXO set keeps in memory from the environment
+/// variable UTXO_MEMORY_LIMIT. If the variable is not found, cannot be parsed or is zero, `None` is returned and
+/// the UTXO set is kept entirely in memory.
+pub fn retrieve_utxo_memory_limit() -> Option<usize> {
+    Some(retrieve_env(UTXO_MEMORY_LIMIT, 0, |_| true)).filter(|memory_limit| *memory_limit > 0)
+}
+
+/// Retrieves whether the block files are saved in subdirectories named after the year and month of the blocks from the
+/// environment variable BLOCKS_SHARDED_BY_DATE. Blocks are saved in a flat directory unless the variable is set to `true`.
+pub fn retrieve_blocks_sharded_by_date() -> bool {
+    match std::env::var(BLOCKS_SHARDED_BY_DATE) {
+        Ok(sharded) => sharded.trim().eq_ignore_ascii_case("true"),
+        Err(_) => false,
+    }
+}
+
+/// Retrieves whether the scripts of the received blocks should be verified from the environment variable FULL_VALIDATION.
+/// Full validation is heavy, so it is only enabled when the variable is set to `true`.
+pub fn retrieve_full_validation() -> bool {
+    match std::env::var(FULL_VALIDATION) {
+        Ok(full_validation) => full_validation.trim().eq_ignore_ascii_case("true"),
+        Err(_) => false,
+    }
+}
+
+/// Retrieves whether the node should only download and validate the block headers, without the blocks, from
+/// the environment variable HEADERS_ONLY. It's disabled unless the variable is set to `true`.
+pub fn retrieve_headers_only() -> bool {
+    match std::env::var(HEADERS_ONLY) {
+        Ok(headers_only) => headers_only.trim().eq_ignore_ascii_case("true"),
+        Err(_) => false,
+    }
+}
+
+/// Retrieves the magic bytes that start the messages of the network the node runs on, from the environment
+/// variable NETWORK. The node runs on testnet unless the variable is set to `regtest`, to connect to a local
+/// `bitcoind -regtest` node.
+pub fn retrieve_magic_bytes() -> [u8; 4] {
+    match std::env::var(NETWORK) {
+        Ok(network) if network.trim().eq_ignore_ascii_case(REGTEST_NETWORK) => REGTEST_MAGIC_BYTES,
+        _ => TESTNET_MAGIC_BYTES,
+    }
+}
+
+/// Retrieves the genesis block header of the network the node runs on, from the environment variable
+/// NETWORK, which the block headers file starts with.
+pub fn retrieve_genesis_block_header() -> BlockHeader {
+    match std::env::var(NETWORK) {
+        Ok(network) if network.trim().eq_ignore_ascii_case(REGTEST_NETWORK) => {
+            REGTEST_GENESIS_BLOCK_HEADER
+        }
+        _ => GENESIS_BLOCK_HEADER,
+    }
+}
+
+/// Retrieves the height BIP34 activated at in the network the node runs on, from the environment variable
+/// NETWORK. From that height on, the coinbase of the blocks of version 2 or later starts with their height.
+pub fn retrieve_bip34_height() -> u32 {
+    match std::env::var(NETWORK) {
+        Ok(network) if network.trim().eq_ignore_ascii_case(REGTEST_NETWORK) => REGTEST_BIP34_HEIGHT,
+        _ => TESTNET_BIP34_HEIGHT,
+    }
+}
+
+/// Retrieves whether the log of the previous runs is kept, appending the new lines to it, from the environment
+/// variable LOG_APPEND. It's disabled unless the variable is set to `true`, so the log is cleared on startup.
+pub fn retrieve_log_append() -> bool {
+    match std::env::var(LOG_APPEND) {
+        Ok(log_append) => log_append.trim().eq_ignore_ascii_case("true"),
+        Err(_) => false,
+    }
+}
+
+/// Retrieves whether the log of the previous run is moved to `<PATH_LOG>.1` on startup instead of being deleted,
+/// from the environment variable LOG_ROTATE. It's disabled unless the variable is set to `true`.
+pub fn retrieve_log_rotate() -> bool {
+    match std::env::var(LOG_ROTATE) {
+        Ok(log_rotate) => log_rotate.trim().eq_ignore_ascii_case("true"),
+        Err(_) => false,
+    }
+}
+
+/// Retrieves whether the peers listened to are asked for the transactions of their mempool on connect, from the
+/// environment variable REQUEST_MEMPOOL. It's disabled unless the variable is set to `true`, since peers that
+/// don't serve bloom filters disconnect the node when asked.
+pub fn retrieve_request_mempool() -> bool {
+    match std::env::var(REQUEST_MEMPOOL) {
+        Ok(request_mempool) => request_mempool.trim().eq_ignore_ascii_case("true"),
+        Err(_) => false,
+    }
+}
+
+/// Retrieves the minimum fee rate, in satoshis per virtual byte, a transaction needs to be broadcasted from the
+/// environment variable MIN_RELAY_FEE_RATE. If the variable is not found, cannot be parsed or is negative, the
+/// default value DEFAULT_MIN_RELAY_FEE_RATE (1 sat/vByte) is returned.
+pub fn retrieve_min_relay_fee_rate() -> f64 {
+    retrieve_env(MIN_RELAY_FEE_RATE, DEFAULT_MIN_RELAY_FEE_RATE, |fee_rate| {
+        *fee_rate >= 0.0
+    })
+}
+
+/// Retrieves the maximum size, in bytes, of a transaction the node broadcasts from the environment variable
+/// MAX_TX_SIZE. If the variable is not found or cannot be parsed, the default value DEFAULT_MAX_TX_SIZE
+/// (100,000 bytes) is returned.
+pub fn retrieve_max_tx_size() -> usize {
+    retrieve_env(MAX_TX_SIZE, DEFAULT_MAX_TX_SIZE, |_| true)
+}
+
 /// Reads the starting date from the environment variable STARTING_DATE and returns it as a u32.
 ///
 /// # Errors
@@ -137,7 +536,8 @@ pub fn read_timestamp() -> Result<u32, NodeError> {
 }
 
 /// Reads the last block header from the block headers file and returns it as a byte vector.
-/// If the block headers file is empty, the genesis block header is returned.
+/// If the block headers file is empty, the genesis block header of the network is written to it
+/// and returned.
 ///
 /// # Errors
 ///
@@ -148,6 +548,7 @@ pub fn read_timestamp() -> Result<u32, NodeError> {
 /// Returns a NodeError::FailedToSeek error if the block headers file cannot be seeked.
 pub fn read_last_block_header() -> Result<BlockHeaderBytes, NodeError> {
     let dir_headers_file = obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?;
+    let _headers_file_guard = lock_headers_file()?;
     let mut file = OpenOptions::new()
         .read(true)
         .write(true)
@@ -159,17 +560,19 @@ pub fn read_last_block_header() -> Result<BlockHeaderBytes, NodeError> {
         .seek(io::SeekFrom::End(0))
         .map_err(|_| NodeError::FailedToRead("Failed to seek end of file".to_string()))?;
 
+    let complete_headers_size = file_size - file_size % LENGTH_BLOCK_HEADERS as u64;
     let mut pos = 0;
 
-    if file_size >= LENGTH_BLOCK_HEADERS as u64 {
-        pos = file_size - LENGTH_BLOCK_HEADERS as u64
+    if complete_headers_size >= LENGTH_BLOCK_HEADERS as u64 {
+        pos = complete_headers_size - LENGTH_BLOCK_HEADERS as u64
     }
 
     if pos == 0 {
         println!("Writing genesis block header to file");
-        file.write_all(&GENESIS_BLOCK_HEADER.to_bytes())
+        let genesis_block_header = retrieve_genesis_block_header().to_bytes();
+        file.write_all(&genesis_block_header)
             .map_err(|_| NodeError::FailedToWrite("Failed to write to file".to_string()))?;
-        return Ok(GENESIS_BLOCK_HEADER.to_bytes());
+        return Ok(genesis_block_header);
     }
 
     file.seek(io::SeekFrom::Start(pos)).map_err(|_| {
@@ -181,6 +584,40 @@ pub fn read_last_block_header() -> Result<BlockHeaderBytes, NodeError> {
     Ok(buffer.to_vec())
 }
 
+/// Truncates the block headers file back to its last complete header, removing the partial header
+/// left at its end when the node stops in the middle of an append.
+///
+/// # Arguments
+///
+/// * `path` - The path of the block headers file.
+///
+/// # Returns
+///
+/// The number of bytes removed from the end of the file, 0 if it was not truncated.
+///
+/// # Errors
+///
+/// Returns a `NodeError::FailedToOpenFile` if the file cannot be opened, or a
+/// `NodeError::FailedToWrite` if it cannot be truncated.
+pub fn repair_headers_file(path: &str) -> Result<u64, NodeError> {
+    let _headers_file_guard = lock_headers_file()?;
+    let file = OpenOptions::new().write(true).open(path).map_err(|_| {
+        NodeError::FailedToOpenFile("Failed to open block headers file".to_string())
+    })?;
+    let file_size = file
+        .metadata()
+        .map_err(|_| NodeError::FailedToRead("Failed to obtain headers file len".to_string()))?
+        .len();
+
+    let partial_header_size = file_size % LENGTH_BLOCK_HEADERS as u64;
+    if partial_header_size > 0 {
+        file.set_len(file_size - partial_header_size).map_err(|_| {
+            NodeError::FailedToWrite("Failed to truncate block headers file".to_string())
+        })?;
+    }
+    Ok(partial_header_size)
+}
+
 /// Reads the initial block headers from a file containing block header bytes.
 ///
 /// # Returns
@@ -194,12 +631,90 @@ pub fn read_last_block_header() -> Result<BlockHeaderBytes, NodeError> {
 /// the block header bytes.
 pub fn read_initial_block_headers_from_file() -> Result<Vec<BlockHeader>, NodeError> {
     let dir_headers_file = obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?;
-    let mut file = OpenOptions::new()
-        .read(true)
-        .open(dir_headers_file)
-        .map_err(|_| {
-            NodeError::FailedToOpenFile("Failed to open block headers file".to_string())
-        })?;
+    read_initial_block_headers_from_path(&dir_headers_file, read_timestamp()?)
+}
+
+/// Reads the block headers not older than the given timestamp from the block headers file, along
+/// with the height of the first of them, so the blocks mined since then can be scanned.
+///
+/// # Arguments
+///
+/// * `timestamp` - The timestamp of the oldest header to read.
+///
+/// # Returns
+///
+/// The height of the first header read and the headers, in chain order.
+///
+/// # Errors
+///
+/// Returns an error of type `NodeError` if there is a problem reading or parsing the block
+/// header bytes.
+pub fn read_block_headers_since(timestamp: u32) -> Result<(u32, Vec<BlockHeader>), NodeError> {
+    let dir_headers_file = obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?;
+    read_block_headers_since_in(&dir_headers_file, timestamp)
+}
+
+/// Returns the height of the given block in the header chain, or None if its header is not in the
+/// block headers file.
+///
+/// # Errors
+///
+/// Returns an error of type `NodeError` if there is a problem reading or parsing the block
+/// header bytes.
+pub fn read_block_height(block_header: &BlockHeader) -> Result<Option<u32>, NodeError> {
+    let dir_headers_file = obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?;
+    read_block_height_in(&dir_headers_file, block_header)
+}
+
+/// Returns the height of the given block in the block headers file at the given path. The file is
+/// searched from its end back to the headers too old to be mined after the block: the timestamp of
+/// a block can be earlier than the one of its parent, but not by more than the time a block can be
+/// ahead of the clock of the nodes, so a margin of twice that is kept.
+fn read_block_height_in(path: &str, block_header: &BlockHeader) -> Result<Option<u32>, NodeError> {
+    let oldest_timestamp = block_header
+        .timestamp
+        .saturating_sub(2 * MAX_FUTURE_BLOCK_TIME);
+    let (first_height, block_headers) = read_block_headers_since_in(path, oldest_timestamp)?;
+    Ok(block_headers
+        .iter()
+        .position(|header| header.hash == block_header.hash)
+        .map(|position| first_height + position as u32))
+}
+
+/// Reads the block headers not older than the given timestamp from the block headers file at the
+/// given path, along with the height of the first of them.
+fn read_block_headers_since_in(
+    path: &str,
+    timestamp: u32,
+) -> Result<(u32, Vec<BlockHeader>), NodeError> {
+    let block_headers = read_initial_block_headers_from_path(path, timestamp)?;
+    let file_size = std::fs::metadata(path)
+        .map_err(|_| NodeError::FailedToRead("Failed to read block headers file".to_string()))?
+        .len();
+    let headers_count = file_size / LENGTH_BLOCK_HEADERS as u64;
+    let first_height = headers_count.saturating_sub(block_headers.len() as u64);
+    Ok((first_height as u32, block_headers))
+}
+
+/// Reads the block headers newer than the starting timestamp from the block headers file at the
+/// given path. The file is expected to be repaired already by `repair_headers_file`.
+///
+/// # Arguments
+///
+/// * `path` - The path of the block headers file.
+/// * `starting_timestamp` - The timestamp of the oldest header to read.
+///
+/// # Errors
+///
+/// Returns an error of type `NodeError` if there is a problem reading or parsing the block
+/// header bytes.
+fn read_initial_block_headers_from_path(
+    path: &str,
+    starting_timestamp: u32,
+) -> Result<Vec<BlockHeader>, NodeError> {
+    let mut file = OpenOptions::new().read(true).open(path).map_err(|_| {
+        NodeError::FailedToOpenFile("Failed to open block headers file".to_string())
+    })?;
 
     println!("Getting initial block headers from file");
 
@@ -209,8 +724,8 @@ pub fn read_initial_block_headers_from_file() -> Result<Vec<BlockHeader>, NodeEr
         .seek(io::SeekFrom::End(0))
         .map_err(|_| NodeError::FailedToRead("Failed to seek end of file".to_string()))?;
 
-    let pos = file_size - LENGTH_BLOCK_HEADERS as u64;
-    read_block_headers(pos, file, &mut initial_block_headers)?;
+    let pos = file_size.saturating_sub(LENGTH_BLOCK_HEADERS as u64);
+    read_block_headers(pos, file, starting_timestamp, &mut initial_block_headers)?;
 
     initial_block_headers.reverse();
 
@@ -222,6 +737,7 @@ pub fn read_initial_block_headers_from_file() -> Result<Vec<BlockHeader>, NodeEr
 ///
 /// * `pos` - The initial position from which to start reading in the file.
 /// * `file` - A mutable reference to a `File` to read from.
+/// * `starting_timestamp` - The timestamp of the oldest header to read.
 /// * `initial_block_headers` - A mutable reference to a vector of `BlockHeader` instances
 ///                             to store the read block headers.
 ///
@@ -237,10 +753,9 @@ pub fn read_initial_block_headers_from_file() -> Result<Vec<BlockHeader>, NodeEr
 fn read_block_headers(
     mut pos: u64,
     mut file: File,
+    starting_timestamp: u32,
     initial_block_headers: &mut Vec<BlockHeader>,
 ) -> Result<(), NodeError> {
-    let starting_timestamp = read_timestamp()?;
-
     while pos > 0 {
         file.seek(io::SeekFrom::Start(pos)).map_err(|_| {
             NodeError::FailedToRead("Failed to seek position while reading from file".to_string())
@@ -265,7 +780,7 @@ fn read_block_headers(
 #[cfg(test)]
 mod tests {
 
-    use std::net::IpAddr;
+    use std::net::{IpAddr, Ipv4Addr};
 
     use crate::messages::block_message::BlockMessage;
 
@@ -325,4 +840,196 @@ mod tests {
         ];
         assert_eq!(ips, expected_ips);
     }
+
+    #[test]
+    fn test_headers_since_a_timestamp_are_read_with_their_height() -> Result<(), NodeError> {
+        let path = "blocks-test/test_headers_since.bin";
+        let mut headers = GENESIS_BLOCK_HEADER.to_bytes();
+        for timestamp in [1_700_000_000, 1_700_000_600, 1_700_001_200] {
+            let block_header = BlockHeader {
+                timestamp,
+                ..GENESIS_BLOCK_HEADER
+            };
+            headers.extend(block_header.to_bytes());
+        }
+        std::fs::write(path, &headers)
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+
+        let headers_since = read_block_headers_since_in(path, 1_700_000_600);
+        let _ = std::fs::remove_file(path);
+
+        let (first_height, block_headers) = headers_since?;
+        assert_eq!(first_height, 2);
+        let timestamps: Vec<u32> = block_headers
+            .iter()
+            .map(|header| header.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![1_700_000_600, 1_700_001_200]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_height_is_read_from_the_header_chain() -> Result<(), NodeError> {
+        let path = "blocks-test/test_block_height.bin";
+        let mut headers = GENESIS_BLOCK_HEADER.to_bytes();
+        let mut block_headers = Vec::new();
+        for timestamp in [1_700_000_000, 1_700_000_600, 1_699_999_000] {
+            let block_header = BlockHeader {
+                timestamp,
+                ..GENESIS_BLOCK_HEADER
+            };
+            headers.extend(block_header.to_bytes());
+            block_headers.push(BlockHeader::from_bytes(&block_header.to_bytes())?);
+        }
+        std::fs::write(path, &headers)
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+        let unknown = BlockHeader::from_bytes(
+            &BlockHeader {
+                nonce: 7,
+                ..block_headers[1].clone()
+            }
+            .to_bytes(),
+        )?;
+
+        let heights = [&block_headers[0], &block_headers[1], &unknown]
+            .map(|block_header| read_block_height_in(path, block_header));
+        let _ = std::fs::remove_file(path);
+
+        let [first, second, unknown] = heights;
+        assert_eq!(first?, Some(1));
+        assert_eq!(second?, Some(2));
+        assert_eq!(unknown?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_header_is_removed_from_headers_file() -> Result<(), NodeError> {
+        let path = "blocks-test/test_truncated_headers.bin";
+        let mut headers = GENESIS_BLOCK_HEADER.to_bytes();
+        for block in [
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+            "blocks-test/0000000000000005847b65f037ec3d08f499c3c22ae6723ffefee1adca3e9af5.bin",
+        ] {
+            let block_data = std::fs::read(block)
+                .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?;
+            headers.extend(&block_data[..LENGTH_BLOCK_HEADERS]);
+        }
+        let complete_headers = headers.clone();
+        headers.extend([0xab; 40]);
+        std::fs::write(path, &headers)
+            .map_err(|_| NodeError::FailedToWrite("Failed to write headers".to_string()))?;
+
+        let removed_bytes = repair_headers_file(path);
+        let initial_block_headers = read_initial_block_headers_from_path(path, 0);
+        let repaired_headers = std::fs::read(path);
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(removed_bytes?, 40);
+        let initial_block_headers = initial_block_headers?;
+        assert_eq!(initial_block_headers.len(), 2);
+        assert_eq!(
+            initial_block_headers[1].to_bytes(),
+            complete_headers[2 * LENGTH_BLOCK_HEADERS..]
+        );
+        assert_eq!(repaired_headers.ok(), Some(complete_headers));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failing_resolver_falls_back_to_cached_peers() -> Result<(), NodeError> {
+        let cache_path = "blocks-test/test_peers_cache.txt";
+        let peers = vec![
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 18333),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 18333),
+        ];
+        let attempts = std::cell::Cell::new(0);
+        let failing_resolver = || {
+            attempts.set(attempts.get() + 1);
+            Err(NodeError::FailedToConnectDNS("DNS outage".to_string()))
+        };
+
+        let resolved = resolve_peers(|| Ok(peers.clone()), 2, Duration::ZERO, Some(cache_path))?;
+        let fallback = resolve_peers(failing_resolver, 2, Duration::ZERO, Some(cache_path));
+        let _ = std::fs::remove_file(cache_path);
+        let without_cache = resolve_peers(failing_resolver, 0, Duration::ZERO, Some(cache_path));
+
+        assert_eq!(resolved, peers);
+        assert_eq!(fallback?, peers);
+        assert_eq!(attempts.get(), 4);
+        assert!(matches!(
+            without_cache,
+            Err(NodeError::FailedToConnectDNS(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_list_is_used_without_consulting_dns() -> Result<(), NodeError> {
+        let dns_consulted = std::cell::Cell::new(false);
+        let resolve = || {
+            dns_consulted.set(true);
+            Ok(Vec::new())
+        };
+
+        let peers = select_peers(Some("127.0.0.1:18444, 10.0.0.1:18333,"), resolve)?;
+        let skipped = parse_peer_list("127.0.0.1:18444,localhost:18333");
+
+        assert_eq!(
+            peers,
+            vec![
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 18444),
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 18333),
+            ]
+        );
+        assert!(!dns_consulted.get());
+        assert_eq!(
+            skipped,
+            vec![SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                18444
+            )]
+        );
+        select_peers(Some(" "), resolve)?;
+        assert!(dns_consulted.get());
+        Ok(())
+    }
+
+    #[test]
+    fn test_retrieve_env_trims_and_validates_the_value() {
+        std::env::set_var("TEST_RETRIEVE_ENV", " 7 ");
+        assert_eq!(
+            retrieve_env("TEST_RETRIEVE_ENV", 1, |value: &u64| *value > 0),
+            7
+        );
+
+        std::env::set_var("TEST_RETRIEVE_ENV", "0");
+        assert_eq!(
+            retrieve_env("TEST_RETRIEVE_ENV", 1, |value: &u64| *value > 0),
+            1
+        );
+
+        std::env::set_var("TEST_RETRIEVE_ENV", "seven");
+        assert_eq!(
+            retrieve_env("TEST_RETRIEVE_ENV", 1, |value: &u64| *value > 0),
+            1
+        );
+
+        std::env::remove_var("TEST_RETRIEVE_ENV");
+        assert_eq!(
+            retrieve_env("TEST_RETRIEVE_ENV", 1, |value: &u64| *value > 0),
+            1
+        );
+    }
+
+    #[test]
+    fn test_retrieve_max_workers() {
+        std::env::set_var("TEST_MAX_WORKERS", "3");
+        assert_eq!(retrieve_max_workers("TEST_MAX_WORKERS"), 3);
+
+        std::env::set_var("TEST_MAX_WORKERS", "0");
+        assert_eq!(retrieve_max_workers("TEST_MAX_WORKERS"), MAX_THREADS);
+
+        std::env::remove_var("TEST_MAX_WORKERS");
+        assert_eq!(retrieve_max_workers("TEST_MAX_WORKERS"), MAX_THREADS);
+    }
 }
@@ -1,16 +1,35 @@
-use std::net::TcpStream;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
 
 use crate::{
+    block::{block_hash::BlockHash, tx_hash::TxHash},
     connectors::peer_connector::{receive_message, send_message},
-    constants::{COMMAND_NAME_PONG, MSG_BLOCK, MSG_TX},
+    constants::{
+        COMMAND_NAME_GETCFHEADERS, COMMAND_NAME_GETCFILTERS, LENGTH_BLOCK_HEADERS,
+        MSG_FILTERED_BLOCK, MSG_TX, MSG_WITNESS_BLOCK,
+    },
     header::Header,
-    messages::{get_data_message::GetDataMessage, inv_message::InvMessage},
+    messages::{
+        compact_filter_message::{CFHeadersMessage, CFilterMessage, GetCFiltersMessage},
+        feefilter_message::FeeFilterMessage,
+        get_data_message::GetDataMessage,
+        inv_message::{InvMessage, InvType},
+        merkle_block_message::MerkleBlockMessage,
+        ping_message::PingMessage,
+    },
+    node::message_type::MessageType,
     node_error::NodeError,
     transactions::transaction::Transaction,
+    utils::Utils,
 };
+use bitcoin_hashes::{sha256d, Hash};
 
 /// Handles a `ping` message received from a peer. The function receives a `ping` message from a peer over the given `TcpStream` and returns a `pong` message to the peer.
-/// The `ping` message is expected to return via the TcpStream a 8-byte nonce. The function reads the message from the stream and returns a `pong` message containing the nonce.
+/// The `ping` message is expected to return via the TcpStream a 8-byte nonce. The function parses the nonce from the payload and returns a `pong` message echoing it, so the peer can match it to its ping.
+/// A `ping` without an 8-byte nonce, such as the ones sent before BIP31, is ignored without answering it.
 ///
 /// # Arguments
 ///
@@ -23,18 +42,17 @@ use crate::{
 ///
 /// # Errors
 ///
-/// Returns a `NodeError` if an error occurs while receiving the message over the TCP connection.
+/// Returns a `NodeError` if an error occurs while receiving the message or sending the `pong` over the
+/// TCP connection.
 pub fn send_pong_message(stream: &mut TcpStream, header: &Header) -> Result<(), NodeError> {
     let payload_size = header.payload_size();
     let ping_payload = receive_message(stream, payload_size)?;
-    let pong_header = Header::create_header(&ping_payload, COMMAND_NAME_PONG)?;
-    let mut pong_message = Vec::new();
-    pong_message.extend_from_slice(&pong_header);
-    pong_message.extend_from_slice(&ping_payload);
-    if let Err(e) = send_message(stream, pong_message) {
-        Err(e)
-    } else {
-        Ok(())
+    match PingMessage::from_bytes(&ping_payload) {
+        Ok(ping) => send_message(stream, ping.pong_message()?),
+        Err(_) => {
+            println!("Ignoring a ping without an 8-byte nonce");
+            Ok(())
+        }
     }
 }
 
@@ -60,7 +78,29 @@ pub fn receive_addr_message(stream: &mut TcpStream, header: &Header) -> Result<(
     Ok(())
 }
 
-/// Handles an incoming 'feefilter' message received from its peer. The “feefilter” messages allows a node to inform its peers that it will not accept transactions below a specified fee rate into its mempool, and therefore that the peers can skip relaying inv messages for transactions below that fee rate to that node. The receiving peer may choose to ignore the message and not filter transaction inv messages, which is what is done in our case.
+/// Handles an incoming 'sendcmpct' message received from its peer. The “sendcmpct” message (BIP152) announces that the peer can relay blocks as compact blocks. Compact blocks aren't supported by this node, so the payload is read and discarded, keeping the connection alive.
+///
+/// # Arguments
+///
+/// * `stream` - A mutable reference to the reader the message is being received from.
+/// * `header` - A reference to the header of the message.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating whether the operation was successful or not.
+///
+/// # Errors
+///
+/// This function may return a `NodeError` if the payload could not be read.
+pub fn receive_sendcmpct_message<R: Read>(
+    stream: &mut R,
+    header: &Header,
+) -> Result<(), NodeError> {
+    header.skip_payload(stream)
+}
+
+/// Handles an incoming 'feefilter' message received from its peer. The “feefilter” messages allows a node to inform its peers that it will not accept transactions below a specified fee rate into its mempool, and therefore that the peers can skip relaying inv messages for transactions below that fee rate to that node. The fee rate is stored for the peer, so transactions it would ignore are broadcasted to other peers.
+/// A `feefilter` whose payload is not an 8-byte fee rate is logged and ignored.
 ///
 /// # Arguments
 ///
@@ -73,10 +113,20 @@ pub fn receive_addr_message(stream: &mut TcpStream, header: &Header) -> Result<(
 ///
 /// # Errors
 ///
-/// This function may return a `NodeError` if there was an error reading or parsing the message.
+/// This function may return a `NodeError` if there was an error reading the message or storing the fee rate.
 pub fn receive_feefilter_message(stream: &mut TcpStream, header: &Header) -> Result<(), NodeError> {
     let payload_size = header.payload_size();
-    receive_message(stream, payload_size)?;
+    let payload = receive_message(stream, payload_size)?;
+    let feefilter = match FeeFilterMessage::from_bytes(&payload) {
+        Ok(feefilter) => feefilter,
+        Err(e) => {
+            println!("Ignoring a malformed feefilter: {:?}", e);
+            return Ok(());
+        }
+    };
+    if let Ok(peer) = stream.peer_addr() {
+        feefilter.store_for_peer(peer)?;
+    }
     Ok(())
 }
 
@@ -108,7 +158,8 @@ pub fn receive_not_found_message(stream: &mut TcpStream, header: &Header) -> Nod
     NodeError::SyncNodeDoesNotHaveTheBlock("NotFound".to_string())
 }
 
-/// Handles the inv message received over a TCP stream, if the inv type is MSG_BLOCK, it extracts the block hash, if the inv type is MSG_TX, it sends a GetData Message to receive the new transaction.
+/// Handles the inv message received over a TCP stream, if the inv type is a block, it extracts the block hash, if the inv type is a transaction, it sends a GetData Message to receive the new transaction.
+/// Inventories of other types are ignored.
 ///
 /// # Arguments
 ///
@@ -129,16 +180,44 @@ pub fn receive_and_handle_inv_message(
 ) -> Result<Vec<u8>, NodeError> {
     let inv_message = receive_message(stream, header.payload_size())?;
     let inv_message = InvMessage::from_bytes(&inv_message)?;
+    handle_inv_message(&inv_message, stream)
+}
 
-    for inv in inv_message.inventory {
-        if inv.inv_type == MSG_BLOCK {
-            return Ok(inv.hash.to_vec());
-        } else if inv.inv_type == MSG_TX {
-            let data_message = GetDataMessage::new(1, MSG_TX, inv.hash)?;
-            data_message.send_message(stream)?;
+/// Handles the inventories of an inv message, such as the answer of a peer to a mempool message.
+/// A single `getdata` message is sent for the transactions before the first block.
+///
+/// # Arguments
+///
+/// * `inv_message` - The inv message received.
+/// * `stream` - A mutable reference to the stream of the peer that sent it.
+///
+/// # Returns
+///
+/// The hash of the first block of the inventory, or an empty vector if it has no blocks.
+///
+/// # Errors
+///
+/// Returns a `NodeError` if a `getdata` message could not be sent.
+pub fn handle_inv_message<W: Write>(
+    inv_message: &InvMessage,
+    stream: &mut W,
+) -> Result<Vec<u8>, NodeError> {
+    let mut tx_ids = Vec::new();
+    let mut block_hash = Vec::new();
+    for (inv_type, hash) in inv_message.items() {
+        match inv_type {
+            InvType::Block | InvType::WitnessBlock => {
+                block_hash = hash.to_vec();
+                break;
+            }
+            InvType::Tx | InvType::WitnessTx => tx_ids.push(hash),
+            _ => println!("Ignoring inventory of unsupported type {:?}", inv_type),
         }
     }
-    Ok(Vec::new())
+    if !tx_ids.is_empty() {
+        GetDataMessage::with_hashes(MSG_TX, &tx_ids).send_message(stream)?;
+    }
+    Ok(block_hash)
 }
 
 /// Receives a transaction message over a TCP Stream
@@ -160,3 +239,433 @@ pub fn receive_and_handle_inv_message(
 pub fn receive_tx_message(stream: &mut TcpStream) -> Result<Transaction, NodeError> {
     Transaction::read_transaction(stream)
 }
+
+/// Requests a transaction from a peer and waits for it.
+///
+/// A `getdata` message asking for the transaction is sent, and the messages received from the peer
+/// are handled until the transaction arrives. Transactions relayed by the peer in the meantime are
+/// discarded.
+///
+/// # Arguments
+///
+/// * `tx_id` - The id of the transaction to fetch, in internal byte order.
+/// * `stream` - A mutable reference to a `TcpStream` connected to the peer.
+///
+/// # Returns
+///
+/// The `Transaction` sent by the peer.
+///
+/// # Errors
+///
+/// Returns a `NodeError::TransactionNotFound` if the peer replies with a `notfound` message, a
+/// `NodeError::FailedToConnect` if the connection is closed, or a `NodeError` if a message could
+/// not be sent or received.
+pub fn fetch_transaction(tx_id: &TxHash, stream: &mut TcpStream) -> Result<Transaction, NodeError> {
+    let hash: [u8; 32] = tx_id.as_slice().try_into().map_err(|_| {
+        NodeError::FailedToConvert("A transaction id must be 32 bytes long".to_string())
+    })?;
+    GetDataMessage::new(1, MSG_TX, hash)?.send_message(stream)?;
+
+    loop {
+        if !Utils::is_tcpstream_connected(stream) {
+            return Err(NodeError::FailedToConnect(
+                "The TCP stream is not connected anymore".to_string(),
+            ));
+        }
+        let mut header = Header::new(stream)?;
+        match header.extract_command_name()? {
+            MessageType::Tx => {
+                let transaction = receive_tx_message(stream)?;
+                if transaction.tx_id() == *tx_id {
+                    return Ok(transaction);
+                }
+            }
+            MessageType::NotFound => {
+                header.skip_payload(stream)?;
+                return Err(NodeError::TransactionNotFound(format!(
+                    "The peer does not have the transaction {}",
+                    Utils::bytes_to_hex(tx_id)
+                )));
+            }
+            MessageType::Ping => send_pong_message(stream, &header)?,
+            MessageType::FeeFilter => receive_feefilter_message(stream, &header)?,
+            MessageType::SendCmpct => receive_sendcmpct_message(stream, &header)?,
+            _ => header.skip_payload(stream)?,
+        }
+    }
+}
+
+/// Asks a peer for a block, which also finds out if it has it.
+///
+/// A `getdata` message asking for the block is sent, and the messages received from the peer are
+/// handled until it answers with the block or with a `notfound` message. The block received is
+/// returned, so asking a peer whether it has a block downloads it only once. Blocks whose hash is not
+/// the requested one, such as new blocks the peer announces, are skipped.
+///
+/// # Arguments
+///
+/// * `block_hash` - The hash of the block, in internal byte order.
+/// * `stream` - A mutable reference to a `TcpStream` connected to the peer.
+///
+/// # Returns
+///
+/// The bytes of the block if the peer sent it, or `None` if it replied that it doesn't have it.
+///
+/// # Errors
+///
+/// Returns a `NodeError::FailedToConnect` if the connection is closed, or a `NodeError` if a
+/// message could not be sent or received, such as when the peer doesn't answer in time.
+pub fn request_block(
+    block_hash: BlockHash,
+    stream: &mut TcpStream,
+) -> Result<Option<Vec<u8>>, NodeError> {
+    GetDataMessage::new(1, MSG_WITNESS_BLOCK, block_hash)?.send_message(stream)?;
+
+    loop {
+        if !Utils::is_tcpstream_connected(stream) {
+            return Err(NodeError::FailedToConnect(
+                "The TCP stream is not connected anymore".to_string(),
+            ));
+        }
+        let mut header = Header::new(stream)?;
+        let command_name = header.extract_command_name()?;
+        match command_name {
+            MessageType::Block => {
+                let block = receive_message(stream, header.payload_size())?;
+                if is_block_with_hash(&block, &block_hash) {
+                    return Ok(Some(block));
+                }
+                println!("Skipping a block that was not requested");
+            }
+            MessageType::NotFound => {
+                header.skip_payload(stream)?;
+                return Ok(None);
+            }
+            MessageType::Ping => send_pong_message(stream, &header)?,
+            MessageType::FeeFilter => receive_feefilter_message(stream, &header)?,
+            _ => header.skip_payload(stream)?,
+        }
+    }
+}
+
+/// Checks if the header of a block hashes to the given hash, in internal byte order.
+fn is_block_with_hash(block: &[u8], block_hash: &BlockHash) -> bool {
+    match block.get(..LENGTH_BLOCK_HEADERS) {
+        Some(header) => sha256d::Hash::hash(header).to_byte_array() == *block_hash,
+        None => false,
+    }
+}
+
+/// Requests a filtered block from a peer: the peer answers with a merkleblock (BIP37) proving the
+/// transactions of the block that match the filter loaded in it, followed by those transactions.
+///
+/// Other messages received in the meantime are handled or skipped, and transactions relayed by the
+/// peer that are not matched by the merkleblock are discarded.
+///
+/// # Arguments
+///
+/// * `stream` - A mutable reference to a `TcpStream` connected to the peer, with a filter loaded.
+/// * `block_hash` - The hash of the block, in internal byte order.
+/// * `timeout` - The maximum time to wait for the merkleblock and all its matched transactions.
+///
+/// # Returns
+///
+/// The merkleblock and the matched transactions of the block.
+///
+/// # Errors
+///
+/// Returns a `NodeError::SyncNodeDoesNotHaveTheBlock` if the peer answers with a `notfound`
+/// message, a `NodeError::FilteredBlockTimeout` if the merkleblock or any of its transactions
+/// doesn't arrive before the timeout, a `NodeError::InvalidMerkleTree` or
+/// `NodeError::InvalidMerkleRoot` if the merkleblock doesn't prove its transactions, or a
+/// `NodeError` if a message could not be sent or received.
+pub fn fetch_merkle_block(
+    stream: &mut TcpStream,
+    block_hash: BlockHash,
+    timeout: Duration,
+) -> Result<(MerkleBlockMessage, Vec<Transaction>), NodeError> {
+    let previous_timeout = stream
+        .read_timeout()
+        .map_err(|_| NodeError::ReadTimeoutFromStream("Failed to get read timeout".to_string()))?;
+    let deadline = Instant::now() + timeout;
+    let result = receive_merkle_block(stream, block_hash, deadline).map_err(|e| match e {
+        NodeError::FailedToReceiveMessage(msg) if Instant::now() >= deadline => {
+            NodeError::FilteredBlockTimeout(format!(
+                "The filtered block didn't arrive in time: {}",
+                msg
+            ))
+        }
+        other => other,
+    });
+    stream.set_read_timeout(previous_timeout).map_err(|_| {
+        NodeError::ReadTimeoutFromStream("Failed to restore read timeout".to_string())
+    })?;
+    result
+}
+
+/// Sets the read timeout of the stream to the time left until the deadline, so no message of a
+/// filtered block is waited for past it.
+///
+/// # Errors
+///
+/// Returns a `NodeError::FilteredBlockTimeout` if the deadline already passed, or a
+/// `NodeError::ReadTimeoutFromStream` if the timeout could not be set.
+fn wait_until(stream: &mut TcpStream, deadline: Instant) -> Result<(), NodeError> {
+    let time_left = deadline
+        .checked_duration_since(Instant::now())
+        .filter(|time_left| !time_left.is_zero())
+        .ok_or_else(|| {
+            NodeError::FilteredBlockTimeout("The filtered block didn't arrive in time".to_string())
+        })?;
+    stream
+        .set_read_timeout(Some(time_left))
+        .map_err(|_| NodeError::ReadTimeoutFromStream("Failed to set read timeout".to_string()))
+}
+
+/// Requests a filtered block and receives the merkleblock and its matched transactions, waiting
+/// for each message no longer than the deadline.
+fn receive_merkle_block(
+    stream: &mut TcpStream,
+    block_hash: BlockHash,
+    deadline: Instant,
+) -> Result<(MerkleBlockMessage, Vec<Transaction>), NodeError> {
+    GetDataMessage::new(1, MSG_FILTERED_BLOCK, block_hash)?.send_message(stream)?;
+
+    let merkle_block = loop {
+        wait_until(stream, deadline)?;
+        let mut header = Header::new(stream)?;
+        match header.extract_command_name()? {
+            MessageType::MerkleBlock => {
+                let payload = receive_message(stream, header.payload_size())?;
+                break MerkleBlockMessage::from_bytes(&payload)?;
+            }
+            MessageType::NotFound => {
+                header.skip_payload(stream)?;
+                return Err(NodeError::SyncNodeDoesNotHaveTheBlock(
+                    "The peer doesn't have the filtered block".to_string(),
+                ));
+            }
+            MessageType::Ping => send_pong_message(stream, &header)?,
+            _ => header.skip_payload(stream)?,
+        }
+    };
+
+    let mut missing_tx_ids = merkle_block.matched_tx_ids()?;
+    let mut transactions = Vec::new();
+    while !missing_tx_ids.is_empty() {
+        wait_until(stream, deadline)?;
+        let mut header = Header::new(stream)?;
+        match header.extract_command_name()? {
+            MessageType::Tx => {
+                let transaction = receive_tx_message(stream)?;
+                let tx_id = transaction.tx_id();
+                if let Some(position) = missing_tx_ids.iter().position(|id| *id == tx_id) {
+                    missing_tx_ids.remove(position);
+                    transactions.push(transaction);
+                }
+            }
+            MessageType::Ping => send_pong_message(stream, &header)?,
+            _ => header.skip_payload(stream)?,
+        }
+    }
+    Ok((merkle_block, transactions))
+}
+
+/// Requests the compact filters (BIP157) of a range of blocks from a peer, and verifies them against
+/// the filter headers the peer sends for the same range.
+///
+/// # Arguments
+///
+/// * `stream` - A mutable reference to a `TcpStream` connected to a peer serving compact filters.
+/// * `start_height` - The height of the first block of the range.
+/// * `block_hashes` - The hashes of the blocks of the range, in order.
+///
+/// # Returns
+///
+/// A `Result` containing the filters of the blocks of the range, in order.
+///
+/// # Errors
+///
+/// Returns a `NodeError::InvalidFilterHeader` if the filters don't answer the request or don't match
+/// their filter headers, or a `NodeError` if the messages could not be sent or received.
+pub fn fetch_compact_filters(
+    stream: &mut TcpStream,
+    start_height: u32,
+    block_hashes: &[BlockHash],
+) -> Result<Vec<CFilterMessage>, NodeError> {
+    let stop_hash = match block_hashes.last() {
+        Some(stop_hash) => *stop_hash,
+        None => return Ok(vec![]),
+    };
+    let request = GetCFiltersMessage::new(start_height, stop_hash);
+    request.send_message(stream, COMMAND_NAME_GETCFHEADERS)?;
+    let cfheaders =
+        CFHeadersMessage::from_bytes(&receive_payload_of(stream, MessageType::CFHeaders)?)?;
+
+    request.send_message(stream, COMMAND_NAME_GETCFILTERS)?;
+    let mut cfilters = Vec::new();
+    while cfilters.len() < block_hashes.len() {
+        cfilters.push(CFilterMessage::from_bytes(&receive_payload_of(
+            stream,
+            MessageType::CFilter,
+        )?)?);
+    }
+
+    cfheaders.validate_filters(&request, block_hashes, &cfilters)?;
+    Ok(cfilters)
+}
+
+/// Waits for the next message of the given type, answering pings and skipping other messages.
+///
+/// # Returns
+///
+/// A `Result` containing the payload of the message.
+fn receive_payload_of(
+    stream: &mut TcpStream,
+    message_type: MessageType,
+) -> Result<Vec<u8>, NodeError> {
+    loop {
+        if !Utils::is_tcpstream_connected(stream) {
+            return Err(NodeError::FailedToConnect(
+                "The TCP stream is not connected anymore".to_string(),
+            ));
+        }
+        let mut header = Header::new(stream)?;
+        let command_name = header.extract_command_name()?;
+        if command_name == message_type {
+            return receive_message(stream, header.payload_size());
+        }
+        match command_name {
+            MessageType::Ping => send_pong_message(stream, &header)?,
+            MessageType::FeeFilter => receive_feefilter_message(stream, &header)?,
+            _ => header.skip_payload(stream)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::mpsc, thread};
+
+    use super::*;
+    use crate::{
+        block::Block,
+        constants::{COMMAND_NAME_FEEFILTER, COMMAND_NAME_MERKLEBLOCK, COMMAND_NAME_PING},
+    };
+
+    /// Connects two streams through a local listener, returning the connecting and accepted ends.
+    fn connected_streams() -> Result<(TcpStream, TcpStream), NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToConnect("Failed to bind listener".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToConnect("Failed to get address".to_string()))?;
+        let stream = TcpStream::connect(addr)
+            .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+        let (peer, _) = listener
+            .accept()
+            .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+        Ok((stream, peer))
+    }
+
+    #[test]
+    fn test_a_malformed_feefilter_is_ignored() -> Result<(), NodeError> {
+        let (mut stream, mut peer) = connected_streams()?;
+        let payload = vec![1, 2, 3];
+        let mut feefilter = Header::create_header(&payload, COMMAND_NAME_FEEFILTER)?;
+        feefilter.extend(&payload);
+        send_message(&mut peer, feefilter)?;
+        let header = Header::new(&mut stream)?;
+
+        receive_feefilter_message(&mut stream, &header)?;
+
+        let peer_addr = stream
+            .peer_addr()
+            .map_err(|_| NodeError::FailedToConnect("Failed to get address".to_string()))?;
+        assert!(FeeFilterMessage::for_peer(&peer_addr).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_ping_without_nonce_is_ignored() -> Result<(), NodeError> {
+        let (mut stream, mut peer) = connected_streams()?;
+        let header = Header::from_bytes(&Header::create_header(&vec![], COMMAND_NAME_PING)?);
+
+        send_pong_message(&mut stream, &header)?;
+
+        peer.set_read_timeout(Some(Duration::from_millis(100)))
+            .map_err(|_| NodeError::ReadTimeoutFromStream("Failed to set timeout".to_string()))?;
+        let mut answer = [0u8; 1];
+        assert!(std::io::Read::read(&mut peer, &mut answer).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inv_of_transactions_sends_a_single_getdata_for_all() -> Result<(), NodeError> {
+        let tx_ids = [[1; 32], [2; 32]];
+        let mut payload = vec![tx_ids.len() as u8];
+        for tx_id in &tx_ids {
+            payload.extend(MSG_TX.to_le_bytes());
+            payload.extend(tx_id);
+        }
+        let inv_message = InvMessage::from_bytes(&payload)?;
+        let mut stream = vec![];
+
+        let block_hash = handle_inv_message(&inv_message, &mut stream)?;
+
+        assert!(block_hash.is_empty());
+        let mut header = Header::from_bytes(&stream);
+        assert_eq!(header.extract_command_name()?, MessageType::GetData);
+        assert_eq!(stream.len(), 24 + header.payload_size());
+        let get_data = GetDataMessage::from_bytes(&stream[24..])?;
+        assert_eq!(
+            get_data.items(),
+            vec![(InvType::Tx, tx_ids[0]), (InvType::Tx, tx_ids[1])]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_filtered_block_whose_transactions_never_arrive_times_out() -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin";
+        let block = Block::from_bytes(
+            &std::fs::read(block_path)
+                .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?,
+        )?;
+        let tx_ids: Vec<TxHash> = block.transactions.iter().map(|tx| tx.tx_id()).collect();
+        let mut matches = vec![false; tx_ids.len()];
+        matches[0] = true;
+        let merkle_block = MerkleBlockMessage::new(block.header.clone(), &tx_ids, &matches);
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToConnect("Failed to bind listener".to_string()))?;
+        let peer_addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToConnect("Failed to get address".to_string()))?;
+        let (done_sender, done_receiver) = mpsc::channel::<()>();
+        let peer = thread::spawn(move || -> Result<(), NodeError> {
+            let (mut stream, _) = listener
+                .accept()
+                .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+            let mut header = Header::new(&mut stream)?;
+            GetDataMessage::from_stream(&mut stream, &mut header)?;
+            let payload = merkle_block.to_bytes();
+            let mut bytes = Header::create_header(&payload, COMMAND_NAME_MERKLEBLOCK)?;
+            bytes.extend(payload);
+            send_message(&mut stream, bytes)?;
+            let _ = done_receiver.recv();
+            Ok(())
+        });
+
+        let mut stream = TcpStream::connect(peer_addr)
+            .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+        let result = fetch_merkle_block(&mut stream, [0; 32], Duration::from_millis(200));
+        let _ = done_sender.send(());
+        peer.join()
+            .map_err(|_| NodeError::FailedToJoinThread("Peer thread panicked".to_string()))??;
+
+        assert!(matches!(result, Err(NodeError::FilteredBlockTimeout(_))));
+        assert_eq!(stream.read_timeout().ok().flatten(), None);
+        Ok(())
+    }
+}
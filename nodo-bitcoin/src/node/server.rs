@@ -7,8 +7,11 @@ use std::{
 use crate::{
     config::obtain_dir_path,
     connectors::peer_connector::{receive_message, send_message},
-    constants::{BLOCK_HEADERS_FILE, LENGTH_HEADER_MESSAGE, LOCAL_IP, PORT},
+    constants::{
+        BLOCK_HEADERS_FILE, LENGTH_HEADER_MESSAGE, LOCAL_IP, PORT, SERVER_BIND_ADDR, SERVER_PORT,
+    },
     header::Header,
+    logger::Logger,
     messages::{
         block_message::BlockMessage,
         get_data_message::GetDataMessage,
@@ -17,42 +20,58 @@ use crate::{
         verack_message::{is_verack_message, VERACK_MESSAGE},
         version_message::VersionMessage,
     },
-    node::message_type::MessageType,
+    node::{
+        connection_limiter::{ConnectionLimiter, InboundPermit},
+        message_type::MessageType,
+    },
     node_error::NodeError,
     utils::Utils,
 };
 
 /// Starts the server and listens for incoming client connections.
 ///
-/// The function reads the port number from the `PORT` environment variable,
-/// binds a `TcpListener` to the specified port, and listens for incoming
-/// client connections. For each incoming connection, it spawns a new thread
-/// to handle the client connection by calling the `handle_client` function.
+/// The function reads the bind address and port from the `SERVER_BIND_ADDR` and `SERVER_PORT`
+/// environment variables (defaulting to `LOCAL_IP` and `PORT`), binds a `TcpListener` to them,
+/// and listens for incoming client connections. For each incoming connection, it spawns a new thread
+/// to handle the client connection by calling the `handle_client` function. When the node has as many
+/// connections as MAX_CONNECTIONS allows, the least recently active client is disconnected to make room
+/// for the new one.
+///
+/// # Arguments
+///
+/// * `logger` - A `Logger` for logging the messages of the clients.
 ///
 /// # Errors
 ///
 /// Returns a `Result` indicating whether the server was started successfully
 /// (`Ok(())`) or an error occurred during the server startup process (`Err`).
-pub fn start_server() -> Result<(), NodeError> {
-    let port = std::env::var(PORT)
-        .map_err(|_| NodeError::EnvironVarNotFound("PORT not found in env vars".to_string()))?
-        .parse::<u16>()
-        .map_err(|_| NodeError::FailedToParse("Invalid PORT format in env vars".to_string()))?;
-    let addr = std::env::var(LOCAL_IP)
-        .map_err(|_| NodeError::EnvironVarNotFound("Local ip no found".to_string()))?;
-
-    let listener = TcpListener::bind((addr, port))
-        .map_err(|_| NodeError::FailedToBind(format!("Failed to bind to port {}", port)))?;
+pub fn start_server(logger: Logger) -> Result<(), NodeError> {
+    let (addr, port) = server_address()?;
+    let listener = bind_server(&addr, port)?;
+    let port = listener
+        .local_addr()
+        .map_err(|_| NodeError::FailedToBind("Failed to obtain the server address".to_string()))?
+        .port();
 
     println!("Server started, listening on port {}", port);
 
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                thread::spawn(|| match handle_client(stream) {
-                    Ok(_) => println!("Connection processed!"),
-                    Err(e) => {
-                        println!("Failed to process connection: {:?}", e);
+                let logger = logger.clone();
+                thread::spawn(move || {
+                    let permit = match ConnectionLimiter::global().accept_inbound(&stream) {
+                        Ok(permit) => permit,
+                        Err(e) => {
+                            println!("Refusing connection: {:?}", e);
+                            return;
+                        }
+                    };
+                    match handle_client(stream, &permit, &logger) {
+                        Ok(_) => println!("Connection processed!"),
+                        Err(e) => {
+                            println!("Failed to process connection: {:?}", e);
+                        }
                     }
                 });
             }
@@ -64,17 +83,55 @@ pub fn start_server() -> Result<(), NodeError> {
     Ok(())
 }
 
+/// Returns the address and port the server should bind to.
+///
+/// They are read from the `SERVER_BIND_ADDR` and `SERVER_PORT` environment variables, falling back
+/// to `LOCAL_IP` and `PORT` when they are not set.
+///
+/// # Errors
+///
+/// Returns a `NodeError::EnvironVarNotFound` if neither variable of a pair is set, or a
+/// `NodeError::FailedToParse` if the port is not a valid u16.
+pub fn server_address() -> Result<(String, u16), NodeError> {
+    let addr = std::env::var(SERVER_BIND_ADDR)
+        .or_else(|_| std::env::var(LOCAL_IP))
+        .map_err(|_| NodeError::EnvironVarNotFound("Local ip no found".to_string()))?;
+    let port = std::env::var(SERVER_PORT)
+        .or_else(|_| std::env::var(PORT))
+        .map_err(|_| NodeError::EnvironVarNotFound("PORT not found in env vars".to_string()))?
+        .parse::<u16>()
+        .map_err(|_| NodeError::FailedToParse("Invalid PORT format in env vars".to_string()))?;
+
+    Ok((addr, port))
+}
+
+/// Binds a `TcpListener` to the given address and port. A port of 0 binds to an ephemeral port.
+///
+/// # Errors
+///
+/// Returns a `NodeError::FailedToBind` with the attempted address if the listener could not be bound.
+pub fn bind_server(addr: &str, port: u16) -> Result<TcpListener, NodeError> {
+    TcpListener::bind((addr, port))
+        .map_err(|_| NodeError::FailedToBind(format!("Failed to bind to {}:{}", addr, port)))
+}
+
 /// Handles incoming messages from the connected stream, processing each message based on its command type.
 ///
 /// # Arguments
 ///
 /// * `stream` - A mutable reference to a TCP stream representing the connection to the node.
+/// * `permit` - The connection permit of the client, which records when it was last active.
+/// * `logger` - A `Logger` for logging the skipped messages.
 ///
 /// # Returns
 ///
 /// * `Ok(())` - If the message handling loop completes successfully.
 /// * `Err(NodeError)` - If there is an error while receiving or processing the messages.
-pub fn client_message_handler(stream: &mut TcpStream) -> Result<(), NodeError> {
+pub fn client_message_handler(
+    stream: &mut TcpStream,
+    permit: &InboundPermit,
+    logger: &Logger,
+) -> Result<(), NodeError> {
     let dir_headers_file = obtain_dir_path(BLOCK_HEADERS_FILE.to_owned())?;
     let mut headers_file = OpenOptions::new()
         .read(true)
@@ -98,6 +155,7 @@ pub fn client_message_handler(stream: &mut TcpStream) -> Result<(), NodeError> {
             ))
         })?;
         let command_name = header.extract_command_name()?;
+        permit.record_activity();
 
         match command_name {
             MessageType::GetHeaders => {
@@ -118,6 +176,10 @@ pub fn client_message_handler(stream: &mut TcpStream) -> Result<(), NodeError> {
                 BlockMessage::send_message(stream, getdata_message)?;
                 count_getdata += 1;
             }
+            MessageType::Unknown(command) => {
+                logger.debug(format!("Skipping unknown command: {}", command))?;
+                header.skip_payload(stream)?;
+            }
             _ => {
                 println!("Command not supported");
                 receive_message(stream, header.payload_size())?;
@@ -136,12 +198,18 @@ pub fn client_message_handler(stream: &mut TcpStream) -> Result<(), NodeError> {
 /// # Arguments
 ///
 /// * `stream` - A mutable reference to a `TcpStream` representing the client connection.
+/// * `permit` - The connection permit of the client.
+/// * `logger` - A `Logger` for logging the messages of the client.
 ///
 /// # Errors
 ///
 /// Returns a `Result` indicating whether the handling of the client connection was successful
 /// (`Ok(())`) or an error occurred during the handshake process (`Err`).
-fn handle_client(mut stream: TcpStream) -> Result<(), NodeError> {
+fn handle_client(
+    mut stream: TcpStream,
+    permit: &InboundPermit,
+    logger: &Logger,
+) -> Result<(), NodeError> {
     if !server_handshake(&mut stream)? {
         println!("Handshake failed with node: {:?}", stream.peer_addr());
         return Err(NodeError::HandshakeFailed(
@@ -149,7 +217,7 @@ fn handle_client(mut stream: TcpStream) -> Result<(), NodeError> {
         ));
     }
     println!("Handshake completed with node: {:?}", stream.peer_addr());
-    match client_message_handler(&mut stream) {
+    match client_message_handler(&mut stream, permit, logger) {
         Ok(_) => {
             println!("Client message handling completed");
             Ok(())
@@ -200,7 +268,7 @@ pub fn server_handshake(stream: &mut TcpStream) -> Result<bool, NodeError> {
 
 #[cfg(test)]
 mod tests {
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
 
     use crate::utils::Utils;
 
@@ -222,4 +290,40 @@ mod tests {
         assert_eq!(result, expected_result);
         Ok(())
     }
+
+    #[test]
+    fn test_server_binds_to_ephemeral_port_and_accepts_connection() -> Result<(), NodeError> {
+        let listener = bind_server("127.0.0.1", 0)?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to obtain address".to_string()))?;
+        assert_ne!(addr.port(), 0);
+
+        let client = thread::spawn(move || TcpStream::connect(addr).is_ok());
+        let accepted = listener.accept();
+
+        assert!(accepted.is_ok());
+        assert!(client.join().unwrap_or(false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bind_server_to_invalid_address_fails() {
+        let result = bind_server("256.0.0.1", 0);
+
+        assert!(matches!(result, Err(NodeError::FailedToBind(msg)) if msg.contains("256.0.0.1:0")));
+    }
+
+    #[test]
+    fn test_server_address_is_read_from_the_server_variables() -> Result<(), NodeError> {
+        std::env::set_var(SERVER_BIND_ADDR, "0.0.0.0");
+        std::env::set_var(SERVER_PORT, "28333");
+        let address = server_address();
+        std::env::remove_var(SERVER_BIND_ADDR);
+        std::env::remove_var(SERVER_PORT);
+
+        assert_eq!(address?, ("0.0.0.0".to_string(), 28333));
+        Ok(())
+    }
 }
@@ -147,4 +147,48 @@ pub enum NodeError {
     FailedToCloneStream(String),
     ///Failed to delete file
     FailedToDeleteFile(String),
+    /// A different block file already exists at the path.
+    BlockFileMismatch(String),
+    /// Invalid transaction serialization.
+    InvalidTransaction(String),
+    /// A transaction script failed to validate.
+    ScriptValidationFailed(String),
+    /// The script uses features the interpreter doesn't support.
+    UnsupportedScript(String),
+    /// The coinbase transaction claims more than the block subsidy plus the fees.
+    InvalidCoinbaseValue(String),
+    /// The address doesn't belong to the network or its checksum is wrong.
+    InvalidAddressVersion(String),
+    /// The fee rate of the transaction is below the minimum relay fee rate.
+    FeeTooLow(String),
+    /// A block file on disk doesn't hold the block it should.
+    CorruptBlockFile(String),
+    /// The timestamp of a block is too far ahead of the local time.
+    TimestampTooFuture(String),
+    /// The peer doesn't have the requested transaction.
+    TransactionNotFound(String),
+    /// The output spent by a transaction input is not known.
+    OutpointNotFound(String),
+    /// A compact block filter doesn't match its filter header.
+    InvalidFilterHeader(String),
+    /// The node has as many connections open as it's allowed to.
+    ConnectionLimitReached(String),
+    /// The witness commitment of the coinbase is missing or doesn't match the witnesses of the block.
+    InvalidWitnessCommitment(String),
+    /// The coinbase doesn't start with the height of the block (BIP34).
+    MissingCoinbaseHeight(String),
+    /// A block forks the chain deeper than the maximum reorg depth, or below the checkpoint.
+    ReorgTooDeep(String),
+    /// A block forks the chain below its tip, which the node can't rewind to switch branches.
+    UnsupportedReorg(String),
+    /// The parent of a block is not stored, so the block can't be connected to the chain.
+    UnknownParent(String),
+    /// A transaction is larger than the maximum size peers relay.
+    TransactionTooLarge(String),
+    /// The initial block download didn't finish before its timeout.
+    IbdTimeout(String),
+    /// The compact block filter a peer sent doesn't match the one built from the block.
+    FilterMismatch(String),
+    /// A peer didn't send a filtered block or its matched transactions before the timeout.
+    FilteredBlockTimeout(String),
 }
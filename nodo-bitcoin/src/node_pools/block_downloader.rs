@@ -1,36 +1,101 @@
 use std::{
-    net::TcpStream,
+    net::{SocketAddr, TcpStream},
     path::Path,
-    sync::{mpsc, Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, OnceLock,
+    },
     thread,
+    time::Instant,
 };
 
 use crate::{
     block::{block_hash::BlockHash, validate_and_save_block},
+    channels::event_sink::{EventSink, NodeEvent},
     connectors::peer_connector::receive_message,
     constants::MAX_FAILED_COUNT,
-    constants::MSG_BLOCK,
+    constants::{DOWNLOAD_RATE_WINDOW, MIN_BLOCKS_PER_MINUTE, MSG_WITNESS_BLOCK},
     header::Header,
-    logger::Logger,
+    logger::{progress_logger::ProgressLogger, Logger},
     messages::{block_message::BlockMessage, get_data_message::GetDataMessage},
     node::{
         connect_to_ip,
+        connection_limiter::OutboundStream,
         message_type::MessageType,
+        read::retrieve_progress_log_interval,
         receive_messages::{
             receive_addr_message, receive_feefilter_message, receive_inv_message,
-            receive_not_found_message, send_pong_message,
+            receive_not_found_message, receive_sendcmpct_message, send_pong_message,
         },
     },
     node_error::NodeError,
-    ui::ui_message::UIMessage,
     utils::Utils,
 };
 use std::time::Duration;
 
+/// Summarizes the blocks downloaded by every downloader, so the log gets a line per interval
+/// instead of one per block.
+static DOWNLOAD_PROGRESS: OnceLock<ProgressLogger> = OnceLock::new();
+
+/// Tracks how many blocks a downloader receives from its peer since it connected.
+#[derive(Debug, Clone)]
+pub struct DownloadRate {
+    /// The moment the downloader started using the current peer.
+    since: Instant,
+    /// The number of blocks downloaded from the current peer.
+    downloaded_blocks: usize,
+}
+
+impl DownloadRate {
+    /// Starts tracking the download rate of a new peer.
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Starts tracking the download rate of a new peer from the given moment.
+    pub fn starting_at(since: Instant) -> Self {
+        DownloadRate {
+            since,
+            downloaded_blocks: 0,
+        }
+    }
+
+    /// Records a block downloaded from the peer.
+    pub fn record_block(&mut self) {
+        self.downloaded_blocks += 1;
+    }
+
+    /// Returns true if the peer has been used for at least `DOWNLOAD_RATE_WINDOW` seconds and it
+    /// delivered less than `MIN_BLOCKS_PER_MINUTE` blocks per minute.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The moment the rate is checked at.
+    pub fn is_slow(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.since).as_secs_f64();
+        if elapsed < DOWNLOAD_RATE_WINDOW as f64 {
+            return false;
+        }
+        let blocks_per_minute = self.downloaded_blocks as f64 * 60.0 / elapsed;
+        blocks_per_minute < MIN_BLOCKS_PER_MINUTE
+    }
+}
+
+impl Default for DownloadRate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// A worker thread in the thread pool.
 pub struct BlockDownloader {
     /// The `JoinHandle` of the worker thread.
-    thread: thread::JoinHandle<TcpStream>,
+    thread: thread::JoinHandle<OutboundStream>,
+    /// Set when the download is stopped, so the worker thread doesn't take more hashes.
+    stopped: Arc<AtomicBool>,
+    /// A clone of the connection to the current peer of the worker, to shut it down when the
+    /// download is stopped.
+    peer: Arc<Mutex<Option<TcpStream>>>,
 }
 
 impl BlockDownloader {
@@ -50,7 +115,10 @@ impl BlockDownloader {
     ///   This allows multiple workers to receive tasks concurrently without causing concurrency issues.
     /// * `ip` - The IP address of the node to connect to.
     /// * `sender` - The `mpsc::Sender` that the worker thread should send jobs to.
+    /// * `spare_ips` - The IP addresses of peers that are not used by any worker, a slow peer is
+    ///   replaced by one of them.
     /// * `logger` - The `Logger` instance to be used by the `BlockDownloader` instance to send received blocks.
+    /// * `events` - The sink the download progress is emitted to, such as the UI.
     ///
     /// # Errors
     ///
@@ -60,8 +128,9 @@ impl BlockDownloader {
         receiver: Arc<Mutex<mpsc::Receiver<BlockHash>>>,
         ip: std::net::SocketAddr,
         failed_sender: mpsc::Sender<BlockHash>,
+        spare_ips: Arc<Mutex<Vec<SocketAddr>>>,
         logger: Arc<Mutex<Logger>>,
-        ui_sender: glib::Sender<UIMessage>,
+        events: impl EventSink + Send + 'static,
     ) -> Result<BlockDownloader, NodeError> {
         let builder = thread::Builder::new();
         let mut failed_count: usize = 0;
@@ -69,13 +138,19 @@ impl BlockDownloader {
             .lock()
             .map_err(|_| NodeError::FailedToLog("Failed to acquire lock on logger".to_string()))?
             .clone();
-        let mut stream = Self::connect_to_node(ip, id, &logger_)?;
-        stream
-            .set_read_timeout(Some(Duration::from_secs(60)))
-            .map_err(|_| NodeError::ReadTimeoutFromStream("Failed to set timeout".to_string()))?;
+        let mut stream = Self::connect_with_download_timeout(ip, id, &logger_)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let peer = Arc::new(Mutex::new(stream.try_clone().ok()));
+        let thread_stopped = Arc::clone(&stopped);
+        let thread_peer = Arc::clone(&peer);
         let thread = builder
             .spawn(move || {
+                let mut download_rate = DownloadRate::new();
                 loop {
+                    if thread_stopped.load(Ordering::SeqCst) {
+                        println!("Downloader {} stopped", id);
+                        break;
+                    }
                     let result = Self::process_hash_and_download(
                         &receiver,
                         &mut stream,
@@ -83,14 +158,29 @@ impl BlockDownloader {
                         &failed_sender,
                         &mut failed_count,
                         &logger,
-                        &ui_sender,
+                        &events,
                     );
                     match result {
                         None => {
                             Self::delete_timeout(&mut stream).unwrap_or(()); //delete timeout so that it doesn't affect broadcasting
                             break;
                         }
-                        Some(_) => {
+                        Some(downloaded) => {
+                            if downloaded.is_ok() {
+                                download_rate.record_block();
+                            }
+                            if Self::replace_peer_if_slow(
+                                &mut stream,
+                                &mut download_rate,
+                                &spare_ips,
+                                id,
+                                &logger_,
+                            ) {
+                                failed_count = 0;
+                                if let Ok(mut peer) = thread_peer.lock() {
+                                    *peer = stream.try_clone().ok();
+                                }
+                            }
                             if failed_count > MAX_FAILED_COUNT {
                                 println!(
                                     "Killing thread {} too many failures: {}",
@@ -108,7 +198,28 @@ impl BlockDownloader {
             })
             .map_err(|_| NodeError::FailedToCreateThread("Failed to create thread".to_string()))?;
 
-        Ok(BlockDownloader { thread })
+        Ok(BlockDownloader {
+            thread,
+            stopped,
+            peer,
+        })
+    }
+
+    /// Returns `true` once the worker thread finished, so joining it doesn't block.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Tells the worker thread to stop and shuts down its connection, so a download waiting on a
+    /// peer fails right away instead of blocking the thread. The hashes still queued are not
+    /// downloaded.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Ok(peer) = self.peer.lock() {
+            if let Some(stream) = peer.as_ref() {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+            }
+        }
     }
 
     /// Waits for the worker thread to finish execution.
@@ -116,7 +227,7 @@ impl BlockDownloader {
     /// or a `NodeError` on failure.
     /// # Errors
     /// Returns a `NodeError::FailedToJoinThread` variant if the thread join fails.
-    pub fn join(self) -> Result<TcpStream, NodeError> {
+    pub fn join(self) -> Result<OutboundStream, NodeError> {
         let result = self
             .thread
             .join()
@@ -128,7 +239,8 @@ impl BlockDownloader {
     /// Downloads a block from a peer given the block's hash.
     ///
     /// The function first sets a read stimeout on the stream and then sends a getdata message to request the
-    /// block with the given hash from the peer. Then it enters into a loop to receive messages from the peer
+    /// block with the given hash from the peer, witnesses included (MSG_WITNESS_BLOCK) so the witness commitment
+    /// of the block can be verified. Then it enters into a loop to receive messages from the peer
     /// until it receives the requested block. The loop handles different types of messages that may be received
     /// from the peer and ignores all messages that are not relevant to the block download. Once the block is
     /// received, the function returns the block bytes in a Vec.
@@ -148,7 +260,7 @@ impl BlockDownloader {
         hash_bytes: BlockHash,
         logger: &Arc<Mutex<Logger>>,
     ) -> Result<Vec<u8>, NodeError> {
-        let data_message = GetDataMessage::new(1, MSG_BLOCK, hash_bytes)?;
+        let data_message = GetDataMessage::new(1, MSG_WITNESS_BLOCK, hash_bytes)?;
         data_message.send_message(stream)?;
         Self::handle_block_download(stream, logger)
     }
@@ -184,47 +296,42 @@ impl BlockDownloader {
             logger
                 .lock()
                 .map_err(|e| NodeError::FailedToLog(format!("{}", e)))?
-                .log(format!(
-                    "Receiving command in block download: {:?}",
-                    command_name
-                ))?;
-            println!("Receiving command in blocks download: {:?}", command_name);
+                .log_message(
+                    &command_name,
+                    format!("Receiving command in block download: {:?}", command_name),
+                )?;
 
             match command_name {
                 MessageType::Headers => {
-                    println!("Received headers message");
                     continue;
                 }
                 MessageType::Block => {
-                    println!("Recieved a block message");
                     let block_bytes = receive_message(stream, header.payload_size())?;
                     return Ok(block_bytes);
                 }
                 MessageType::Ping => {
-                    println!("Handle ping message");
                     send_pong_message(stream, &header)?;
                     continue;
                 }
                 MessageType::Pong => {
-                    println!("Handle pong message");
                     continue;
                 }
                 MessageType::SendHeaders => {
-                    println!("Handle sendheaders message");
                     continue;
                 }
                 MessageType::Addr => {
-                    println!("Recieved an addr message");
                     receive_addr_message(stream, &header)?;
                     continue;
                 }
                 MessageType::FeeFilter => {
-                    println!("Recieved a feefilter message");
                     receive_feefilter_message(stream, &header)?;
                     continue;
                 }
+                MessageType::SendCmpct => {
+                    receive_sendcmpct_message(stream, &header)?;
+                    continue;
+                }
                 MessageType::Inv => {
-                    println!("Recieved a inv message");
                     receive_inv_message(stream, &header)?;
                     continue;
                 }
@@ -232,8 +339,14 @@ impl BlockDownloader {
                     println!("Sync node does not have the block");
                     return Err(receive_not_found_message(stream, &header));
                 }
+                MessageType::Unknown(command) => {
+                    logger
+                        .lock()
+                        .map_err(|e| NodeError::FailedToLog(format!("{}", e)))?
+                        .debug(format!("Skipping unknown command: {}", command))?;
+                    header.skip_payload(stream)?;
+                }
                 _ => {
-                    println!("Command not supported");
                     receive_message(stream, header.payload_size())?;
                 }
             }
@@ -273,18 +386,10 @@ impl BlockDownloader {
         failed_count: &mut usize,
         logger: &Arc<Mutex<Logger>>,
     ) -> Result<(), NodeError> {
-        match Self::block_download(stream, block_hash, logger) {
-            Ok(block) => {
-                println!("Downloader {} downloaded block {:?}", id, path);
-                logger
-                    .lock()
-                    .map_err(|e| NodeError::FailedToLog(format!("{}", e)))?
-                    .log(format!("Downloaded block to {:?} from thread {}", path, id))?;
-                if let Err(err) = Self::save_block(block, path) {
-                    println!("Error save block: {:?}", err);
-                };
-                Ok(())
-            }
+        match Self::block_download(stream, block_hash, logger)
+            .and_then(|block| Self::record_and_save(block, path, id, logger))
+        {
+            Ok(()) => Ok(()),
             Err(err) => {
                 println!("Error: {:?}. Queuing to failed channel..", err);
                 *failed_count += 1;
@@ -301,6 +406,69 @@ impl BlockDownloader {
         }
     }
 
+    /// Saves a downloaded block to disk and records it in the download progress.
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - The bytes of the downloaded block.
+    /// * `path` - The file path the block is saved to if it can't be computed from the block.
+    /// * `id` - An identifier for the downloader.
+    /// * `logger` - A reference to the logger instance to be used to log blocks.
+    /// # Errors
+    /// Returns a `NodeError` if the block can't be validated or saved, the logger can't be locked or
+    /// the progress can't be logged.
+    fn record_and_save(
+        block: Vec<u8>,
+        path: String,
+        id: &usize,
+        logger: &Arc<Mutex<Logger>>,
+    ) -> Result<(), NodeError> {
+        let path = BlockMessage::block_path_for_block(&block).unwrap_or(path);
+        Self::save_block(block, path.clone())?;
+        let logger = logger
+            .lock()
+            .map_err(|e| NodeError::FailedToLog(format!("{}", e)))?;
+        DOWNLOAD_PROGRESS
+            .get_or_init(|| {
+                ProgressLogger::new(
+                    logger.clone(),
+                    "blocks downloaded",
+                    Duration::from_secs(retrieve_progress_log_interval()),
+                )
+            })
+            .record(format!("Downloaded block to {:?} from thread {}", path, id))?;
+        Ok(())
+    }
+
+    /// Saves a block that was already received from a peer, the same way a downloaded block is
+    /// saved, and emits its download to the given sink.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_hash` - A 32-byte array containing the hash of the block.
+    /// * `block` - The bytes of the block sent by the peer.
+    /// * `id` - An identifier for the downloader.
+    /// * `logger` - A reference to the logger instance to be used to log blocks.
+    /// * `events` - The sink the download progress is emitted to, such as the UI.
+    /// # Errors
+    /// Returns a `NodeError` if the block path can't be computed or the progress can't be logged.
+    pub fn save_downloaded_block(
+        block_hash: BlockHash,
+        block: Vec<u8>,
+        id: usize,
+        logger: &Arc<Mutex<Logger>>,
+        events: &impl EventSink,
+    ) -> Result<(), NodeError> {
+        let path = BlockMessage::block_path(&block_hash).ok_or_else(|| {
+            NodeError::FailedToDownloadBlock("Failed to get block path".to_string())
+        })?;
+        Self::record_and_save(block, path, &id, logger)?;
+        events.emit(NodeEvent::BlockDownloaded).unwrap_or_else(|e| {
+            println!("Error sending update progress: {:?}", e);
+        });
+        Ok(())
+    }
+
     /// Downloads the block with the given hash from the peer listening on the provided
     /// TCP stream if it has not already been downloaded and saved to disk. If the block is
     /// successfully downloaded and saved, its hash is sent to the provided sender using
@@ -314,6 +482,7 @@ impl BlockDownloader {
     /// * `id` - An identifier for the downloader.
     /// * `failed_count` - A mutable reference to an integer representing the number of failed downloads.
     /// * `logger` - A reference to the logger instance to be used to log blocks.
+    /// * `events` - The sink the download progress is emitted to, such as the UI.
     /// # Errors
     /// Returns a `NodeError` if there was an error while downloading the block or saving it to
     /// the specified file path.
@@ -324,7 +493,7 @@ impl BlockDownloader {
         failed_sender: Option<&mpsc::Sender<BlockHash>>,
         failed_count: &mut usize,
         logger: &Arc<Mutex<Logger>>,
-        ui_sender: &glib::Sender<UIMessage>,
+        events: &impl EventSink,
     ) -> Result<(), NodeError> {
         let path = match BlockMessage::block_path(&block_hash) {
             Some(value) => value,
@@ -349,11 +518,9 @@ impl BlockDownloader {
             println!("Won't download block {:?}, already downloaded", path);
         }
 
-        ui_sender
-            .send(UIMessage::UpdateBlocksProgress)
-            .unwrap_or_else(|e| {
-                println!("Error sending update progress: {:?}", e);
-            });
+        events.emit(NodeEvent::BlockDownloaded).unwrap_or_else(|e| {
+            println!("Error sending update progress: {:?}", e);
+        });
         Ok(())
     }
 
@@ -372,10 +539,10 @@ impl BlockDownloader {
     /// Returns a `NodeError::FailedToConnect` if there is an error connecting to or performing a handshake with the
     /// remote node.
     fn connect_to_node(
-        ip: std::net::SocketAddr,
+        ip: SocketAddr,
         id: usize,
         logger: &Logger,
-    ) -> Result<TcpStream, NodeError> {
+    ) -> Result<OutboundStream, NodeError> {
         let stream = connect_to_ip(&ip, logger).ok_or(NodeError::FailedToConnect(format!(
             "Failed to connect to peer {} in thread pool",
             ip
@@ -393,8 +560,9 @@ impl BlockDownloader {
     /// * `failed_sender` - An `Arc` wrapped `Mutex` containing an `mpsc::Sender` for sending failed block hashes.
     /// * `failed_count` - A mutable reference to an integer representing the number of failed downloads.
     /// * `logger` - A reference to the logger instance to be used to log blocks.
-    /// # Errors
-    /// Returns `None` if the channel is closed, meaning that the IBD is finished.
+    /// * `events` - The sink the download progress is emitted to, such as the UI.
+    /// # Returns
+    /// The result of downloading the received hash, or `None` if the channel is closed, meaning that the IBD is finished.
     fn process_hash_and_download(
         receiver: &Arc<Mutex<mpsc::Receiver<BlockHash>>>,
         stream: &mut TcpStream,
@@ -402,29 +570,84 @@ impl BlockDownloader {
         failed_sender: &mpsc::Sender<BlockHash>,
         failed_count: &mut usize,
         logger: &Arc<Mutex<Logger>>,
-        ui_sender: &glib::Sender<UIMessage>,
-    ) -> Option<()> {
+        events: &impl EventSink,
+    ) -> Option<Result<(), NodeError>> {
         let received_hash = receiver.lock().ok()?.recv().ok();
 
-        if let Some(received_hash) = received_hash {
-            let _result = Self::download_block(
+        match received_hash {
+            Some(received_hash) => Some(Self::download_block(
                 received_hash,
                 stream,
                 id,
                 Some(failed_sender),
                 failed_count,
                 logger,
-                ui_sender,
-            );
-        } else {
-            println!(
-                "Downloader {} finished because channel is closed. Finished IBD",
-                id
-            );
-            return None;
+                events,
+            )),
+            None => {
+                println!(
+                    "Downloader {} finished because channel is closed. Finished IBD",
+                    id
+                );
+                None
+            }
+        }
+    }
+
+    /// Replaces the peer of the downloader with one of the spare peers if it's delivering blocks
+    /// too slowly. Failed downloads are already queued to the failed channel, so no hash is lost
+    /// when the peer is dropped.
+    ///
+    /// # Arguments
+    /// * `stream` - A mutable reference to the connection to the current peer, replaced by the new connection.
+    /// * `download_rate` - The download rate of the current peer, restarted if the peer is replaced.
+    /// * `spare_ips` - The IP addresses of the peers that are not used by any downloader.
+    /// * `id` - An identifier for the downloader.
+    /// * `logger` - A reference to the logger instance used to connect to the new peer.
+    /// # Returns
+    /// `true` if the peer was replaced.
+    pub fn replace_peer_if_slow(
+        stream: &mut OutboundStream,
+        download_rate: &mut DownloadRate,
+        spare_ips: &Arc<Mutex<Vec<SocketAddr>>>,
+        id: usize,
+        logger: &Logger,
+    ) -> bool {
+        if !download_rate.is_slow(Instant::now()) {
+            return false;
         }
 
-        Some(())
+        loop {
+            let ip = match spare_ips.lock().ok().and_then(|mut ips| ips.pop()) {
+                Some(ip) => ip,
+                None => {
+                    println!("Downloader {} is slow but there are no spare peers", id);
+                    *download_rate = DownloadRate::new();
+                    return false;
+                }
+            };
+            if let Ok(new_stream) = Self::connect_with_download_timeout(ip, id, logger) {
+                println!("Downloader {} replaced its slow peer with {}", id, ip);
+                Self::delete_timeout(stream).unwrap_or(());
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                *stream = new_stream;
+                *download_rate = DownloadRate::new();
+                return true;
+            }
+        }
+    }
+
+    /// Connects to a peer and sets the read timeout used while downloading blocks.
+    fn connect_with_download_timeout(
+        ip: SocketAddr,
+        id: usize,
+        logger: &Logger,
+    ) -> Result<OutboundStream, NodeError> {
+        let stream = Self::connect_to_node(ip, id, logger)?;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(60)))
+            .map_err(|_| NodeError::ReadTimeoutFromStream("Failed to set timeout".to_string()))?;
+        Ok(stream)
     }
 
     /// Saves a block to the specified file path and updates the UtxoSet.
@@ -460,9 +683,82 @@ impl BlockDownloader {
 
 #[cfg(test)]
 mod tests {
-    use std::{fs, io::Read};
+    use std::{
+        fs,
+        io::Read,
+        net::{SocketAddr, TcpListener},
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        config::load_app_config,
+        logger::Logger,
+        node::server::server_handshake,
+        node_error::NodeError,
+        node_pools::block_downloader::{BlockDownloader, DownloadRate},
+    };
 
-    use crate::node_pools::block_downloader::BlockDownloader;
+    /// Starts a fake peer that accepts one connection, performs the handshake and keeps the
+    /// connection open until the downloader closes it.
+    fn spawn_fake_peer() -> Result<SocketAddr, NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind fake peer".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to get fake peer addr".to_string()))?;
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                if server_handshake(&mut stream).unwrap_or(false) {
+                    let mut buffer = [0u8; 1];
+                    let _ = stream.read(&mut buffer);
+                }
+            }
+        });
+        Ok(addr)
+    }
+
+    #[test]
+    fn test_download_rate_is_slow_only_after_the_window() {
+        let mut download_rate = DownloadRate::new();
+        assert!(!download_rate.is_slow(Instant::now()));
+
+        let two_minutes_ago = Instant::now() - Duration::from_secs(120);
+        download_rate = DownloadRate::starting_at(two_minutes_ago);
+        assert!(download_rate.is_slow(Instant::now()));
+
+        for _ in 0..10 {
+            download_rate.record_block();
+        }
+        assert!(!download_rate.is_slow(Instant::now()));
+    }
+
+    #[test]
+    fn test_slow_worker_reconnects_to_spare_peer() -> Result<(), NodeError> {
+        load_app_config(None)?;
+        let logger = Logger::new()?;
+        let slow_peer = spawn_fake_peer()?;
+        let spare_peer = spawn_fake_peer()?;
+        let mut stream = BlockDownloader::connect_with_download_timeout(slow_peer, 0, &logger)?;
+        let spare_ips = Arc::new(Mutex::new(vec![spare_peer]));
+        let mut download_rate =
+            DownloadRate::starting_at(Instant::now() - Duration::from_secs(120));
+
+        let replaced = BlockDownloader::replace_peer_if_slow(
+            &mut stream,
+            &mut download_rate,
+            &spare_ips,
+            0,
+            &logger,
+        );
+
+        assert!(replaced);
+        assert_eq!(stream.peer_addr().ok(), Some(spare_peer));
+        assert!(!download_rate.is_slow(Instant::now()));
+        assert!(spare_ips.lock().map(|ips| ips.is_empty()).unwrap_or(false));
+        Ok(())
+    }
 
     #[test]
     fn test_save_block() {
@@ -480,7 +776,7 @@ mod tests {
         assert!(result.is_ok());
         let path = "test_save_block.bin".to_string();
         let result = BlockDownloader::save_block(block_bytes, path);
-        assert!(result.is_err());
+        assert!(result.is_ok());
         fs::remove_file("test_save_block.bin").unwrap();
     }
 }
@@ -1,10 +1,14 @@
 use super::block_downloader::BlockDownloader;
 use crate::block::block_hash::BlockHash;
+use crate::channels::event_sink::EventSink;
+use crate::constants::IBD_JOIN_POLL_INTERVAL_MILLIS;
 use crate::logger::Logger;
+use crate::node::connection_limiter::OutboundStream;
 use crate::node_error::NodeError;
-use crate::ui::ui_message::UIMessage;
-use std::net::{SocketAddr, TcpStream};
+use std::net::SocketAddr;
 use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// A thread pool that can execute jobs in parallel.
 pub struct BlockDownloaderPool {
@@ -21,16 +25,19 @@ pub struct BlockDownloaderPool {
 /// Represents the result of a `ThreadPool::join` call.
 /// It contains a vector of `TcpStream` representing the connections to the peers
 /// and an `Option<mpsc::Receiver<[u8; 32]>>` used for receiving failed hashes in order to retry them in the main thread.
-type JoinResult = (Vec<TcpStream>, Option<mpsc::Receiver<BlockHash>>);
+type JoinResult = (Vec<OutboundStream>, Option<mpsc::Receiver<BlockHash>>);
 
 impl BlockDownloaderPool {
     /// Creates a new thread pool with the given size.
     ///
     /// # Arguments
     ///
-    /// * `size` - The desired number of worker threads in the pool. If `size` is less than or equal to 0, the default size will be used.
-    /// * `ips` - A vector of `SocketAddr` representing the IP addresses to connect to.
+    /// * `size` - The desired number of worker threads in the pool.
+    /// * `ips` - A vector of `SocketAddr` representing the IP addresses to connect to. If there are more IPs than worker
+    ///   slots, the workers are created with the first IPs that accept the connection. If there are fewer, the workers
+    ///   are distributed across the IPs, with several connections to the same peer.
     /// * `logger` - The `Logger` instance to be used by the `BlockDownloader` instances.
+    /// * `events` - The sink the download progress is emitted to, such as the UI.
     ///
     /// # Errors
     ///
@@ -39,7 +46,7 @@ impl BlockDownloaderPool {
         size: usize,
         ips: &[SocketAddr],
         logger: Logger,
-        ui_sender: &glib::Sender<UIMessage>,
+        events: &(impl EventSink + Clone + Send + 'static),
     ) -> Result<BlockDownloaderPool, NodeError> {
         if size == 0 {
             return Err(NodeError::FailedToCreateThread(
@@ -58,7 +65,7 @@ impl BlockDownloaderPool {
             failed_sender.clone(),
             &mut downloaders,
             logger,
-            ui_sender,
+            events,
         )?;
 
         if downloaders.is_empty() {
@@ -96,21 +103,65 @@ impl BlockDownloaderPool {
         Ok((connections, self.failed_hash_receiver))
     }
 
+    /// Waits for all worker threads to finish, like `join`, but only until the deadline.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - The instant the worker threads must finish by.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::IbdTimeout` with the number of workers still downloading if the deadline
+    /// passes, after stopping them, or a `NodeError::FailedToJoinThread` like `join`.
+    pub fn join_before(mut self, deadline: Instant) -> Result<JoinResult, NodeError> {
+        loop {
+            let pending = self
+                .block_downloaders
+                .iter()
+                .filter(|downloader| !downloader.is_finished())
+                .count();
+            if pending == 0 {
+                return self.join();
+            }
+            if Instant::now() >= deadline {
+                self.stop();
+                return Err(NodeError::IbdTimeout(format!(
+                    "{} block downloaders didn't finish in time",
+                    pending
+                )));
+            }
+            thread::sleep(Duration::from_millis(IBD_JOIN_POLL_INTERVAL_MILLIS));
+        }
+    }
+
     pub fn close_channel(&mut self) {
         drop(self.hash_sender.take());
     }
 
+    /// Stops the worker threads without waiting for them, shutting down their connections. The
+    /// hashes still queued are not downloaded.
+    pub fn stop(&mut self) {
+        self.close_channel();
+        for downloader in &self.block_downloaders {
+            downloader.stop();
+        }
+    }
+
     /// Handles the creation of `BlockDownloader` instances and populates the `downloaders` vector.
     ///
     /// # Arguments
     ///
-    /// * `ips` - A vector of `SocketAddr` representing the IP addresses to connect to.
+    /// * `size` - The number of `BlockDownloader` instances to create.
+    /// * `ips` - A vector of `SocketAddr` representing the IP addresses to connect to. The ones that are not
+    ///   used by any downloader are kept as spare peers to replace slow ones. If all of them are used and there
+    ///   are still worker slots, the peers that accepted a connection are connected to again in turns.
     /// * `id` - The initial identifier for the `BlockDownloader` instances.
     /// * `receiver` - An `Arc<Mutex<mpsc::Receiver<[u8; 32]>>>` used for receiving blocks.
     /// * `failed_sender_arc` - An `Arc<Mutex<mpsc::Sender<[u8; 32]>>>` used for sending failed blocks.
     /// * `failed_receiver` - An `Arc<Mutex<mpsc::Receiver<[u8; 32]>>>` used for receiving failed blocks.
     /// * `downloaders` - A mutable reference to a vector of `BlockDownloader` instances.
     /// * `logger` - The `Logger` instance to be used by the `BlockDownloader` instances.
+    /// * `events` - The sink the download progress is emitted to, such as the UI.
     ///
     /// # Returns
     ///
@@ -127,43 +178,194 @@ impl BlockDownloaderPool {
         failed_sender: mpsc::Sender<BlockHash>,
         downloaders: &mut Vec<BlockDownloader>,
         logger: Logger,
-        ui_sender: &glib::Sender<UIMessage>,
+        events: &(impl EventSink + Clone + Send + 'static),
     ) -> Result<(), NodeError> {
-        let mut id = 0;
         let logger_arc = Arc::new(Mutex::new(logger));
+        let mut ips: Vec<SocketAddr> = ips.into_iter().filter(|ip| ip.is_ipv4()).collect();
+        ips.reverse();
+        let spare_ips = Arc::new(Mutex::new(Vec::new()));
 
-        for ip in ips {
-            if ip.is_ipv6() {
-                continue;
-            }
-            if id == size {
-                break;
-            }
+        let mut add_downloader = |id: usize, ip: SocketAddr| -> Result<bool, NodeError> {
             match BlockDownloader::new(
                 id,
                 Arc::clone(&receiver),
                 ip,
                 failed_sender.clone(),
+                Arc::clone(&spare_ips),
                 Arc::clone(&logger_arc),
-                ui_sender.clone(),
+                events.clone(),
             ) {
                 Ok(downloader) => {
                     downloaders.push(downloader);
-                    id += 1;
+                    Ok(true)
                 }
-                Err(err) => {
-                    if let NodeError::FailedToConnect(_) = err {
-                        println!(
-                            "Failed to connect to peer at {}. Timeout. Retrying with other ip...",
-                            ip
-                        );
-                        continue;
-                    } else {
-                        return Err(err);
+                Err(NodeError::FailedToConnect(_)) => {
+                    println!(
+                        "Failed to connect to peer at {}. Timeout. Retrying with other ip...",
+                        ip
+                    );
+                    Ok(false)
+                }
+                Err(err) => Err(err),
+            }
+        };
+
+        let mut id = 0;
+        let mut connected_ips = Vec::new();
+        while let Some(ip) = ips.pop() {
+            if id == size {
+                ips.push(ip);
+                break;
+            }
+            if add_downloader(id, ip)? {
+                connected_ips.push(ip);
+                id += 1;
+            }
+        }
+
+        // With more worker slots than peers, the remaining workers open more connections to the
+        // peers that accepted one, taking them in turns.
+        let mut next = 0;
+        while id < size && !connected_ips.is_empty() {
+            next %= connected_ips.len();
+            if add_downloader(id, connected_ips[next])? {
+                id += 1;
+                next += 1;
+            } else {
+                connected_ips.remove(next);
+            }
+        }
+
+        if let Ok(mut spare_ips) = spare_ips.lock() {
+            *spare_ips = ips;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        net::{SocketAddr, TcpListener},
+        thread,
+    };
+
+    use super::*;
+    use crate::{
+        channels::event_sink::NodeEvent, config::load_app_config, node::server::server_handshake,
+    };
+
+    /// Starts a fake peer that accepts one connection, performs the handshake and never answers the
+    /// requests it receives, until the downloader closes the connection. The returned receiver
+    /// gets a message once the connection is closed.
+    fn spawn_stalled_peer() -> Result<(SocketAddr, mpsc::Receiver<()>), NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind fake peer".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to get fake peer addr".to_string()))?;
+        let (closed_sender, closed_receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                if server_handshake(&mut stream).unwrap_or(false) {
+                    let mut buffer = [0u8; 1024];
+                    while let Ok(read) = stream.read(&mut buffer) {
+                        if read == 0 {
+                            break;
+                        }
                     }
+                    let _ = closed_sender.send(());
                 }
             }
+        });
+        Ok((addr, closed_receiver))
+    }
+
+    /// Starts a fake peer that accepts `connections` connections, performs the handshake on each and keeps
+    /// them open until the downloader closes them.
+    fn spawn_fake_peer(connections: usize) -> Result<SocketAddr, NodeError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind fake peer".to_string()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToBind("Failed to get fake peer addr".to_string()))?;
+        thread::spawn(move || {
+            for _ in 0..connections {
+                if let Ok((mut stream, _)) = listener.accept() {
+                    thread::spawn(move || {
+                        if server_handshake(&mut stream).unwrap_or(false) {
+                            let mut buffer = [0u8; 1];
+                            let _ = stream.read(&mut buffer);
+                        }
+                    });
+                }
+            }
+        });
+        Ok(addr)
+    }
+
+    #[test]
+    fn test_pool_creates_max_workers_with_more_ips() -> Result<(), NodeError> {
+        load_app_config(None)?;
+        let mut ips = Vec::new();
+        for _ in 0..10 {
+            ips.push(spawn_fake_peer(1)?);
         }
+        let logger = Logger::new()?;
+        let (events, _event_receiver) = mpsc::channel::<NodeEvent>();
+
+        let mut pool = BlockDownloaderPool::new(3, &ips, logger, &events)?;
+
+        assert_eq!(pool.block_downloaders.len(), 3);
+        pool.close_channel();
+        let (connections, _) = pool.join()?;
+        assert_eq!(connections.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pool_distributes_workers_across_fewer_ips() -> Result<(), NodeError> {
+        load_app_config(None)?;
+        let ips = vec![spawn_fake_peer(3)?, spawn_fake_peer(3)?];
+        let logger = Logger::new()?;
+        let (events, _event_receiver) = mpsc::channel::<NodeEvent>();
+
+        let mut pool = BlockDownloaderPool::new(5, &ips, logger, &events)?;
+
+        assert_eq!(pool.block_downloaders.len(), 5);
+        pool.close_channel();
+        let (connections, _) = pool.join()?;
+        let peers: Vec<SocketAddr> = connections
+            .iter()
+            .filter_map(|connection| connection.peer_addr().ok())
+            .collect();
+        assert_eq!(peers.iter().filter(|peer| **peer == ips[0]).count(), 3);
+        assert_eq!(peers.iter().filter(|peer| **peer == ips[1]).count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_before_times_out_with_a_stalled_peer() -> Result<(), NodeError> {
+        load_app_config(None)?;
+        let (ip, closed_receiver) = spawn_stalled_peer()?;
+        let ips = vec![ip];
+        let logger = Logger::new()?;
+        let (events, _event_receiver) = mpsc::channel::<NodeEvent>();
+        let mut pool = BlockDownloaderPool::new(1, &ips, logger, &events)?;
+
+        if let Some(hash_sender) = &pool.hash_sender {
+            hash_sender
+                .send([9; 32])
+                .map_err(|_| NodeError::FailedToSendHash("Failed to queue the hash".to_string()))?;
+        }
+        pool.close_channel();
+        let result = pool.join_before(Instant::now() + Duration::from_millis(500));
+
+        assert!(matches!(result, Err(NodeError::IbdTimeout(_))));
+        assert!(closed_receiver.recv_timeout(Duration::from_secs(5)).is_ok());
         Ok(())
     }
 }
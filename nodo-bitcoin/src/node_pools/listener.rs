@@ -3,29 +3,36 @@ use std::{
     path::Path,
     sync::{Arc, Mutex},
     thread,
+    time::Duration,
 };
 
-use glib::Sender;
-
 use crate::{
-    block::{block_hash::BlockHash, validate_and_save_block_listener},
-    channels::wallet_channel::WalletChannel,
+    block::{
+        block_hash::{BlockHash, InternalHash},
+        validate_and_save_block_listener, validate_and_save_merkle_block_listener,
+        validate_block_coinbase, validate_block_scripts, BlockSave,
+    },
+    channels::{
+        event_sink::{EventSink, NodeEvent},
+        wallet_channel::WalletChannel,
+    },
     connectors::peer_connector::receive_message,
+    constants::MERKLE_BLOCK_TIMEOUT,
     header::Header,
     logger::Logger,
-    messages::block_message::BlockMessage,
+    messages::{block_message::BlockMessage, filter_load_message::FilterLoadMessage},
     node::{
         message_type::MessageType,
+        read::{retrieve_full_validation, retrieve_headers_only},
         receive_messages::{
-            receive_addr_message, receive_and_handle_inv_message, receive_feefilter_message,
-            receive_tx_message, send_pong_message,
+            fetch_merkle_block, receive_addr_message, receive_and_handle_inv_message,
+            receive_feefilter_message, receive_sendcmpct_message, receive_tx_message,
+            send_pong_message,
         },
         send_tx_to_wallet,
     },
     node_error::NodeError,
     transactions::{transaction::Transaction, utxo_set::UtxoSet},
-    ui::ui_message::UIMessage,
-    utils::Utils,
     wallet::node_wallet_message::NodeWalletMsg,
 };
 
@@ -47,6 +54,7 @@ impl MessageListener {
     /// * `utxo_set` - A shared reference to a `Mutex<UtxoSet>`.
     /// * `logger` - A shared reference to a `Mutex<Logger>`.
     /// * `wallet_channel` - A shared reference to a `Mutex<WalletChannel>`.
+    /// * `events` - The sink the new blocks and transactions are emitted to, such as the UI.
     ///
     /// # Returns
     ///
@@ -61,7 +69,7 @@ impl MessageListener {
         mut stream: TcpStream,
         utxo_set: Arc<Mutex<UtxoSet>>,
         wallet_channel: Arc<Mutex<WalletChannel>>,
-        ui_sender: Sender<UIMessage>,
+        events: impl EventSink + Send + 'static,
         logger: Arc<Mutex<Logger>>,
     ) -> Result<MessageListener, NodeError> {
         let builder = thread::Builder::new();
@@ -73,7 +81,7 @@ impl MessageListener {
                         id,
                         &utxo_set,
                         &wallet_channel,
-                        &ui_sender,
+                        &events,
                         &logger,
                     );
                     match result {
@@ -116,66 +124,64 @@ impl MessageListener {
             logger
                 .lock()
                 .map_err(|e| NodeError::FailedToLog(format!("{}", e)))?
-                .log(format!(
-                    "Receiving command in blocks broadcasting: {:?}",
-                    command_name
-                ))?;
-            println!(
-                "Receiving command in blocks broadcasting: {:?}",
-                command_name
-            );
+                .log_message(
+                    &command_name,
+                    format!(
+                        "Receiving command in blocks broadcasting: {:?}",
+                        command_name
+                    ),
+                )?;
 
             match command_name {
                 MessageType::Ping => {
-                    println!("Handle ping message");
                     send_pong_message(stream, &header)?;
                     continue;
                 }
                 MessageType::Pong => {
-                    println!("Handle pong message");
                     continue;
                 }
                 MessageType::SendHeaders => {
-                    println!("Handle sendheaders message");
                     continue;
                 }
                 MessageType::Addr => {
-                    println!("Recieved an addr message");
                     receive_addr_message(stream, &header)?;
                     continue;
                 }
                 MessageType::FeeFilter => {
-                    println!("Recieved a feefilter message");
                     receive_feefilter_message(stream, &header)?;
                     continue;
                 }
-                MessageType::Inv => {
-                    println!("Recieved a inv message");
-                    match receive_and_handle_inv_message(stream, &header) {
-                        Ok(block_hash) => {
-                            if block_hash.is_empty() {
-                                continue;
-                            }
-                            return Ok(ReceivedDataFromPeers::BlockHash(block_hash));
-                        }
-                        Err(e) => {
-                            println!("Error in handling inv message: {:?}", e);
-                            continue;
-                        }
-                    }
+                MessageType::SendCmpct => {
+                    receive_sendcmpct_message(stream, &header)?;
+                    continue;
                 }
-                MessageType::Tx => {
-                    println!("Recieved a tx message");
-                    match receive_tx_message(stream) {
-                        Ok(tx) => return Ok(ReceivedDataFromPeers::Transaction(tx)),
-                        Err(e) => {
-                            println!("Error in handling tx message: {:?}", e);
+                MessageType::Inv => match receive_and_handle_inv_message(stream, &header) {
+                    Ok(block_hash) => {
+                        if block_hash.is_empty() {
                             continue;
                         }
+                        return Ok(ReceivedDataFromPeers::BlockHash(block_hash));
+                    }
+                    Err(e) => {
+                        println!("Error in handling inv message: {:?}", e);
+                        continue;
+                    }
+                },
+                MessageType::Tx => match receive_tx_message(stream) {
+                    Ok(tx) => return Ok(ReceivedDataFromPeers::Transaction(tx)),
+                    Err(e) => {
+                        println!("Error in handling tx message: {:?}", e);
+                        continue;
                     }
+                },
+                MessageType::Unknown(command) => {
+                    logger
+                        .lock()
+                        .map_err(|e| NodeError::FailedToLog(format!("{}", e)))?
+                        .debug(format!("Skipping unknown command: {}", command))?;
+                    header.skip_payload(stream)?;
                 }
                 _ => {
-                    println!("Command not supported");
                     receive_message(stream, header.payload_size())?;
                 }
             }
@@ -201,7 +207,7 @@ impl MessageListener {
         id: usize,
         utxo_set: &Arc<Mutex<UtxoSet>>,
         wallet_channel: &Arc<Mutex<WalletChannel>>,
-        ui_sender: &Sender<UIMessage>,
+        events: &impl EventSink,
         logger: &Arc<Mutex<Logger>>,
     ) -> Option<()> {
         match Self::broadcasting_start(stream, logger) {
@@ -213,12 +219,12 @@ impl MessageListener {
                     utxo_set,
                     logger,
                     wallet_channel,
-                    ui_sender,
+                    events,
                 );
                 Some(())
             }
             Ok(ReceivedDataFromPeers::Transaction(tx)) => {
-                match Self::process_transaction(tx, logger, wallet_channel) {
+                match Self::process_transaction(tx, logger, wallet_channel, events) {
                     Ok(_) => {}
                     Err(e) => {
                         println!("Error in processing transaction: {:?}", e);
@@ -233,8 +239,8 @@ impl MessageListener {
         }
     }
 
-    /// Saves a block to the specified file path. If the file already exists, this function will
-    /// return an error.
+    /// Saves a block to the specified file path and applies it to the UTXO set and the wallet. A
+    /// block that was already saved, for example by another listener, is not applied again.
     ///
     /// # Arguments
     ///
@@ -244,15 +250,27 @@ impl MessageListener {
     ///
     /// # Errors
     ///
-    /// Returns a 'NodeError' if the file could not be opened or written to.
+    /// Returns a 'NodeError' if the file could not be opened or written to, or if `FULL_VALIDATION` is
+    /// enabled and a script or the coinbase value of the block fails to validate.
     pub fn save_block(
         block_bytes: Vec<u8>,
         path: String,
         utxo_set: &Arc<Mutex<UtxoSet>>,
         wallet_channel: &Arc<Mutex<WalletChannel>>,
-        ui_sender: &Sender<UIMessage>,
+        events: &impl EventSink,
     ) -> Result<(), NodeError> {
-        validate_and_save_block_listener(block_bytes, &path, ui_sender)?;
+        if retrieve_full_validation() {
+            let utxo_set = utxo_set
+                .lock()
+                .map_err(|_| NodeError::UtxoSetMutexError("UtxoSet mutex poisoned".to_string()))?;
+            validate_block_scripts(&block_bytes, &utxo_set)?;
+            validate_block_coinbase(&block_bytes, &utxo_set)?;
+        }
+        if validate_and_save_block_listener(block_bytes, &path, events)? == BlockSave::AlreadySaved
+        {
+            println!("Block {} was already saved", path);
+            return Ok(());
+        }
         println!("Saving block to {}...", path);
         utxo_set
             .lock()
@@ -268,6 +286,7 @@ impl MessageListener {
     }
 
     /// Downloads a block from the provided TCP stream and saves it to the specified file path.
+    /// In headers-only mode, only the merkleblock of the block is requested instead.
     ///
     /// # Arguments
     ///
@@ -277,7 +296,7 @@ impl MessageListener {
     /// * `utxo_set` - An `Arc` wrapped `Mutex` containing a `UtxoSet` instance.
     /// * `logger` - A reference to an Arc Mutex `Logger` for logging.
     /// * `wallet_channel` - A reference to an Arc Mutex `WalletChannel` for sending transactions to the wallet.
-    /// * `ui_sender` - A reference to a `Sender` for sending messages to the UI thread.
+    /// * `events` - The sink the new block is emitted to, such as the UI.
     pub fn download_block(
         block_hash: Vec<u8>,
         stream: &mut TcpStream,
@@ -285,7 +304,7 @@ impl MessageListener {
         utxo_set: &Arc<Mutex<UtxoSet>>,
         logger: &Arc<Mutex<Logger>>,
         wallet_channel: &Arc<Mutex<WalletChannel>>,
-        ui_sender: &Sender<UIMessage>,
+        events: &impl EventSink,
     ) {
         println!("New block hash: {:?}", block_hash);
         let new_block_hash = block_hash.try_into().unwrap_or([0; 32]);
@@ -294,6 +313,21 @@ impl MessageListener {
             Some(value) => value,
             None => return,
         };
+        if retrieve_headers_only() {
+            match stream.peer_addr() {
+                Ok(peer) if FilterLoadMessage::is_served_by(&peer) => {}
+                _ => {
+                    println!("Won't request a filtered block from a peer without bloom filters");
+                    return;
+                }
+            }
+            if let Err(e) =
+                Self::download_merkle_block(stream, new_block_hash, path, wallet_channel, events)
+            {
+                println!("Error downloading merkleblock: {:?}", e);
+            }
+            return;
+        }
         if !Path::new(&path).exists() {
             println!("Downloading block {:?} from downloader {}", path, id);
             Self::download_and_save(
@@ -303,7 +337,7 @@ impl MessageListener {
                 &id,
                 utxo_set,
                 logger,
-                (wallet_channel, ui_sender),
+                (wallet_channel, events),
             );
         } else {
             println!("Won't download block {:?}, already downloaded", path);
@@ -327,10 +361,11 @@ impl MessageListener {
         id: &usize,
         utxo_set: &Arc<Mutex<UtxoSet>>,
         logger: &Arc<Mutex<Logger>>,
-        channels: (&Arc<Mutex<WalletChannel>>, &Sender<UIMessage>),
+        channels: (&Arc<Mutex<WalletChannel>>, &impl EventSink),
     ) {
         match BlockDownloader::block_download(stream, block_hash, logger) {
             Ok(block) => {
+                let path = BlockMessage::block_path_for_block(&block).unwrap_or(path);
                 println!("Downloader {} downloaded block {:?}", id, path);
                 if Self::save_block(block, path, utxo_set, channels.0, channels.1).is_err() {
                     println!("Didn't save block because other thread saved it");
@@ -342,6 +377,47 @@ impl MessageListener {
         }
     }
 
+    /// Requests the merkleblock (BIP37) of a new block in headers-only mode, saves its header and
+    /// sends the wallet the transactions of its accounts the merkleblock proves.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - A mutable reference to a `TcpStream` connected to a peer with the filter of the
+    ///   wallet loaded.
+    /// * `block_hash` - The hash of the new block.
+    /// * `path` - The path the block would be saved to, which identifies it in the wallet.
+    /// * `wallet_channel` - A reference to an Arc Mutex `WalletChannel` for sending the merkleblock to the wallet.
+    /// * `events` - The sink the new block is emitted to, such as the UI.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the merkleblock could not be received, is invalid or its header
+    /// could not be saved, or if the wallet could not be sent the merkleblock.
+    fn download_merkle_block(
+        stream: &mut TcpStream,
+        block_hash: BlockHash,
+        path: String,
+        wallet_channel: &Arc<Mutex<WalletChannel>>,
+        events: &impl EventSink,
+    ) -> Result<(), NodeError> {
+        let (merkle_block, transactions) = fetch_merkle_block(
+            stream,
+            block_hash,
+            Duration::from_secs(MERKLE_BLOCK_TIMEOUT),
+        )?;
+        validate_and_save_merkle_block_listener(&merkle_block, events)?;
+        let path = BlockMessage::block_path_for_header(&merkle_block.block_header).unwrap_or(path);
+
+        wallet_channel
+            .lock()
+            .map_err(|_| NodeError::FailedToConvert("failed to lock wallet channel".to_string()))?
+            .send(NodeWalletMsg::NewMerkleBlock(
+                path,
+                merkle_block,
+                transactions,
+            ))
+    }
+
     /// Waits for the worker thread to finish execution.
     /// Returns a `Result` containing the `TcpStream` returned by the worker thread on success,
     /// or a `NodeError` on failure.
@@ -359,41 +435,41 @@ impl MessageListener {
     /// Processes a new transaction.
     ///
     /// This function takes a `Transaction`, a reference to a `Logger` wrapped in an `Arc<Mutex>`,
-    /// a reference to a `WalletChannel` wrapped in an `Arc<Mutex>`, and a reference to a `Sender<UIMessage>`.
-    /// It prints the received transaction ID, sends a `NewTransaction` message to the UI thread,
-    /// logs the transaction, and retrieves user addresses from the transaction.
+    /// a reference to a `WalletChannel` wrapped in an `Arc<Mutex>`, and an `EventSink`.
+    /// It prints the received transaction ID, logs the transaction, emits a `NewTransaction` event
+    /// and sends the transaction to the wallet.
     ///
     /// # Arguments
     ///
     /// * `tx` - A `Transaction` representing the new transaction to process.
     /// * `logger` - A reference to a `Logger` wrapped in an `Arc<Mutex>` for logging purposes.
     /// * `wallet_channel` - A reference to a `WalletChannel` wrapped in an `Arc<Mutex>` for communication with the wallet.
-    /// * `ui_sender` - A reference to a `Sender<UIMessage>` for sending messages to the UI thread.
+    /// * `events` - The sink the new transaction is emitted to, such as the UI.
     fn process_transaction(
         tx: Transaction,
         logger: &Arc<Mutex<Logger>>,
         wallet_channel: &Arc<Mutex<WalletChannel>>,
+        events: &impl EventSink,
     ) -> Result<(), NodeError> {
-        let mut tx_id = tx.tx_id();
-        tx_id.reverse();
-        println!(
-            "Received new transaction: {:?}",
-            Utils::bytes_to_hex(&tx_id)
-        );
+        let tx_id = InternalHash::from_slice(&tx.tx_id())?.to_display();
+        println!("Received new transaction: {}", tx_id);
 
         match logger
             .lock()
             .map_err(|_| NodeError::FailedToLog("Failed to lock logger in listener".to_string()))?
-            .log(format!(
-                "Received transaction: {:?}",
-                Utils::bytes_to_hex(&tx_id)
-            )) {
+            .log(format!("Received transaction: {}", tx_id))
+        {
             Ok(_) => {}
             Err(e) => {
                 println!("Failed to log new transaction: {:?}", e);
             }
         }
 
+        events
+            .emit(NodeEvent::NewTransaction(tx.clone()))
+            .unwrap_or_else(|e| {
+                println!("Failed to emit new transaction event: {:?}", e);
+            });
         send_tx_to_wallet(tx, wallet_channel)?;
         Ok(())
     }
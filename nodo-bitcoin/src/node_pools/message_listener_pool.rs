@@ -1,11 +1,9 @@
-use glib::Sender;
-
 use super::listener::MessageListener;
-use crate::channels::wallet_channel::WalletChannel;
+use crate::channels::{event_sink::EventSink, wallet_channel::WalletChannel};
 use crate::logger::Logger;
+use crate::node::connection_limiter::OutboundStream;
 use crate::node_error::NodeError;
 use crate::transactions::utxo_set::UtxoSet;
-use crate::ui::ui_message::UIMessage;
 use crate::utils::Utils;
 use std::net::TcpStream;
 use std::sync::{Arc, Mutex};
@@ -20,9 +18,10 @@ impl MessageListenerPool {
     ///
     /// # Arguments
     ///
-    /// * `size` - The number of threads in the thread pool.
+    /// * `size` - The maximum number of threads in the thread pool, the remaining connections are not listened to.
     /// * `streams` - A vector of `TcpStream` instances.
     /// * `utxo_set` - The `UtxoSet` as an Arc Mutex instance to be used by the `BlockBroadcasting` instances.
+    /// * `events` - The sink the new blocks and transactions are emitted to, such as the UI.
     /// * `logger` - The `Logger` instance to be used by the `BlockBroadcasting` instances.
     ///
     /// # Returns
@@ -36,9 +35,9 @@ impl MessageListenerPool {
     /// Returns a `NodeError::FailedToConnect` variant if no thread could be created.
     pub fn new(
         size: usize,
-        connections: &Vec<TcpStream>,
+        connections: &[OutboundStream],
         utxo_set_arc: Arc<Mutex<UtxoSet>>,
-        ui_sender: Sender<UIMessage>,
+        events: impl EventSink + Clone + Send + 'static,
         wallet_channel: WalletChannel,
         logger: Logger,
     ) -> Result<MessageListenerPool, NodeError> {
@@ -53,6 +52,9 @@ impl MessageListenerPool {
 
         let logger = Arc::new(Mutex::new(logger));
         for stream in connections {
+            if id == size {
+                break;
+            }
             if !Utils::is_tcpstream_connected(stream) {
                 continue;
             }
@@ -65,7 +67,7 @@ impl MessageListenerPool {
                 })?,
                 Arc::clone(&utxo_set_arc),
                 Arc::clone(&wallet_channel_arc),
-                ui_sender.clone(),
+                events.clone(),
                 Arc::clone(&logger),
             ) {
                 Ok(downloader) => {
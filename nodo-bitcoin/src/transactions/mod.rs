@@ -1,7 +1,10 @@
 pub mod outpoint;
 pub mod pk_script;
+pub mod script_interpreter;
 pub mod signature_script;
 pub mod transaction;
 pub mod tx_input;
 pub mod tx_output;
 pub mod utxo_set;
+pub mod utxo_spill_store;
+pub mod witness;
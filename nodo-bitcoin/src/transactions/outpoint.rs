@@ -4,7 +4,7 @@ use crate::{
 };
 use std::io::Read;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// Represents an outpoint.
 pub struct Outpoint {
     /// The hash of the transaction of the output being spent.
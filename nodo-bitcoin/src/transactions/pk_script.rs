@@ -1,2 +1,153 @@
+use std::fmt;
+
+use crate::{
+    constants::{OP_0, OP_EQUAL, OP_HASH160, OP_RETURN, PK_HASH_LENGTH},
+    wallet::{
+        account::Account,
+        bitcoin_address::{BitcoinAddress, Network},
+    },
+};
+
+use super::script_interpreter::is_p2pkh;
+
 /// Respresents a Public Key Script, which is a vec of u8.
 pub type PkScript = Vec<u8>;
+
+/// The standard kinds of public key scripts.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum ScriptType {
+    /// Pays to the hash of a public key.
+    P2PKH,
+    /// Pays to the hash of a script.
+    P2SH,
+    /// Pays to the hash of a public key, with the signature in the witness.
+    P2WPKH,
+    /// Holds data and can't be spent.
+    OpReturn,
+    /// Any other script.
+    NonStandard,
+}
+
+impl fmt::Display for ScriptType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            ScriptType::P2PKH => "P2PKH",
+            ScriptType::P2SH => "P2SH",
+            ScriptType::P2WPKH => "P2WPKH",
+            ScriptType::OpReturn => "OP_RETURN",
+            ScriptType::NonStandard => "Non standard",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// Helpers to show a public key script in a readable way.
+pub trait PkScriptExt {
+    /// Returns the kind of the script.
+    fn script_type(&self) -> ScriptType;
+
+    /// Returns the address the script pays to in the given network.
+    ///
+    /// # Returns
+    ///
+    /// The address of P2PKH and P2SH scripts, or `None` for other scripts, as P2WPKH scripts have
+    /// bech32 addresses.
+    fn to_address(&self, network: Network) -> Option<BitcoinAddress>;
+
+    /// Returns a label with the kind of the script and its address, like "P2PKH mx...".
+    fn label(&self, network: Network) -> String;
+}
+
+impl PkScriptExt for PkScript {
+    fn script_type(&self) -> ScriptType {
+        let hash_length = PK_HASH_LENGTH as usize;
+        if is_p2pkh(self) {
+            ScriptType::P2PKH
+        } else if self.len() == hash_length + 3
+            && self[0] == OP_HASH160
+            && self[1] == PK_HASH_LENGTH
+            && self[hash_length + 2] == OP_EQUAL
+        {
+            ScriptType::P2SH
+        } else if self.len() == hash_length + 2 && self[0] == OP_0 && self[1] == PK_HASH_LENGTH {
+            ScriptType::P2WPKH
+        } else if self.first() == Some(&OP_RETURN) {
+            ScriptType::OpReturn
+        } else {
+            ScriptType::NonStandard
+        }
+    }
+
+    fn to_address(&self, network: Network) -> Option<BitcoinAddress> {
+        match self.script_type() {
+            ScriptType::P2PKH => Account::pk_script_to_pk_hash(self)
+                .ok()
+                .map(|pk_hash| BitcoinAddress::from_hash(network.p2pkh_version(), &pk_hash)),
+            ScriptType::P2SH => Some(BitcoinAddress::from_hash(
+                network.p2sh_version(),
+                &self[2..PK_HASH_LENGTH as usize + 2],
+            )),
+            _ => None,
+        }
+    }
+
+    fn label(&self, network: Network) -> String {
+        match self.to_address(network) {
+            Some(address) => format!("{} {}", self.script_type(), address.bs58_to_string()),
+            None => self.script_type().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::{OP_CHECKSIG, OP_DUP, OP_EQUALVERIFY},
+        node_error::NodeError,
+    };
+
+    const ADDRESS: &str = "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk";
+    const P2SH_ADDRESS: &str = "2MzQwSSnBHWHqSAqtTVQ6v47XtaisrJa1Vc";
+
+    #[test]
+    fn test_script_types_are_classified() -> Result<(), NodeError> {
+        let address = BitcoinAddress::from_string(&ADDRESS.to_string())?;
+        let p2pkh = BitcoinAddress::to_pk_script(&address);
+        let p2sh_address = BitcoinAddress::from_string(&P2SH_ADDRESS.to_string())?;
+        let p2sh = BitcoinAddress::to_pk_script(&p2sh_address);
+        let mut p2wpkh = vec![OP_0, PK_HASH_LENGTH];
+        p2wpkh.extend([7; 20]);
+        let op_return: PkScript = vec![OP_RETURN, 0x02, 0xca, 0xfe];
+        let mut truncated_p2pkh = p2pkh.clone();
+        truncated_p2pkh.pop();
+        let bare_checksig: PkScript = vec![OP_DUP, OP_EQUALVERIFY, OP_CHECKSIG];
+
+        assert_eq!(p2pkh.script_type(), ScriptType::P2PKH);
+        assert_eq!(p2sh.script_type(), ScriptType::P2SH);
+        assert_eq!(p2wpkh.script_type(), ScriptType::P2WPKH);
+        assert_eq!(op_return.script_type(), ScriptType::OpReturn);
+        assert_eq!(truncated_p2pkh.script_type(), ScriptType::NonStandard);
+        assert_eq!(bare_checksig.script_type(), ScriptType::NonStandard);
+        assert_eq!(PkScript::new().script_type(), ScriptType::NonStandard);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scripts_are_decoded_into_addresses() -> Result<(), NodeError> {
+        let address = BitcoinAddress::from_string(&ADDRESS.to_string())?;
+        let p2pkh = BitcoinAddress::to_pk_script(&address);
+        let p2sh_address = BitcoinAddress::from_string(&P2SH_ADDRESS.to_string())?;
+        let p2sh = BitcoinAddress::to_pk_script(&p2sh_address);
+
+        assert_eq!(p2pkh.to_address(Network::Testnet), Some(address));
+        assert_eq!(p2sh.to_address(Network::Testnet), Some(p2sh_address));
+        assert_eq!(p2pkh.label(Network::Testnet), format!("P2PKH {}", ADDRESS));
+        assert!(p2pkh
+            .to_address(Network::Mainnet)
+            .is_some_and(|address| address.bs58_to_string().starts_with('1')));
+        assert_eq!(vec![OP_RETURN].to_address(Network::Testnet), None);
+        assert_eq!(vec![OP_RETURN].label(Network::Testnet), "OP_RETURN");
+        Ok(())
+    }
+}
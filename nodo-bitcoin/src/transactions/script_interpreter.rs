@@ -0,0 +1,301 @@
+use bitcoin_hashes::{hash160, sha256, Hash};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1};
+
+use crate::{
+    compact_size::CompactSize,
+    constants::{
+        OP_CHECKSIG, OP_DUP, OP_EQUALVERIFY, OP_HASH160, OP_PUSHDATA1, OP_PUSHDATA2, SIGHASH_ALL,
+    },
+    node_error::NodeError,
+};
+
+use super::{pk_script::PkScript, transaction::Transaction};
+
+/// Stack used while executing a script.
+type Stack = Vec<Vec<u8>>;
+
+/// Verifies that an input of a transaction correctly spends a P2PKH output.
+///
+/// The signature script of the input is executed first, and then the public key script of the
+/// previous output, as in OP_DUP OP_HASH160 <pk_hash> OP_EQUALVERIFY OP_CHECKSIG.
+///
+/// # Arguments
+///
+/// * `transaction` - The transaction that contains the input.
+/// * `input_index` - The index of the input to verify.
+/// * `prev_pk_script` - The public key script of the output spent by the input.
+///
+/// # Errors
+///
+/// Returns a `NodeError::ScriptValidationFailed` if the script does not evaluate to true, or a
+/// `NodeError::UnsupportedScript` if it uses an opcode or sighash type this interpreter doesn't support.
+pub fn verify_p2pkh_input(
+    transaction: &Transaction,
+    input_index: usize,
+    prev_pk_script: &PkScript,
+) -> Result<(), NodeError> {
+    let tx_input = transaction.tx_inputs.get(input_index).ok_or_else(|| {
+        NodeError::ScriptValidationFailed(format!("Input {} does not exist", input_index))
+    })?;
+
+    let mut stack = script_pushes(&tx_input.signature_script)?;
+    execute_script(prev_pk_script, &mut stack, |signature, public_key| {
+        check_signature(
+            transaction,
+            input_index,
+            prev_pk_script,
+            signature,
+            public_key,
+        )
+    })?;
+
+    match stack.pop() {
+        Some(top) if top == [1] => Ok(()),
+        _ => Err(NodeError::ScriptValidationFailed(
+            "Script did not evaluate to true".to_string(),
+        )),
+    }
+}
+
+/// Returns true if the public key script is a P2PKH script.
+pub fn is_p2pkh(pk_script: &PkScript) -> bool {
+    pk_script.len() == 25
+        && pk_script[0] == OP_DUP
+        && pk_script[1] == OP_HASH160
+        && pk_script[2] == 20
+        && pk_script[23] == OP_EQUALVERIFY
+        && pk_script[24] == OP_CHECKSIG
+}
+
+/// Returns the data pushed by a script made only of push operations, such as a signature script.
+///
+/// # Errors
+///
+/// Returns a `NodeError::ScriptValidationFailed` if a push exceeds the script length, or a
+/// `NodeError::UnsupportedScript` if the script has an operation other than a push.
+pub fn script_pushes(script: &[u8]) -> Result<Vec<Vec<u8>>, NodeError> {
+    let mut stack = Stack::new();
+    execute_script(script, &mut stack, |_, _| {
+        Err(NodeError::UnsupportedScript(
+            "Signature scripts can only push data".to_string(),
+        ))
+    })?;
+    Ok(stack)
+}
+
+/// Executes a script over the given stack.
+///
+/// # Arguments
+///
+/// * `script` - The script to execute.
+/// * `stack` - The stack the script operates on.
+/// * `checksig` - Verifies a signature against a public key when OP_CHECKSIG is found.
+///
+/// # Errors
+///
+/// Returns a `NodeError` if the script is malformed, fails, or uses an unsupported opcode.
+fn execute_script<F>(script: &[u8], stack: &mut Stack, checksig: F) -> Result<(), NodeError>
+where
+    F: Fn(&[u8], &[u8]) -> Result<bool, NodeError>,
+{
+    let mut position = 0;
+    while position < script.len() {
+        let opcode = script[position];
+        position += 1;
+
+        match opcode {
+            0x01..=0x4b => {
+                stack.push(read_push(script, &mut position, opcode as usize)?);
+            }
+            OP_PUSHDATA1 => {
+                let length = read_push(script, &mut position, 1)?[0] as usize;
+                stack.push(read_push(script, &mut position, length)?);
+            }
+            OP_PUSHDATA2 => {
+                let length_bytes = read_push(script, &mut position, 2)?;
+                let length = u16::from_le_bytes([length_bytes[0], length_bytes[1]]) as usize;
+                stack.push(read_push(script, &mut position, length)?);
+            }
+            OP_DUP => {
+                let top = peek(stack)?.clone();
+                stack.push(top);
+            }
+            OP_HASH160 => {
+                let top = pop(stack)?;
+                stack.push(hash160::Hash::hash(&top).to_byte_array().to_vec());
+            }
+            OP_EQUALVERIFY => {
+                if pop(stack)? != pop(stack)? {
+                    return Err(NodeError::ScriptValidationFailed(
+                        "OP_EQUALVERIFY failed".to_string(),
+                    ));
+                }
+            }
+            OP_CHECKSIG => {
+                let public_key = pop(stack)?;
+                let signature = pop(stack)?;
+                let result = checksig(&signature, &public_key)?;
+                stack.push(if result { vec![1] } else { vec![] });
+            }
+            _ => {
+                return Err(NodeError::UnsupportedScript(format!(
+                    "Unsupported opcode {:#04x}",
+                    opcode
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads `length` bytes from the script, advancing the position.
+fn read_push(script: &[u8], position: &mut usize, length: usize) -> Result<Vec<u8>, NodeError> {
+    let end = *position + length;
+    let data = script.get(*position..end).ok_or_else(|| {
+        NodeError::ScriptValidationFailed("Push exceeds the script length".to_string())
+    })?;
+    *position = end;
+    Ok(data.to_vec())
+}
+
+/// Returns the element at the top of the stack.
+fn peek(stack: &Stack) -> Result<&Vec<u8>, NodeError> {
+    stack
+        .last()
+        .ok_or_else(|| NodeError::ScriptValidationFailed("Stack is empty".to_string()))
+}
+
+/// Removes and returns the element at the top of the stack.
+fn pop(stack: &mut Stack) -> Result<Vec<u8>, NodeError> {
+    stack
+        .pop()
+        .ok_or_else(|| NodeError::ScriptValidationFailed("Stack is empty".to_string()))
+}
+
+/// Verifies a SIGHASH_ALL signature of an input against a public key.
+///
+/// The signature hash is computed over a copy of the transaction where every signature script is
+/// empty, except for the one of the verified input, which is replaced by the previous public key script.
+///
+/// # Returns
+///
+/// `Ok(true)` if the signature is valid, `Ok(false)` if it isn't.
+///
+/// # Errors
+///
+/// Returns a `NodeError::UnsupportedScript` if the signature uses a sighash type other than SIGHASH_ALL.
+fn check_signature(
+    transaction: &Transaction,
+    input_index: usize,
+    prev_pk_script: &PkScript,
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<bool, NodeError> {
+    let (sighash_type, der_signature) = match signature.split_last() {
+        Some(split) => split,
+        None => return Ok(false),
+    };
+    if *sighash_type as u32 != SIGHASH_ALL {
+        return Err(NodeError::UnsupportedScript(format!(
+            "Unsupported sighash type {}",
+            sighash_type
+        )));
+    }
+
+    let mut signature = match Signature::from_der_lax(der_signature) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(false),
+    };
+    signature.normalize_s();
+    let public_key = match PublicKey::from_slice(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return Ok(false),
+    };
+
+    let mut unsigned_transaction = transaction.clone();
+    for tx_input in unsigned_transaction.tx_inputs.iter_mut() {
+        tx_input.script_bytes = CompactSize::new(0);
+        tx_input.signature_script = vec![];
+    }
+    let message = Message::from_hashed_data::<sha256::Hash>(
+        &unsigned_transaction.individual_signature_hash(input_index, prev_pk_script.clone()),
+    );
+
+    Ok(Secp256k1::verification_only()
+        .verify_ecdsa(&message, &signature, &public_key)
+        .is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        block::retrieve_transactions_from_block, node_error::NodeError,
+        transactions::transaction::Transaction,
+    };
+
+    use super::*;
+
+    /// Returns a P2PKH spend from the test block and the public key script of the output it spends.
+    fn p2pkh_spend() -> Result<(Transaction, PkScript), NodeError> {
+        let transactions = retrieve_transactions_from_block(
+            &"blocks-test/00000000a04a58762cdf594616b5875945de5b0dc3ad7ee08749940bf130b7d3.bin"
+                .to_string(),
+        )?;
+
+        for transaction in transactions.iter() {
+            let outpoint = &transaction.tx_inputs[0].previous_output;
+            for previous in transactions.iter() {
+                if previous.tx_id() != outpoint.tx_id {
+                    continue;
+                }
+                let pk_script = previous.tx_outputs[outpoint.index as usize]
+                    .pk_script
+                    .clone();
+                if is_p2pkh(&pk_script) {
+                    return Ok((transaction.clone(), pk_script));
+                }
+            }
+        }
+        Err(NodeError::FailedToRead("No P2PKH spend found".to_string()))
+    }
+
+    #[test]
+    fn test_verify_real_p2pkh_spend() -> Result<(), NodeError> {
+        let (transaction, pk_script) = p2pkh_spend()?;
+
+        verify_p2pkh_input(&transaction, 0, &pk_script)
+    }
+
+    #[test]
+    fn test_verify_tampered_signature_fails() -> Result<(), NodeError> {
+        let (mut transaction, pk_script) = p2pkh_spend()?;
+        // The first byte is the push length and the second one the DER sequence tag.
+        transaction.tx_inputs[0].signature_script[10] ^= 0x01;
+
+        let result = verify_p2pkh_input(&transaction, 0, &pk_script);
+
+        assert!(matches!(result, Err(NodeError::ScriptValidationFailed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_wrong_pk_script_fails() -> Result<(), NodeError> {
+        let (transaction, mut pk_script) = p2pkh_spend()?;
+        pk_script[3] ^= 0x01;
+
+        let result = verify_p2pkh_input(&transaction, 0, &pk_script);
+
+        assert!(matches!(result, Err(NodeError::ScriptValidationFailed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_p2pkh() {
+        let mut pk_script = vec![OP_DUP, OP_HASH160, 20];
+        pk_script.extend([0; 20]);
+        pk_script.extend([OP_EQUALVERIFY, OP_CHECKSIG]);
+
+        assert!(is_p2pkh(&pk_script));
+        assert!(!is_p2pkh(&vec![OP_DUP, OP_HASH160]));
+    }
+}
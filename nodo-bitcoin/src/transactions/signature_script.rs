@@ -1,2 +1,51 @@
+use crate::node_error::NodeError;
+
+use super::script_interpreter::script_pushes;
+
 /// Respresents Signature Script, which is a vec of u8.
 pub type SignatureScript = Vec<u8>;
+
+/// Helpers to inspect the parts of a P2PKH signature script: <signature> <public key>.
+pub trait SignatureScriptExt {
+    /// Returns the DER signature of the script and the sighash byte appended to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::NotP2PKHScript` if the script is not made of a signature push followed
+    /// by a public key push, or the error of parsing its pushes.
+    fn extract_signature(&self) -> Result<(Vec<u8>, u8), NodeError>;
+
+    /// Returns the public key of the script, in SEC format.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::NotP2PKHScript` if the script is not made of a signature push followed
+    /// by a public key push, or the error of parsing its pushes.
+    fn extract_pubkey(&self) -> Result<Vec<u8>, NodeError>;
+}
+
+impl SignatureScriptExt for SignatureScript {
+    fn extract_signature(&self) -> Result<(Vec<u8>, u8), NodeError> {
+        let (mut signature, _) = signature_and_pubkey(self)?;
+        let sighash_type = signature.pop().ok_or_else(|| {
+            NodeError::NotP2PKHScript("The signature of the script is empty".to_string())
+        })?;
+        Ok((signature, sighash_type))
+    }
+
+    fn extract_pubkey(&self) -> Result<Vec<u8>, NodeError> {
+        let (_, pubkey) = signature_and_pubkey(self)?;
+        Ok(pubkey)
+    }
+}
+
+/// Returns the two pushes of a P2PKH signature script.
+fn signature_and_pubkey(script: &[u8]) -> Result<(Vec<u8>, Vec<u8>), NodeError> {
+    let mut pushes = script_pushes(script)?;
+    match (pushes.pop(), pushes.pop(), pushes.is_empty()) {
+        (Some(pubkey), Some(signature), true) => Ok((signature, pubkey)),
+        _ => Err(NodeError::NotP2PKHScript(
+            "The script doesn't push a signature and a public key".to_string(),
+        )),
+    }
+}
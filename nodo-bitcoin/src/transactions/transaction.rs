@@ -1,19 +1,33 @@
-use std::io::Read;
+use std::{collections::HashSet, io::Read};
 
 use bitcoin_hashes::{sha256, sha256d, Hash};
 
 use crate::{
-    block::tx_hash::TxHash,
+    block::{block_hash::InternalHash, tx_hash::TxHash},
     compact_size::CompactSize,
     connectors::peer_connector::receive_message,
-    constants::{LENGTH_LOCK_TIME, LENGTH_VERSION, SIGHASH_ALL},
+    constants::{
+        LENGTH_LOCK_TIME, LENGTH_VERSION, MAX_BLOCK_SIZE, MAX_MONEY, MIN_TX_INPUT_SIZE,
+        MIN_TX_OUTPUT_SIZE, P2PKH_INPUT_SIZE, P2PKH_OUTPUT_SIZE, SEGWIT_FLAG, SEGWIT_MARKER,
+        SIGHASH_ALL, WITNESS_SCALE_FACTOR,
+    },
     node_error::NodeError,
     ui::components::transactions_confirmed_data::Amount,
-    wallet::{account::Account, bitcoin_address::BitcoinAddress},
+    utils::Utils,
+    wallet::{
+        account::Account,
+        bitcoin_address::{BitcoinAddress, Network},
+    },
 };
 
 use super::{
-    pk_script::PkScript, signature_script::SignatureScript, tx_input::TxInput, tx_output::TxOutput,
+    outpoint::Outpoint,
+    pk_script::{PkScript, PkScriptExt},
+    signature_script::SignatureScript,
+    tx_input::TxInput,
+    tx_output::TxOutput,
+    utxo_set::UtxoSet,
+    witness::Witness,
 };
 
 #[derive(Debug, Clone)]
@@ -31,6 +45,8 @@ pub struct Transaction {
     pub tx_outputs: Vec<TxOutput>,
     /// The lock time of the transaction.
     pub lock_time: u32,
+    /// The witness of each transaction input, empty if the transaction is not a SegWit transaction.
+    pub witnesses: Vec<Witness>,
 }
 
 impl Transaction {
@@ -43,13 +59,41 @@ impl Transaction {
     /// # Returns
     ///
     /// A `Result` containing the parsed `Transaction` if successful, or a `NodeError` if an error occurs.
+    ///
+    /// # Remarks
+    ///
+    /// SegWit transactions (BIP141) are detected by the marker after the version, and their witnesses
+    /// are read after the outputs.
+    ///
+    /// The transaction can't be bigger than a block, see `read_transaction_with_limit`.
     pub fn read_transaction<R: Read>(block: &mut R) -> Result<Transaction, NodeError> {
+        Self::read_transaction_with_limit(block, MAX_BLOCK_SIZE)
+    }
+
+    /// Reads a transaction from a reader, rejecting the counts that can't fit in the payload before
+    /// reading the items they count, so a peer can't make the node allocate or wait for more data
+    /// than it declared.
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A mutable reference to a reader implementing the `Read` trait, can be a file, TcpStream, etc.
+    /// * `payload_size` - The size of the payload the transaction is read from, in bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidMessageFormat` if an input, output or witness count can't fit in
+    /// the payload, or another `NodeError` if the transaction could not be read.
+    pub fn read_transaction_with_limit<R: Read>(
+        block: &mut R,
+        payload_size: usize,
+    ) -> Result<Transaction, NodeError> {
         let version = receive_message(block, LENGTH_VERSION)?;
 
         //Input
-        let tx_in_count = CompactSize::read_varint(block)?;
+        let (tx_in_count, is_segwit) = Self::read_tx_in_count(block)?;
         let mut tx_inputs = Vec::new();
         let tx_in_count_value = tx_in_count.get_value();
+        check_count(tx_in_count_value, MIN_TX_INPUT_SIZE, payload_size, "inputs")?;
 
         for _ in 0..tx_in_count_value {
             let tx_input = TxInput::read_tx_input(block)?;
@@ -60,12 +104,21 @@ impl Transaction {
         let tx_out_count = CompactSize::read_varint(block)?;
         let mut tx_outputs = Vec::new();
         let tx_out_count_value = tx_out_count.get_value();
+        check_count(
+            tx_out_count_value,
+            MIN_TX_OUTPUT_SIZE,
+            payload_size,
+            "outputs",
+        )?;
 
         for i in 0..tx_out_count_value {
             let tx_output = TxOutput::read_tx_output_from_block(block, i)?;
             tx_outputs.push(tx_output);
         }
 
+        //Witnesses
+        let witnesses = Self::read_witnesses(block, is_segwit, tx_in_count_value, payload_size)?;
+
         //Lock time
         let lock_time = receive_message(block, LENGTH_LOCK_TIME)?;
 
@@ -76,6 +129,7 @@ impl Transaction {
             tx_out_count,
             tx_outputs,
             lock_time: u32::from_le_bytes([lock_time[0], lock_time[1], lock_time[2], lock_time[3]]),
+            witnesses,
         };
 
         tx.add_tx_id_to_tx_outs();
@@ -92,17 +146,25 @@ impl Transaction {
     ///
     /// Returns a `Result` containing the parsed `Transaction` if successful, or a `NodeError` if an error occurs.
     pub fn read_coinbase_transaction<R: Read>(block: &mut R) -> Result<Transaction, NodeError> {
+        let payload_size = MAX_BLOCK_SIZE;
         let version = receive_message(block, LENGTH_VERSION)?;
 
         //Input
-        let tx_in_count = CompactSize::read_varint(block)?;
-        let _tx_in_count_value = tx_in_count.get_value();
+        let (tx_in_count, is_segwit) = Self::read_tx_in_count(block)?;
+        let tx_in_count_value = tx_in_count.get_value();
+        check_count(tx_in_count_value, MIN_TX_INPUT_SIZE, payload_size, "inputs")?;
         let tx_inputs = vec![TxInput::read_tx_coinbase_input(block)?];
 
         //Output
         let tx_out_count = CompactSize::read_varint(block)?;
         let mut tx_outputs = Vec::new();
         let tx_out_count_value = tx_out_count.get_value();
+        check_count(
+            tx_out_count_value,
+            MIN_TX_OUTPUT_SIZE,
+            payload_size,
+            "outputs",
+        )?;
 
         for i in 0..tx_out_count_value {
             let tx_output = TxOutput::read_tx_output_from_block(block, i)?;
@@ -110,6 +172,9 @@ impl Transaction {
             tx_outputs.push(tx_output);
         }
 
+        //Witnesses
+        let witnesses = Self::read_witnesses(block, is_segwit, tx_in_count_value, payload_size)?;
+
         //Lock time
         let lock_time = receive_message(block, LENGTH_LOCK_TIME)?;
 
@@ -120,6 +185,7 @@ impl Transaction {
             tx_out_count,
             tx_outputs,
             lock_time: u32::from_le_bytes([lock_time[0], lock_time[1], lock_time[2], lock_time[3]]),
+            witnesses,
         };
 
         tx.add_tx_id_to_tx_outs();
@@ -127,24 +193,175 @@ impl Transaction {
         Ok(tx)
     }
 
+    /// Reads the number of transaction inputs, skipping the SegWit marker and flag if present.
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A mutable reference to a reader implementing the `Read` trait, positioned after the version.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the number of inputs and whether the transaction is a SegWit transaction,
+    /// or a `NodeError::InvalidTransaction` if the marker is not followed by a valid flag.
+    fn read_tx_in_count<R: Read>(block: &mut R) -> Result<(CompactSize, bool), NodeError> {
+        let tx_in_count = CompactSize::read_varint(block)?;
+        if tx_in_count.get_value() != u64::from(SEGWIT_MARKER) {
+            return Ok((tx_in_count, false));
+        }
+
+        let flag = receive_message(block, 1)?;
+        if flag[0] != SEGWIT_FLAG {
+            return Err(NodeError::InvalidTransaction(
+                "Invalid SegWit flag in transaction".to_string(),
+            ));
+        }
+
+        Ok((CompactSize::read_varint(block)?, true))
+    }
+
+    /// Reads the witness of each transaction input.
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - A mutable reference to a reader implementing the `Read` trait, positioned after the outputs.
+    /// * `is_segwit` - Whether the transaction is a SegWit transaction, if not no bytes are read.
+    /// * `tx_in_count` - The number of transaction inputs.
+    /// * `payload_size` - The size of the payload the transaction is read from, bounding the witness items.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a witness for each input, or a `NodeError` if an error occurs while reading.
+    fn read_witnesses<R: Read>(
+        block: &mut R,
+        is_segwit: bool,
+        tx_in_count: u64,
+        payload_size: usize,
+    ) -> Result<Vec<Witness>, NodeError> {
+        let mut witnesses = Vec::new();
+        if !is_segwit {
+            return Ok(witnesses);
+        }
+
+        for _ in 0..tx_in_count {
+            let items_count = CompactSize::read_varint(block)?.get_value();
+            check_count(items_count, 1, payload_size, "witness items")?;
+            let mut witness = Vec::new();
+            for _ in 0..items_count {
+                let item_length = CompactSize::read_varint(block)?.get_value();
+                check_count(item_length, 1, payload_size, "witness item bytes")?;
+                witness.push(receive_message(block, item_length as usize)?);
+            }
+            witnesses.push(witness);
+        }
+
+        Ok(witnesses)
+    }
+
+    /// Creates a new transaction without witnesses, deriving the input and output counts from the vectors.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_inputs` - The transaction inputs.
+    /// * `tx_outputs` - The transaction outputs.
+    /// * `version` - The version of the transaction.
+    /// * `lock_time` - The lock time of the transaction.
+    pub fn new(
+        tx_inputs: Vec<TxInput>,
+        tx_outputs: Vec<TxOutput>,
+        version: u32,
+        lock_time: u32,
+    ) -> Transaction {
+        Transaction {
+            version,
+            tx_in_count: CompactSize::new(tx_inputs.len()),
+            tx_inputs,
+            tx_out_count: CompactSize::new(tx_outputs.len()),
+            tx_outputs,
+            lock_time,
+            witnesses: Vec::new(),
+        }
+    }
+
     /// Creates a new transaction with unsigned inputs.
     pub fn new_unsigned(unsigned_tx_ins: Vec<TxInput>, tx_outs: Vec<TxOutput>) -> Transaction {
-        Transaction {
-            version: 1,
-            tx_in_count: CompactSize::new(unsigned_tx_ins.len()),
-            tx_inputs: unsigned_tx_ins,
-            tx_out_count: CompactSize::new(tx_outs.len()),
-            tx_outputs: tx_outs,
-            lock_time: 0,
+        Self::new(unsigned_tx_ins, tx_outs, 1, 0)
+    }
+
+    /// Checks that the input and output counts match the number of inputs and outputs of the transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidTransaction` if any of the counts doesn't match, as the transaction
+    /// would be serialized wrongly.
+    pub fn validate_counts(&self) -> Result<(), NodeError> {
+        if self.tx_in_count.get_value() != self.tx_inputs.len() as u64 {
+            return Err(NodeError::InvalidTransaction(format!(
+                "The input count is {} but the transaction has {} inputs",
+                self.tx_in_count.get_value(),
+                self.tx_inputs.len()
+            )));
+        }
+        if self.tx_out_count.get_value() != self.tx_outputs.len() as u64 {
+            return Err(NodeError::InvalidTransaction(format!(
+                "The output count is {} but the transaction has {} outputs",
+                self.tx_out_count.get_value(),
+                self.tx_outputs.len()
+            )));
         }
+        Ok(())
     }
 
-    /// Converts the transaction to a byte representation.
+    /// Converts the transaction to a byte representation, as it's broadcasted.
+    ///
+    /// SegWit transactions are serialized with the marker, the flag and the witnesses (BIP144).
+    /// Transactions without witness data are serialized in the legacy format.
     ///
     /// # Returns
     ///
     /// A vector of bytes representing the transaction.
     pub fn to_bytes(&self) -> Vec<u8> {
+        if !self.has_witness() {
+            return self.to_bytes_no_witness();
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend(self.version.to_le_bytes().to_vec());
+        bytes.push(SEGWIT_MARKER);
+        bytes.push(SEGWIT_FLAG);
+        bytes.extend(self.tx_in_count.to_bytes());
+        for tx_input in &self.tx_inputs {
+            bytes.extend(tx_input.to_bytes());
+        }
+
+        bytes.extend(self.tx_out_count.to_bytes());
+        for tx_output in &self.tx_outputs {
+            bytes.extend(tx_output.to_bytes());
+        }
+
+        for i in 0..self.tx_inputs.len() {
+            let witness = self.witnesses.get(i).cloned().unwrap_or_default();
+            bytes.extend(CompactSize::new(witness.len()).to_bytes());
+            for item in witness {
+                bytes.extend(CompactSize::new(item.len()).to_bytes());
+                bytes.extend(item);
+            }
+        }
+        bytes.extend(self.lock_time.to_le_bytes().to_vec());
+        bytes
+    }
+
+    /// Returns true if any input of the transaction has witness data.
+    pub fn has_witness(&self) -> bool {
+        self.witnesses.iter().any(|witness| !witness.is_empty())
+    }
+
+    /// Converts the transaction to a byte representation without the witnesses, the one the
+    /// transaction id and the legacy signature hashes are computed over.
+    ///
+    /// # Returns
+    ///
+    /// A vector of bytes representing the transaction.
+    pub fn to_bytes_no_witness(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend(self.version.to_le_bytes().to_vec());
         bytes.extend(self.tx_in_count.to_bytes());
@@ -161,8 +378,49 @@ impl Transaction {
         bytes
     }
 
-    /// Gets the transaction id.
+    /// Returns the size in bytes of the transaction as it's sent to the network, witnesses included.
+    pub fn serialized_size(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    /// Returns the virtual size of the transaction in vBytes (BIP141): the witness data is discounted
+    /// by the witness scale factor, so a transaction without witnesses weighs as much as its bytes.
+    pub fn virtual_size(&self) -> usize {
+        let base_size = self.to_bytes_no_witness().len();
+        let total_size = self.serialized_size();
+        let weight = base_size * (WITNESS_SCALE_FACTOR - 1) + total_size;
+        weight.div_ceil(WITNESS_SCALE_FACTOR)
+    }
+
+    /// Estimates the serialized size of a signed transaction that spends and pays only P2PKH
+    /// scripts, before its inputs are chosen.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_inputs` - The number of inputs of the transaction.
+    /// * `num_outputs` - The number of outputs of the transaction, including the change.
+    ///
+    /// # Returns
+    ///
+    /// The estimated size in bytes, assuming signatures of the maximum length.
+    pub fn estimate_size(num_inputs: usize, num_outputs: usize) -> usize {
+        LENGTH_VERSION
+            + CompactSize::new(num_inputs).to_bytes().len()
+            + num_inputs * P2PKH_INPUT_SIZE
+            + CompactSize::new(num_outputs).to_bytes().len()
+            + num_outputs * P2PKH_OUTPUT_SIZE
+            + LENGTH_LOCK_TIME
+    }
+
+    /// Gets the transaction id, computed over the serialization without witnesses.
     pub fn tx_id(&self) -> TxHash {
+        let tx_bytes = self.to_bytes_no_witness();
+        sha256d::Hash::hash(&tx_bytes).to_byte_array().to_vec()
+    }
+
+    /// Gets the witness transaction id (BIP141), computed over the serialization with witnesses.
+    /// It's the same as the transaction id for transactions without witnesses.
+    pub fn witness_tx_id(&self) -> TxHash {
         let tx_bytes = self.to_bytes();
         sha256d::Hash::hash(&tx_bytes).to_byte_array().to_vec()
     }
@@ -181,7 +439,7 @@ impl Transaction {
         self.tx_inputs[i].script_bytes = CompactSize::new(pk_script.len());
         self.tx_inputs[i].signature_script = pk_script;
 
-        let mut tx_bytes = self.to_bytes();
+        let mut tx_bytes = self.to_bytes_no_witness();
         tx_bytes.append(&mut SIGHASH_ALL.to_le_bytes().to_vec());
 
         let sig_hash = sha256::Hash::hash(&tx_bytes).to_byte_array().to_vec();
@@ -225,6 +483,162 @@ impl Transaction {
         &self.tx_inputs
     }
 
+    /// Returns the number of transaction inputs.
+    pub fn input_count(&self) -> usize {
+        self.tx_in_count.get_value() as usize
+    }
+
+    /// Returns the number of transaction outputs.
+    pub fn output_count(&self) -> usize {
+        self.tx_out_count.get_value() as usize
+    }
+
+    /// Returns the sum of the values of the transaction outputs, in satoshis.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidTransaction` if an output has a negative value or the sum overflows.
+    pub fn total_output_value(&self) -> Result<u64, NodeError> {
+        sum_output_values(&self.tx_outputs)
+    }
+
+    /// Checks that the transaction is well formed: it has inputs and outputs, doesn't spend the
+    /// same output twice, and its outputs don't create more than the 21 million coins that can exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::InvalidMessageFormat` describing the first check that failed.
+    pub fn validate_structure(&self) -> Result<(), NodeError> {
+        if self.tx_inputs.is_empty() {
+            return Err(NodeError::InvalidMessageFormat(
+                "The transaction has no inputs".to_string(),
+            ));
+        }
+        if self.tx_outputs.is_empty() {
+            return Err(NodeError::InvalidMessageFormat(
+                "The transaction has no outputs".to_string(),
+            ));
+        }
+
+        let mut spent_outputs = HashSet::new();
+        if !self
+            .tx_inputs
+            .iter()
+            .all(|tx_input| spent_outputs.insert(&tx_input.previous_output))
+        {
+            return Err(NodeError::InvalidMessageFormat(
+                "The transaction spends the same output twice".to_string(),
+            ));
+        }
+
+        for tx_output in &self.tx_outputs {
+            if !(0..=MAX_MONEY).contains(&tx_output.value) {
+                return Err(NodeError::InvalidMessageFormat(format!(
+                    "Output value {} is out of range",
+                    tx_output.value
+                )));
+            }
+        }
+        if self.total_output_value()? > MAX_MONEY.unsigned_abs() {
+            return Err(NodeError::InvalidMessageFormat(
+                "The outputs create more than the maximum supply".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks if the transaction spends any of the outputs spent by another transaction, so both
+    /// can't be confirmed. Only the outpoints are compared, not the scripts of the inputs.
+    pub fn conflicts_with(&self, other: &Transaction) -> bool {
+        let spent_outputs: HashSet<&Outpoint> = self
+            .tx_inputs
+            .iter()
+            .map(|tx_input| &tx_input.previous_output)
+            .collect();
+        other
+            .tx_inputs
+            .iter()
+            .any(|tx_input| spent_outputs.contains(&tx_input.previous_output))
+    }
+
+    /// Checks if the transaction is a coinbase: a single input that doesn't spend any output.
+    pub fn is_coinbase(&self) -> bool {
+        match self.tx_inputs.as_slice() {
+            [tx_input] => {
+                tx_input.previous_output.index == u32::MAX
+                    && tx_input.previous_output.tx_id.iter().all(|byte| *byte == 0)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the fee of the transaction, in satoshis: the value of the outputs it spends minus the
+    /// value of its outputs.
+    ///
+    /// # Arguments
+    ///
+    /// * `utxo_set` - The UTXO set holding the outputs spent by the transaction.
+    ///
+    /// # Returns
+    ///
+    /// The fee, or `None` if the transaction is a coinbase.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::OutpointNotFound` if an output spent by the transaction is not in the
+    /// UTXO set, or a `NodeError::InvalidTransaction` if the outputs are worth more than the inputs.
+    pub fn fee(&self, utxo_set: &UtxoSet) -> Result<Option<u64>, NodeError> {
+        self.fee_spending(|outpoint| utxo_set.output(outpoint))
+    }
+
+    /// Returns the fee of the transaction, in satoshis, looking up the outputs it spends with the
+    /// given function. Useful when they are not all in a UTXO set, like the outputs created
+    /// earlier in the same block.
+    ///
+    /// # Returns
+    ///
+    /// The fee, or `None` if the transaction is a coinbase.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::OutpointNotFound` if the function doesn't find an output spent by the
+    /// transaction, or a `NodeError::InvalidTransaction` if the outputs are worth more than the inputs.
+    pub fn fee_spending<F>(&self, spent_output: F) -> Result<Option<u64>, NodeError>
+    where
+        F: Fn(&Outpoint) -> Option<TxOutput>,
+    {
+        if self.is_coinbase() {
+            return Ok(None);
+        }
+
+        let spent_outputs = self
+            .tx_inputs
+            .iter()
+            .map(|tx_input| {
+                let outpoint = &tx_input.previous_output;
+                spent_output(outpoint).ok_or_else(|| {
+                    NodeError::OutpointNotFound(format!(
+                        "Output {}:{} spent by the transaction is not known",
+                        Utils::bytes_to_hex(&outpoint.tx_id),
+                        outpoint.index
+                    ))
+                })
+            })
+            .collect::<Result<Vec<TxOutput>, NodeError>>()?;
+        let input_value = sum_output_values(&spent_outputs)?;
+        let output_value = self.total_output_value()?;
+
+        input_value
+            .checked_sub(output_value)
+            .map(Some)
+            .ok_or_else(|| {
+                NodeError::InvalidTransaction(format!(
+                    "The outputs are worth {} satoshis but the inputs only {}",
+                    output_value, input_value
+                ))
+            })
+    }
+
     /// Checks if the Transaction contains a specific bitcoin address.
     pub fn contains_address(&self, address: &BitcoinAddress) -> bool {
         for tx_output in &self.tx_outputs {
@@ -242,13 +656,16 @@ impl Transaction {
     /// # Returns
     /// The amount of bitcoin received by the address.
     pub fn amount_received_by_address(&self, address: &BitcoinAddress) -> Amount {
-        let mut amount: f64 = 0.0;
-        for tx_output in &self.tx_outputs {
-            if tx_output.contains_address(address) {
-                amount += tx_output.value();
-            }
-        }
-        amount.to_string()
+        self.value_received_by_address(address).to_string()
+    }
+
+    /// Returns the sum of the values of the outputs paying to a specific address, in BTC.
+    pub fn value_received_by_address(&self, address: &BitcoinAddress) -> f64 {
+        self.tx_outputs
+            .iter()
+            .filter(|tx_output| tx_output.contains_address(address))
+            .map(|tx_output| tx_output.value())
+            .sum()
     }
 
     /// Gets the amount of bitcoin spent by a specific address.
@@ -257,18 +674,386 @@ impl Transaction {
     /// # Returns
     /// The amount of bitcoin spent by the account.
     pub fn amount_spent_by_account(&self, account: &mut Account) -> f64 {
-        let bitcoin_address = &account.bitcoin_address();
-        let mut utxo_set = account.utxo_set.clone();
-        let mut input = 0.0;
+        self.tx_inputs
+            .iter()
+            .filter_map(|tx_input| account.output(&tx_input.previous_output))
+            .map(|tx_output| tx_output.value())
+            .sum()
+    }
+
+    /// Formats the transaction in a readable multi-line layout, for the CLI and debugging.
+    ///
+    /// Each input shows the outpoint it spends as `txid:vout` and its sequence, and each output its
+    /// value in BTC and its script type, with the address it pays to when it has one. Hashes are
+    /// shown in display byte order, as block explorers do.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the id of the transaction or of a spent output isn't a valid hash.
+    pub fn pretty_print(&self) -> Result<String, NodeError> {
+        let mut lines = vec![
+            format!(
+                "Transaction {}",
+                InternalHash::from_slice(&self.tx_id())?.to_display()
+            ),
+            format!("  Version: {}", self.version),
+            format!("  Inputs ({}):", self.tx_inputs.len()),
+        ];
         for tx_input in &self.tx_inputs {
-            if let Some(tx_outputs) = utxo_set.tx_outputs(&tx_input.previous_output.tx_id) {
-                for tx in tx_outputs {
-                    if tx.contains_address(bitcoin_address) {
-                        input += tx.value();
-                    }
-                }
-            }
+            lines.push(format!(
+                "    {}:{} sequence {:#010x}",
+                InternalHash::from_slice(&tx_input.previous_output.tx_id)?.to_display(),
+                tx_input.previous_output.index,
+                tx_input.sequence
+            ));
+        }
+        lines.push(format!("  Outputs ({}):", self.tx_outputs.len()));
+        for (index, tx_output) in self.tx_outputs.iter().enumerate() {
+            lines.push(format!(
+                "    #{} {:.8} BTC {}",
+                index,
+                tx_output.value(),
+                tx_output.pk_script.label(Network::Testnet)
+            ));
         }
-        input
+        lines.push(format!("  Lock time: {}", self.lock_time));
+        Ok(lines.join("\n"))
+    }
+}
+
+/// Returns the sum of the values of the given outputs, in satoshis.
+///
+/// # Errors
+///
+/// Returns a `NodeError::InvalidTransaction` if an output has a negative value or the sum overflows.
+fn sum_output_values(tx_outputs: &[TxOutput]) -> Result<u64, NodeError> {
+    tx_outputs.iter().try_fold(0u64, |total, tx_output| {
+        u64::try_from(tx_output.value)
+            .ok()
+            .and_then(|value| total.checked_add(value))
+            .ok_or_else(|| {
+                NodeError::InvalidTransaction(format!(
+                    "Output value {} is out of range",
+                    tx_output.value
+                ))
+            })
+    })
+}
+
+/// Checks that a count read from a transaction can fit in its payload.
+///
+/// # Arguments
+///
+/// * `count` - The number of items declared.
+/// * `min_item_size` - The smallest size an item can have, in bytes.
+/// * `payload_size` - The size of the payload the items are read from, in bytes.
+/// * `items` - What the items are, for the error message.
+///
+/// # Errors
+///
+/// Returns a `NodeError::InvalidMessageFormat` if the items can't fit in the payload.
+fn check_count(
+    count: u64,
+    min_item_size: usize,
+    payload_size: usize,
+    items: &str,
+) -> Result<(), NodeError> {
+    if count.saturating_mul(min_item_size as u64) > payload_size as u64 {
+        return Err(NodeError::InvalidMessageFormat(format!(
+            "{} {} can't fit in a payload of {} bytes",
+            count, items, payload_size
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::constants::DEFAULT_CONFIRMATION_DEPTH;
+
+    use super::*;
+
+    fn output() -> TxOutput {
+        TxOutput::new(0.5, vec![0x76, 0xa9], 0)
+    }
+
+    #[test]
+    fn test_transaction_without_inputs_is_rejected() {
+        let transaction = Transaction::new_unsigned(vec![], vec![output()]);
+
+        assert!(matches!(
+            transaction.validate_structure(),
+            Err(NodeError::InvalidMessageFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_transactions_spending_the_same_outpoint_conflict() {
+        let shared_input = TxInput::new_unsigned(&vec![3; 32], &0, &[]);
+        let mut resigned_input = shared_input.clone();
+        resigned_input.signature_script = vec![1, 2, 3];
+        let first = Transaction::new_unsigned(
+            vec![TxInput::new_unsigned(&vec![4; 32], &0, &[]), shared_input],
+            vec![output()],
+        );
+        let double_spend = Transaction::new_unsigned(vec![resigned_input], vec![output()]);
+        let independent = Transaction::new_unsigned(
+            vec![TxInput::new_unsigned(&vec![3; 32], &1, &[])],
+            vec![output()],
+        );
+        let other_independent = Transaction::new_unsigned(
+            vec![TxInput::new_unsigned(&vec![5; 32], &0, &[])],
+            vec![output()],
+        );
+
+        assert!(first.conflicts_with(&double_spend));
+        assert!(double_spend.conflicts_with(&first));
+        assert!(!first.conflicts_with(&independent));
+        assert!(!independent.conflicts_with(&other_independent));
+    }
+
+    #[test]
+    fn test_transaction_spending_an_output_twice_is_rejected() -> Result<(), NodeError> {
+        let tx_input = TxInput::new_unsigned(&vec![3; 32], &0, &[]);
+        let other_input = TxInput::new_unsigned(&vec![3; 32], &1, &[]);
+        let valid = Transaction::new_unsigned(vec![tx_input.clone(), other_input], vec![output()]);
+        let duplicated =
+            Transaction::new_unsigned(vec![tx_input.clone(), tx_input], vec![output()]);
+
+        valid.validate_structure()?;
+        assert!(matches!(
+            duplicated.validate_structure(),
+            Err(NodeError::InvalidMessageFormat(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_segwit_transaction() -> Result<(), NodeError> {
+        // Native P2WPKH example from BIP143: the first input is legacy and the second one is SegWit.
+        let tx_bytes = Utils::hex_string_to_bytes(
+            "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000"
+                .to_string(),
+        )?;
+        let mut cursor = Cursor::new(&tx_bytes);
+
+        let transaction = Transaction::read_transaction(&mut cursor)?;
+
+        let tx_id = InternalHash::from_slice(&transaction.tx_id())?.to_display();
+        assert_eq!(
+            tx_id.to_string(),
+            "e8151a2af31c368a35053ddd4bdb285a8595c769a3ad83e0fa02314a602d4609"
+        );
+        assert_eq!(transaction.input_count(), 2);
+        assert_eq!(transaction.output_count(), 2);
+        assert_eq!(transaction.witnesses.len(), 2);
+        assert_eq!(transaction.witnesses[0].len(), 0);
+        assert_eq!(transaction.witnesses[1].len(), 2);
+        assert_eq!(transaction.lock_time, 17);
+        assert_eq!(cursor.position() as usize, tx_bytes.len());
+        assert_eq!(transaction.to_bytes(), tx_bytes);
+        assert!(transaction.to_bytes_no_witness().len() < tx_bytes.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_counts_that_cant_fit_in_the_payload_are_rejected() {
+        let mut tx_bytes = 1u32.to_le_bytes().to_vec();
+        tx_bytes.push(0xff);
+        tx_bytes.extend(u64::MAX.to_le_bytes());
+        let transaction = Transaction::new_unsigned(
+            vec![TxInput::new_unsigned(&vec![1; 32], &0, &[])],
+            vec![output(); 6],
+        )
+        .to_bytes();
+
+        assert!(matches!(
+            Transaction::read_transaction(&mut Cursor::new(&tx_bytes)),
+            Err(NodeError::InvalidMessageFormat(_))
+        ));
+        assert!(matches!(
+            Transaction::read_transaction_with_limit(&mut Cursor::new(&transaction), 50),
+            Err(NodeError::InvalidMessageFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_legacy_transaction_has_no_witnesses() -> Result<(), NodeError> {
+        let transaction =
+            Transaction::new_unsigned(vec![TxInput::new_unsigned(&vec![1; 32], &0, &[])], vec![]);
+        let tx_bytes = transaction.to_bytes();
+
+        let read_transaction = Transaction::read_transaction(&mut Cursor::new(&tx_bytes))?;
+
+        assert!(read_transaction.witnesses.is_empty());
+        assert_eq!(read_transaction.tx_id(), transaction.tx_id());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_totals_of_multi_output_transaction() -> Result<(), NodeError> {
+        let tx_inputs = vec![
+            TxInput::new_unsigned(&vec![1; 32], &0, &[]),
+            TxInput::new_unsigned(&vec![2; 32], &1, &[]),
+        ];
+        let tx_outputs = vec![
+            TxOutput::new(0.5, vec![0, 1, 2], 0),
+            TxOutput::new(0.25, vec![3, 4, 5], 1),
+            TxOutput::new(0.00001, vec![6, 7, 8], 2),
+        ];
+        let transaction = Transaction::new_unsigned(tx_inputs, tx_outputs);
+
+        assert_eq!(transaction.input_count(), 2);
+        assert_eq!(transaction.output_count(), 3);
+        assert_eq!(transaction.total_output_value()?, 75_001_000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_without_outputs_has_zero_value() -> Result<(), NodeError> {
+        let transaction =
+            Transaction::new_unsigned(vec![TxInput::new_unsigned(&vec![1; 32], &0, &[])], vec![]);
+
+        assert_eq!(transaction.output_count(), 0);
+        assert_eq!(transaction.total_output_value()?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_output_value_is_rejected_by_the_total() {
+        let mut tx_output = TxOutput::new(0.0, vec![0, 1, 2], 0);
+        tx_output.value = -1;
+        let transaction = Transaction::new_unsigned(
+            vec![TxInput::new_unsigned(&vec![1; 32], &0, &[])],
+            vec![tx_output],
+        );
+
+        assert!(matches!(
+            transaction.total_output_value(),
+            Err(NodeError::InvalidTransaction(_))
+        ));
+    }
+
+    #[test]
+    fn test_estimate_size_matches_signed_transaction() -> Result<(), NodeError> {
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.update(
+            &"blocks-test/0000000000000005847b65f037ec3d08f499c3c22ae6723ffefee1adca3e9af5.bin"
+                .to_string(),
+        )?;
+        let account = Account::new(
+            &Arc::new(Mutex::new(utxo_set)),
+            "mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC".to_string(),
+            "92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5".to_string(),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        let (mut transaction, pk_scripts) = account.create_unsigned_transaction(
+            &"mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB".to_string(),
+            0.01,
+            0.005,
+        )?;
+        account.sign_transaction(&mut transaction, pk_scripts)?;
+
+        let estimated_size =
+            Transaction::estimate_size(transaction.input_count(), transaction.output_count());
+        let actual_size = transaction.to_bytes().len();
+
+        assert!(estimated_size >= actual_size);
+        assert!(estimated_size - actual_size <= 2 * transaction.input_count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_derives_counts_and_validate_counts_catches_mismatch() -> Result<(), NodeError> {
+        let tx_inputs = vec![
+            TxInput::new_unsigned(&vec![1; 32], &0, &[]),
+            TxInput::new_unsigned(&vec![2; 32], &1, &[]),
+        ];
+        let tx_outputs = vec![TxOutput::new(0.1, vec![], 0)];
+
+        let transaction = Transaction::new(tx_inputs.clone(), tx_outputs.clone(), 2, 100);
+        let inconsistent = Transaction {
+            tx_in_count: CompactSize::new(1),
+            ..transaction.clone()
+        };
+
+        assert_eq!(transaction.tx_in_count, CompactSize::new(2));
+        assert_eq!(transaction.tx_out_count, CompactSize::new(1));
+        assert_eq!(transaction.version, 2);
+        assert_eq!(transaction.lock_time, 100);
+        transaction.validate_counts()?;
+        assert!(matches!(
+            inconsistent.validate_counts(),
+            Err(NodeError::InvalidTransaction(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_of_transaction_spending_known_outputs() -> Result<(), NodeError> {
+        let transaction = Transaction::new(
+            vec![
+                TxInput::new_unsigned(&vec![1; 32], &0, &[]),
+                TxInput::new_unsigned(&vec![2; 32], &1, &[]),
+            ],
+            vec![TxOutput::new(0.003, vec![], 0)],
+            1,
+            0,
+        );
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.insert(vec![1; 32], vec![TxOutput::new(0.002, vec![], 0)]);
+        utxo_set.insert(vec![2; 32], vec![TxOutput::new(0.0015, vec![], 1)]);
+
+        assert_eq!(transaction.fee(&utxo_set)?, Some(50000));
+        assert!(matches!(
+            transaction.fee(&UtxoSet::new()),
+            Err(NodeError::OutpointNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_of_coinbase_is_none() -> Result<(), NodeError> {
+        let mut block = std::fs::File::open(
+            "blocks-test/000000000000001035138c7d63a9f79a25afc119403e2384d8ad285bce01bf8b.bin",
+        )
+        .map_err(|_| NodeError::FailedToOpenFile("Failed to open block".to_string()))?;
+        receive_message(&mut block, 81)?;
+        let coinbase = Transaction::read_transaction(&mut block)?;
+
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase.fee(&UtxoSet::new())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pretty_print_shows_the_address_and_btc_value_of_outputs() -> Result<(), NodeError> {
+        let address =
+            BitcoinAddress::from_string(&"mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string())?;
+        let previous_tx_id: Vec<u8> = (0..32).collect();
+        let transaction = Transaction::new_unsigned(
+            vec![TxInput::new_unsigned(&previous_tx_id, &3, &[])],
+            vec![TxOutput::new(
+                0.125,
+                BitcoinAddress::to_pk_script(&address),
+                0,
+            )],
+        );
+
+        let pretty_print = transaction.pretty_print()?;
+
+        assert!(pretty_print
+            .contains("1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100:3"));
+        assert!(pretty_print.contains("#0 0.12500000 BTC P2PKH mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"));
+        assert!(pretty_print.contains("Lock time: 0"));
+        Ok(())
     }
 }
@@ -1,18 +1,110 @@
-use super::tx_output::TxOutput;
+use super::{
+    outpoint::Outpoint, transaction::Transaction, tx_output::TxOutput,
+    utxo_spill_store::UtxoSpillStore,
+};
 use crate::{
-    block::{retrieve_transactions_from_block, tx_hash::TxHash},
+    block::{
+        block_hash::BlockHash, read_block_timestamp, retrieve_transactions_from_block,
+        tx_hash::TxHash, validate_stored_block, validated_blocks::ValidatedBlocks,
+    },
     block_header::BlockHeader,
+    config::obtain_dir_path,
+    constants::{SATOSHI_CONVERSION_COEFFICIENT, UTXO_SPILL_FILE},
     messages::block_message::BlockMessage,
+    node::read::{retrieve_full_validation, retrieve_utxo_memory_limit},
     node_error::NodeError,
     wallet::{account::Account, bitcoin_address::BitcoinAddress},
 };
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map, HashMap, HashSet},
+    path::Path,
+    sync::{Arc, Mutex},
+};
 #[derive(Debug, Clone)]
 /// Represents the Unspent Transaction Outputs (UTXO) set.
 pub struct UtxoSet {
     /// The data structure that stores the UTXO set, transaction IDs are mapped to their associated transaction outputs as a key-value pair.
-    pub set: HashMap<TxHash, Vec<TxOutput>>,
+    /// When spilling is enabled, it only holds the entries kept in memory.
+    set: HashMap<TxHash, Vec<TxOutput>>,
+    /// The entries evicted to disk, if the memory used by the set is capped.
+    spill: Option<UtxoSpill>,
+    /// The transaction IDs of the entries, in memory or evicted to disk, with outputs paying to each
+    /// public key hash, so the outputs of an account are found without scanning the whole set.
+    by_pk_hash: HashMap<Vec<u8>, HashSet<TxHash>>,
+}
+
+/// The totals of a UTXO set, to check that it's sane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UtxoStats {
+    /// The number of unspent outputs.
+    pub count: usize,
+    /// The total value of the unspent outputs, in satoshis.
+    pub total_value: u64,
+}
+
+impl UtxoStats {
+    /// Adds the outputs of a transaction to the totals.
+    fn add(&mut self, tx_outputs: &[TxOutput]) {
+        self.count += tx_outputs.len();
+        self.total_value += tx_outputs
+            .iter()
+            .map(|tx_output| tx_output.value.max(0) as u64)
+            .sum::<u64>();
+    }
+
+    /// Returns the total value of the unspent outputs, in BTC.
+    pub fn total_value_btc(&self) -> f64 {
+        self.total_value as f64 / SATOSHI_CONVERSION_COEFFICIENT
+    }
+}
+
+/// The outpoints added and removed between two UTXO sets, sorted, to find where they diverge.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UtxoDiff {
+    /// The outpoints of the other set that are not in this one.
+    pub added: Vec<Outpoint>,
+    /// The outpoints of this set that are not in the other one.
+    pub removed: Vec<Outpoint>,
+}
+
+impl UtxoDiff {
+    /// Checks if both sets have the same outpoints.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The state needed to keep a UTXO set under its memory limit.
+#[derive(Debug, Clone)]
+struct UtxoSpill {
+    /// The maximum number of transactions whose outputs are kept in memory.
+    memory_limit: usize,
+    /// The store of the evicted entries, shared by the clones of the set.
+    store: Arc<Mutex<UtxoSpillStore>>,
+    /// The last time each entry in memory was used, to evict the least recently used first.
+    last_used: HashMap<TxHash, u64>,
+    /// Incremented every time an entry is used.
+    clock: u64,
+    /// The public key hashes whose outputs are never evicted, the ones of the wallet accounts.
+    kept_pk_hashes: HashSet<Vec<u8>>,
 }
+
+impl UtxoSpill {
+    /// Marks an entry as used now.
+    fn touch(&mut self, tx_id: &TxHash) {
+        self.clock += 1;
+        self.last_used.insert(tx_id.clone(), self.clock);
+    }
+
+    /// Checks if any of the outputs pays to a public key hash that must be kept in memory.
+    fn is_kept(&self, tx_outputs: &[TxOutput]) -> bool {
+        tx_outputs.iter().any(|tx_output| {
+            Account::pk_script_to_pk_hash(&tx_output.pk_script)
+                .is_ok_and(|pk_hash| self.kept_pk_hashes.contains(&pk_hash))
+        })
+    }
+}
+
 impl UtxoSet {
     /// Updates the UTXO set by processing the transactions in a block.
     ///
@@ -33,20 +125,7 @@ impl UtxoSet {
             let tx_outputs = transaction.tx_outputs();
             let tx_id = transaction.tx_id();
             for tx_input in transaction.tx_inputs() {
-                let outpoint = &tx_input.previous_output;
-                if self.contains_key(&outpoint.tx_id) {
-                    if let Some(tx_outputs) = self.tx_outputs(&outpoint.tx_id) {
-                        for i in 0..tx_outputs.len() {
-                            if tx_outputs[i].index == outpoint.index as u64 {
-                                tx_outputs.remove(i);
-                                if tx_outputs.is_empty() {
-                                    self.remove(&outpoint.tx_id);
-                                }
-                                break;
-                            }
-                        }
-                    }
-                }
+                self.spend_output(&tx_input.previous_output);
             }
             self.insert(tx_id, tx_outputs.clone());
         }
@@ -68,6 +147,7 @@ impl UtxoSet {
     ) {
         for tx_output in tx_outputs {
             if tx_output.contains_address(address) {
+                self.index_outputs(&tx_id, std::slice::from_ref(&tx_output));
                 let outputs = self.set.entry(tx_id.clone()).or_insert_with(Vec::new);
                 outputs.push(tx_output);
             }
@@ -97,30 +177,34 @@ impl UtxoSet {
             address.bs58_to_string()
         );
         let transactions = retrieve_transactions_from_block(block_path)?;
+        self.update_for_account_with(block_path, transactions, address);
+        Ok(())
+    }
+
+    /// Updates the UTXO set of an account with the given transactions of a block, such as the ones
+    /// proven by a merkleblock when the block itself is not saved.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_path` - The path of the block the transactions belong to, recorded in their outputs.
+    /// * `transactions` - The transactions of the block, in order.
+    /// * `address` - The Bitcoin address associated with the UTXO set to be updated.
+    pub fn update_for_account_with(
+        &mut self,
+        block_path: &str,
+        transactions: Vec<Transaction>,
+        address: &BitcoinAddress,
+    ) {
         for mut transaction in transactions {
             transaction.add_block_path_to_tx_outs(block_path);
 
             let tx_outputs = transaction.tx_outputs();
             let tx_id = transaction.tx_id();
             for tx_input in transaction.tx_inputs() {
-                let outpoint = &tx_input.previous_output;
-                if self.contains_key(&outpoint.tx_id) {
-                    if let Some(tx_outputs) = self.tx_outputs(&outpoint.tx_id) {
-                        for i in 0..tx_outputs.len() {
-                            if tx_outputs[i].index == outpoint.index as u64 {
-                                tx_outputs.remove(i);
-                                if tx_outputs.is_empty() {
-                                    self.remove(&outpoint.tx_id);
-                                }
-                                break;
-                            }
-                        }
-                    }
-                }
+                self.spend_output(&tx_input.previous_output);
             }
             self.insert_for_account(tx_id, tx_outputs.clone(), address);
         }
-        Ok(())
     }
     /// Creates the UTXO (Unspent Transaction Outputs) set from a list of block headers.
     ///
@@ -131,13 +215,40 @@ impl UtxoSet {
     /// # Returns
     ///
     /// Returns a Result containing the UTXO set as a HashMap with transaction IDs as keys and associated transaction outputs as values if successful, or an error of type `NodeError` if there was a problem updating the UTXO set.
+    /// Blocks that are not in the validated blocks file are validated before being applied.
     pub fn new_from_block_headers(block_headers: Vec<BlockHeader>) -> Result<UtxoSet, NodeError> {
+        Self::new_from_block_headers_inspecting(block_headers, |_, _, _| {})
+    }
+
+    /// Creates the UTXO set from a list of block headers like `new_from_block_headers`, letting the
+    /// caller inspect each block saved on disk before it is applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_headers` - A vector of BlockHeader representing the block headers from which to retrieve the UTXO set.
+    /// * `inspect` - A function that receives the position of the block in the headers, the path of
+    ///   the block file and the UTXO set before the block is applied.
+    ///
+    /// # Returns
+    ///
+    /// Returns a Result containing the UTXO set, or an error of type `NodeError` if there was a problem updating it.
+    pub fn new_from_block_headers_inspecting<F>(
+        block_headers: Vec<BlockHeader>,
+        mut inspect: F,
+    ) -> Result<UtxoSet, NodeError>
+    where
+        F: FnMut(usize, &String, &UtxoSet),
+    {
         let mut utxo_set = UtxoSet::new();
-        for block_header in block_headers.iter() {
-            let block_hash = block_header.hash().as_slice().try_into().map_err(|_| {
-                NodeError::FailedToParse("Failed to convert block hash to array".to_string())
-            })?;
-            let block_path = match BlockMessage::block_path(block_hash) {
+        if let Some(memory_limit) = retrieve_utxo_memory_limit() {
+            utxo_set
+                .enable_spilling(&obtain_dir_path(UTXO_SPILL_FILE.to_owned())?, memory_limit)?;
+        }
+        let mut validated_blocks = ValidatedBlocks::open()?;
+        let full_validation = retrieve_full_validation();
+        for (position, block_header) in block_headers.iter().enumerate() {
+            let block_hash = block_header.block_hash();
+            let block_path = match BlockMessage::block_path_for_header(block_header) {
                 Some(block_path) => block_path,
                 None => {
                     return Err(NodeError::FailedToRead(
@@ -145,36 +256,433 @@ impl UtxoSet {
                     ))
                 }
             };
-            match utxo_set.update(&block_path) {
+            if Path::new(&block_path).exists() {
+                inspect(position, &block_path, &utxo_set);
+            }
+            match utxo_set.update_from_stored_block(
+                &block_path,
+                &block_hash,
+                &mut validated_blocks,
+                |block_path, utxo_set| validate_stored_block(block_path, utxo_set, full_validation),
+            ) {
                 Ok(_) => (),
-                Err(_) => {
+                Err(NodeError::FailedToOpenFile(_)) | Err(NodeError::FailedToRead(_)) => {
                     println!("UTXO set was not updated because block isn't downloaded");
                 }
+                Err(e) => {
+                    println!("UTXO set was not updated because block is invalid: {:?}", e);
+                }
             }
         }
         Ok(utxo_set)
     }
+
+    /// Updates the UTXO set with a block saved on disk, validating it first if it wasn't validated before.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_path` - The path to the block file.
+    /// * `block_hash` - The hash of the block.
+    /// * `validated_blocks` - The blocks that were already validated, the block is added to them if it passes the validation.
+    /// * `validate` - The validation of the stored block, only called for blocks that weren't validated before. It
+    ///   receives the UTXO set before the block is applied.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the UTXO set is updated, or a `NodeError` if the block is invalid or could not be read.
+    pub fn update_from_stored_block<F>(
+        &mut self,
+        block_path: &String,
+        block_hash: &BlockHash,
+        validated_blocks: &mut ValidatedBlocks,
+        validate: F,
+    ) -> Result<(), NodeError>
+    where
+        F: FnOnce(&String, &UtxoSet) -> Result<(), NodeError>,
+    {
+        if !validated_blocks.contains(block_hash) {
+            validate(block_path, self)?;
+            validated_blocks.mark(block_hash)?;
+        }
+        self.update(block_path)
+    }
     /// Creates an empty UTXO set
     pub fn new() -> UtxoSet {
         UtxoSet {
             set: HashMap::new(),
+            spill: None,
+            by_pk_hash: HashMap::new(),
+        }
+    }
+
+    /// Caps the number of transactions whose outputs are kept in memory.
+    ///
+    /// When the cap is exceeded, the least recently used entries are evicted to a file-backed store,
+    /// and looking them up loads them back. Entries paying to the public key hashes passed to
+    /// `keep_in_memory` are never evicted.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file the evicted entries are written to. It's truncated.
+    /// * `memory_limit` - The maximum number of transactions kept in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the file could not be created or the entries could not be written to it.
+    pub fn enable_spilling(&mut self, path: &str, memory_limit: usize) -> Result<(), NodeError> {
+        let mut spill = UtxoSpill {
+            memory_limit: memory_limit.max(1),
+            store: Arc::new(Mutex::new(UtxoSpillStore::create(path)?)),
+            last_used: HashMap::new(),
+            clock: 0,
+            kept_pk_hashes: HashSet::new(),
+        };
+        for tx_id in self.set.keys() {
+            spill.touch(tx_id);
+        }
+        self.spill = Some(spill);
+        self.evict_if_needed()
+    }
+
+    /// Keeps the outputs paying to a public key hash in memory, even if the set is over its memory limit.
+    /// The ones already evicted to disk are loaded back, so reading them never touches the disk again.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the evicted entries could not be read.
+    pub fn keep_in_memory(&mut self, pk_hash: Vec<u8>) -> Result<(), NodeError> {
+        let spill = match self.spill.as_mut() {
+            Some(spill) => spill,
+            None => return Ok(()),
+        };
+        if !spill.kept_pk_hashes.insert(pk_hash.clone()) {
+            return Ok(());
+        }
+        let kept_tx_ids: Vec<TxHash> = self
+            .by_pk_hash
+            .get(&pk_hash)
+            .map(|tx_ids| {
+                tx_ids
+                    .iter()
+                    .filter(|tx_id| !self.set.contains_key(*tx_id))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        for tx_id in kept_tx_ids {
+            self.load_spilled(&tx_id)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of transactions whose outputs were evicted to disk.
+    pub fn spilled_count(&self) -> usize {
+        match &self.spill {
+            Some(spill) => spill.store.lock().map_or(0, |store| store.len()),
+            None => 0,
+        }
+    }
+
+    /// Evicts the least recently used entries to disk until the set is under its memory limit.
+    ///
+    /// Entries are evicted until the set is 10% under the limit, so they are not evicted one by one.
+    fn evict_if_needed(&mut self) -> Result<(), NodeError> {
+        let spill = match self.spill.as_mut() {
+            Some(spill) if self.set.len() > spill.memory_limit => spill,
+            _ => return Ok(()),
+        };
+        let target_len = spill.memory_limit - spill.memory_limit / 10;
+
+        let mut candidates: Vec<(u64, TxHash)> = self
+            .set
+            .iter()
+            .filter(|(_, tx_outputs)| !spill.is_kept(tx_outputs))
+            .map(|(tx_id, _)| {
+                let last_used = spill.last_used.get(tx_id).copied().unwrap_or(0);
+                (last_used, tx_id.clone())
+            })
+            .collect();
+        candidates.sort();
+
+        let mut store = spill
+            .store
+            .lock()
+            .map_err(|_| NodeError::MutexError("Failed to lock UTXO spill store".to_string()))?;
+        for (_, tx_id) in candidates {
+            if self.set.len() <= target_len {
+                break;
+            }
+            if let Some(tx_outputs) = self.set.remove(&tx_id) {
+                store.write(tx_id.clone(), &tx_outputs)?;
+                spill.last_used.remove(&tx_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the outputs of a transaction evicted to disk back into memory.
+    fn load_spilled(&mut self, tx_id: &TxHash) -> Result<(), NodeError> {
+        let spill = match self.spill.as_mut() {
+            Some(spill) => spill,
+            None => return Ok(()),
+        };
+        let mut store = spill
+            .store
+            .lock()
+            .map_err(|_| NodeError::MutexError("Failed to lock UTXO spill store".to_string()))?;
+        if let Some(tx_outputs) = store.read(tx_id)? {
+            store.remove(tx_id);
+            self.set.insert(tx_id.clone(), tx_outputs);
+        }
+        Ok(())
+    }
+
+    /// Adds the transaction ID to the index of the public key hashes its outputs pay to.
+    fn index_outputs(&mut self, tx_id: &TxHash, tx_outputs: &[TxOutput]) {
+        for tx_output in tx_outputs {
+            if let Ok(pk_hash) = Account::pk_script_to_pk_hash(&tx_output.pk_script) {
+                self.by_pk_hash
+                    .entry(pk_hash)
+                    .or_default()
+                    .insert(tx_id.clone());
+            }
+        }
+    }
+
+    /// Removes the transaction ID from the index of the public key hashes the removed outputs pay
+    /// to, unless one of the remaining outputs of the transaction still pays to them.
+    fn unindex_outputs(&mut self, tx_id: &TxHash, removed: &[TxOutput], remaining: &[TxOutput]) {
+        for tx_output in removed {
+            if let Ok(pk_hash) = Account::pk_script_to_pk_hash(&tx_output.pk_script) {
+                if !pays_to(remaining, &pk_hash) {
+                    self.unindex(tx_id, &pk_hash);
+                }
+            }
+        }
+    }
+
+    /// Removes the transaction ID from the index of a public key hash.
+    fn unindex(&mut self, tx_id: &TxHash, pk_hash: &[u8]) {
+        if let Some(tx_ids) = self.by_pk_hash.get_mut(pk_hash) {
+            tx_ids.remove(tx_id);
+            if tx_ids.is_empty() {
+                self.by_pk_hash.remove(pk_hash);
+            }
+        }
+    }
+
+    /// Calls the given function with every unspent output paying to a public key hash, in memory or
+    /// evicted to disk, found through the index instead of scanning the set.
+    fn for_each_output_of<F>(&self, pk_hash: &[u8], mut f: F)
+    where
+        F: FnMut(&TxHash, &TxOutput),
+    {
+        let tx_ids = match self.by_pk_hash.get(pk_hash) {
+            Some(tx_ids) => tx_ids,
+            None => return,
+        };
+        let store = self
+            .spill
+            .as_ref()
+            .and_then(|spill| spill.store.lock().ok());
+        for tx_id in tx_ids {
+            let spilled;
+            let tx_outputs = match self.set.get(tx_id) {
+                Some(tx_outputs) => tx_outputs,
+                None => match store.as_ref().and_then(|store| store.read(tx_id).ok()?) {
+                    Some(tx_outputs) => {
+                        spilled = tx_outputs;
+                        &spilled
+                    }
+                    None => continue,
+                },
+            };
+            for tx_output in tx_outputs {
+                if pays_to(std::slice::from_ref(tx_output), pk_hash) {
+                    f(tx_id, tx_output);
+                }
+            }
+        }
+    }
+
+    /// Returns the outputs of every transaction evicted to disk.
+    fn spilled_entries(&self) -> Vec<(TxHash, Vec<TxOutput>)> {
+        let store = match &self.spill {
+            Some(spill) => match spill.store.lock() {
+                Ok(store) => store,
+                Err(_) => return Vec::new(),
+            },
+            None => return Vec::new(),
+        };
+        store
+            .tx_ids()
+            .into_iter()
+            .filter_map(|tx_id| match store.read(&tx_id) {
+                Ok(Some(tx_outputs)) => Some((tx_id, tx_outputs)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the number of unspent outputs of the set and the total value they hold, computed in
+    /// one pass over the entries in memory and the ones evicted to disk.
+    pub fn stats(&self) -> UtxoStats {
+        let mut stats = UtxoStats::default();
+        for tx_outputs in self.set.values() {
+            stats.add(tx_outputs);
+        }
+        if let Some(Ok(store)) = self.spill.as_ref().map(|spill| spill.store.lock()) {
+            for tx_id in store.tx_ids() {
+                if let Ok(Some(tx_outputs)) = store.read(&tx_id) {
+                    stats.add(&tx_outputs);
+                }
+            }
+        }
+        stats
+    }
+
+    /// Compares the outpoints of the set with the ones of another set, such as the set after applying
+    /// a block or a known-good snapshot. The entries evicted to disk are compared too.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The set to compare with.
+    ///
+    /// # Returns
+    ///
+    /// The outpoints only the other set has as added, and the ones only this set has as removed.
+    pub fn diff(&self, other: &UtxoSet) -> UtxoDiff {
+        let outpoints = self.outpoints();
+        let other_outpoints = other.outpoints();
+        let mut added: Vec<Outpoint> = other_outpoints.difference(&outpoints).cloned().collect();
+        let mut removed: Vec<Outpoint> = outpoints.difference(&other_outpoints).cloned().collect();
+        let sort_key = |outpoint: &Outpoint| (outpoint.tx_id.clone(), outpoint.index);
+        added.sort_by_key(sort_key);
+        removed.sort_by_key(sort_key);
+        UtxoDiff { added, removed }
+    }
+
+    /// Returns the outpoints of every unspent output of the set, in memory or evicted to disk.
+    fn outpoints(&self) -> HashSet<Outpoint> {
+        let mut outpoints = HashSet::new();
+        let spilled = self.spilled_entries();
+        let entries = self.set.iter().chain(
+            spilled
+                .iter()
+                .map(|(tx_id, tx_outputs)| (tx_id, tx_outputs)),
+        );
+        for (tx_id, tx_outputs) in entries {
+            for tx_output in tx_outputs {
+                outpoints.insert(Outpoint {
+                    tx_id: tx_id.clone(),
+                    index: tx_output.index as u32,
+                });
+            }
         }
+        outpoints
     }
+
+    /// Iterates over the transaction IDs and outputs kept in memory. Entries evicted to disk are not included.
+    pub fn iter(&self) -> hash_map::Iter<'_, TxHash, Vec<TxOutput>> {
+        self.set.iter()
+    }
+
     /// Checks if the UTXO set contains a transaction ID.
     pub fn contains_key(&self, tx_id: &TxHash) -> bool {
-        self.set.contains_key(tx_id)
+        if self.set.contains_key(tx_id) {
+            return true;
+        }
+        match &self.spill {
+            Some(spill) => spill.store.lock().is_ok_and(|store| store.contains(tx_id)),
+            None => false,
+        }
+    }
+    /// Gets the unspent output an outpoint refers to, reading it from disk without loading it back
+    /// into memory if it was evicted.
+    pub fn output(&self, outpoint: &Outpoint) -> Option<TxOutput> {
+        let find_output = |tx_outputs: &Vec<TxOutput>| {
+            tx_outputs
+                .iter()
+                .find(|tx_output| tx_output.index == outpoint.index as u64)
+                .cloned()
+        };
+        if let Some(tx_outputs) = self.set.get(&outpoint.tx_id) {
+            return find_output(tx_outputs);
+        }
+        let store = self.spill.as_ref()?.store.lock().ok()?;
+        find_output(&store.read(&outpoint.tx_id).ok()??)
     }
     /// Gets the transaction outputs associated with a transaction ID.
+    /// If they were evicted to disk, they are loaded back into memory.
     pub fn tx_outputs(&mut self, tx_id: &TxHash) -> Option<&mut Vec<TxOutput>> {
+        if !self.set.contains_key(tx_id) {
+            if let Err(e) = self.load_spilled(tx_id) {
+                println!("Failed to load UTXOs from disk: {:?}", e);
+            }
+        }
+        if let Some(spill) = self.spill.as_mut() {
+            spill.touch(tx_id);
+        }
         self.set.get_mut(tx_id)
     }
     /// Inserts a transaction ID and associated transaction outputs into the UTXO set.
     pub fn insert(&mut self, tx_id: TxHash, tx_outputs: Vec<TxOutput>) {
+        self.index_outputs(&tx_id, &tx_outputs);
+        if let Some(spill) = self.spill.as_mut() {
+            spill.touch(&tx_id);
+            if let Ok(mut store) = spill.store.lock() {
+                store.remove(&tx_id);
+            }
+        }
         self.set.insert(tx_id, tx_outputs);
+        if let Err(e) = self.evict_if_needed() {
+            println!("Failed to evict UTXOs to disk: {:?}", e);
+        }
     }
     /// Removes a transaction ID and associated transaction outputs from the UTXO set.
     pub fn remove(&mut self, tx_id: &TxHash) {
-        self.set.remove(tx_id);
+        let mut removed = self.set.remove(tx_id);
+        if let Some(spill) = self.spill.as_mut() {
+            spill.last_used.remove(tx_id);
+            if let Ok(mut store) = spill.store.lock() {
+                if removed.is_none() {
+                    removed = store.read(tx_id).ok().flatten();
+                }
+                store.remove(tx_id);
+            }
+        }
+        if let Some(tx_outputs) = removed {
+            self.unindex_outputs(tx_id, &tx_outputs, &[]);
+        }
+    }
+
+    /// Removes the unspent output an outpoint refers to, if it's in the set, and the entry of its
+    /// transaction once all of its outputs are spent.
+    fn spend_output(&mut self, outpoint: &Outpoint) {
+        if !self.contains_key(&outpoint.tx_id) {
+            return;
+        }
+        let tx_outputs = match self.tx_outputs(&outpoint.tx_id) {
+            Some(tx_outputs) => tx_outputs,
+            None => return,
+        };
+        let spent = match tx_outputs
+            .iter()
+            .position(|tx_output| tx_output.index == outpoint.index as u64)
+        {
+            Some(position) => tx_outputs.remove(position),
+            None => return,
+        };
+        let spent_pk_hash = Account::pk_script_to_pk_hash(&spent.pk_script)
+            .ok()
+            .filter(|pk_hash| !pays_to(tx_outputs, pk_hash));
+        let is_spent = tx_outputs.is_empty();
+        if let Some(pk_hash) = spent_pk_hash {
+            self.unindex(&outpoint.tx_id, &pk_hash);
+        }
+        if is_spent {
+            self.remove(&outpoint.tx_id);
+        }
     }
     /// Gets the UTXO set for a given Bitcoin address.
     ///
@@ -185,26 +693,78 @@ impl UtxoSet {
     /// # Returns
     ///
     /// Returns a UtxoSet containing the UTXOs for the given Bitcoin address.
-    pub fn users_utxo_set(&self, users_pk_hash: &Vec<u8>) -> UtxoSet {
+    pub fn users_utxo_set(&self, users_pk_hash: &[u8]) -> UtxoSet {
+        let mut users_outputs: HashMap<TxHash, Vec<TxOutput>> = HashMap::new();
+        self.for_each_output_of(users_pk_hash, |tx_id, tx_output| {
+            users_outputs
+                .entry(tx_id.clone())
+                .or_default()
+                .push(tx_output.clone());
+        });
         let mut users_utxo_set = UtxoSet::new();
-        println!("Creating the users UTXO set...");
-        for tx_tuple in self.set.iter() {
-            let tx_outputs = tx_tuple.1;
-            let mut users_tx_outputs = Vec::new();
-            for tx_output in tx_outputs {
-                let tx_output_pk_hash = match Account::pk_script_to_pk_hash(&tx_output.pk_script) {
-                    Ok(pk_hash) => pk_hash,
-                    Err(_) => continue,
-                };
-                if &tx_output_pk_hash == users_pk_hash {
-                    users_tx_outputs.push(tx_output.clone());
+        for (tx_id, tx_outputs) in users_outputs {
+            users_utxo_set.insert(tx_id, tx_outputs);
+        }
+        users_utxo_set
+    }
+
+    /// Returns the balance of a public key hash, reading its outputs in place through the index
+    /// instead of scanning the set or cloning the outputs of the user into a new set.
+    ///
+    /// # Arguments
+    ///
+    /// * `users_pk_hash` - The public key hash of the user.
+    /// * `birthday` - The timestamp of the oldest block whose outputs are counted.
+    ///
+    /// # Returns
+    ///
+    /// The sum of the values of the outputs of the user, in BTC.
+    pub fn balance_for_pk_hash(&self, users_pk_hash: &[u8], birthday: u32) -> f64 {
+        let mut block_timestamps: HashMap<String, Option<u32>> = HashMap::new();
+        let mut balance = 0.0;
+        self.for_each_output_of(users_pk_hash, |_, tx_output| {
+            if birthday > 0 {
+                let block_timestamp = *block_timestamps
+                    .entry(tx_output.block_path.clone())
+                    .or_insert_with(|| known_block_timestamp(&tx_output.block_path));
+                if matches!(block_timestamp, Some(block_timestamp) if block_timestamp < birthday) {
+                    return;
                 }
             }
-            if !users_tx_outputs.is_empty() {
-                users_utxo_set.insert(tx_tuple.0.clone(), users_tx_outputs);
+            balance += tx_output.value();
+        });
+        balance
+    }
+
+    /// Removes the outputs that come from blocks older than the given timestamp.
+    /// Outputs whose block timestamp can't be read are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The timestamp of the oldest block whose outputs are kept.
+    pub fn remove_outputs_before(&mut self, timestamp: u32) {
+        let mut block_timestamps: HashMap<String, Option<u32>> = HashMap::new();
+        let mut removed_outputs = Vec::new();
+        for (tx_id, tx_outputs) in self.set.iter_mut() {
+            let (kept, removed): (Vec<TxOutput>, Vec<TxOutput>) =
+                tx_outputs.drain(..).partition(|tx_output| {
+                    let block_timestamp = *block_timestamps
+                        .entry(tx_output.block_path.clone())
+                        .or_insert_with(|| known_block_timestamp(&tx_output.block_path));
+                    !matches!(block_timestamp, Some(block_timestamp) if block_timestamp < timestamp)
+                });
+            *tx_outputs = kept;
+            if !removed.is_empty() {
+                removed_outputs.push((tx_id.clone(), removed));
+            }
+        }
+        for (tx_id, removed) in removed_outputs {
+            let remaining = self.set.get(&tx_id).cloned().unwrap_or_default();
+            self.unindex_outputs(&tx_id, &removed, &remaining);
+            if remaining.is_empty() {
+                self.remove(&tx_id);
             }
         }
-        users_utxo_set
     }
 
     /// Gets the sum of the UTXOs that can be spent.
@@ -237,6 +797,27 @@ impl UtxoSet {
         ))
     }
 }
+/// Reads the timestamp of the block in the given path, logging the error if it can't be read.
+/// Returns whether any of the outputs pays to the public key hash.
+fn pays_to(tx_outputs: &[TxOutput], pk_hash: &[u8]) -> bool {
+    tx_outputs.iter().any(|tx_output| {
+        Account::pk_script_to_pk_hash(&tx_output.pk_script).is_ok_and(|hash| hash == pk_hash)
+    })
+}
+
+fn known_block_timestamp(block_path: &String) -> Option<u32> {
+    match read_block_timestamp(block_path) {
+        Ok(timestamp) => Some(timestamp),
+        Err(e) => {
+            println!(
+                "Failed to read the timestamp of block {:?}: {:?}",
+                block_path, e
+            );
+            None
+        }
+    }
+}
+
 impl Default for UtxoSet {
     /// Creates an empty UTXO set.
     fn default() -> Self {
@@ -245,6 +826,11 @@ impl Default for UtxoSet {
 }
 #[cfg(test)]
 mod tests {
+    use crate::{
+        compact_size::CompactSize,
+        constants::{OP_CHECKSIG, OP_DUP, OP_EQUALVERIFY, OP_HASH160},
+    };
+
     use super::*;
     #[test]
     fn test_utxo_set_de_bloque_existente_contiene_txid() {
@@ -287,4 +873,274 @@ mod tests {
 
         assert!(tx_outputs.len() == 1);
     }
+
+    /// Validates a stored block, counting how many times the validation runs.
+    fn counting_validation(
+        calls: &mut usize,
+    ) -> impl FnOnce(&String, &UtxoSet) -> Result<(), NodeError> + '_ {
+        move |block_path, utxo_set| {
+            *calls += 1;
+            validate_stored_block(block_path, utxo_set, false)
+        }
+    }
+
+    #[test]
+    fn test_validated_block_skips_proof_of_work() -> Result<(), NodeError> {
+        let validated_path = "blocks-test/test_skip_validated_blocks.txt";
+        let _ = std::fs::remove_file(validated_path);
+        let block_path = String::from(
+            "blocks-test/0000000000000027898516270708e0c8db276e6f8302b05c8c8c208bab36ea59.bin",
+        );
+        let block_hash = [7; 32];
+        let mut validated_blocks = ValidatedBlocks::open_from_path(validated_path)?;
+        validated_blocks.mark(&block_hash)?;
+        let mut calls = 0;
+
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.update_from_stored_block(
+            &block_path,
+            &block_hash,
+            &mut validated_blocks,
+            counting_validation(&mut calls),
+        )?;
+        let _ = std::fs::remove_file(validated_path);
+
+        assert_eq!(calls, 0);
+        assert!(!utxo_set.set.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_block_is_validated_and_marked() -> Result<(), NodeError> {
+        let validated_path = "blocks-test/test_mark_validated_blocks.txt";
+        let _ = std::fs::remove_file(validated_path);
+        let block_path = String::from(
+            "blocks-test/0000000000000027898516270708e0c8db276e6f8302b05c8c8c208bab36ea59.bin",
+        );
+        let block_hash = [8; 32];
+        let mut validated_blocks = ValidatedBlocks::open_from_path(validated_path)?;
+        let mut calls = 0;
+
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.update_from_stored_block(
+            &block_path,
+            &block_hash,
+            &mut validated_blocks,
+            counting_validation(&mut calls),
+        )?;
+        let reloaded = ValidatedBlocks::open_from_path(validated_path)?;
+        let _ = std::fs::remove_file(validated_path);
+
+        assert_eq!(calls, 1);
+        assert!(reloaded.contains(&block_hash));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exceeding_memory_limit_spills_entries_that_are_still_found() -> Result<(), NodeError> {
+        let spill_path = "blocks-test/test_utxo_set_spill.bin";
+        let block_path = String::from(
+            "blocks-test/0000000000000027898516270708e0c8db276e6f8302b05c8c8c208bab36ea59.bin",
+        );
+        let transactions = retrieve_transactions_from_block(&block_path)?;
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.enable_spilling(spill_path, 5)?;
+
+        utxo_set.update(&block_path)?;
+        let in_memory = utxo_set.set.len();
+        let spilled = utxo_set.spilled_count();
+        let all_found = transactions
+            .iter()
+            .all(|transaction| utxo_set.contains_key(&transaction.tx_id()));
+        let outputs_count = utxo_set
+            .tx_outputs(&transactions[12].tx_id())
+            .map(|tx_outputs| tx_outputs.len());
+        let _ = std::fs::remove_file(spill_path);
+
+        assert!(in_memory <= 5);
+        assert_eq!(in_memory + spilled, transactions.len());
+        assert!(all_found);
+        assert_eq!(outputs_count, Some(2));
+        assert!(utxo_set.set.contains_key(&transactions[12].tx_id()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_after_applying_a_block_has_its_outputs_and_spent_outpoints(
+    ) -> Result<(), NodeError> {
+        let mut before = UtxoSet::new();
+        before.update(&String::from(
+            "blocks-test/0000000000000027898516270708e0c8db276e6f8302b05c8c8c208bab36ea59.bin",
+        ))?;
+        let block_path = String::from(
+            "blocks-test/00000000000000100415543e85ed470b4c381f6adc97850c0124f367a45b4bfe.bin",
+        );
+        let mut after = before.clone();
+        after.update(&block_path)?;
+
+        let transactions = retrieve_transactions_from_block(&block_path)?;
+        let spent: HashSet<Outpoint> = transactions
+            .iter()
+            .flat_map(|transaction| transaction.tx_inputs.iter())
+            .map(|tx_input| tx_input.previous_output.clone())
+            .collect();
+        let created: HashSet<Outpoint> = transactions
+            .iter()
+            .flat_map(|transaction| {
+                (0..transaction.tx_outputs.len()).map(|index| Outpoint {
+                    tx_id: transaction.tx_id(),
+                    index: index as u32,
+                })
+            })
+            .collect();
+        let diff = before.diff(&after);
+
+        let added: HashSet<Outpoint> = diff.added.iter().cloned().collect();
+        let removed: HashSet<Outpoint> = diff.removed.iter().cloned().collect();
+        assert_eq!(added, created.difference(&spent).cloned().collect());
+        assert_eq!(
+            removed,
+            spent
+                .into_iter()
+                .filter(|outpoint| before.output(outpoint).is_some())
+                .collect()
+        );
+        assert!(!diff.removed.is_empty());
+        assert!(after.diff(&after).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_count_the_outputs_and_their_total_value() -> Result<(), NodeError> {
+        let spill_path = "blocks-test/test_utxo_set_stats_spill.bin";
+        let tx_output = |value: i64, index: u64| TxOutput {
+            value,
+            pk_script_bytes: CompactSize::new(0),
+            pk_script: Vec::new(),
+            tx_id: Vec::new(),
+            index,
+            block_path: String::new(),
+        };
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.enable_spilling(spill_path, 1)?;
+
+        utxo_set.insert(
+            vec![1; 32],
+            vec![tx_output(50_000, 0), tx_output(25_000, 1)],
+        );
+        utxo_set.insert(vec![2; 32], vec![tx_output(100_000_000, 0)]);
+        utxo_set.insert(vec![3; 32], vec![tx_output(1, 3)]);
+        let spilled = utxo_set.spilled_count();
+        let stats = utxo_set.stats();
+        let _ = std::fs::remove_file(spill_path);
+
+        assert!(spilled > 0);
+        assert_eq!(
+            stats,
+            UtxoStats {
+                count: 4,
+                total_value: 100_075_001,
+            }
+        );
+        assert_eq!(stats.total_value_btc(), 1.00075001);
+        assert_eq!(UtxoSet::new().stats(), UtxoStats::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kept_outputs_are_loaded_back_and_read_without_the_disk() -> Result<(), NodeError> {
+        let spill_path = "blocks-test/test_utxo_set_kept_spill.bin";
+        let pk_hash = vec![7; 20];
+        let mut pk_script = vec![OP_DUP, OP_HASH160, 20];
+        pk_script.extend(&pk_hash);
+        pk_script.extend([OP_EQUALVERIFY, OP_CHECKSIG]);
+        let tx_output = |value: i64, pk_script: Vec<u8>| TxOutput {
+            value,
+            pk_script_bytes: CompactSize::new(pk_script.len()),
+            pk_script,
+            tx_id: Vec::new(),
+            index: 0,
+            block_path: String::new(),
+        };
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.enable_spilling(spill_path, 1)?;
+
+        utxo_set.insert(vec![1; 32], vec![tx_output(50_000, pk_script.clone())]);
+        utxo_set.insert(vec![2; 32], vec![tx_output(25_000, Vec::new())]);
+        utxo_set.insert(vec![3; 32], vec![tx_output(1, Vec::new())]);
+        let spilled_before = !utxo_set.set.contains_key(&vec![1; 32]);
+        let kept = utxo_set.keep_in_memory(pk_hash.clone());
+        let _ = std::fs::remove_file(spill_path);
+        kept?;
+
+        assert!(spilled_before);
+        assert!(utxo_set.set.contains_key(&vec![1; 32]));
+        assert!(!utxo_set
+            .spilled_entries()
+            .iter()
+            .any(|(tx_id, _)| tx_id == &vec![1; 32]));
+        assert_eq!(utxo_set.balance_for_pk_hash(&pk_hash, 0), 0.0005);
+        Ok(())
+    }
+
+    #[test]
+    fn test_outputs_of_a_pk_hash_are_indexed_until_they_are_spent() {
+        let pk_hash = vec![7; 20];
+        let mut pk_script = vec![OP_DUP, OP_HASH160, 20];
+        pk_script.extend(&pk_hash);
+        pk_script.extend([OP_EQUALVERIFY, OP_CHECKSIG]);
+        let tx_output = |value: i64, index: u64| TxOutput {
+            value,
+            pk_script_bytes: CompactSize::new(pk_script.len()),
+            pk_script: pk_script.clone(),
+            tx_id: Vec::new(),
+            index,
+            block_path: String::new(),
+        };
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.insert(
+            vec![1; 32],
+            vec![tx_output(50_000, 0), tx_output(25_000, 1)],
+        );
+        let outpoint = |index: u32| Outpoint {
+            tx_id: vec![1; 32],
+            index,
+        };
+
+        utxo_set.spend_output(&outpoint(0));
+        let balance_after_first_spend = utxo_set.balance_for_pk_hash(&pk_hash, 0);
+        let indexed_after_first_spend = utxo_set.by_pk_hash.contains_key(&pk_hash);
+        utxo_set.spend_output(&outpoint(1));
+
+        assert_eq!(balance_after_first_spend, 0.00025);
+        assert!(indexed_after_first_spend);
+        assert!(!utxo_set.contains_key(&vec![1; 32]));
+        assert!(utxo_set.by_pk_hash.is_empty());
+        assert_eq!(utxo_set.balance_for_pk_hash(&pk_hash, 0), 0.0);
+    }
+
+    #[test]
+    fn test_remove_outputs_before_keeps_outputs_with_unknown_block_timestamp(
+    ) -> Result<(), NodeError> {
+        let old_block_path = String::from(
+            "blocks-test/0000000000000027898516270708e0c8db276e6f8302b05c8c8c208bab36ea59.bin",
+        );
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.update(&old_block_path)?;
+        let unknown_output = TxOutput {
+            value: 1,
+            pk_script_bytes: CompactSize::new(0),
+            pk_script: Vec::new(),
+            tx_id: vec![1; 32],
+            index: 0,
+            block_path: String::from("blocks-test/missing_block.bin"),
+        };
+        utxo_set.insert(vec![1; 32], vec![unknown_output]);
+
+        utxo_set.remove_outputs_before(u32::MAX);
+
+        assert_eq!(utxo_set.stats().count, 1);
+        assert!(utxo_set.contains_key(&vec![1; 32]));
+        Ok(())
+    }
 }
@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    mem::size_of,
+};
+
+use crate::{
+    block::tx_hash::TxHash, compact_size::CompactSize, connectors::peer_connector::receive_message,
+    node_error::NodeError,
+};
+
+use super::tx_output::TxOutput;
+
+/// A file-backed store for the UTXOs evicted from the in-memory UTXO set.
+///
+/// The outputs of each transaction are appended to the file as a single record, and only the
+/// position of the records is kept in memory. Removed records stay in the file until the store is
+/// created again, which truncates it.
+#[derive(Debug)]
+pub struct UtxoSpillStore {
+    file: File,
+    records: HashMap<TxHash, (u64, usize)>,
+}
+
+impl UtxoSpillStore {
+    /// Creates an empty store, truncating the file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToOpenFile` if the file could not be created.
+    pub fn create(path: &str) -> Result<UtxoSpillStore, NodeError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|_| {
+                NodeError::FailedToOpenFile(format!("Failed to open UTXO spill file {}", path))
+            })?;
+
+        Ok(UtxoSpillStore {
+            file,
+            records: HashMap::new(),
+        })
+    }
+
+    /// Checks if the store holds the outputs of a transaction.
+    pub fn contains(&self, tx_id: &TxHash) -> bool {
+        self.records.contains_key(tx_id)
+    }
+
+    /// Returns the number of transactions whose outputs are in the store.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Checks if the store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns the ids of the transactions whose outputs are in the store.
+    pub fn tx_ids(&self) -> Vec<TxHash> {
+        self.records.keys().cloned().collect()
+    }
+
+    /// Writes the outputs of a transaction to the store, replacing the ones it already held.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToWrite` if the record could not be appended to the file.
+    pub fn write(&mut self, tx_id: TxHash, tx_outputs: &[TxOutput]) -> Result<(), NodeError> {
+        let record = outputs_to_bytes(tx_outputs);
+        let position = self
+            .file
+            .seek(SeekFrom::End(0))
+            .map_err(|_| NodeError::FailedToWrite("Failed to seek UTXO spill file".to_string()))?;
+        self.file
+            .write_all(&record)
+            .map_err(|_| NodeError::FailedToWrite("Failed to write UTXO spill file".to_string()))?;
+
+        self.records.insert(tx_id, (position, record.len()));
+        Ok(())
+    }
+
+    /// Reads the outputs of a transaction from the store.
+    ///
+    /// # Returns
+    ///
+    /// The outputs, or `None` if the store doesn't hold the transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the record could not be read or parsed.
+    pub fn read(&self, tx_id: &TxHash) -> Result<Option<Vec<TxOutput>>, NodeError> {
+        let (position, length) = match self.records.get(tx_id) {
+            Some(record) => *record,
+            None => return Ok(None),
+        };
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(position))
+            .map_err(|_| NodeError::FailedToRead("Failed to seek UTXO spill file".to_string()))?;
+        let mut record = vec![0; length];
+        file.read_exact(&mut record)
+            .map_err(|_| NodeError::FailedToRead("Failed to read UTXO spill file".to_string()))?;
+
+        outputs_from_bytes(tx_id, &record).map(Some)
+    }
+
+    /// Removes the outputs of a transaction from the store.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the store held the transaction.
+    pub fn remove(&mut self, tx_id: &TxHash) -> bool {
+        self.records.remove(tx_id).is_some()
+    }
+}
+
+/// Serializes transaction outputs with the fields that are not part of the wire format: the index
+/// and the block path of each output.
+fn outputs_to_bytes(tx_outputs: &[TxOutput]) -> Vec<u8> {
+    let mut bytes = CompactSize::new(tx_outputs.len()).to_bytes();
+    for tx_output in tx_outputs {
+        bytes.extend(tx_output.to_bytes());
+        bytes.extend(tx_output.index.to_le_bytes());
+        bytes.extend(CompactSize::new(tx_output.block_path.len()).to_bytes());
+        bytes.extend(tx_output.block_path.as_bytes());
+    }
+    bytes
+}
+
+/// Parses the transaction outputs serialized by `outputs_to_bytes`.
+fn outputs_from_bytes(tx_id: &TxHash, bytes: &[u8]) -> Result<Vec<TxOutput>, NodeError> {
+    let mut cursor = Cursor::new(bytes);
+    let outputs_count = CompactSize::read_varint(&mut cursor)?.get_value();
+
+    let mut tx_outputs = Vec::new();
+    for _ in 0..outputs_count {
+        let mut tx_output = TxOutput::read_tx_output_from_block(&mut cursor, 0)?;
+        let index = receive_message(&mut cursor, size_of::<u64>())?;
+        tx_output.index = u64::from_le_bytes(index.try_into().map_err(|_| {
+            NodeError::FailedToParse("Failed to convert Vec<u8> to [u8;8]".to_string())
+        })?);
+        let path_length = CompactSize::read_varint(&mut cursor)?.get_value() as usize;
+        tx_output.block_path = String::from_utf8(receive_message(&mut cursor, path_length)?)
+            .map_err(|_| NodeError::FailedToParse("Invalid block path".to_string()))?;
+        tx_output.tx_id = tx_id.clone();
+        tx_outputs.push(tx_output);
+    }
+    Ok(tx_outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spilled_outputs_are_read_back() -> Result<(), NodeError> {
+        let path = "blocks-test/test_utxo_spill_store.bin";
+        let tx_id = vec![3; 32];
+        let mut tx_output = TxOutput::new(0.5, vec![0x76, 0xa9], 1);
+        tx_output.tx_id = tx_id.clone();
+        tx_output.block_path = "blocks/block.bin".to_string();
+
+        let mut store = UtxoSpillStore::create(path)?;
+        store.write(tx_id.clone(), &[tx_output.clone()])?;
+        let read = store.read(&tx_id);
+        let _ = std::fs::remove_file(path);
+        let read = read?.ok_or(NodeError::FailedToRead("Missing outputs".to_string()))?;
+
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].value, tx_output.value);
+        assert_eq!(read[0].pk_script, tx_output.pk_script);
+        assert_eq!(read[0].index, 1);
+        assert_eq!(read[0].tx_id, tx_id);
+        assert_eq!(read[0].block_path, tx_output.block_path);
+        Ok(())
+    }
+}
@@ -0,0 +1,2 @@
+/// Represents the witness of a transaction input, which is a stack of items (vec of u8).
+pub type Witness = Vec<Vec<u8>>;
@@ -4,6 +4,7 @@ use crate::node_error::NodeError;
 use crate::transactions::transaction::Transaction;
 use crate::wallet::account::Account;
 use crate::wallet::wallet_account_info::AccountInfo;
+use crate::wallet::wallet_impl::Wallet;
 use std::sync::mpsc;
 use std::thread;
 
@@ -54,7 +55,12 @@ fn build_ui(app: &Application) -> Result<(), NodeError> {
 
     handle_ui_messages(ui_reciever, main_window, ui_sender, accounts_page);
 
-    thread::spawn(move || run_node(wallet_node_sender, wallet_receiver));
+    thread::spawn(move || {
+        run_node(
+            wallet_node_sender.clone(),
+            Some((wallet_node_sender, wallet_receiver)),
+        )
+    });
     Ok(())
 }
 
@@ -140,7 +146,7 @@ fn handle_ui_messages(
                 main_window.block_explorer_page.increment_progress_bar();
             }
             UIMessage::InitialBlockHeaders(block_headers) => {
-                build_block_list(&main_window.block_explorer_page, block_headers);
+                build_block_list(&mut main_window.block_explorer_page, block_headers);
             }
             UIMessage::AddNewAccount(account, account_info) => {
                 add_account_to_list(&accounts_page, account_info.clone(), ui_sender.clone());
@@ -168,12 +174,62 @@ fn handle_ui_messages(
                     .block_explorer_page
                     .add_new_block_received(block);
             }
+            UIMessage::ChainTip(height, block_hash) => {
+                main_window
+                    .block_explorer_page
+                    .set_chain_tip(height, block_hash);
+            }
             UIMessage::UpdateHeadersProgress => {
                 main_window.block_explorer_page.show_loading_headers();
             }
             UIMessage::HeadersDownloadFinished => {
                 main_window.block_explorer_page.hide_loading_headers();
             }
+            UIMessage::TransactionConflict(old_tx_id, new_tx_id) => {
+                main_window
+                    .overview_page
+                    .mark_transaction_as_replaced(old_tx_id, new_tx_id)
+                    .unwrap_or_else(|e| println!("Failed to mark the replaced tx: {:?}", e));
+            }
+            UIMessage::InsufficientFunds(available, requested) => {
+                main_window.send_page.show_send_error(&format!(
+                    "Insufficient funds: {} BTC available, {} BTC requested",
+                    available, requested
+                ));
+            }
+            UIMessage::TransactionDraft(tx_id, fee, outputs) => {
+                main_window
+                    .send_page
+                    .show_transaction_draft(tx_id, fee, outputs)
+                    .unwrap_or_else(|e| println!("Failed to show the transaction draft: {:?}", e));
+            }
+            UIMessage::SendFailed(reason) => {
+                main_window
+                    .send_page
+                    .show_send_error(&format!("Failed to send transaction: {}", reason));
+            }
+            UIMessage::LogLine(line) => {
+                main_window.log_page.append_line(line);
+            }
+            UIMessage::ShowReceiveQr(uri) => {
+                accounts_page
+                    .show_receive_qr(&uri)
+                    .unwrap_or_else(|e| println!("Failed to show the receive QR code: {:?}", e));
+            }
+            UIMessage::TransactionExpired(tx_id) => {
+                main_window
+                    .overview_page
+                    .remove_expired_transaction(tx_id)
+                    .unwrap_or_else(|e| println!("Failed to remove the expired tx: {:?}", e));
+            }
+            UIMessage::UtxoList(utxos) => {
+                main_window
+                    .transactions_page
+                    .clear_and_build_coins_list(utxos)
+                    .unwrap_or_else(|e| {
+                        println!("Failed to build coins list: {:?}", e);
+                    });
+            }
             _ => {
                 println!("Message not handled");
             }
@@ -314,7 +370,7 @@ fn add_account_to_list(
 ///
 /// This function builds a block list in the `block_explorer` UI page using the provided `block_headers`.
 fn build_block_list(
-    block_explorer: &BlockExplorerPage,
+    block_explorer: &mut BlockExplorerPage,
     block_headers: Vec<crate::block_header::BlockHeader>,
 ) {
     block_explorer
@@ -327,6 +383,7 @@ pub fn run_ui() {
     let app = Application::builder().build();
 
     app.connect_startup(|_| load_css());
+    app.connect_shutdown(|_| Wallet::save_running_wallet());
     if std::env::args().len() > NO_ARGS_LEN {
         app.set_flags(ApplicationFlags::HANDLES_OPEN);
         app.connect_open(move |app: &Application, _files, _| {
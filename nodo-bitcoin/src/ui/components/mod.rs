@@ -1 +1,3 @@
+pub mod receive_qr_code;
+pub mod sync_eta;
 pub mod transactions_confirmed_data;
@@ -0,0 +1,97 @@
+use qrcode::{Color, QrCode};
+
+use crate::{
+    constants::{QR_CODE_MODULE_PIXELS, QR_CODE_QUIET_ZONE_MODULES},
+    node_error::NodeError,
+};
+
+/// The number of bytes of each pixel of the image, one for each RGB channel
+const BYTES_PER_PIXEL: usize = 3;
+
+/// The image of a QR code, drawn as square RGB pixels with a light quiet zone around it
+pub struct ReceiveQrCode {
+    /// The width and height of the image, in pixels
+    pub size: usize,
+    /// The RGB bytes of the pixels, row by row
+    pub pixels: Vec<u8>,
+}
+
+impl ReceiveQrCode {
+    /// Encodes the data as a QR code and draws it
+    /// # Arguments
+    /// * `data` - The data to encode, such as a BIP21 URI
+    /// # Returns
+    /// * `Result<ReceiveQrCode, NodeError>` - The image of the QR code
+    /// # Errors
+    /// Returns a `NodeError::FailedToConvert` if the data is too long for a QR code
+    pub fn new(data: &str) -> Result<Self, NodeError> {
+        let qr_code = QrCode::new(data.as_bytes()).map_err(|e| {
+            NodeError::FailedToConvert(format!("Failed to encode the QR code: {}", e))
+        })?;
+        let modules = qr_code.width();
+        let size = (modules + 2 * QR_CODE_QUIET_ZONE_MODULES) * QR_CODE_MODULE_PIXELS;
+        let mut pixels = vec![u8::MAX; size * size * BYTES_PER_PIXEL];
+
+        for (index, color) in qr_code.to_colors().into_iter().enumerate() {
+            if color == Color::Light {
+                continue;
+            }
+            let x = (index % modules + QR_CODE_QUIET_ZONE_MODULES) * QR_CODE_MODULE_PIXELS;
+            let y = (index / modules + QR_CODE_QUIET_ZONE_MODULES) * QR_CODE_MODULE_PIXELS;
+            for row in y..y + QR_CODE_MODULE_PIXELS {
+                let start = (row * size + x) * BYTES_PER_PIXEL;
+                pixels[start..start + QR_CODE_MODULE_PIXELS * BYTES_PER_PIXEL].fill(0);
+            }
+        }
+
+        Ok(ReceiveQrCode { size, pixels })
+    }
+
+    /// Returns whether the pixel at the given position is dark
+    /// # Arguments
+    /// * `x` - The column of the pixel
+    /// * `y` - The row of the pixel
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.pixels[(y * self.size + x) * BYTES_PER_PIXEL] == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_qr_code_is_drawn_with_its_quiet_zone() -> Result<(), NodeError> {
+        let uri = "bitcoin:mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun?label=savings";
+        let qr_code = QrCode::new(uri.as_bytes())
+            .map_err(|_| NodeError::FailedToConvert("Failed to encode".to_string()))?;
+        let image = ReceiveQrCode::new(uri)?;
+
+        let quiet_zone = QR_CODE_QUIET_ZONE_MODULES * QR_CODE_MODULE_PIXELS;
+        assert_eq!(
+            image.size,
+            qr_code.width() * QR_CODE_MODULE_PIXELS + 2 * quiet_zone
+        );
+        assert_eq!(
+            image.pixels.len(),
+            image.size * image.size * BYTES_PER_PIXEL
+        );
+        assert!((0..image.size).all(|i| !image.is_dark(i, quiet_zone - 1)));
+        assert!((0..image.size).all(|i| !image.is_dark(quiet_zone - 1, i)));
+        // The finder pattern at the top left starts with a dark module
+        assert!(image.is_dark(quiet_zone, quiet_zone));
+        let dark_modules = qr_code
+            .to_colors()
+            .into_iter()
+            .filter(|color| *color == Color::Dark)
+            .count();
+        let dark_pixels = (0..image.size * image.size)
+            .filter(|pixel| image.is_dark(pixel % image.size, pixel / image.size))
+            .count();
+        assert_eq!(
+            dark_pixels,
+            dark_modules * QR_CODE_MODULE_PIXELS * QR_CODE_MODULE_PIXELS
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+/// The number of seconds in a minute
+const SECONDS_PER_MINUTE: u64 = 60;
+/// The number of seconds in an hour
+const SECONDS_PER_HOUR: u64 = 3600;
+
+/// Estimates the time left to download the remaining blocks, at the rate the downloaded ones took
+/// # Arguments
+/// * `downloaded` - The number of blocks downloaded
+/// * `total` - The total number of blocks to download
+/// * `elapsed` - The time since the download started
+/// # Returns
+/// * `Option<Duration>` - The time left, or `None` if nothing was downloaded yet or the download
+///   finished
+pub fn estimate_remaining(downloaded: i64, total: i64, elapsed: Duration) -> Option<Duration> {
+    if downloaded <= 0 || downloaded >= total {
+        return None;
+    }
+    Some(elapsed.mul_f64((total - downloaded) as f64 / downloaded as f64))
+}
+
+/// Formats the time left of a download, like "~2h 5min remaining"
+/// # Arguments
+/// * `remaining` - The time left
+/// # Returns
+/// * `String` - The formatted time left
+pub fn format_remaining(remaining: Duration) -> String {
+    let seconds = remaining.as_secs();
+    let hours = seconds / SECONDS_PER_HOUR;
+    let minutes = seconds % SECONDS_PER_HOUR / SECONDS_PER_MINUTE;
+    match (hours, minutes) {
+        (0, 0) => "<1min remaining".to_string(),
+        (0, minutes) => format!("~{}min remaining", minutes),
+        (hours, 0) => format!("~{}h remaining", hours),
+        (hours, minutes) => format!("~{}h {}min remaining", hours, minutes),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remaining_time_follows_the_download_rate() {
+        let elapsed = Duration::from_secs(600);
+
+        assert_eq!(
+            estimate_remaining(100, 1300, elapsed),
+            Some(Duration::from_secs(7200))
+        );
+        assert_eq!(estimate_remaining(0, 1300, elapsed), None);
+        assert_eq!(estimate_remaining(1300, 1300, elapsed), None);
+    }
+
+    #[test]
+    fn test_remaining_time_is_formatted_in_hours_and_minutes() {
+        assert_eq!(format_remaining(Duration::from_secs(7200)), "~2h remaining");
+        assert_eq!(
+            format_remaining(Duration::from_secs(7500)),
+            "~2h 5min remaining"
+        );
+        assert_eq!(
+            format_remaining(Duration::from_secs(300)),
+            "~5min remaining"
+        );
+        assert_eq!(format_remaining(Duration::from_secs(59)), "<1min remaining");
+    }
+}
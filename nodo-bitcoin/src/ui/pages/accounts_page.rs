@@ -1,9 +1,16 @@
-use gtk::{prelude::*, Builder, Button, Label, ListBox};
+use gtk::{
+    gdk_pixbuf::{Colorspace, Pixbuf},
+    prelude::*,
+    Builder, Button, Image, Label, ListBox,
+};
 use std::sync::mpsc;
 
 use crate::{
     node_error::NodeError,
-    ui::{ui_message::UIMessage, utils::get_object_by_name},
+    ui::{
+        components::receive_qr_code::ReceiveQrCode, ui_message::UIMessage,
+        utils::get_object_by_name,
+    },
     wallet::wallet_account_info::AccountInfo,
 };
 
@@ -18,6 +25,8 @@ pub struct AccountsPage {
     pub no_saved_accounts: Label,
     /// Shows the bitcoin address of the current account
     pub bitcoin_address_info: Label,
+    /// Shows the receive QR code of the current account
+    pub receive_qr_code: Image,
 }
 
 impl AccountsPage {
@@ -38,6 +47,7 @@ impl AccountsPage {
         let accounts_list: ListBox = get_object_by_name(&builder, "accounts_list")?;
         let no_saved_accounts: Label = get_object_by_name(&builder, "no_saved_accounts")?;
         let bitcoin_address_info: Label = get_object_by_name(&builder, "bitcoin_address_info")?;
+        let receive_qr_code: Image = get_object_by_name(&builder, "receive_qr_code")?;
 
         Self::build_accounts_list(
             saved_accounts,
@@ -52,6 +62,7 @@ impl AccountsPage {
             accounts_list,
             no_saved_accounts,
             bitcoin_address_info,
+            receive_qr_code,
         })
     }
     /// Builds the accounts list
@@ -114,6 +125,31 @@ impl AccountsPage {
         Ok(())
     }
 
+    /// Shows the receive QR code of the current account, encoding its BIP21 URI. The URI is also
+    /// shown when hovering the QR code
+    /// # Arguments
+    /// * `uri` - The BIP21 URI of the current account
+    /// # Returns
+    /// * `Result<(), NodeError>` - Result
+    pub fn show_receive_qr(&self, uri: &str) -> Result<(), NodeError> {
+        let qr_code = ReceiveQrCode::new(uri)?;
+        let size = i32::try_from(qr_code.size).map_err(|_| {
+            NodeError::FailedToConvert("The QR code is too large to show".to_string())
+        })?;
+        let pixbuf = Pixbuf::from_bytes(
+            &glib::Bytes::from_owned(qr_code.pixels),
+            Colorspace::Rgb,
+            false,
+            8,
+            size,
+            size,
+            size * 3,
+        );
+        self.receive_qr_code.set_from_pixbuf(Some(&pixbuf));
+        self.receive_qr_code.set_tooltip_text(Some(uri));
+        Ok(())
+    }
+
     /// Shows the no accounts saved label
     /// # Returns
     /// * `Result<(), NodeError>` - Result
@@ -48,8 +48,7 @@ impl AddAccountPage {
         let cloned_main_window = main_window.window.clone();
         new_account.connect_clicked(clone!(@weak bitcoin_address, @weak private_key, @weak account_name => move |_|{
             let new_account = AccountInfo::new_from_values(bitcoin_address.buffer().text(),private_key.buffer().text(), account_name.buffer().text());
-            let _ = new_account.save_to_file();
-            ui_sender_to_wallet.send(AddAccount(new_account.copy())).unwrap_or_else(|_| println!("Error sending AddAccount message to wallet"));
+            ui_sender_to_wallet.send(AddAccount(new_account)).unwrap_or_else(|_| println!("Error sending AddAccount message to wallet"));
             cloned_login.set_visible(false);
             cloned_main_window.set_visible(true);
             account_name.set_text("");
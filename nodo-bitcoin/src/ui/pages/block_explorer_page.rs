@@ -2,12 +2,17 @@ use gtk::{
     prelude::*, Box, Builder, Button, Fixed as GtkFixed, Label, ListBox, Popover, ProgressBar,
     ScrolledWindow, Widget,
 };
+use std::time::Instant;
 
 use crate::{
-    block_header::BlockHeader,
-    constants::COMPLETE_DOWNLOAD_FRACTION,
+    block::block_hash::InternalHash,
+    block_header::{average_interval, BlockHeader},
+    constants::{BLOCK_INTERVAL_WINDOW, COMPLETE_DOWNLOAD_FRACTION},
     node_error::NodeError,
-    ui::utils::{build_block_info, get_object_by_name, timestamp_to_date, u8_to_hex_string},
+    ui::{
+        components::sync_eta::{estimate_remaining, format_remaining},
+        utils::{build_block_info, get_object_by_name, timestamp_to_date},
+    },
 };
 
 /// BlockExplorerPage shows all the block hashes from the timestamp defined in config
@@ -26,6 +31,8 @@ pub struct BlockExplorerPage {
     headers_count: i64,
     /// The total number of blocks to download
     total_blocks: i64,
+    /// When the first block was downloaded, to estimate the time left of the download
+    download_start: Option<Instant>,
     /// The label that shows the title Blocks Download Status
     label_blocks: Label,
     /// The starting date timestamp
@@ -34,6 +41,10 @@ pub struct BlockExplorerPage {
     label_headers: Label,
     /// The icon that shows the loading of the headers
     icon_loading: gtk::Spinner,
+    /// The label that shows the height and hash of the current chain tip
+    label_chain_tip: Label,
+    /// The most recent block headers, to compute the observed block interval
+    recent_headers: Vec<BlockHeader>,
 }
 
 impl BlockExplorerPage {
@@ -55,6 +66,7 @@ impl BlockExplorerPage {
         let icon_loading = get_object_by_name(&builder, "headers_loading")?;
         let label_blocks: Label = get_object_by_name(&builder, "status_label")?;
         label_blocks.hide();
+        let label_chain_tip = get_object_by_name(&builder, "chain_tip")?;
 
         Ok(BlockExplorerPage {
             page,
@@ -63,11 +75,14 @@ impl BlockExplorerPage {
             progress_bar,
             blocks_count: 0,
             total_blocks: 0,
+            download_start: None,
             label_blocks,
             headers_count: 0,
             timestamp,
             label_headers,
             icon_loading,
+            label_chain_tip,
+            recent_headers: Vec::new(),
         })
     }
 
@@ -94,6 +109,21 @@ impl BlockExplorerPage {
             .set_text(&format!("Blocks since {}", &timestamp_date));
     }
 
+    /// Shows the height and hash of the current chain tip
+    /// # Arguments
+    /// * `height` - The height of the last saved block
+    /// * `block_hash` - The hash of the last saved block
+    pub fn set_chain_tip(&self, height: u64, block_hash: String) {
+        let mut chain_tip = format!("Chain tip: {}\n{}", height, block_hash);
+        if let Some(interval) = average_interval(&self.recent_headers, BLOCK_INTERVAL_WINDOW) {
+            chain_tip.push_str(&format!(
+                "\nAverage block interval: {:.1} min",
+                interval / 60.0
+            ));
+        }
+        self.label_chain_tip.set_text(&chain_tip);
+    }
+
     /// Sets the total number of blocks to download
     pub fn set_total_blocks(&mut self, total_blocks: i64) {
         self.total_blocks = total_blocks;
@@ -111,10 +141,11 @@ impl BlockExplorerPage {
     /// * `block_headers` - The block headers to build the list box with
     /// # Returns
     /// * `Result<(), NodeError>` - The result of the building
-    pub fn build_list_box(&self, block_headers: Vec<BlockHeader>) -> Result<(), NodeError> {
+    pub fn build_list_box(&mut self, block_headers: Vec<BlockHeader>) -> Result<(), NodeError> {
         let scrolled_window: ScrolledWindow =
             get_object_by_name(&self.builder, "scrolled_view_block")?;
 
+        self.record_recent_headers(&block_headers);
         self.build_list_block_from_headers(block_headers);
         self.list_box.show_all();
         let box_layout = Box::new(gtk::Orientation::Vertical, 0);
@@ -126,16 +157,23 @@ impl BlockExplorerPage {
         Ok(())
     }
 
-    /// Updates the progress bar by adding a new block to the count
+    /// Updates the progress bar by adding a new block to the count, along with the estimated time
+    /// left of the download
     pub fn increment_progress_bar(&mut self) {
         self.blocks_count += 1;
+        let download_start = *self.download_start.get_or_insert_with(Instant::now);
         if self.total_blocks > 0 {
             let fraction = self.blocks_count as f64 / self.total_blocks as f64;
             self.progress_bar.set_fraction(fraction);
-            self.progress_bar.set_text(Some(&format!(
-                "{}/{}",
-                self.blocks_count, self.total_blocks
-            )));
+            let mut progress = format!("{}/{}", self.blocks_count, self.total_blocks);
+            if let Some(remaining) = estimate_remaining(
+                self.blocks_count,
+                self.total_blocks,
+                download_start.elapsed(),
+            ) {
+                progress.push_str(&format!(" - {}", format_remaining(remaining)));
+            }
+            self.progress_bar.set_text(Some(&progress));
             if fraction == COMPLETE_DOWNLOAD_FRACTION {
                 self.progress_bar.set_text(Some(&format!(
                     "Finished download. Total: {}",
@@ -148,19 +186,32 @@ impl BlockExplorerPage {
     /// Adds a block header to the list box
     /// # Arguments
     /// * `block_header` - The block header to add
-    pub fn add_new_block_received(&self, block_header: BlockHeader) {
+    pub fn add_new_block_received(&mut self, block_header: BlockHeader) {
+        self.record_recent_headers(&[block_header.clone()]);
         self.add_block(block_header);
         self.list_box.show_all();
     }
 
+    /// Keeps the last BLOCK_INTERVAL_WINDOW headers received, oldest first
+    /// # Arguments
+    /// * `block_headers` - The new block headers, oldest first
+    fn record_recent_headers(&mut self, block_headers: &[BlockHeader]) {
+        self.recent_headers.extend_from_slice(block_headers);
+        let excess = self
+            .recent_headers
+            .len()
+            .saturating_sub(BLOCK_INTERVAL_WINDOW);
+        self.recent_headers.drain(..excess);
+    }
+
     /// Adds a row to the list box containing the block header
     /// # Arguments
     /// * `block_header` - The block header to add
     fn add_block(&self, block_header: BlockHeader) {
         let row = gtk::ListBoxRow::new();
-        let mut cloned_hash = block_header.hash.clone();
-        cloned_hash.reverse();
-        let hash = u8_to_hex_string(&cloned_hash);
+        let hash = InternalHash::from_slice(block_header.hash())
+            .map(|hash| hash.to_display().to_string())
+            .unwrap_or_default();
         let button_label = Button::new();
         button_label.set_label(&hash);
         let cloned_row = row.clone();
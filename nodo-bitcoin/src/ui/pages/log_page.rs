@@ -0,0 +1,65 @@
+use gtk::{prelude::*, Builder, Fixed as GtkFixed, TextMark, TextView, Widget};
+
+use crate::{
+    constants::LOG_CONSOLE_MAX_LINES, node_error::NodeError, ui::utils::get_object_by_name,
+};
+
+/// The log page, a console that shows the last lines written to the log by the node.
+pub struct LogPage {
+    /// The page itself.
+    pub page: GtkFixed,
+    /// The text view that holds the log lines
+    text_view: TextView,
+    /// A mark that stays at the end of the log, to scroll to the last line
+    end_mark: TextMark,
+}
+
+impl LogPage {
+    /// Creates a new log page.
+    /// # Arguments
+    /// * `child` - The child widget.
+    /// * `builder` - The builder used to create the page.
+    /// # Returns
+    /// * The log page.
+    /// # Errors
+    /// NodeError::UIError if the child widget could not be downcast to a GtkFixed, or the text
+    /// view has no buffer.
+    pub fn new(child: Widget, builder: Builder) -> Result<Self, NodeError> {
+        let page = child
+            .downcast::<GtkFixed>()
+            .map_err(|_| NodeError::UIError("Failed to downcast to GtkFixed".to_string()))?;
+        let text_view: TextView = get_object_by_name(&builder, "log_text_view")?;
+        let buffer = text_view
+            .buffer()
+            .ok_or(NodeError::UIError("Failed to get log buffer".to_string()))?;
+        let end_mark = buffer.create_mark(None, &buffer.end_iter(), false);
+
+        Ok(LogPage {
+            page,
+            text_view,
+            end_mark,
+        })
+    }
+
+    /// Appends a line to the log, removing the oldest lines so only the last LOG_CONSOLE_MAX_LINES
+    /// are kept, and scrolls to it.
+    /// # Arguments
+    /// * `line` - The line to append.
+    pub fn append_line(&self, line: String) {
+        let buffer = match self.text_view.buffer() {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        buffer.insert(&mut buffer.end_iter(), &format!("{}\n", line));
+
+        let exceeding_lines = buffer.line_count() - 1 - LOG_CONSOLE_MAX_LINES;
+        if exceeding_lines > 0 {
+            buffer.delete(
+                &mut buffer.start_iter(),
+                &mut buffer.iter_at_line(exceeding_lines),
+            );
+        }
+        self.text_view
+            .scroll_to_mark(&self.end_mark, 0.0, false, 0.0, 0.0);
+    }
+}
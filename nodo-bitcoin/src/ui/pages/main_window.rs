@@ -1,6 +1,7 @@
 use std::sync::mpsc;
 
 use super::block_explorer_page::BlockExplorerPage;
+use super::log_page::LogPage;
 use super::overview_page::OverviewPage;
 use super::send_page::SendPage;
 use super::transactions_page::TransactionsPage;
@@ -23,6 +24,8 @@ pub struct MainWindow {
     pub block_explorer_page: BlockExplorerPage,
     /// Transactions page to see txs history
     pub transactions_page: TransactionsPage,
+    /// Log page to see the node activity
+    pub log_page: LogPage,
 }
 
 impl MainWindow {
@@ -43,6 +46,7 @@ impl MainWindow {
             .map_err(|_| NodeError::UIError("Failed to set icon from file".to_string()))?;
         let (overview, send, block_explorer, transactions) =
             Self::build_navigation(&builder, ui_sender_to_wallet)?;
+        let log_page = LogPage::new(get_object_by_name(&builder, "log_page")?, builder.clone())?;
         window.set_application(Some(app));
         window.set_title("Inoxidables Node");
         Ok(MainWindow {
@@ -52,6 +56,7 @@ impl MainWindow {
             send_page: send,
             block_explorer_page: block_explorer,
             transactions_page: transactions,
+            log_page,
         })
     }
 
@@ -99,6 +104,7 @@ impl MainWindow {
         let transactions = TransactionsPage::new(
             get_object_by_name(builder, "transactions_page")?,
             builder.clone(),
+            ui_sender_to_wallet.clone(),
         )?;
         Ok((overview, send, block_explorer, transactions))
     }
@@ -1,6 +1,7 @@
 pub mod accounts_page;
 pub mod add_account_page;
 pub mod block_explorer_page;
+pub mod log_page;
 pub mod main_window;
 pub mod overview_page;
 pub mod send_page;
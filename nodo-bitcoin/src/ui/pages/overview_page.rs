@@ -1,11 +1,14 @@
+use std::collections::HashMap;
+
 use gtk::{prelude::*, Builder, Fixed as GtkFixed, Label, TreeStore, TreeView, Widget};
 
 use crate::{
+    block::{block_hash::InternalHash, tx_hash::TxHash},
     node_error::NodeError,
     transactions::transaction::Transaction,
     ui::{
         components::transactions_confirmed_data::{Amount, TransactionConfirmedData},
-        utils::{get_object_by_name, u8_to_hex_string},
+        utils::get_object_by_name,
     },
     wallet::account::Account,
 };
@@ -91,14 +94,16 @@ impl OverviewPage {
         amount: &Amount,
     ) -> Result<(), NodeError> {
         let iter = self.pending_txs_store.append(None);
-        let mut tx_id = transaction.tx_id();
-        tx_id.reverse();
-        let tx_id_text = &u8_to_hex_string(tx_id.as_slice());
+        let tx_id_text = &InternalHash::from_slice(&transaction.tx_id())?
+            .to_display()
+            .to_string();
 
         self.pending_txs_store
             .set_value(&iter, 0, &tx_id_text.to_value());
         self.pending_txs_store
             .set_value(&iter, 1, &amount.to_value());
+        self.pending_txs_store
+            .set_value(&iter, 2, &"pending".to_value());
 
         self.page.show_all();
 
@@ -118,9 +123,9 @@ impl OverviewPage {
         let all_txs = transactions.txs.all_txs();
         for transaction in all_txs {
             let iter = self.confirmed_txs_store.append(None);
-            let mut tx_id = transaction.tx_id();
-            tx_id.reverse();
-            let tx_id_text = &u8_to_hex_string(tx_id.as_slice());
+            let tx_id_text = &InternalHash::from_slice(&transaction.tx_id())?
+                .to_display()
+                .to_string();
 
             let amount = transaction.amount_received_by_address(&bitcoin_address);
 
@@ -151,6 +156,69 @@ impl OverviewPage {
         });
     }
 
+    /// Marks a pending transaction as possibly replaced by a conflicting transaction
+    /// # Arguments
+    /// * `old_tx_id` - The id of the pending transaction
+    /// * `new_tx_id` - The id of the transaction that spends the same outpoints
+    /// # Returns
+    /// * `Result<(), NodeError>` - The result
+    pub fn mark_transaction_as_replaced(
+        &self,
+        old_tx_id: TxHash,
+        new_tx_id: TxHash,
+    ) -> Result<(), NodeError> {
+        let old_tx_id_text = InternalHash::from_slice(&old_tx_id)?
+            .to_display()
+            .to_string();
+        let new_tx_id_text = InternalHash::from_slice(&new_tx_id)?.to_display();
+        let num_rows = self.pending_txs_store.iter_n_children(None);
+
+        for i in 0..num_rows {
+            if let Some(iter) = self.pending_txs_store.iter_nth_child(None, i) {
+                let value = self.pending_txs_store.value(&iter, 0);
+                if let Ok(tx_id_row) = value.get::<String>() {
+                    if tx_id_row == old_tx_id_text {
+                        let replaced_text = format!("{} (possibly replaced)", old_tx_id_text);
+                        self.pending_txs_store
+                            .set_value(&iter, 0, &replaced_text.to_value());
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.show_new_tx_alert(format!(
+            "Tx {} conflicts with tx {}",
+            old_tx_id_text, new_tx_id_text
+        ));
+        Ok(())
+    }
+
+    /// Removes a pending transaction that expired without confirming
+    /// # Arguments
+    /// * `tx_id` - The id of the expired transaction
+    /// # Returns
+    /// * `Result<(), NodeError>` - The result
+    pub fn remove_expired_transaction(&self, tx_id: TxHash) -> Result<(), NodeError> {
+        let tx_id_text = InternalHash::from_slice(&tx_id)?.to_display().to_string();
+        let num_rows = self.pending_txs_store.iter_n_children(None);
+
+        for i in 0..num_rows {
+            if let Some(iter) = self.pending_txs_store.iter_nth_child(None, i) {
+                let value = self.pending_txs_store.value(&iter, 0);
+                if let Ok(tx_id_row) = value.get::<String>() {
+                    if tx_id_row == tx_id_text {
+                        self.pending_txs_store.remove(&iter);
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.show_new_tx_alert(format!("Tx {} expired without confirming", tx_id_text));
+        Ok(())
+    }
+
     /// Removes a pending transaction from the tree store
     /// # Arguments
     /// * `tx_id` - The transaction id to remove
@@ -159,11 +227,11 @@ impl OverviewPage {
     fn remove_pending_transactions(&self, txs: Vec<Transaction>) -> Result<(), NodeError> {
         let mut iters_to_remove = Vec::new();
         for tx in txs {
-            let mut tx_id = tx.tx_id();
-            tx_id.reverse();
             let num_rows = self.pending_txs_store.iter_n_children(None);
 
-            let tx_id_text = &u8_to_hex_string(&tx_id);
+            let tx_id_text = &InternalHash::from_slice(&tx.tx_id())?
+                .to_display()
+                .to_string();
 
             for i in 0..num_rows {
                 if let Some(iter) = self.pending_txs_store.iter_nth_child(None, i) {
@@ -185,6 +253,40 @@ impl OverviewPage {
         Ok(())
     }
 
+    /// Shows the confirmations of the pending transactions, as "pending (k/N)"
+    /// # Arguments
+    /// * `account` - The account the pending transactions belong to
+    /// # Returns
+    /// * `Result<(), NodeError>` - The result
+    fn update_pending_statuses(&self, account: &Account) -> Result<(), NodeError> {
+        let statuses = account
+            .unconfirmed_transactions
+            .all_txs()
+            .iter()
+            .map(|transaction| {
+                let tx_id = transaction.tx_id();
+                let tx_id_text = InternalHash::from_slice(&tx_id)?.to_display().to_string();
+                Ok((tx_id_text, account.confirmation_status(&tx_id)))
+            })
+            .collect::<Result<HashMap<String, String>, NodeError>>()?;
+
+        let num_rows = self.pending_txs_store.iter_n_children(None);
+        for i in 0..num_rows {
+            if let Some(iter) = self.pending_txs_store.iter_nth_child(None, i) {
+                let value = self.pending_txs_store.value(&iter, 0);
+                if let Some(status) = value
+                    .get::<String>()
+                    .ok()
+                    .and_then(|tx_id_row| statuses.get(&tx_id_row))
+                {
+                    self.pending_txs_store
+                        .set_value(&iter, 2, &status.to_value());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Updates the transactions, removing the pending transactions
     /// # Arguments
     /// * `transactions` - The transactions id to update
@@ -196,6 +298,7 @@ impl OverviewPage {
     ) -> Result<(), NodeError> {
         let all_txs = txs_data.txs.all_txs();
         self.remove_pending_transactions(all_txs)?;
+        self.update_pending_statuses(&txs_data.account)?;
 
         self.update_account(&mut txs_data.account);
         self.add_confirmed_transactions(txs_data)?;
@@ -294,6 +397,7 @@ impl OverviewPage {
             let amount = transaction.amount_received_by_address(&bitcoin_address);
             self.add_pending_transaction(transaction, &amount.to_string())?;
         }
+        self.update_pending_statuses(&account)?;
 
         let confirmed_txs =
             TransactionConfirmedData::new(account.confirmed_transactions.clone(), account);
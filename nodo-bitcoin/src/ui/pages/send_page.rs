@@ -1,11 +1,15 @@
 use std::sync::mpsc;
 
 use crate::{
+    block::{block_hash::InternalHash, tx_hash::TxHash},
     node_error::NodeError,
     ui::{ui_message::UIMessage, utils::get_object_by_name},
 };
 use glib::clone;
-use gtk::{prelude::*, Builder, Button, Entry, Fixed as GtkFixed, Widget};
+use gtk::{
+    prelude::*, Builder, Button, ButtonsType, DialogFlags, Entry, Fixed as GtkFixed, Label,
+    MessageDialog, MessageType, ResponseType, Widget, Window,
+};
 
 /// Page to create new transactions
 pub struct SendPage {
@@ -13,6 +17,10 @@ pub struct SendPage {
     pub page: GtkFixed,
     /// The builder to get the widgets from
     pub builder: Builder,
+    /// The label that shows why the last transaction could not be sent
+    pub label_send_status: Label,
+    /// The sender to send messages to the wallet
+    ui_sender_to_wallet: mpsc::Sender<UIMessage>,
 }
 
 impl SendPage {
@@ -37,9 +45,12 @@ impl SendPage {
         let fee: Entry = get_object_by_name(&builder, "send_fee")?;
         let bitcoin_address: Entry = get_object_by_name(&builder, "send_bitcoin_address")?;
         let amount_entry: Entry = get_object_by_name(&builder, "send_amount")?;
+        let label_send_status: Label = get_object_by_name(&builder, "send_status")?;
 
+        let sender = ui_sender_to_wallet.clone();
         send_transaction.connect_clicked(
-            clone!(@weak fee, @weak bitcoin_address, @weak amount_entry => move |_| {
+            clone!(@weak fee, @weak bitcoin_address, @weak amount_entry, @weak label_send_status => move |_| {
+                label_send_status.set_text("");
                 let fee_num = fee.text().to_string().parse::<f64>().unwrap_or(0.0);
                 let address_text = bitcoin_address.text().to_string();
                 let amount = amount_entry
@@ -47,7 +58,7 @@ impl SendPage {
                     .to_string()
                     .parse::<f64>()
                     .unwrap_or(0.0);
-                ui_sender_to_wallet
+                sender
                     .send(UIMessage::CreateNewTransaction(
                         address_text,
                         amount,
@@ -65,7 +76,60 @@ impl SendPage {
 
         Self::clear_all(clear_all, fee, bitcoin_address, amount_entry);
 
-        Ok(SendPage { page, builder })
+        Ok(SendPage {
+            page,
+            builder,
+            label_send_status,
+            ui_sender_to_wallet,
+        })
+    }
+
+    /// Asks the user to confirm a transaction created by the wallet before it's broadcasted
+    /// # Arguments
+    /// * `tx_id` - The id of the transaction
+    /// * `fee` - The fee of the transaction
+    /// * `outputs` - The label and value of each output of the transaction
+    /// # Returns
+    /// * `Result<(), NodeError>` - The result
+    pub fn show_transaction_draft(
+        &self,
+        tx_id: TxHash,
+        fee: f64,
+        outputs: Vec<(String, f64)>,
+    ) -> Result<(), NodeError> {
+        let tx_id_text = InternalHash::from_slice(&tx_id)?.to_display();
+        let mut text = format!("Send transaction {}?\n", tx_id_text);
+        for (label, value) in outputs {
+            text.push_str(&format!("\n{} BTC to {}", value, label));
+        }
+        text.push_str(&format!("\n\nFee: {} BTC", fee));
+
+        let dialog = MessageDialog::new(
+            None::<&Window>,
+            DialogFlags::MODAL,
+            MessageType::Question,
+            ButtonsType::YesNo,
+            &text,
+        );
+        let sender = self.ui_sender_to_wallet.clone();
+        dialog.connect_response(move |dialog, response| {
+            let message = if response == ResponseType::Yes {
+                UIMessage::ConfirmSend(tx_id.clone())
+            } else {
+                UIMessage::CancelSend(tx_id.clone())
+            };
+            sender.send(message).unwrap_or_else(|e| {
+                println!("Error sending the confirmation to wallet {}", e);
+            });
+            dialog.close();
+        });
+        dialog.show_all();
+        Ok(())
+    }
+
+    /// Shows why the last transaction could not be sent
+    pub fn show_send_error(&self, message: &str) {
+        self.label_send_status.set_text(message);
     }
 
     /// Clears all the entries
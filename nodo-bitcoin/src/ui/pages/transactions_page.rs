@@ -1,14 +1,17 @@
-use gtk::{prelude::*, Builder, Fixed as GtkFixed, TreeStore, TreeView, Widget};
+use std::sync::mpsc;
+
+use gtk::{prelude::*, Builder, Button, Fixed as GtkFixed, TreeStore, TreeView, Widget};
 
 use crate::{
-    block::proof_of_inclusion::MerkleProof,
+    block::{block_hash::InternalHash, proof_of_inclusion::MerkleProof},
     node_error::NodeError,
-    ui::utils::{get_object_by_name, u8_to_hex_string},
+    transactions::outpoint::Outpoint,
+    ui::{ui_message::UIMessage, utils::get_object_by_name},
     wallet::account::Account,
 };
 
 /// The transactions page.
-/// This page is used to display the UTXO of the current account.
+/// This page is used to display the UTXO of the current account, as a list of coins.
 pub struct TransactionsPage {
     /// The page itself.
     pub page: GtkFixed,
@@ -21,15 +24,29 @@ impl TransactionsPage {
     /// # Arguments
     /// * `child` - The child widget.
     /// * `builder` - The builder used to create the page.
+    /// * `ui_sender_to_wallet` - The sender the export history button asks the wallet with.
     /// # Returns
     /// * The transactions page.
     /// # Errors
     /// NodeError::UIError if the child widget could not be downcast to a GtkFixed.
-    pub fn new(child: Widget, builder: Builder) -> Result<Self, NodeError> {
+    pub fn new(
+        child: Widget,
+        builder: Builder,
+        ui_sender_to_wallet: mpsc::Sender<UIMessage>,
+    ) -> Result<Self, NodeError> {
         let page = child
             .downcast::<GtkFixed>()
             .map_err(|_| NodeError::UIError("Failed to downcast to GtkFixed".to_string()))?;
 
+        let export_history: Button = get_object_by_name(&builder, "export_history_button")?;
+        export_history.connect_clicked(move |_| {
+            ui_sender_to_wallet
+                .send(UIMessage::ExportHistory)
+                .unwrap_or_else(|e| {
+                    println!("Error sending the history export to wallet {}", e);
+                });
+        });
+
         let txs_tree_view: TreeView = get_object_by_name(&builder, "utxo_tree_view")?;
 
         let txs_store = match txs_tree_view.model() {
@@ -48,29 +65,38 @@ impl TransactionsPage {
     /// # Returns
     /// * Result<(), NodeError> - The result of the operation.
     pub fn build_transactions_list(&self, account: &Account) -> Result<(), NodeError> {
-        let transactions = account.copy().utxo_set.set;
-        for transaction in transactions {
-            let iter = self.txs_store.append(None);
-            let iter_child = self.txs_store.append(Some(&iter));
-            let mut tx_id = transaction.0;
-            tx_id.reverse();
-            let tx_id_text = u8_to_hex_string(&tx_id);
+        self.build_coins_list(account.list_utxos()?)
+    }
 
-            let mut total_amount: f64 = 0.0;
-            for output in transaction.1.clone() {
-                total_amount += output.value();
-            }
+    /// Builds the coins list, with a row for each unspent output. Each row has the block the
+    /// output comes from and the proof of inclusion of its transaction as children.
+    /// # Arguments
+    /// * `utxos` - The outpoint, value and block path of each unspent output.
+    /// # Returns
+    /// * Result<(), NodeError> - The result of the operation.
+    pub fn build_coins_list(&self, utxos: Vec<(Outpoint, f64, String)>) -> Result<(), NodeError> {
+        for (outpoint, value, block_path) in utxos {
+            let iter = self.txs_store.append(None);
+            let tx_id_text = InternalHash::from_slice(&outpoint.tx_id)?
+                .to_display()
+                .to_string();
+            let outpoint_text = format!("{}:{}", tx_id_text, outpoint.index);
 
-            let block_path = transaction.1[0].block_path.clone();
             let proof_result =
-                match MerkleProof::path_for_tx_in_block(u8_to_hex_string(&tx_id), block_path) {
+                match MerkleProof::path_for_tx_in_block(tx_id_text, block_path.clone()) {
                     Ok(proof_of_inclusion) => proof_of_inclusion,
                     Err(e) => format!("Not found - Error: {:?}", e),
                 };
-            self.txs_store.set_value(&iter, 0, &tx_id_text.to_value());
-            self.txs_store.set_value(&iter, 1, &total_amount.to_value());
             self.txs_store
-                .set_value(&iter_child, 0, &proof_result.to_value());
+                .set_value(&iter, 0, &outpoint_text.to_value());
+            self.txs_store.set_value(&iter, 1, &value.to_value());
+
+            let block_iter = self.txs_store.append(Some(&iter));
+            self.txs_store
+                .set_value(&block_iter, 0, &format!("Block: {}", block_path).to_value());
+            let proof_iter = self.txs_store.append(Some(&iter));
+            self.txs_store
+                .set_value(&proof_iter, 0, &proof_result.to_value());
         }
 
         self.page.show_all();
@@ -101,4 +127,17 @@ impl TransactionsPage {
         self.clear_transactions_list()?;
         self.build_transactions_list(account)
     }
+
+    /// Clears the coins list in the UI and builds it again with the given unspent outputs.
+    /// # Arguments
+    /// * `utxos` - The outpoint, value and block path of each unspent output.
+    /// # Returns
+    /// * Result<(), NodeError> - The result of the operation.
+    pub fn clear_and_build_coins_list(
+        &self,
+        utxos: Vec<(Outpoint, f64, String)>,
+    ) -> Result<(), NodeError> {
+        self.clear_transactions_list()?;
+        self.build_coins_list(utxos)
+    }
 }
@@ -1,6 +1,7 @@
 use crate::{
+    block::tx_hash::TxHash,
     block_header::BlockHeader,
-    transactions::transaction::Transaction,
+    transactions::{outpoint::Outpoint, transaction::Transaction},
     wallet::{account::Account, wallet_account_info::AccountInfo},
 };
 
@@ -26,6 +27,8 @@ pub enum UIMessage {
     CreateNewTransaction(String, f64, f64),
     /// The node sends the UI the new block hash
     NewBlock(BlockHeader),
+    /// The node sends the UI the new chain tip after saving a block: height and block hash
+    ChainTip(u64, String),
     /// The node sends the UI the new transaction received and the amount
     NewTransactionReceived(Transaction, Amount),
     /// The node sends the UI the new transaction sent and the amount
@@ -44,4 +47,29 @@ pub enum UIMessage {
     UpdateHeadersProgress,
     /// Message to hide the headers count and show the block progress bar
     HeadersDownloadFinished,
+    /// The wallet sends the UI the ids of an unconfirmed transaction and of a new transaction that
+    /// spends one of its outpoints: old_txid, new_txid
+    TransactionConflict(TxHash, TxHash),
+    /// The wallet sends the UI the unspent outputs of the current account: outpoint, value and block path
+    UtxoList(Vec<(Outpoint, f64, String)>),
+    /// The wallet tells the UI the current account can't pay a transaction: available and
+    /// requested amounts
+    InsufficientFunds(f64, f64),
+    /// The wallet tells the UI a transaction could not be sent, and why
+    SendFailed(String),
+    /// The wallet sends the UI a created transaction that waits to be confirmed before it's
+    /// broadcasted: txid, fee and the label and value of each output
+    TransactionDraft(TxHash, f64, Vec<(String, f64)>),
+    /// The UI asks the wallet to broadcast a transaction draft: txid
+    ConfirmSend(TxHash),
+    /// The UI asks the wallet to discard a transaction draft: txid
+    CancelSend(TxHash),
+    /// The logger sends the UI a line written to the log, to show it in the log console
+    LogLine(String),
+    /// The wallet sends the UI the BIP21 URI of the current account, to show it as a QR code
+    ShowReceiveQr(String),
+    /// The wallet tells the UI an unconfirmed transaction was dropped because it never confirmed: txid
+    TransactionExpired(TxHash),
+    /// The UI asks the wallet to export the transaction history of the current account as CSV
+    ExportHistory,
 }
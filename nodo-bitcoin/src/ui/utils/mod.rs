@@ -5,10 +5,6 @@ use crate::{
 use chrono::{DateTime, Local, NaiveDateTime, Utc};
 use glib::Object;
 use gtk::{prelude::*, Box, Label};
-use std::{
-    fs::OpenOptions,
-    io::{BufRead, BufReader},
-};
 
 /// Function to convert a vector of bytes to a hex string
 pub fn u8_to_hex_string(bytes: &[u8]) -> String {
@@ -34,7 +30,8 @@ pub fn create_label_with_title(title: &str, info: &str) -> Label {
 }
 
 /// Function to build a block info box
-/// This box contains the block hash, merkle root, previous block hash, nonce, timestamp and version
+/// This box contains the block hash, merkle root, previous block hash, nonce, timestamp, version
+/// and difficulty
 /// of a block
 /// This function is used in the block info page
 pub fn build_block_info(block_header: &BlockHeader) -> Box {
@@ -46,6 +43,7 @@ pub fn build_block_info(block_header: &BlockHeader) -> Box {
     let nonce = block_header.nonce.to_string();
     let timestamp = block_header.timestamp.to_string();
     let version = block_header.version.to_string();
+    let difficulty = format!("{:.2}", block_header.difficulty());
 
     let block_hash_label = create_label_with_title("Block Hash", &block_hash);
     let merkle_root_label = create_label_with_title("Merkle Root", &merkle_root);
@@ -54,6 +52,7 @@ pub fn build_block_info(block_header: &BlockHeader) -> Box {
     let nonce_label = create_label_with_title("Nonce", &nonce);
     let timestamp_label = create_label_with_title("Timestamp", &timestamp);
     let version_label = create_label_with_title("Version", &version);
+    let difficulty_label = create_label_with_title("Difficulty", &difficulty);
 
     block_info.add(&block_hash_label);
     block_info.add(&merkle_root_label);
@@ -61,6 +60,7 @@ pub fn build_block_info(block_header: &BlockHeader) -> Box {
     block_info.add(&nonce_label);
     block_info.add(&timestamp_label);
     block_info.add(&version_label);
+    block_info.add(&difficulty_label);
 
     block_info
 }
@@ -70,32 +70,7 @@ pub fn build_block_info(block_header: &BlockHeader) -> Box {
 /// - Ok(Vec<AccountInfo>) if the file was read successfully
 /// - Err(NodeError) if the file could not be read
 pub fn read_saved_wallet_and_accounts_from_file() -> Result<Vec<AccountInfo>, NodeError> {
-    let mut wallets = Vec::new();
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(SAVED_ACCOUNTS)
-        .map_err(|_| NodeError::FailedToOpenFile("Failed to open saved_wallet file".to_string()))?;
-
-    if !file
-        .metadata()
-        .map_err(|_| NodeError::FailedToConvert("Failed to obtain file metadata".to_string()))?
-        .is_file()
-    {
-        return Ok(wallets);
-    }
-
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.map_err(|e| {
-            NodeError::FailedToRead(format!("Failed to read from saved wallets file: {}", e))
-        })?;
-
-        wallets.push(AccountInfo::new_from_string(line))
-    }
-
-    Ok(wallets)
+    AccountInfo::read_all(SAVED_ACCOUNTS)
 }
 /// Function to get an object by name from a builder
 /// # Arguments
@@ -1,5 +1,6 @@
 use std::{
-    io,
+    fs::{self, File},
+    io::{self, Write},
     net::{Ipv4Addr, SocketAddr, TcpStream},
 };
 
@@ -7,6 +8,8 @@ use crate::{constants::LENGTH_IP, node_error::NodeError};
 
 use crate::constants::HEXADECIMAL_DIGITS_BASE;
 
+pub mod semaphore;
+
 pub struct Utils;
 
 impl Utils {
@@ -80,6 +83,27 @@ impl Utils {
         }
     }
 
+    /// Replaces the content of the file at the given path. The content is written to a temporary
+    /// file that is then renamed over the file, so a crash while writing never leaves it partially
+    /// written.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToWrite` if the temporary file could not be written or renamed.
+    pub fn write_atomically(path: &str, content: &[u8]) -> Result<(), NodeError> {
+        let temp_path = format!("{}.tmp", path);
+        let mut file = File::create(&temp_path).map_err(|_| {
+            NodeError::FailedToWrite(format!("Failed to create temporary file {}", temp_path))
+        })?;
+        file.write_all(content)
+            .and_then(|_| file.sync_all())
+            .map_err(|_| {
+                NodeError::FailedToWrite(format!("Failed to write temporary file {}", temp_path))
+            })?;
+        fs::rename(&temp_path, path)
+            .map_err(|_| NodeError::FailedToWrite(format!("Failed to replace file {}", path)))
+    }
+
     /// Converts a byte vector to a hex string.
     pub fn bytes_to_hex(bytes: &[u8]) -> String {
         let hex_chars: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
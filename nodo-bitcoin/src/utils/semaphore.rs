@@ -0,0 +1,107 @@
+use std::sync::{Condvar, Mutex};
+
+use crate::node_error::NodeError;
+
+/// A counting semaphore that bounds how many threads can use a resource at the same time.
+#[derive(Debug)]
+pub struct Semaphore {
+    /// The number of permits that can still be acquired.
+    available_permits: Mutex<usize>,
+    /// Notified every time a permit is released.
+    released: Condvar,
+}
+
+/// A permit acquired from a `Semaphore`, released when it's dropped.
+#[derive(Debug)]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a semaphore with the given number of permits. At least one permit is always available.
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            available_permits: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a permit is available and acquires it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::MutexError` if the mutex of the semaphore is poisoned.
+    pub fn acquire(&self) -> Result<SemaphorePermit<'_>, NodeError> {
+        let mut available_permits = self
+            .available_permits
+            .lock()
+            .map_err(|_| NodeError::MutexError("Semaphore mutex poisoned".to_string()))?;
+        while *available_permits == 0 {
+            available_permits = self
+                .released
+                .wait(available_permits)
+                .map_err(|_| NodeError::MutexError("Semaphore mutex poisoned".to_string()))?;
+        }
+        *available_permits -= 1;
+
+        Ok(SemaphorePermit { semaphore: self })
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut available_permits) = self.semaphore.available_permits.lock() {
+            *available_permits += 1;
+        }
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_semaphore_limits_concurrent_holders() -> Result<(), NodeError> {
+        let threads = 6;
+        let semaphore = Arc::new(Semaphore::new(2));
+        let barrier = Arc::new(Barrier::new(threads));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let semaphore = Arc::clone(&semaphore);
+                let barrier = Arc::clone(&barrier);
+                let in_flight = Arc::clone(&in_flight);
+                let max_in_flight = Arc::clone(&max_in_flight);
+                thread::spawn(move || -> Result<(), NodeError> {
+                    barrier.wait();
+                    let _permit = semaphore.acquire()?;
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    Ok(())
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| NodeError::FailedToJoinThread("Failed to join".to_string()))??;
+        }
+
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
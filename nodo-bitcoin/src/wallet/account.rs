@@ -1,20 +1,43 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
 use super::{
     bitcoin_address::BitcoinAddress, transactions_spent_received::TransactionsSpentAndReceived,
 };
 use crate::{
-    block::retrieve_transactions_from_block,
+    block::{
+        block_hash::InternalHash, current_timestamp, read_block_timestamp, retrieve_block_height,
+        retrieve_transactions_from_block, tx_hash::TxHash,
+    },
     compact_size::CompactSize,
-    constants::{OP_CHECKSIG, OP_DUP, OP_EQUALVERIFY, OP_HASH160, PK_HASH_LENGTH},
+    constants::{
+        ACCOUNT_HISTORY_PREFIX, MESSAGE_SIGNATURE_COMPRESSED_FLAG, MESSAGE_SIGNATURE_HEADER,
+        MESSAGE_SIGNATURE_LENGTH, MESSAGE_SIGNATURE_PREFIX, OP_CHECKSIG, OP_DUP, OP_EQUALVERIFY,
+        OP_HASH160, PK_HASH_LENGTH,
+    },
     node_error::NodeError,
     transactions::{
-        pk_script::PkScript, signature_script::SignatureScript, transaction::Transaction,
-        tx_input::TxInput, tx_output::TxOutput, utxo_set::UtxoSet,
+        outpoint::Outpoint, pk_script::PkScript, signature_script::SignatureScript,
+        transaction::Transaction, tx_input::TxInput, tx_output::TxOutput, utxo_set::UtxoSet,
+    },
+    ui::{
+        components::transactions_confirmed_data::Amount, ui_message::UIMessage,
+        utils::timestamp_to_date,
     },
-    ui::{components::transactions_confirmed_data::Amount, ui_message::UIMessage},
+    utils::Utils,
 };
-use bitcoin_hashes::sha256;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bitcoin_hashes::{hash160, sha256, sha256d, Hash};
 use glib::Sender;
-use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use secp256k1::{
+    ecdsa::{RecoverableSignature, RecoveryId},
+    Message, PublicKey, Secp256k1, SecretKey,
+};
 
 type Wif = String;
 
@@ -25,32 +48,68 @@ pub struct Account {
     pub bitcoin_address: BitcoinAddress,
     /// The private key in WIF format.
     pub private_key: Wif,
-    /// The UTXO set for the account.
+    /// The unspent outputs the account tracks itself, from the merkleblocks (BIP37) the wallet is sent
+    /// in headers-only mode, where the UTXO set of the node doesn't hold them.
     pub utxo_set: UtxoSet,
 
     pub unconfirmed_transactions: TransactionsSpentAndReceived,
 
     pub confirmed_transactions: TransactionsSpentAndReceived,
+    /// The timestamp of the creation of the account. Blocks older than it are not scanned.
+    pub birthday: u32,
+    /// The UTXO set of the node, shared with the account to read its outputs on demand.
+    pub shared_utxo_set: Arc<Mutex<UtxoSet>>,
+    /// The height of the blocks that included the transactions of the account.
+    pub mined_heights: HashMap<TxHash, u32>,
+    /// The height of the last block checked for the transactions of the account.
+    pub tip_height: u32,
+    /// The number of confirmations a transaction needs to be shown as confirmed.
+    pub confirmation_depth: u32,
+    /// The time each unconfirmed transaction was first seen, by its id.
+    pub first_seen: HashMap<TxHash, u32>,
+    /// The net amount each transaction sent by the account spent, its inputs minus the change paid
+    /// back to it, by its id. It's recorded when the transaction is first seen, since its inputs leave
+    /// the UTXO set once it's mined.
+    pub sent_amounts: HashMap<TxHash, f64>,
 }
 
 impl Account {
-    /// Returns an account for the user.
+    /// Returns an account for the user, ignoring the outputs of blocks older than its birthday.
+    ///
+    /// The account reads its outputs from the shared UTXO set of the node instead of copying them, and
+    /// the outputs paying to it are kept in memory so reading them never touches the disk.
+    /// # Arguments
+    /// * `utxo_set_arc` - The UTXO set of the node, inside an Arc Mutex to be shared between threads.
+    /// * `bitcoin_address_string` - The address of the account.
+    /// * `private_key` - The private key in WIF format.
+    /// * `birthday` - The timestamp of the creation of the account.
+    /// * `confirmation_depth` - The number of confirmations a transaction needs to be shown as confirmed.
     pub fn new(
-        utxo_set: &UtxoSet,
+        utxo_set_arc: &Arc<Mutex<UtxoSet>>,
         bitcoin_address_string: String,
         private_key: String,
+        birthday: u32,
+        confirmation_depth: u32,
     ) -> Result<Account, NodeError> {
         let bitcoin_address = BitcoinAddress::from_string(&bitcoin_address_string)?;
-        let users_pk_hash = BitcoinAddress::to_pk_hash(&bitcoin_address);
-
-        let users_utxo_set = utxo_set.users_utxo_set(&users_pk_hash);
+        utxo_set_arc
+            .lock()
+            .map_err(|_| NodeError::UtxoSetMutexError("Failed to lock utxo set".to_string()))?
+            .keep_in_memory(BitcoinAddress::to_pk_hash(&bitcoin_address))?;
 
         let account = Account {
             bitcoin_address,
             private_key,
-            utxo_set: users_utxo_set,
+            utxo_set: UtxoSet::new(),
             unconfirmed_transactions: TransactionsSpentAndReceived::new(),
             confirmed_transactions: TransactionsSpentAndReceived::new(),
+            birthday,
+            shared_utxo_set: Arc::clone(utxo_set_arc),
+            mined_heights: HashMap::new(),
+            tip_height: 0,
+            confirmation_depth,
+            first_seen: HashMap::new(),
+            sent_amounts: HashMap::new(),
         };
 
         Ok(account)
@@ -118,7 +177,7 @@ impl Account {
     pub fn calculate_balance(users_pk_hash: &Vec<u8>, utxo_set: &UtxoSet) -> f64 {
         let mut balance = 0.0;
 
-        for tx_tuple in utxo_set.set.iter() {
+        for tx_tuple in utxo_set.iter() {
             let tx_outputs = tx_tuple.1;
 
             for tx_output in tx_outputs {
@@ -137,12 +196,55 @@ impl Account {
         balance
     }
 
-    /// Returns the balance for the user.
+    /// Returns the balance for the user, or 0 if the UTXO set of the node can't be read.
     pub fn balance_for_user(&self) -> f64 {
-        Self::calculate_balance(
-            &BitcoinAddress::to_pk_hash(&self.bitcoin_address),
-            &self.utxo_set,
-        )
+        self.live_balance().unwrap_or(0.0)
+    }
+
+    /// Returns the balance for the user, reading the shared UTXO set of the node in place instead of
+    /// copying the outputs of the account, plus the outputs the account tracks itself.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::UtxoSetMutexError` if the shared UTXO set can't be locked.
+    pub fn live_balance(&self) -> Result<f64, NodeError> {
+        let users_pk_hash = BitcoinAddress::to_pk_hash(&self.bitcoin_address);
+        let shared_balance = self
+            .shared_utxo_set
+            .lock()
+            .map_err(|_| NodeError::UtxoSetMutexError("Failed to lock utxo set".to_string()))?
+            .balance_for_pk_hash(&users_pk_hash, self.birthday);
+        Ok(shared_balance + Self::calculate_balance(&users_pk_hash, &self.utxo_set))
+    }
+
+    /// Returns the unspent outputs of the account: the ones paying to it in the shared UTXO set of the
+    /// node, from blocks not older than its birthday, and the ones the account tracks itself.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::UtxoSetMutexError` if the shared UTXO set can't be locked.
+    pub fn unspent_outputs(&self) -> Result<UtxoSet, NodeError> {
+        let mut unspent_outputs = self
+            .shared_utxo_set
+            .lock()
+            .map_err(|_| NodeError::UtxoSetMutexError("Failed to lock utxo set".to_string()))?
+            .users_utxo_set(&BitcoinAddress::to_pk_hash(&self.bitcoin_address));
+        unspent_outputs.remove_outputs_before(self.birthday);
+        for (tx_id, tx_outputs) in self.utxo_set.iter() {
+            unspent_outputs.insert(tx_id.clone(), tx_outputs.clone());
+        }
+        Ok(unspent_outputs)
+    }
+
+    /// Returns the unspent output at the outpoint if it pays to the account.
+    pub fn output(&self, outpoint: &Outpoint) -> Option<TxOutput> {
+        let tx_output = match self.utxo_set.output(outpoint) {
+            Some(tx_output) => tx_output,
+            None => self.shared_utxo_set.lock().ok()?.output(outpoint)?,
+        };
+        tx_output
+            .contains_address(&self.bitcoin_address)
+            .then_some(tx_output)
     }
 
     /// Creates a list of unsigned transaction inputs (TxInput) to spend UTXOs from the current wallet.
@@ -165,7 +267,8 @@ impl Account {
         &self,
         amount: &f64,
     ) -> Result<(Vec<TxInput>, f64, Vec<PkScript>), NodeError> {
-        let tx_outs_to_spend = self.utxo_set.search_utxos_to_spend(amount)?;
+        let unspent_outputs = self.unspent_outputs()?;
+        let tx_outs_to_spend = unspent_outputs.search_utxos_to_spend(amount)?;
         let mut txs_inputs = Vec::new();
 
         for tx_out_to_spend in tx_outs_to_spend.iter() {
@@ -201,6 +304,33 @@ impl Account {
         target_address_str: &String,
         amount: f64,
         fee: f64,
+    ) -> Result<(Transaction, Vec<PkScript>), NodeError> {
+        self.create_unsigned_transaction_with_change(
+            target_address_str,
+            amount,
+            fee,
+            &self.bitcoin_address,
+        )
+    }
+
+    /// Creates an unsigned transaction that sends its change to the given address.
+    ///
+    /// # Arguments
+    ///
+    /// * `target_address_str` - The target Bitcoin address as a string.
+    /// * `amount` - The amount of Bitcoin to transfer.
+    /// * `fee` - The fee to pay for the transaction.
+    /// * `change_address` - The address that receives the change.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the unsigned transaction and a vec of PkScripts to be used to sign the transaction.
+    pub fn create_unsigned_transaction_with_change(
+        &self,
+        target_address_str: &String,
+        amount: f64,
+        fee: f64,
+        change_address: &BitcoinAddress,
     ) -> Result<(Transaction, Vec<PkScript>), NodeError> {
         if self.balance_for_user() < amount {
             return Err(NodeError::NotEnoughCoins(
@@ -211,7 +341,7 @@ impl Account {
         let (txs_inputs, value_spent, pk_scripts) = self.create_unsigned_inputs(&amount)?;
 
         let change = value_spent - amount;
-        let change_script = BitcoinAddress::to_pk_script(&self.bitcoin_address);
+        let change_script = BitcoinAddress::to_pk_script(change_address);
         let change_tx_out = TxOutput::new(change, change_script, 0);
 
         let target_address = BitcoinAddress::from_string(target_address_str)?;
@@ -242,11 +372,6 @@ impl Account {
         transaction: &mut Transaction,
         pk_scripts: Vec<PkScript>,
     ) -> Result<Vec<SignatureScript>, NodeError> {
-        let secp = Secp256k1::new();
-        let secret_key = SecretKey::from_slice(&self.wif_to_private_key()?).map_err(|_| {
-            NodeError::SigningError("Failed to parse private key into secret key".to_string())
-        })?;
-
         let mut script_sigs = Vec::new();
 
         for (i, pk_script) in pk_scripts
@@ -254,29 +379,58 @@ impl Account {
             .enumerate()
             .take(transaction.tx_inputs.len())
         {
-            let message = Message::from_hashed_data::<sha256::Hash>(
-                &transaction.individual_signature_hash(i, pk_script.clone()),
-            );
+            script_sigs.push(self.create_script_sig(transaction, i, pk_script)?);
+        }
+
+        Ok(script_sigs)
+    }
 
-            let mut signature_bytes = secp
-                .sign_ecdsa(&message, &secret_key)
-                .serialize_der()
-                .to_vec();
-            signature_bytes.push(1_u8);
+    /// Creates the signature script of a single input of a transaction with the key of this account.
+    ///
+    /// # Arguments
+    ///
+    /// * `transaction` - A mutable reference to the transaction that contains the input.
+    /// * `input_index` - The index of the input to sign.
+    /// * `pk_script` - The public key script of the output spent by the input.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the signature script of the input.
+    ///
+    /// # Errors
+    ///
+    /// Returns a NodeError if there are any issues with signing the transaction or parsing the private key
+    pub fn create_script_sig(
+        &self,
+        transaction: &mut Transaction,
+        input_index: usize,
+        pk_script: &PkScript,
+    ) -> Result<SignatureScript, NodeError> {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&self.wif_to_private_key()?).map_err(|_| {
+            NodeError::SigningError("Failed to parse private key into secret key".to_string())
+        })?;
 
-            let sec_public_key = PublicKey::from_secret_key(&secp, &secret_key)
-                .serialize()
-                .to_vec();
+        let message = Message::from_hashed_data::<sha256::Hash>(
+            &transaction.individual_signature_hash(input_index, pk_script.clone()),
+        );
 
-            let mut script_sig = CompactSize::new(signature_bytes.len()).to_bytes();
-            script_sig.extend(signature_bytes);
-            script_sig.extend(CompactSize::new(sec_public_key.len()).to_bytes());
-            script_sig.extend(sec_public_key);
+        let mut signature_bytes = secp
+            .sign_ecdsa(&message, &secret_key)
+            .serialize_der()
+            .to_vec();
+        signature_bytes.push(1_u8);
 
-            script_sigs.push(script_sig);
-        }
+        let sec_public_key = PublicKey::from_secret_key(&secp, &secret_key)
+            .serialize()
+            .to_vec();
 
-        Ok(script_sigs)
+        let mut script_sig = CompactSize::new(signature_bytes.len()).to_bytes();
+        script_sig.extend(signature_bytes);
+        script_sig.extend(CompactSize::new(sec_public_key.len()).to_bytes());
+        script_sig.extend(sec_public_key);
+
+        Ok(script_sig)
     }
 
     /// Sign the given transaction with the provided private key.
@@ -306,6 +460,104 @@ impl Account {
         Ok(())
     }
 
+    /// Signs an arbitrary message with the private key of the account, proving the ownership of its
+    /// address (BIP137).
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to sign.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the base64 encoded signature: a header byte with the recovery id,
+    /// followed by the compact signature over the Bitcoin-prefixed message hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns a NodeError if the private key could not be parsed.
+    pub fn sign_message(&self, message: &str) -> Result<String, NodeError> {
+        let secp = Secp256k1::new();
+        let private_key = self.wif_to_private_key()?;
+        let secret_key = private_key
+            .get(..32)
+            .and_then(|private_key| SecretKey::from_slice(private_key).ok())
+            .ok_or_else(|| {
+                NodeError::SigningError("Failed to parse private key into secret key".to_string())
+            })?;
+        let public_key = PublicKey::from_secret_key(&secp, &secret_key);
+        let pk_hash = BitcoinAddress::to_pk_hash(&self.bitcoin_address);
+        let compressed = hash160::Hash::hash(&public_key.serialize())[..] == pk_hash[..];
+
+        let (recovery_id, signature) = secp
+            .sign_ecdsa_recoverable(&signed_message_hash(message), &secret_key)
+            .serialize_compact();
+        let mut header = MESSAGE_SIGNATURE_HEADER + recovery_id.to_i32() as u8;
+        if compressed {
+            header += MESSAGE_SIGNATURE_COMPRESSED_FLAG;
+        }
+
+        let mut signature_bytes = vec![header];
+        signature_bytes.extend(signature);
+        Ok(STANDARD.encode(signature_bytes))
+    }
+
+    /// Verifies a message signature created by `sign_message`, or by any wallet following BIP137
+    /// for a P2PKH address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address that supposedly signed the message.
+    /// * `message` - The signed message.
+    /// * `signature` - The base64 encoded signature.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing true if the signature was made with the key of the address.
+    ///
+    /// # Errors
+    ///
+    /// Returns a NodeError if the address is invalid or the signature is malformed.
+    pub fn verify_message(
+        address: &str,
+        message: &str,
+        signature: &str,
+    ) -> Result<bool, NodeError> {
+        let bitcoin_address = BitcoinAddress::from_string(&address.to_string())?;
+        let signature_bytes = STANDARD
+            .decode(signature)
+            .map_err(|_| NodeError::FailedToParse("The signature is not base64".to_string()))?;
+        if signature_bytes.len() != MESSAGE_SIGNATURE_LENGTH {
+            return Err(NodeError::FailedToParse(
+                "The signature must be 65 bytes long".to_string(),
+            ));
+        }
+
+        let header = signature_bytes[0]
+            .checked_sub(MESSAGE_SIGNATURE_HEADER)
+            .filter(|header| *header < 2 * MESSAGE_SIGNATURE_COMPRESSED_FLAG)
+            .ok_or_else(|| NodeError::FailedToParse("Invalid signature header byte".to_string()))?;
+        let compressed = header >= MESSAGE_SIGNATURE_COMPRESSED_FLAG;
+        let recovery_id = RecoveryId::from_i32((header % MESSAGE_SIGNATURE_COMPRESSED_FLAG) as i32)
+            .map_err(|_| NodeError::FailedToParse("Invalid recovery id".to_string()))?;
+        let signature = RecoverableSignature::from_compact(&signature_bytes[1..], recovery_id)
+            .map_err(|_| NodeError::FailedToParse("Invalid signature".to_string()))?;
+
+        let public_key =
+            match Secp256k1::new().recover_ecdsa(&signed_message_hash(message), &signature) {
+                Ok(public_key) => public_key,
+                Err(_) => return Ok(false),
+            };
+        let public_key_bytes = if compressed {
+            public_key.serialize().to_vec()
+        } else {
+            public_key.serialize_uncompressed().to_vec()
+        };
+
+        Ok(!bitcoin_address.is_p2sh()
+            && hash160::Hash::hash(&public_key_bytes)[..]
+                == BitcoinAddress::to_pk_hash(&bitcoin_address)[..])
+    }
+
     /// Creates a valid transaction, ready to be broadcasted to the bitcoin testnet.
     ///
     /// # Arguments
@@ -327,19 +579,104 @@ impl Account {
         amount: f64,
         fee: f64,
     ) -> Result<Transaction, NodeError> {
-        let (mut transaction, pk_scripts) =
-            self.create_unsigned_transaction(target_address_str, amount, fee)?;
+        self.create_transaction_with_change(target_address_str, amount, fee, &self.bitcoin_address)
+    }
+
+    /// Creates a valid transaction that sends its change to the given address.
+    ///
+    /// # Arguments
+    ///
+    /// * `target_address_str` - A reference to a string containing the target address to send the coins.
+    /// * `amount` - The amount of coins to send to the target address.
+    /// * `fee` - The transaction fee to be included in the transaction.
+    /// * `change_address` - The address that receives the change.
+    ///
+    /// # Errors
+    ///
+    /// Returns a NodeError if there are any issues with creating the unsigned transaction or signing the transaction.
+    pub fn create_transaction_with_change(
+        &self,
+        target_address_str: &String,
+        amount: f64,
+        fee: f64,
+        change_address: &BitcoinAddress,
+    ) -> Result<Transaction, NodeError> {
+        let (mut transaction, pk_scripts) = self.create_unsigned_transaction_with_change(
+            target_address_str,
+            amount,
+            fee,
+            change_address,
+        )?;
         self.sign_transaction(&mut transaction, pk_scripts)?;
 
         Ok(transaction)
     }
 
-    /// Updates the UTXO set for this account.
+    /// Updates the UTXO set for this account. Blocks older than the birthday of the account are skipped.
     pub fn update_utxo(&mut self, block_path: &String) -> Result<(), NodeError> {
+        if read_block_timestamp(block_path)? < self.birthday {
+            return Ok(());
+        }
         self.utxo_set
             .update_for_account(block_path, &self.bitcoin_address)
     }
 
+    /// Updates the UTXO set for this account with the given transactions of a block, such as the ones
+    /// proven by a merkleblock. Blocks older than the birthday of the account are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `block_path` - The path of the block, recorded in the outputs of the transactions.
+    /// * `timestamp` - The timestamp of the block.
+    /// * `transactions` - The transactions of the block.
+    pub fn update_utxo_with(
+        &mut self,
+        block_path: &str,
+        timestamp: u32,
+        transactions: Vec<Transaction>,
+    ) {
+        if timestamp < self.birthday {
+            return;
+        }
+        self.utxo_set
+            .update_for_account_with(block_path, transactions, &self.bitcoin_address);
+    }
+
+    /// Returns the elements the bloom filter (BIP37) of the account holds: its public key hash, so
+    /// peers match the transactions paying it, and the outpoints of its unspent outputs, so they
+    /// match the transactions spending them.
+    pub fn bloom_filter_elements(&self) -> Result<Vec<Vec<u8>>, NodeError> {
+        let mut elements = vec![BitcoinAddress::to_pk_hash(&self.bitcoin_address)];
+        for (outpoint, _, _) in self.list_utxos()? {
+            elements.push(outpoint.to_bytes());
+        }
+        Ok(elements)
+    }
+
+    /// Lists the unspent outputs of the account, sorted by the block they come from.
+    ///
+    /// # Returns
+    ///
+    /// A vector with the outpoint, the value in BTC and the path of the originating block of each unspent output.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::UtxoSetMutexError` if the shared UTXO set can't be locked.
+    pub fn list_utxos(&self) -> Result<Vec<(Outpoint, f64, String)>, NodeError> {
+        let mut utxos = Vec::new();
+        for (tx_id, tx_outputs) in self.unspent_outputs()?.iter() {
+            for tx_output in tx_outputs {
+                let outpoint = Outpoint {
+                    tx_id: tx_id.clone(),
+                    index: tx_output.index as u32,
+                };
+                utxos.push((outpoint, tx_output.value(), tx_output.block_path.clone()));
+            }
+        }
+        utxos.sort_by(|a, b| (&a.2, &a.0.tx_id, a.0.index).cmp(&(&b.2, &b.0.tx_id, b.0.index)));
+        Ok(utxos)
+    }
+
     /// Implements the Copy trait for Account.
     pub fn copy(&self) -> Account {
         Account {
@@ -348,15 +685,29 @@ impl Account {
             utxo_set: self.utxo_set.clone(),
             unconfirmed_transactions: self.unconfirmed_transactions.clone(),
             confirmed_transactions: self.confirmed_transactions.clone(),
+            birthday: self.birthday,
+            shared_utxo_set: self.shared_utxo_set.clone(),
+            mined_heights: self.mined_heights.clone(),
+            tip_height: self.tip_height,
+            confirmation_depth: self.confirmation_depth,
+            first_seen: self.first_seen.clone(),
+            sent_amounts: self.sent_amounts.clone(),
         }
     }
 
-    /// Adds a new unconfirmed transaction to the account.
+    /// Adds a new unconfirmed transaction to the account, first seen now. The net amount a transaction
+    /// spending outputs of the account sends is recorded while its inputs are still unspent.
     /// # Arguments
     /// * `transaction` - The transaction to add.
     pub fn add_new_unconfirmed_transaction(&mut self, transaction: Transaction) {
+        if let Ok(now) = current_timestamp() {
+            self.first_seen.entry(transaction.tx_id()).or_insert(now);
+        }
         for tx_input in transaction.tx_inputs.iter() {
-            if self.utxo_set.contains_key(&tx_input.previous_output.tx_id) {
+            if self.output(&tx_input.previous_output).is_some() {
+                let sent_amount = transaction.amount_spent_by_account(self)
+                    - transaction.value_received_by_address(&self.bitcoin_address);
+                self.sent_amounts.insert(transaction.tx_id(), sent_amount);
                 self.unconfirmed_transactions.add_spent(transaction);
                 return;
             }
@@ -364,8 +715,83 @@ impl Account {
         self.unconfirmed_transactions.add_received(transaction);
     }
 
+    /// Removes the unconfirmed transactions that were first seen more than `max_age_secs` ago and are
+    /// not in a block yet, as they were most likely dropped from the mempools, and notifies the UI
+    /// with a `UIMessage::TransactionExpired` for each of them.
+    ///
+    /// Transactions without a first seen time start aging now.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time, as a Unix timestamp.
+    /// * `max_age_secs` - The time an unconfirmed transaction is kept for.
+    /// * `ui_sender` - The sender channel to communicate with the UI.
+    ///
+    /// # Returns
+    ///
+    /// The ids of the removed transactions.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToSendMessage` if the UI could not be notified.
+    pub fn prune_stale_unconfirmed(
+        &mut self,
+        now: u32,
+        max_age_secs: u32,
+        ui_sender: &Sender<UIMessage>,
+    ) -> Result<Vec<TxHash>, NodeError> {
+        let mut expired = Vec::new();
+        for transaction in self.unconfirmed_transactions.all_txs() {
+            let tx_id = transaction.tx_id();
+            if self.mined_heights.contains_key(&tx_id) {
+                continue;
+            }
+            let first_seen = *self.first_seen.entry(tx_id.clone()).or_insert(now);
+            if now.saturating_sub(first_seen) <= max_age_secs {
+                continue;
+            }
+            if !self.unconfirmed_transactions.remove_spent(&transaction) {
+                self.unconfirmed_transactions.remove_received(&transaction);
+            }
+            self.first_seen.remove(&tx_id);
+            self.sent_amounts.remove(&tx_id);
+            ui_sender
+                .send(UIMessage::TransactionExpired(tx_id.clone()))
+                .map_err(|_| {
+                    NodeError::FailedToSendMessage(
+                        "Error sending expired tx message to UI".to_string(),
+                    )
+                })?;
+            expired.push(tx_id);
+        }
+        Ok(expired)
+    }
+
+    /// Returns the number of confirmations of a transaction of the account: the number of blocks from the one
+    /// that included it to the last checked block, both included. It's 0 if the transaction is not in a block yet.
+    pub fn confirmations(&self, tx_id: &TxHash) -> u32 {
+        match self.mined_heights.get(tx_id) {
+            Some(mined_height) => self.tip_height.saturating_sub(*mined_height) + 1,
+            None => 0,
+        }
+    }
+
+    /// Returns the status of a transaction of the account to show in the UI: "confirmed" once it has the
+    /// configured number of confirmations, or "pending (k/N)" with its confirmations until then.
+    pub fn confirmation_status(&self, tx_id: &TxHash) -> String {
+        let confirmations = self.confirmations(tx_id);
+        if confirmations >= self.confirmation_depth {
+            return "confirmed".to_string();
+        }
+        format!("pending ({}/{})", confirmations, self.confirmation_depth)
+    }
+
     /// Confirms transactions that where previously unconfirmed, because they appeared in a new block, updating the Node's state and notifying the UI.
     ///
+    /// Transactions are confirmed once they are buried under the configured confirmation depth, counting the block
+    /// that included them. The height of each block is read from its coinbase, or assumed to follow the previous
+    /// one if the coinbase doesn't have it.
+    ///
     /// # Arguments
     ///
     /// * `self` - A mutable reference to the Node object.
@@ -384,8 +810,37 @@ impl Account {
         ui_sender: &Sender<UIMessage>,
     ) -> Result<TransactionsSpentAndReceived, NodeError> {
         let transactions = retrieve_transactions_from_block(path)?;
+        let height = retrieve_block_height(path);
+        self.confirm_block_transactions(path, height, transactions, ui_sender)
+    }
+
+    /// Confirms the transactions of the account among the given transactions of a block, as
+    /// `confirm_transactions` does with the ones read from the block file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the block the transactions belong to.
+    /// * `height` - The height of the block, or None if it's unknown and it's assumed to follow the
+    ///   previous one.
+    /// * `transactions` - The transactions of the block, or the ones a merkleblock proves.
+    /// * `ui_sender` - The sender channel to communicate with the UI.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if sending a message to the UI fails.
+    pub fn confirm_block_transactions(
+        &mut self,
+        path: &str,
+        height: Option<u32>,
+        mut transactions: Vec<Transaction>,
+        ui_sender: &Sender<UIMessage>,
+    ) -> Result<TransactionsSpentAndReceived, NodeError> {
+        for transaction in &mut transactions {
+            transaction.add_block_path_to_tx_outs(path);
+        }
         let mut confirmed_tx_to_ui = TransactionsSpentAndReceived::new();
 
+        self.tip_height = height.unwrap_or(self.tip_height + 1);
         self.update_transactions_if_confirmed(transactions, &mut confirmed_tx_to_ui);
 
         confirmed_tx_to_ui.send_confirmations_to_ui(ui_sender)?;
@@ -394,12 +849,30 @@ impl Account {
     }
 
     /// Updates the vector of unconfirmed transactions, removing those that have been confirmed.
+    /// The transactions of the account included in the block are recorded at the height of the tip, and replaced
+    /// by their copy from the block, which knows the path of the block.
     fn update_transactions_if_confirmed(
         &mut self,
         transactions: Vec<Transaction>,
         confirmed_tx_to_ui: &mut TransactionsSpentAndReceived,
     ) {
         for transaction in transactions {
+            if self.unconfirmed_transactions.remove_spent(&transaction) {
+                self.mined_heights
+                    .insert(transaction.tx_id(), self.tip_height);
+                self.unconfirmed_transactions.add_spent(transaction);
+            } else if self.unconfirmed_transactions.remove_received(&transaction) {
+                self.mined_heights
+                    .insert(transaction.tx_id(), self.tip_height);
+                self.unconfirmed_transactions.add_received(transaction);
+            }
+        }
+
+        for transaction in self.unconfirmed_transactions.all_txs() {
+            if self.confirmations(&transaction.tx_id()) < self.confirmation_depth {
+                continue;
+            }
+            self.first_seen.remove(&transaction.tx_id());
             if self.unconfirmed_transactions.remove_spent(&transaction) {
                 self.confirmed_transactions.add_spent(transaction.clone());
                 confirmed_tx_to_ui.add_spent(transaction);
@@ -411,6 +884,229 @@ impl Account {
         }
     }
 
+    /// Returns the timestamps of the first and the last block with a confirmed transaction of the
+    /// account, read from the headers of the blocks the transactions were confirmed in.
+    ///
+    /// # Returns
+    ///
+    /// An Option with the (first seen, last activity) timestamps, or None if the account has no
+    /// confirmed transactions.
+    pub fn activity_range(&self) -> Option<(u32, u32)> {
+        let block_paths: HashSet<&String> = self
+            .confirmed_transactions
+            .spent
+            .iter()
+            .chain(self.confirmed_transactions.received.iter())
+            .filter_map(|transaction| transaction.tx_outputs.first())
+            .map(|tx_output| &tx_output.block_path)
+            .filter(|block_path| !block_path.is_empty())
+            .collect();
+
+        let timestamps: Vec<u32> = block_paths
+            .into_iter()
+            .filter_map(|block_path| read_block_timestamp(block_path).ok())
+            .collect();
+        Some((*timestamps.iter().min()?, *timestamps.iter().max()?))
+    }
+
+    /// Exports the confirmed transactions of the account as CSV, to be opened in a spreadsheet for
+    /// tax or accounting, oldest first.
+    ///
+    /// Each row has the date of the block that confirmed the transaction, its id, whether it was
+    /// `sent` or `received`, the amount in BTC and the hash of the block. The amount of a sent
+    /// transaction is the net amount recorded when it was first seen, without the change. The date is
+    /// left empty if the block can't be read.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the id of a transaction isn't a valid hash.
+    pub fn export_history_csv(&self) -> Result<String, NodeError> {
+        let mut entries = Vec::new();
+        for transaction in &self.confirmed_transactions.spent {
+            let amount = self
+                .sent_amounts
+                .get(&transaction.tx_id())
+                .copied()
+                .unwrap_or_default()
+                .to_string();
+            entries.push((transaction, "sent", amount));
+        }
+        for transaction in &self.confirmed_transactions.received {
+            let amount = transaction.amount_received_by_address(&self.bitcoin_address);
+            entries.push((transaction, "received", amount));
+        }
+
+        let mut rows = entries
+            .into_iter()
+            .map(|(transaction, kind, amount)| {
+                let block_path = transaction
+                    .tx_outputs
+                    .first()
+                    .map(|tx_output| tx_output.block_path.clone())
+                    .unwrap_or_default();
+                let timestamp = read_block_timestamp(&block_path).ok();
+                let date = timestamp
+                    .and_then(|timestamp| timestamp_to_date(timestamp).ok())
+                    .unwrap_or_default();
+                let block_hash = Path::new(&block_path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let tx_id = InternalHash::from_slice(&transaction.tx_id())?.to_display();
+                let row = format!("{},{},{},{},{}", date, tx_id, kind, amount, block_hash);
+                Ok((timestamp.unwrap_or(0), row))
+            })
+            .collect::<Result<Vec<(u32, String)>, NodeError>>()?;
+        rows.sort();
+
+        let mut csv = String::from("date,txid,type,amount_btc,block\n");
+        for (_, row) in rows {
+            csv.push_str(&row);
+            csv.push('\n');
+        }
+        Ok(csv)
+    }
+
+    /// Returns the path of the file the history of the account is saved to.
+    pub fn history_path(&self) -> String {
+        format!(
+            "{}{}.txt",
+            ACCOUNT_HISTORY_PREFIX,
+            self.bitcoin_address.bs58_to_string()
+        )
+    }
+
+    /// Saves the history of the account to the file at the given path, replacing its content: its
+    /// transactions, the heights they were mined at, when the unconfirmed ones were first seen and
+    /// the net amounts the sent ones spent, which can't be computed again once their inputs are spent.
+    ///
+    /// Each line is a record with `;` separated fields, the transactions and their ids in hex:
+    /// `tip;<height>`, `tx;<confirmed|unconfirmed>;<spent|received>;<transaction>;<block path>`,
+    /// `mined;<tx id>;<height>`, `seen;<tx id>;<timestamp>` or `sent;<tx id>;<amount>`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToWrite` if the file could not be written.
+    pub fn save_history(&self, path: &str) -> Result<(), NodeError> {
+        let mut records = vec![format!("tip;{}", self.tip_height)];
+        let transactions = [
+            ("confirmed", "spent", &self.confirmed_transactions.spent),
+            (
+                "confirmed",
+                "received",
+                &self.confirmed_transactions.received,
+            ),
+            ("unconfirmed", "spent", &self.unconfirmed_transactions.spent),
+            (
+                "unconfirmed",
+                "received",
+                &self.unconfirmed_transactions.received,
+            ),
+        ];
+        for (status, kind, transactions) in transactions {
+            for transaction in transactions {
+                let block_path = transaction
+                    .tx_outputs
+                    .first()
+                    .map(|tx_output| tx_output.block_path.as_str())
+                    .unwrap_or_default();
+                records.push(format!(
+                    "tx;{};{};{};{}",
+                    status,
+                    kind,
+                    Utils::bytes_to_hex(&transaction.to_bytes()),
+                    block_path
+                ));
+            }
+        }
+        for (tx_id, height) in &self.mined_heights {
+            records.push(format!("mined;{};{}", Utils::bytes_to_hex(tx_id), height));
+        }
+        for (tx_id, timestamp) in &self.first_seen {
+            records.push(format!("seen;{};{}", Utils::bytes_to_hex(tx_id), timestamp));
+        }
+        for (tx_id, amount) in &self.sent_amounts {
+            records.push(format!("sent;{};{}", Utils::bytes_to_hex(tx_id), amount));
+        }
+
+        let content: String = records
+            .iter()
+            .map(|record| format!("{}\n", record))
+            .collect();
+        Utils::write_atomically(path, content.as_bytes())
+    }
+
+    /// Loads the history of the account saved by `save_history` to the file at the given path. An
+    /// account without a saved history is left as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToRead` if the file could not be read or has an invalid record.
+    pub fn load_history(&mut self, path: &str) -> Result<(), NodeError> {
+        if !Path::new(path).exists() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead(format!("Failed to read history {}", path)))?;
+        for record in content.lines().filter(|record| !record.is_empty()) {
+            self.load_history_record(record).map_err(|e| {
+                NodeError::FailedToRead(format!("Invalid history record {}: {:?}", record, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Loads a record of the history saved by `save_history`.
+    fn load_history_record(&mut self, record: &str) -> Result<(), NodeError> {
+        let fields: Vec<&str> = record.splitn(5, ';').collect();
+        match fields.as_slice() {
+            ["tip", height] => self.tip_height = parse_history_field(height)?,
+            ["tx", status, kind, transaction, block_path] => {
+                let bytes = Utils::hex_string_to_bytes(transaction.to_string())?;
+                let mut transaction = Transaction::read_transaction(&mut bytes.as_slice())?;
+                transaction.add_block_path_to_tx_outs(block_path);
+                match (*status, *kind) {
+                    ("confirmed", "spent") => self.confirmed_transactions.add_spent(transaction),
+                    ("confirmed", "received") => {
+                        self.confirmed_transactions.add_received(transaction)
+                    }
+                    ("unconfirmed", "spent") => {
+                        self.unconfirmed_transactions.add_spent(transaction)
+                    }
+                    ("unconfirmed", "received") => {
+                        self.unconfirmed_transactions.add_received(transaction)
+                    }
+                    _ => {
+                        return Err(NodeError::FailedToConvert(
+                            "Unknown kind of transaction".to_string(),
+                        ))
+                    }
+                }
+            }
+            ["mined", tx_id, height] => {
+                let tx_id = Utils::hex_string_to_bytes(tx_id.to_string())?;
+                self.mined_heights
+                    .insert(tx_id, parse_history_field(height)?);
+            }
+            ["seen", tx_id, timestamp] => {
+                let tx_id = Utils::hex_string_to_bytes(tx_id.to_string())?;
+                self.first_seen
+                    .insert(tx_id, parse_history_field(timestamp)?);
+            }
+            ["sent", tx_id, amount] => {
+                let tx_id = Utils::hex_string_to_bytes(tx_id.to_string())?;
+                self.sent_amounts
+                    .insert(tx_id, parse_history_field(amount)?);
+            }
+            _ => {
+                return Err(NodeError::FailedToConvert(
+                    "Unknown history record".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
     /// # Returns
     /// The amount of coins that have been spent but not confirmed yet.
     pub fn unconfirmed_spent_balance(&mut self) -> Amount {
@@ -421,15 +1117,38 @@ impl Account {
     /// # Returns
     /// The amount of coins that have been received but not confirmed yet.
     pub fn unconfirmed_received_balance(&self) -> Amount {
-        let users_pk_hash = &BitcoinAddress::to_pk_hash(&self.bitcoin_address);
         self.unconfirmed_transactions
-            .received_balance(users_pk_hash)
+            .received_balance(&self.bitcoin_address)
     }
 }
 
+/// Parses a field of a record of the history of an account.
+fn parse_history_field<T: FromStr>(field: &str) -> Result<T, NodeError> {
+    field
+        .parse()
+        .map_err(|_| NodeError::FailedToConvert(format!("Invalid history field {}", field)))
+}
+
+/// Returns the hash that is signed for a message: the double SHA-256 of the message, prefixed with
+/// "Bitcoin Signed Message:\n" so it can't be a valid transaction.
+fn signed_message_hash(message: &str) -> Message {
+    let mut bytes = CompactSize::new(MESSAGE_SIGNATURE_PREFIX.len()).to_bytes();
+    bytes.extend(MESSAGE_SIGNATURE_PREFIX.as_bytes());
+    bytes.extend(CompactSize::new(message.len()).to_bytes());
+    bytes.extend(message.as_bytes());
+    Message::from_hashed_data::<sha256d::Hash>(&bytes)
+}
+
 #[cfg(test)]
 mod test {
-    use crate::{node_error::NodeError, utils::Utils};
+    use crate::{
+        block::block_hash::DisplayHash,
+        constants::{DEFAULT_CONFIRMATION_DEPTH, SIGHASH_ALL},
+        node_error::NodeError,
+        transactions::signature_script::SignatureScriptExt,
+        utils::Utils,
+    };
+    use secp256k1::ecdsa::Signature;
 
     use super::*;
 
@@ -480,9 +1199,11 @@ mod test {
             )
             .unwrap();
         let account = Account::new(
-            &utxo_set,
+            &Arc::new(Mutex::new(utxo_set)),
             String::from("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"),
             String::from("92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
         )
         .unwrap();
 
@@ -515,9 +1236,11 @@ mod test {
             )
             .unwrap();
         let account = Account::new(
-            &utxo_set,
+            &Arc::new(Mutex::new(utxo_set)),
             String::from("mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz"),
             String::from("9319Nrhiz9UD4EgeW3n18YpRTbcjTYkvS57b3WxX96P24bGFxHv"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
         )
         .unwrap();
 
@@ -559,9 +1282,11 @@ mod test {
             .unwrap();
 
         let account = Account::new(
-            &utxo_set,
+            &Arc::new(Mutex::new(utxo_set)),
             String::from("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"),
             String::from("92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
         )
         .unwrap();
 
@@ -621,9 +1346,11 @@ mod test {
             .unwrap();
 
         let account = Account::new(
-            &utxo_set,
+            &Arc::new(Mutex::new(utxo_set)),
             String::from("mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz"),
             String::from("9319Nrhiz9UD4EgeW3n18YpRTbcjTYkvS57b3WxX96P24bGFxHv"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
         )
         .unwrap();
 
@@ -643,4 +1370,439 @@ mod test {
             Utils::bytes_to_hex(&tx.to_bytes())
         );
     }
+
+    #[test]
+    fn test_script_sigs_hold_the_signature_and_public_key_of_the_account() -> Result<(), NodeError>
+    {
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.update(
+            &"blocks-test/0000000000000005847b65f037ec3d08f499c3c22ae6723ffefee1adca3e9af5.bin"
+                .to_string(),
+        )?;
+        let account = Account::new(
+            &Arc::new(Mutex::new(utxo_set)),
+            String::from("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"),
+            String::from("92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        let (mut tx, pk_scripts) = account.create_unsigned_transaction(
+            &String::from("mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB"),
+            0.01,
+            0.005,
+        )?;
+
+        let script_sigs = account.create_script_sigs(&mut tx, pk_scripts.clone())?;
+
+        let secp = Secp256k1::verification_only();
+        for (i, script_sig) in script_sigs.iter().enumerate() {
+            let (der_signature, sighash_type) = script_sig.extract_signature()?;
+            let pubkey = script_sig.extract_pubkey()?;
+            let signature = Signature::from_der(&der_signature)
+                .map_err(|_| NodeError::SigningError("Invalid DER signature".to_string()))?;
+            let public_key = PublicKey::from_slice(&pubkey)
+                .map_err(|_| NodeError::SigningError("Invalid public key".to_string()))?;
+            let message = Message::from_hashed_data::<sha256::Hash>(
+                &tx.individual_signature_hash(i, pk_scripts[i].clone()),
+            );
+
+            assert_eq!(sighash_type as u32, SIGHASH_ALL);
+            assert_eq!(
+                hash160::Hash::hash(&pubkey).to_byte_array().to_vec(),
+                BitcoinAddress::to_pk_hash(&account.bitcoin_address())
+            );
+            assert!(secp.verify_ecdsa(&message, &signature, &public_key).is_ok());
+        }
+        assert!(vec![OP_DUP].extract_pubkey().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_utxos_after_applying_block() -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin"
+                .to_string();
+        let mut account = Account::new(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            String::from("mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"),
+            String::from("cRJzHMCgDLsvttTH8R8t6LLcZgMDs1WtgwQXxk8bFFk7E2AJp1tw"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        assert!(account.list_utxos()?.is_empty());
+
+        account.update_utxo(&block_path)?;
+        let utxos = account.list_utxos()?;
+
+        let expected_tx_id = DisplayHash::from_hex(
+            "906f8b36d88a6c827e9a5c63a5f01ed9a3ed7ec1a03108cd35efc0d277f00861",
+        )?
+        .to_internal()
+        .to_vec();
+
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].0.tx_id, expected_tx_id);
+        assert_eq!(utxos[0].0.index, 3);
+        assert_eq!(utxos[0].1, 0.02432823);
+        assert_eq!(utxos[0].2, block_path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_birthday_after_block_skips_it_during_rescan() -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin"
+                .to_string();
+        let block_timestamp = read_block_timestamp(&block_path)?;
+
+        let mut account = Account::new(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            String::from("mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"),
+            String::from("cRJzHMCgDLsvttTH8R8t6LLcZgMDs1WtgwQXxk8bFFk7E2AJp1tw"),
+            block_timestamp + 1,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        account.update_utxo(&block_path)?;
+        assert!(account.list_utxos()?.is_empty());
+
+        account.birthday = block_timestamp;
+        account.update_utxo(&block_path)?;
+        assert_eq!(account.list_utxos()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_birthday_after_block_ignores_its_utxos() -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin"
+                .to_string();
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.update(&block_path)?;
+        let block_timestamp = read_block_timestamp(&block_path)?;
+
+        let account = Account::new(
+            &Arc::new(Mutex::new(utxo_set)),
+            String::from("mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"),
+            String::from("cRJzHMCgDLsvttTH8R8t6LLcZgMDs1WtgwQXxk8bFFk7E2AJp1tw"),
+            block_timestamp + 1,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+
+        assert_eq!(account.balance_for_user(), 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transactions_are_confirmed_at_the_confirmation_depth() -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/000000000000000c5d6cc58f545057a781c46c100a0f2ea5f8f6a31c1b44c784.bin"
+                .to_string();
+        let transaction = retrieve_transactions_from_block(&block_path)?[1].clone();
+        let tx_id = transaction.tx_id();
+        let mut account = Account::new(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            String::from("mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"),
+            String::from("cRJzHMCgDLsvttTH8R8t6LLcZgMDs1WtgwQXxk8bFFk7E2AJp1tw"),
+            0,
+            6,
+        )?;
+        account.unconfirmed_transactions.add_received(transaction);
+        let (ui_sender, _ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+        assert_eq!(account.confirmation_status(&tx_id), "pending (0/6)");
+
+        account.confirm_transactions(&block_path, &ui_sender)?;
+        assert_eq!(account.confirmations(&tx_id), 1);
+        assert_eq!(account.confirmation_status(&tx_id), "pending (1/6)");
+
+        account.confirm_transactions(
+            &"blocks-test/0000000000000008771c98eab6cbcea3c63138d3715e67d244b52dd183053f80.bin"
+                .to_string(),
+            &ui_sender,
+        )?;
+        assert_eq!(account.confirmation_status(&tx_id), "pending (3/6)");
+        assert!(account.confirmed_transactions.received.is_empty());
+
+        let confirmed = account.confirm_transactions(
+            &"blocks-test/000000000000001fe07dd7d936489026a1dc1906ba797f0cac12b645367c9952.bin"
+                .to_string(),
+            &ui_sender,
+        )?;
+        assert_eq!(account.confirmations(&tx_id), 6);
+        assert_eq!(account.confirmation_status(&tx_id), "confirmed");
+        assert_eq!(confirmed.received.len(), 1);
+        assert!(account.unconfirmed_transactions.received.is_empty());
+        assert_eq!(account.confirmed_transactions.received[0].tx_id(), tx_id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_unconfirmed_transactions_are_pruned() -> Result<(), NodeError> {
+        let transactions = retrieve_transactions_from_block(
+            &"blocks-test/000000000000000c5d6cc58f545057a781c46c100a0f2ea5f8f6a31c1b44c784.bin"
+                .to_string(),
+        )?;
+        let (stale, recent) = (transactions[1].clone(), transactions[2].clone());
+        let mut account = Account::new(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            String::from("mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"),
+            String::from("cRJzHMCgDLsvttTH8R8t6LLcZgMDs1WtgwQXxk8bFFk7E2AJp1tw"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        account.unconfirmed_transactions.add_received(stale.clone());
+        account
+            .unconfirmed_transactions
+            .add_received(recent.clone());
+        account.first_seen.insert(stale.tx_id(), 1_000);
+        account.first_seen.insert(recent.tx_id(), 9_000);
+        let (ui_sender, _ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+
+        let expired = account.prune_stale_unconfirmed(10_000, 3_600, &ui_sender)?;
+
+        assert_eq!(expired, vec![stale.tx_id()]);
+        assert!(!account.unconfirmed_transactions.contains(&stale));
+        assert!(account.unconfirmed_transactions.contains(&recent));
+        assert!(!account.first_seen.contains_key(&stale.tx_id()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_is_loaded_as_it_was_saved() -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/000000000000000c5d6cc58f545057a781c46c100a0f2ea5f8f6a31c1b44c784.bin"
+                .to_string();
+        let transactions = retrieve_transactions_from_block(&block_path)?;
+        let (mut confirmed, unconfirmed) = (transactions[1].clone(), transactions[2].clone());
+        confirmed.add_block_path_to_tx_outs(&block_path);
+        let new_account = || {
+            Account::new(
+                &Arc::new(Mutex::new(UtxoSet::new())),
+                String::from("mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"),
+                String::from("cRJzHMCgDLsvttTH8R8t6LLcZgMDs1WtgwQXxk8bFFk7E2AJp1tw"),
+                0,
+                DEFAULT_CONFIRMATION_DEPTH,
+            )
+        };
+        let mut account = new_account()?;
+        account.tip_height = 2_431_000;
+        account.confirmed_transactions.add_spent(confirmed.clone());
+        account
+            .unconfirmed_transactions
+            .add_received(unconfirmed.clone());
+        account.mined_heights.insert(confirmed.tx_id(), 2_430_990);
+        account
+            .first_seen
+            .insert(unconfirmed.tx_id(), 1_686_000_000);
+        account.sent_amounts.insert(confirmed.tx_id(), 0.00012345);
+
+        let path = "test_account_history.txt";
+        let saved = account.save_history(path);
+        let mut loaded = new_account()?;
+        let result = loaded.load_history(path);
+        let _ = fs::remove_file(path);
+        saved?;
+        result?;
+
+        assert_eq!(loaded.tip_height, 2_431_000);
+        assert_eq!(
+            loaded.confirmed_transactions.spent[0].tx_id(),
+            confirmed.tx_id()
+        );
+        assert_eq!(
+            loaded.confirmed_transactions.spent[0].tx_outputs[0].block_path,
+            block_path
+        );
+        assert_eq!(
+            loaded.unconfirmed_transactions.received[0].tx_id(),
+            unconfirmed.tx_id()
+        );
+        assert_eq!(loaded.mined_heights, account.mined_heights);
+        assert_eq!(loaded.first_seen, account.first_seen);
+        assert_eq!(loaded.sent_amounts, account.sent_amounts);
+        Ok(())
+    }
+
+    #[test]
+    fn test_live_balance_reflects_updates_of_the_shared_utxo_set() -> Result<(), NodeError> {
+        let utxo_set_arc = Arc::new(Mutex::new(UtxoSet::new()));
+        let account = Account::new(
+            &utxo_set_arc,
+            String::from("mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"),
+            String::from("cRJzHMCgDLsvttTH8R8t6LLcZgMDs1WtgwQXxk8bFFk7E2AJp1tw"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        assert_eq!(account.live_balance()?, 0.0);
+
+        utxo_set_arc
+            .lock()
+            .map_err(|_| NodeError::UtxoSetMutexError("Failed to lock utxo set".to_string()))?
+            .update(
+                &"blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin"
+                    .to_string(),
+            )?;
+
+        assert_eq!(account.live_balance()?, 0.02432823);
+        assert_eq!(account.unspent_outputs()?.stats().count, 1);
+        assert_eq!(account.utxo_set.stats().count, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_message_is_verified() -> Result<(), NodeError> {
+        let account = Account::new(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            String::from("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"),
+            String::from("92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+
+        let signature = account.sign_message("I own this address")?;
+
+        assert!(Account::verify_message(
+            "mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC",
+            "I own this address",
+            &signature
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_message_signature_is_rejected() -> Result<(), NodeError> {
+        let account = Account::new(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            String::from("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"),
+            String::from("92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+
+        let signature = account.sign_message("Another message")?;
+
+        assert!(!Account::verify_message(
+            "mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC",
+            "I own this address",
+            &signature
+        )?);
+        assert!(!Account::verify_message(
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk",
+            "Another message",
+            &signature
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_activity_range_spans_the_blocks_of_confirmed_transactions() -> Result<(), NodeError> {
+        let first_block =
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin"
+                .to_string();
+        let second_block =
+            "blocks-test/0000000000000005847b65f037ec3d08f499c3c22ae6723ffefee1adca3e9af5.bin"
+                .to_string();
+        let (ui_sender, _ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+        let mut account = Account::new(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            String::from("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"),
+            String::from("92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        assert_eq!(account.activity_range(), None);
+
+        for block_path in [&first_block, &second_block] {
+            let transaction = retrieve_transactions_from_block(block_path)?.remove(1);
+            account.add_new_unconfirmed_transaction(transaction);
+            account.confirm_transactions(block_path, &ui_sender)?;
+        }
+
+        let first_timestamp = read_block_timestamp(&first_block)?;
+        let second_timestamp = read_block_timestamp(&second_block)?;
+        assert_eq!(account.confirmed_transactions.all_txs().len(), 2);
+        assert_eq!(
+            account.activity_range(),
+            Some((
+                first_timestamp.min(second_timestamp),
+                first_timestamp.max(second_timestamp)
+            ))
+        );
+        assert_ne!(first_timestamp, second_timestamp);
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_csv_has_a_row_for_a_confirmed_transaction() -> Result<(), NodeError> {
+        let block_hash = "0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a";
+        let block_path = format!("blocks-test/{}.bin", block_hash);
+        let (ui_sender, _ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+        let mut account = Account::new(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            String::from("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"),
+            String::from("92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        let transaction = retrieve_transactions_from_block(&block_path)?.remove(1);
+        let tx_id = InternalHash::from_slice(&transaction.tx_id())?.to_display();
+        account.add_new_unconfirmed_transaction(transaction);
+        account.confirm_transactions(&block_path, &ui_sender)?;
+
+        let csv = account.export_history_csv()?;
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "date,txid,type,amount_btc,block");
+        assert_eq!(lines.len(), 2);
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(
+            fields[0],
+            timestamp_to_date(read_block_timestamp(&block_path)?)?
+        );
+        assert_eq!(fields[1], tx_id.to_string());
+        assert_eq!(fields[2], "received");
+        assert!(fields[3].parse::<f64>().is_ok());
+        assert_eq!(fields[4], block_hash);
+        Ok(())
+    }
+
+    #[test]
+    fn test_history_csv_shows_the_net_amount_of_a_confirmed_sent_transaction(
+    ) -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin";
+        let (ui_sender, _ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+        let shared_utxo_set = Arc::new(Mutex::new(UtxoSet::new()));
+        let mut account = Account::new(
+            &shared_utxo_set,
+            String::from("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"),
+            String::from("92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5"),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        let pk_script = BitcoinAddress::to_pk_script(&account.bitcoin_address);
+        let mut transaction = retrieve_transactions_from_block(&block_path.to_string())?.remove(1);
+        let funding_outpoint = transaction.tx_inputs[0].previous_output.clone();
+        let change = TxOutput::new(0.3, pk_script.clone(), transaction.tx_outputs.len() as u64);
+        transaction.tx_outputs.push(change);
+        let funding = TxOutput::new(1.0, pk_script, funding_outpoint.index as u64);
+        shared_utxo_set
+            .lock()
+            .map_err(|_| NodeError::UtxoSetMutexError("Failed to lock utxo set".to_string()))?
+            .insert(funding_outpoint.tx_id.clone(), vec![funding]);
+
+        account.add_new_unconfirmed_transaction(transaction.clone());
+        shared_utxo_set
+            .lock()
+            .map_err(|_| NodeError::UtxoSetMutexError("Failed to lock utxo set".to_string()))?
+            .remove(&funding_outpoint.tx_id);
+        account.confirm_block_transactions(block_path, None, vec![transaction], &ui_sender)?;
+        let csv = account.export_history_csv()?;
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(fields[2], "sent");
+        assert_eq!(fields[3].parse::<f64>().ok(), Some(0.7));
+        Ok(())
+    }
 }
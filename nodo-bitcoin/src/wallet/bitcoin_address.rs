@@ -1,7 +1,41 @@
-use crate::node_error::NodeError;
+use bitcoin_hashes::{sha256d, Hash};
+
+use crate::{
+    constants::{
+        ADDRESS_CHECKSUM_LENGTH, ADDRESS_LENGTH, BIP21_URI_SCHEME, MAINNET_P2PKH_VERSION,
+        MAINNET_P2SH_VERSION, OP_EQUAL, OP_HASH160, PK_HASH_LENGTH, SATOSHI_CONVERSION_COEFFICIENT,
+        TESTNET_P2PKH_VERSION, TESTNET_P2SH_VERSION,
+    },
+    node_error::NodeError,
+};
 
 use super::account::Account;
 
+/// The network an address belongs to, which sets its version byte.
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+impl Network {
+    /// Returns the version byte of the P2PKH addresses of the network.
+    pub fn p2pkh_version(&self) -> u8 {
+        match self {
+            Network::Mainnet => MAINNET_P2PKH_VERSION,
+            Network::Testnet => TESTNET_P2PKH_VERSION,
+        }
+    }
+
+    /// Returns the version byte of the P2SH addresses of the network.
+    pub fn p2sh_version(&self) -> u8 {
+        match self {
+            Network::Mainnet => MAINNET_P2SH_VERSION,
+            Network::Testnet => TESTNET_P2SH_VERSION,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
 /// Represents a bitcoin address.
 pub struct BitcoinAddress {
@@ -11,19 +45,81 @@ pub struct BitcoinAddress {
 
 impl BitcoinAddress {
     /// Returns a BitcoinAddress from a String.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToParse` if the string is not base58, or a
+    /// `NodeError::InvalidAddressVersion` if the address is not a testnet P2PKH or P2SH address
+    /// or its checksum is wrong.
     pub fn from_string(address: &String) -> Result<BitcoinAddress, NodeError> {
         let address = bs58::decode(address)
             .into_vec()
             .map_err(|_| NodeError::FailedToParse("Failed to convert into vec".to_string()))?;
 
+        if address.len() != ADDRESS_LENGTH {
+            return Err(NodeError::InvalidAddressVersion(
+                "Invalid address length".to_string(),
+            ));
+        }
+        if address[0] != TESTNET_P2PKH_VERSION && address[0] != TESTNET_P2SH_VERSION {
+            return Err(NodeError::InvalidAddressVersion(format!(
+                "Address version {:#04x} is not a testnet version",
+                address[0]
+            )));
+        }
+        let (payload, checksum) = address.split_at(ADDRESS_LENGTH - ADDRESS_CHECKSUM_LENGTH);
+        if sha256d::Hash::hash(payload)[..ADDRESS_CHECKSUM_LENGTH] != *checksum {
+            return Err(NodeError::InvalidAddressVersion(
+                "Invalid address checksum".to_string(),
+            ));
+        }
+
         Ok(BitcoinAddress { address })
     }
 
+    /// Builds an address from its version byte and the hash it pays to, appending the checksum.
+    pub fn from_hash(version: u8, hash: &[u8]) -> BitcoinAddress {
+        let mut address = vec![version];
+        address.extend(hash);
+        let checksum = sha256d::Hash::hash(&address);
+        address.extend(&checksum[..ADDRESS_CHECKSUM_LENGTH]);
+
+        BitcoinAddress { address }
+    }
+
+    /// Returns true if the address is a Pay-to-Script-Hash address.
+    pub fn is_p2sh(&self) -> bool {
+        self.address.first() == Some(&TESTNET_P2SH_VERSION)
+    }
+
     /// Turns the Bitcoin Address into a string format.
     pub fn bs58_to_string(&self) -> String {
         bs58::encode(&self.address).into_string()
     }
 
+    /// Returns the BIP21 `bitcoin:` URI of the address, to be shown as a QR code.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount` - The amount requested, in BTC.
+    /// * `label` - A label for the address, such as the name of the account.
+    pub fn to_uri(&self, amount: Option<f64>, label: Option<&str>) -> String {
+        let mut params = Vec::new();
+        if let Some(amount) = amount {
+            params.push(format!("amount={}", format_btc_amount(amount)));
+        }
+        if let Some(label) = label {
+            params.push(format!("label={}", percent_encode(label)));
+        }
+
+        let mut uri = format!("{}{}", BIP21_URI_SCHEME, self.bs58_to_string());
+        if !params.is_empty() {
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        uri
+    }
+
     /// Takes the first byte and the last 4 out of the bitcoin address, the resulting Vec<u8> is the pk script
     pub fn to_pk_hash(bitcoin_address: &BitcoinAddress) -> Vec<u8> {
         let mut pk_hash = bitcoin_address.address.clone();
@@ -36,10 +132,113 @@ impl BitcoinAddress {
         pk_hash
     }
 
-    /// Converts a BitcoinAddress into a pk script.
+    /// Converts a BitcoinAddress into a pk script, a P2SH script for P2SH addresses and a P2PKH one otherwise.
     pub fn to_pk_script(bitcoin_address: &BitcoinAddress) -> Vec<u8> {
         let pk_hash = BitcoinAddress::to_pk_hash(bitcoin_address);
 
+        if bitcoin_address.is_p2sh() {
+            let mut pk_script = vec![OP_HASH160, PK_HASH_LENGTH];
+            pk_script.extend(pk_hash);
+            pk_script.push(OP_EQUAL);
+            return pk_script;
+        }
         Account::pk_hash_to_pk_script(&pk_hash)
     }
 }
+
+/// Formats an amount of BTC as BIP21 requires: a decimal number of at most 8 decimals, without
+/// exponent nor trailing zeros.
+fn format_btc_amount(amount: f64) -> String {
+    let satoshis = (amount * SATOSHI_CONVERSION_COEFFICIENT).round() as u64;
+    let coefficient = SATOSHI_CONVERSION_COEFFICIENT as u64;
+    let (whole, fraction) = (satoshis / coefficient, satoshis % coefficient);
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    format!("{}.{:08}", whole, fraction)
+        .trim_end_matches('0')
+        .to_string()
+}
+
+/// Percent-encodes every byte of a text but the unreserved characters of RFC 3986, so it can be
+/// used as the value of a URI query parameter.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_testnet_address() -> Result<(), NodeError> {
+        let address =
+            BitcoinAddress::from_string(&"mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string())?;
+
+        assert_eq!(
+            address.bs58_to_string(),
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"
+        );
+        assert!(!address.is_p2sh());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mainnet_address_is_rejected() {
+        let result = BitcoinAddress::from_string(&"1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2".to_string());
+
+        assert!(matches!(result, Err(NodeError::InvalidAddressVersion(_))));
+    }
+
+    #[test]
+    fn test_corrupted_checksum_is_rejected() {
+        let result = BitcoinAddress::from_string(&"mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyz".to_string());
+
+        assert!(matches!(result, Err(NodeError::InvalidAddressVersion(_))));
+    }
+
+    #[test]
+    fn test_bip21_uri() -> Result<(), NodeError> {
+        let address =
+            BitcoinAddress::from_string(&"mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string())?;
+
+        assert_eq!(
+            address.to_uri(None, None),
+            "bitcoin:mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk"
+        );
+        assert_eq!(
+            address.to_uri(Some(0.001), None),
+            "bitcoin:mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk?amount=0.001"
+        );
+        assert_eq!(
+            address.to_uri(Some(20.3), Some("Luke-Jr & co")),
+            "bitcoin:mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk?amount=20.3&label=Luke-Jr%20%26%20co"
+        );
+        assert_eq!(
+            address.to_uri(Some(2.0), None),
+            "bitcoin:mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk?amount=2"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_p2sh_address_pk_script() -> Result<(), NodeError> {
+        let address =
+            BitcoinAddress::from_string(&"2MzQwSSnBHWHqSAqtTVQ6v47XtaisrJa1Vc".to_string())?;
+
+        let pk_script = BitcoinAddress::to_pk_script(&address);
+
+        assert!(address.is_p2sh());
+        assert_eq!(pk_script.len(), 23);
+        assert_eq!(pk_script[0], OP_HASH160);
+        assert_eq!(pk_script[22], OP_EQUAL);
+        Ok(())
+    }
+}
@@ -0,0 +1,10 @@
+/// Where the wallet sends the change of the transactions it creates.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ChangeAddressStrategy {
+    /// The change goes back to the address that sends the transaction.
+    #[default]
+    SenderAddress,
+    /// The change goes to the address of another account of the wallet, so the sender address is
+    /// not reused.
+    ChangeAccount(String),
+}
@@ -1,5 +1,6 @@
 pub mod account;
 pub mod bitcoin_address;
+pub mod change_address_strategy;
 pub mod node_wallet_message;
 pub mod transactions_spent_received;
 pub mod wallet_account_info;
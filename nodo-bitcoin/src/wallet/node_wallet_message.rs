@@ -1,4 +1,9 @@
-use crate::transactions::{transaction::Transaction, utxo_set::UtxoSet};
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    messages::merkle_block_message::MerkleBlockMessage,
+    transactions::{transaction::Transaction, utxo_set::UtxoSet},
+};
 
 use super::wallet_account_info::AccountInfo;
 
@@ -8,6 +13,9 @@ pub enum NodeWalletMsg {
     NewTransaction(Transaction),
     /// The node sends the wallet a new block file path
     NewBlock(String),
+    /// The node sends the wallet the merkleblock of a new block in headers-only mode, with the path
+    /// the block would be saved to and the transactions the merkleblock proves
+    NewMerkleBlock(String, MerkleBlockMessage, Vec<Transaction>),
     /// The node sends the wallet the new account information
-    CreateNewAccount(AccountInfo, UtxoSet),
+    CreateNewAccount(AccountInfo, Arc<Mutex<UtxoSet>>),
 }
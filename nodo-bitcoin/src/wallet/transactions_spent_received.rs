@@ -1,13 +1,13 @@
 use glib::Sender;
 
 use crate::{
+    block::block_hash::InternalHash,
     node_error::NodeError,
-    transactions::{pk_script::PkScript, transaction::Transaction},
+    transactions::transaction::Transaction,
     ui::{components::transactions_confirmed_data::Amount, ui_message::UIMessage},
-    utils::Utils,
 };
 
-use super::account::Account;
+use super::{account::Account, bitcoin_address::BitcoinAddress};
 
 /// Represents the transactions sent and received by the user.
 #[derive(Debug, Clone, Default)]
@@ -97,12 +97,11 @@ impl TransactionsSpentAndReceived {
     pub fn send_confirmations_to_ui(&self, ui_sender: &Sender<UIMessage>) -> Result<(), NodeError> {
         let transactions = self.all_txs();
         for tx in transactions {
-            let mut id = tx.tx_id();
-            id.reverse();
+            let tx_id = InternalHash::from_slice(&tx.tx_id())?.to_display();
             ui_sender
                 .send(UIMessage::NotificationMessage(format!(
                     "Tx {} confirmed",
-                    Utils::bytes_to_hex(&id)
+                    tx_id
                 )))
                 .map_err(|_| {
                     NodeError::FailedToSendMessage(
@@ -114,42 +113,17 @@ impl TransactionsSpentAndReceived {
         Ok(())
     }
 
-    /// Checks if a transaction output pk script is equal to the user's pk hash.
-    /// # Arguments
-    /// * `users_pk_hash` - The user's pk hash.
-    /// * `pk_script` - The transaction output pk script.
-    /// # Returns
-    /// A boolean indicating if the pk script is equal to the user's pk hash.
-    fn check_output_pk(users_pk_hash: &Vec<u8>, pk_script: &PkScript) -> bool {
-        let tx_output_pk_hash = match Account::pk_script_to_pk_hash(pk_script) {
-            Ok(pk_hash) => pk_hash,
-            Err(_) => {
-                println!("This is not a P2PKH script");
-                return false;
-            }
-        };
-        if users_pk_hash == &tx_output_pk_hash {
-            return true;
-        }
-
-        false
-    }
-
     /// Gets the received balance for a specific address.
     /// # Arguments
-    /// * `users_pk_hash` - The user's pk hash.
+    /// * `address` - The address of the user.
     /// # Returns
     /// The received balance for the address.
-    pub fn received_balance(&self, users_pk_hash: &Vec<u8>) -> Amount {
-        let mut balance: f64 = 0.0;
-        for tx in &self.received {
-            for tx_output in &tx.tx_outputs {
-                if Self::check_output_pk(users_pk_hash, &tx_output.pk_script) {
-                    balance += tx_output.value();
-                }
-            }
-        }
-        balance.to_string()
+    pub fn received_balance(&self, address: &BitcoinAddress) -> Amount {
+        self.received
+            .iter()
+            .map(|tx| tx.value_received_by_address(address))
+            .sum::<f64>()
+            .to_string()
     }
 
     /// Gets the spent balance for a specific account.
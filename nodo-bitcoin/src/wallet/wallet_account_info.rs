@@ -1,6 +1,9 @@
-use crate::{constants::SAVED_ACCOUNTS, node_error::NodeError};
+use crate::{node::read::read_timestamp, node_error::NodeError, utils::Utils};
 
-use std::{fs::File, io::Write};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+};
 
 /// Represents information about an account.
 #[derive(Clone)]
@@ -11,6 +14,8 @@ pub struct AccountInfo {
     pub private_key: String,
     /// The Bitcoin address associated with the account.
     pub bitcoin_address: String,
+    /// The timestamp of the creation of the account. Blocks older than it are not scanned.
+    pub birthday: Option<u32>,
 }
 
 impl AccountInfo {
@@ -19,7 +24,7 @@ impl AccountInfo {
     /// # Arguments
     ///
     /// * `info` - The string representation of wallet information in the format:
-    ///                   "<bitcoin_address>;<private_key>;<name>"
+    ///                   "<bitcoin_address>;<private_key>;<name>[;<birthday>]"
     ///
     /// # Returns
     ///
@@ -30,6 +35,9 @@ impl AccountInfo {
             bitcoin_address: substrings[0].clone(),
             private_key: substrings[1].clone(),
             name: substrings[2].clone(),
+            birthday: substrings
+                .get(3)
+                .and_then(|birthday| birthday.parse::<u32>().ok()),
         }
     }
 
@@ -39,31 +47,80 @@ impl AccountInfo {
             bitcoin_address,
             private_key,
             name,
+            birthday: None,
+        }
+    }
+
+    /// Returns the `AccountInfo` with the given birthday timestamp.
+    pub fn with_birthday(mut self, birthday: u32) -> Self {
+        self.birthday = Some(birthday);
+        self
+    }
+
+    /// Returns the birthday timestamp of the account, or the configured STARTING_DATE if it has none.
+    pub fn birthday(&self) -> u32 {
+        match self.birthday {
+            Some(birthday) => birthday,
+            None => read_timestamp().unwrap_or(0),
         }
     }
     /// Creates a new `AccountInfo` struct by parsing a string representation of account information.
     pub fn to_string_format(&self) -> String {
-        format!(
+        let info = format!(
             "{};{};{}",
             self.bitcoin_address, self.private_key, self.name
-        )
+        );
+        match self.birthday {
+            Some(birthday) => format!("{};{}", info, birthday),
+            None => info,
+        }
+    }
+    /// Saves the information of the given accounts to the file at the given path, replacing its content.
+    ///
+    /// The accounts are written to a temporary file that is then renamed over the saved one, so a crash
+    /// while writing never leaves a partially written file.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToWrite` if the temporary file could not be written or renamed.
+    pub fn save_all(accounts_info: &[AccountInfo], path: &str) -> Result<(), NodeError> {
+        let content: String = accounts_info
+            .iter()
+            .map(|account_info| format!("{}\n", account_info.to_string_format()))
+            .collect();
+        Utils::write_atomically(path, content.as_bytes())
     }
-    /// Saves the `AccountInfo` struct to a file.
-    pub fn save_to_file(&self) -> Result<(), NodeError> {
-        let info = self.to_string_format();
-        let mut file = File::options()
-            .write(true)
-            .append(true)
-            .open(SAVED_ACCOUNTS)
-            .map_err(|_| NodeError::FailedToOpenFile("Failed to open file block".to_string()))?;
 
-        file.write(info.as_bytes())
-            .map_err(|_| NodeError::FailedToWrite("Failed to write file block".to_string()))?;
-        file.write("\n".as_bytes())
-            .map_err(|_| NodeError::FailedToWrite("Failed to write file block".to_string()))?;
+    /// Reads the information of the accounts saved in the file at the given path, creating the file if
+    /// it doesn't exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToOpenFile` if the file could not be opened, or a
+    /// `NodeError::FailedToRead` if it could not be read.
+    pub fn read_all(path: &str) -> Result<Vec<AccountInfo>, NodeError> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|_| {
+                NodeError::FailedToOpenFile("Failed to open saved accounts file".to_string())
+            })?;
 
-        Ok(())
+        let mut accounts_info = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| {
+                NodeError::FailedToRead(format!("Failed to read from saved accounts file: {}", e))
+            })?;
+            if !line.is_empty() {
+                accounts_info.push(AccountInfo::new_from_string(line));
+            }
+        }
+        Ok(accounts_info)
     }
+
     /// Extracts the name of the wallet.
     pub fn extract_name(&self) -> String {
         self.name.clone()
@@ -83,6 +140,75 @@ impl AccountInfo {
             bitcoin_address: self.bitcoin_address.clone(),
             private_key: self.private_key.clone(),
             name: self.name.clone(),
+            birthday: self.birthday,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_account_info_birthday_round_trip() {
+        let account_info = AccountInfo::new_from_values(
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string(),
+            "private_key".to_string(),
+            "name".to_string(),
+        )
+        .with_birthday(1686000000);
+
+        let parsed = AccountInfo::new_from_string(account_info.to_string_format());
+
+        assert_eq!(parsed.birthday, Some(1686000000));
+        assert_eq!(parsed.name, "name");
+    }
+
+    #[test]
+    fn test_save_all_replaces_the_file_without_leaving_a_temporary_one() -> Result<(), NodeError> {
+        let path = "test_save_all_accounts.txt";
+        fs::write(path, "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk;old_key;old")
+            .map_err(|_| NodeError::FailedToWrite("Failed to write accounts".to_string()))?;
+        let accounts_info = vec![
+            AccountInfo::new_from_values(
+                "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string(),
+                "private_key".to_string(),
+                "first".to_string(),
+            ),
+            AccountInfo::new_from_values(
+                "mtEoVpBV5H8bbmNDEPwaoJHXnF1MxbkkQf".to_string(),
+                "other_key".to_string(),
+                "second".to_string(),
+            )
+            .with_birthday(1686000000),
+        ];
+
+        let result = AccountInfo::save_all(&accounts_info, path);
+        let saved = AccountInfo::read_all(path);
+        let temp_left = std::path::Path::new(&format!("{}.tmp", path)).exists();
+        let _ = fs::remove_file(path);
+        result?;
+        let saved = saved?;
+
+        assert!(!temp_left);
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0].private_key, "private_key");
+        assert_eq!(saved[1].name, "second");
+        assert_eq!(saved[1].birthday, Some(1686000000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_info_without_birthday() {
+        let account_info = AccountInfo::new_from_string(
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk;private_key;name".to_string(),
+        );
+
+        assert_eq!(account_info.birthday, None);
+        assert_eq!(
+            account_info.to_string_format(),
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk;private_key;name"
+        );
+    }
+}
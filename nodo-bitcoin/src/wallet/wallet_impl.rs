@@ -1,21 +1,51 @@
 use std::{
+    collections::HashMap,
+    fs,
     net::TcpStream,
-    sync::{mpsc, Arc, Mutex},
+    sync::{mpsc, Arc, Mutex, OnceLock, Weak},
     thread,
+    time::Duration,
 };
 
 use super::{
-    account::Account, bitcoin_address::BitcoinAddress, node_wallet_message::NodeWalletMsg,
+    account::Account,
+    bitcoin_address::{BitcoinAddress, Network},
+    change_address_strategy::ChangeAddressStrategy,
+    node_wallet_message::NodeWalletMsg,
     wallet_account_info::AccountInfo,
 };
 
 use glib::Sender;
 
 use crate::{
+    block::{
+        block_hash::BlockHash, current_timestamp, retrieve_block_height,
+        retrieve_transactions_from_block, tx_hash::TxHash,
+    },
     channels::wallet_channel::WalletChannel,
-    node::broadcast_transaction,
+    constants::{
+        BLOOM_FALSE_POSITIVE_RATE, HISTORY_CSV_PREFIX, MERKLE_BLOCK_TIMEOUT,
+        SATOSHI_CONVERSION_COEFFICIENT, SAVED_ACCOUNTS, UNCONFIRMED_TX_MAX_AGE,
+    },
+    logger::Logger,
+    messages::{
+        block_message::BlockMessage, filter_load_message::FilterLoadMessage,
+        mempool_message::MempoolMessage, merkle_block_message::MerkleBlockMessage,
+    },
+    node::{
+        broadcast_transaction, check_transaction_size, connect_to_ip,
+        read::{
+            read_block_headers_since, read_timestamp, retrieve_change_account,
+            retrieve_confirmation_depth, retrieve_headers_only, retrieve_max_tx_size,
+            retrieve_request_mempool, retrieve_wallet_autosave_secs,
+        },
+        receive_messages::fetch_merkle_block,
+    },
     node_error::NodeError,
-    transactions::{transaction::Transaction, utxo_set::UtxoSet},
+    transactions::{
+        pk_script::PkScriptExt, transaction::Transaction, tx_input::TxInput, tx_output::TxOutput,
+        utxo_set::UtxoSet,
+    },
     ui::{
         components::transactions_confirmed_data::TransactionConfirmedData, ui_message::UIMessage,
     },
@@ -23,8 +53,12 @@ use crate::{
 
 use crate::wallet::node_wallet_message::NodeWalletMsg::NewBlock;
 use crate::wallet::wallet_impl::NodeWalletMsg::CreateNewAccount;
+use crate::wallet::wallet_impl::NodeWalletMsg::NewMerkleBlock;
 use crate::wallet::wallet_impl::NodeWalletMsg::NewTransaction;
 
+/// The wallet run by the node, kept so its accounts can be saved when the application shuts down.
+static RUNNING_WALLET: OnceLock<Arc<Mutex<Wallet>>> = OnceLock::new();
+
 /// Represents a Wallet for the user.
 pub struct Wallet {
     /// The wallet contains a list of accounts. The account at the head is the one that is
@@ -32,6 +66,37 @@ pub struct Wallet {
     pub accounts: Vec<Account>,
     /// The list of blocks that have been checked by the wallet.
     checked_blocks: Vec<String>,
+    /// Where the change of the created transactions is sent.
+    change_address_strategy: ChangeAddressStrategy,
+    /// The number of confirmations a transaction of the accounts needs to be shown as confirmed.
+    confirmation_depth: u32,
+    /// The information of the accounts the user chose to save, which is written to the accounts
+    /// file. It keeps the saved accounts that are not loaded in the wallet.
+    saved_accounts: Vec<AccountInfo>,
+}
+
+/// A transaction created by the wallet that waits for the user to confirm it before it's broadcasted.
+struct TransactionDraft {
+    /// The signed transaction.
+    transaction: Transaction,
+    /// The outputs of the account that created the transaction, which hold the ones it spends.
+    spent_outputs: UtxoSet,
+}
+
+impl TransactionDraft {
+    /// Returns the label and the value in BTC of each output of the transaction, to be shown to the user.
+    fn outputs(&self) -> Vec<(String, f64)> {
+        self.transaction
+            .tx_outputs
+            .iter()
+            .map(|tx_output| {
+                (
+                    tx_output.pk_script.label(Network::Testnet),
+                    tx_output.value as f64 / SATOSHI_CONVERSION_COEFFICIENT,
+                )
+            })
+            .collect()
+    }
 }
 
 impl Wallet {
@@ -57,7 +122,7 @@ impl Wallet {
                 "Account not found in wallet".to_string(),
             ))?;
 
-        Ok(account.balance_for_user())
+        account.live_balance()
     }
 
     /// Returns a Wallet for the user.
@@ -65,20 +130,25 @@ impl Wallet {
     /// * `utxo_set_arc` - The UTXO set to be used by the wallet, inside an Arc Mutex to be shared between threads.
     /// * `account_info` - The AccountInfo instance containing the Bitcoin address and private key for the user.
     /// * `ui_sender` - The Sender instance to be used to send messages to the UI.
+    /// * `confirmation_depth` - The number of confirmations a transaction needs to be shown as confirmed.
     /// # Returns
     /// Returns a Result containing Ok(Wallet) if the wallet was created successfully, or a NodeError if an error occurs.
     pub fn initialize_wallet_for_user(
         utxo_set_arc: &Arc<Mutex<UtxoSet>>,
         account_info: &AccountInfo,
         ui_sender: &Sender<UIMessage>,
+        confirmation_depth: u32,
     ) -> Result<Wallet, NodeError> {
         let bitcoin_address = account_info.extract_bitcoin_address();
         let private_key = account_info.extract_private_key();
-        let utxo_lock = utxo_set_arc
-            .lock()
-            .map_err(|_| NodeError::FailedToSendMessage("Failed to lock utxo set".to_string()))?;
 
-        let initial_account = Account::new(&utxo_lock, bitcoin_address, private_key)?;
+        let initial_account = Account::new(
+            utxo_set_arc,
+            bitcoin_address,
+            private_key,
+            account_info.birthday(),
+            confirmation_depth,
+        )?;
 
         ui_sender
             .send(UIMessage::AddNewAccount(
@@ -92,6 +162,9 @@ impl Wallet {
         Ok(Wallet {
             accounts: vec![initial_account],
             checked_blocks: Vec::new(),
+            change_address_strategy: ChangeAddressStrategy::default(),
+            confirmation_depth,
+            saved_accounts: Vec::new(),
         })
     }
 
@@ -100,22 +173,27 @@ impl Wallet {
     /// * `utxo_set_arc` - The UTXO set to be used by the wallet, inside an Arc Mutex to be shared between threads.
     /// * `accounts_info` - The Vec<AccountInfo> instance containing the accounts saved in the file.
     /// * `ui_sender` - The Sender instance to be used to send messages to the UI.
+    /// * `confirmation_depth` - The number of confirmations a transaction needs to be shown as confirmed.
     /// # Returns
     /// Returns a Result containing Ok(Wallet) if the wallet was created successfully, or a NodeError if an error occurs.
     pub fn initialize_wallet_with_saved_accounts(
         utxo_set_arc: &Arc<Mutex<UtxoSet>>,
         accounts_info: Vec<AccountInfo>,
         ui_sender: &Sender<UIMessage>,
+        confirmation_depth: u32,
     ) -> Result<Wallet, NodeError> {
         let mut accounts = Vec::new();
         for account_info in accounts_info.clone() {
             let bitcoin_address = account_info.extract_bitcoin_address();
             let private_key = account_info.extract_private_key();
-            let utxo_lock = utxo_set_arc.lock().map_err(|_| {
-                NodeError::FailedToSendMessage("Failed to lock utxo set".to_string())
-            })?;
 
-            let account = Account::new(&utxo_lock, bitcoin_address, private_key)?;
+            let account = Account::new(
+                utxo_set_arc,
+                bitcoin_address,
+                private_key,
+                account_info.birthday(),
+                confirmation_depth,
+            )?;
             accounts.push(account);
         }
 
@@ -128,31 +206,47 @@ impl Wallet {
                 NodeError::FailedToChangeAccount("Failed to send account changed to ui".to_string())
             })?;
 
-        Ok(Wallet {
+        let wallet = Wallet {
             accounts,
             checked_blocks: Vec::new(),
-        })
+            change_address_strategy: ChangeAddressStrategy::default(),
+            confirmation_depth,
+            saved_accounts: accounts_info,
+        };
+        wallet.send_receive_qr(&wallet.saved_accounts[0], ui_sender);
+        Ok(wallet)
     }
 
     /// Adds an account to the wallet and sends the AddNewAccount message to the UI.
     /// # Arguments
-    /// * `utxo_set` - The UTXO set to be used by the account.
-    /// * `bitcoin_address` - The Bitcoin Address to be added.
-    /// * `private_key` - The private key for the Bitcoin Address.
+    /// * `utxo_set_arc` - The UTXO set to be used by the account, inside an Arc Mutex to be shared between threads.
+    /// * `account_info` - The information of the account to be added.
     /// * `wallet_node_sender` - The channel to send messages to the UI.
     /// # Returns
     /// Returns a Result containing Ok if the account was added and the message was sent successfully, or a NodeError if an error occurs.
     pub fn add_account(
         &mut self,
-        utxo_set: &UtxoSet,
+        utxo_set_arc: &Arc<Mutex<UtxoSet>>,
         account_info: AccountInfo,
         wallet_node_sender: &Sender<UIMessage>,
     ) -> Result<(), NodeError> {
         let bitcoin_address = account_info.extract_bitcoin_address();
         let private_key = account_info.extract_private_key();
-        let new_account = Account::new(utxo_set, bitcoin_address, private_key)?;
+        let mut new_account = Account::new(
+            utxo_set_arc,
+            bitcoin_address,
+            private_key,
+            account_info.birthday(),
+            self.confirmation_depth,
+        )?;
+        if let Err(e) = new_account.load_history(&new_account.history_path()) {
+            println!("Failed to load the history of the account: {:?}", e);
+        }
         wallet_node_sender
-            .send(UIMessage::AddNewAccount(new_account.copy(), account_info))
+            .send(UIMessage::AddNewAccount(
+                new_account.copy(),
+                account_info.clone(),
+            ))
             .map_err(|_| {
                 NodeError::FailedToSendMessage("Failed to send new account to ui".to_string())
             })?;
@@ -170,10 +264,168 @@ impl Wallet {
 
         self.accounts
             .retain(|account| account.bitcoin_address != bitcoin_address_to_remove);
+        self.saved_accounts
+            .retain(|account_info| &account_info.bitcoin_address != bitcoin_address);
+        if self.change_address_strategy
+            == ChangeAddressStrategy::ChangeAccount(bitcoin_address.to_string())
+        {
+            self.change_address_strategy = ChangeAddressStrategy::SenderAddress;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the information of the accounts the user chose to save to the file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToWrite` if the file could not be written.
+    pub fn save_accounts(&self, path: &str) -> Result<(), NodeError> {
+        AccountInfo::save_all(&self.saved_accounts, path)
+    }
 
+    /// Saves the accounts the user chose to save to the file at the given path, and the history of
+    /// each of them to its own file, so their transactions are loaded back with them.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToWrite` if a file could not be written.
+    pub fn save_wallet(&self, path: &str) -> Result<(), NodeError> {
+        self.save_accounts(path)?;
+        for account in self.accounts.iter() {
+            let address = account.bitcoin_address.bs58_to_string();
+            if self
+                .saved_accounts
+                .iter()
+                .any(|saved| saved.bitcoin_address == address)
+            {
+                account.save_history(&account.history_path())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the accounts saved in the file at the given path, so they are kept when the accounts of the
+    /// wallet are saved even if they are not loaded in it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the file could not be read.
+    pub fn read_saved_accounts(&mut self, path: &str) -> Result<(), NodeError> {
+        self.saved_accounts = AccountInfo::read_all(path)?;
+        Ok(())
+    }
+
+    /// Adds the account to the ones the user chose to save, replacing the saved one with the same address,
+    /// and saves them to the file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToWrite` if the file could not be written.
+    pub fn save_account(&mut self, account_info: AccountInfo, path: &str) -> Result<(), NodeError> {
+        match self
+            .saved_accounts
+            .iter_mut()
+            .find(|saved| saved.bitcoin_address == account_info.bitcoin_address)
+        {
+            Some(saved) => *saved = account_info,
+            None => self.saved_accounts.push(account_info),
+        }
+        self.save_accounts(path)
+    }
+
+    /// Saves the accounts of the wallet run by the node and their history, if there is one. It's
+    /// called when the application shuts down, so what changed since the last auto-save is not lost.
+    pub fn save_running_wallet() {
+        let saved = match RUNNING_WALLET.get() {
+            Some(wallet) => wallet
+                .lock()
+                .map_err(|_| NodeError::WalletMutexError("Failed to lock wallet".to_string()))
+                .and_then(|wallet| wallet.save_wallet(SAVED_ACCOUNTS)),
+            None => Ok(()),
+        };
+        if let Err(e) = saved {
+            println!("Failed to save the wallet accounts: {:?}", e);
+        }
+    }
+
+    /// Spawns a thread that saves the accounts of the wallet to the file at the given path, and
+    /// their history, every `interval`, until the wallet is dropped.
+    fn spawn_autosave(wallet: &Arc<Mutex<Wallet>>, path: String, interval: Duration) {
+        let wallet: Weak<Mutex<Wallet>> = Arc::downgrade(wallet);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let wallet = match wallet.upgrade() {
+                Some(wallet) => wallet,
+                None => break,
+            };
+            let saved = wallet
+                .lock()
+                .map_err(|_| NodeError::WalletMutexError("Failed to lock wallet".to_string()))
+                .and_then(|wallet| wallet.save_wallet(&path));
+            if let Err(e) = saved {
+                println!("Failed to auto-save the wallet accounts: {:?}", e);
+            }
+        });
+    }
+
+    /// Returns where the change of the created transactions is sent.
+    pub fn change_address_strategy(&self) -> &ChangeAddressStrategy {
+        &self.change_address_strategy
+    }
+
+    /// Sets where the change of the created transactions is sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - The new change address strategy.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::AccountNotFound` if the change account is not an account of the wallet,
+    /// as the wallet couldn't spend the change sent to it.
+    pub fn set_change_address_strategy(
+        &mut self,
+        strategy: ChangeAddressStrategy,
+    ) -> Result<(), NodeError> {
+        if let ChangeAddressStrategy::ChangeAccount(address) = &strategy {
+            if !self.owns_address(address) {
+                return Err(NodeError::AccountNotFound(
+                    "The change account is not an account of the wallet".to_string(),
+                ));
+            }
+        }
+        self.change_address_strategy = strategy;
         Ok(())
     }
 
+    /// Sends the change of the created transactions to the configured change account. Without one,
+    /// or if it's not an account of the wallet, the change goes back to the sender address.
+    ///
+    /// # Arguments
+    ///
+    /// * `change_account` - The address of the change account, from CHANGE_ACCOUNT.
+    pub fn configure_change_account(&mut self, change_account: Option<String>) {
+        let strategy = match change_account {
+            Some(address) => ChangeAddressStrategy::ChangeAccount(address),
+            None => ChangeAddressStrategy::SenderAddress,
+        };
+        if let Err(e) = self.set_change_address_strategy(strategy) {
+            println!("The configured change account can't be used: {:?}", e);
+        }
+    }
+
+    /// Returns the address that receives the change of a transaction sent by the given account.
+    fn change_address(&self, sender: &Account) -> BitcoinAddress {
+        match &self.change_address_strategy {
+            ChangeAddressStrategy::ChangeAccount(address) => self
+                .account_from_address(address.to_string())
+                .map(|account| account.bitcoin_address.clone())
+                .unwrap_or_else(|| sender.bitcoin_address.clone()),
+            ChangeAddressStrategy::SenderAddress => sender.bitcoin_address.clone(),
+        }
+    }
+
     /// Returns the Bitcoin Addresses for the user.
     /// # Returns
     /// Returns a vector containing the Bitcoin Addresses for the user.
@@ -187,6 +439,167 @@ impl Wallet {
         addresses
     }
 
+    /// Builds the bloom filter (BIP37) of the wallet, which matches the transactions of its accounts.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the unspent outputs of an account can't be read.
+    pub fn bloom_filter(&self) -> Result<FilterLoadMessage, NodeError> {
+        let mut elements: Vec<Vec<u8>> = Vec::new();
+        for account in self.accounts.iter() {
+            elements.extend(account.bloom_filter_elements()?);
+        }
+        Ok(FilterLoadMessage::new(
+            &elements,
+            BLOOM_FALSE_POSITIVE_RATE,
+            rand::random(),
+        ))
+    }
+
+    /// In headers-only mode, loads the bloom filter of the wallet in the peers, so they answer the
+    /// filtered block requests with the transactions of its accounts. The filter is also kept to be
+    /// sent to the peers on handshake.
+    ///
+    /// A filter that could not be built or a peer it could not be sent to is only reported.
+    ///
+    /// # Arguments
+    ///
+    /// * `peers` - The connections to the peers the node listens to.
+    fn load_bloom_filter(&self, peers: &mut [TcpStream]) {
+        if !retrieve_headers_only() {
+            return;
+        }
+        let filter = match self.bloom_filter() {
+            Ok(filter) => filter,
+            Err(e) => {
+                println!("Failed to build the wallet filter: {:?}", e);
+                return;
+            }
+        };
+        for peer in peers.iter_mut() {
+            match peer.peer_addr() {
+                Ok(addr) if FilterLoadMessage::is_served_by(&addr) => {}
+                _ => continue,
+            }
+            if let Err(e) = filter.send_message(peer) {
+                println!("Failed to load the wallet filter in a peer: {:?}", e);
+            }
+        }
+        FilterLoadMessage::set_wallet_filter(filter);
+    }
+
+    /// Asks the peers for the transactions of their mempools, so the unconfirmed transactions of
+    /// the wallet are received on start instead of waiting for new broadcasts. The peers answer
+    /// with an inv of them, which is handled by their listeners like the ones they relay.
+    ///
+    /// It must be sent after the wallet filter is loaded: a peer only filters the answer with the
+    /// filter it already has, so in headers-only mode it is only sent to the peers serving bloom
+    /// filters. A failure is only logged.
+    ///
+    /// # Arguments
+    ///
+    /// * `peers` - The connections to the peers the node listens to.
+    /// * `logger` - The logger of the connection.
+    fn request_mempool(peers: &mut [TcpStream], logger: &Logger) {
+        if !retrieve_request_mempool() {
+            return;
+        }
+        for peer in peers.iter_mut() {
+            let addr = peer.peer_addr();
+            if retrieve_headers_only() {
+                match &addr {
+                    Ok(addr) if FilterLoadMessage::is_served_by(addr) => {}
+                    _ => continue,
+                }
+            }
+            let message = match MempoolMessage.send_message(peer) {
+                Ok(_) => format!("Requested the mempool of {:?}", addr),
+                Err(e) => format!("Failed to request the mempool of {:?}: {:?}", addr, e),
+            };
+            if let Err(e) = logger.log(message) {
+                println!("Failed to log the mempool request: {:?}", e);
+            }
+        }
+    }
+
+    /// In headers-only mode, scans the blocks mined since the oldest birthday of the accounts for
+    /// their transactions, requesting their filtered blocks from a peer that serves bloom filters.
+    /// New blocks only bring the transactions sent from then on, so without this scan the
+    /// outputs the accounts received before the node started would be missing.
+    ///
+    /// The scan uses its own connection, which loads the wallet filter on handshake, so it
+    /// doesn't compete with the listener of the peer for its messages. A scan that could not be
+    /// started or finished is only reported.
+    ///
+    /// # Arguments
+    ///
+    /// * `peers` - The connections to the peers the node listens to.
+    /// * `logger` - The logger of the connection.
+    /// * `ui_sender` - The channel to send messages to the UI.
+    fn fetch_history(
+        &mut self,
+        peers: &[TcpStream],
+        logger: &Logger,
+        ui_sender: &Sender<UIMessage>,
+    ) {
+        if !retrieve_headers_only() {
+            return;
+        }
+        let peer = peers
+            .iter()
+            .filter_map(|peer| peer.peer_addr().ok())
+            .find(FilterLoadMessage::is_served_by);
+        let mut stream = match peer.and_then(|peer| connect_to_ip(&peer, logger)) {
+            Some(stream) => stream,
+            None => {
+                println!("No peer serving bloom filters to scan the wallet history");
+                return;
+            }
+        };
+        if let Err(e) = self.apply_history(&mut stream, ui_sender) {
+            println!("Failed to scan the wallet history: {:?}", e);
+        }
+    }
+
+    /// Requests from a peer the filtered blocks mined since the oldest birthday of the accounts,
+    /// and applies them to the wallet.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the block headers could not be read, or if a filtered block could
+    /// not be received or applied.
+    fn apply_history(
+        &mut self,
+        stream: &mut TcpStream,
+        ui_sender: &Sender<UIMessage>,
+    ) -> Result<(), NodeError> {
+        let oldest_birthday = self
+            .accounts
+            .iter()
+            .map(|account| account.birthday)
+            .min()
+            .unwrap_or_default();
+        let (first_height, block_headers) =
+            read_block_headers_since(oldest_birthday.max(read_timestamp()?))?;
+        for (height, block_header) in (first_height..).zip(block_headers) {
+            let block_hash: BlockHash =
+                block_header.hash.as_slice().try_into().map_err(|_| {
+                    NodeError::FailedToConvert("Invalid block header hash".to_string())
+                })?;
+            let path = match BlockMessage::block_path_for_header(&block_header) {
+                Some(path) => path,
+                None => continue,
+            };
+            let (merkle_block, transactions) = fetch_merkle_block(
+                stream,
+                block_hash,
+                Duration::from_secs(MERKLE_BLOCK_TIMEOUT),
+            )?;
+            self.apply_merkle_block(&path, Some(height), &merkle_block, transactions, ui_sender)?;
+        }
+        Ok(())
+    }
+
     /// Sends to the UI the NewTransactionSent or NewTransactionReceived message, depending on the
     /// type of transaction received from the node.
     /// # Arguments
@@ -201,10 +614,7 @@ impl Wallet {
         ui_sender: &Sender<UIMessage>,
     ) -> Result<(), NodeError> {
         for tx_input in transaction.tx_inputs.iter() {
-            if account
-                .utxo_set
-                .contains_key(&tx_input.previous_output.tx_id)
-            {
+            if account.output(&tx_input.previous_output).is_some() {
                 let amount = transaction.amount_spent_by_account(account).to_string();
                 ui_sender
                     .send(UIMessage::NewTransactionSent(transaction.clone(), amount))
@@ -281,8 +691,20 @@ impl Wallet {
             .find(|account| account.bitcoin_address.bs58_to_string() == address)
     }
 
+    /// Checks if an address is the address of one of the accounts of the wallet, so the wallet
+    /// controls the coins sent to it.
+    /// # Arguments
+    /// * `address` - The address to check, in base58.
+    /// # Returns
+    /// Returns `true` if an account of the wallet has the address.
+    pub fn owns_address(&self, address: &str) -> bool {
+        self.accounts
+            .iter()
+            .any(|account| account.bitcoin_address.bs58_to_string() == address)
+    }
+
     /// Creates a new transaction from the specified base address to the target address
-    /// with the given amount.
+    /// with the given amount. The change is sent as the change address strategy of the wallet says.
     ///
     /// # Arguments
     ///
@@ -313,26 +735,243 @@ impl Wallet {
             None => return Err(NodeError::AccountNotFound("Account not found".to_string())),
         };
 
-        account.create_transaction(target_address_str, amount, fee)
+        let change_address = self.change_address(account);
+        account.create_transaction_with_change(target_address_str, amount, fee, &change_address)
+    }
+
+    /// Creates a new transaction that pools the UTXOs of every account of the wallet.
+    ///
+    /// The inputs are selected from the accounts in order, and each one is signed with the key
+    /// of the account that owns it. The change is returned to the first account, or to the change
+    /// account if the wallet has one.
+    ///
+    /// # Arguments
+    ///
+    /// * `targets` - The addresses to pay and the amount each one receives.
+    /// * `fee` - The fee to be paid for the transaction, on top of the amounts of the targets.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the signed `Transaction`, or a `NodeError` if the wallet has no
+    /// accounts, a target address is invalid or the accounts don't have enough coins.
+    pub fn create_transaction_across_accounts(
+        &self,
+        targets: &[(String, f64)],
+        fee: f64,
+    ) -> Result<Transaction, NodeError> {
+        let change_account = match self.accounts.first() {
+            Some(account) => account,
+            None => return Err(NodeError::AccountNotFound("Account not found".to_string())),
+        };
+        let amount = targets.iter().map(|(_, value)| value).sum::<f64>() + fee;
+
+        let mut tx_inputs = Vec::new();
+        let mut signers = Vec::new();
+        let mut value_spent = 0.0;
+        for account in self.accounts.iter() {
+            let unspent_outputs = account.unspent_outputs()?;
+            for tx_output in unspent_outputs
+                .iter()
+                .flat_map(|(_, tx_outputs)| tx_outputs)
+            {
+                if value_spent >= amount {
+                    break;
+                }
+                tx_inputs.push(TxInput::new_unsigned(
+                    &tx_output.tx_id,
+                    &(tx_output.index as u32),
+                    &[],
+                ));
+                signers.push((account, tx_output.pk_script.clone()));
+                value_spent += tx_output.value();
+            }
+        }
+        if value_spent < amount {
+            return Err(NodeError::NotEnoughCoins(
+                "Not enough coins to spend".to_string(),
+            ));
+        }
+
+        let mut tx_outputs = Vec::new();
+        for (target_address_str, value) in targets {
+            let target_address = BitcoinAddress::from_string(target_address_str)?;
+            let target_script = BitcoinAddress::to_pk_script(&target_address);
+            tx_outputs.push(TxOutput::new(
+                *value,
+                target_script,
+                tx_outputs.len() as u64,
+            ));
+        }
+        let change = value_spent - amount;
+        if change > 0.0 {
+            let change_script = BitcoinAddress::to_pk_script(&self.change_address(change_account));
+            tx_outputs.push(TxOutput::new(
+                change,
+                change_script,
+                tx_outputs.len() as u64,
+            ));
+        }
+
+        let mut transaction = Transaction::new_unsigned(tx_inputs, tx_outputs);
+        let mut script_sigs = Vec::new();
+        for (i, (account, pk_script)) in signers.iter().enumerate() {
+            script_sigs.push(account.create_script_sig(&mut transaction, i, pk_script)?);
+        }
+        transaction.add_script_sigs(script_sigs);
+        transaction.add_tx_id_to_tx_outs();
+
+        Ok(transaction)
     }
 
-    /// Given a path of a new block, searches the unconfirmed txs of the wallet and removes
+    /// Creates a single transaction paying the same amount to every address listed in a file, one
+    /// address per line. Empty lines are ignored.
+    ///
+    /// The transaction pools the UTXOs of every account of the wallet and is not broadcasted.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file with the addresses.
+    /// * `amount_each` - The amount each address receives.
+    /// * `fee` - The fee to be paid for the transaction, on top of the amounts of the addresses.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the signed `Transaction`, ready to be broadcasted.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError` if the file could not be read or has no addresses, if an address is
+    /// invalid, or a `NodeError::NotEnoughCoins` if the total exceeds the balance of the wallet.
+    pub fn send_batch_from_file(
+        &self,
+        path: &str,
+        amount_each: f64,
+        fee: f64,
+    ) -> Result<Transaction, NodeError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|_| NodeError::FailedToRead("Failed to read addresses file".to_string()))?;
+
+        let mut targets = Vec::new();
+        for line in contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            let address = line.to_string();
+            BitcoinAddress::from_string(&address)?;
+            targets.push((address, amount_each));
+        }
+        if targets.is_empty() {
+            return Err(NodeError::FailedToRead(
+                "The addresses file has no addresses".to_string(),
+            ));
+        }
+
+        let total = amount_each * targets.len() as f64 + fee;
+        if self.balances_for_user().iter().sum::<f64>() < total {
+            return Err(NodeError::NotEnoughCoins(
+                "Not enough coins to pay every address".to_string(),
+            ));
+        }
+
+        self.create_transaction_across_accounts(&targets, fee)
+    }
+
+    /// Creates a transaction that spends every UTXO of an account into a single output to another
+    /// address, paying a fee for the estimated size of the transaction.
+    ///
+    /// The transaction is not broadcasted.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_address` - The address of the account to empty.
+    /// * `to_address` - The address that receives the funds.
+    /// * `fee_rate` - The fee rate to pay, in satoshis per byte.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the signed `Transaction`, ready to be broadcasted.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::AccountNotFound` if the account is not in the wallet, a `NodeError` if
+    /// the destination address is invalid, or a `NodeError::NotEnoughCoins` if the account has no
+    /// funds or the fee would take all of them.
+    pub fn sweep(
+        &self,
+        from_address: String,
+        to_address: String,
+        fee_rate: f64,
+    ) -> Result<Transaction, NodeError> {
+        let account = self
+            .account_from_address(from_address)
+            .ok_or_else(|| NodeError::AccountNotFound("Account not found".to_string()))?;
+        let target_address = BitcoinAddress::from_string(&to_address)?;
+
+        let unspent_outputs = account.unspent_outputs()?;
+        let tx_outs_to_spend: Vec<&TxOutput> = unspent_outputs
+            .iter()
+            .flat_map(|(_, tx_outputs)| tx_outputs)
+            .collect();
+        if tx_outs_to_spend.is_empty() {
+            return Err(NodeError::NotEnoughCoins(
+                "The account has no funds to sweep".to_string(),
+            ));
+        }
+        let balance: i64 = tx_outs_to_spend
+            .iter()
+            .map(|tx_output| tx_output.value)
+            .sum();
+        let fee =
+            (Transaction::estimate_size(tx_outs_to_spend.len(), 1) as f64 * fee_rate).ceil() as i64;
+        if fee >= balance {
+            return Err(NodeError::NotEnoughCoins(format!(
+                "The fee of {} satoshis exceeds the balance of {} satoshis",
+                fee, balance
+            )));
+        }
+
+        let tx_inputs = tx_outs_to_spend
+            .iter()
+            .map(|tx_output| {
+                TxInput::new_unsigned(&tx_output.tx_id, &(tx_output.index as u32), &[])
+            })
+            .collect();
+        let mut tx_output = TxOutput::new(0.0, BitcoinAddress::to_pk_script(&target_address), 0);
+        tx_output.value = balance - fee;
+
+        let mut transaction = Transaction::new_unsigned(tx_inputs, vec![tx_output]);
+        account.sign_transaction(&mut transaction, TxOutput::pk_scripts(&tx_outs_to_spend))?;
+
+        Ok(transaction)
+    }
+
+    /// Given the transactions of a new block, searches the unconfirmed txs of the wallet and removes
     /// the ones that are included in the block, adding them to the confirmed txs.
     /// It sends a message to the UI with the new confirmed txs for the current account.
     /// # Arguments
     /// * `path` - The path of the new block.
+    /// * `height` - The height of the block, if it's known.
+    /// * `transactions` - The transactions of the block, or the ones a merkleblock proves.
     /// * `ui_sender` - The channel to send messages to the UI.
     /// # Returns
     /// Returns a Result containing Ok if the transactions were confirmed successfully, or a NodeError if an error occurs.
-    fn confirm_transactions(
+    fn confirm_block_transactions(
         &mut self,
         path: &String,
+        height: Option<u32>,
+        transactions: &[Transaction],
         ui_sender: &Sender<UIMessage>,
     ) -> Result<(), NodeError> {
         self.checked_blocks.push(path.to_string());
         let current_account = self.current_account()?.clone();
         for account in self.accounts.iter_mut() {
-            let confirmed_transactions = account.confirm_transactions(path, ui_sender)?;
+            let confirmed_transactions = account.confirm_block_transactions(
+                path,
+                height,
+                transactions.to_vec(),
+                ui_sender,
+            )?;
             if account.bitcoin_address() == current_account.bitcoin_address() {
                 ui_sender
                     .send(UIMessage::NewTransactionsConfirmed(
@@ -352,19 +991,101 @@ impl Wallet {
         self.checked_blocks.contains(block_path)
     }
 
-    /// Updates the UTXO set of the accounts in the wallet.
+    /// Applies a new block to the wallet: confirms the transactions of the accounts included in it,
+    /// drops the unconfirmed transactions older than `UNCONFIRMED_TX_MAX_AGE` and sends the UI the
+    /// unspent outputs of the current account, which the node already updated in its UTXO set.
+    /// Blocks that were already applied are skipped.
     /// # Arguments
     /// * `block_path` - The path of the new block.
+    /// * `ui_sender` - The channel to send messages to the UI.
+    /// # Returns
+    /// Returns a Result containing Ok if the block was applied successfully, or a NodeError if an error occurs.
+    pub fn apply_block(
+        &mut self,
+        block_path: &str,
+        ui_sender: &Sender<UIMessage>,
+    ) -> Result<(), NodeError> {
+        let block_path = block_path.to_string();
+        if self.has_block_been_checked(&block_path) {
+            return Ok(());
+        }
+        let transactions = retrieve_transactions_from_block(&block_path)?;
+        let height = retrieve_block_height(&block_path);
+        self.apply_block_transactions(&block_path, height, &transactions, ui_sender)?;
+        self.send_utxo_list(ui_sender)
+    }
+
+    /// Applies a new block the node only has the merkleblock (BIP37) of, in headers-only mode, as
+    /// `apply_block` does with a block saved on disk. Only the transactions proven by the
+    /// merkleblock are applied, the rest of the block is not relevant to the wallet. As the node has no
+    /// UTXO set in this mode, the accounts track the outputs of those transactions themselves.
+    /// Blocks that were already applied are skipped.
+    /// # Arguments
+    /// * `block_path` - The path the block would be saved to, which identifies it.
+    /// * `height` - The height of the block, if it is known from the header chain.
+    /// * `merkle_block` - The merkleblock of the block.
+    /// * `transactions` - The transactions the peer sent along with the merkleblock.
+    /// * `ui_sender` - The channel to send messages to the UI.
     /// # Returns
-    /// Returns a Result containing Ok if the UTXO set was updated successfully, or a NodeError if an error occurs.
-    pub fn update_accounts_utxo(&mut self, block_path: &String) -> Result<(), NodeError> {
+    /// Returns a Result containing Ok if the block was applied successfully, or a NodeError if the merkleblock
+    /// is invalid or an error occurs.
+    pub fn apply_merkle_block(
+        &mut self,
+        block_path: &str,
+        height: Option<u32>,
+        merkle_block: &MerkleBlockMessage,
+        transactions: Vec<Transaction>,
+        ui_sender: &Sender<UIMessage>,
+    ) -> Result<(), NodeError> {
+        let block_path = block_path.to_string();
+        if self.has_block_been_checked(&block_path) {
+            return Ok(());
+        }
+        let matched_tx_ids = merkle_block.matched_tx_ids()?;
+        let transactions: Vec<Transaction> = transactions
+            .into_iter()
+            .filter(|tx| matched_tx_ids.contains(&tx.tx_id()))
+            .collect();
+        self.apply_block_transactions(&block_path, height, &transactions, ui_sender)?;
         for account in self.accounts.iter_mut() {
-            account.update_utxo(block_path)?;
+            account.update_utxo_with(
+                &block_path,
+                merkle_block.block_header.timestamp,
+                transactions.clone(),
+            );
         }
+        self.send_utxo_list(ui_sender)
+    }
 
+    /// Confirms the transactions of the accounts among the given transactions of a block and drops the
+    /// unconfirmed transactions older than `UNCONFIRMED_TX_MAX_AGE`.
+    fn apply_block_transactions(
+        &mut self,
+        block_path: &String,
+        height: Option<u32>,
+        transactions: &[Transaction],
+        ui_sender: &Sender<UIMessage>,
+    ) -> Result<(), NodeError> {
+        self.confirm_block_transactions(block_path, height, transactions, ui_sender)?;
+        let now = current_timestamp()?;
+        for account in self.accounts.iter_mut() {
+            account.prune_stale_unconfirmed(now, UNCONFIRMED_TX_MAX_AGE, ui_sender)?;
+        }
         Ok(())
     }
 
+    /// Sends the unspent outputs of the current account to the UI.
+    /// # Arguments
+    /// * `ui_sender` - The channel to send messages to the UI.
+    /// # Returns
+    /// Returns a Result containing Ok if the message was sent successfully, or a NodeError if an error occurs.
+    fn send_utxo_list(&self, ui_sender: &Sender<UIMessage>) -> Result<(), NodeError> {
+        let utxos = self.current_account()?.list_utxos()?;
+        ui_sender
+            .send(UIMessage::UtxoList(utxos))
+            .map_err(|_| NodeError::FailedToSendMessage("Error sending message to UI".to_string()))
+    }
+
     /// Handles the communication between the wallet and the node.
     ///
     /// # Arguments
@@ -397,13 +1118,27 @@ impl Wallet {
                         let mut wallet_locked: std::sync::MutexGuard<'_, Wallet> = wallet
                             .lock()
                             .map_err(|e| NodeError::FailedToLockWallet(e.to_string()))?;
-                        if !wallet_locked.has_block_been_checked(&block_path) {
-                            wallet_locked.confirm_transactions(&block_path, &ui_sender)?;
-                            wallet_locked.update_accounts_utxo(&block_path)?;
-                        }
+                        wallet_locked.apply_block(&block_path, &ui_sender)?;
+                    }
+                    NewMerkleBlock(block_path, merkle_block, transactions) => {
+                        let mut wallet_locked: std::sync::MutexGuard<'_, Wallet> = wallet
+                            .lock()
+                            .map_err(|e| NodeError::FailedToLockWallet(e.to_string()))?;
+                        wallet_locked.apply_merkle_block(
+                            &block_path,
+                            None,
+                            &merkle_block,
+                            transactions,
+                            &ui_sender,
+                        )?;
                     }
-                    CreateNewAccount(wallet_account_info, utxo_set) => {
-                        Self::create_account(&wallet, utxo_set, wallet_account_info, &ui_sender)?;
+                    CreateNewAccount(wallet_account_info, utxo_set_arc) => {
+                        Self::create_account(
+                            &wallet,
+                            &utxo_set_arc,
+                            wallet_account_info,
+                            &ui_sender,
+                        )?;
                     }
                 },
                 Err(e) => {
@@ -419,7 +1154,7 @@ impl Wallet {
     ///
     /// * `wallet` - The wallet instance in an Arc Mutex.
     /// * `ui_receiver` - The receiver channel for receiving messages from the UI.
-    /// * `peer` - The peer connection to the node.
+    /// * `peers` - The connections to the peers transactions are broadcasted to.
     /// * `ui_sender` - The sender channel for sending messages from the wallet to the UI.
     /// * `utxo_set_arc` - The UTXO set of the node, inside an Arc Mutex.
     ///
@@ -429,10 +1164,11 @@ impl Wallet {
     fn handle_ui_connection(
         wallet: Arc<Mutex<Wallet>>,
         ui_receiver: mpsc::Receiver<UIMessage>,
-        peer: &mut TcpStream,
+        peers: &mut [TcpStream],
         wallet_node_sender: Sender<UIMessage>,
         utxo_set: Arc<Mutex<UtxoSet>>,
     ) -> Result<(), NodeError> {
+        let mut drafts = HashMap::new();
         loop {
             let message = ui_receiver.recv().map_err(|_| {
                 NodeError::FailedToRead("Failed to read msg from ui in wallet".to_string())
@@ -440,24 +1176,73 @@ impl Wallet {
 
             match message {
                 UIMessage::CreateNewTransaction(target_address, amount, fee) => {
-                    Self::create_and_broadcast_tx(&wallet, target_address, amount, fee, peer)?;
+                    Self::create_transaction_draft(
+                        &wallet,
+                        target_address,
+                        amount,
+                        fee,
+                        &mut drafts,
+                        &wallet_node_sender,
+                    )?;
+                }
+                UIMessage::ConfirmSend(tx_id) => {
+                    Self::confirm_send(&mut drafts, &tx_id, peers, &wallet_node_sender)?;
+                }
+                UIMessage::CancelSend(tx_id) => {
+                    drafts.remove(&tx_id);
                 }
                 UIMessage::AddAccount(account_info) => {
                     Self::add_account_to_wallet(
                         account_info.clone(),
                         &wallet,
                         &utxo_set,
+                        peers,
                         &wallet_node_sender,
                     )?;
                 }
                 UIMessage::AccountChanged(account_info) => {
                     Self::change_account(&wallet, account_info, &wallet_node_sender)?;
                 }
+                UIMessage::ExportHistory => {
+                    Self::export_history(&wallet, &wallet_node_sender);
+                }
                 _ => {}
             }
         }
     }
 
+    /// Writes the transaction history of the current account to `history_<address>.csv`, and tells the UI where it
+    /// was written or why it could not be.
+    /// # Arguments
+    /// * `wallet` - The wallet instance wrapped in an arc mutex.
+    /// * `wallet_node_sender` - The sender channel for sending messages from the wallet to the UI.
+    fn export_history(wallet: &Arc<Mutex<Wallet>>, wallet_node_sender: &Sender<UIMessage>) {
+        let history = match wallet.lock() {
+            Ok(wallet) => wallet.current_account().and_then(|account| {
+                Ok((
+                    account.bitcoin_address.bs58_to_string(),
+                    account.export_history_csv()?,
+                ))
+            }),
+            Err(_) => Err(NodeError::WalletMutexError(
+                "Failed to lock wallet".to_string(),
+            )),
+        };
+        let message = match history {
+            Ok((address, csv)) => {
+                let path = format!("{}{}.csv", HISTORY_CSV_PREFIX, address);
+                match fs::write(&path, csv) {
+                    Ok(_) => format!("Exported the transaction history to {}", path),
+                    Err(e) => format!("Failed to export the transaction history: {}", e),
+                }
+            }
+            Err(e) => format!("Failed to export the transaction history: {:?}", e),
+        };
+        wallet_node_sender
+            .send(UIMessage::NotificationMessage(message))
+            .unwrap_or_else(|_| println!("Failed to send the history export to ui"));
+    }
+
     /// Changes the current account to the one specified. This is done by moving the account to the head of the
     /// accounts vector.
     /// # Arguments
@@ -488,13 +1273,14 @@ impl Wallet {
             wallet_node_sender
                 .send(UIMessage::NewCurrentAccount(
                     current_account.copy(),
-                    account_info,
+                    account_info.clone(),
                 ))
                 .map_err(|_| {
                     NodeError::FailedToChangeAccount(
                         "Failed to send account changed to ui".to_string(),
                     )
                 })?;
+            wallet.send_receive_qr(&account_info, wallet_node_sender);
         }
 
         Ok(())
@@ -502,12 +1288,14 @@ impl Wallet {
 
     /// Runs the wallet for the user. Creates it from the login information, and then handles
     /// the communication between the wallet and the node, and the wallet and the GTK UI.
+    /// The accounts are saved every WALLET_AUTOSAVE_SECS seconds.
     /// # Arguments
     /// * `utxo_set_arc` - The UTXO set for the wallet, inside an Arc Mutex to be shared between threads.
     /// * `node_channel` - The channel for communication with the node.
     /// * `ui_receiver` - The receiver channel for receiving messages from the GTK UI.
     /// * `ui_sender` - The sender channel for sending messages to the GTK UI.
-    /// * `peer` - The peer to send transactions to.
+    /// * `peers` - The peers to send transactions to.
+    /// * `logger` - The logger of the connection used to scan the wallet history.
     /// # Returns
     /// Returns `Ok(())` if the function completes successfully, or an `Err` if there was an error.
     pub fn run_wallet(
@@ -515,23 +1303,46 @@ impl Wallet {
         node_channel: WalletChannel,
         ui_receiver: mpsc::Receiver<UIMessage>,
         ui_sender: Sender<UIMessage>,
-        peer: &mut TcpStream,
+        peers: &mut [TcpStream],
+        logger: Logger,
     ) -> Result<(), NodeError> {
-        let wallet =
-            Self::create_wallet_from_login(&ui_receiver, &utxo_set_arc, ui_sender.clone())?;
+        let mut wallet = Self::create_wallet_from_login(
+            &ui_receiver,
+            &utxo_set_arc,
+            ui_sender.clone(),
+            retrieve_confirmation_depth(),
+        )?;
+        wallet.configure_change_account(retrieve_change_account());
+        wallet.load_bloom_filter(peers);
+        Self::request_mempool(peers, &logger);
+        wallet.fetch_history(peers, &logger, &ui_sender);
 
         let wallet_arc = Arc::new(Mutex::new(wallet));
+        let _ = RUNNING_WALLET.set(Arc::clone(&wallet_arc));
+        let autosave_secs = retrieve_wallet_autosave_secs();
+        if autosave_secs > 0 {
+            Self::spawn_autosave(
+                &wallet_arc,
+                SAVED_ACCOUNTS.to_string(),
+                Duration::from_secs(autosave_secs),
+            );
+        }
         let cloned_wallet_arc = Arc::clone(&wallet_arc);
-        let mut cloned_peer = peer
-            .try_clone()
-            .map_err(|_| NodeError::FailedToConnect("Failed to clone peer stream".to_string()))?;
+        let mut cloned_peers = peers
+            .iter()
+            .map(|peer| {
+                peer.try_clone().map_err(|_| {
+                    NodeError::FailedToConnect("Failed to clone peer stream".to_string())
+                })
+            })
+            .collect::<Result<Vec<TcpStream>, NodeError>>()?;
         let sender = ui_sender.clone();
 
         thread::spawn(move || {
             Wallet::handle_ui_connection(
                 cloned_wallet_arc,
                 ui_receiver,
-                &mut cloned_peer,
+                &mut cloned_peers,
                 sender,
                 Arc::clone(&utxo_set_arc),
             )
@@ -556,6 +1367,7 @@ impl Wallet {
         tx: Transaction,
         ui_sender: &Sender<UIMessage>,
     ) -> Result<(), NodeError> {
+        self.check_tx_conflicts(&tx, ui_sender)?;
         let user_addresses = self.bitcoin_addresses();
         for address in user_addresses {
             if tx.contains_address(&address) {
@@ -565,17 +1377,72 @@ impl Wallet {
         Ok(())
     }
 
+    /// Returns the ids of the unconfirmed transactions of the wallet that spend at least one of
+    /// the outpoints spent by the given transaction.
+    /// # Arguments
+    /// * `tx` - The new transaction received from the node.
+    /// # Returns
+    /// A vector with the ids of the conflicting unconfirmed transactions, without repetitions.
+    pub fn conflicting_transactions(&self, tx: &Transaction) -> Vec<TxHash> {
+        let new_tx_id = tx.tx_id();
+        let mut conflicts: Vec<TxHash> = Vec::new();
+        for account in &self.accounts {
+            for unconfirmed_tx in account.unconfirmed_transactions.all_txs() {
+                let old_tx_id = unconfirmed_tx.tx_id();
+                if old_tx_id == new_tx_id || conflicts.contains(&old_tx_id) {
+                    continue;
+                }
+                if unconfirmed_tx.conflicts_with(tx) {
+                    conflicts.push(old_tx_id);
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Checks if a transaction conflicts with an unconfirmed transaction of the wallet (possible
+    /// double-spend or replacement) and in that case sends the TransactionConflict message to the UI.
+    ///
+    /// # Arguments
+    ///* `tx` - A `Transaction` representing the transaction to check.
+    ///* ui_sender - The sender channel for sending messages from the wallet to GTK UI.
+    /// # Errors
+    ///
+    /// Returns an `Err` variant of `NodeError` if the message could not be sent to the UI.
+    fn check_tx_conflicts(
+        &self,
+        tx: &Transaction,
+        ui_sender: &Sender<UIMessage>,
+    ) -> Result<(), NodeError> {
+        for old_tx_id in self.conflicting_transactions(tx) {
+            println!(
+                "Transaction {:?} conflicts with an unconfirmed transaction",
+                tx.tx_id()
+            );
+            ui_sender
+                .send(UIMessage::TransactionConflict(old_tx_id, tx.tx_id()))
+                .map_err(|_| {
+                    NodeError::FailedToSendMessage(
+                        "Failed to send transaction conflict to ui".to_string(),
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
     /// Creates a new wallet from the login information.
     /// # Arguments
     /// * `ui_receiver` - The receiver channel for receiving messages from the GTK UI.
     /// * `utxo_set_arc` - The UTXO set for the wallet, inside an Arc Mutex to be shared between threads.
     /// * `ui_sender` - The sender channel for sending messages from the wallet to GTK UI.
+    /// * `confirmation_depth` - The number of confirmations a transaction needs to be shown as confirmed.
     /// # Returns
     /// Returns `Ok(())` if the function completes successfully, or an `Err` if there was an error.
     fn create_wallet_from_login(
         ui_receiver: &mpsc::Receiver<UIMessage>,
         utxo_set_arc: &Arc<Mutex<UtxoSet>>,
         ui_sender: Sender<UIMessage>,
+        confirmation_depth: u32,
     ) -> Result<Wallet, NodeError> {
         println!("Creating wallet...");
         let wallet = loop {
@@ -586,13 +1453,19 @@ impl Wallet {
             match ui_message {
                 UIMessage::AddAccount(account_info) => {
                     println!("Received login message from UI");
-                    let wallet =
-                        Wallet::initialize_wallet_for_user(utxo_set_arc, &account_info, &ui_sender)
-                            .map_err(|_| {
-                                NodeError::FailedToCreateWallet(
-                                    "Failed to create wallet".to_string(),
-                                )
-                            })?;
+                    let mut wallet = Wallet::initialize_wallet_for_user(
+                        utxo_set_arc,
+                        &account_info,
+                        &ui_sender,
+                        confirmation_depth,
+                    )
+                    .map_err(|_| {
+                        NodeError::FailedToCreateWallet("Failed to create wallet".to_string())
+                    })?;
+                    wallet.read_saved_accounts(SAVED_ACCOUNTS)?;
+                    if let Err(e) = wallet.save_account(account_info, SAVED_ACCOUNTS) {
+                        println!("Failed to save the account: {:?}", e);
+                    }
                     break wallet;
                 }
                 UIMessage::AddAccountsFromAppStart(accounts_info) => {
@@ -600,6 +1473,7 @@ impl Wallet {
                         utxo_set_arc,
                         accounts_info,
                         &ui_sender,
+                        confirmation_depth,
                     )
                     .map_err(|_| {
                         NodeError::FailedToCreateWallet("Failed to create wallet".to_string())
@@ -613,7 +1487,8 @@ impl Wallet {
         Ok(wallet)
     }
 
-    /// Creates and broadcasts a transaction, sending it to the peer and UI.
+    /// Creates a transaction and sends it to the UI as a draft, which is only broadcasted when the
+    /// user confirms it with a `UIMessage::ConfirmSend`.
     ///
     /// # Arguments
     ///
@@ -621,42 +1496,140 @@ impl Wallet {
     /// * `target_address` - The target address for the transaction.
     /// * `amount` - The amount to send in the transaction.
     /// * `fee` - The transaction fee.
-    /// * `peer` - A mutable reference to the `TcpStream` for broadcasting the transaction.
+    /// * `drafts` - The transactions waiting to be confirmed, by their id.
     /// * `ui_sender` - A reference to the `Sender<UIMessage>` for sending UI messages.
     ///
     /// # Returns
     ///
-    /// An `Ok(())` result if the transaction was created, broadcasted, and sent to the UI,
-    /// or a `Result<(), NodeError>` indicating the error encountered.
+    /// An `Ok(())` result if the draft or the failure was sent to the UI, or a
+    /// `Result<(), NodeError>` indicating the error encountered.
     ///
     /// # Remarks
     ///
-    /// This function obtains the current address from the wallet using the `obtain_current_address` method.
-    /// It then locks the wallet, creates a transaction using the `create_transaction` method,
-    /// broadcasts the transaction to the peer using the `broadcast_transaction` function,
-    /// and sends the transaction to the UI using the `UIMessage::NewTransactionToBeSent` message.
-    fn create_and_broadcast_tx(
+    /// If the current account doesn't have enough coins, the UI receives a
+    /// `UIMessage::InsufficientFunds` with the balance of the account and the requested amount.
+    /// Any other failure is sent to the UI as a `UIMessage::SendFailed`.
+    fn create_transaction_draft(
         wallet: &Arc<Mutex<Wallet>>,
         target_address: String,
         amount: f64,
         fee: f64,
-        peer: &mut TcpStream,
+        drafts: &mut HashMap<TxHash, TransactionDraft>,
+        ui_sender: &Sender<UIMessage>,
     ) -> Result<(), NodeError> {
         let my_address = Self::obtain_current_address(wallet)?;
-        let transaction = wallet
-            .lock()
-            .map_err(|_| NodeError::FailedToCreateTransaction("Failed to lock wallet".to_string()))?
-            .create_transaction(my_address, &target_address, amount, fee)?;
+        let ui_message = match Self::try_create_transaction_draft(
+            wallet,
+            my_address.clone(),
+            target_address,
+            amount,
+            fee,
+        ) {
+            Ok(draft) => {
+                let tx_id = draft.transaction.tx_id();
+                let ui_message = UIMessage::TransactionDraft(tx_id.clone(), fee, draft.outputs());
+                drafts.insert(tx_id, draft);
+                ui_message
+            }
+            Err(NodeError::NotEnoughCoins(_)) => {
+                let wallet = wallet
+                    .lock()
+                    .map_err(|e| NodeError::FailedToLockWallet(e.to_string()))?;
+                let available = wallet
+                    .account_from_address(my_address)
+                    .map_or(0.0, |account| account.balance_for_user());
+                UIMessage::InsufficientFunds(available, amount)
+            }
+            Err(e) => UIMessage::SendFailed(format!("{:?}", e)),
+        };
+
+        ui_sender.send(ui_message).map_err(|_| {
+            NodeError::FailedToSendMessage("Failed to send the transaction draft to UI".to_string())
+        })
+    }
+
+    /// Creates a transaction from the given address, without broadcasting it.
+    ///
+    /// # Arguments
+    ///
+    /// * `wallet` - An `Arc<Mutex<Wallet>>` representing the wallet.
+    /// * `my_address` - The address of the account that sends the transaction.
+    /// * `target_address` - The target address for the transaction.
+    /// * `amount` - The amount to send in the transaction.
+    /// * `fee` - The transaction fee.
+    ///
+    /// # Returns
+    ///
+    /// The draft of the transaction, or the `NodeError` encountered, such as a
+    /// `NodeError::TransactionTooLarge` if the signed transaction is larger than `MAX_TX_SIZE`.
+    fn try_create_transaction_draft(
+        wallet: &Arc<Mutex<Wallet>>,
+        my_address: String,
+        target_address: String,
+        amount: f64,
+        fee: f64,
+    ) -> Result<TransactionDraft, NodeError> {
+        let wallet = wallet.lock().map_err(|_| {
+            NodeError::FailedToCreateTransaction("Failed to lock wallet".to_string())
+        })?;
+        let transaction =
+            wallet.create_transaction(my_address.clone(), &target_address, amount, fee)?;
+        check_transaction_size(&transaction, retrieve_max_tx_size())?;
+        let account = wallet
+            .account_from_address(my_address)
+            .ok_or_else(|| NodeError::AccountNotFound("Account not found".to_string()))?;
         println!(
             "Created tx: {:?} to address: {:?}",
             transaction.tx_id(),
             target_address
         );
-        broadcast_transaction(transaction, peer)?;
 
-        Ok(())
+        Ok(TransactionDraft {
+            transaction,
+            spent_outputs: account.unspent_outputs()?,
+        })
+    }
+
+    /// Broadcasts a transaction draft confirmed by the user, discarding it.
+    ///
+    /// # Arguments
+    ///
+    /// * `drafts` - The transactions waiting to be confirmed, by their id.
+    /// * `tx_id` - The id of the confirmed transaction.
+    /// * `peers` - The connections to the peers the transaction can be broadcasted to.
+    /// * `ui_sender` - A reference to the `Sender<UIMessage>` for sending UI messages.
+    ///
+    /// # Returns
+    ///
+    /// An `Ok(())` result if the transaction was broadcasted or the failure was reported to the UI
+    /// as a `UIMessage::SendFailed`, or the `NodeError` encountered.
+    fn confirm_send(
+        drafts: &mut HashMap<TxHash, TransactionDraft>,
+        tx_id: &TxHash,
+        peers: &mut [TcpStream],
+        ui_sender: &Sender<UIMessage>,
+    ) -> Result<(), NodeError> {
+        let result = match drafts.remove(tx_id) {
+            Some(draft) => broadcast_transaction(draft.transaction, &draft.spent_outputs, peers),
+            None => Err(NodeError::FailedToCreateTransaction(
+                "The transaction is not waiting to be sent".to_string(),
+            )),
+        };
+        match result {
+            Ok(peers_reached) => {
+                println!("Broadcasted tx to {} peers", peers_reached);
+                Ok(())
+            }
+            Err(e) => ui_sender
+                .send(UIMessage::SendFailed(format!("{:?}", e)))
+                .map_err(|_| {
+                    NodeError::FailedToSendMessage(
+                        "Failed to send the send failure to UI".to_string(),
+                    )
+                }),
+        }
     }
-    /// Adds an account to the wallet
+    /// Adds an account to the wallet and saves it to the saved accounts file.
     ///
     /// # Arguments
     ///
@@ -664,6 +1637,7 @@ impl Wallet {
     /// * `account_info` - The account information to be added to the wallet.
     /// * `wallet` - An `Arc<Mutex<Wallet>>` representing the wallet.
     /// * `utxo_set_arc` - An `Arc<Mutex<UtxoSet>>` representing the UTXO set.
+    /// * `peers` - The peers the bloom filter of the wallet is loaded in, in headers-only mode.
     ///
     /// # Returns
     ///
@@ -673,15 +1647,17 @@ impl Wallet {
         account_info: AccountInfo,
         wallet: &Arc<Mutex<Wallet>>,
         utxo_set_arc: &Arc<Mutex<UtxoSet>>,
+        peers: &mut [TcpStream],
         wallet_node_sender: &Sender<UIMessage>,
     ) -> Result<(), NodeError> {
         let mut wallet_lock = wallet
             .lock()
             .map_err(|_| NodeError::FailedToSendMessage("Failed to lock wallet".to_string()))?;
-        let utxo_lock = utxo_set_arc
-            .lock()
-            .map_err(|_| NodeError::FailedToSendMessage("Failed to lock utxo set".to_string()))?;
-        wallet_lock.add_account(&utxo_lock, account_info, wallet_node_sender)?;
+        wallet_lock.add_account(utxo_set_arc, account_info.clone(), wallet_node_sender)?;
+        wallet_lock.load_bloom_filter(peers);
+        if let Err(e) = wallet_lock.save_account(account_info, SAVED_ACCOUNTS) {
+            println!("Failed to save the account: {:?}", e);
+        }
         Ok(())
     }
 
@@ -702,6 +1678,37 @@ impl Wallet {
         Ok(my_address)
     }
 
+    /// Returns the BIP21 URI of the address of the current account, the data of the QR code the
+    /// UI shows to receive payments.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount` - The amount requested, in BTC.
+    /// * `label` - A label for the address.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `NodeError::FailedToObtainAccount` if the wallet has no accounts.
+    pub fn address_qr_data(
+        &self,
+        amount: Option<f64>,
+        label: Option<&str>,
+    ) -> Result<String, NodeError> {
+        Ok(self
+            .current_account()?
+            .bitcoin_address
+            .to_uri(amount, label))
+    }
+
+    /// Sends the UI the receive QR data of the current account, labeled with its name.
+    ///
+    /// The QR code is not essential, so failing to send it is not an error.
+    fn send_receive_qr(&self, account_info: &AccountInfo, ui_sender: &Sender<UIMessage>) {
+        if let Ok(uri) = self.address_qr_data(None, Some(&account_info.name)) {
+            let _ = ui_sender.send(UIMessage::ShowReceiveQr(uri));
+        }
+    }
+
     /// Returns the first account in the wallet, this is the current account.
     pub fn current_account(&self) -> Result<&Account, NodeError> {
         self.accounts
@@ -715,7 +1722,7 @@ impl Wallet {
     /// # Arguments
     ///
     /// * `wallet` - An `Arc<Mutex<Wallet>>` representing the wallet.
-    /// * `utxo_set` - The UTXO (Unspent Transaction Output) set.
+    /// * `utxo_set_arc` - The UTXO (Unspent Transaction Output) set of the node, shared with the account.
     /// * `wallet_account_info` - The information of the new account to be added to the wallet.
     /// * `wallet_node_sender` - A reference to the `Sender<UIMessage>` for sending UI messages.
     ///
@@ -728,10 +1735,10 @@ impl Wallet {
     ///
     /// This function prints a log message indicating the addition of a new account to the wallet.
     /// It locks the `wallet` and calls the `add_account` method on the locked wallet,
-    /// passing the `utxo_set`, extracted Bitcoin address, and extracted private key from `wallet_account_info` as arguments.
+    /// passing the `utxo_set_arc`, extracted Bitcoin address, and extracted private key from `wallet_account_info` as arguments.
     fn create_account(
         wallet: &Arc<Mutex<Wallet>>,
-        utxo_set: UtxoSet,
+        utxo_set_arc: &Arc<Mutex<UtxoSet>>,
         wallet_account_info: AccountInfo,
         wallet_node_sender: &Sender<UIMessage>,
     ) -> Result<(), NodeError> {
@@ -739,7 +1746,7 @@ impl Wallet {
         let mut wallet_locked = wallet
             .lock()
             .map_err(|_| NodeError::WalletMutexError("Failed to lock wallet".to_string()))?;
-        wallet_locked.add_account(&utxo_set, wallet_account_info, wallet_node_sender)?;
+        wallet_locked.add_account(utxo_set_arc, wallet_account_info, wallet_node_sender)?;
         Ok(())
     }
 }
@@ -747,11 +1754,19 @@ impl Wallet {
 #[cfg(test)]
 mod test {
 
+    use std::{cell::RefCell, io::Read, net::TcpListener, path::Path, rc::Rc};
+
     use glib::Receiver;
 
     use crate::{
-        block::retrieve_transactions_from_block,
-        transactions::{tx_input::TxInput, tx_output::TxOutput},
+        block::{block_hash::BlockHash, Block},
+        connectors::peer_connector::send_message,
+        constants::{ACCOUNT_HISTORY_PREFIX, COMMAND_NAME_MERKLEBLOCK, DEFAULT_CONFIRMATION_DEPTH},
+        header::Header,
+        messages::{get_data_message::GetDataMessage, inv_message::InvType, tx_message::TxMessage},
+        node::receive_messages::fetch_merkle_block,
+        transactions::script_interpreter::verify_p2pkh_input,
+        wallet::bitcoin_address::BitcoinAddress,
     };
 
     use super::*;
@@ -777,6 +1792,7 @@ mod test {
             &Arc::new(Mutex::new(utxo_set.clone())),
             &wallet_info,
             &wallet_node_sender,
+            DEFAULT_CONFIRMATION_DEPTH,
         )
         .unwrap();
 
@@ -786,7 +1802,11 @@ mod test {
             "a".to_string(),
         );
         wallet
-            .add_account(&utxo_set, new_account, &wallet_node_sender)
+            .add_account(
+                &Arc::new(Mutex::new(utxo_set)),
+                new_account,
+                &wallet_node_sender,
+            )
             .unwrap();
 
         assert!(wallet
@@ -797,6 +1817,62 @@ mod test {
         wallet_node_receiver.attach(None, move |_| glib::Continue(true));
     }
 
+    #[test]
+    fn test_autosave_writes_only_the_saved_accounts() -> Result<(), NodeError> {
+        let path = "test_autosave_accounts.txt";
+        fs::write(path, "mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz;a;unloaded\n")
+            .map_err(|_| NodeError::FailedToWrite("Failed to write accounts".to_string()))?;
+        let utxo_set_arc = Arc::new(Mutex::new(UtxoSet::new()));
+        let (ui_sender, _ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+        let login_account = AccountInfo::new_from_values(
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string(),
+            "login_key".to_string(),
+            "login".to_string(),
+        );
+        let mut wallet = Wallet::initialize_wallet_for_user(
+            &utxo_set_arc,
+            &login_account,
+            &ui_sender,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        wallet.read_saved_accounts(path)?;
+        for (address, name) in [
+            ("mtEoVpBV5H8bbmNDEPwaoJHXnF1MxbkkQf", "second"),
+            ("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC", "removed"),
+        ] {
+            let account_info = AccountInfo::new_from_values(
+                address.to_string(),
+                "a".to_string(),
+                name.to_string(),
+            );
+            wallet.add_account(&utxo_set_arc, account_info.clone(), &ui_sender)?;
+            wallet.save_account(account_info, path)?;
+        }
+        wallet.remove_account(&"mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC".to_string())?;
+
+        let wallet = Arc::new(Mutex::new(wallet));
+        Wallet::spawn_autosave(&wallet, path.to_string(), Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(200));
+        drop(wallet);
+        thread::sleep(Duration::from_millis(50));
+        let saved = AccountInfo::read_all(path);
+        let _ = fs::remove_file(path);
+        let history_path = format!(
+            "{}mtEoVpBV5H8bbmNDEPwaoJHXnF1MxbkkQf.txt",
+            ACCOUNT_HISTORY_PREFIX
+        );
+        let history_saved = Path::new(&history_path).exists();
+        let _ = fs::remove_file(&history_path);
+
+        let names: Vec<String> = saved?
+            .into_iter()
+            .map(|account_info| account_info.name)
+            .collect();
+        assert_eq!(names, vec!["unloaded".to_string(), "second".to_string()]);
+        assert!(history_saved);
+        Ok(())
+    }
+
     #[test]
     fn test_receive_tx() -> Result<(), NodeError> {
         let mut utxo_set = UtxoSet::new();
@@ -815,6 +1891,7 @@ mod test {
             &Arc::new(Mutex::new(utxo_set)),
             &wallet_info,
             &wallet_node_sender,
+            DEFAULT_CONFIRMATION_DEPTH,
         )
         .unwrap();
 
@@ -842,6 +1919,7 @@ mod test {
             tx_out_count: crate::compact_size::CompactSize::U16(1),
             tx_outputs: tx_output,
             lock_time: 0,
+            witnesses: Vec::new(),
         };
         let bc_address =
             BitcoinAddress::from_string(&"mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string()).unwrap();
@@ -878,6 +1956,7 @@ mod test {
             &Arc::new(Mutex::new(utxo_set)),
             &wallet_info,
             &wallet_node_sender,
+            DEFAULT_CONFIRMATION_DEPTH,
         )
         .unwrap();
 
@@ -891,7 +1970,8 @@ mod test {
             .unconfirmed_transactions
             .received
             .push(tx_unconfirmed.clone());
-        wallet.confirm_transactions(&block_path, &wallet_node_sender)?;
+        let transactions = retrieve_transactions_from_block(&block_path)?;
+        wallet.confirm_block_transactions(&block_path, None, &transactions, &wallet_node_sender)?;
         assert!(wallet.accounts[0].unconfirmed_transactions.received.len() == 0);
         assert!(wallet.accounts[0].confirmed_transactions.received.len() == 1);
         assert_eq!(
@@ -911,4 +1991,530 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_apply_block_confirms_transactions_and_updates_the_balance() -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin";
+        let wallet_info = AccountInfo::new_from_values(
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string(),
+            "a".to_string(),
+            "a".to_string(),
+        );
+        let (ui_sender, _ui_receiver): (Sender<UIMessage>, Receiver<UIMessage>) =
+            glib::MainContext::channel(glib::Priority::default());
+        let utxo_set_arc = Arc::new(Mutex::new(UtxoSet::new()));
+        let mut wallet = Wallet::initialize_wallet_for_user(
+            &utxo_set_arc,
+            &wallet_info,
+            &ui_sender,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        let address = wallet.accounts[0].bitcoin_address();
+        let received_tx = retrieve_transactions_from_block(&block_path.to_string())?
+            .into_iter()
+            .find(|tx| {
+                tx.tx_outputs
+                    .iter()
+                    .any(|tx_output| tx_output.contains_address(&address))
+            })
+            .ok_or(NodeError::FailedToRead(
+                "The block doesn't pay the address".to_string(),
+            ))?;
+        wallet.accounts[0].add_new_unconfirmed_transaction(received_tx.clone());
+        assert_eq!(wallet.accounts[0].balance_for_user(), 0.0);
+
+        utxo_set_arc
+            .lock()
+            .map_err(|_| NodeError::UtxoSetMutexError("Failed to lock utxo set".to_string()))?
+            .update(&block_path.to_string())?;
+        wallet.apply_block(block_path, &ui_sender)?;
+        wallet.apply_block(block_path, &ui_sender)?;
+
+        let account = &wallet.accounts[0];
+        assert!(wallet.has_block_been_checked(&block_path.to_string()));
+        assert!(account.unconfirmed_transactions.received.is_empty());
+        assert_eq!(account.confirmed_transactions.received.len(), 1);
+        assert_eq!(
+            account.confirmed_transactions.received[0].tx_id(),
+            received_tx.tx_id()
+        );
+        assert_eq!(account.balance_for_user(), 0.02432823);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merkle_block_from_a_peer_confirms_the_filtered_transaction() -> Result<(), NodeError> {
+        let block_path =
+            "blocks-test/0000000000000014e9428b9aa7427ec63e867030c1d77afeb1b182537e15be0a.bin";
+        let block = Block::from_bytes(
+            &fs::read(block_path)
+                .map_err(|_| NodeError::FailedToRead("Failed to read block".to_string()))?,
+        )?;
+        let block_hash: BlockHash = block
+            .header
+            .hash()
+            .as_slice()
+            .try_into()
+            .map_err(|_| NodeError::FailedToConvert("Invalid block hash".to_string()))?;
+        let wallet_info = AccountInfo::new_from_values(
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string(),
+            "a".to_string(),
+            "a".to_string(),
+        );
+        let (ui_sender, _ui_receiver): (Sender<UIMessage>, Receiver<UIMessage>) =
+            glib::MainContext::channel(glib::Priority::default());
+        let mut wallet = Wallet::initialize_wallet_for_user(
+            &Arc::new(Mutex::new(UtxoSet::new())),
+            &wallet_info,
+            &ui_sender,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        let address = wallet.accounts[0].bitcoin_address();
+        let filter = wallet.bloom_filter()?;
+        let matches: Vec<bool> = block
+            .transactions
+            .iter()
+            .map(|tx| {
+                tx.tx_outputs
+                    .iter()
+                    .any(|tx_output| tx_output.contains_address(&address))
+            })
+            .collect();
+        let received_tx = match matches.iter().position(|matched| *matched) {
+            Some(position) => block.transactions[position].clone(),
+            None => {
+                return Err(NodeError::FailedToRead(
+                    "The block doesn't pay the address".to_string(),
+                ))
+            }
+        };
+        wallet.accounts[0].add_new_unconfirmed_transaction(received_tx.clone());
+        let tx_ids: Vec<TxHash> = block.transactions.iter().map(|tx| tx.tx_id()).collect();
+        let merkle_block = MerkleBlockMessage::new(block.header.clone(), &tx_ids, &matches);
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToConnect("Failed to bind listener".to_string()))?;
+        let peer_addr = listener
+            .local_addr()
+            .map_err(|_| NodeError::FailedToConnect("Failed to get address".to_string()))?;
+        let peer_tx = received_tx.clone();
+        let peer = thread::spawn(move || -> Result<Vec<(InvType, BlockHash)>, NodeError> {
+            let (mut stream, _) = listener
+                .accept()
+                .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+            let mut header = Header::new(&mut stream)?;
+            let get_data = GetDataMessage::from_stream(&mut stream, &mut header)?;
+            let payload = merkle_block.to_bytes();
+            let mut bytes = Header::create_header(&payload, COMMAND_NAME_MERKLEBLOCK)?;
+            bytes.extend(payload);
+            send_message(&mut stream, bytes)?;
+            TxMessage::send_tx_message(&peer_tx, &mut stream)?;
+            Ok(get_data.items())
+        });
+
+        let mut stream = TcpStream::connect(peer_addr)
+            .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+        let (merkle_block, transactions) =
+            fetch_merkle_block(&mut stream, block_hash, Duration::from_secs(5))?;
+        let requested = peer
+            .join()
+            .map_err(|_| NodeError::FailedToJoinThread("Peer thread panicked".to_string()))??;
+        wallet.apply_merkle_block(block_path, None, &merkle_block, transactions, &ui_sender)?;
+
+        assert!(filter.contains(&BitcoinAddress::to_pk_hash(&address)));
+        assert_eq!(requested, vec![(InvType::FilteredBlock, block_hash)]);
+        let account = &wallet.accounts[0];
+        assert!(account.unconfirmed_transactions.received.is_empty());
+        assert_eq!(account.confirmed_transactions.received.len(), 1);
+        assert_eq!(
+            account.confirmed_transactions.received[0].tx_id(),
+            received_tx.tx_id()
+        );
+        assert_eq!(account.balance_for_user(), 0.02432823);
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicting_transactions() -> Result<(), NodeError> {
+        let utxo_set = UtxoSet::new();
+        let wallet_info = AccountInfo::new_from_values(
+            "mxVFsFW5N4mu1HPkxPttorvocvzeZ7KZyk".to_string(),
+            "a".to_string(),
+            "a".to_string(),
+        );
+        let (wallet_node_sender, wallet_node_receiver): (Sender<UIMessage>, Receiver<UIMessage>) =
+            glib::MainContext::channel(glib::Priority::default());
+        let mut wallet = Wallet::initialize_wallet_for_user(
+            &Arc::new(Mutex::new(utxo_set)),
+            &wallet_info,
+            &wallet_node_sender,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+
+        let create_tx = |output_value: i64| Transaction {
+            version: 1,
+            tx_in_count: crate::compact_size::CompactSize::U8(1),
+            tx_inputs: vec![TxInput {
+                previous_output: crate::transactions::outpoint::Outpoint {
+                    tx_id: vec![4; 32],
+                    index: 0,
+                },
+                script_bytes: crate::compact_size::CompactSize::U8(1),
+                signature_script: vec![1],
+                sequence: 0xffffffff,
+            }],
+            tx_out_count: crate::compact_size::CompactSize::U8(1),
+            tx_outputs: vec![TxOutput {
+                value: output_value,
+                pk_script: vec![0, 1, 2],
+                pk_script_bytes: crate::compact_size::CompactSize::U8(3),
+                tx_id: vec![],
+                index: 0,
+                block_path: String::new(),
+            }],
+            lock_time: 0,
+            witnesses: Vec::new(),
+        };
+        let old_tx = create_tx(1000);
+        let new_tx = create_tx(900);
+        wallet.accounts[0]
+            .unconfirmed_transactions
+            .received
+            .push(old_tx.clone());
+
+        assert_eq!(wallet.conflicting_transactions(&old_tx).len(), 0);
+        assert_eq!(
+            wallet.conflicting_transactions(&new_tx),
+            vec![old_tx.tx_id()]
+        );
+
+        wallet.check_tx_contains_addrs(new_tx.clone(), &wallet_node_sender)?;
+        let conflicts = Rc::new(RefCell::new(Vec::new()));
+        let received_conflicts = Rc::clone(&conflicts);
+        let context = glib::MainContext::new();
+        wallet_node_receiver.attach(Some(&context), move |msg| {
+            if let UIMessage::TransactionConflict(old_tx_id, new_tx_id) = msg {
+                received_conflicts.borrow_mut().push((old_tx_id, new_tx_id));
+            }
+            glib::Continue(true)
+        });
+        while context.iteration(false) {}
+
+        assert_eq!(*conflicts.borrow(), vec![(old_tx.tx_id(), new_tx.tx_id())]);
+
+        Ok(())
+    }
+
+    /// Returns an account with the UTXOs the given block pays to its address.
+    fn account_with_block(block_path: &str, address: &str, private_key: &str) -> Account {
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.update(&block_path.to_string()).unwrap();
+        Account::new(
+            &Arc::new(Mutex::new(utxo_set)),
+            address.to_string(),
+            private_key.to_string(),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )
+        .unwrap()
+    }
+
+    /// Returns the account of mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC, with the UTXOs of a test block.
+    fn first_funded_account() -> Account {
+        account_with_block(
+            "blocks-test/0000000000000005847b65f037ec3d08f499c3c22ae6723ffefee1adca3e9af5.bin",
+            "mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC",
+            "92GMMJkoBsXuzFNod6a8fgPFworara3HS6zgGHTFR1Xfo1c9Je5",
+        )
+    }
+
+    /// Returns the account of mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz, with the UTXOs of a test block.
+    fn second_funded_account() -> Account {
+        account_with_block(
+            "blocks-test/000000000000001c49d310478ff08742c26efb8f24d8756412996c51ed384a67.bin",
+            "mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz",
+            "9319Nrhiz9UD4EgeW3n18YpRTbcjTYkvS57b3WxX96P24bGFxHv",
+        )
+    }
+
+    /// Returns a wallet with the given accounts and no saved account information.
+    fn wallet_with_accounts(accounts: Vec<Account>) -> Wallet {
+        Wallet {
+            accounts,
+            checked_blocks: Vec::new(),
+            change_address_strategy: ChangeAddressStrategy::default(),
+            confirmation_depth: DEFAULT_CONFIRMATION_DEPTH,
+            saved_accounts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_create_transaction_across_accounts_spends_from_both() -> Result<(), NodeError> {
+        let first_account = first_funded_account();
+        let second_account = second_funded_account();
+        let fee = 0.0001;
+        let amount = first_account.balance_for_user() + second_account.balance_for_user() / 2.0;
+        let wallet = wallet_with_accounts(vec![first_account, second_account]);
+
+        let transaction = wallet.create_transaction_across_accounts(
+            &[(
+                "mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB".to_string(),
+                amount - fee,
+            )],
+            fee,
+        )?;
+
+        let mut signers = Vec::new();
+        for (i, tx_input) in transaction.tx_inputs.iter().enumerate() {
+            let outpoint = &tx_input.previous_output;
+            let (position, pk_script) = wallet
+                .accounts
+                .iter()
+                .enumerate()
+                .find_map(|(position, account)| {
+                    account
+                        .output(outpoint)
+                        .map(|tx_output| (position, tx_output.pk_script))
+                })
+                .ok_or_else(|| NodeError::FailedToRead("Unknown input".to_string()))?;
+            verify_p2pkh_input(&transaction, i, &pk_script)?;
+            signers.push(position);
+        }
+
+        assert!(signers.contains(&0));
+        assert!(signers.contains(&1));
+        assert_eq!(transaction.tx_outputs.len(), 2);
+        assert_eq!(
+            transaction.tx_outputs[1].pk_script,
+            BitcoinAddress::to_pk_script(&wallet.accounts[0].bitcoin_address)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_batch_from_file_pays_every_address() -> Result<(), NodeError> {
+        let account = first_funded_account();
+        let amount_each = account.balance_for_user() / 4.0;
+        let wallet = wallet_with_accounts(vec![account]);
+        let path = "test_send_batch_addresses.txt";
+        fs::write(
+            path,
+            "mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB\n\nmmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz\n",
+        )
+        .map_err(|_| NodeError::FailedToWrite("Failed to write addresses".to_string()))?;
+
+        let transaction = wallet.send_batch_from_file(path, amount_each, 0.0001);
+        let too_expensive = wallet.send_batch_from_file(path, amount_each * 2.0, 0.0001);
+        let _ = fs::remove_file(path);
+
+        assert_eq!(transaction?.tx_outputs.len(), 3);
+        assert!(matches!(too_expensive, Err(NodeError::NotEnoughCoins(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_is_sent_to_the_change_account() -> Result<(), NodeError> {
+        let sender = first_funded_account();
+        let change_account = second_funded_account();
+        let change_script = BitcoinAddress::to_pk_script(&change_account.bitcoin_address);
+        let mut wallet = wallet_with_accounts(vec![sender, change_account]);
+
+        let unknown_account = wallet.set_change_address_strategy(
+            ChangeAddressStrategy::ChangeAccount("mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB".to_string()),
+        );
+        wallet.set_change_address_strategy(ChangeAddressStrategy::ChangeAccount(
+            "mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz".to_string(),
+        ))?;
+        let transaction = wallet.create_transaction(
+            "mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC".to_string(),
+            &"mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB".to_string(),
+            0.001,
+            0.0001,
+        )?;
+
+        assert!(matches!(
+            unknown_account,
+            Err(NodeError::AccountNotFound(_))
+        ));
+        assert_eq!(transaction.tx_outputs[0].pk_script, change_script);
+        Ok(())
+    }
+
+    #[test]
+    fn test_configured_change_account_is_used_only_if_the_wallet_owns_it() {
+        let mut wallet =
+            wallet_with_accounts(vec![first_funded_account(), second_funded_account()]);
+
+        wallet.configure_change_account(Some("mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz".to_string()));
+        let owned = wallet.change_address_strategy().clone();
+        wallet.configure_change_account(Some("mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB".to_string()));
+        let not_owned = wallet.change_address_strategy().clone();
+        wallet.configure_change_account(None);
+
+        assert_eq!(
+            owned,
+            ChangeAddressStrategy::ChangeAccount("mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz".to_string())
+        );
+        assert_eq!(not_owned, owned);
+        assert_eq!(
+            wallet.change_address_strategy(),
+            &ChangeAddressStrategy::SenderAddress
+        );
+    }
+
+    #[test]
+    fn test_owns_only_the_addresses_of_its_accounts() {
+        let account = first_funded_account();
+        let wallet = wallet_with_accounts(vec![account]);
+
+        assert!(wallet.owns_address("mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC"));
+        assert!(!wallet.owns_address("mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB"));
+        assert!(!wallet.owns_address("not an address"));
+    }
+
+    #[test]
+    fn test_send_exceeding_balance_reports_insufficient_funds() -> Result<(), NodeError> {
+        let account = first_funded_account();
+        let balance = account.balance_for_user();
+        let requested = balance + 1.0;
+        let wallet = Arc::new(Mutex::new(wallet_with_accounts(vec![account])));
+        let (ui_sender, ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+
+        Wallet::create_transaction_draft(
+            &wallet,
+            "mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB".to_string(),
+            requested,
+            0.0001,
+            &mut HashMap::new(),
+            &ui_sender,
+        )?;
+
+        let insufficient_funds = Rc::new(RefCell::new(Vec::new()));
+        let received_insufficient_funds = Rc::clone(&insufficient_funds);
+        let context = glib::MainContext::new();
+        ui_receiver.attach(Some(&context), move |message| {
+            if let UIMessage::InsufficientFunds(available, requested) = message {
+                received_insufficient_funds
+                    .borrow_mut()
+                    .push((available, requested));
+            }
+            glib::Continue(true)
+        });
+        while context.iteration(false) {}
+
+        assert_eq!(*insufficient_funds.borrow(), vec![(balance, requested)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_draft_is_not_broadcasted_until_confirmed() -> Result<(), NodeError> {
+        let account = first_funded_account();
+        let wallet = Arc::new(Mutex::new(wallet_with_accounts(vec![account])));
+        let (ui_sender, ui_receiver) = glib::MainContext::channel(glib::Priority::default());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .map_err(|_| NodeError::FailedToBind("Failed to bind".to_string()))?;
+        let peer = TcpStream::connect(
+            listener
+                .local_addr()
+                .map_err(|_| NodeError::FailedToBind("Failed to get address".to_string()))?,
+        )
+        .map_err(|_| NodeError::FailedToConnect("Failed to connect".to_string()))?;
+        let (mut peer_end, _) = listener
+            .accept()
+            .map_err(|_| NodeError::FailedToConnect("Failed to accept".to_string()))?;
+        let _ = peer_end.set_read_timeout(Some(std::time::Duration::from_millis(100)));
+        let mut drafts = HashMap::new();
+
+        Wallet::create_transaction_draft(
+            &wallet,
+            "mv4rnyY3Su5gjcDNzbMLKBQkBicCtHUtFB".to_string(),
+            0.001,
+            0.0001,
+            &mut drafts,
+            &ui_sender,
+        )?;
+        let mut header = [0; 24];
+        let broadcasted_before_confirmation = peer_end.read_exact(&mut header).is_ok();
+        let tx_id = drafts
+            .keys()
+            .next()
+            .cloned()
+            .ok_or_else(|| NodeError::FailedToCreateTransaction("Missing draft".to_string()))?;
+        Wallet::confirm_send(&mut drafts, &tx_id, &mut [peer], &ui_sender)?;
+        let broadcasted = peer_end.read_exact(&mut header).is_ok();
+
+        let draft_ids = Rc::new(RefCell::new(Vec::new()));
+        let received_draft_ids = Rc::clone(&draft_ids);
+        let context = glib::MainContext::new();
+        ui_receiver.attach(Some(&context), move |message| {
+            if let UIMessage::TransactionDraft(tx_id, _, outputs) = message {
+                received_draft_ids.borrow_mut().push((tx_id, outputs.len()));
+            }
+            glib::Continue(true)
+        });
+        while context.iteration(false) {}
+
+        assert!(!broadcasted_before_confirmation);
+        assert!(broadcasted);
+        assert_eq!(&header[4..6], b"tx");
+        assert!(drafts.is_empty());
+        assert_eq!(*draft_ids.borrow(), vec![(tx_id, 2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sweep_spends_every_utxo_into_one_output() -> Result<(), NodeError> {
+        let mut utxo_set = UtxoSet::new();
+        for block in [
+            "000000000000001fe07dd7d936489026a1dc1906ba797f0cac12b645367c9952",
+            "0000000000000008771c98eab6cbcea3c63138d3715e67d244b52dd183053f80",
+            "000000000000000c5d6cc58f545057a781c46c100a0f2ea5f8f6a31c1b44c784",
+            "000000000000001ea1833f96dbbe35fd5e0d0f2d6fce810bd91a3d236163dc94",
+            "00000000000026d0538e1c26d2362bb6078efd9609bb1954117c6e1aa81811bc",
+        ] {
+            utxo_set.update(&format!("blocks-test/{}.bin", block))?;
+        }
+        let account = Account::new(
+            &Arc::new(Mutex::new(utxo_set)),
+            "mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz".to_string(),
+            "9319Nrhiz9UD4EgeW3n18YpRTbcjTYkvS57b3WxX96P24bGFxHv".to_string(),
+            0,
+            DEFAULT_CONFIRMATION_DEPTH,
+        )?;
+        let spent_outputs = account.unspent_outputs()?;
+        let utxos: Vec<TxOutput> = spent_outputs
+            .iter()
+            .flat_map(|(_, tx_outputs)| tx_outputs.clone())
+            .collect();
+        let balance: i64 = utxos.iter().map(|tx_output| tx_output.value).sum();
+        let wallet = wallet_with_accounts(vec![account]);
+
+        let transaction = wallet.sweep(
+            "mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz".to_string(),
+            "mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC".to_string(),
+            2.0,
+        )?;
+        let too_expensive = wallet.sweep(
+            "mmKLrA7dvdtGez1GH9ChBkQ6FLUiNr3mFz".to_string(),
+            "mna7LXQEht1uRaUEKv1UGvF8N1eqMXCATC".to_string(),
+            balance as f64,
+        );
+
+        assert!(utxos.len() > 1);
+        assert_eq!(transaction.tx_inputs.len(), utxos.len());
+        assert_eq!(transaction.tx_outputs.len(), 1);
+        assert_eq!(
+            transaction.tx_outputs[0].value,
+            balance - Transaction::estimate_size(utxos.len(), 1) as i64 * 2
+        );
+        for (i, tx_input) in transaction.tx_inputs.iter().enumerate() {
+            let spent = spent_outputs
+                .output(&tx_input.previous_output)
+                .ok_or_else(|| NodeError::FailedToRead("Unknown input".to_string()))?;
+            verify_p2pkh_input(&transaction, i, &spent.pk_script)?;
+        }
+        assert!(transaction.fee(&spent_outputs)?.is_some());
+        assert!(matches!(too_expensive, Err(NodeError::NotEnoughCoins(_))));
+        Ok(())
+    }
 }
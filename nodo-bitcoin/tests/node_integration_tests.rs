@@ -1,14 +1,17 @@
 use core::time;
 use std::io::{Read, Seek, SeekFrom};
 use std::sync::{mpsc, Arc, Mutex};
-use std::{fs, io, net::TcpStream, path::Path};
+use std::time::Instant;
+use std::{fs, io, path::Path};
 
 use bitcoin_hashes::{sha256d, Error, Hash};
 use glib::{Receiver, Sender};
 use inoxidables_23c1::block_header::GENESIS_BLOCK_HEADER;
 use inoxidables_23c1::channels::wallet_channel::WalletChannel;
 use inoxidables_23c1::compact_size::CompactSize;
-use inoxidables_23c1::constants::PATH_BLOCKS;
+use inoxidables_23c1::constants::{
+    DEFAULT_CONFIRMATION_DEPTH, DEFAULT_IBD_TIMEOUT_SECS, PATH_BLOCKS,
+};
 use inoxidables_23c1::transactions::outpoint::Outpoint;
 use inoxidables_23c1::transactions::transaction::Transaction;
 use inoxidables_23c1::transactions::tx_input::TxInput;
@@ -24,12 +27,15 @@ use inoxidables_23c1::{
     config::load_app_config,
     constants::PATH_LOG,
     logger::Logger,
-    node::{init_connection, initial_block_headers_download, read::obtain_ips},
+    node::{
+        connection_limiter::OutboundStream, init_connection, initial_block_headers_download,
+        read::obtain_ips,
+    },
     node_error::NodeError,
 };
 
 struct IntegrationTest {
-    stream: TcpStream,
+    stream: OutboundStream,
     test_logger: Logger,
 }
 
@@ -82,9 +88,11 @@ fn test_blocks_download() -> Result<(), NodeError> {
         None => return Err(NodeError::FailedToRead("No hash sender".to_string())),
     };
 
+    let deadline = Instant::now() + time::Duration::from_secs(DEFAULT_IBD_TIMEOUT_SECS);
     let header_blocks = initial_block_headers_download(
         &mut integration_test.stream,
         sender,
+        deadline,
         &ui_channel.0,
         &integration_test.test_logger,
     )?;
@@ -96,6 +104,7 @@ fn test_blocks_download() -> Result<(), NodeError> {
     retry_failed_blocks(
         &result_join.1.unwrap(),
         &mut result_join.0,
+        deadline,
         &integration_test.test_logger,
         &ui_channel.0,
     )?;
@@ -167,10 +176,12 @@ fn test_wallet_operations() {
 
     let (wallet_node_sender, wallet_node_receiver): (Sender<UIMessage>, Receiver<UIMessage>) =
         glib::MainContext::channel(glib::Priority::default());
+    let utxo_set_arc = Arc::new(Mutex::new(utxo_set));
     let mut wallet = Wallet::initialize_wallet_for_user(
-        &Arc::new(Mutex::new(utxo_set.clone())),
+        &utxo_set_arc,
         &account_info,
         &wallet_node_sender,
+        DEFAULT_CONFIRMATION_DEPTH,
     )
     .unwrap();
 
@@ -181,7 +192,7 @@ fn test_wallet_operations() {
     );
 
     wallet
-        .add_account(&utxo_set, new_account, &wallet_node_sender)
+        .add_account(&utxo_set_arc, new_account, &wallet_node_sender)
         .unwrap();
 
     assert_eq!(wallet.accounts.len(), 2);
@@ -217,6 +228,7 @@ fn test_new_tx_wallet_node_connection() -> Result<(), NodeError> {
         &Arc::new(Mutex::new(utxo_set.clone())),
         &wallet_info,
         &ui_sender,
+        DEFAULT_CONFIRMATION_DEPTH,
     )?;
     thread::spawn(move || {
         ui_receiver.attach(None, move |_| glib::Continue(true));
@@ -316,6 +328,7 @@ fn build_tx(bc_addr: BitcoinAddress) -> Transaction {
         ]
         .to_vec(),
         lock_time: 2428442,
+        witnesses: Vec::new(),
     };
     tx
 }
@@ -0,0 +1,189 @@
+//! Integration tests against a local `bitcoind -regtest` node, so the handshake, the header
+//! download and the block download are exercised deterministically instead of against the testnet
+//! peers of the DNS seed.
+//!
+//! The tests are skipped unless PEERS points at a running regtest node with at least one block
+//! mined, for example:
+//!
+//! `PEERS=127.0.0.1:18444 cargo test --test regtest_integration_tests`
+
+use std::{
+    env, fs,
+    net::{SocketAddr, TcpStream},
+    sync::{mpsc, Mutex},
+    time::{Duration, Instant},
+};
+
+use glib::{Receiver, Sender};
+use inoxidables_23c1::{
+    block::{block_hash::BlockHash, verify_block_file},
+    block_header::REGTEST_GENESIS_BLOCK_HEADER,
+    channels::event_sink::NodeEvent,
+    config::load_app_config,
+    constants::{
+        BLOCK_HEADERS_FILE, CONNECTION_TIMEOUT, DEFAULT_IBD_TIMEOUT_SECS, HEADERS_ONLY, NETWORK,
+        PATH_BLOCKS, PATH_LOG, PEERS, REGTEST_NETWORK,
+    },
+    logger::Logger,
+    messages::block_message::BlockMessage,
+    node::{handshake, init_connection, initial_block_headers_download, read::obtain_ips},
+    node_error::NodeError,
+    node_pools::block_downloader_pool::BlockDownloaderPool,
+    ui::ui_message::UIMessage,
+};
+
+const REGTEST_HEADERS_FILE: &str = "block_headers_regtest.bin";
+const REGTEST_BLOCKS_DIR: &str = "blocks-regtest";
+
+/// The configuration is kept in environment variables, so the tests run one at a time.
+static REGTEST_ENV: Mutex<()> = Mutex::new(());
+
+/// A local regtest node the tests connect to.
+struct RegtestNode {
+    addresses: Vec<SocketAddr>,
+    logger: Logger,
+}
+
+/// Configures the node to run on regtest against the peers of PEERS.
+///
+/// # Returns
+///
+/// The regtest node, or `None` if PEERS is not set or no node is listening on its addresses, in
+/// which case the test is skipped.
+fn set_up() -> Result<Option<RegtestNode>, NodeError> {
+    let peers = env::var(PEERS).unwrap_or_default();
+    load_app_config(None)?;
+    env::set_var(PEERS, &peers);
+    env::set_var(NETWORK, REGTEST_NETWORK);
+    env::set_var(PATH_LOG, "log-regtest-tests.txt");
+    env::set_var(BLOCK_HEADERS_FILE, REGTEST_HEADERS_FILE);
+    if peers.trim().is_empty() {
+        println!("PEERS is not set, skipping the regtest test");
+        return Ok(None);
+    }
+
+    let addresses: Vec<SocketAddr> = obtain_ips()?
+        .into_iter()
+        .filter(|address| {
+            TcpStream::connect_timeout(address, Duration::from_secs(CONNECTION_TIMEOUT)).is_ok()
+        })
+        .collect();
+    if addresses.is_empty() {
+        println!(
+            "No regtest node is listening on {}, skipping the test",
+            peers
+        );
+        return Ok(None);
+    }
+    Ok(Some(RegtestNode {
+        addresses,
+        logger: Logger::new()?,
+    }))
+}
+
+#[test]
+fn test_regtest_handshake() -> Result<(), NodeError> {
+    let _env = REGTEST_ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let node = match set_up()? {
+        Some(node) => node,
+        None => return Ok(()),
+    };
+    let address = node.addresses[0];
+    let mut stream = TcpStream::connect_timeout(&address, Duration::from_secs(CONNECTION_TIMEOUT))
+        .map_err(|e| NodeError::FailedToConnect(e.to_string()))?;
+
+    assert!(handshake(&address, &mut stream, &node.logger)?);
+    Ok(())
+}
+
+#[test]
+fn test_regtest_headers_download() -> Result<(), NodeError> {
+    let _env = REGTEST_ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let node = match set_up()? {
+        Some(node) => node,
+        None => return Ok(()),
+    };
+    env::set_var(HEADERS_ONLY, "true");
+    let _ = fs::remove_file(REGTEST_HEADERS_FILE);
+    let mut stream = init_connection(&node.addresses, &node.logger)?;
+    let (hash_sender, _hash_receiver) = mpsc::channel::<BlockHash>();
+    let (ui_sender, _ui_receiver): (Sender<UIMessage>, Receiver<UIMessage>) =
+        glib::MainContext::channel(glib::Priority::default());
+
+    let deadline = Instant::now() + Duration::from_secs(DEFAULT_IBD_TIMEOUT_SECS);
+    let result = initial_block_headers_download(
+        &mut stream,
+        &hash_sender,
+        deadline,
+        &ui_sender,
+        &node.logger,
+    );
+    let _ = fs::remove_file(REGTEST_HEADERS_FILE);
+
+    // The headers file starts with the regtest genesis, which is not returned: the headers
+    // downloaded must be the chain of the regtest node built on top of it.
+    let headers = result?;
+    let first_header = headers
+        .first()
+        .ok_or_else(|| NodeError::FailedToDownloadBlock("No header was downloaded".to_string()))?;
+    assert_eq!(
+        first_header.prev_blockhash,
+        REGTEST_GENESIS_BLOCK_HEADER.block_hash()
+    );
+    for pair in headers.windows(2) {
+        assert_eq!(&pair[1].prev_blockhash.to_vec(), pair[0].hash());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_regtest_block_download() -> Result<(), NodeError> {
+    let _env = REGTEST_ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let node = match set_up()? {
+        Some(node) => node,
+        None => return Ok(()),
+    };
+    env::set_var(HEADERS_ONLY, "false");
+    env::set_var(PATH_BLOCKS, REGTEST_BLOCKS_DIR);
+    let _ = fs::remove_file(REGTEST_HEADERS_FILE);
+    let _ = fs::remove_dir_all(REGTEST_BLOCKS_DIR);
+    fs::create_dir_all(REGTEST_BLOCKS_DIR).map_err(|_| {
+        NodeError::FailedToWrite("Failed to create the blocks directory".to_string())
+    })?;
+    let (events, event_receiver) = mpsc::channel::<NodeEvent>();
+    let mut pool = BlockDownloaderPool::new(2, &node.addresses, node.logger.clone(), &events)?;
+    let hash_sender = pool
+        .hash_sender
+        .take()
+        .ok_or_else(|| NodeError::FailedToSendMessage("No sender found".to_string()))?;
+    let mut stream = init_connection(&node.addresses, &node.logger)?;
+
+    let deadline = Instant::now() + Duration::from_secs(DEFAULT_IBD_TIMEOUT_SECS);
+    let headers =
+        initial_block_headers_download(&mut stream, &hash_sender, deadline, &events, &node.logger);
+    drop(hash_sender);
+    pool.close_channel();
+    let joined = pool.join();
+    // Every block after the regtest genesis must be downloaded and saved intact.
+    let verified = headers.and_then(|headers| {
+        joined?;
+        for header in &headers {
+            let path = BlockMessage::block_path_for_header(header).ok_or_else(|| {
+                NodeError::FailedToDownloadBlock("Failed to get block path".to_string())
+            })?;
+            verify_block_file(&path)?;
+        }
+        Ok(headers.len())
+    });
+    let _ = fs::remove_file(REGTEST_HEADERS_FILE);
+    let _ = fs::remove_dir_all(REGTEST_BLOCKS_DIR);
+
+    let blocks = verified?;
+    let downloaded = event_receiver
+        .try_iter()
+        .filter(|event| matches!(event, NodeEvent::BlockDownloaded))
+        .count();
+    assert!(blocks > 0);
+    assert!(downloaded >= blocks);
+    Ok(())
+}